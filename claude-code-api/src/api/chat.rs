@@ -1,17 +1,19 @@
 use axum::{
-    Json,
+    Extension, Json,
     extract::{Path, State},
     response::IntoResponse,
 };
 use chrono::Utc;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::{
-    api::streaming_handler::handle_enhanced_streaming_response,
-    core::claude_manager::ClaudeManager,
+    api::streaming_handler::{DisconnectAction, handle_enhanced_streaming_response},
+    core::{claude_manager::ClaudeManager, config::ClaudeConfig},
+    middleware::request_id::RequestId,
     models::{
         claude::ClaudeCodeOutput,
         error::{ApiError, ApiResult},
@@ -69,6 +71,7 @@ pub struct ChatState {
     pub cache: Arc<crate::core::cache::ResponseCache>,
     pub use_interactive_sessions: bool,
     pub settings: Arc<crate::core::config::Settings>,
+    pub usage: crate::core::usage::UsageAggregator,
 }
 
 impl ChatState {
@@ -83,6 +86,26 @@ impl ChatState {
         use_interactive_sessions: bool,
         settings: Arc<crate::core::config::Settings>,
     ) -> Self {
+        let usage = crate::core::usage::UsageAggregator::new();
+        usage.evict_closed(interactive_session_manager.subscribe_closed());
+
+        // Conversations served by the process pool have no long-lived session;
+        // their totals go when the conversation itself expires
+        let pruned_usage = usage.clone();
+        let conversations = conversation_manager.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(tokio::time::Duration::from_secs(300)).await;
+                let active = conversations
+                    .list_active_conversations()
+                    .await
+                    .into_iter()
+                    .map(|(id, _)| id)
+                    .collect();
+                pruned_usage.retain_conversations(&active);
+            }
+        });
+
         Self {
             claude_manager,
             process_pool,
@@ -91,12 +114,14 @@ impl ChatState {
             cache,
             use_interactive_sessions,
             settings,
+            usage,
         }
     }
 }
 
 pub async fn chat_completions(
     State(state): State<ChatState>,
+    Extension(RequestId(request_id)): Extension<RequestId>,
     Json(request): Json<ChatCompletionRequest>,
 ) -> ApiResult<impl IntoResponse> {
     use crate::core::cache::ResponseCache;
@@ -138,12 +163,14 @@ pub async fn chat_completions(
     // 根据配置选择使用交互式会话管理器或进程池
     let (session_id, rx) = if state.use_interactive_sessions {
         // 使用交互式会话管理器复用进程
+        // Key the session by the resolved conversation id so follow-ups reuse it
         state
             .interactive_session_manager
             .get_or_create_session_and_send(
-                request.conversation_id.clone(),
+                Some(conversation_id.clone()),
                 request.model.clone(),
                 formatted_message,
+                Some(request_id.clone()),
             )
             .await
             .map_err(|e| ApiError::ClaudeProcess(e.to_string()))?
@@ -151,20 +178,47 @@ pub async fn chat_completions(
         // 使用进程池
         state
             .process_pool
-            .get_or_create(request.model.clone(), formatted_message)
+            .get_or_create(
+                request.model.clone(),
+                formatted_message,
+                Some(request_id.clone()),
+            )
             .await
             .map_err(|e| ApiError::ClaudeProcess(e.to_string()))?
     };
+    let rx = state.usage.track(conversation_id.clone(), rx);
+    let timeouts = TurnTimeouts::from_config(&state.settings.claude);
+    let stop_action = if state.use_interactive_sessions {
+        DisconnectAction::Interrupt {
+            session_manager: state.interactive_session_manager.clone(),
+            conversation_id: conversation_id.clone(),
+        }
+    } else {
+        DisconnectAction::Close {
+            claude_manager: state.claude_manager.clone(),
+            session_id: session_id.clone(),
+        }
+    };
 
     if request.stream.unwrap_or(false) {
-        Ok(handle_streaming_response(
-            request.model,
-            rx,
-            state.interactive_session_manager.clone(),
-            conversation_id.clone(),
+        // Waiting for the first output before answering lets a dead model
+        // still be reported as a 504
+        let rx = match forward_with_deadlines(rx, timeouts).await {
+            Ok(rx) => rx,
+            Err(timed_out) => {
+                stop_action.stop_turn().await;
+                return Err(timed_out.into());
+            },
+        };
+        let include_usage = request
+            .stream_options
+            .as_ref()
+            .is_some_and(|options| options.include_usage);
+        Ok(
+            handle_streaming_response(request.model, rx, include_usage, stop_action)
+                .await?
+                .into_response(),
         )
-        .await?
-        .into_response())
     } else {
         let cache_key = ResponseCache::generate_key(&request.model, &context_messages);
         let response = handle_non_streaming_response(
@@ -172,10 +226,18 @@ pub async fn chat_completions(
             rx,
             session_id,
             state.claude_manager.clone(),
-            state.settings.claude.timeout_seconds,
+            timeouts,
             request.tools.clone(),
         )
-        .await?;
+        .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(TurnError::TimedOut(timed_out)) => {
+                stop_action.stop_turn().await;
+                return Err(timed_out.into());
+            },
+            Err(TurnError::Api(e)) => return Err(e),
+        };
 
         for msg in &request.messages {
             state
@@ -202,6 +264,160 @@ pub async fn chat_completions(
     }
 }
 
+/// Deadlines of one chat completion turn
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TurnTimeouts {
+    /// Limit on the wait for the first non-system output
+    first_token: Option<Duration>,
+    /// Limit on the whole turn
+    total: Duration,
+}
+
+impl TurnTimeouts {
+    fn from_config(config: &ClaudeConfig) -> Self {
+        Self {
+            first_token: config.first_token_timeout_secs.map(Duration::from_secs),
+            total: Duration::from_secs(
+                config
+                    .request_timeout_secs
+                    .unwrap_or(config.timeout_seconds),
+            ),
+        }
+    }
+}
+
+/// The deadline a turn missed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TurnTimeout {
+    FirstToken(Duration),
+    Total(Duration),
+}
+
+impl From<TurnTimeout> for ApiError {
+    fn from(timed_out: TurnTimeout) -> Self {
+        match timed_out {
+            TurnTimeout::FirstToken(limit) => ApiError::FirstTokenTimeout(format!(
+                "No output from Claude within {} seconds",
+                limit.as_secs()
+            )),
+            TurnTimeout::Total(limit) => ApiError::Timeout(format!(
+                "Response not complete within {} seconds",
+                limit.as_secs()
+            )),
+        }
+    }
+}
+
+/// Why collecting a turn failed
+enum TurnError {
+    TimedOut(TurnTimeout),
+    Api(ApiError),
+}
+
+impl From<ApiError> for TurnError {
+    fn from(e: ApiError) -> Self {
+        TurnError::Api(e)
+    }
+}
+
+/// Receives a turn's outputs within its deadlines
+struct DeadlineReceiver {
+    rx: mpsc::Receiver<ClaudeCodeOutput>,
+    timeouts: TurnTimeouts,
+    started: Instant,
+    first_output_seen: bool,
+}
+
+impl DeadlineReceiver {
+    fn new(rx: mpsc::Receiver<ClaudeCodeOutput>, timeouts: TurnTimeouts) -> Self {
+        Self {
+            rx,
+            timeouts,
+            started: Instant::now(),
+            first_output_seen: false,
+        }
+    }
+
+    /// Next output, or the deadline that passed first
+    ///
+    /// `system` outputs (e.g. `init`) are sent before the model is called, so
+    /// they do not count as the first output.
+    async fn recv(&mut self) -> Result<Option<ClaudeCodeOutput>, TurnTimeout> {
+        let elapsed = self.started.elapsed();
+        let mut wait = self.timeouts.total.saturating_sub(elapsed);
+        let mut missed = TurnTimeout::Total(self.timeouts.total);
+        if !self.first_output_seen
+            && let Some(limit) = self.timeouts.first_token
+            && limit.saturating_sub(elapsed) < wait
+        {
+            wait = limit.saturating_sub(elapsed);
+            missed = TurnTimeout::FirstToken(limit);
+        }
+
+        let output = tokio::time::timeout(wait, self.rx.recv())
+            .await
+            .map_err(|_| missed)?;
+        if let Some(ref output) = output
+            && output.r#type != "system"
+        {
+            self.first_output_seen = true;
+        }
+        Ok(output)
+    }
+}
+
+/// Wait for a turn's first output, then forward the rest until the total deadline
+///
+/// Fails if the first output misses its deadline (or the turn ends before
+/// it). Once forwarding has started, missing the total deadline sends an
+/// `error` output carrying the timeout and closes the returned channel
+/// without a `result`, which makes the SSE disconnect guard stop the turn.
+async fn forward_with_deadlines(
+    rx: mpsc::Receiver<ClaudeCodeOutput>,
+    timeouts: TurnTimeouts,
+) -> Result<mpsc::Receiver<ClaudeCodeOutput>, TurnTimeout> {
+    let mut rx = DeadlineReceiver::new(rx, timeouts);
+    let mut buffered = Vec::new();
+    while !rx.first_output_seen {
+        match rx.recv().await? {
+            Some(output) => buffered.push(output),
+            None => break,
+        }
+    }
+
+    let (tx, forwarded) = mpsc::channel(100);
+    tokio::spawn(async move {
+        for output in buffered {
+            if tx.send(output).await.is_err() {
+                return;
+            }
+        }
+        loop {
+            match rx.recv().await {
+                Ok(Some(output)) => {
+                    if tx.send(output).await.is_err() {
+                        return;
+                    }
+                },
+                Ok(None) => return,
+                Err(timed_out) => {
+                    let error = ApiError::from(timed_out);
+                    warn!("Streaming turn stopped: {}", error);
+                    tx.send(ClaudeCodeOutput {
+                        r#type: "error".to_string(),
+                        subtype: None,
+                        data: serde_json::json!({ "error": error.detail() }),
+                    })
+                    .await
+                    .ok();
+                    return;
+                },
+            }
+        }
+    });
+    Ok(forwarded)
+}
+
 /// Interrupt the active request in an interactive session.
 ///
 /// `POST /v1/sessions/:conversation_id/interrupt`
@@ -391,43 +607,37 @@ async fn download_image(url: &str) -> ApiResult<String> {
 async fn handle_streaming_response(
     model: String,
     rx: mpsc::Receiver<ClaudeCodeOutput>,
-    session_manager: Arc<crate::core::interactive_session::InteractiveSessionManager>,
-    conversation_id: String,
+    include_usage: bool,
+    on_disconnect: DisconnectAction,
 ) -> ApiResult<impl IntoResponse> {
     // Use enhanced streaming with text chunking for better UX.
-    // Pass session_manager + conversation_id so the disconnect guard
-    // can auto-interrupt the CLI if the SSE client drops the connection.
+    // The disconnect guard stops the CLI if the SSE client drops the
+    // connection, and releases process-pool sessions once done.
     let stream =
-        handle_enhanced_streaming_response(model, rx, Some(session_manager), Some(conversation_id))
-            .await;
+        handle_enhanced_streaming_response(model, rx, include_usage, Some(on_disconnect)).await;
     Ok(create_sse_stream(stream))
 }
 
 async fn handle_non_streaming_response(
     model: String,
-    mut rx: mpsc::Receiver<ClaudeCodeOutput>,
+    rx: mpsc::Receiver<ClaudeCodeOutput>,
     session_id: String,
     claude_manager: Arc<ClaudeManager>,
-    timeout_seconds: u64,
+    timeouts: TurnTimeouts,
     requested_tools: Option<Vec<crate::models::openai::Tool>>,
-) -> ApiResult<Json<ChatCompletionResponse>> {
+) -> Result<Json<ChatCompletionResponse>, TurnError> {
     use crate::models::openai::{FunctionCall, ToolCall};
-    use tokio::time::{Duration, timeout};
 
     let mut full_content = String::new();
     let mut tool_calls: Vec<ToolCall> = Vec::new();
     let mut token_count = 0;
 
-    info!(
-        "Waiting for Claude response (timeout: {}s)...",
-        timeout_seconds
-    );
+    info!("Waiting for Claude response (timeouts: {:?})...", timeouts);
 
-    let timeout_duration = Duration::from_secs(timeout_seconds);
-    let start = std::time::Instant::now();
+    let mut rx = DeadlineReceiver::new(rx, timeouts);
 
     loop {
-        match timeout(Duration::from_secs(5), rx.recv()).await {
+        match rx.recv().await {
             Ok(Some(output)) => {
                 // Skip messages from subagent sidechains (Task tool executions).
                 // Only top-level messages (parent_tool_use_id == None) should be
@@ -536,23 +746,13 @@ async fn handle_non_streaming_response(
                 );
                 break;
             },
-            Err(_) => {
-                if start.elapsed() > timeout_duration {
-                    error!(
-                        "Timeout waiting for Claude response after {:?}",
-                        start.elapsed()
-                    );
-                    // Close the session to avoid EPIPE error
-                    let _ = claude_manager.close_session(&session_id).await;
-                    return Err(ApiError::ClaudeProcess(format!(
-                        "Timeout waiting for response after {} seconds",
-                        timeout_seconds
-                    )));
-                }
-                info!(
-                    "No data received in 5s, but still waiting... (elapsed: {:?})",
-                    start.elapsed()
+            Err(timed_out) => {
+                error!(
+                    "Timeout waiting for Claude response after {:?}: {:?}",
+                    rx.started.elapsed(),
+                    timed_out
                 );
+                return Err(TurnError::TimedOut(timed_out));
             },
         }
     }
@@ -647,3 +847,70 @@ async fn handle_non_streaming_response(
 
     Ok(Json(response))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn output(r#type: &str) -> ClaudeCodeOutput {
+        ClaudeCodeOutput {
+            r#type: r#type.to_string(),
+            subtype: None,
+            data: json!({}),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deadline_receiver_distinguishes_first_token_and_total() {
+        let timeouts = TurnTimeouts {
+            first_token: Some(Duration::from_millis(50)),
+            total: Duration::from_secs(5),
+        };
+
+        // Only system output: the first-token deadline applies
+        let (tx, rx) = mpsc::channel(4);
+        tx.send(output("system")).await.unwrap();
+        let mut rx = DeadlineReceiver::new(rx, timeouts);
+        assert!(rx.recv().await.unwrap().is_some());
+        assert_eq!(
+            rx.recv().await.unwrap_err(),
+            TurnTimeout::FirstToken(Duration::from_millis(50))
+        );
+
+        // After the first output only the total deadline is left
+        let (tx, rx) = mpsc::channel(4);
+        tx.send(output("assistant")).await.unwrap();
+        let mut rx = DeadlineReceiver::new(
+            rx,
+            TurnTimeouts {
+                total: Duration::from_millis(100),
+                ..timeouts
+            },
+        );
+        assert!(rx.recv().await.unwrap().is_some());
+        assert_eq!(
+            rx.recv().await.unwrap_err(),
+            TurnTimeout::Total(Duration::from_millis(100))
+        );
+        drop(tx);
+    }
+
+    #[tokio::test]
+    async fn test_forwarding_ends_with_error_on_total_timeout() {
+        let (tx, rx) = mpsc::channel(4);
+        tx.send(output("assistant")).await.unwrap();
+        let timeouts = TurnTimeouts {
+            first_token: Some(Duration::from_secs(5)),
+            total: Duration::from_millis(100),
+        };
+        let mut forwarded = forward_with_deadlines(rx, timeouts).await.unwrap();
+
+        assert_eq!(forwarded.recv().await.unwrap().r#type, "assistant");
+        let error = forwarded.recv().await.unwrap();
+        assert_eq!(error.r#type, "error");
+        assert_eq!(error.data["error"]["code"], json!("timeout"));
+        assert!(forwarded.recv().await.is_none());
+        drop(tx);
+    }
+}
@@ -5,3 +5,4 @@ pub mod projects;
 pub mod sessions;
 pub mod stats;
 pub mod streaming_handler;
+pub mod tools;
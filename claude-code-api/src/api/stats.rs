@@ -2,22 +2,35 @@ use axum::{Json, extract::State, response::IntoResponse};
 use serde::Serialize;
 use std::sync::Arc;
 
-use crate::{core::cache::ResponseCache, models::error::ApiResult};
+use crate::{
+    core::{
+        cache::ResponseCache,
+        process_pool::{PoolStats, ProcessPool},
+        usage::{UsageAggregator, UsageSnapshot},
+    },
+    models::error::ApiResult,
+};
 
 #[derive(Clone)]
 pub struct StatsState {
     pub cache: Arc<ResponseCache>,
+    pub usage: UsageAggregator,
+    pub process_pool: Arc<ProcessPool>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct SystemStats {
     pub cache: crate::core::cache::CacheStats,
+    pub usage: UsageSnapshot,
+    pub process_pool: PoolStats,
     pub version: &'static str,
 }
 
 pub async fn get_stats(State(state): State<StatsState>) -> ApiResult<impl IntoResponse> {
     let stats = SystemStats {
         cache: state.cache.stats(),
+        usage: state.usage.snapshot().await,
+        process_pool: state.process_pool.stats(),
         version: env!("CARGO_PKG_VERSION"),
     };
 
@@ -1,18 +1,21 @@
 //! Enhanced streaming handler with real chunking support
 
 use crate::{
-    core::interactive_session::InteractiveSessionManager,
+    core::{claude_manager::ClaudeManager, interactive_session::InteractiveSessionManager},
     models::{
         claude::ClaudeCodeOutput,
+        error::{ApiError, ErrorDetail},
         openai::{
             ChatCompletionStreamResponse, DeltaFunctionCall, DeltaMessage, DeltaToolCall,
-            StreamChoice,
+            StreamChoice, Usage,
         },
     },
     utils::text_chunker::{ChunkConfig, chunk_text},
 };
 use chrono::Utc;
 use futures::stream::{Stream, StreamExt};
+use serde_json::Value;
+use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::{
     Arc,
@@ -22,17 +25,68 @@ use tokio::sync::mpsc;
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-/// Guard that auto-interrupts the CLI session when the SSE stream is dropped.
+/// How to release the CLI session behind an SSE stream.
+pub enum DisconnectAction {
+    /// Interactive session: interrupt the turn if the client leaves early,
+    /// keeping the process for follow-up requests.
+    Interrupt {
+        session_manager: Arc<InteractiveSessionManager>,
+        conversation_id: String,
+    },
+    /// Process-pool session: the one-shot process is closed once the stream
+    /// ends, killing it first if the client left early.
+    Close {
+        claude_manager: Arc<ClaudeManager>,
+        session_id: String,
+    },
+}
+
+impl DisconnectAction {
+    /// Stop the turn in progress, e.g. after it missed its deadline.
+    ///
+    /// An interactive session is interrupted and stays available for the
+    /// conversation's next request; it is closed if the interrupt cannot be
+    /// delivered. A process-pool session is one-shot and is closed.
+    pub async fn stop_turn(&self) {
+        match self {
+            DisconnectAction::Interrupt {
+                session_manager,
+                conversation_id,
+            } => match session_manager.interrupt_session(conversation_id) {
+                Ok(true) => info!("Interrupted session {}", conversation_id),
+                Ok(false) => debug!("Session {} already closed", conversation_id),
+                Err(e) => {
+                    warn!(
+                        "Failed to interrupt session {}, closing it: {}",
+                        conversation_id, e
+                    );
+                    if let Err(e) = session_manager.close_session(conversation_id).await {
+                        debug!("Failed to close session {}: {}", conversation_id, e);
+                    }
+                },
+            },
+            DisconnectAction::Close {
+                claude_manager,
+                session_id,
+            } => {
+                if let Err(e) = claude_manager.close_session(session_id).await {
+                    debug!("Failed to close session {}: {}", session_id, e);
+                }
+            },
+        }
+    }
+}
+
+/// Guard that releases the CLI session when the SSE stream is dropped.
 ///
 /// When the HTTP client disconnects (Escape, close tab, network drop), Axum
-/// drops the SSE response stream. This guard detects that drop and sends an
-/// interrupt to the CLI process so it stops generating tokens.
+/// drops the SSE response stream. This guard detects that drop and stops the
+/// CLI process so it stops generating tokens.
 ///
 /// The guard is "defused" (no interrupt) when the stream completes normally
 /// (i.e. a `result` message was received from the CLI).
 struct SseDisconnectGuard {
-    session_manager: Arc<InteractiveSessionManager>,
-    conversation_id: String,
+    action: DisconnectAction,
     /// Set to `true` when the stream completes normally (result received).
     /// When `false` at drop time, the guard fires an interrupt.
     completed_normally: Arc<AtomicBool>,
@@ -40,61 +94,218 @@ struct SseDisconnectGuard {
 
 impl Drop for SseDisconnectGuard {
     fn drop(&mut self) {
-        if self.completed_normally.load(Ordering::SeqCst) {
-            debug!(
-                "SSE stream completed normally for session {}, no interrupt needed",
-                self.conversation_id
-            );
-            return;
-        }
-
-        // Client disconnected before the stream finished — interrupt the CLI
-        let session_manager = self.session_manager.clone();
-        let conversation_id = self.conversation_id.clone();
-
-        warn!(
-            "SSE client disconnected for session {}, sending interrupt to CLI",
-            conversation_id
-        );
+        let completed = self.completed_normally.load(Ordering::SeqCst);
 
-        // We can't await in Drop, so spawn a task.
-        // interrupt_session() is synchronous (uses read lock + try_send),
-        // but we still spawn to avoid blocking the drop.
-        tokio::spawn(async move {
-            match session_manager.interrupt_session(&conversation_id) {
-                Ok(true) => {
-                    info!(
-                        "Auto-interrupted session {} on SSE disconnect",
-                        conversation_id
-                    );
-                },
-                Ok(false) => {
+        match &self.action {
+            DisconnectAction::Interrupt {
+                session_manager,
+                conversation_id,
+            } => {
+                if completed {
                     debug!(
-                        "Session {} not found for auto-interrupt (already closed?)",
+                        "SSE stream completed normally for session {}, no interrupt needed",
                         conversation_id
                     );
-                },
-                Err(e) => {
+                    return;
+                }
+
+                // Client disconnected before the stream finished — interrupt the CLI
+                let session_manager = session_manager.clone();
+                let conversation_id = conversation_id.clone();
+
+                warn!(
+                    "SSE client disconnected for session {}, sending interrupt to CLI",
+                    conversation_id
+                );
+
+                // We can't await in Drop, so spawn a task.
+                // interrupt_session() is synchronous (uses read lock + try_send),
+                // but we still spawn to avoid blocking the drop.
+                tokio::spawn(async move {
+                    match session_manager.interrupt_session(&conversation_id) {
+                        Ok(true) => {
+                            info!(
+                                "Auto-interrupted session {} on SSE disconnect",
+                                conversation_id
+                            );
+                        },
+                        Ok(false) => {
+                            debug!(
+                                "Session {} not found for auto-interrupt (already closed?)",
+                                conversation_id
+                            );
+                        },
+                        Err(e) => {
+                            warn!(
+                                "Failed to auto-interrupt session {} on SSE disconnect: {}",
+                                conversation_id, e
+                            );
+                        },
+                    }
+                });
+            },
+            DisconnectAction::Close {
+                claude_manager,
+                session_id,
+            } => {
+                if !completed {
                     warn!(
-                        "Failed to auto-interrupt session {} on SSE disconnect: {}",
-                        conversation_id, e
+                        "SSE client disconnected for session {}, closing CLI process",
+                        session_id
                     );
-                },
+                }
+
+                let claude_manager = claude_manager.clone();
+                let session_id = session_id.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = claude_manager.close_session(&session_id).await {
+                        debug!("Failed to close session {}: {}", session_id, e);
+                    }
+                });
+            },
+        }
+    }
+}
+
+/// Build a `chat.completion.chunk` with a single choice.
+fn stream_chunk(
+    stream_id: &str,
+    model: &str,
+    delta: DeltaMessage,
+    finish_reason: Option<&str>,
+) -> ChatCompletionStreamResponse {
+    ChatCompletionStreamResponse {
+        id: stream_id.to_string(),
+        object: "chat.completion.chunk".to_string(),
+        created: Utc::now().timestamp(),
+        model: model.to_string(),
+        choices: vec![StreamChoice {
+            index: 0,
+            delta,
+            finish_reason: finish_reason.map(str::to_string),
+        }],
+        usage: None,
+        error: None,
+    }
+}
+
+/// Error carried by an `error` output
+///
+/// Errors raised by the API (e.g. a missed deadline) carry a full
+/// [`ErrorDetail`]; those reported by the CLI session only a message.
+fn output_error(output: &ClaudeCodeOutput) -> ErrorDetail {
+    let error = output.data.get("error");
+    if let Some(detail) = error.and_then(|e| serde_json::from_value(e.clone()).ok()) {
+        return detail;
+    }
+    let message = error.and_then(|e| e.as_str()).unwrap_or("unknown error");
+    ApiError::ClaudeProcess(message.to_string()).detail()
+}
+
+/// Token usage reported by a `result` message.
+fn result_usage(output: &ClaudeCodeOutput) -> Usage {
+    let (prompt_tokens, completion_tokens) = output.token_counts();
+    let prompt_tokens = prompt_tokens as i32;
+    let completion_tokens = completion_tokens as i32;
+
+    Usage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    }
+}
+
+/// Convert a partial-message `stream_event` into a delta.
+///
+/// `tool_calls` maps the Anthropic content block index of each streamed
+/// tool_use to its OpenAI tool call index; `next_tool_call` is the index the
+/// next tool call gets.
+fn partial_delta(
+    event: &Value,
+    tool_calls: &mut HashMap<u64, i32>,
+    next_tool_call: &mut i32,
+) -> Option<DeltaMessage> {
+    let block_index = event.get("index").and_then(|v| v.as_u64());
+
+    match event.get("type")?.as_str()? {
+        "content_block_start" => {
+            let block = event.get("content_block")?;
+            if block.get("type")?.as_str()? != "tool_use" {
+                return None;
             }
-        });
+
+            let index = *next_tool_call;
+            *next_tool_call += 1;
+            tool_calls.insert(block_index?, index);
+
+            Some(DeltaMessage {
+                tool_calls: Some(vec![DeltaToolCall {
+                    index,
+                    id: Some(
+                        block
+                            .get("id")
+                            .and_then(|v| v.as_str())
+                            .map(|s| s.to_string())
+                            .unwrap_or_else(|| format!("call_{}", Uuid::new_v4())),
+                    ),
+                    tool_type: Some("function".to_string()),
+                    function: Some(DeltaFunctionCall {
+                        name: block
+                            .get("name")
+                            .and_then(|v| v.as_str())
+                            .map(|s| s.to_string()),
+                        arguments: Some(String::new()),
+                    }),
+                }]),
+                ..Default::default()
+            })
+        },
+        "content_block_delta" => {
+            let delta = event.get("delta")?;
+            match delta.get("type")?.as_str()? {
+                "text_delta" => Some(DeltaMessage {
+                    content: Some(delta.get("text")?.as_str()?.to_string()),
+                    ..Default::default()
+                }),
+                "input_json_delta" => Some(DeltaMessage {
+                    tool_calls: Some(vec![DeltaToolCall {
+                        index: *tool_calls.get(&block_index?)?,
+                        id: None,
+                        tool_type: None,
+                        function: Some(DeltaFunctionCall {
+                            name: None,
+                            arguments: Some(delta.get("partial_json")?.as_str()?.to_string()),
+                        }),
+                    }]),
+                    ..Default::default()
+                }),
+                _ => None,
+            }
+        },
+        _ => None,
     }
 }
 
 /// Handle streaming response with text chunking for better UX.
 ///
-/// When `session_manager` and `conversation_id` are provided, an
-/// [`SseDisconnectGuard`] is installed that auto-interrupts the CLI if the
-/// HTTP client drops the SSE connection before the stream finishes.
+/// Assistant text is sent as `content` deltas and tool_use blocks as
+/// `tool_calls` deltas. When the CLI runs with partial messages, its
+/// `stream_event` deltas are forwarded as they arrive instead, and the
+/// complete assistant messages that follow them are not sent again. The
+/// top-level `result` ends the stream with a `finish_reason` chunk, followed
+/// by a usage chunk with empty `choices` when `include_usage` is set (the
+/// request's `stream_options.include_usage`). An `error` output ends it with
+/// a chunk whose `error` holds the OpenAI error object and whose `choices`
+/// is empty.
+///
+/// When `on_disconnect` is provided, an [`SseDisconnectGuard`] is installed
+/// that stops the CLI if the HTTP client drops the SSE connection before the
+/// stream finishes.
 pub async fn handle_enhanced_streaming_response(
     model: String,
     mut rx: mpsc::Receiver<ClaudeCodeOutput>,
-    session_manager: Option<Arc<InteractiveSessionManager>>,
-    conversation_id: Option<String>,
+    include_usage: bool,
+    on_disconnect: Option<DisconnectAction>,
 ) -> Pin<Box<dyn Stream<Item = ChatCompletionStreamResponse> + Send>> {
     let stream = async_stream::stream! {
         let stream_id = Uuid::new_v4().to_string();
@@ -103,30 +314,29 @@ pub async fn handle_enhanced_streaming_response(
         // The guard is held alive for the lifetime of the stream.
         // If the stream is dropped (client disconnect), the guard fires.
         let completed_flag = Arc::new(AtomicBool::new(false));
-        let _guard = session_manager.zip(conversation_id).map(|(sm, cid)| {
-            SseDisconnectGuard {
-                session_manager: sm,
-                conversation_id: cid,
-                completed_normally: completed_flag.clone(),
-            }
+        let _guard = on_disconnect.map(|action| SseDisconnectGuard {
+            action,
+            completed_normally: completed_flag.clone(),
         });
 
         // First, send the initial message with role
-        yield ChatCompletionStreamResponse {
-            id: stream_id.clone(),
-            object: "chat.completion.chunk".to_string(),
-            created: Utc::now().timestamp(),
-            model: model.clone(),
-            choices: vec![StreamChoice {
-                index: 0,
-                delta: DeltaMessage {
-                    role: Some("assistant".to_string()),
-                    content: None,
-                    tool_calls: None,
-                },
-                finish_reason: None,
-            }],
-        };
+        yield stream_chunk(
+            &stream_id,
+            &model,
+            DeltaMessage {
+                role: Some("assistant".to_string()),
+                ..Default::default()
+            },
+            None,
+        );
+
+        // Tool call indices are unique across the whole response
+        let mut tool_call_index: i32 = 0;
+        let mut has_content = false;
+        // Partial messages are a per-process CLI flag, so once one arrives
+        // every assistant message is also streamed as partials
+        let mut streaming_partials = false;
+        let mut partial_tool_calls: HashMap<u64, i32> = HashMap::new();
 
         while let Some(output) = rx.recv().await {
             // Skip messages from subagent sidechains (Task tool executions).
@@ -140,19 +350,40 @@ pub async fn handle_enhanced_streaming_response(
             }
 
             match output.r#type.as_str() {
+                "stream_event" => {
+                    streaming_partials = true;
+                    let Some(event) = output.data.get("event") else {
+                        continue;
+                    };
+
+                    if event.get("type").and_then(|t| t.as_str()) == Some("message_start") {
+                        // Block indices restart with every API message
+                        partial_tool_calls.clear();
+                    }
+
+                    if let Some(delta) =
+                        partial_delta(event, &mut partial_tool_calls, &mut tool_call_index)
+                    {
+                        has_content |= delta.content.is_some();
+                        yield stream_chunk(&stream_id, &model, delta, None);
+                    }
+                },
+                "assistant" if streaming_partials => {
+                    debug!("Streaming: assistant message already sent as partial messages");
+                },
                 "assistant" => {
                     // Extract content blocks (text + tool_use) from the assistant message
                     if let Some(message) = output.data.get("message")
                         && let Some(content_array) = message.get("content").and_then(|c| c.as_array()) {
 
-                        let mut tool_call_index: i32 = 0;
-
                         for content in content_array {
                             let block_type = content.get("type").and_then(|t| t.as_str()).unwrap_or("");
 
                             match block_type {
                                 "text" => {
                                     if let Some(text) = content.get("text").and_then(|t| t.as_str()) {
+                                        has_content |= !text.is_empty();
+
                                         // Chunk the text for streaming
                                         let config = ChunkConfig {
                                             chunk_size: 15,  // Smaller chunks for better streaming effect
@@ -163,21 +394,15 @@ pub async fn handle_enhanced_streaming_response(
                                         let mut chunker = chunk_text(text.to_string(), Some(config));
 
                                         while let Some(chunk) = chunker.next().await {
-                                            yield ChatCompletionStreamResponse {
-                                                id: stream_id.clone(),
-                                                object: "chat.completion.chunk".to_string(),
-                                                created: Utc::now().timestamp(),
-                                                model: model.clone(),
-                                                choices: vec![StreamChoice {
-                                                    index: 0,
-                                                    delta: DeltaMessage {
-                                                        role: None,
-                                                        content: Some(chunk),
-                                                        tool_calls: None,
-                                                    },
-                                                    finish_reason: None,
-                                                }],
-                                            };
+                                            yield stream_chunk(
+                                                &stream_id,
+                                                &model,
+                                                DeltaMessage {
+                                                    content: Some(chunk),
+                                                    ..Default::default()
+                                                },
+                                                None,
+                                            );
                                         }
                                     }
                                 },
@@ -202,29 +427,23 @@ pub async fn handle_enhanced_streaming_response(
 
                                     // Send the complete tool call in a single chunk
                                     // (Claude CLI gives us complete tool_use, not incremental)
-                                    yield ChatCompletionStreamResponse {
-                                        id: stream_id.clone(),
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: Utc::now().timestamp(),
-                                        model: model.clone(),
-                                        choices: vec![StreamChoice {
-                                            index: 0,
-                                            delta: DeltaMessage {
-                                                role: None,
-                                                content: None,
-                                                tool_calls: Some(vec![DeltaToolCall {
-                                                    index: tool_call_index,
-                                                    id: Some(tool_id),
-                                                    tool_type: Some("function".to_string()),
-                                                    function: Some(DeltaFunctionCall {
-                                                        name: Some(tool_name),
-                                                        arguments: Some(tool_input.to_string()),
-                                                    }),
-                                                }]),
-                                            },
-                                            finish_reason: None,
-                                        }],
-                                    };
+                                    yield stream_chunk(
+                                        &stream_id,
+                                        &model,
+                                        DeltaMessage {
+                                            tool_calls: Some(vec![DeltaToolCall {
+                                                index: tool_call_index,
+                                                id: Some(tool_id),
+                                                tool_type: Some("function".to_string()),
+                                                function: Some(DeltaFunctionCall {
+                                                    name: Some(tool_name),
+                                                    arguments: Some(tool_input.to_string()),
+                                                }),
+                                            }]),
+                                            ..Default::default()
+                                        },
+                                        None,
+                                    );
 
                                     tool_call_index += 1;
                                 },
@@ -239,18 +458,40 @@ pub async fn handle_enhanced_streaming_response(
                     // Defuse the disconnect guard — stream completed normally
                     completed_flag.store(true, Ordering::SeqCst);
 
-                    // Send the final chunk with finish_reason
+                    // Same rule as the non-streaming response
+                    let finish_reason = if tool_call_index > 0 && !has_content {
+                        "tool_calls"
+                    } else {
+                        "stop"
+                    };
+                    yield stream_chunk(&stream_id, &model, DeltaMessage::default(), Some(finish_reason));
+
+                    if include_usage {
+                        yield ChatCompletionStreamResponse {
+                            id: stream_id.clone(),
+                            object: "chat.completion.chunk".to_string(),
+                            created: Utc::now().timestamp(),
+                            model: model.clone(),
+                            choices: Vec::new(),
+                            usage: Some(result_usage(&output)),
+                            error: None,
+                        };
+                    }
+                    break;
+                }
+                "error" => {
+                    // The turn failed midway: tell the client before closing,
+                    // leaving the disconnect guard armed to stop the turn
                     yield ChatCompletionStreamResponse {
                         id: stream_id.clone(),
                         object: "chat.completion.chunk".to_string(),
                         created: Utc::now().timestamp(),
                         model: model.clone(),
-                        choices: vec![StreamChoice {
-                            index: 0,
-                            delta: DeltaMessage::default(),
-                            finish_reason: Some("stop".to_string()),
-                        }],
+                        choices: Vec::new(),
+                        usage: None,
+                        error: Some(output_error(&output)),
                     };
+                    break;
                 }
                 _ => {}
             }
@@ -278,3 +519,210 @@ impl Default for StreamingConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn output(r#type: &str, data: Value) -> ClaudeCodeOutput {
+        ClaudeCodeOutput {
+            r#type: r#type.to_string(),
+            subtype: None,
+            data,
+        }
+    }
+
+    fn result() -> ClaudeCodeOutput {
+        output(
+            "result",
+            json!({
+                "is_error": false,
+                "usage": {"input_tokens": 10, "cache_read_input_tokens": 5, "output_tokens": 7}
+            }),
+        )
+    }
+
+    async fn collect(outputs: Vec<ClaudeCodeOutput>) -> Vec<ChatCompletionStreamResponse> {
+        collect_with_usage(outputs, false).await
+    }
+
+    async fn collect_with_usage(
+        outputs: Vec<ClaudeCodeOutput>,
+        include_usage: bool,
+    ) -> Vec<ChatCompletionStreamResponse> {
+        let (tx, rx) = mpsc::channel(outputs.len().max(1));
+        for output in outputs {
+            tx.send(output).await.unwrap();
+        }
+        drop(tx);
+        handle_enhanced_streaming_response("claude".to_string(), rx, include_usage, None)
+            .await
+            .collect()
+            .await
+    }
+
+    fn content(chunks: &[ChatCompletionStreamResponse]) -> String {
+        chunks
+            .iter()
+            .flat_map(|c| &c.choices)
+            .filter_map(|c| c.delta.content.as_deref())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_stream_ends_with_finish_and_usage_chunks() {
+        let outputs = vec![
+            output(
+                "assistant",
+                json!({"message": {"content": [{"type": "text", "text": "Hello there"}]}}),
+            ),
+            result(),
+            // Anything after the result belongs to another turn
+            output(
+                "assistant",
+                json!({"message": {"content": [{"type": "text", "text": "late"}]}}),
+            ),
+        ];
+
+        // Without stream_options.include_usage the finish chunk is the last
+        let chunks = collect(outputs.clone()).await;
+        let last = chunks.last().unwrap();
+        assert_eq!(last.choices[0].finish_reason.as_deref(), Some("stop"));
+        assert!(chunks.iter().all(|c| c.usage.is_none()));
+
+        let chunks = collect_with_usage(outputs, true).await;
+
+        assert_eq!(
+            chunks[0].choices[0].delta.role.as_deref(),
+            Some("assistant")
+        );
+        assert_eq!(content(&chunks), "Hello there");
+
+        let finish = &chunks[chunks.len() - 2];
+        assert_eq!(finish.choices[0].finish_reason.as_deref(), Some("stop"));
+
+        let last = chunks.last().unwrap();
+        assert!(last.choices.is_empty());
+        let usage = last.usage.as_ref().unwrap();
+        assert_eq!(
+            (
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                usage.total_tokens
+            ),
+            (15, 7, 22)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tool_use_streams_as_tool_calls() {
+        let chunks = collect(vec![
+            output(
+                "assistant",
+                json!({"message": {"content": [
+                    {"type": "tool_use", "id": "toolu_1", "name": "Read", "input": {"path": "a"}}
+                ]}}),
+            ),
+            output(
+                "assistant",
+                json!({"message": {"content": [
+                    {"type": "tool_use", "id": "toolu_2", "name": "Read", "input": {"path": "b"}}
+                ]}}),
+            ),
+            result(),
+        ])
+        .await;
+
+        let calls: Vec<_> = chunks
+            .iter()
+            .flat_map(|c| &c.choices)
+            .filter_map(|c| c.delta.tool_calls.as_ref())
+            .flatten()
+            .collect();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(
+            (calls[0].index, calls[0].id.as_deref()),
+            (0, Some("toolu_1"))
+        );
+        assert_eq!(
+            (calls[1].index, calls[1].id.as_deref()),
+            (1, Some("toolu_2"))
+        );
+
+        let finish = chunks.last().unwrap();
+        assert_eq!(
+            finish.choices[0].finish_reason.as_deref(),
+            Some("tool_calls")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_partial_messages_are_not_sent_twice() {
+        let event = |event: Value| output("stream_event", json!({"event": event}));
+        let chunks = collect(vec![
+            event(json!({"type": "message_start", "message": {}})),
+            event(json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": "Hel"}})),
+            event(json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": "lo"}})),
+            event(json!({"type": "content_block_start", "index": 1,
+                "content_block": {"type": "tool_use", "id": "toolu_1", "name": "Read", "input": {}}})),
+            event(json!({"type": "content_block_delta", "index": 1,
+                "delta": {"type": "input_json_delta", "partial_json": "{\"path\":"}})),
+            event(json!({"type": "content_block_delta", "index": 1,
+                "delta": {"type": "input_json_delta", "partial_json": "\"a\"}"}})),
+            output(
+                "assistant",
+                json!({"message": {"content": [
+                    {"type": "text", "text": "Hello"},
+                    {"type": "tool_use", "id": "toolu_1", "name": "Read", "input": {"path": "a"}}
+                ]}}),
+            ),
+            result(),
+        ])
+        .await;
+
+        assert_eq!(content(&chunks), "Hello");
+
+        let calls: Vec<_> = chunks
+            .iter()
+            .flat_map(|c| &c.choices)
+            .filter_map(|c| c.delta.tool_calls.as_ref())
+            .flatten()
+            .collect();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0].id.as_deref(), Some("toolu_1"));
+        assert!(calls.iter().all(|c| c.index == 0));
+        let arguments: String = calls
+            .iter()
+            .filter_map(|c| c.function.as_ref()?.arguments.as_deref())
+            .collect();
+        assert_eq!(arguments, r#"{"path":"a"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_error_output_ends_stream_with_error_chunk() {
+        let timeout = ApiError::Timeout("Response not complete within 1 seconds".to_string());
+        let chunks = collect(vec![
+            output(
+                "assistant",
+                json!({"message": {"content": [{"type": "text", "text": "Partial"}]}}),
+            ),
+            output("error", json!({"error": timeout.detail()})),
+            result(),
+        ])
+        .await;
+
+        assert_eq!(content(&chunks), "Partial");
+        let last = chunks.last().unwrap();
+        assert!(last.choices.is_empty());
+        assert_eq!(last.error.as_ref(), Some(&timeout.detail()));
+
+        // CLI session errors only carry a message
+        let chunks = collect(vec![output("error", json!({"error": "CLI exited"}))]).await;
+        let error = chunks.last().unwrap().error.as_ref().unwrap();
+        assert_eq!(error.r#type, "claude_process_error");
+        assert!(error.message.contains("CLI exited"));
+    }
+}
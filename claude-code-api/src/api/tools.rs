@@ -0,0 +1,169 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::{
+    core::config::{MCPConfig, McpConfigSource, Settings},
+    models::error::ApiResult,
+};
+
+#[derive(Clone)]
+pub struct ToolsState {
+    /// Computed at startup, so the MCP config file is read only once
+    pub tools: Arc<AvailableTools>,
+}
+
+impl ToolsState {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            tools: Arc::new(AvailableTools::from_settings(settings)),
+        }
+    }
+}
+
+/// Effective tool configuration of the CLI processes spawned by the gateway
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AvailableTools {
+    pub object: &'static str,
+    /// Base tool set; `None` means the CLI's default tools
+    pub tools: Option<Vec<String>>,
+    pub allowed_tools: Vec<String>,
+    pub disallowed_tools: Vec<String>,
+    /// MCP servers whose tools are exposed as `mcp__<server>__<tool>`
+    pub mcp_servers: Vec<String>,
+    pub skip_permissions: bool,
+}
+
+impl AvailableTools {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            object: "tools",
+            tools: settings.tools.available.clone(),
+            allowed_tools: settings.tools.allowed.clone(),
+            disallowed_tools: settings.tools.disallowed.clone(),
+            mcp_servers: mcp_server_names(&settings.mcp),
+            skip_permissions: settings.file_access.skip_permissions,
+        }
+    }
+}
+
+/// Names of the servers declared in the MCP configuration the CLI loads, sorted
+fn mcp_server_names(mcp: &MCPConfig) -> Vec<String> {
+    let raw = match mcp.source() {
+        Some(McpConfigSource::Json(json)) => json.to_string(),
+        Some(McpConfigSource::File(path)) => match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Failed to read MCP config file {}: {}", path, e);
+                return Vec::new();
+            },
+        },
+        None => return Vec::new(),
+    };
+
+    let config: serde_json::Value = match serde_json::from_str(&raw) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Failed to parse MCP config: {}", e);
+            return Vec::new();
+        },
+    };
+
+    let mut names: Vec<String> = config
+        .get("mcpServers")
+        .and_then(|servers| servers.as_object())
+        .map(|servers| servers.keys().cloned().collect())
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+pub async fn list_tools(State(state): State<ToolsState>) -> ApiResult<impl IntoResponse> {
+    Ok(Json(AvailableTools::clone(&state.tools)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, routing::get};
+    use axum_test::TestServer;
+    use serde_json::json;
+
+    fn settings(overrides: serde_json::Value) -> Settings {
+        let mut value = json!({
+            "server": { "host": "127.0.0.1", "port": 8080 },
+            "claude": {
+                "command": "claude",
+                "timeout_seconds": 300,
+                "max_concurrent_sessions": 10,
+            },
+            "auth": {
+                "enabled": false,
+                "secret_key": "test",
+                "token_expiry_hours": 24,
+            },
+        });
+        for (key, section) in overrides.as_object().unwrap() {
+            value[key] = section.clone();
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_list_tools_reflects_settings() {
+        let settings = settings(json!({
+            "tools": {
+                "available": ["Read", "Edit", "Bash"],
+                "allowed": ["Read"],
+                "disallowed": ["WebSearch"],
+            },
+            "mcp": {
+                "enabled": true,
+                "config_json": r#"{"mcpServers":{"github":{},"filesystem":{}}}"#,
+                "strict": false,
+                "debug": false,
+            },
+            "file_access": { "skip_permissions": true, "additional_dirs": [] },
+        }));
+
+        let app = Router::new()
+            .route("/v1/tools", get(list_tools))
+            .with_state(ToolsState::new(&settings));
+        let server = TestServer::new(app).unwrap();
+
+        let body: serde_json::Value = server.get("/v1/tools").await.json();
+        assert_eq!(body["tools"], json!(["Read", "Edit", "Bash"]));
+        assert_eq!(body["allowed_tools"], json!(["Read"]));
+        assert_eq!(body["disallowed_tools"], json!(["WebSearch"]));
+        assert_eq!(body["mcp_servers"], json!(["filesystem", "github"]));
+        assert_eq!(body["skip_permissions"], json!(true));
+    }
+
+    #[test]
+    fn test_mcp_config_file_wins_over_inline_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mcp.json");
+        std::fs::write(&path, r#"{"mcpServers":{"from_file":{}}}"#).unwrap();
+
+        let tools = AvailableTools::from_settings(&settings(json!({
+            "mcp": {
+                "enabled": true,
+                "config_file": path.to_str().unwrap(),
+                "config_json": r#"{"mcpServers":{"inline":{}}}"#,
+                "strict": false,
+                "debug": false,
+            },
+        })));
+        assert_eq!(tools.mcp_servers, ["from_file"]);
+    }
+
+    #[test]
+    fn test_default_settings_use_cli_defaults() {
+        let tools = AvailableTools::from_settings(&settings(json!({})));
+        assert_eq!(tools.tools, None);
+        assert!(tools.allowed_tools.is_empty());
+        assert!(tools.disallowed_tools.is_empty());
+        assert!(tools.mcp_servers.is_empty());
+    }
+}
@@ -9,9 +9,48 @@ use tokio::sync::mpsc;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use crate::core::config::{FileAccessConfig, MCPConfig};
+use crate::core::config::{FileAccessConfig, MCPConfig, ToolsSettings};
 use crate::models::claude::ClaudeCodeOutput;
 
+/// Export the gateway request id to a spawned CLI so its logs can be correlated
+pub fn apply_request_id(cmd: &mut Command, request_id: Option<&str>) {
+    if let Some(request_id) = request_id {
+        cmd.env(nexus_claude::REQUEST_ID_ENV_VAR, request_id);
+    }
+}
+
+/// Pass the configured tool set and permissions to a spawned CLI
+pub fn apply_tool_settings(cmd: &mut Command, tools: &ToolsSettings) {
+    if let Some(ref available) = tools.available {
+        cmd.arg("--tools").arg(available.join(","));
+    }
+    if !tools.allowed.is_empty() {
+        cmd.arg("--allowedTools").arg(tools.allowed.join(","));
+    }
+    if !tools.disallowed.is_empty() {
+        cmd.arg("--disallowedTools").arg(tools.disallowed.join(","));
+    }
+}
+
+/// Pass the configured MCP servers to a spawned CLI
+pub fn apply_mcp_config(cmd: &mut Command, mcp: &MCPConfig) {
+    if !mcp.enabled {
+        return;
+    }
+
+    if let Some(source) = mcp.source() {
+        cmd.arg("--mcp-config").arg(source.as_arg());
+    }
+
+    if mcp.strict {
+        cmd.arg("--strict-mcp-config");
+    }
+
+    if mcp.debug {
+        cmd.arg("--debug");
+    }
+}
+
 pub struct ClaudeProcess {
     #[allow(dead_code)]
     pub id: String,
@@ -23,9 +62,9 @@ pub struct ClaudeProcess {
 pub struct ClaudeManager {
     processes: Arc<RwLock<HashMap<String, ClaudeProcess>>>,
     claude_command: String,
-    #[allow(dead_code)]
     file_access_config: FileAccessConfig,
     mcp_config: MCPConfig,
+    tools: ToolsSettings,
 }
 
 impl ClaudeManager {
@@ -33,15 +72,32 @@ impl ClaudeManager {
         claude_command: String,
         file_access_config: FileAccessConfig,
         mcp_config: MCPConfig,
+        tools: ToolsSettings,
     ) -> Self {
         Self {
             processes: Arc::new(RwLock::new(HashMap::new())),
             claude_command,
             file_access_config,
             mcp_config,
+            tools,
         }
     }
 
+    /// Tool configuration applied to every spawned CLI process
+    pub fn tool_settings(&self) -> &ToolsSettings {
+        &self.tools
+    }
+
+    /// MCP configuration applied to every spawned CLI process
+    pub fn mcp_config(&self) -> &MCPConfig {
+        &self.mcp_config
+    }
+
+    /// File access configuration applied to every spawned CLI process
+    pub fn file_access_config(&self) -> &FileAccessConfig {
+        &self.file_access_config
+    }
+
     #[allow(dead_code)]
     pub async fn create_interactive_session(
         &self,
@@ -154,6 +210,7 @@ impl ClaudeManager {
         project_path: Option<String>,
         model: Option<String>,
         message: &str,
+        request_id: Option<&str>,
     ) -> Result<(String, mpsc::Receiver<ClaudeCodeOutput>)> {
         let session_id = session_id.unwrap_or_else(|| Uuid::new_v4().to_string());
 
@@ -173,25 +230,15 @@ impl ClaudeManager {
         // 默认跳过权限检查以提高性能
         cmd.arg("--dangerously-skip-permissions");
 
-        if self.mcp_config.enabled {
-            if let Some(ref config_file) = self.mcp_config.config_file {
-                cmd.arg("--mcp-config").arg(config_file);
-            } else if let Some(ref config_json) = self.mcp_config.config_json {
-                cmd.arg("--mcp-config").arg(config_json);
-            }
+        apply_tool_settings(&mut cmd, &self.tools);
 
-            if self.mcp_config.strict {
-                cmd.arg("--strict-mcp-config");
-            }
-
-            if self.mcp_config.debug {
-                cmd.arg("--debug");
-            }
-        }
+        apply_mcp_config(&mut cmd, &self.mcp_config);
 
         // 不要将 message 作为命令行参数
         // cmd.arg(message);
 
+        apply_request_id(&mut cmd, request_id);
+
         cmd.stdin(Stdio::piped())  // 改为 piped 以便写入
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
@@ -375,3 +422,72 @@ impl Drop for ClaudeManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_id_env(cmd: &Command) -> Option<String> {
+        cmd.as_std()
+            .get_envs()
+            .find(|(key, _)| *key == nexus_claude::REQUEST_ID_ENV_VAR)
+            .and_then(|(_, value)| value)
+            .map(|value| value.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn test_apply_request_id_sets_child_env() {
+        let mut cmd = Command::new("claude");
+        apply_request_id(&mut cmd, Some("req-1234"));
+        assert_eq!(request_id_env(&cmd).as_deref(), Some("req-1234"));
+    }
+
+    #[test]
+    fn test_apply_request_id_without_id() {
+        let mut cmd = Command::new("claude");
+        apply_request_id(&mut cmd, None);
+        assert_eq!(request_id_env(&cmd), None);
+    }
+
+    #[test]
+    fn test_apply_mcp_config_passes_inline_json() {
+        let mcp = MCPConfig {
+            enabled: true,
+            config_file: None,
+            config_json: Some(r#"{"mcpServers":{"github":{}}}"#.to_string()),
+            strict: true,
+            debug: false,
+        };
+        let mut cmd = Command::new("claude");
+        apply_mcp_config(&mut cmd, &mcp);
+
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            args,
+            [
+                "--mcp-config",
+                r#"{"mcpServers":{"github":{}}}"#,
+                "--strict-mcp-config"
+            ]
+        );
+
+        // A config file wins over inline JSON
+        let mcp = MCPConfig {
+            config_file: Some("/etc/mcp.json".to_string()),
+            strict: false,
+            ..mcp
+        };
+        let mut cmd = Command::new("claude");
+        apply_mcp_config(&mut cmd, &mcp);
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(args, ["--mcp-config", "/etc/mcp.json"]);
+    }
+}
@@ -13,6 +13,10 @@ pub struct Settings {
     pub mcp: MCPConfig,
     #[serde(default)]
     pub process_pool: ProcessPoolConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub tools: ToolsSettings,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -28,6 +32,14 @@ pub struct ClaudeConfig {
     pub max_concurrent_sessions: usize,
     #[serde(default)]
     pub use_interactive_sessions: bool,
+    /// Limit on a whole chat completion turn, in seconds; defaults to `timeout_seconds`
+    #[serde(default)]
+    pub request_timeout_secs: Option<u64>,
+    /// Limit on the wait for a turn's first output, in seconds
+    ///
+    /// Catches a dead model without cutting off long but progressing answers.
+    #[serde(default)]
+    pub first_token_timeout_secs: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -52,6 +64,53 @@ pub struct MCPConfig {
     pub debug: bool,
 }
 
+/// Where the MCP server configuration given to the CLI comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum McpConfigSource<'a> {
+    /// Path of a JSON file
+    File(&'a str),
+    /// Inline JSON
+    Json(&'a str),
+}
+
+impl McpConfigSource<'_> {
+    /// Value of the CLI's `--mcp-config` argument
+    pub fn as_arg(&self) -> &str {
+        match self {
+            Self::File(path) => path,
+            Self::Json(json) => json,
+        }
+    }
+}
+
+impl MCPConfig {
+    /// The configuration the CLI loads, `None` when MCP is disabled
+    ///
+    /// `config_file` wins over `config_json` when both are set.
+    pub fn source(&self) -> Option<McpConfigSource<'_>> {
+        if !self.enabled {
+            return None;
+        }
+        self.config_file
+            .as_deref()
+            .map(McpConfigSource::File)
+            .or_else(|| self.config_json.as_deref().map(McpConfigSource::Json))
+    }
+}
+
+/// Tool configuration passed to every spawned CLI process
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ToolsSettings {
+    /// Base tool set (`--tools`); `None` keeps the CLI's default tools
+    pub available: Option<Vec<String>>,
+    /// Tools auto-approved without a permission prompt (`--allowedTools`)
+    #[serde(default)]
+    pub allowed: Vec<String>,
+    /// Tools removed entirely (`--disallowedTools`)
+    #[serde(default)]
+    pub disallowed: Vec<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ProcessPoolConfig {
     pub size: usize,
@@ -69,6 +128,56 @@ impl Default for ProcessPoolConfig {
     }
 }
 
+/// Conversation storage backend selected at startup
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    #[default]
+    InMemory,
+    Neo4j,
+    Meilisearch,
+    Combined,
+}
+
+impl StorageBackend {
+    /// Name of the backend as written in the configuration
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InMemory => "in_memory",
+            Self::Neo4j => "neo4j",
+            Self::Meilisearch => "meilisearch",
+            Self::Combined => "combined",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackend,
+    pub neo4j: Option<Neo4jSettings>,
+    pub meilisearch: Option<MeilisearchSettings>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Neo4jSettings {
+    pub uri: String,
+    pub user: String,
+    pub password: String,
+    #[serde(default = "default_neo4j_max_connections")]
+    pub max_connections: usize,
+}
+
+fn default_neo4j_max_connections() -> usize {
+    10
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MeilisearchSettings {
+    pub url: String,
+    pub api_key: Option<String>,
+}
+
 impl Settings {
     pub fn new() -> Result<Self, ConfigError> {
         let run_mode = env::var("RUN_MODE").unwrap_or_else(|_| "development".into());
@@ -88,6 +197,7 @@ impl Settings {
             .set_default("mcp.enabled", false)?
             .set_default("mcp.strict", false)?
             .set_default("mcp.debug", false)?
+            .set_default("storage.backend", "in_memory")?
             .add_source(File::with_name(&format!("config/{run_mode}")).required(false))
             .add_source(File::with_name("config/local").required(false))
             .add_source(Environment::with_prefix("CLAUDE_CODE").separator("__"))
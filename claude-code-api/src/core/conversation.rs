@@ -4,11 +4,13 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::info;
 
-use crate::core::storage::{ConversationStore, InMemoryConversationStore};
+use crate::core::storage::{ConversationStore, DynConversationStore};
 use crate::models::openai::{ChatMessage, MessageContent};
 
-/// Type alias for the default ConversationManager using in-memory storage
-pub type DefaultConversationManager = ConversationManager<InMemoryConversationStore>;
+/// Type alias for the ConversationManager used by the gateway
+///
+/// The store is type-erased so the backend can be selected from `Settings` at startup.
+pub type DefaultConversationManager = ConversationManager<DynConversationStore>;
 
 /// Configuration for the conversation manager
 #[derive(Clone)]
@@ -9,8 +9,10 @@ use tokio::sync::{broadcast, mpsc};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use crate::core::claude_manager::ClaudeManager;
-use crate::core::config::{FileAccessConfig, MCPConfig};
+use crate::core::claude_manager::{
+    ClaudeManager, apply_mcp_config, apply_request_id, apply_tool_settings,
+};
+use crate::core::config::{FileAccessConfig, MCPConfig, ToolsSettings};
 use crate::models::claude::ClaudeCodeOutput;
 
 /// Interactive session manager — reuses one Claude CLI process per session.
@@ -56,10 +58,12 @@ pub struct InteractiveSessionManager {
     claude_command: String,
     file_access_config: FileAccessConfig,
     mcp_config: MCPConfig,
+    tools: ToolsSettings,
+    /// Conversation ids of sessions that were closed or cleaned up
+    closed_tx: broadcast::Sender<String>,
 }
 
 struct InteractiveSession {
-    #[allow(dead_code)]
     id: String,
     #[allow(dead_code)]
     conversation_id: String,
@@ -105,20 +109,23 @@ fn build_process_died_event(reason: &str) -> ClaudeCodeOutput {
 }
 
 impl InteractiveSessionManager {
-    pub fn new(_claude_manager: Arc<ClaudeManager>, claude_command: String) -> Self {
+    pub fn new(claude_manager: Arc<ClaudeManager>, claude_command: String) -> Self {
         let manager = Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
             claude_command,
-            file_access_config: FileAccessConfig::default(),
-            mcp_config: MCPConfig::default(),
+            file_access_config: claude_manager.file_access_config().clone(),
+            mcp_config: claude_manager.mcp_config().clone(),
+            tools: claude_manager.tool_settings().clone(),
+            closed_tx: broadcast::channel(100).0,
         };
 
         // Start background cleanup task
         let sessions_clone = manager.sessions.clone();
+        let closed_tx = manager.closed_tx.clone();
         tokio::spawn(async move {
             loop {
                 tokio::time::sleep(tokio::time::Duration::from_secs(300)).await; // every 5 min
-                Self::cleanup_expired_sessions(sessions_clone.clone(), 30).await; // 30 min timeout
+                Self::cleanup_expired_sessions(sessions_clone.clone(), 30, &closed_tx).await; // 30 min timeout
             }
         });
 
@@ -135,6 +142,7 @@ impl InteractiveSessionManager {
         conversation_id: Option<String>,
         model: String,
         message: String,
+        request_id: Option<String>,
     ) -> Result<(String, mpsc::Receiver<ClaudeCodeOutput>)> {
         let conversation_id = conversation_id.unwrap_or_else(|| Uuid::new_v4().to_string());
 
@@ -186,13 +194,21 @@ impl InteractiveSessionManager {
                     message,
                     response_tx,
                     true, // continue_conversation
+                    request_id.as_deref(),
                 )
                 .await?;
             },
             SessionStatus::NotFound => {
                 info!("Creating new interactive session: {}", conversation_id);
-                self.create_session(conversation_id.clone(), model, message, response_tx, false)
-                    .await?;
+                self.create_session(
+                    conversation_id.clone(),
+                    model,
+                    message,
+                    response_tx,
+                    false,
+                    request_id.as_deref(),
+                )
+                .await?;
             },
         }
 
@@ -202,8 +218,8 @@ impl InteractiveSessionManager {
     /// Send a message to an existing (alive) session.
     ///
     /// Spawns a background task that acquires the interaction lock, subscribes
-    /// to the broadcast channel, sends the message, and collects responses
-    /// until a `result` or `error` message is received.
+    /// to the broadcast channel, sends the message, and forwards responses as
+    /// they arrive until a `result` or `error` message is received.
     async fn send_to_existing_session(
         &self,
         conversation_id: String,
@@ -239,8 +255,12 @@ impl InteractiveSessionManager {
                 // Spawn response collector
                 // Uses Result message detection instead of timeout heuristic.
                 // Sidechain messages (from Task tool subagents) are filtered out.
+                // Outputs are forwarded right away so the caller's deadlines
+                // see the first token, not the end of the turn. The collector
+                // keeps reading after the caller hangs up so the lock is only
+                // released once the turn is over.
+                let collector_tx = response_tx.clone();
                 let response_handle = tokio::spawn(async move {
-                    let mut responses = Vec::new();
                     let start_time = std::time::Instant::now();
 
                     loop {
@@ -262,20 +282,28 @@ impl InteractiveSessionManager {
                                     continue;
                                 }
 
-                                responses.push(output.clone());
+                                let r#type = output.r#type.clone();
+                                collector_tx.send(output).await.ok();
 
                                 // Detect end-of-response via Result message
-                                if output.r#type == "result" {
+                                if r#type == "result" {
                                     info!("Response complete (received result message)");
                                     break;
                                 }
 
                                 // Also break on error
-                                if output.r#type == "error" {
+                                if r#type == "error" {
                                     break;
                                 }
                             },
-                            Ok(Err(_)) => {
+                            Ok(Err(broadcast::error::RecvError::Lagged(skipped))) => {
+                                // The caller reads slower than the CLI writes
+                                warn!(
+                                    "Interactive: caller fell behind, dropped {} outputs",
+                                    skipped
+                                );
+                            },
+                            Ok(Err(broadcast::error::RecvError::Closed)) => {
                                 // Broadcast channel closed
                                 break;
                             },
@@ -289,8 +317,6 @@ impl InteractiveSessionManager {
                             },
                         }
                     }
-
-                    responses
                 });
 
                 // Send message
@@ -306,16 +332,12 @@ impl InteractiveSessionManager {
                         })
                         .await
                         .ok();
+                    response_handle.abort();
                     return;
                 }
 
-                // Wait for response collection to complete
-                let responses = response_handle.await.unwrap_or_default();
-
-                // Forward responses to caller
-                for output in responses {
-                    response_tx.send(output).await.ok();
-                }
+                // Wait for the turn to complete
+                response_handle.await.ok();
 
                 // Close channel
                 drop(response_tx);
@@ -336,6 +358,7 @@ impl InteractiveSessionManager {
         initial_message: String,
         initial_response_tx: mpsc::Sender<ClaudeCodeOutput>,
         continue_conversation: bool,
+        request_id: Option<&str>,
     ) -> Result<()> {
         let mut cmd = Command::new(&self.claude_command);
 
@@ -353,11 +376,12 @@ impl InteractiveSessionManager {
         }
 
         // MCP configuration
-        if self.mcp_config.enabled
-            && let Some(ref config_file) = self.mcp_config.config_file
-        {
-            cmd.arg("--mcp-config").arg(config_file);
-        }
+        apply_mcp_config(&mut cmd, &self.mcp_config);
+
+        apply_tool_settings(&mut cmd, &self.tools);
+
+        // The process outlives this request, so only the request that spawned it is recorded
+        apply_request_id(&mut cmd, request_id);
 
         cmd.stdin(Stdio::piped())
             .stdout(Stdio::piped())
@@ -589,6 +613,7 @@ impl InteractiveSessionManager {
     async fn cleanup_expired_sessions(
         sessions: Arc<RwLock<HashMap<String, InteractiveSession>>>,
         timeout_minutes: u64,
+        closed_tx: &broadcast::Sender<String>,
     ) {
         let now = std::time::Instant::now();
         let timeout = std::time::Duration::from_secs(timeout_minutes * 60);
@@ -637,9 +662,16 @@ impl InteractiveSessionManager {
                 }
             }
             let _ = session.child.kill().await;
+            let _ = closed_tx.send(id);
         }
     }
 
+    /// Subscribe to the conversation ids of sessions as they are closed,
+    /// explicitly or by the idle/dead-process cleanup
+    pub fn subscribe_closed(&self) -> broadcast::Receiver<String> {
+        self.closed_tx.subscribe()
+    }
+
     /// Interrupt the active request in a session without closing it.
     ///
     /// Sends a `control_request` interrupt to the CLI via `stdin_tx` (lock-free,
@@ -695,7 +727,6 @@ impl InteractiveSessionManager {
     }
 
     /// Close a specific session.
-    #[allow(dead_code)]
     pub async fn close_session(&self, conversation_id: &str) -> Result<()> {
         let session_opt = {
             let mut sessions = self.sessions.write();
@@ -710,6 +741,7 @@ impl InteractiveSessionManager {
                     libc::kill(-(pid as i32), libc::SIGKILL);
                 }
             }
+            let _ = self.closed_tx.send(conversation_id.to_string());
             session.child.kill().await?;
             Ok(())
         } else {
@@ -727,6 +759,18 @@ impl InteractiveSessionManager {
         Ok(())
     }
 
+    /// Id of the session currently bound to `conversation_id`, if any.
+    ///
+    /// Follow-up requests for the same conversation are routed to this session
+    /// until it dies or is reaped after being idle.
+    #[allow(dead_code)]
+    pub fn session_id(&self, conversation_id: &str) -> Option<String> {
+        self.sessions
+            .read()
+            .get(conversation_id)
+            .map(|session| session.id.clone())
+    }
+
     /// Get the number of active sessions.
     #[allow(dead_code)]
     pub fn active_sessions(&self) -> usize {
@@ -891,7 +935,12 @@ mod tests {
         assert_eq!(sessions.read().len(), 1);
 
         // Run cleanup with a very long timeout (so only dead detection triggers)
-        InteractiveSessionManager::cleanup_expired_sessions(sessions.clone(), 9999).await;
+        InteractiveSessionManager::cleanup_expired_sessions(
+            sessions.clone(),
+            9999,
+            &broadcast::channel(1).0,
+        )
+        .await;
 
         assert_eq!(
             sessions.read().len(),
@@ -936,7 +985,12 @@ mod tests {
         sessions.write().insert("conv-alive".to_string(), session);
 
         // Cleanup with long timeout — alive process should stay
-        InteractiveSessionManager::cleanup_expired_sessions(sessions.clone(), 9999).await;
+        InteractiveSessionManager::cleanup_expired_sessions(
+            sessions.clone(),
+            9999,
+            &broadcast::channel(1).0,
+        )
+        .await;
 
         assert_eq!(
             sessions.read().len(),
@@ -988,13 +1042,15 @@ mod tests {
 
         // Use timeout_minutes=0 so ANY session is immediately "expired".
         // This avoids Instant subtraction overflow on Windows.
-        InteractiveSessionManager::cleanup_expired_sessions(sessions.clone(), 0).await;
+        let (closed_tx, mut closed_rx) = broadcast::channel(1);
+        InteractiveSessionManager::cleanup_expired_sessions(sessions.clone(), 0, &closed_tx).await;
 
         assert_eq!(
             sessions.read().len(),
             0,
             "Expired session should have been removed"
         );
+        assert_eq!(closed_rx.try_recv().unwrap(), "conv-expired");
     }
 
     #[tokio::test]
@@ -1038,7 +1094,12 @@ mod tests {
             .insert("conv-dead-notify".to_string(), session);
 
         // Run cleanup
-        InteractiveSessionManager::cleanup_expired_sessions(sessions.clone(), 9999).await;
+        InteractiveSessionManager::cleanup_expired_sessions(
+            sessions.clone(),
+            9999,
+            &broadcast::channel(1).0,
+        )
+        .await;
 
         // Should have received a process_died event
         let event =
@@ -1049,4 +1110,69 @@ mod tests {
         assert_eq!(event.r#type, "result");
         assert_eq!(event.subtype.as_deref(), Some("process_died"));
     }
+
+    // ── Conversation affinity ──
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_same_conversation_id_reuses_session() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stand-in CLI: ignores its flags and stays alive reading stdin
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("fake-claude");
+        std::fs::write(&script, "#!/bin/sh\ncat > /dev/null\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let command = script.to_string_lossy().into_owned();
+
+        let claude_manager = Arc::new(ClaudeManager::new(
+            command.clone(),
+            FileAccessConfig::default(),
+            MCPConfig::default(),
+            ToolsSettings::default(),
+        ));
+        let manager = InteractiveSessionManager::new(claude_manager, command);
+
+        let (first_id, _rx1) = manager
+            .get_or_create_session_and_send(
+                Some("conv-affinity".to_string()),
+                "test".to_string(),
+                "hello".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+        let first_session = manager.session_id("conv-affinity").unwrap();
+
+        let (second_id, _rx2) = manager
+            .get_or_create_session_and_send(
+                Some("conv-affinity".to_string()),
+                "test".to_string(),
+                "follow-up".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(first_id, "conv-affinity");
+        assert_eq!(second_id, "conv-affinity");
+        assert_eq!(
+            manager.session_id("conv-affinity").as_deref(),
+            Some(first_session.as_str())
+        );
+        assert_eq!(manager.active_sessions(), 1);
+
+        // A different conversation gets its own session
+        manager
+            .get_or_create_session_and_send(
+                Some("conv-other".to_string()),
+                "test".to_string(),
+                "hi".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(manager.active_sessions(), 2);
+        assert_ne!(manager.session_id("conv-other"), Some(first_session));
+    }
 }
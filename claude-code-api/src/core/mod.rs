@@ -11,3 +11,4 @@ pub mod process_pool;
 pub mod retry;
 pub mod session_manager;
 pub mod storage;
+pub mod usage;
@@ -2,8 +2,10 @@
 
 use anyhow::{Result, anyhow};
 use parking_lot::Mutex;
+use serde::Serialize;
 use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tracing::{error, info};
 
@@ -23,22 +25,97 @@ struct ProcessPoolInner {
 
 struct Pool {
     idle: VecDeque<PooledProcess>,
-    #[allow(dead_code)]
     active: Vec<ActiveProcess>,
+    counters: PoolCounters,
+}
+
+/// 累计计数器，供 `ProcessPool::stats` 使用
+#[derive(Default)]
+struct PoolCounters {
+    spawned: u64,
+    reused: u64,
+    idle_timeouts: u64,
+    checkouts: u64,
+    checkout_wait: Duration,
+}
+
+/// Snapshot of the process pool for monitoring
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PoolStats {
+    /// Processes currently checked out, i.e. still streaming a response
+    pub active: usize,
+    /// Processes waiting in the pool
+    pub idle: usize,
+    /// Processes started since the pool was created
+    pub total_spawned: u64,
+    /// Checkouts served by an idle process; `get_or_create` always starts
+    /// a new one, counted in `total_spawned`
+    pub total_reused: u64,
+    /// Average time a checkout took, in milliseconds
+    pub avg_checkout_wait_ms: f64,
+    /// Idle processes closed by the idle timeout reaper
+    pub idle_timeouts: u64,
+}
+
+impl Pool {
+    fn new() -> Self {
+        Self {
+            idle: VecDeque::new(),
+            active: Vec::new(),
+            counters: PoolCounters::default(),
+        }
+    }
+
+    fn record_checkout(&mut self, started: Instant) {
+        self.counters.checkouts += 1;
+        self.counters.checkout_wait += started.elapsed();
+    }
+
+    /// 移除超过 `timeout` 的空闲进程，返回它们的会话 ID
+    fn take_expired(&mut self, timeout: Duration) -> Vec<String> {
+        let mut expired = Vec::new();
+        self.idle.retain(|p| {
+            if p.created_at.elapsed() > timeout {
+                expired.push(p.session_id.clone());
+                false
+            } else {
+                true
+            }
+        });
+        self.counters.idle_timeouts += expired.len() as u64;
+        expired
+    }
+
+    fn stats(&self) -> PoolStats {
+        let counters = &self.counters;
+        let avg_checkout_wait_ms = if counters.checkouts == 0 {
+            0.0
+        } else {
+            counters.checkout_wait.as_secs_f64() * 1000.0 / counters.checkouts as f64
+        };
+        PoolStats {
+            active: self.active.len(),
+            idle: self.idle.len(),
+            total_spawned: counters.spawned,
+            total_reused: counters.reused,
+            avg_checkout_wait_ms,
+            idle_timeouts: counters.idle_timeouts,
+        }
+    }
 }
 
 struct PooledProcess {
     session_id: String,
     #[allow(dead_code)]
     model: String,
-    created_at: std::time::Instant,
+    created_at: Instant,
 }
 
 struct ActiveProcess {
     #[allow(dead_code)]
     session_id: String,
     #[allow(dead_code)]
-    in_use_since: std::time::Instant,
+    in_use_since: Instant,
 }
 
 #[derive(Clone)]
@@ -69,10 +146,7 @@ impl ProcessPool {
         let pool = ProcessPool {
             inner: Arc::new(ProcessPoolInner {
                 manager,
-                pool: Mutex::new(Pool {
-                    idle: VecDeque::new(),
-                    active: Vec::new(),
-                }),
+                pool: Mutex::new(Pool::new()),
                 config,
             }),
         };
@@ -92,17 +166,61 @@ impl ProcessPool {
         pool
     }
 
+    /// Snapshot of the pool's current size and lifetime counters
+    pub fn stats(&self) -> PoolStats {
+        self.inner.pool.lock().stats()
+    }
+
     pub async fn get_or_create(
         &self,
         model: String,
         message: String,
+        request_id: Option<String>,
     ) -> Result<(String, mpsc::Receiver<ClaudeCodeOutput>)> {
         // 直接创建新会话，暂时不使用池化（需要更复杂的实现）
         info!("Creating new Claude session for model: {}", model);
-        self.inner
+        let started = Instant::now();
+        let result = self
+            .inner
             .manager
-            .create_session_with_message(None, None, Some(model), &message)
-            .await
+            .create_session_with_message(None, None, Some(model), &message, request_id.as_deref())
+            .await;
+        let (session_id, rx) = {
+            let mut pool = self.inner.pool.lock();
+            pool.record_checkout(started);
+            let (session_id, rx) = result?;
+            pool.counters.spawned += 1;
+            (session_id, rx)
+        };
+        let rx = self.check_out(session_id.clone(), rx);
+        Ok((session_id, rx))
+    }
+
+    /// 将会话记为活跃，直到其输出结束或调用方丢弃接收端
+    fn check_out(
+        &self,
+        session_id: String,
+        mut upstream: mpsc::Receiver<ClaudeCodeOutput>,
+    ) -> mpsc::Receiver<ClaudeCodeOutput> {
+        self.inner.pool.lock().active.push(ActiveProcess {
+            session_id: session_id.clone(),
+            in_use_since: Instant::now(),
+        });
+        let (tx, rx) = mpsc::channel(100);
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            while let Some(output) = upstream.recv().await {
+                if tx.send(output).await.is_err() {
+                    break;
+                }
+            }
+            inner
+                .pool
+                .lock()
+                .active
+                .retain(|p| p.session_id != session_id);
+        });
+        rx
     }
 
     #[allow(dead_code)]
@@ -111,6 +229,7 @@ impl ProcessPool {
         model: Option<String>,
     ) -> Result<(String, mpsc::Receiver<ClaudeCodeOutput>)> {
         let model = model.unwrap_or_else(|| self.inner.config.default_model.clone());
+        let started = Instant::now();
 
         // 尝试从池中获取空闲进程
         let session_id = {
@@ -125,8 +244,10 @@ impl ProcessPool {
 
                 pool.active.push(ActiveProcess {
                     session_id: session_id.clone(),
-                    in_use_since: std::time::Instant::now(),
+                    in_use_since: Instant::now(),
                 });
+                pool.counters.reused += 1;
+                pool.record_checkout(started);
 
                 info!("Acquired process from pool: {}", session_id);
                 Some(session_id)
@@ -162,8 +283,10 @@ impl ProcessPool {
                 let mut pool = self.inner.pool.lock();
                 pool.active.push(ActiveProcess {
                     session_id: result.0.clone(),
-                    in_use_since: std::time::Instant::now(),
+                    in_use_since: Instant::now(),
                 });
+                pool.counters.spawned += 1;
+                pool.record_checkout(started);
             }
 
             Ok(result)
@@ -184,7 +307,7 @@ impl ProcessPool {
                 pool.idle.push_back(PooledProcess {
                     session_id: session_id.clone(),
                     model,
-                    created_at: std::time::Instant::now(),
+                    created_at: Instant::now(),
                 });
                 info!("Released process back to pool");
                 false
@@ -224,8 +347,9 @@ impl ProcessPool {
                         pool.idle.push_back(PooledProcess {
                             session_id,
                             model: self.inner.config.default_model.clone(),
-                            created_at: std::time::Instant::now(),
+                            created_at: Instant::now(),
                         });
+                        pool.counters.spawned += 1;
                         info!("Pre-warmed process added to pool");
                     },
                     Err(e) => {
@@ -239,27 +363,13 @@ impl ProcessPool {
     }
 
     async fn cleanup_loop(&self) {
-        let timeout = std::time::Duration::from_secs(self.inner.config.idle_timeout_secs);
+        let timeout = Duration::from_secs(self.inner.config.idle_timeout_secs);
 
         loop {
-            tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+            tokio::time::sleep(Duration::from_secs(60)).await;
 
-            let expired = {
-                let mut pool = self.inner.pool.lock();
-                let mut expired = Vec::new();
-
-                // 检查过期的空闲进程
-                pool.idle.retain(|p| {
-                    if p.created_at.elapsed() > timeout {
-                        expired.push(p.session_id.clone());
-                        false
-                    } else {
-                        true
-                    }
-                });
-
-                expired
-            };
+            // 检查过期的空闲进程
+            let expired = self.inner.pool.lock().take_expired(timeout);
 
             // 关闭过期进程
             for session_id in expired {
@@ -269,3 +379,85 @@ impl ProcessPool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::{FileAccessConfig, MCPConfig, ToolsSettings};
+
+    fn pooled(session_id: &str, age: Duration) -> PooledProcess {
+        PooledProcess {
+            session_id: session_id.to_string(),
+            model: "sonnet".to_string(),
+            created_at: Instant::now() - age,
+        }
+    }
+
+    #[test]
+    fn test_stats_count_reaped_processes_and_checkout_waits() {
+        let mut pool = Pool::new();
+        assert_eq!(pool.stats(), PoolStats::default());
+
+        pool.idle.push_back(pooled("old", Duration::from_secs(600)));
+        pool.idle.push_back(pooled("fresh", Duration::ZERO));
+        pool.active.push(ActiveProcess {
+            session_id: "busy".to_string(),
+            in_use_since: Instant::now(),
+        });
+        pool.counters.spawned = 3;
+        pool.counters.reused = 1;
+
+        assert_eq!(pool.take_expired(Duration::from_secs(300)), ["old"]);
+        assert!(pool.take_expired(Duration::from_secs(300)).is_empty());
+
+        pool.record_checkout(Instant::now() - Duration::from_millis(30));
+        pool.record_checkout(Instant::now() - Duration::from_millis(10));
+
+        let stats = pool.stats();
+        assert_eq!(stats.active, 1);
+        assert_eq!(stats.idle, 1);
+        assert_eq!(stats.total_spawned, 3);
+        assert_eq!(stats.total_reused, 1);
+        assert_eq!(stats.idle_timeouts, 1);
+        assert!(
+            (20.0..25.0).contains(&stats.avg_checkout_wait_ms),
+            "{}",
+            stats.avg_checkout_wait_ms
+        );
+    }
+
+    #[tokio::test]
+    async fn test_checked_out_session_is_active_until_its_output_ends() {
+        // Built directly so no pre-warming or reaper tasks run
+        let pool = ProcessPool {
+            inner: Arc::new(ProcessPoolInner {
+                manager: Arc::new(ClaudeManager::new(
+                    "claude".to_string(),
+                    FileAccessConfig::default(),
+                    MCPConfig::default(),
+                    ToolsSettings::default(),
+                )),
+                pool: Mutex::new(Pool::new()),
+                config: PoolConfig::default(),
+            }),
+        };
+
+        let (tx, upstream) = mpsc::channel(10);
+        let mut rx = pool.check_out("s1".to_string(), upstream);
+        assert_eq!(pool.stats().active, 1);
+
+        tx.send(ClaudeCodeOutput {
+            r#type: "result".to_string(),
+            subtype: Some("success".to_string()),
+            data: serde_json::json!({}),
+        })
+        .await
+        .unwrap();
+        assert_eq!(rx.recv().await.unwrap().r#type, "result");
+        assert_eq!(pool.stats().active, 1);
+
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+        assert_eq!(pool.stats().active, 0);
+    }
+}
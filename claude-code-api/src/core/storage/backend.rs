@@ -0,0 +1,260 @@
+//! Runtime selection of the conversation storage backend
+//!
+//! Turns the `storage` section of `Settings` into a concrete [`ConversationStore`]
+//! so operators can switch backends without recompiling.
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::core::config::{MeilisearchSettings, Neo4jSettings, StorageBackend, StorageConfig};
+
+use super::combined::CombinedConversationStore;
+use super::meilisearch::{MeilisearchClient, MeilisearchConfig};
+use super::memory::{InMemoryConversationConfig, InMemoryConversationStore};
+use super::neo4j::{Neo4jClient, Neo4jConfig, Neo4jConversationStore};
+use super::traits::DynConversationStore;
+
+impl From<&Neo4jSettings> for Neo4jConfig {
+    fn from(settings: &Neo4jSettings) -> Self {
+        Self {
+            uri: settings.uri.clone(),
+            user: settings.user.clone(),
+            password: settings.password.clone(),
+            max_connections: settings.max_connections,
+        }
+    }
+}
+
+impl From<&MeilisearchSettings> for MeilisearchConfig {
+    fn from(settings: &MeilisearchSettings) -> Self {
+        Self {
+            url: settings.url.clone(),
+            api_key: settings.api_key.clone(),
+        }
+    }
+}
+
+/// Opens the connections needed by the networked backends
+///
+/// Abstracted so backend selection can be exercised without live servers.
+#[async_trait]
+pub trait StorageConnector: Send + Sync {
+    /// Neo4j-backed store
+    async fn neo4j(&self, config: Neo4jConfig) -> Result<DynConversationStore>;
+
+    /// In-memory store indexed in Meilisearch for search
+    async fn meilisearch(&self, config: MeilisearchConfig) -> Result<DynConversationStore>;
+
+    /// Neo4j store indexed in Meilisearch
+    async fn combined(
+        &self,
+        neo4j: Neo4jConfig,
+        meilisearch: MeilisearchConfig,
+    ) -> Result<DynConversationStore>;
+}
+
+/// Connector that talks to real Neo4j / Meilisearch servers
+pub struct LiveStorageConnector;
+
+#[async_trait]
+impl StorageConnector for LiveStorageConnector {
+    async fn neo4j(&self, config: Neo4jConfig) -> Result<DynConversationStore> {
+        let client = Neo4jClient::new(config).await?;
+        Ok(Arc::new(Neo4jConversationStore::new(client)))
+    }
+
+    async fn meilisearch(&self, config: MeilisearchConfig) -> Result<DynConversationStore> {
+        let client = MeilisearchClient::new(config).await?;
+        let primary = InMemoryConversationStore::new(InMemoryConversationConfig::default());
+        Ok(Arc::new(CombinedConversationStore::with_primary(
+            Box::new(primary),
+            Some(Arc::new(client)),
+        )))
+    }
+
+    async fn combined(
+        &self,
+        neo4j: Neo4jConfig,
+        meilisearch: MeilisearchConfig,
+    ) -> Result<DynConversationStore> {
+        let neo4j_client = Neo4jClient::new(neo4j).await?;
+        let ms_client = MeilisearchClient::new(meilisearch).await?;
+        Ok(Arc::new(CombinedConversationStore::new(
+            neo4j_client,
+            Some(Arc::new(ms_client)),
+        )))
+    }
+}
+
+/// Build the conversation store selected by `storage.backend`
+///
+/// Fails if the selected backend's connection section is missing or the
+/// connection cannot be established.
+pub async fn build_conversation_store(config: &StorageConfig) -> Result<DynConversationStore> {
+    build_conversation_store_with(config, &LiveStorageConnector).await
+}
+
+async fn build_conversation_store_with(
+    config: &StorageConfig,
+    connector: &dyn StorageConnector,
+) -> Result<DynConversationStore> {
+    let backend = config.backend;
+    info!("Initializing '{}' conversation store", backend.as_str());
+
+    match backend {
+        StorageBackend::InMemory => Ok(Arc::new(InMemoryConversationStore::new(
+            InMemoryConversationConfig::default(),
+        ))),
+        StorageBackend::Neo4j => {
+            let neo4j = neo4j_config(config)?;
+            connector
+                .neo4j(neo4j)
+                .await
+                .context("Failed to connect to Neo4j conversation store")
+        },
+        StorageBackend::Meilisearch => {
+            let meilisearch = meilisearch_config(config)?;
+            connector
+                .meilisearch(meilisearch)
+                .await
+                .context("Failed to connect to Meilisearch conversation index")
+        },
+        StorageBackend::Combined => {
+            let neo4j = neo4j_config(config)?;
+            let meilisearch = meilisearch_config(config)?;
+            connector
+                .combined(neo4j, meilisearch)
+                .await
+                .context("Failed to connect to combined Neo4j + Meilisearch store")
+        },
+    }
+}
+
+fn neo4j_config(config: &StorageConfig) -> Result<Neo4jConfig> {
+    config.neo4j.as_ref().map(Neo4jConfig::from).ok_or_else(|| {
+        anyhow!(
+            "storage backend '{}' requires a `storage.neo4j` section (uri, user, password)",
+            config.backend.as_str()
+        )
+    })
+}
+
+fn meilisearch_config(config: &StorageConfig) -> Result<MeilisearchConfig> {
+    config
+        .meilisearch
+        .as_ref()
+        .map(MeilisearchConfig::from)
+        .ok_or_else(|| {
+            anyhow!(
+                "storage backend '{}' requires a `storage.meilisearch` section (url)",
+                config.backend.as_str()
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    /// Records which backend was requested and hands back an in-memory store
+    #[derive(Default)]
+    struct MockConnector {
+        calls: Mutex<Vec<&'static str>>,
+    }
+
+    impl MockConnector {
+        fn record(&self, backend: &'static str) -> Result<DynConversationStore> {
+            self.calls.lock().push(backend);
+            Ok(Arc::new(InMemoryConversationStore::default()))
+        }
+    }
+
+    #[async_trait]
+    impl StorageConnector for MockConnector {
+        async fn neo4j(&self, config: Neo4jConfig) -> Result<DynConversationStore> {
+            assert_eq!(config.uri, "bolt://neo4j:7687");
+            self.record("neo4j")
+        }
+
+        async fn meilisearch(&self, config: MeilisearchConfig) -> Result<DynConversationStore> {
+            assert_eq!(config.url, "http://meili:7700");
+            self.record("meilisearch")
+        }
+
+        async fn combined(
+            &self,
+            neo4j: Neo4jConfig,
+            meilisearch: MeilisearchConfig,
+        ) -> Result<DynConversationStore> {
+            assert_eq!(neo4j.uri, "bolt://neo4j:7687");
+            assert_eq!(meilisearch.url, "http://meili:7700");
+            self.record("combined")
+        }
+    }
+
+    fn storage_config(backend: &str, with_connections: bool) -> StorageConfig {
+        let mut value = serde_json::json!({ "backend": backend });
+        if with_connections {
+            value["neo4j"] = serde_json::json!({
+                "uri": "bolt://neo4j:7687",
+                "user": "neo4j",
+                "password": "secret",
+            });
+            value["meilisearch"] = serde_json::json!({ "url": "http://meili:7700" });
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_each_backend_constructs_its_store() {
+        let cases = [
+            ("in_memory", None),
+            ("neo4j", Some("neo4j")),
+            ("meilisearch", Some("meilisearch")),
+            ("combined", Some("combined")),
+        ];
+
+        for (backend, expected_call) in cases {
+            let connector = MockConnector::default();
+            let config = storage_config(backend, true);
+            assert_eq!(config.backend.as_str(), backend);
+
+            let store = build_conversation_store_with(&config, &connector)
+                .await
+                .unwrap();
+            let id = store.create(None).await.unwrap();
+            assert!(store.get(&id).await.unwrap().is_some());
+
+            let calls = connector.calls.lock().clone();
+            assert_eq!(calls, expected_call.into_iter().collect::<Vec<_>>());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missing_connection_config_fails() {
+        for (backend, section) in [
+            ("neo4j", "storage.neo4j"),
+            ("meilisearch", "storage.meilisearch"),
+            ("combined", "storage.neo4j"),
+        ] {
+            let connector = MockConnector::default();
+            let config = storage_config(backend, false);
+
+            let err = build_conversation_store_with(&config, &connector)
+                .await
+                .err()
+                .expect("missing connection config should fail");
+            assert!(err.to_string().contains(section), "{err}");
+            assert!(connector.calls.lock().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_backend_defaults_to_in_memory() {
+        let config: StorageConfig = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert_eq!(config.backend, StorageBackend::InMemory);
+    }
+}
@@ -1,7 +1,7 @@
 //! Combined Neo4j + Meilisearch storage
 //!
 //! This module provides a storage implementation that combines:
-//! - Neo4j for persistent graph storage
+//! - Neo4j (or any other [`ConversationStore`]) for primary storage
 //! - Meilisearch for full-text search indexing
 //!
 //! Messages are automatically indexed in Meilisearch when added to conversations.
@@ -29,15 +29,26 @@ use crate::core::session_manager::Session;
 /// - Automatic indexing in Meilisearch for search
 /// - Search capabilities across conversation history
 pub struct CombinedConversationStore {
-    neo4j_store: Neo4jConversationStore,
+    primary: Box<dyn ConversationStore>,
     meilisearch: Option<Arc<MeilisearchClient>>,
 }
 
 impl CombinedConversationStore {
     /// Create a new combined store with Neo4j and optional Meilisearch
     pub fn new(neo4j_client: Neo4jClient, meilisearch: Option<Arc<MeilisearchClient>>) -> Self {
+        Self::with_primary(
+            Box::new(Neo4jConversationStore::new(neo4j_client)),
+            meilisearch,
+        )
+    }
+
+    /// Create a combined store indexing into Meilisearch on top of any primary store
+    pub fn with_primary(
+        primary: Box<dyn ConversationStore>,
+        meilisearch: Option<Arc<MeilisearchClient>>,
+    ) -> Self {
         Self {
-            neo4j_store: Neo4jConversationStore::new(neo4j_client),
+            primary,
             meilisearch,
         }
     }
@@ -147,7 +158,7 @@ impl CombinedConversationStore {
 #[async_trait]
 impl ConversationStore for CombinedConversationStore {
     async fn create(&self, model: Option<String>) -> Result<String> {
-        let id = self.neo4j_store.create(model.clone()).await?;
+        let id = self.primary.create(model.clone()).await?;
 
         // Index the new conversation
         if let Some(ref ms) = self.meilisearch {
@@ -170,7 +181,7 @@ impl ConversationStore for CombinedConversationStore {
     }
 
     async fn get(&self, id: &str) -> Result<Option<Conversation>> {
-        self.neo4j_store.get(id).await
+        self.primary.get(id).await
     }
 
     async fn add_message(&self, id: &str, message: ChatMessage) -> Result<()> {
@@ -181,8 +192,8 @@ impl ConversationStore for CombinedConversationStore {
             0
         };
 
-        // Add to Neo4j
-        self.neo4j_store.add_message(id, message.clone()).await?;
+        // Add to the primary store
+        self.primary.add_message(id, message.clone()).await?;
 
         // Index in Meilisearch
         self.index_message(id, &message, turn_index).await;
@@ -196,7 +207,7 @@ impl ConversationStore for CombinedConversationStore {
     }
 
     async fn update_metadata(&self, id: &str, metadata: ConversationMetadata) -> Result<()> {
-        self.neo4j_store.update_metadata(id, metadata).await?;
+        self.primary.update_metadata(id, metadata).await?;
 
         // Update conversation index
         if let Some(conversation) = self.get(id).await? {
@@ -207,7 +218,7 @@ impl ConversationStore for CombinedConversationStore {
     }
 
     async fn list_active(&self) -> Result<Vec<(String, DateTime<Utc>)>> {
-        self.neo4j_store.list_active().await
+        self.primary.list_active().await
     }
 
     async fn cleanup_expired(&self, timeout_minutes: i64) -> Result<usize> {
@@ -230,8 +241,8 @@ impl ConversationStore for CombinedConversationStore {
             }
         }
 
-        // Clean up from Neo4j
-        self.neo4j_store.cleanup_expired(timeout_minutes).await
+        // Clean up from the primary store
+        self.primary.cleanup_expired(timeout_minutes).await
     }
 
     async fn delete(&self, id: &str) -> Result<bool> {
@@ -240,8 +251,8 @@ impl ConversationStore for CombinedConversationStore {
             let _ = ms.delete_conversation(id).await;
         }
 
-        // Delete from Neo4j
-        self.neo4j_store.delete(id).await
+        // Delete from the primary store
+        self.primary.delete(id).await
     }
 }
 
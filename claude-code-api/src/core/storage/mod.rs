@@ -8,7 +8,12 @@
 //! - `memory`: In-memory storage using HashMap/DashMap (default)
 //! - `neo4j`: Neo4j graph database storage
 //! - `meilisearch`: Meilisearch for full-text search
+//! - `combined`: Neo4j storage with Meilisearch indexing
+//!
+//! The backend used by the gateway is selected with `storage.backend` in
+//! `Settings`; see [`build_conversation_store`].
 
+mod backend;
 pub mod combined;
 pub mod meilisearch;
 mod memory;
@@ -18,11 +23,14 @@ mod traits;
 
 // Re-export for public API
 #[allow(unused_imports)]
+pub use backend::{LiveStorageConnector, StorageConnector, build_conversation_store};
+#[allow(unused_imports)]
 pub use combined::{CombinedConversationStore, CombinedSessionStore};
 #[allow(unused_imports)]
 pub use meilisearch::{
     ConversationDocument, MeilisearchClient, MeilisearchConfig, MessageDocument,
 };
+#[allow(unused_imports)]
 pub use memory::*;
 #[allow(unused_imports)]
 pub use neo4j::{Neo4jClient, Neo4jConfig, Neo4jConversationStore, Neo4jSessionStore};
@@ -42,6 +42,40 @@ pub trait ConversationStore: Send + Sync {
     async fn delete(&self, id: &str) -> Result<bool>;
 }
 
+/// Shared, type-erased conversation store (used when the backend is chosen at runtime)
+pub type DynConversationStore = std::sync::Arc<dyn ConversationStore>;
+
+#[async_trait]
+impl<T: ConversationStore + ?Sized> ConversationStore for std::sync::Arc<T> {
+    async fn create(&self, model: Option<String>) -> Result<String> {
+        (**self).create(model).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Conversation>> {
+        (**self).get(id).await
+    }
+
+    async fn add_message(&self, id: &str, message: ChatMessage) -> Result<()> {
+        (**self).add_message(id, message).await
+    }
+
+    async fn update_metadata(&self, id: &str, metadata: ConversationMetadata) -> Result<()> {
+        (**self).update_metadata(id, metadata).await
+    }
+
+    async fn list_active(&self) -> Result<Vec<(String, DateTime<Utc>)>> {
+        (**self).list_active().await
+    }
+
+    async fn cleanup_expired(&self, timeout_minutes: i64) -> Result<usize> {
+        (**self).cleanup_expired(timeout_minutes).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool> {
+        (**self).delete(id).await
+    }
+}
+
 /// Trait for session storage backends
 #[async_trait]
 pub trait SessionStore: Send + Sync {
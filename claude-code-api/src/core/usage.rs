@@ -0,0 +1,285 @@
+//! Token and cost aggregation across all gateway sessions
+//!
+//! Every CLI `result` message carries the turn's token usage and cost. The
+//! [`UsageAggregator`] rolls those up into a global total (backed by the SDK's
+//! [`BudgetManager`]) and per-conversation totals, and is safe to update from
+//! many concurrent requests. Per-conversation totals are dropped once the
+//! conversation's session closes; the global total is kept.
+
+use dashmap::DashMap;
+use nexus_claude::{BudgetManager, TokenUsageTracker};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tracing::debug;
+
+use crate::models::claude::ClaudeCodeOutput;
+
+/// Usage totals for one scope (the whole gateway or a single conversation)
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct UsageTotals {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_tokens: u64,
+    pub cost_usd: f64,
+    pub requests: usize,
+}
+
+impl From<&TokenUsageTracker> for UsageTotals {
+    fn from(tracker: &TokenUsageTracker) -> Self {
+        Self {
+            input_tokens: tracker.total_input_tokens,
+            output_tokens: tracker.total_output_tokens,
+            total_tokens: tracker.total_tokens(),
+            cost_usd: tracker.total_cost_usd,
+            requests: tracker.session_count,
+        }
+    }
+}
+
+/// Point-in-time view of the aggregated usage, as reported by `/stats`
+#[derive(Debug, Serialize)]
+pub struct UsageSnapshot {
+    pub total: UsageTotals,
+    pub conversations: HashMap<String, UsageTotals>,
+}
+
+/// Shared usage accumulator for the gateway
+#[derive(Clone, Default)]
+pub struct UsageAggregator {
+    budget: BudgetManager,
+    conversations: Arc<DashMap<String, TokenUsageTracker>>,
+}
+
+impl UsageAggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one request's usage to the global and per-conversation totals
+    pub async fn record(
+        &self,
+        conversation_id: &str,
+        input_tokens: u64,
+        output_tokens: u64,
+        cost_usd: f64,
+    ) {
+        // The entry guard holds the shard lock, so the update is atomic per conversation
+        self.conversations
+            .entry(conversation_id.to_string())
+            .or_default()
+            .update(input_tokens, output_tokens, cost_usd);
+
+        self.budget
+            .update_usage(input_tokens, output_tokens, cost_usd)
+            .await;
+    }
+
+    /// Record the usage carried by a top-level `result` message; other outputs are ignored
+    ///
+    /// Input tokens include prompt-cache writes and reads, as in the usage
+    /// returned to clients.
+    pub async fn record_output(&self, conversation_id: &str, output: &ClaudeCodeOutput) {
+        if output.r#type != "result" || output.is_sidechain() {
+            return;
+        }
+
+        let (input_tokens, output_tokens) = output.token_counts();
+        let cost_usd = output
+            .data
+            .get("total_cost_usd")
+            .and_then(|v| v.as_f64())
+            .unwrap_or(0.0);
+
+        self.record(conversation_id, input_tokens, output_tokens, cost_usd)
+            .await;
+    }
+
+    /// Forward a session's output channel, recording usage from its `result` messages
+    pub fn track(
+        &self,
+        conversation_id: String,
+        mut rx: mpsc::Receiver<ClaudeCodeOutput>,
+    ) -> mpsc::Receiver<ClaudeCodeOutput> {
+        let (tx, tracked_rx) = mpsc::channel(100);
+        let aggregator = self.clone();
+
+        tokio::spawn(async move {
+            while let Some(output) = rx.recv().await {
+                aggregator.record_output(&conversation_id, &output).await;
+                if tx.send(output).await.is_err() {
+                    debug!("Usage tracking receiver dropped for {}", conversation_id);
+                    break;
+                }
+            }
+        });
+
+        tracked_rx
+    }
+
+    /// Drop a conversation's totals, e.g. when its session has closed
+    pub fn remove(&self, conversation_id: &str) {
+        self.conversations.remove(conversation_id);
+    }
+
+    /// Drop each conversation's totals as its session closes
+    pub fn evict_closed(&self, mut closed: broadcast::Receiver<String>) {
+        let aggregator = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match closed.recv().await {
+                    Ok(conversation_id) => aggregator.remove(&conversation_id),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Drop the totals of every conversation not listed in `active`
+    pub fn retain_conversations(&self, active: &HashSet<String>) {
+        self.conversations.retain(|id, _| active.contains(id));
+    }
+
+    /// Snapshot of the global and per-conversation totals
+    pub async fn snapshot(&self) -> UsageSnapshot {
+        let total = UsageTotals::from(&self.budget.get_usage().await);
+        let conversations = self
+            .conversations
+            .iter()
+            .map(|entry| (entry.key().clone(), UsageTotals::from(entry.value())))
+            .collect();
+
+        UsageSnapshot {
+            total,
+            conversations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn result_output(input_tokens: u64, output_tokens: u64, cost_usd: f64) -> ClaudeCodeOutput {
+        ClaudeCodeOutput {
+            r#type: "result".to_string(),
+            subtype: Some("success".to_string()),
+            data: json!({
+                "type": "result",
+                "usage": { "input_tokens": input_tokens, "output_tokens": output_tokens },
+                "total_cost_usd": cost_usd,
+            }),
+        }
+    }
+
+    fn cached_result_output(
+        input_tokens: u64,
+        cache_creation_input_tokens: u64,
+        cache_read_input_tokens: u64,
+        output_tokens: u64,
+        cost_usd: f64,
+    ) -> ClaudeCodeOutput {
+        let mut output = result_output(input_tokens, output_tokens, cost_usd);
+        output.data["usage"]["cache_creation_input_tokens"] = json!(cache_creation_input_tokens);
+        output.data["usage"]["cache_read_input_tokens"] = json!(cache_read_input_tokens);
+        output
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_updates_are_exact() {
+        let aggregator = UsageAggregator::new();
+
+        let handles: Vec<_> = (0..200)
+            .map(|i| {
+                let aggregator = aggregator.clone();
+                tokio::spawn(async move {
+                    let conversation_id = format!("conv-{}", i % 4);
+                    // Half the requests hit the prompt cache: 2 + 3 + 5 = 10 input tokens
+                    let output = if i % 2 == 0 {
+                        result_output(10, 5, 0.25)
+                    } else {
+                        cached_result_output(2, 3, 5, 5, 0.25)
+                    };
+                    aggregator.record_output(&conversation_id, &output).await;
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        let snapshot = aggregator.snapshot().await;
+        assert_eq!(
+            snapshot.total,
+            UsageTotals {
+                input_tokens: 2000,
+                output_tokens: 1000,
+                total_tokens: 3000,
+                cost_usd: 50.0,
+                requests: 200,
+            }
+        );
+        assert_eq!(snapshot.conversations.len(), 4);
+        for totals in snapshot.conversations.values() {
+            assert_eq!(totals.requests, 50);
+            assert_eq!(totals.input_tokens, 500);
+            assert_eq!(totals.output_tokens, 250);
+            assert_eq!(totals.cost_usd, 12.5);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_records_only_top_level_results() {
+        let aggregator = UsageAggregator::new();
+        let (tx, rx) = mpsc::channel(10);
+        let mut tracked = aggregator.track("conv".to_string(), rx);
+
+        let mut sidechain = result_output(100, 100, 1.0);
+        sidechain.data["parent_tool_use_id"] = json!("toolu_1");
+        let assistant = ClaudeCodeOutput {
+            r#type: "assistant".to_string(),
+            subtype: None,
+            data: json!({ "type": "assistant" }),
+        };
+
+        for output in [assistant, sidechain, result_output(7, 3, 0.5)] {
+            tx.send(output).await.unwrap();
+        }
+        drop(tx);
+
+        let mut forwarded = 0;
+        while tracked.recv().await.is_some() {
+            forwarded += 1;
+        }
+        assert_eq!(forwarded, 3);
+
+        let snapshot = aggregator.snapshot().await;
+        assert_eq!(snapshot.total.input_tokens, 7);
+        assert_eq!(snapshot.total.output_tokens, 3);
+        assert_eq!(snapshot.total.requests, 1);
+        assert_eq!(snapshot.conversations["conv"].cost_usd, 0.5);
+    }
+
+    #[tokio::test]
+    async fn test_closed_conversations_are_evicted() {
+        let aggregator = UsageAggregator::new();
+        for conversation_id in ["conv-a", "conv-b", "conv-c"] {
+            aggregator
+                .record_output(conversation_id, &result_output(10, 5, 0.1))
+                .await;
+        }
+
+        aggregator.remove("conv-a");
+        aggregator.retain_conversations(&HashSet::from(["conv-b".to_string()]));
+
+        let snapshot = aggregator.snapshot().await;
+        assert_eq!(
+            snapshot.conversations.keys().collect::<Vec<_>>(),
+            ["conv-b"]
+        );
+        assert_eq!(snapshot.total.requests, 3);
+    }
+}
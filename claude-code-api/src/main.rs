@@ -57,7 +57,7 @@ async fn create_app(settings: Settings) -> Result<Router> {
         cache::{CacheConfig, ResponseCache},
         conversation::{ConversationConfig, ConversationManager},
         interactive_session::InteractiveSessionManager,
-        storage::{InMemoryConversationConfig, InMemoryConversationStore},
+        storage::build_conversation_store,
     };
     use crate::middleware::{error_handler, request_id};
     use axum::middleware;
@@ -68,6 +68,7 @@ async fn create_app(settings: Settings) -> Result<Router> {
         settings.claude.command.clone(),
         settings.file_access.clone(),
         settings.mcp.clone(),
+        settings.tools.clone(),
     ));
 
     // 创建进程池配置
@@ -100,7 +101,7 @@ async fn create_app(settings: Settings) -> Result<Router> {
         tracing::error!("Failed to pre-warm Claude process: {}", e);
     }
 
-    let conversation_store = InMemoryConversationStore::new(InMemoryConversationConfig::default());
+    let conversation_store = build_conversation_store(&settings.storage).await?;
     let conversation_manager = Arc::new(ConversationManager::new(
         conversation_store,
         ConversationConfig::default(),
@@ -123,8 +124,12 @@ async fn create_app(settings: Settings) -> Result<Router> {
 
     let stats_state = api::stats::StatsState {
         cache: cache.clone(),
+        usage: chat_state.usage.clone(),
+        process_pool: process_pool.clone(),
     };
 
+    let tools_state = api::tools::ToolsState::new(&settings);
+
     let api_routes = Router::new()
         .route("/v1/chat/completions", post(api::chat::chat_completions))
         .route(
@@ -152,6 +157,10 @@ async fn create_app(settings: Settings) -> Result<Router> {
         .route("/stats", get(api::stats::get_stats))
         .with_state(stats_state);
 
+    let tools_routes = Router::new()
+        .route("/v1/tools", get(api::tools::list_tools))
+        .with_state(tools_state);
+
     // 组合所有路由
     let app = Router::new()
         .route("/health", get(health_check))
@@ -159,6 +168,7 @@ async fn create_app(settings: Settings) -> Result<Router> {
         .merge(api_routes)
         .merge(conversation_routes)
         .merge(stats_routes)
+        .merge(tools_routes)
         .layer(middleware::from_fn(request_id::add_request_id))
         .layer(middleware::from_fn(error_handler::handle_errors))
         .layer(cors);
@@ -1,8 +1,13 @@
 use axum::{extract::Request, http::HeaderName, middleware::Next, response::Response};
+use tracing::Instrument;
 use uuid::Uuid;
 
 pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
 
+/// Request id made available to handlers as an `Extension`
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
 pub async fn add_request_id(mut req: Request, next: Next) -> Response {
     let request_id = req
         .headers()
@@ -13,8 +18,10 @@ pub async fn add_request_id(mut req: Request, next: Next) -> Response {
 
     req.headers_mut()
         .insert(X_REQUEST_ID.clone(), request_id.parse().unwrap());
+    req.extensions_mut().insert(RequestId(request_id.clone()));
 
-    let mut response = next.run(req).await;
+    let span = tracing::info_span!("request", request_id = %request_id);
+    let mut response = next.run(req).instrument(span).await;
 
     response
         .headers_mut()
@@ -22,3 +29,30 @@ pub async fn add_request_id(mut req: Request, next: Next) -> Response {
 
     response
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Extension, Router, http::HeaderValue, middleware, routing::get};
+    use axum_test::TestServer;
+
+    async fn echo_request_id(Extension(RequestId(id)): Extension<RequestId>) -> String {
+        id
+    }
+
+    #[tokio::test]
+    async fn test_request_id_reaches_handlers() {
+        let app = Router::new()
+            .route("/", get(echo_request_id))
+            .layer(middleware::from_fn(add_request_id));
+        let server = TestServer::new(app).unwrap();
+
+        let response = server
+            .get("/")
+            .add_header(X_REQUEST_ID.clone(), HeaderValue::from_static("req-abc"))
+            .await;
+
+        assert_eq!(response.text(), "req-abc");
+        assert_eq!(response.header(X_REQUEST_ID.clone()), "req-abc");
+    }
+}
@@ -94,6 +94,23 @@ impl ClaudeCodeOutput {
     pub fn is_sidechain(&self) -> bool {
         self.parent_tool_use_id().is_some()
     }
+
+    /// Prompt and completion token counts from a `result` message's usage.
+    /// Prompt tokens include those written to and read from the prompt cache.
+    pub fn token_counts(&self) -> (u64, u64) {
+        let usage = self.data.get("usage");
+        let tokens = |field: &str| {
+            usage
+                .and_then(|u| u.get(field))
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0)
+        };
+
+        let prompt_tokens = tokens("input_tokens")
+            + tokens("cache_creation_input_tokens")
+            + tokens("cache_read_input_tokens");
+        (prompt_tokens, tokens("output_tokens"))
+    }
 }
 
 #[cfg(test)]
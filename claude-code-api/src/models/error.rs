@@ -38,6 +38,9 @@ pub enum ApiError {
     #[error("Timeout error: {0}")]
     Timeout(String),
 
+    #[error("Timeout error: {0}")]
+    FirstTokenTimeout(String),
+
     #[error("Rate limit exceeded: {0}")]
     RateLimit(String),
 
@@ -56,7 +59,7 @@ pub struct ErrorResponse {
     pub error: ErrorDetail,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ErrorDetail {
     pub message: String,
     pub r#type: String,
@@ -64,9 +67,10 @@ pub struct ErrorDetail {
     pub code: Option<String>,
 }
 
-impl IntoResponse for ApiError {
-    fn into_response(self) -> Response {
-        let (status, error_type, code) = match &self {
+impl ApiError {
+    /// The error as sent to clients, with its HTTP status
+    fn status_and_detail(&self) -> (StatusCode, ErrorDetail) {
+        let (status, error_type, code) = match self {
             ApiError::BadRequest(_) => (StatusCode::BAD_REQUEST, "invalid_request_error", None),
             ApiError::Unauthorized(_) => (StatusCode::UNAUTHORIZED, "authentication_error", None),
             ApiError::NotFound(_) => (StatusCode::NOT_FOUND, "not_found_error", None),
@@ -80,6 +84,11 @@ impl IntoResponse for ApiError {
                 "timeout_error",
                 Some("timeout"),
             ),
+            ApiError::FirstTokenTimeout(_) => (
+                StatusCode::GATEWAY_TIMEOUT,
+                "timeout_error",
+                Some("first_token_timeout"),
+            ),
             ApiError::RateLimit(_) => (
                 StatusCode::TOO_MANY_REQUESTS,
                 "rate_limit_error",
@@ -101,16 +110,25 @@ impl IntoResponse for ApiError {
             _ => (StatusCode::INTERNAL_SERVER_ERROR, "internal_error", None),
         };
 
-        let error_response = ErrorResponse {
-            error: ErrorDetail {
-                message: self.to_string(),
-                r#type: error_type.to_string(),
-                param: None,
-                code: code.map(String::from),
-            },
+        let detail = ErrorDetail {
+            message: self.to_string(),
+            r#type: error_type.to_string(),
+            param: None,
+            code: code.map(String::from),
         };
+        (status, detail)
+    }
 
-        (status, Json(error_response)).into_response()
+    /// The error body sent to clients
+    pub fn detail(&self) -> ErrorDetail {
+        self.status_and_detail().1
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, error) = self.status_and_detail();
+        (status, Json(ErrorResponse { error })).into_response()
     }
 }
 
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::models::error::ErrorDetail;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatCompletionRequest {
     pub model: String,
@@ -31,6 +33,17 @@ pub struct ChatCompletionRequest {
     pub tools: Option<Vec<Tool>>,
     #[serde(default)]
     pub tool_choice: Option<ToolChoice>,
+    #[serde(default)]
+    pub stream_options: Option<StreamOptions>,
+}
+
+/// Options for streamed responses
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StreamOptions {
+    /// End the stream with a chunk carrying the token usage, whose `choices`
+    /// is empty
+    #[serde(default)]
+    pub include_usage: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -100,6 +113,12 @@ pub struct ChatCompletionStreamResponse {
     pub created: i64,
     pub model: String,
     pub choices: Vec<StreamChoice>,
+    /// Only set on the final chunk, whose `choices` is empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Set on the last chunk of a stream that failed midway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorDetail>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -221,6 +240,7 @@ impl Default for ChatCompletionRequest {
             conversation_id: None,
             tools: None,
             tool_choice: None,
+            stream_options: None,
         }
     }
 }
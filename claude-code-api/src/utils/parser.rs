@@ -23,6 +23,8 @@ pub fn claude_to_openai_stream(
                             object: "chat.completion.chunk".to_string(),
                             created: Utc::now().timestamp(),
                             model: model.to_string(),
+                            usage: None,
+                            error: None,
                             choices: vec![StreamChoice {
                                 index: 0,
                                 delta: DeltaMessage {
@@ -44,6 +46,8 @@ pub fn claude_to_openai_stream(
                 object: "chat.completion.chunk".to_string(),
                 created: Utc::now().timestamp(),
                 model: model.to_string(),
+                usage: None,
+                error: None,
                 choices: vec![StreamChoice {
                     index: 0,
                     delta: DeltaMessage::default(),
@@ -68,6 +72,8 @@ fn convert_claude_event_to_openai(
             object: "chat.completion.chunk".to_string(),
             created: Utc::now().timestamp(),
             model: model.to_string(),
+            usage: None,
+            error: None,
             choices: vec![StreamChoice {
                 index: 0,
                 delta: DeltaMessage {
@@ -84,6 +90,8 @@ fn convert_claude_event_to_openai(
                 object: "chat.completion.chunk".to_string(),
                 created: Utc::now().timestamp(),
                 model: model.to_string(),
+                usage: None,
+                error: None,
                 choices: vec![StreamChoice {
                     index: 0,
                     delta: DeltaMessage {
@@ -100,6 +108,8 @@ fn convert_claude_event_to_openai(
             object: "chat.completion.chunk".to_string(),
             created: Utc::now().timestamp(),
             model: model.to_string(),
+            usage: None,
+            error: None,
             choices: vec![StreamChoice {
                 index: 0,
                 delta: DeltaMessage::default(),
@@ -9,8 +9,10 @@ where
     S: Stream<Item = T> + Send + 'static,
     T: Serialize,
 {
+    // OpenAI clients stop reading at the `[DONE]` sentinel
     let event_stream = stream
-        .map(|data| Ok(Event::default().data(serde_json::to_string(&data).unwrap_or_default())));
+        .map(|data| Ok(Event::default().data(serde_json::to_string(&data).unwrap_or_default())))
+        .chain(futures::stream::once(async { Ok(create_done_event()) }));
 
     Sse::new(event_stream).keep_alive(
         KeepAlive::new()
@@ -19,7 +21,6 @@ where
     )
 }
 
-#[allow(dead_code)]
 pub fn create_done_event() -> Event {
     Event::default().data("[DONE]")
 }
@@ -72,13 +72,9 @@ async fn main() -> Result<()> {
                 println!("📊 Response Stats:");
                 println!("   ⏱️  Duration: {}ms", duration_ms);
 
-                if let Some(usage_json) = usage {
-                    if let Some(input_tokens) = usage_json.get("input_tokens") {
-                        println!("   📥 Input tokens: {}", input_tokens);
-                    }
-                    if let Some(output_tokens) = usage_json.get("output_tokens") {
-                        println!("   📤 Output tokens: {}", output_tokens);
-                    }
+                if let Some(usage) = usage {
+                    println!("   📥 Input tokens: {}", usage.input_tokens);
+                    println!("   📤 Output tokens: {}", usage.output_tokens);
                 }
 
                 if let Some(cost) = total_cost_usd {
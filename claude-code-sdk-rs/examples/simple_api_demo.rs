@@ -59,6 +59,8 @@ impl MockClient {
             usage: None,
             result: Some("Success".to_string()),
             structured_output: None,
+            parent_tool_use_id: None,
+            permission_denials: vec![],
         };
 
         Ok(vec![assistant_msg, result_msg])
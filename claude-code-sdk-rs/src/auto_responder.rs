@@ -0,0 +1,146 @@
+//! Local answers for whitelisted tools
+//!
+//! In semi-autonomous setups some tools are cheap and read-only (a clock, a
+//! config lookup) and not worth a round trip through the application. An
+//! [`AutoResponder`] maps tool names to local handlers; the interactive client
+//! consults it for every tool use it receives and sends the handler's output
+//! back as the tool result. Tool uses for other tools are left to the caller.
+
+use crate::{
+    transport::InputMessage,
+    types::{ContentBlock, Message},
+};
+use serde_json::Value;
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// Handler computing a tool result from the tool-use input
+///
+/// `Ok` content is sent as a normal result; `Err` is sent with `is_error`
+/// set, so Claude can react or retry.
+pub type AutoResponseHandler =
+    Arc<dyn Fn(&Value) -> std::result::Result<String, String> + Send + Sync>;
+
+/// Registry of tools answered locally by the client
+#[derive(Clone, Default)]
+pub struct AutoResponder {
+    handlers: HashMap<String, AutoResponseHandler>,
+}
+
+impl AutoResponder {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer tool uses of `tool_name` with `handler`
+    ///
+    /// Registering the same name twice replaces the earlier handler.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::AutoResponder;
+    /// let responder = AutoResponder::new()
+    ///     .register("get_time", |_input| Ok("12:00".to_string()));
+    /// assert!(responder.handles("get_time"));
+    /// ```
+    pub fn register(
+        mut self,
+        tool_name: impl Into<String>,
+        handler: impl Fn(&Value) -> std::result::Result<String, String> + Send + Sync + 'static,
+    ) -> Self {
+        self.handlers.insert(tool_name.into(), Arc::new(handler));
+        self
+    }
+
+    /// Whether `tool_name` is answered locally
+    pub fn handles(&self, tool_name: &str) -> bool {
+        self.handlers.contains_key(tool_name)
+    }
+
+    /// Names of the registered tools, sorted
+    pub fn tool_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.handlers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Tool results for the whitelisted tool uses in an assistant message
+    ///
+    /// Runs the handlers in block order. Other messages and tool uses of
+    /// unregistered tools yield nothing.
+    pub fn responses(&self, message: &Message) -> Vec<InputMessage> {
+        let Message::Assistant { message, .. } = message else {
+            return Vec::new();
+        };
+        message
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::ToolUse(tool_use) => {
+                    let handler = self.handlers.get(&tool_use.name)?;
+                    Some(InputMessage::tool_result_from(
+                        tool_use.id.clone(),
+                        handler(&tool_use.input),
+                        "default".to_string(),
+                    ))
+                },
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for AutoResponder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AutoResponder")
+            .field("tools", &self.tool_names())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AssistantMessage, TextContent, ToolUseContent};
+
+    fn tool_use(id: &str, name: &str) -> ContentBlock {
+        ContentBlock::ToolUse(ToolUseContent {
+            id: id.to_string(),
+            name: name.to_string(),
+            input: serde_json::json!({"zone": "UTC"}),
+        })
+    }
+
+    #[test]
+    fn test_responses_cover_registered_tools_only() {
+        let responder = AutoResponder::new()
+            .register("get_time", |input| Ok(format!("12:00 {}", input["zone"])))
+            .register("fail", |_| Err("unavailable".to_string()));
+        let message = Message::Assistant {
+            message: AssistantMessage {
+                content: vec![
+                    ContentBlock::Text(TextContent {
+                        text: "Checking".to_string(),
+                    }),
+                    tool_use("toolu_1", "get_time"),
+                    tool_use("toolu_2", "Bash"),
+                    tool_use("toolu_3", "fail"),
+                ],
+            },
+            parent_tool_use_id: None,
+        };
+
+        let responses = responder.responses(&message);
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].parent_tool_use_id.as_deref(), Some("toolu_1"));
+        assert_eq!(
+            responses[0].message["content"][0]["content"],
+            "12:00 \"UTC\""
+        );
+        assert_eq!(responses[0].message["content"][0]["is_error"], false);
+        assert_eq!(responses[1].parent_tool_use_id.as_deref(), Some("toolu_3"));
+        assert_eq!(responses[1].message["content"][0]["is_error"], true);
+        assert_eq!(responder.tool_names(), vec!["fail", "get_time"]);
+    }
+}
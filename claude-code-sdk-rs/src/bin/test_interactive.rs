@@ -3,7 +3,7 @@
 use futures::StreamExt;
 use nexus_claude::{ClaudeCodeOptions, ClaudeSDKClient, Result};
 
-#[tokio::main]
+#[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     // Set up simple println-based debugging
     unsafe {
@@ -0,0 +1,203 @@
+//! CLI versions and the features they support
+//!
+//! [`CliFeatures::detect`] maps the CLI version and its initialize response to
+//! the capabilities the SDK can rely on, so both callers and the SDK itself
+//! check one place before using something an older CLI does not have.
+
+use crate::errors::{Result, SdkError};
+use serde_json::Value;
+
+/// Simple semantic version struct
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SemVer {
+    /// Major version number.
+    pub major: u32,
+    /// Minor version number.
+    pub minor: u32,
+    /// Patch version number.
+    pub patch: u32,
+}
+
+impl std::fmt::Display for SemVer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl SemVer {
+    /// Create a new `SemVer` from major, minor, patch components.
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse semantic version from string (e.g., "2.0.0" or "v2.0.0")
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim().trim_start_matches('v');
+
+        // Handle versions like "@anthropic-ai/claude-code/2.0.0"
+        let version = if let Some(v) = version.split('/').next_back() {
+            v
+        } else {
+            version
+        };
+
+        let parts: Vec<&str> = version.split('.').collect();
+        if parts.len() < 2 {
+            return None;
+        }
+
+        Some(Self {
+            major: parts[0].parse().ok()?,
+            minor: parts.get(1)?.parse().ok()?,
+            patch: parts.get(2).and_then(|p| p.parse().ok()).unwrap_or(0),
+        })
+    }
+}
+
+/// First CLI release honoring sandbox settings
+///
+/// Claude Code CHANGELOG, 2.0.24: sandbox mode for the Bash tool
+/// (<https://github.com/anthropics/claude-code/blob/main/CHANGELOG.md#2024>).
+pub(crate) const SANDBOX_SINCE: SemVer = SemVer::new(2, 0, 24);
+/// First CLI release accepting `rewind_files`
+///
+/// Claude Code CHANGELOG, 2.0.64: file checkpointing (`rewind_files`) in the
+/// Agent SDK (<https://github.com/anthropics/claude-code/blob/main/CHANGELOG.md#2064>).
+const REWIND_FILES_SINCE: SemVer = SemVer::new(2, 0, 64);
+
+/// Features the connected CLI supports
+///
+/// Only features that arrived after the minimum supported CLI (2.0.0) are
+/// listed. When the version is unknown (e.g. a mock or WebSocket transport)
+/// every feature is assumed available and the CLI has the final word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CliFeatures {
+    /// CLI version the features were derived from, if known
+    pub version: Option<SemVer>,
+    /// `rewind_files` control request (file checkpointing)
+    pub rewind_files: bool,
+    /// Sandbox settings
+    pub sandbox: bool,
+    /// Model list in the initialize response, see `available_models`
+    pub list_models: bool,
+}
+
+impl CliFeatures {
+    /// Feature set assuming the CLI supports everything
+    pub fn all() -> Self {
+        Self {
+            version: None,
+            rewind_files: true,
+            sandbox: true,
+            list_models: true,
+        }
+    }
+
+    /// Derive the feature set from the CLI version and initialize response
+    ///
+    /// `version` is usually the CLI's `--version`; without it the
+    /// `claude_code_version` reported in `init_response` is used. Features
+    /// the initialize response advertises directly (the model list) are read
+    /// from it when present. Pass only the control protocol's initialize
+    /// response here, not the data of the `System` init message, which
+    /// never lists models.
+    pub fn detect(version: Option<&SemVer>, init_response: Option<&Value>) -> Self {
+        let version = version.cloned().or_else(|| {
+            init_response
+                .and_then(|init| init.get("claude_code_version"))
+                .and_then(|v| v.as_str())
+                .and_then(SemVer::parse)
+        });
+        let list_models = init_response.is_none_or(|init| init.get("models").is_some());
+        let Some(version) = version else {
+            return Self {
+                list_models,
+                ..Self::all()
+            };
+        };
+
+        Self {
+            rewind_files: version >= REWIND_FILES_SINCE,
+            sandbox: version >= SANDBOX_SINCE,
+            list_models,
+            version: Some(version),
+        }
+    }
+
+    /// Fail with `SdkError::NotSupported` unless `supported` holds
+    pub(crate) fn require(&self, supported: bool, feature: &str) -> Result<()> {
+        if supported {
+            return Ok(());
+        }
+        let version = self
+            .version
+            .as_ref()
+            .map_or_else(|| "the connected CLI".to_string(), |v| format!("CLI {v}"));
+        Err(SdkError::NotSupported {
+            feature: format!("{feature} (not available in {version})"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_detect_maps_versions_to_features() {
+        let early_two = CliFeatures::detect(Some(&SemVer::new(2, 0, 10)), None);
+        assert!(!early_two.sandbox && !early_two.rewind_files);
+
+        let mid_two = CliFeatures::detect(Some(&SemVer::new(2, 0, 30)), None);
+        assert!(mid_two.sandbox && !mid_two.rewind_files);
+
+        let current = CliFeatures::detect(Some(&SemVer::new(2, 1, 0)), None);
+        assert_eq!(
+            current,
+            CliFeatures {
+                version: Some(SemVer::new(2, 1, 0)),
+                ..CliFeatures::all()
+            }
+        );
+    }
+
+    #[test]
+    fn test_detect_reads_init_response() {
+        let init = json!({"claude_code_version": "2.0.30", "commands": []});
+        let features = CliFeatures::detect(None, Some(&init));
+        assert_eq!(features.version, Some(SemVer::new(2, 0, 30)));
+        assert!(features.sandbox && !features.rewind_files);
+        assert!(!features.list_models);
+
+        // An explicit version wins over the reported one
+        let features = CliFeatures::detect(Some(&SemVer::new(2, 0, 70)), Some(&init));
+        assert!(features.rewind_files);
+
+        let init = json!({"models": []});
+        assert!(CliFeatures::detect(Some(&SemVer::new(2, 0, 70)), Some(&init)).list_models);
+    }
+
+    #[test]
+    fn test_unknown_version_assumes_everything() {
+        let features = CliFeatures::detect(None, None);
+        assert_eq!(features, CliFeatures::all());
+        assert!(
+            features
+                .require(features.rewind_files, "rewind_files")
+                .is_ok()
+        );
+
+        let old = CliFeatures::detect(Some(&SemVer::new(2, 0, 10)), None);
+        match old.require(old.rewind_files, "rewind_files") {
+            Err(SdkError::NotSupported { feature }) => {
+                assert_eq!(feature, "rewind_files (not available in CLI 2.0.10)")
+            },
+            other => panic!("expected NotSupported, got {other:?}"),
+        }
+    }
+}
@@ -4,10 +4,11 @@
 //! conversations with Claude Code CLI.
 
 use crate::{
+    cli_features::{CliFeatures, SemVer},
     errors::{Result, SdkError},
     internal_query::Query,
     token_tracker::BudgetManager,
-    transport::{InputMessage, SubprocessTransport, Transport},
+    transport::{ControlLog, ControlLogEntry, InputMessage, RecordingTransport, Transport},
     types::{ClaudeCodeOptions, ContentBlock, ControlRequest, ControlResponse, Message},
 };
 use futures::stream::{Stream, StreamExt};
@@ -25,6 +26,8 @@ pub enum ClientState {
     Disconnected,
     /// Connected and ready
     Connected,
+    /// Input closed with `end_input()`; remaining output can still be received
+    InputEnded,
     /// Error state
     Error,
 }
@@ -102,6 +105,10 @@ pub struct ClaudeSDKClient {
     request_counter: Arc<Mutex<u64>>,
     /// Budget manager for token tracking
     budget_manager: BudgetManager,
+    /// Recorded control messages (when `record_control_protocol` is set)
+    control_log: Option<ControlLog>,
+    /// CLI version reported by the transport at connect time
+    cli_version: Option<SemVer>,
 }
 
 /// Session data
@@ -117,7 +124,10 @@ struct SessionData {
 
 impl ClaudeSDKClient {
     /// Create a new client with the given options
+    #[cfg(feature = "subprocess")]
     pub fn new(options: ClaudeCodeOptions) -> Self {
+        use crate::transport::SubprocessTransport;
+
         // Set environment variable to indicate SDK usage
         unsafe {
             std::env::set_var("CLAUDE_CODE_ENTRYPOINT", "sdk-rust");
@@ -132,11 +142,7 @@ impl ClaudeSDKClient {
             },
         };
 
-        // Wrap transport in Arc for sharing
-        let transport_arc: Arc<Mutex<Box<dyn Transport + Send>>> =
-            Arc::new(Mutex::new(Box::new(transport)));
-
-        Self::with_transport_internal(options, transport_arc)
+        Self::with_transport_internal(options, Box::new(transport))
     }
 
     /// Create a new client with a custom transport implementation
@@ -169,17 +175,27 @@ impl ClaudeSDKClient {
             std::env::set_var("CLAUDE_CODE_ENTRYPOINT", "sdk-rust");
         }
 
-        // Wrap transport in Arc for sharing
-        let transport_arc: Arc<Mutex<Box<dyn Transport + Send>>> = Arc::new(Mutex::new(transport));
-
-        Self::with_transport_internal(options, transport_arc)
+        Self::with_transport_internal(options, transport)
     }
 
-    /// Internal helper to construct client with pre-wrapped transport
+    /// Internal helper to construct client around a transport
     fn with_transport_internal(
         options: ClaudeCodeOptions,
-        transport_arc: Arc<Mutex<Box<dyn Transport + Send>>>,
+        transport: Box<dyn Transport + Send>,
     ) -> Self {
+        // Record the control protocol if requested
+        let (transport, control_log): (Box<dyn Transport + Send>, _) =
+            if options.record_control_protocol {
+                let recording = RecordingTransport::new(transport);
+                let log = recording.control_log();
+                (Box::new(recording), Some(log))
+            } else {
+                (transport, None)
+            };
+
+        // Wrap transport in Arc for sharing
+        let transport_arc: Arc<Mutex<Box<dyn Transport + Send>>> = Arc::new(Mutex::new(transport));
+
         // Create query handler if control protocol features are enabled
         let query_handler = if options.can_use_tool.is_some()
             || options.hooks.is_some()
@@ -205,13 +221,14 @@ impl ClaudeSDKClient {
                 || options.hooks.is_some()
                 || !sdk_mcp_servers.is_empty();
 
-            let query = Query::new(
+            let mut query = Query::new(
                 transport_arc.clone(), // Share the same transport
                 is_streaming,          // Enable streaming for control protocol
                 options.can_use_tool.clone(),
                 options.hooks.clone(),
                 sdk_mcp_servers,
             );
+            query.set_permission_callback_timeout(options.permission_callback_timeout);
             Some(Arc::new(Mutex::new(query)))
         } else {
             None
@@ -227,6 +244,8 @@ impl ClaudeSDKClient {
             message_buffer: Arc::new(Mutex::new(Vec::new())),
             request_counter: Arc::new(Mutex::new(0)),
             budget_manager: BudgetManager::new(),
+            control_log,
+            cli_version: None,
         }
     }
 
@@ -244,6 +263,7 @@ impl ClaudeSDKClient {
         {
             let mut transport = self.transport.lock().await;
             transport.connect().await?;
+            self.cli_version = transport.cli_version();
         }
 
         // Initialize query handler if present
@@ -273,17 +293,22 @@ impl ClaudeSDKClient {
         Ok(())
     }
 
+    /// Fail unless the client is connected and still accepting input
+    async fn ensure_accepting_input(&self) -> Result<()> {
+        match *self.state.read().await {
+            ClientState::Connected => Ok(()),
+            ClientState::InputEnded => Err(SdkError::InvalidState {
+                message: "Input already ended with end_input()".into(),
+            }),
+            _ => Err(SdkError::InvalidState {
+                message: "Not connected".into(),
+            }),
+        }
+    }
+
     /// Send a user message to Claude
     pub async fn send_user_message(&mut self, prompt: String) -> Result<()> {
-        // Check connection
-        {
-            let state = self.state.read().await;
-            if *state != ClientState::Connected {
-                return Err(SdkError::InvalidState {
-                    message: "Not connected".into(),
-                });
-            }
-        }
+        self.ensure_accepting_input().await?;
 
         // Use default session ID
         let session_id = "default".to_string();
@@ -373,7 +398,7 @@ impl ClaudeSDKClient {
 
         // If we have a query handler, use it
         if let Some(ref query_handler) = self.query_handler {
-            let mut handler = query_handler.lock().await;
+            let handler = query_handler.lock().await;
             return handler.interrupt().await;
         }
 
@@ -433,9 +458,21 @@ impl ClaudeSDKClient {
     }
 
     /// Check if the client is connected
+    ///
+    /// Still true after `end_input()`, while the remaining output is drained.
     pub async fn is_connected(&self) -> bool {
         let state = self.state.read().await;
-        *state == ClientState::Connected
+        matches!(*state, ClientState::Connected | ClientState::InputEnded)
+    }
+
+    /// Control messages exchanged with the CLI so far, oldest first
+    ///
+    /// Empty unless [`ClaudeCodeOptions::record_control_protocol`] is set.
+    pub fn control_log(&self) -> Vec<ControlLogEntry> {
+        self.control_log
+            .as_ref()
+            .map(ControlLog::entries)
+            .unwrap_or_default()
     }
 
     /// Get active session IDs
@@ -451,13 +488,14 @@ impl ClaudeSDKClient {
     pub async fn receive_response(
         &mut self,
     ) -> Pin<Box<dyn Stream<Item = Result<Message>> + Send + '_>> {
+        let result_policy = self.options.result_policy;
         let mut messages = self.receive_messages().await;
 
         // Create a stream that stops after ResultMessage
         Box::pin(async_stream::stream! {
             while let Some(msg_result) = messages.next().await {
                 match &msg_result {
-                    Ok(Message::Result { .. }) => {
+                    Ok(msg) if result_policy.ends_turn(msg) => {
                         yield msg_result;
                         return;
                     }
@@ -496,6 +534,33 @@ impl ClaudeSDKClient {
         None
     }
 
+    /// Features supported by the connected CLI
+    ///
+    /// Derived from the CLI version and the initialize response, see
+    /// [`CliFeatures::detect`]. `rewind_files` consults it and fails with
+    /// `SdkError::NotSupported` without sending anything when the CLI is too
+    /// old.
+    pub async fn features(&self) -> CliFeatures {
+        let init = match &self.query_handler {
+            Some(handler) => handler.lock().await.get_initialization_result().cloned(),
+            None => None,
+        };
+        // Without the initialize response only the System init message is
+        // available: it reports the version, but never lists models
+        let version = match (&self.cli_version, &init) {
+            (Some(version), _) => Some(version.clone()),
+            (None, Some(_)) => None,
+            (None, None) => self
+                .get_server_info()
+                .await
+                .as_ref()
+                .and_then(|info| info.get("claude_code_version"))
+                .and_then(|v| v.as_str())
+                .and_then(SemVer::parse),
+        };
+        CliFeatures::detect(version.as_ref(), init.as_ref())
+    }
+
     /// Get account information
     ///
     /// This method attempts to retrieve Claude account information through multiple methods:
@@ -545,6 +610,7 @@ impl ClaudeSDKClient {
         }
 
         // Method 2: Try reading from Claude config
+        #[cfg(feature = "subprocess")]
         if let Some(config_info) = Self::read_claude_config().await {
             return Ok(config_info);
         }
@@ -565,7 +631,7 @@ impl ClaudeSDKClient {
                         }
                     }
                 },
-                Message::Result { .. } => break,
+                message if self.options.result_policy.ends_turn(&message) => break,
                 _ => {},
             }
         }
@@ -588,6 +654,7 @@ impl ClaudeSDKClient {
     }
 
     /// Read Claude config file
+    #[cfg(feature = "subprocess")]
     async fn read_claude_config() -> Option<String> {
         // Try common config locations
         let config_paths = vec![
@@ -684,6 +751,7 @@ impl ClaudeSDKClient {
     ///
     /// This method is similar to Python SDK's query method in ClaudeSDKClient
     pub async fn query(&mut self, prompt: String, session_id: Option<String>) -> Result<()> {
+        self.ensure_accepting_input().await?;
         let session_id = session_id.unwrap_or_else(|| "default".to_string());
 
         // Send the message
@@ -752,6 +820,9 @@ impl ClaudeSDKClient {
             });
         }
 
+        let features = self.features().await;
+        features.require(features.rewind_files, "rewind_files")?;
+
         // Require query handler for control protocol
         if let Some(ref query_handler) = self.query_handler {
             let mut handler = query_handler.lock().await;
@@ -763,6 +834,39 @@ impl ClaudeSDKClient {
         }
     }
 
+    /// Signal that no more input is coming
+    ///
+    /// Closes the CLI's stdin so it can finish the conversation. The client
+    /// then only drains output: `receive_messages` keeps working, while
+    /// sending fails with `SdkError::InvalidState`. Calling it again is a no-op.
+    pub async fn end_input(&mut self) -> Result<()> {
+        {
+            let state = self.state.read().await;
+            match *state {
+                ClientState::Connected => {},
+                ClientState::InputEnded => return Ok(()),
+                _ => {
+                    return Err(SdkError::InvalidState {
+                        message: "Not connected".into(),
+                    });
+                },
+            }
+        }
+
+        {
+            let mut transport = self.transport.lock().await;
+            transport.end_input().await?;
+        }
+
+        {
+            let mut state = self.state.write().await;
+            *state = ClientState::InputEnded;
+        }
+
+        info!("Ended input to Claude CLI");
+        Ok(())
+    }
+
     /// Disconnect from Claude CLI
     pub async fn disconnect(&mut self) -> Result<()> {
         // Check if already disconnected
@@ -802,6 +906,7 @@ impl ClaudeSDKClient {
         let message_buffer = self.message_buffer.clone();
         let state = self.state.clone();
         let budget_manager = self.budget_manager.clone();
+        let mut model = self.options.model.clone();
 
         tokio::spawn(async move {
             // Subscribe to messages without holding the lock
@@ -813,33 +918,19 @@ impl ClaudeSDKClient {
             while let Some(result) = stream.next().await {
                 match result {
                     Ok(message) => {
-                        // Update token usage for Result messages
-                        if let Message::Result { .. } = &message
-                            && let Message::Result {
-                                usage,
-                                total_cost_usd,
-                                ..
-                            } = &message
+                        // The init message names the model the CLI resolved
+                        if let Message::System { subtype, data } = &message
+                            && subtype == "init"
+                            && let Some(resolved) = data.get("model").and_then(|m| m.as_str())
                         {
-                            let (input_tokens, output_tokens) = if let Some(usage_json) = usage {
-                                let input = usage_json
-                                    .get("input_tokens")
-                                    .and_then(|v| v.as_u64())
-                                    .unwrap_or(0);
-                                let output = usage_json
-                                    .get("output_tokens")
-                                    .and_then(|v| v.as_u64())
-                                    .unwrap_or(0);
-                                (input, output)
-                            } else {
-                                (0, 0)
-                            };
-                            let cost = total_cost_usd.unwrap_or(0.0);
-                            budget_manager
-                                .update_usage(input_tokens, output_tokens, cost)
-                                .await;
+                            model = Some(resolved.to_string());
                         }
 
+                        // Update token usage for Result messages
+                        budget_manager
+                            .record_result(model.as_deref(), &message)
+                            .await;
+
                         // Buffer init messages for get_server_info()
                         if let Message::System { subtype, .. } = &message
                             && subtype == "init"
@@ -946,6 +1037,19 @@ impl ClaudeSDKClient {
         self.budget_manager.is_exceeded().await
     }
 
+    /// Estimate the cost of turns that report none from `pricing`
+    pub async fn set_pricing(&self, pricing: crate::token_tracker::PricingTable) {
+        self.budget_manager.set_pricing(pricing).await;
+    }
+
+    /// Spend per model since the last reset
+    ///
+    /// Turns are attributed to the model the CLI reported at session start,
+    /// falling back to the configured model.
+    pub async fn cost_by_model(&self) -> HashMap<String, f64> {
+        self.budget_manager.cost_by_model().await
+    }
+
     // Removed unused helper; usage is updated inline in message receiver
 }
 
@@ -958,7 +1062,7 @@ impl Drop for ClaudeSDKClient {
         if let Ok(handle) = tokio::runtime::Handle::try_current() {
             handle.spawn(async move {
                 let state = state.read().await;
-                if *state == ClientState::Connected {
+                if matches!(*state, ClientState::Connected | ClientState::InputEnded) {
                     let mut transport = transport.lock().await;
                     if let Err(e) = transport.disconnect().await {
                         debug!("Error disconnecting in drop: {}", e);
@@ -972,7 +1076,154 @@ impl Drop for ClaudeSDKClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transport::ControlDirection;
+    #[cfg(all(unix, feature = "subprocess"))]
+    use crate::transport::subprocess::tests::fake_cli;
+
+    #[tokio::test]
+    async fn test_rewind_files_rejected_for_old_cli() {
+        let (transport, script) = crate::transport::mock::MockTransport::builder()
+            .cli_version(SemVer::new(2, 0, 30))
+            .build();
+        let mut client = ClaudeSDKClient::with_transport(ClaudeCodeOptions::default(), transport);
+        client.connect(None).await.unwrap();
+        client.options.enable_file_checkpointing = true;
+
+        let features = client.features().await;
+        assert_eq!(features.version, Some(SemVer::new(2, 0, 30)));
+        assert!(features.sandbox && !features.rewind_files);
+
+        match client.rewind_files("msg_1").await {
+            Err(SdkError::NotSupported { feature }) => assert!(feature.contains("rewind_files")),
+            other => panic!("expected NotSupported, got {other:?}"),
+        }
+        assert!(script.sent_control_requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_features_from_system_init_keep_model_list() {
+        let (transport, handle) = crate::transport::mock::MockTransport::pair();
+        let mut client = ClaudeSDKClient::with_transport(ClaudeCodeOptions::default(), transport);
+        client.connect(None).await.unwrap();
+        while handle.inbound_message_tx.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+        handle
+            .inbound_message_tx
+            .send(Message::System {
+                subtype: "init".into(),
+                data: serde_json::json!({"claude_code_version": "2.0.30"}),
+            })
+            .unwrap();
+        while client.get_server_info().await.is_none() {
+            tokio::task::yield_now().await;
+        }
+
+        // The System init message reports the version but never lists models
+        let features = client.features().await;
+        assert_eq!(features.version, Some(SemVer::new(2, 0, 30)));
+        assert!(features.list_models && !features.rewind_files);
+    }
+
+    /// The custom-transport path must work without the `subprocess` feature
+    #[tokio::test]
+    async fn test_client_with_custom_transport() {
+        let (transport, mut handle) = crate::transport::mock::MockTransport::pair();
+        let mut client = ClaudeSDKClient::with_transport(ClaudeCodeOptions::default(), transport);
+        client.connect(None).await.unwrap();
+        assert!(client.is_connected().await);
+
+        client.send_user_message("hello".into()).await.unwrap();
+        let sent = handle.sent_input_rx.recv().await.unwrap();
+        assert_eq!(sent.message["content"], "hello");
+        client.disconnect().await.unwrap();
+    }
 
+    #[tokio::test]
+    async fn test_control_log_records_can_use_tool_exchange() {
+        use crate::types::{
+            CanUseTool, PermissionResult, PermissionResultAllow, ToolPermissionContext,
+        };
+
+        struct AllowAll;
+
+        #[async_trait::async_trait]
+        impl CanUseTool for AllowAll {
+            async fn can_use_tool(
+                &self,
+                _tool_name: &str,
+                _input: &serde_json::Value,
+                _context: &ToolPermissionContext,
+            ) -> PermissionResult {
+                PermissionResult::Allow(PermissionResultAllow {
+                    updated_input: None,
+                    updated_permissions: None,
+                })
+            }
+        }
+
+        let (transport, mut handle) = crate::transport::mock::MockTransport::pair();
+        let mut options = ClaudeCodeOptions::builder()
+            .record_control_protocol(true)
+            .build();
+        options.can_use_tool = Some(Arc::new(AllowAll));
+        let mut client = ClaudeSDKClient::with_transport(options, transport);
+
+        // Answer the initialize request like the CLI would
+        let sdk_control_tx = handle.sdk_control_tx.clone();
+        let mut outbound_requests = handle.outbound_control_request_rx;
+        tokio::spawn(async move {
+            let init = outbound_requests.recv().await.unwrap();
+            sdk_control_tx
+                .send(serde_json::json!({
+                    "type": "control_response",
+                    "response": {"subtype": "success", "request_id": init["request_id"]}
+                }))
+                .await
+                .unwrap();
+        });
+        client.connect(None).await.unwrap();
+
+        let request = serde_json::json!({
+            "type": "control_request",
+            "request_id": "req_perm",
+            "request": {
+                "subtype": "can_use_tool",
+                "tool_name": "Write",
+                "input": {"path": "/tmp/demo.txt"}
+            }
+        });
+        handle.sdk_control_tx.send(request.clone()).await.unwrap();
+        let response = handle.outbound_control_rx.recv().await.unwrap();
+        assert_eq!(response["response"]["request_id"], "req_perm");
+
+        let log = client.control_log();
+        let directions: Vec<_> = log.iter().map(|entry| entry.direction).collect();
+        assert_eq!(
+            directions,
+            vec![
+                ControlDirection::Outbound,
+                ControlDirection::Inbound,
+                ControlDirection::Inbound,
+                ControlDirection::Outbound,
+            ]
+        );
+        assert_eq!(log[0].message["request"]["subtype"], "initialize");
+        assert_eq!(log[2].message, request);
+        assert_eq!(log[3].message, response);
+        assert!(log.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        client.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_control_log_is_opt_in() {
+        let (transport, _handle) = crate::transport::mock::MockTransport::pair();
+        let client = ClaudeSDKClient::with_transport(ClaudeCodeOptions::default(), transport);
+        assert!(client.control_log.is_none());
+        assert!(client.control_log().is_empty());
+    }
+
+    #[cfg(feature = "subprocess")]
     #[tokio::test]
     async fn test_client_lifecycle() {
         let options = ClaudeCodeOptions::default();
@@ -982,6 +1233,7 @@ mod tests {
         assert_eq!(client.get_sessions().await.len(), 0);
     }
 
+    #[cfg(feature = "subprocess")]
     #[tokio::test]
     async fn test_client_state_transitions() {
         let options = ClaudeCodeOptions::default();
@@ -991,6 +1243,52 @@ mod tests {
         assert_eq!(*state, ClientState::Disconnected);
     }
 
+    #[cfg(all(unix, feature = "subprocess"))]
+    #[tokio::test]
+    async fn test_end_input_closes_stdin_and_rejects_sends() {
+        use std::time::Duration;
+
+        // Fake CLI that leaves a marker once its stdin reaches EOF
+        let dir = tempfile::tempdir().unwrap();
+        let cli = fake_cli(
+            dir.path(),
+            r#"cat > /dev/null
+touch "$(dirname "$0")/stdin-closed"
+exec sleep 30
+"#,
+        );
+
+        let options = ClaudeCodeOptions::default();
+        let transport = crate::transport::SubprocessTransport::with_cli_path(options.clone(), &cli);
+        let mut client = ClaudeSDKClient::with_transport(options, Box::new(transport));
+        client.connect(None).await.unwrap();
+        client.send_user_message("hello".into()).await.unwrap();
+
+        client.end_input().await.unwrap();
+        client.end_input().await.unwrap();
+        assert_eq!(*client.state.read().await, ClientState::InputEnded);
+        assert!(client.is_connected().await);
+
+        let marker = dir.path().join("stdin-closed");
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while !marker.exists() {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "stdin was not closed"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let err = client.send_user_message("more".into()).await.unwrap_err();
+        assert!(matches!(err, SdkError::InvalidState { .. }));
+        let err = client.query("more".into(), None).await.unwrap_err();
+        assert!(matches!(err, SdkError::InvalidState { .. }));
+
+        client.disconnect().await.unwrap();
+        assert!(!client.is_connected().await);
+    }
+
+    #[cfg(feature = "subprocess")]
     #[test]
     fn test_file_checkpointing_enables_query_handler() {
         let options = ClaudeCodeOptions::builder()
@@ -194,7 +194,7 @@ impl ClaudeSDKClientWorking {
         let mut messages = Vec::new();
 
         while let Some(msg) = self.receive_message().await? {
-            let is_result = matches!(msg, Message::Result { .. });
+            let is_result = self.options.result_policy.ends_turn(&msg);
             messages.push(msg);
             if is_result {
                 break;
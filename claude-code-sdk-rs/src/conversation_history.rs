@@ -0,0 +1,258 @@
+//! Conversation history reconstructed from the messages a client observed
+//!
+//! The CLI owns the real transcript, but a UI that joins mid-session or needs
+//! to re-render only sees the stream. [`ConversationHistory`] groups what the
+//! interactive client sent and received into turns: a prompt followed by the
+//! messages Claude produced for it, up to the turn's `Result`. It also
+//! tracks the compactions announced on the stream.
+
+use crate::transcript::{CompactionResult, CompactionTracker};
+use crate::types::{Message, ResultPolicy};
+use std::collections::VecDeque;
+
+/// One prompt and the messages received in response
+#[derive(Debug, Clone)]
+pub struct Turn {
+    /// The user prompt that started the turn
+    pub prompt: String,
+    /// Messages received for this prompt, in order, ending with the
+    /// `Result` message once the turn is complete
+    pub messages: Vec<Message>,
+    complete: bool,
+}
+
+impl Turn {
+    /// Whether the `Result` message ending the turn has arrived
+    ///
+    /// Which `Result` that is depends on the `ResultPolicy` the messages
+    /// were recorded with.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Concatenated text of the assistant messages in this turn
+    pub fn assistant_text(&self) -> String {
+        self.messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::Assistant { message, .. } => Some(message.text()),
+                _ => None,
+            })
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Render the turn as markdown, with thinking elided
+    ///
+    /// See [`to_markdown_with`](Self::to_markdown_with).
+    pub fn to_markdown(&self) -> String {
+        self.to_markdown_with(false)
+    }
+
+    /// Render the turn as markdown: the prompt under a `## User` heading,
+    /// then Claude's messages and the tool results under `## Assistant`
+    ///
+    /// Messages of subagents are left out. Each message is rendered with
+    /// [`AssistantMessage::to_markdown_with`](crate::AssistantMessage::to_markdown_with).
+    pub fn to_markdown_with(&self, include_thinking: bool) -> String {
+        let mut sections = vec![format!("## User\n\n{}", self.prompt)];
+        let blocks: Vec<String> = self
+            .messages
+            .iter()
+            .filter(|message| message.is_top_level())
+            .flat_map(|message| match message {
+                Message::Assistant { message, .. } => message.content.as_slice(),
+                Message::User { message, .. } => message.content_blocks.as_deref().unwrap_or(&[]),
+                _ => &[],
+            })
+            .map(|block| block.to_markdown(include_thinking))
+            .collect();
+        if !blocks.is_empty() {
+            sections.push(format!("## Assistant\n\n{}", blocks.join("\n\n")));
+        }
+        sections.join("\n\n")
+    }
+}
+
+/// Turns kept by a history unless configured otherwise
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+/// Turns observed by a client, oldest first
+#[derive(Debug, Clone)]
+pub struct ConversationHistory {
+    turns: VecDeque<Turn>,
+    limit: usize,
+    compactions: CompactionTracker,
+}
+
+impl Default for ConversationHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LIMIT)
+    }
+}
+
+impl ConversationHistory {
+    /// Create a history keeping at most `limit` turns
+    pub fn new(limit: usize) -> Self {
+        Self {
+            turns: VecDeque::new(),
+            limit,
+            compactions: CompactionTracker::new(),
+        }
+    }
+
+    /// Start a new turn for `prompt`, evicting the oldest turns over the limit
+    pub fn start_turn(&mut self, prompt: impl Into<String>) {
+        self.compactions.record_prompt();
+        self.turns.push_back(Turn {
+            prompt: prompt.into(),
+            messages: Vec::new(),
+            complete: false,
+        });
+        while self.turns.len() > self.limit {
+            self.turns.pop_front();
+        }
+    }
+
+    /// Add a received message to the current turn
+    ///
+    /// A `Result` that ends the turn under `policy` completes it. Messages
+    /// arriving while no turn is open (before the first prompt, or after a
+    /// turn's `Result`) are not part of any turn and are ignored, but still
+    /// count towards [`compactions`](Self::compactions).
+    pub fn record(&mut self, message: &Message, policy: ResultPolicy) {
+        self.compactions.observe(message);
+        if let Some(turn) = self.turns.back_mut()
+            && !turn.complete
+        {
+            turn.messages.push(message.clone());
+            turn.complete = policy.ends_turn(message);
+        }
+    }
+
+    /// Snapshot of the retained turns, oldest first
+    pub fn turns(&self) -> Vec<Turn> {
+        self.turns.iter().cloned().collect()
+    }
+
+    /// Compactions announced on the stream, oldest first
+    ///
+    /// Each prompt counts as one removed message; see [`CompactionTracker`].
+    pub fn compactions(&self) -> Vec<CompactionResult> {
+        self.compactions.compactions().to_vec()
+    }
+
+    /// Drop every retained turn
+    pub fn clear(&mut self) {
+        self.turns.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AssistantMessage, ContentBlock, TextContent};
+
+    #[test]
+    fn test_history_groups_messages_and_respects_limit() {
+        let mut history = ConversationHistory::new(2);
+        history.record(
+            &Message::assistant("before any prompt"),
+            ResultPolicy::TopLevel,
+        );
+
+        for (prompt, reply) in [("one", "1"), ("two", "2"), ("three", "3")] {
+            history.start_turn(prompt);
+            history.record(&Message::assistant(reply), ResultPolicy::TopLevel);
+            history.record(&Message::result_success("s"), ResultPolicy::TopLevel);
+            history.record(
+                &Message::assistant("after the result"),
+                ResultPolicy::TopLevel,
+            );
+        }
+        history.start_turn("four");
+        history.record(&Message::assistant("4"), ResultPolicy::TopLevel);
+
+        let turns = history.turns();
+        assert_eq!(turns.len(), 2);
+        assert_eq!(turns[0].prompt, "three");
+        assert_eq!(turns[0].messages.len(), 2);
+        assert!(turns[0].is_complete());
+        assert_eq!(turns[1].prompt, "four");
+        assert_eq!(turns[1].assistant_text(), "4");
+        assert!(!turns[1].is_complete());
+    }
+
+    #[test]
+    fn test_result_policy_decides_which_result_completes_a_turn() {
+        let mut subagent_result = Message::result_success("s");
+        if let Message::Result {
+            parent_tool_use_id, ..
+        } = &mut subagent_result
+        {
+            *parent_tool_use_id = Some("toolu_task".to_string());
+        }
+
+        for (policy, complete, recorded) in [
+            (ResultPolicy::TopLevel, false, 2),
+            (ResultPolicy::First, true, 1),
+        ] {
+            let mut history = ConversationHistory::default();
+            history.start_turn("Delegate");
+            history.record(&subagent_result, policy);
+            assert_eq!(history.turns()[0].is_complete(), complete);
+            history.record(&Message::result_success("s"), policy);
+            assert_eq!(history.turns()[0].messages.len(), recorded);
+        }
+    }
+
+    #[test]
+    fn test_turn_to_markdown() {
+        use crate::types::{ToolResultContent, ToolUseContent, UserMessage};
+
+        let turn = Turn {
+            prompt: "How many files?".to_string(),
+            messages: vec![
+                Message::Assistant {
+                    message: AssistantMessage {
+                        content: vec![ContentBlock::ToolUse(ToolUseContent {
+                            id: "toolu_1".to_string(),
+                            name: "Bash".to_string(),
+                            input: serde_json::json!({"command": "ls | wc -l"}),
+                        })],
+                    },
+                    parent_tool_use_id: None,
+                },
+                Message::User {
+                    message: UserMessage {
+                        content: String::new(),
+                        content_blocks: Some(vec![ContentBlock::ToolResult(
+                            ToolResultContent::from_ok("toolu_1", "3"),
+                        )]),
+                    },
+                    parent_tool_use_id: None,
+                },
+                Message::Assistant {
+                    message: AssistantMessage {
+                        content: vec![ContentBlock::Text(TextContent {
+                            text: "subagent chatter".to_string(),
+                        })],
+                    },
+                    parent_tool_use_id: Some("toolu_task".to_string()),
+                },
+                Message::assistant("There are 3 files."),
+                Message::result_success("s"),
+            ],
+            complete: true,
+        };
+
+        assert_eq!(
+            turn.to_markdown(),
+            "## User\n\nHow many files?\n\n## Assistant\n\n\
+             **Tool use:** `Bash`\n\n```json\n{\n  \"command\": \"ls | wc -l\"\n}\n```\n\n\
+             **Tool result**\n\n```\n3\n```\n\n\
+             There are 3 files."
+        );
+    }
+}
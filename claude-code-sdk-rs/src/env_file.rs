@@ -0,0 +1,150 @@
+//! Dotenv-style environment files
+//!
+//! Loads `KEY=VALUE` pairs for [`ClaudeCodeOptions::extra_env_file`] so secrets
+//! reach the CLI through its environment rather than its command line.
+//!
+//! [`ClaudeCodeOptions::extra_env_file`]: crate::ClaudeCodeOptions::extra_env_file
+
+use crate::errors::{Result, SdkError};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Parse dotenv-style contents
+///
+/// Supports blank lines, `#` comments, an optional `export ` prefix and values
+/// wrapped in single or double quotes. `source` is only used in error messages.
+pub(crate) fn parse_env(contents: &str, source: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+
+    for (index, raw_line) in contents.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+
+        let (key, value) = line.split_once('=').ok_or_else(|| {
+            SdkError::ConfigError(format!(
+                "{source}:{}: expected KEY=VALUE, got {:?}",
+                index + 1,
+                raw_line
+            ))
+        })?;
+
+        let key = key.trim();
+        let valid_key = !key.is_empty()
+            && !key.starts_with(|c: char| c.is_ascii_digit())
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_key {
+            return Err(SdkError::ConfigError(format!(
+                "{source}:{}: invalid variable name {:?}",
+                index + 1,
+                key
+            )));
+        }
+
+        vars.push((key.to_string(), unquote(value.trim()).to_string()));
+    }
+
+    Ok(vars)
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+/// Load an env file into `cmd`, leaving keys that are set explicitly in `explicit` untouched
+pub(crate) fn apply_env_file(
+    cmd: &mut Command,
+    path: &Path,
+    explicit: &HashMap<String, String>,
+) -> Result<()> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        SdkError::ConfigError(format!("Failed to read env file {}: {}", path.display(), e))
+    })?;
+
+    for (key, value) in parse_env(&contents, &path.display().to_string())? {
+        if !explicit.contains_key(&key) {
+            cmd.env(key, value);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse_env_formats() {
+        let vars = parse_env(
+            "# secrets\n\nAPI_KEY=abc123\nexport REGION = eu-west-1\nQUOTED=\"a b\"\nSINGLE='x=y'\nEMPTY=\n",
+            "test.env",
+        )
+        .unwrap();
+
+        assert_eq!(
+            vars,
+            vec![
+                ("API_KEY".to_string(), "abc123".to_string()),
+                ("REGION".to_string(), "eu-west-1".to_string()),
+                ("QUOTED".to_string(), "a b".to_string()),
+                ("SINGLE".to_string(), "x=y".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_env_reports_line_numbers() {
+        let err = parse_env("OK=1\nnot a pair\n", "test.env").unwrap_err();
+        assert!(err.to_string().contains("test.env:2"), "{err}");
+
+        let err = parse_env("1BAD=x\n", "test.env").unwrap_err();
+        assert!(err.to_string().contains("invalid variable name"), "{err}");
+    }
+
+    #[test]
+    fn test_missing_file_is_a_config_error() {
+        let mut cmd = Command::new("true");
+        let err = apply_env_file(
+            &mut cmd,
+            Path::new("/nonexistent/secrets.env"),
+            &HashMap::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, SdkError::ConfigError(_)));
+        assert!(err.to_string().contains("/nonexistent/secrets.env"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_child_receives_env_file_vars() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "NEXUS_TEST_SECRET=from-file").unwrap();
+        writeln!(file, "NEXUS_TEST_OVERRIDE=from-file").unwrap();
+
+        let explicit = HashMap::from([("NEXUS_TEST_OVERRIDE".to_string(), "explicit".to_string())]);
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg("echo \"$NEXUS_TEST_SECRET $NEXUS_TEST_OVERRIDE\"");
+        apply_env_file(&mut cmd, file.path(), &explicit).unwrap();
+        for (key, value) in &explicit {
+            cmd.env(key, value);
+        }
+
+        let output = cmd.output().await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            "from-file explicit"
+        );
+    }
+}
@@ -4,6 +4,8 @@
 //! The errors are designed to be informative and actionable, helping users
 //! understand what went wrong and how to fix it.
 
+use crate::types::{ContentBlock, Message};
+use std::time::Duration;
 use thiserror::Error;
 
 /// Main error type for the Claude Code SDK
@@ -22,15 +24,29 @@ pub enum SdkError {
     #[error("Failed to connect to Claude CLI: {0}")]
     ConnectionError(String),
 
+    /// Could not reach a server through the configured HTTP proxy
+    #[error("Failed to connect through proxy {proxy}: {message}")]
+    ProxyError {
+        /// Proxy URL that was used
+        proxy: String,
+        /// Underlying connection error
+        message: String,
+    },
+
     /// Process-related errors
     #[error("Process error: {0}")]
     ProcessError(#[from] std::io::Error),
 
     /// Failed to parse a message
-    #[error("Failed to parse message: {error}\nRaw message: {raw}")]
+    #[error(
+        "Failed to parse message{}: {error}\nRaw message: {raw}",
+        .path.as_deref().map(|path| format!(" at `{path}`")).unwrap_or_default()
+    )]
     MessageParseError {
         /// Parse error description
         error: String,
+        /// JSON path of the offending field (e.g. `message.content[2].text`), when known
+        path: Option<String>,
         /// Raw message that failed to parse
         raw: String,
     },
@@ -122,6 +138,60 @@ pub enum SdkError {
         /// Description of unsupported feature
         feature: String,
     },
+
+    /// The API rate-limited the request
+    #[error(
+        "Rate limited by the API{}",
+        .retry_after.map(|d| format!(" (retry after {d:?})")).unwrap_or_default()
+    )]
+    RateLimited {
+        /// How long the server asked to wait before retrying, if it said
+        retry_after: Option<Duration>,
+    },
+
+    /// A tool_use_id was requested or answered more than once
+    #[error("Duplicate tool_use_id {tool_use_id}: {reason}")]
+    DuplicateToolUseId {
+        /// The offending tool use ID
+        tool_use_id: String,
+        /// Whether the id was requested twice or answered twice
+        reason: String,
+    },
+
+    /// A configured MCP server failed to start or connect
+    #[error("MCP server {name} failed: {detail}")]
+    McpServerFailed {
+        /// Name of the server in `mcp_servers`
+        name: String,
+        /// The error or status the CLI reported
+        detail: String,
+    },
+
+    /// A streamed content block ended before it was fully assembled
+    #[error("Incomplete content block: {reason}")]
+    IncompleteContentBlock {
+        /// Whatever was assembled before the block was cut off
+        partial: Box<ContentBlock>,
+        /// Why the block could not be completed
+        reason: String,
+    },
+
+    /// The operation was cancelled by the caller
+    #[error("Operation cancelled")]
+    Cancelled {
+        /// Messages received before the cancellation took effect
+        partial: Vec<Message>,
+    },
+
+    /// An operation still failed after being retried
+    #[error("Failed after {attempts} attempts: {last_error}")]
+    RetryFailed {
+        /// Number of attempts made, including the first
+        attempts: u32,
+        /// Error of the last attempt
+        #[source]
+        last_error: Box<SdkError>,
+    },
 }
 
 /// Result type alias for SDK operations
@@ -132,10 +202,54 @@ impl SdkError {
     pub fn parse_error(error: impl Into<String>, raw: impl Into<String>) -> Self {
         Self::MessageParseError {
             error: error.into(),
+            path: None,
+            raw: raw.into(),
+        }
+    }
+
+    /// Create a new MessageParseError pointing at the field at `path`
+    pub fn parse_error_at(
+        path: impl Into<String>,
+        error: impl Into<String>,
+        raw: impl Into<String>,
+    ) -> Self {
+        Self::MessageParseError {
+            error: error.into(),
+            path: Some(path.into()),
+            raw: raw.into(),
+        }
+    }
+
+    /// Create a MessageParseError from a path-tracking serde failure
+    pub(crate) fn from_path_error(
+        context: &str,
+        err: serde_path_to_error::Error<serde_json::Error>,
+        raw: impl Into<String>,
+    ) -> Self {
+        // serde_path_to_error renders the root as "."
+        let path = err.path().to_string();
+        Self::MessageParseError {
+            error: format!("{context}: {}", err.inner()),
+            path: (path != ".").then_some(path),
             raw: raw.into(),
         }
     }
 
+    /// Prefix the path of a MessageParseError with the location of the enclosing value
+    pub(crate) fn within(self, prefix: &str) -> Self {
+        match self {
+            Self::MessageParseError { error, path, raw } => Self::MessageParseError {
+                error,
+                path: Some(match path {
+                    Some(path) => format!("{prefix}.{path}"),
+                    None => prefix.to_string(),
+                }),
+                raw,
+            },
+            other => other,
+        }
+    }
+
     /// Create a new Timeout error
     pub fn timeout(seconds: u64) -> Self {
         Self::Timeout { seconds }
@@ -164,6 +278,77 @@ impl SdkError {
         }
     }
 
+    /// Detect a rate-limit failure reported by the CLI
+    ///
+    /// Returns `SdkError::RateLimited` for a failed `Result` message whose
+    /// API error has status 429 or type `rate_limit_error`. The CLI reports
+    /// these as `API Error: <status> <json body>`; the retry-after duration is
+    /// taken from a `retry_after` (seconds) or `retry_after_ms` field of the
+    /// body when present. The subprocess transport's message streams already
+    /// yield this error just before the message; this is for callers reading
+    /// messages some other way.
+    pub fn rate_limited(message: &Message) -> Option<Self> {
+        let Message::Result {
+            is_error: true,
+            result: Some(text),
+            ..
+        } = message
+        else {
+            return None;
+        };
+        let (status, body) = parse_api_error(text)?;
+        let error = body.as_ref().map(|body| body.get("error").unwrap_or(body));
+        let error_type = error
+            .and_then(|error| error.get("type"))
+            .and_then(|v| v.as_str());
+        if status != Some(429) && error_type != Some("rate_limit_error") {
+            return None;
+        }
+        Some(Self::RateLimited {
+            retry_after: body.as_ref().and_then(|body| {
+                retry_after_field(body).or_else(|| retry_after_field(body.get("error")?))
+            }),
+        })
+    }
+
+    /// Detect MCP servers the CLI could not start or connect to
+    ///
+    /// Reads the server list of the CLI's `init` system message and returns
+    /// an `SdkError::McpServerFailed` for each server that is neither
+    /// connected nor still pending. The session keeps running without the
+    /// tools of those servers, so these are warnings rather than stream
+    /// errors; the subprocess transport also reports them to the log sink as
+    /// `DiagnosticEvent::McpServerFailed`.
+    pub fn mcp_server_failures(message: &Message) -> Vec<Self> {
+        let Message::System { subtype, data } = message else {
+            return Vec::new();
+        };
+        if subtype != "init" {
+            return Vec::new();
+        }
+        let Some(servers) = data.get("mcp_servers").and_then(|v| v.as_array()) else {
+            return Vec::new();
+        };
+        servers
+            .iter()
+            .filter_map(|server| {
+                let name = server.get("name")?.as_str()?;
+                let status = server.get("status")?.as_str()?;
+                if matches!(status, "connected" | "pending") {
+                    return None;
+                }
+                let detail = server
+                    .get("error")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or(status);
+                Some(Self::McpServerFailed {
+                    name: name.to_string(),
+                    detail: detail.to_string(),
+                })
+            })
+            .collect()
+    }
+
     /// Check if the error is recoverable
     pub fn is_recoverable(&self) -> bool {
         matches!(
@@ -172,9 +357,40 @@ impl SdkError {
                 | Self::ChannelClosed
                 | Self::UnexpectedStreamEnd
                 | Self::ProcessExited { .. }
+                | Self::RateLimited { .. }
         )
     }
 
+    /// Check if running the whole operation again may succeed
+    ///
+    /// True for transient failures to start or talk to the CLI: spawn and
+    /// connection errors, the process crashing or the stream ending early,
+    /// timeouts and rate limits. False for permanent errors such as a missing
+    /// CLI, invalid configuration or an error reported by the CLI (which
+    /// includes rejected authentication). A process that exited with code 1
+    /// or 2 stopped on its own error, such as rejected credentials or invalid
+    /// arguments, and would fail the same way again, so only other exits
+    /// (e.g. killed by a signal) are retried. Unlike
+    /// [`is_recoverable`](Self::is_recoverable), this is about restarting
+    /// from scratch rather than continuing the current session.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::ProcessError(e) => !matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+            ),
+            Self::ConnectionError(_)
+            | Self::ProxyError { .. }
+            | Self::TransportError(_)
+            | Self::Timeout { .. }
+            | Self::ChannelClosed
+            | Self::UnexpectedStreamEnd
+            | Self::RateLimited { .. } => true,
+            Self::ProcessExited { code } => !matches!(code, Some(1 | 2)),
+            _ => false,
+        }
+    }
+
     /// Check if the error is a configuration issue
     pub fn is_config_error(&self) -> bool {
         matches!(
@@ -184,6 +400,29 @@ impl SdkError {
     }
 }
 
+/// Split an `API Error: <status> <json body>` result into its parts
+///
+/// Returns `None` when the text is not an API error; either part is `None`
+/// when missing.
+fn parse_api_error(text: &str) -> Option<(Option<u16>, Option<serde_json::Value>)> {
+    let rest = text.trim().strip_prefix("API Error:")?.trim_start();
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let status = rest[..digits].parse().ok();
+    let body = serde_json::from_str(rest[digits..].trim()).ok();
+    Some((status, body))
+}
+
+/// Read `retry_after` (seconds) or `retry_after_ms` from an error object
+fn retry_after_field(error: &serde_json::Value) -> Option<Duration> {
+    if let Some(millis) = error.get("retry_after_ms").and_then(|v| v.as_u64()) {
+        return Some(Duration::from_millis(millis));
+    }
+    let secs = error.get("retry_after").and_then(|v| v.as_f64())?;
+    Duration::try_from_secs_f64(secs).ok()
+}
+
 // Implement From for common channel errors
 impl<T> From<tokio::sync::mpsc::error::SendError<T>> for SdkError {
     fn from(_: tokio::sync::mpsc::error::SendError<T>) -> Self {
@@ -211,6 +450,117 @@ mod tests {
         assert!(msg.contains("/usr/local/bin"));
     }
 
+    fn failed_result(text: &str) -> Message {
+        let mut message = Message::result_success("s");
+        if let Message::Result {
+            subtype,
+            is_error,
+            result,
+            ..
+        } = &mut message
+        {
+            *subtype = "error_during_execution".into();
+            *is_error = true;
+            *result = Some(text.into());
+        }
+        message
+    }
+
+    fn retry_after(message: &Message) -> Option<Option<Duration>> {
+        match SdkError::rate_limited(message)? {
+            SdkError::RateLimited { retry_after } => Some(retry_after),
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_rate_limited_from_api_error_result() {
+        assert_eq!(
+            retry_after(&failed_result(
+                r#"API Error: 429 {"type":"error","error":{"type":"rate_limit_error","message":"slow down"}}"#
+            )),
+            Some(None)
+        );
+        assert_eq!(
+            retry_after(&failed_result(
+                r#"API Error: 429 {"type":"error","error":{"type":"rate_limit_error"},"retry_after":30}"#
+            )),
+            Some(Some(Duration::from_secs(30)))
+        );
+        assert_eq!(
+            retry_after(&failed_result(
+                r#"API Error: 529 {"type":"error","error":{"type":"rate_limit_error","retry_after_ms":250}}"#
+            )),
+            Some(Some(Duration::from_millis(250)))
+        );
+        assert_eq!(retry_after(&failed_result("API Error: 429")), Some(None));
+
+        // Text that merely mentions rate limits or 429 is not a rate limit
+        assert!(SdkError::rate_limited(&failed_result("Rate limit exceeded")).is_none());
+        assert!(SdkError::rate_limited(&failed_result("Wrote 429 lines")).is_none());
+        assert!(
+            SdkError::rate_limited(&failed_result(
+                r#"API Error: 500 {"type":"error","error":{"type":"api_error"}}"#
+            ))
+            .is_none()
+        );
+        let mut ok = failed_result("API Error: 429");
+        if let Message::Result { is_error, .. } = &mut ok {
+            *is_error = false;
+        }
+        assert!(SdkError::rate_limited(&ok).is_none());
+        let stderr = Message::System {
+            subtype: "error".into(),
+            data: serde_json::json!({
+                "source": "stderr",
+                "details": "Error: 429 Too Many Requests"
+            }),
+        };
+        assert!(SdkError::rate_limited(&stderr).is_none());
+
+        let err = SdkError::RateLimited {
+            retry_after: Some(Duration::from_secs(2)),
+        };
+        assert!(err.is_recoverable());
+        assert_eq!(err.to_string(), "Rate limited by the API (retry after 2s)");
+    }
+
+    #[test]
+    fn test_mcp_server_failures_from_init() {
+        let init = Message::System {
+            subtype: "init".into(),
+            data: serde_json::json!({
+                "session_id": "s",
+                "mcp_servers": [
+                    {"name": "search", "status": "connected"},
+                    {"name": "db", "status": "failed", "error": "spawn pg-mcp ENOENT"},
+                    {"name": "remote", "status": "needs-auth"},
+                    {"name": "slow", "status": "pending"}
+                ]
+            }),
+        };
+        let failures: Vec<(String, String)> = SdkError::mcp_server_failures(&init)
+            .into_iter()
+            .map(|err| match err {
+                SdkError::McpServerFailed { name, detail } => (name, detail),
+                other => panic!("unexpected error: {other}"),
+            })
+            .collect();
+        assert_eq!(
+            failures,
+            vec![
+                ("db".into(), "spawn pg-mcp ENOENT".into()),
+                ("remote".into(), "needs-auth".into()),
+            ]
+        );
+
+        let status = Message::System {
+            subtype: "status".into(),
+            data: serde_json::json!({"mcp_servers": [{"name": "db", "status": "failed"}]}),
+        };
+        assert!(SdkError::mcp_server_failures(&status).is_empty());
+    }
+
     #[test]
     fn test_is_recoverable() {
         assert!(SdkError::timeout(30).is_recoverable());
@@ -218,6 +568,30 @@ mod tests {
         assert!(!SdkError::ConfigError("test".into()).is_recoverable());
     }
 
+    #[test]
+    fn test_is_retryable() {
+        let spawn_failure = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe");
+        assert!(SdkError::ProcessError(spawn_failure).is_retryable());
+        assert!(SdkError::ProcessExited { code: None }.is_retryable());
+        assert!(SdkError::ProcessExited { code: Some(137) }.is_retryable());
+        // The CLI's own error exits (auth, config, usage) are not transient
+        assert!(!SdkError::ProcessExited { code: Some(1) }.is_retryable());
+        assert!(!SdkError::ProcessExited { code: Some(2) }.is_retryable());
+        assert!(SdkError::UnexpectedStreamEnd.is_retryable());
+        assert!(SdkError::ConnectionError("reset".into()).is_retryable());
+
+        let missing = std::io::Error::new(std::io::ErrorKind::NotFound, "claude");
+        assert!(!SdkError::ProcessError(missing).is_retryable());
+        assert!(
+            !SdkError::CliNotFound {
+                searched_paths: "test".into()
+            }
+            .is_retryable()
+        );
+        assert!(!SdkError::ConfigError("test".into()).is_retryable());
+        assert!(!SdkError::cli_error("Invalid API key", None).is_retryable());
+    }
+
     #[test]
     fn test_is_config_error() {
         assert!(SdkError::ConfigError("test".into()).is_config_error());
@@ -245,12 +619,34 @@ mod tests {
         assert!(error_str.contains(&line));
     }
 
+    #[test]
+    fn test_parse_error_path_is_prefixed_and_displayed() {
+        let err = SdkError::parse_error_at("text", "Missing 'text' field", "{}")
+            .within("message.content[3]");
+        match &err {
+            SdkError::MessageParseError { path, .. } => {
+                assert_eq!(path.as_deref(), Some("message.content[3].text"));
+            },
+            _ => panic!("expected MessageParseError"),
+        }
+        assert!(err.to_string().starts_with(
+            "Failed to parse message at `message.content[3].text`: Missing 'text' field"
+        ));
+
+        // Other variants pass through untouched
+        assert!(matches!(
+            SdkError::timeout(1).within("message"),
+            SdkError::Timeout { seconds: 1 }
+        ));
+    }
+
     #[test]
     fn test_parse_error_constructor() {
         let err = SdkError::parse_error("bad json", r#"{"broken"#);
         match &err {
-            SdkError::MessageParseError { error, raw } => {
+            SdkError::MessageParseError { error, path, raw } => {
                 assert_eq!(error, "bad json");
+                assert_eq!(path, &None);
                 assert_eq!(raw, r#"{"broken"#);
             },
             _ => panic!("expected MessageParseError"),
@@ -0,0 +1,166 @@
+//! Route one message stream to several sinks
+//!
+//! A server often wants every message persisted but only part of them shown,
+//! e.g. full messages to storage and assistant messages to a UI. [`fan_out`]
+//! drives the client's stream once and hands each message to every sink whose
+//! filter accepts it, instead of each consumer subscribing separately.
+
+use crate::{errors::Result, types::Message};
+use futures::stream::{Stream, StreamExt};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// Decides whether a message goes to a sink
+pub type MessageFilter = Box<dyn Fn(&Message) -> bool + Send + Sync>;
+
+/// A sink and the filter selecting the messages it receives
+pub struct Route {
+    sink: mpsc::Sender<Message>,
+    filter: MessageFilter,
+}
+
+impl Route {
+    /// Send the messages accepted by `filter` to `sink`
+    pub fn new(
+        sink: mpsc::Sender<Message>,
+        filter: impl Fn(&Message) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            sink,
+            filter: Box::new(filter),
+        }
+    }
+
+    /// Send every message to `sink`
+    pub fn all(sink: mpsc::Sender<Message>) -> Self {
+        Self::new(sink, |_| true)
+    }
+}
+
+/// Drive `messages` to the end, routing each message to the matching sinks
+///
+/// Messages are delivered in stream order; a slow sink applies backpressure
+/// to the whole fan-out. A sink whose receiver was dropped is skipped from
+/// then on, and the stream is only drained while some sink is still open.
+/// The first stream error is returned without being routed, which drops
+/// the senders so every sink sees the end of its channel.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use nexus_claude::{InteractiveClient, ClaudeCodeOptions, Message, Route, fan_out};
+/// # async fn example() -> nexus_claude::Result<()> {
+/// let mut client = InteractiveClient::new(ClaudeCodeOptions::default())?;
+/// client.connect().await?;
+/// client.send_message("Summarize the repo".to_string()).await?;
+///
+/// let (store_tx, _store_rx) = tokio::sync::mpsc::channel(64);
+/// let (ui_tx, _ui_rx) = tokio::sync::mpsc::channel(64);
+/// let stream = client.receive_response_stream().await;
+/// fan_out(
+///     stream,
+///     vec![
+///         Route::all(store_tx),
+///         Route::new(ui_tx, |msg| matches!(msg, Message::Assistant { .. })),
+///     ],
+/// )
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn fan_out<S>(messages: S, mut routes: Vec<Route>) -> Result<()>
+where
+    S: Stream<Item = Result<Message>>,
+{
+    let mut messages = std::pin::pin!(messages);
+    while !routes.is_empty() {
+        let Some(message) = messages.next().await else {
+            break;
+        };
+        let message = message?;
+
+        let mut closed = Vec::new();
+        for (i, route) in routes.iter().enumerate() {
+            if (route.filter)(&message) && route.sink.send(message.clone()).await.is_err() {
+                closed.push(i);
+            }
+        }
+        for i in closed.into_iter().rev() {
+            debug!("Fan-out sink closed, dropping its route");
+            routes.remove(i);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::SdkError;
+
+    fn system() -> Message {
+        Message::System {
+            subtype: "init".to_string(),
+            data: serde_json::json!({}),
+        }
+    }
+
+    async fn drain(mut rx: mpsc::Receiver<Message>) -> Vec<Message> {
+        let mut received = Vec::new();
+        while let Some(message) = rx.recv().await {
+            received.push(message);
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_routes_by_filter() {
+        let messages = futures::stream::iter(vec![
+            Ok(system()),
+            Ok(Message::assistant("one")),
+            Ok(Message::assistant("two")),
+        ]);
+        let (all_tx, all_rx) = mpsc::channel(8);
+        let (ui_tx, ui_rx) = mpsc::channel(8);
+        let (closed_tx, closed_rx) = mpsc::channel(8);
+        drop(closed_rx);
+
+        fan_out(
+            messages,
+            vec![
+                Route::all(all_tx),
+                Route::all(closed_tx),
+                Route::new(ui_tx, |msg| matches!(msg, Message::Assistant { .. })),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let all = drain(all_rx).await;
+        assert_eq!(all.len(), 3);
+        assert!(matches!(all[0], Message::System { .. }));
+        let ui: Vec<String> = drain(ui_rx)
+            .await
+            .iter()
+            .map(|msg| match msg {
+                Message::Assistant { message, .. } => message.text(),
+                other => panic!("UI sink got {other:?}"),
+            })
+            .collect();
+        assert_eq!(ui, vec!["one", "two"]);
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_stops_at_stream_error() {
+        let messages = futures::stream::iter(vec![
+            Ok(Message::assistant("one")),
+            Err(SdkError::Timeout { seconds: 1 }),
+            Ok(Message::assistant("two")),
+        ]);
+        let (tx, rx) = mpsc::channel(8);
+
+        let result = fan_out(messages, vec![Route::all(tx)]).await;
+        assert!(matches!(result, Err(SdkError::Timeout { .. })));
+        assert_eq!(drain(rx).await.len(), 1);
+    }
+}
@@ -1,22 +1,214 @@
 //! Working interactive client implementation
 
 use crate::{
+    auto_responder::AutoResponder,
+    conversation_history::{ConversationHistory, DEFAULT_HISTORY_LIMIT, Turn},
     errors::{Result, SdkError},
-    transport::{InputMessage, SubprocessTransport, Transport},
+    perf_utils::TokenRateMeter,
+    tool_use_guard::ToolUseGuard,
+    transcript::CompactionResult,
+    transport::{InputMessage, Transport, is_reconnect_marker},
     types::{
-        ClaudeCodeOptions, ControlRequest, HookCallback, HookContext, HookInput, HookJSONOutput,
-        HookMatcher, Message, SDKControlInitializeRequest, SDKControlRequest,
-        SDKHookCallbackRequest,
+        ClaudeCodeOptions, ContentBlock, ControlRequest, ControlResponse, HookCallback,
+        HookContext, HookJSONOutput, HookMatcher, Message, PermissionMode, ResultPolicy,
+        SDKControlInitializeRequest, SDKControlRequest, SDKHookCallbackRequest,
     },
 };
 use futures::{Stream, StreamExt};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock, mpsc, oneshot, watch};
 use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
+/// How long to wait for the CLI to acknowledge an SDK control request
+const CONTROL_RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait for the CLI to acknowledge an interrupt
+const INTERRUPT_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `drain` waits for further output when no turn is in progress
+const DRAIN_IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long `drain` waits for each message of a turn in progress
+const DRAIN_TURN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Result of an acknowledged interrupt (see `InteractiveClient::interrupt_and_confirm`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterruptOutcome {
+    /// ID of the interrupt control request
+    pub request_id: String,
+    /// Whether the CLI reported the interrupt as successful
+    pub success: bool,
+    /// Whether a turn was in progress, i.e. whether there was anything to interrupt
+    pub turn_in_progress: bool,
+}
+
+/// What an interrupt achieved (see `InteractiveClient::interrupt_with_result`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptResult {
+    /// Nothing was running when the interrupt was sent
+    NoActiveTurn,
+    /// The CLI acknowledged interrupting the turn in progress
+    TurnInterrupted,
+    /// A turn was in progress but the CLI rejected the interrupt, so the
+    /// turn keeps running
+    Rejected,
+    /// The CLI did not acknowledge the interrupt in time
+    Timeout,
+}
+
+/// An interrupt the CLI has not answered yet
+struct PendingInterrupt {
+    request_id: String,
+    sent: Instant,
+    /// Resolved by the control router when the CLI answers
+    response_rx: oneshot::Receiver<serde_json::Value>,
+}
+
+impl PendingInterrupt {
+    /// Whether later interrupts should wait on this one instead of sending
+    fn in_flight(&mut self) -> bool {
+        self.sent.elapsed() < INTERRUPT_ACK_TIMEOUT
+            && matches!(
+                self.response_rx.try_recv(),
+                Err(oneshot::error::TryRecvError::Empty)
+            )
+    }
+}
+
+/// Routes inbound SDK control messages once the client awaits its own requests
+///
+/// Responses to requests sent by the client are delivered to their waiter
+/// (the pending-response mechanism of `Query`); every other message is
+/// forwarded to the receiver handed out by `take_sdk_control_receiver()`.
+/// When the transport restarts the CLI, pending waiters are failed and the
+/// initialize request sent by `initialize_hooks` is sent again.
+struct ControlRouter {
+    pending: PendingResponses,
+    forwarded_rx: Option<mpsc::Receiver<serde_json::Value>>,
+    /// Initialize request to re-send to a restarted CLI
+    init_request: Arc<std::sync::Mutex<Option<SDKControlRequest>>>,
+}
+
+/// Waiters for the responses to control requests, keyed by request ID
+type PendingResponses = Arc<Mutex<HashMap<String, oneshot::Sender<serde_json::Value>>>>;
+
+impl ControlRouter {
+    fn spawn(
+        mut inbound: mpsc::Receiver<serde_json::Value>,
+        transport: Arc<Mutex<Box<dyn Transport + Send>>>,
+    ) -> Self {
+        let pending: PendingResponses = Arc::default();
+        let init_request: Arc<std::sync::Mutex<Option<SDKControlRequest>>> = Arc::default();
+        let (forward_tx, forwarded_rx) = mpsc::channel(100);
+
+        // Queue everything else without bound and forward it with backpressure:
+        // the CLI waits for an answer to each of its requests, so none may be
+        // dropped, and a slow receiver must not stall the responses routed below
+        let (queue_tx, mut queue_rx) = mpsc::unbounded_channel::<serde_json::Value>();
+        tokio::spawn(async move {
+            while let Some(message) = queue_rx.recv().await {
+                if forward_tx.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let pending_clone = pending.clone();
+        let init_request_clone = init_request.clone();
+        tokio::spawn(async move {
+            while let Some(message) = inbound.recv().await {
+                if is_reconnect_marker(&message) {
+                    // Requests sent before the CLI restarted will never be answered
+                    pending_clone.lock().await.clear();
+                    // The new process knows nothing of the registered hooks
+                    let request = init_request_clone
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .clone();
+                    if let Some(request) = request {
+                        tokio::spawn(reinitialize(
+                            transport.clone(),
+                            pending_clone.clone(),
+                            request,
+                        ));
+                    }
+                    continue;
+                }
+                if message.get("type").and_then(|v| v.as_str()) == Some("control_response")
+                    && let Some(response) = message.get("response")
+                    && let Some(request_id) = response
+                        .get("request_id")
+                        .or_else(|| response.get("requestId"))
+                        .and_then(|v| v.as_str())
+                    && let Some(waiter) = pending_clone.lock().await.remove(request_id)
+                {
+                    let _ = waiter.send(response.clone());
+                    continue;
+                }
+
+                let _ = queue_tx.send(message);
+            }
+            debug!("SDK control channel closed, stopping control router");
+        });
+
+        Self {
+            pending,
+            forwarded_rx: Some(forwarded_rx),
+            init_request,
+        }
+    }
+}
+
+/// Send `request` to a restarted CLI, logging the outcome
+async fn reinitialize(
+    transport: Arc<Mutex<Box<dyn Transport + Send>>>,
+    pending: PendingResponses,
+    request: SDKControlRequest,
+) {
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let (tx, rx) = oneshot::channel();
+    pending.lock().await.insert(request_id.clone(), tx);
+
+    let control_msg = serde_json::json!({
+        "type": "control_request",
+        "request_id": request_id,
+        "request": request
+    });
+    let sent = transport
+        .lock()
+        .await
+        .send_sdk_control_request(control_msg)
+        .await;
+    let outcome = match sent {
+        Ok(()) => match tokio::time::timeout(CONTROL_RESPONSE_TIMEOUT, rx).await {
+            Ok(Ok(response))
+                if response.get("subtype").and_then(|v| v.as_str()) != Some("error") =>
+            {
+                Ok(())
+            },
+            Ok(Ok(response)) => Err(response
+                .get("error")
+                .and_then(|v| v.as_str())
+                .unwrap_or("Unknown control request error")
+                .to_string()),
+            Ok(Err(_)) => Err("CLI connection lost".to_string()),
+            Err(_) => Err("no response".to_string()),
+        },
+        Err(e) => Err(e.to_string()),
+    };
+    pending.lock().await.remove(&request_id);
+    match outcome {
+        Ok(()) => info!("Re-registered hooks with the restarted CLI"),
+        Err(e) => warn!("Failed to re-register hooks with the restarted CLI: {}", e),
+    }
+}
+
 /// Interactive client for stateful conversations with Claude
 ///
 /// This is the recommended client for interactive use. It provides a clean API
@@ -30,48 +222,95 @@ pub struct InteractiveClient {
     hook_callbacks: Arc<RwLock<HashMap<String, Arc<dyn HookCallback>>>>,
     /// Counter for generating unique callback IDs
     callback_counter: Arc<Mutex<u64>>,
+    /// Duplicate tool_use_id tracking (enabled via `duplicate_tool_use_policy`)
+    tool_use_guard: Option<Arc<Mutex<ToolUseGuard>>>,
+    /// Inbound control routing, started by the first awaited control request
+    control_router: Arc<Mutex<Option<ControlRouter>>>,
+    /// True between sending a prompt and receiving its Result message
+    turn_in_progress: Arc<watch::Sender<bool>>,
+    /// Set by `end_input`; the client then only drains output
+    input_ended: bool,
+    /// Turns observed by this client (bounded by `history_limit`)
+    history: Arc<Mutex<ConversationHistory>>,
+    /// Tools answered locally (from `auto_responder`)
+    auto_responder: Option<AutoResponder>,
+    /// Active model (from `model`, updated by `set_model`)
+    model: Option<String>,
+    /// Working directory of the CLI (from `cwd`)
+    cwd: Option<PathBuf>,
+    /// Active permission mode (from `permission_mode`, updated by `set_permission_mode`)
+    permission_mode: PermissionMode,
+    /// Which Result message ends a turn (from `result_policy`)
+    result_policy: ResultPolicy,
+    /// Graceful shutdown window for `disconnect` (from `graceful_shutdown_timeout`)
+    graceful_shutdown_timeout: Option<Duration>,
+    /// Output rate of the current turn
+    token_rate: Arc<std::sync::Mutex<TokenRateMeter>>,
+    /// Last interrupt sent, for coalescing repeated interrupts
+    pending_interrupt: Option<PendingInterrupt>,
 }
 
 impl InteractiveClient {
     /// Create a client from a pre-built transport (for testing or custom transports)
     pub fn from_transport(transport: Box<dyn Transport + Send>) -> Self {
-        Self {
-            transport: Arc::new(Mutex::new(transport)),
-            connected: false,
-            hooks: None,
-            hook_callbacks: Arc::new(RwLock::new(HashMap::new())),
-            callback_counter: Arc::new(Mutex::new(0)),
-        }
+        Self::from_transport_with_options(transport, &ClaudeCodeOptions::default())
     }
 
     /// Create a client from a pre-built transport with hooks (for testing)
     pub fn from_transport_with_hooks(
         transport: Box<dyn Transport + Send>,
         hooks: HashMap<String, Vec<HookMatcher>>,
+    ) -> Self {
+        let mut client = Self::from_transport(transport);
+        client.hooks = Some(hooks);
+        client
+    }
+
+    /// Create a client from a pre-built transport, applying the client-side
+    /// settings of `options` (hooks, `duplicate_tool_use_policy`,
+    /// `auto_responder`, `history_limit`, `result_policy`, ...)
+    ///
+    /// Settings that configure the CLI process itself are the transport's
+    /// concern and are ignored here.
+    pub fn from_transport_with_options(
+        transport: Box<dyn Transport + Send>,
+        options: &ClaudeCodeOptions,
     ) -> Self {
         Self {
             transport: Arc::new(Mutex::new(transport)),
             connected: false,
-            hooks: Some(hooks),
+            hooks: options.hooks.clone(),
             hook_callbacks: Arc::new(RwLock::new(HashMap::new())),
             callback_counter: Arc::new(Mutex::new(0)),
+            tool_use_guard: options
+                .duplicate_tool_use_policy
+                .map(|policy| Arc::new(Mutex::new(ToolUseGuard::new(policy)))),
+            control_router: Arc::new(Mutex::new(None)),
+            turn_in_progress: Arc::new(watch::channel(false).0),
+            input_ended: false,
+            history: Arc::new(Mutex::new(ConversationHistory::new(
+                options.history_limit.unwrap_or(DEFAULT_HISTORY_LIMIT),
+            ))),
+            auto_responder: options.auto_responder.clone(),
+            model: options.model.clone(),
+            cwd: options.cwd.clone(),
+            permission_mode: options.permission_mode,
+            result_policy: options.result_policy,
+            graceful_shutdown_timeout: options.graceful_shutdown_timeout,
+            token_rate: Arc::default(),
+            pending_interrupt: None,
         }
     }
 
     /// Create a new client
+    #[cfg(feature = "subprocess")]
     pub fn new(options: ClaudeCodeOptions) -> Result<Self> {
         unsafe {
             std::env::set_var("CLAUDE_CODE_ENTRYPOINT", "sdk-rust");
         }
-        let hooks = options.hooks.clone();
-        let transport: Box<dyn Transport + Send> = Box::new(SubprocessTransport::new(options)?);
-        Ok(Self {
-            transport: Arc::new(Mutex::new(transport)),
-            connected: false,
-            hooks,
-            hook_callbacks: Arc::new(RwLock::new(HashMap::new())),
-            callback_counter: Arc::new(Mutex::new(0)),
-        })
+        let transport: Box<dyn Transport + Send> =
+            Box::new(crate::transport::SubprocessTransport::new(options.clone())?);
+        Ok(Self::from_transport_with_options(transport, &options))
     }
 
     /// Take the SDK control receiver for handling inbound control requests
@@ -82,13 +321,83 @@ impl InteractiveClient {
     ///
     /// Use this to listen for permission requests when running in non-BypassPermissions
     /// modes and handle them via `send_control_response()`.
+    ///
+    /// After `initialize_hooks()`, responses to the client's own control
+    /// requests are consumed internally and do not appear on this receiver.
     pub async fn take_sdk_control_receiver(
         &self,
     ) -> Option<tokio::sync::mpsc::Receiver<serde_json::Value>> {
+        if let Some(router) = self.control_router.lock().await.as_mut() {
+            return router.forwarded_rx.take();
+        }
         let mut transport = self.transport.lock().await;
         transport.take_sdk_control_receiver()
     }
 
+    /// Register a waiter for the control response to `request_id`
+    ///
+    /// Starts routing inbound control messages on first use, which requires the
+    /// transport's SDK control receiver to still be available.
+    async fn expect_control_response(
+        &self,
+        request_id: &str,
+    ) -> Result<oneshot::Receiver<serde_json::Value>> {
+        let mut router = self.control_router.lock().await;
+        if router.is_none() {
+            let inbound = self
+                .transport
+                .lock()
+                .await
+                .take_sdk_control_receiver()
+                .ok_or_else(|| {
+                    SdkError::invalid_state(
+                        "SDK control receiver already taken; call initialize_hooks() \
+                         before take_sdk_control_receiver()",
+                    )
+                })?;
+            *router = Some(ControlRouter::spawn(inbound, self.transport.clone()));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        if let Some(router) = router.as_ref() {
+            router
+                .pending
+                .lock()
+                .await
+                .insert(request_id.to_string(), tx);
+        }
+        Ok(rx)
+    }
+
+    /// Wait for the control response registered with `expect_control_response`
+    async fn await_control_response(
+        &self,
+        request_id: &str,
+        response_rx: oneshot::Receiver<serde_json::Value>,
+    ) -> Result<serde_json::Value> {
+        let response = match tokio::time::timeout(CONTROL_RESPONSE_TIMEOUT, response_rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => {
+                return Err(SdkError::ConnectionError(
+                    "CLI connection lost before the control response arrived".to_string(),
+                ));
+            },
+            Err(_) => {
+                self.forget_control_response(request_id).await;
+                return Err(SdkError::timeout(CONTROL_RESPONSE_TIMEOUT.as_secs()));
+            },
+        };
+
+        if response.get("subtype").and_then(|v| v.as_str()) == Some("error") {
+            let msg = response
+                .get("error")
+                .and_then(|v| v.as_str())
+                .unwrap_or("Unknown control request error");
+            return Err(SdkError::ControlRequestError(msg.to_string()));
+        }
+        Ok(response)
+    }
+
     /// Get a clone of the hook callbacks registry.
     ///
     /// This allows the caller (e.g., PO backend `stream_response`) to dispatch
@@ -121,7 +430,8 @@ impl InteractiveClient {
     /// operations on the client.
     ///
     /// Returns `None` if the underlying transport doesn't expose a broadcast
-    /// (e.g. mock transport, or `connect()` was not called yet).
+    /// (e.g. mock transport, `connect()` was not called yet, or the options
+    /// did not enable `allow_multiple_receivers`).
     pub async fn subscribe_messages(
         &self,
     ) -> Option<Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>>> {
@@ -144,44 +454,192 @@ impl InteractiveClient {
         Ok(())
     }
 
+    /// Fail if `end_input` already closed the CLI's stdin
+    fn ensure_input_open(&self) -> Result<()> {
+        if self.input_ended {
+            return Err(SdkError::InvalidState {
+                message: "Input already ended with end_input()".into(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Signal that no more input is coming
+    ///
+    /// Closes the CLI's stdin so it can finish the conversation. Afterwards
+    /// the client only drains output: receiving keeps working, while sending
+    /// prompts, tool results or control messages fails with
+    /// `SdkError::InvalidState`. Calling it again is a no-op.
+    pub async fn end_input(&mut self) -> Result<()> {
+        if !self.connected {
+            return Err(SdkError::InvalidState {
+                message: "Not connected".into(),
+            });
+        }
+        if self.input_ended {
+            return Ok(());
+        }
+
+        let mut transport = self.transport.lock().await;
+        transport.end_input().await?;
+        drop(transport);
+        self.input_ended = true;
+
+        info!("Ended input to Claude CLI");
+        Ok(())
+    }
+
     /// Send a message and receive all messages until Result message
+    ///
+    /// A turn the API rate-limited fails with `SdkError::RateLimited` once
+    /// its Result has arrived, so the client is ready for the next prompt.
     pub async fn send_and_receive(&mut self, prompt: String) -> Result<Vec<Message>> {
+        self.send_and_receive_cancellable(prompt, CancellationToken::new())
+            .await
+    }
+
+    /// Like `send_and_receive`, with `additional_context` prepended to the
+    /// prompt
+    ///
+    /// Useful for context such as "the user is on mobile" without
+    /// configuring a `UserPromptSubmit` hook. The context is sent as a text
+    /// block ahead of the prompt in the same user message, not as part of
+    /// the system prompt. Only this message carries it, but the CLI keeps it
+    /// in the session transcript, so the model still sees it in later turns
+    /// and after a resume. `conversation_history` records only `prompt`.
+    ///
+    /// Memory injection (`memory_enabled`) is unaffected: retrieved memory
+    /// goes into the system prompt, while this context is part of the user
+    /// message and is not stored as a prompt of its own.
+    pub async fn send_and_receive_with_context(
+        &mut self,
+        prompt: String,
+        additional_context: impl Into<String>,
+    ) -> Result<Vec<Message>> {
+        let message = InputMessage::user_with_context(
+            prompt.clone(),
+            additional_context.into(),
+            "default".to_string(),
+        );
+        self.send_turn(message, prompt, CancellationToken::new(), None)
+            .await
+    }
+
+    /// Like `send_and_receive`, with at most `max_turns` model turns for
+    /// this prompt
+    ///
+    /// Overrides the `max_turns` option for a single prompt, e.g. one turn
+    /// for a quick question and more for a multi-step task. The CLI has no
+    /// per-prompt limit, so the client counts the model's responses itself:
+    /// once `max_turns` responses have requested tools and the tool results
+    /// are in, it interrupts the turn instead of letting the model continue.
+    /// The interrupted turn is drained, and the returned messages end with
+    /// its Result. A limit of 0 is treated as 1. The `max_turns` option
+    /// still applies, so the lower of the two limits wins.
+    pub async fn send_and_receive_with_max_turns(
+        &mut self,
+        prompt: String,
+        max_turns: u32,
+    ) -> Result<Vec<Message>> {
+        let message = InputMessage::user(prompt.clone(), "default".to_string());
+        let limit = TurnLimit::new(max_turns);
+        self.send_turn(message, prompt, CancellationToken::new(), Some(limit))
+            .await
+    }
+
+    /// Like `send_and_receive`, but gives up once `cancel` fires
+    ///
+    /// On cancellation the turn is interrupted and drained, so the client is
+    /// ready for the next prompt, and `SdkError::Cancelled` is returned with
+    /// the messages received so far, including those drained. A token that
+    /// is already cancelled sends nothing. If the interrupted turn does not
+    /// finish within 5 seconds the client is left mid-turn; call `drain`
+    /// before reusing it.
+    pub async fn send_and_receive_cancellable(
+        &mut self,
+        prompt: String,
+        cancel: CancellationToken,
+    ) -> Result<Vec<Message>> {
+        let message = InputMessage::user(prompt.clone(), "default".to_string());
+        self.send_turn(message, prompt, cancel, None).await
+    }
+
+    /// Send `message` and receive until the turn's Result, recording the
+    /// turn under `prompt`
+    ///
+    /// With a `limit`, the turn is interrupted once the limit is reached.
+    async fn send_turn(
+        &mut self,
+        message: InputMessage,
+        prompt: String,
+        cancel: CancellationToken,
+        mut limit: Option<TurnLimit>,
+    ) -> Result<Vec<Message>> {
         if !self.connected {
             return Err(SdkError::InvalidState {
                 message: "Not connected".into(),
             });
         }
+        self.ensure_input_open()?;
+        if cancel.is_cancelled() {
+            return Err(SdkError::Cancelled {
+                partial: Vec::new(),
+            });
+        }
 
         // Send message
         {
             let mut transport = self.transport.lock().await;
-            let message = InputMessage::user(prompt, "default".to_string());
             transport.send_message(message).await?;
         } // Lock released here
+        self.turn_in_progress.send_replace(true);
+        self.history.lock().await.start_turn(prompt);
+        update_token_rate(&self.token_rate, TokenRateMeter::reset);
 
         debug!("Message sent, waiting for response");
 
         // Receive messages
         let mut messages = Vec::new();
+        let mut rate_limited = None;
         loop {
-            // Try to get a message
-            let msg_result = {
-                let mut transport = self.transport.lock().await;
-                let mut stream = transport.receive_messages();
-                stream.next().await
-            }; // Lock released here
+            // Try to get a message; losing the race drops the receive future
+            // and with it the transport lock
+            let next = tokio::select! {
+                next = async {
+                    let mut transport = self.transport.lock().await;
+                    let mut stream = transport.receive_messages();
+                    stream.next().await
+                } => Some(next),
+                _ = cancel.cancelled() => None,
+            };
+            let Some(msg_result) = next else {
+                return self.cancel_turn(messages).await;
+            };
 
             // Process the message
             if let Some(result) = msg_result {
                 match result {
                     Ok(msg) => {
                         debug!("Received: {:?}", msg);
-                        let is_result = matches!(msg, Message::Result { .. });
+                        self.observe_tool_uses(&msg).await?;
+                        self.auto_respond(&msg).await?;
+                        self.history.lock().await.record(&msg, self.result_policy);
+                        update_token_rate(&self.token_rate, |m| m.observe(&msg));
+                        end_turn_on_result(self.result_policy, &self.turn_in_progress, &msg);
+                        let is_result = self.result_policy.ends_turn(&msg);
+                        let limit_reached = limit.as_mut().is_some_and(|limit| limit.observe(&msg));
                         messages.push(msg);
                         if is_result {
                             break;
                         }
+                        if limit_reached {
+                            info!("Turn limit reached, interrupting");
+                            self.interrupt_turn(&mut messages).await;
+                            break;
+                        }
                     },
+                    // The turn's Result follows; finish the turn before failing
+                    Err(e @ SdkError::RateLimited { .. }) => rate_limited = Some(e),
                     Err(e) => return Err(e),
                 }
             } else {
@@ -190,7 +648,37 @@ impl InteractiveClient {
             }
         }
 
-        Ok(messages)
+        match rate_limited {
+            Some(e) => Err(e),
+            None => Ok(messages),
+        }
+    }
+
+    /// Interrupt and drain a cancelled turn, returning `SdkError::Cancelled`
+    async fn cancel_turn(&mut self, mut partial: Vec<Message>) -> Result<Vec<Message>> {
+        if !self.is_ready_for_input() {
+            info!("Turn cancelled, interrupting");
+            self.interrupt_turn(&mut partial).await;
+        }
+        Err(SdkError::Cancelled { partial })
+    }
+
+    /// Interrupt the current turn and drain it into `partial`
+    ///
+    /// Failures are logged: the caller returns what it has either way.
+    async fn interrupt_turn(&mut self, partial: &mut Vec<Message>) {
+        match self.interrupt().await {
+            Ok(()) => {
+                let drained =
+                    tokio::time::timeout(INTERRUPT_ACK_TIMEOUT, self.drain_into(partial)).await;
+                match drained {
+                    Ok(Ok(_)) => {},
+                    Ok(Err(e)) => warn!("Failed to drain interrupted turn: {}", e),
+                    Err(_) => warn!("Interrupted turn did not finish after interrupt"),
+                }
+            },
+            Err(e) => warn!("Failed to interrupt turn: {}", e),
+        }
     }
 
     /// Send a message without waiting for response
@@ -200,16 +688,167 @@ impl InteractiveClient {
                 message: "Not connected".into(),
             });
         }
+        self.ensure_input_open()?;
 
         let mut transport = self.transport.lock().await;
-        let message = InputMessage::user(prompt, "default".to_string());
+        let message = InputMessage::user(prompt.clone(), "default".to_string());
         transport.send_message(message).await?;
         drop(transport);
+        self.turn_in_progress.send_replace(true);
+        self.history.lock().await.start_turn(prompt);
+        update_token_rate(&self.token_rate, TokenRateMeter::reset);
 
         debug!("Message sent");
         Ok(())
     }
 
+    /// Send several messages in order with a single transport operation
+    ///
+    /// Cheaper than repeated `send_message` calls when replaying a transcript
+    /// or feeding batched tool results, since the transport lock is taken
+    /// once and `SubprocessTransport` writes the batch to stdin in one go.
+    /// The batch is one turn: its prompts, joined by blank lines, open a
+    /// single entry in [`conversation_history`](Self::conversation_history),
+    /// and a batch of only tool results continues the current turn.
+    pub async fn send_messages(&mut self, messages: Vec<InputMessage>) -> Result<()> {
+        if !self.connected {
+            return Err(SdkError::InvalidState {
+                message: "Not connected".into(),
+            });
+        }
+        self.ensure_input_open()?;
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let count = messages.len();
+        let prompts: Vec<String> = messages.iter().filter_map(prompt_text).collect();
+        let mut transport = self.transport.lock().await;
+        transport.send_messages(messages).await?;
+        drop(transport);
+        self.turn_in_progress.send_replace(true);
+        if !prompts.is_empty() {
+            self.history.lock().await.start_turn(prompts.join("\n\n"));
+        }
+        update_token_rate(&self.token_rate, TokenRateMeter::reset);
+
+        debug!("Sent batch of {} messages", count);
+        Ok(())
+    }
+
+    /// Snapshot of the output rate of the current turn
+    ///
+    /// The meter (`current_tps`, `peak_tps`, `time_to_first_token`) restarts
+    /// with every prompt sent and is updated as the turn's output is consumed
+    /// through the client.
+    pub fn token_rate(&self) -> TokenRateMeter {
+        self.token_rate
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Conversation so far, reconstructed from the messages this client saw
+    ///
+    /// Each [`Turn`] holds a prompt sent through this client and the messages
+    /// received for it, oldest first. Only output consumed through the client
+    /// (`send_and_receive`, `receive_response`, the streams or `drain`) is
+    /// recorded, and at most `history_limit` turns are kept.
+    pub async fn conversation_history(&self) -> Vec<Turn> {
+        self.history.lock().await.turns()
+    }
+
+    /// Compactions of the conversation announced while this client read it
+    ///
+    /// A `compact_boundary` system message adds an entry counting the
+    /// prompts sent and top-level messages received since the previous
+    /// compaction; its summary is filled in when the summary message
+    /// follows. Like the history, only output consumed through the client is
+    /// seen.
+    pub async fn compactions(&self) -> Vec<CompactionResult> {
+        self.history.lock().await.compactions()
+    }
+
+    /// Whether the CLI is idle and a new prompt can be sent
+    ///
+    /// Returns `false` from the moment a prompt is sent until its `Result`
+    /// message has been received through this client, so callers can avoid
+    /// interleaving prompts with a turn that is still streaming.
+    pub fn is_ready_for_input(&self) -> bool {
+        !*self.turn_in_progress.borrow()
+    }
+
+    /// Wait until the current turn (if any) has finished
+    ///
+    /// Completes once the `Result` message of the in-flight prompt is received
+    /// through this client. Something must still be consuming the response
+    /// (e.g. `receive_response` or a stream) for this to make progress.
+    pub async fn await_ready(&self) {
+        let mut turn = self.turn_in_progress.subscribe();
+        // The sender lives as long as `self`, so this cannot fail
+        let _ = turn.wait_for(|in_progress| !*in_progress).await;
+    }
+
+    /// Send a tool result back to Claude for a previous tool use
+    ///
+    /// When `duplicate_tool_use_policy` is set, answering the same
+    /// `tool_use_id` twice is reported according to the policy before
+    /// anything is written to the CLI.
+    pub async fn send_tool_result(
+        &mut self,
+        tool_use_id: String,
+        content: String,
+        is_error: bool,
+    ) -> Result<()> {
+        if !self.connected {
+            return Err(SdkError::InvalidState {
+                message: "Not connected".into(),
+            });
+        }
+        self.ensure_input_open()?;
+
+        if let Some(ref guard) = self.tool_use_guard {
+            guard.lock().await.record_result(&tool_use_id)?;
+        }
+
+        let mut transport = self.transport.lock().await;
+        let message =
+            InputMessage::tool_result(tool_use_id, content, "default".to_string(), is_error);
+        transport.send_message(message).await?;
+        drop(transport);
+
+        debug!("Tool result sent");
+        Ok(())
+    }
+
+    /// Feed a received message to the duplicate tool-use tracker, if enabled
+    async fn observe_tool_uses(&self, message: &Message) -> Result<()> {
+        if let Some(ref guard) = self.tool_use_guard {
+            guard.lock().await.observe_message(message)?;
+        }
+        Ok(())
+    }
+
+    /// The auto-responder, unless `end_input` closed the CLI's stdin
+    fn active_auto_responder(&self) -> Option<&AutoResponder> {
+        self.auto_responder.as_ref().filter(|_| !self.input_ended)
+    }
+
+    /// Answer the whitelisted tool uses in a received message, if enabled
+    async fn auto_respond(&self, message: &Message) -> Result<()> {
+        let Some(responder) = self.active_auto_responder() else {
+            return Ok(());
+        };
+        let mut transport = self.transport.lock().await;
+        auto_respond(
+            responder,
+            self.tool_use_guard.as_ref(),
+            &mut **transport,
+            message,
+        )
+        .await
+    }
+
     /// Send a raw SDK control response to the Claude CLI subprocess.
     ///
     /// This is used to respond to control protocol requests (e.g., `can_use_tool`
@@ -226,6 +865,7 @@ impl InteractiveClient {
                 message: "Not connected".into(),
             });
         }
+        self.ensure_input_open()?;
 
         let mut transport = self.transport.lock().await;
         transport.send_sdk_control_response(response).await?;
@@ -274,6 +914,7 @@ impl InteractiveClient {
                 message: "Not connected".into(),
             });
         }
+        self.ensure_input_open()?;
 
         // Create channel for forwarding messages
         let (tx, rx) = tokio::sync::mpsc::channel(100);
@@ -287,8 +928,11 @@ impl InteractiveClient {
             let mut stream = transport.receive_messages();
 
             // 2. THEN send the message
-            let message = InputMessage::user(prompt, "default".to_string());
+            let message = InputMessage::user(prompt.clone(), "default".to_string());
             transport.send_message(message).await?;
+            self.turn_in_progress.send_replace(true);
+            self.history.lock().await.start_turn(prompt);
+            update_token_rate(&self.token_rate, TokenRateMeter::reset);
 
             debug!("Message sent, subscription active");
 
@@ -304,6 +948,14 @@ impl InteractiveClient {
             });
         } // Lock released here, after subscription and send
 
+        let tool_use_guard = self.tool_use_guard.clone();
+        let turn_in_progress = self.turn_in_progress.clone();
+        let history = self.history.clone();
+        let token_rate = self.token_rate.clone();
+        let auto_responder = self.active_auto_responder().cloned();
+        let transport = self.transport.clone();
+        let result_policy = self.result_policy;
+
         // Return stream that stops at Result message
         Ok(async_stream::stream! {
             let mut rx_stream = ReceiverStream::new(rx);
@@ -311,12 +963,35 @@ impl InteractiveClient {
             while let Some(result) = rx_stream.next().await {
                 match &result {
                     Ok(msg) => {
-                        let is_result = matches!(msg, Message::Result { .. });
+                        if let Some(ref guard) = tool_use_guard
+                            && let Err(e) = guard.lock().await.observe_message(msg)
+                        {
+                            yield Err(e);
+                            break;
+                        }
+                        if let Some(ref responder) = auto_responder
+                            && let Err(e) = auto_respond(
+                                responder,
+                                tool_use_guard.as_ref(),
+                                &mut **transport.lock().await,
+                                msg,
+                            )
+                            .await
+                        {
+                            yield Err(e);
+                            break;
+                        }
+                        history.lock().await.record(msg, result_policy);
+                        update_token_rate(&token_rate, |m| m.observe(msg));
+                        end_turn_on_result(result_policy, &turn_in_progress, msg);
+                        let is_result = result_policy.ends_turn(msg);
                         yield result;
                         if is_result {
                             break;
                         }
                     }
+                    // The turn's Result follows a rate limit
+                    Err(SdkError::RateLimited { .. }) => yield result,
                     Err(_) => {
                         yield result;
                         break;
@@ -327,6 +1002,9 @@ impl InteractiveClient {
     }
 
     /// Receive messages until Result message (convenience method like Python SDK)
+    ///
+    /// Fails with `SdkError::RateLimited` after a rate-limited turn's Result,
+    /// like `send_and_receive`.
     pub async fn receive_response(&mut self) -> Result<Vec<Message>> {
         if !self.connected {
             return Err(SdkError::InvalidState {
@@ -335,6 +1013,7 @@ impl InteractiveClient {
         }
 
         let mut messages = Vec::new();
+        let mut rate_limited = None;
         loop {
             // Try to get a message
             let msg_result = {
@@ -348,12 +1027,19 @@ impl InteractiveClient {
                 match result {
                     Ok(msg) => {
                         debug!("Received: {:?}", msg);
-                        let is_result = matches!(msg, Message::Result { .. });
+                        self.observe_tool_uses(&msg).await?;
+                        self.auto_respond(&msg).await?;
+                        self.history.lock().await.record(&msg, self.result_policy);
+                        update_token_rate(&self.token_rate, |m| m.observe(&msg));
+                        end_turn_on_result(self.result_policy, &self.turn_in_progress, &msg);
+                        let is_result = self.result_policy.ends_turn(&msg);
                         messages.push(msg);
                         if is_result {
                             break;
                         }
                     },
+                    // The turn's Result follows; finish the turn before failing
+                    Err(e @ SdkError::RateLimited { .. }) => rate_limited = Some(e),
                     Err(e) => return Err(e),
                 }
             } else {
@@ -362,7 +1048,25 @@ impl InteractiveClient {
             }
         }
 
-        Ok(messages)
+        match rate_limited {
+            Some(e) => Err(e),
+            None => Ok(messages),
+        }
+    }
+
+    /// Receive the current turn and deserialize its structured output into `T`
+    ///
+    /// Like `receive_response`, but returns the `Result` message's
+    /// `structured_output` deserialized into `T`; the other messages of the
+    /// turn are still recorded in the history. Requires the client to be
+    /// created with a `json_schema` `output_format`. Fails with `SdkError::CliError`
+    /// if the turn ends in an error and with `SdkError::MessageParseError`
+    /// (carrying the raw JSON) if there is no structured output or it does
+    /// not match `T`.
+    pub async fn receive_structured<T: serde::de::DeserializeOwned>(&mut self) -> Result<T> {
+        let messages = self.receive_response().await?;
+        let result = messages.last().ok_or(SdkError::UnexpectedStreamEnd)?;
+        crate::message_parser::parse_structured_output(result)
     }
 
     /// Receive messages as a stream (streaming output support)
@@ -400,6 +1104,12 @@ impl InteractiveClient {
         // Create a channel for messages
         let (tx, rx) = tokio::sync::mpsc::channel(100);
         let transport = self.transport.clone();
+        let turn_in_progress = self.turn_in_progress.clone();
+        let history = self.history.clone();
+        let token_rate = self.token_rate.clone();
+        let result_policy = self.result_policy;
+        let auto_responder = self.active_auto_responder().cloned();
+        let tool_use_guard = self.tool_use_guard.clone();
 
         // Spawn a task to receive messages from transport
         tokio::spawn(async move {
@@ -407,6 +1117,19 @@ impl InteractiveClient {
             let mut stream = transport.receive_messages();
 
             while let Some(result) = stream.next().await {
+                if let Ok(msg) = &result {
+                    if let Some(ref responder) = auto_responder
+                        && let Err(e) =
+                            auto_respond(responder, tool_use_guard.as_ref(), &mut **transport, msg)
+                                .await
+                    {
+                        let _ = tx.send(Err(e)).await;
+                        break;
+                    }
+                    history.lock().await.record(msg, result_policy);
+                    update_token_rate(&token_rate, |m| m.observe(msg));
+                    end_turn_on_result(result_policy, &turn_in_progress, msg);
+                }
                 // Send each message through the channel
                 if tx.send(result).await.is_err() {
                     // Receiver dropped, stop sending
@@ -424,6 +1147,7 @@ impl InteractiveClient {
     /// This is a convenience method that collects messages until a Result message
     /// is received, similar to Python SDK's `receive_response()`.
     pub async fn receive_response_stream(&mut self) -> impl Stream<Item = Result<Message>> + '_ {
+        let result_policy = self.result_policy;
         // Create a stream that stops after Result message
         async_stream::stream! {
             let mut stream = self.receive_messages_stream().await;
@@ -431,7 +1155,7 @@ impl InteractiveClient {
             while let Some(result) = stream.next().await {
                 match &result {
                     Ok(msg) => {
-                        let is_result = matches!(msg, Message::Result { .. });
+                        let is_result = result_policy.ends_turn(msg);
                         yield result;
                         if is_result {
                             break;
@@ -476,10 +1200,11 @@ impl InteractiveClient {
                 message: "Not connected".into(),
             });
         }
+        self.ensure_input_open()?;
 
         // Validate mode
         const VALID_MODES: &[&str] = &["default", "acceptEdits", "bypassPermissions", "plan"];
-        if !VALID_MODES.contains(&mode) {
+        let Ok(permission_mode) = serde_json::from_value::<PermissionMode>(mode.into()) else {
             return Err(SdkError::InvalidState {
                 message: format!(
                     "Invalid permission mode '{}'. Valid modes: {}",
@@ -487,7 +1212,7 @@ impl InteractiveClient {
                     VALID_MODES.join(", ")
                 ),
             });
-        }
+        };
 
         let request = serde_json::json!({
             "type": "control_request",
@@ -501,39 +1226,100 @@ impl InteractiveClient {
         let mut transport = self.transport.lock().await;
         transport.send_sdk_control_request(request).await?;
         drop(transport);
+        self.permission_mode = permission_mode;
 
         info!(mode = %mode, "Permission mode change request sent");
         Ok(())
     }
 
-    // ========================================================================
-    // Hook lifecycle — initialize, dispatch, respond
-    // ========================================================================
-
-    /// Initialize the control protocol and register hooks with the CLI.
-    ///
-    /// This reproduces the logic from `Query::initialize()`: it generates unique
-    /// callback IDs for each `HookCallback`, stores them locally, and sends an
-    /// `SDKControlRequest::Initialize` message to the CLI subprocess so it knows
-    /// which hooks to trigger.
+    /// Switch the model of the active CLI session
     ///
-    /// **Must be called after `connect()` and before `take_sdk_control_receiver()`.**
-    /// The init message expects a response on the SDK control channel. If the
-    /// receiver has already been taken, the response will be lost.
-    ///
-    /// No-op if no hooks were configured in `ClaudeCodeOptions`.
-    pub async fn initialize_hooks(&self) -> Result<()> {
-        let hooks = match &self.hooks {
-            Some(h) if !h.is_empty() => h,
-            _ => {
-                debug!("No hooks configured — skipping initialize_hooks");
-                return Ok(());
-            },
-        };
+    /// Sends a `set_model` control request; `None` restores the CLI's
+    /// default model. Like `set_permission_mode`, this does not wait for the
+    /// CLI to acknowledge the change.
+    pub async fn set_model(&mut self, model: Option<String>) -> Result<()> {
+        if !self.connected {
+            return Err(SdkError::InvalidState {
+                message: "Not connected".into(),
+            });
+        }
+        self.ensure_input_open()?;
 
-        // Generate callback IDs and register callbacks (mirrors Query::initialize)
-        let mut counter = self.callback_counter.lock().await;
-        let mut callbacks_map = self.hook_callbacks.write().await;
+        let request = serde_json::json!({
+            "type": "control_request",
+            "request_id": uuid::Uuid::new_v4().to_string(),
+            "request": {
+                "subtype": "set_model",
+                "model": model
+            }
+        });
+
+        let mut transport = self.transport.lock().await;
+        transport.send_sdk_control_request(request).await?;
+        drop(transport);
+
+        info!(model = ?model, "Model change request sent");
+        self.model = model;
+        Ok(())
+    }
+
+    /// Model of the session, as configured or last set with `set_model`
+    ///
+    /// `None` means the CLI's default model.
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    /// Working directory the CLI was started in, if one was configured
+    pub fn cwd(&self) -> Option<&Path> {
+        self.cwd.as_deref()
+    }
+
+    /// Permission mode, as configured or last set with `set_permission_mode`
+    pub fn permission_mode(&self) -> PermissionMode {
+        self.permission_mode
+    }
+
+    /// Choose which Result message ends a turn for the receive methods
+    ///
+    /// Clients created with `new` start with the `result_policy` option.
+    pub fn set_result_policy(&mut self, policy: ResultPolicy) {
+        self.result_policy = policy;
+    }
+
+    // ========================================================================
+    // Hook lifecycle — initialize, dispatch, respond
+    // ========================================================================
+
+    /// Initialize the control protocol and register hooks with the CLI.
+    ///
+    /// This reproduces the logic from `Query::initialize()`: it generates unique
+    /// callback IDs for each `HookCallback`, stores them locally, and sends an
+    /// `SDKControlRequest::Initialize` message to the CLI subprocess so it knows
+    /// which hooks to trigger.
+    ///
+    /// Returns once the CLI has acknowledged the init request, so hooks are
+    /// live before the first prompt is sent. Fails with
+    /// `SdkError::ControlRequestError` if the CLI rejects it, or
+    /// `SdkError::Timeout` if no acknowledgment arrives within 60 seconds.
+    ///
+    /// **Must be called after `connect()` and before `take_sdk_control_receiver()`**,
+    /// since the acknowledgment arrives on the SDK control channel; otherwise
+    /// `SdkError::InvalidState` is returned.
+    ///
+    /// No-op if no hooks were configured in `ClaudeCodeOptions`.
+    pub async fn initialize_hooks(&self) -> Result<()> {
+        let hooks = match &self.hooks {
+            Some(h) if !h.is_empty() => h,
+            _ => {
+                debug!("No hooks configured — skipping initialize_hooks");
+                return Ok(());
+            },
+        };
+
+        // Generate callback IDs and register callbacks (mirrors Query::initialize)
+        let mut counter = self.callback_counter.lock().await;
+        let mut callbacks_map = self.hook_callbacks.write().await;
 
         let hooks_json: HashMap<String, serde_json::Value> = hooks
             .iter()
@@ -580,13 +1366,25 @@ impl InteractiveClient {
             "request": init_request
         });
 
+        // Register the waiter before sending so a fast response is not missed
+        let response_rx = self.expect_control_response(&request_id).await?;
+        if let Some(router) = self.control_router.lock().await.as_ref() {
+            *router
+                .init_request
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()) = Some(init_request.clone());
+        }
+
         // Send via transport stdin
         {
             let mut transport = self.transport.lock().await;
             transport.send_sdk_control_request(control_msg).await?;
         }
+        debug!("initialize_hooks: sent init with hook callback IDs to CLI");
 
-        info!("initialize_hooks: sent init with hook callback IDs to CLI");
+        self.await_control_response(&request_id, response_rx)
+            .await?;
+        info!("initialize_hooks: CLI acknowledged hook registration");
         Ok(())
     }
 
@@ -654,7 +1452,7 @@ impl InteractiveClient {
 
         // Parse HookInput and execute
         let context = HookContext { signal: None };
-        let result = match serde_json::from_value::<HookInput>(input.clone()) {
+        let result = match crate::message_parser::parse_hook_input(&input) {
             Ok(hook_input) => {
                 callback
                     .execute(&hook_input, tool_use_id.as_deref(), &context)
@@ -662,10 +1460,7 @@ impl InteractiveClient {
             },
             Err(parse_err) => {
                 error!("Failed to parse hook input: {}", parse_err);
-                Err(SdkError::MessageParseError {
-                    error: format!("Invalid hook input: {parse_err}"),
-                    raw: input.to_string(),
-                })
+                Err(parse_err)
             },
         };
 
@@ -688,7 +1483,7 @@ impl InteractiveClient {
     ) -> Result<()> {
         let response_json = match output {
             Ok(hook_output) => {
-                let output_value = serde_json::to_value(hook_output).unwrap_or_else(|e| {
+                let output_value = hook_output.to_cli_value().unwrap_or_else(|e| {
                     error!("Failed to serialize hook output: {}", e);
                     serde_json::json!({})
                 });
@@ -763,22 +1558,219 @@ impl InteractiveClient {
     }
 
     /// Send interrupt signal to cancel current operation
+    ///
+    /// Interrupts are coalesced: while an earlier interrupt is still
+    /// unanswered, no further request is sent, so hammering interrupt keeps
+    /// at most one interrupt pending. This relies on the client routing
+    /// control responses; once the SDK control receiver has been taken from
+    /// the transport directly, every call sends a request.
     pub async fn interrupt(&mut self) -> Result<()> {
         if !self.connected {
             return Err(SdkError::InvalidState {
                 message: "Not connected".into(),
             });
         }
+        self.ensure_input_open()?;
+
+        self.send_interrupt().await?;
+        info!("Interrupt sent");
+        Ok(())
+    }
+
+    /// Send an interrupt unless an earlier one is still in flight
+    ///
+    /// Returns the request ID of the interrupt whose acknowledgment answers
+    /// this call.
+    async fn send_interrupt(&mut self) -> Result<String> {
+        if let Some(pending) = self.pending_interrupt.as_mut()
+            && pending.in_flight()
+        {
+            debug!("Coalescing interrupt with the one in flight");
+            return Ok(pending.request_id.clone());
+        }
+        if let Some(pending) = self.pending_interrupt.take() {
+            self.forget_control_response(&pending.request_id).await;
+        }
 
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let response_rx = self.expect_control_response(&request_id).await.ok();
+        let sent = self
+            .transport
+            .lock()
+            .await
+            .send_control_request(ControlRequest::Interrupt {
+                request_id: request_id.clone(),
+            })
+            .await;
+        if let Err(e) = sent {
+            self.forget_control_response(&request_id).await;
+            return Err(e);
+        }
+        self.pending_interrupt = response_rx.map(|response_rx| PendingInterrupt {
+            request_id: request_id.clone(),
+            sent: Instant::now(),
+            response_rx,
+        });
+        Ok(request_id)
+    }
+
+    /// Drop the waiter registered for `request_id`, if any
+    async fn forget_control_response(&self, request_id: &str) {
+        if let Some(router) = self.control_router.lock().await.as_ref() {
+            router.pending.lock().await.remove(request_id);
+        }
+    }
+
+    /// Send an interrupt and wait for the CLI to acknowledge it
+    ///
+    /// Unlike `interrupt`, this reports whether the CLI accepted the
+    /// interrupt and whether a turn was actually in progress when it was
+    /// sent. A rejected interrupt is returned with `success: false` rather
+    /// than as an error. Fails with `SdkError::Timeout` if no acknowledgment
+    /// arrives within 5 seconds. Like `interrupt`, it waits for an earlier
+    /// interrupt still in flight instead of sending another.
+    pub async fn interrupt_and_confirm(&mut self) -> Result<InterruptOutcome> {
+        if !self.connected {
+            return Err(SdkError::InvalidState {
+                message: "Not connected".into(),
+            });
+        }
+        self.ensure_input_open()?;
+
+        let turn_in_progress = !self.is_ready_for_input();
+        let request_id = self.send_interrupt().await?;
+        info!("Interrupt sent, waiting for acknowledgment");
         let mut transport = self.transport.lock().await;
-        let request = ControlRequest::Interrupt {
-            request_id: uuid::Uuid::new_v4().to_string(),
-        };
-        transport.send_control_request(request).await?;
+
+        let ack = tokio::time::timeout(INTERRUPT_ACK_TIMEOUT, async {
+            // Acks for earlier control requests may still be queued; skip them
+            loop {
+                match transport.receive_control_response().await? {
+                    Some(ControlResponse::InterruptAck {
+                        request_id: ack_id,
+                        success,
+                    }) if ack_id == request_id => return Ok(success),
+                    Some(other) => debug!("Skipping unrelated control response: {:?}", other),
+                    None => {
+                        return Err(SdkError::ControlRequestError(
+                            "No interrupt acknowledgment received".into(),
+                        ));
+                    },
+                }
+            }
+        })
+        .await;
         drop(transport);
 
-        info!("Interrupt sent");
-        Ok(())
+        if ack.is_ok()
+            && let Some(pending) = self.pending_interrupt.take()
+        {
+            self.forget_control_response(&pending.request_id).await;
+        }
+        let success = ack.map_err(|_| SdkError::timeout(INTERRUPT_ACK_TIMEOUT.as_secs()))??;
+        Ok(InterruptOutcome {
+            request_id,
+            success,
+            turn_in_progress,
+        })
+    }
+
+    /// Send an interrupt and report whether it cancelled anything
+    ///
+    /// Combines the CLI's acknowledgment with the client's turn tracking, so
+    /// a UI can tell "nothing to cancel" from "cancelled". The interrupt is
+    /// sent even when the client believes it is idle. A missing
+    /// acknowledgment is reported as `InterruptResult::Timeout` instead of
+    /// an error; other failures are returned as errors.
+    pub async fn interrupt_with_result(&mut self) -> Result<InterruptResult> {
+        match self.interrupt_and_confirm().await {
+            Ok(outcome) if !outcome.turn_in_progress => Ok(InterruptResult::NoActiveTurn),
+            Ok(outcome) if outcome.success => Ok(InterruptResult::TurnInterrupted),
+            Ok(_) => Ok(InterruptResult::Rejected),
+            Err(SdkError::Timeout { .. }) => Ok(InterruptResult::Timeout),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Discard output left over from a previous turn
+    ///
+    /// While a turn is in progress (e.g. after `interrupt`), consumes messages
+    /// up to and including its `Result`. Otherwise discards whatever is
+    /// already buffered, stopping once nothing arrives for 100ms. Either way
+    /// the next `send_and_receive` only sees its own turn. Returns the number
+    /// of messages discarded, or `SdkError::Timeout` if a turn in progress
+    /// goes quiet for 60 seconds.
+    pub async fn drain(&mut self) -> Result<usize> {
+        self.drain_into(&mut Vec::new()).await
+    }
+
+    /// Like `drain`, but keeps the drained messages in `kept`
+    async fn drain_into(&mut self, kept: &mut Vec<Message>) -> Result<usize> {
+        if !self.connected {
+            return Err(SdkError::InvalidState {
+                message: "Not connected".into(),
+            });
+        }
+
+        let mut discarded = 0;
+        loop {
+            let next = {
+                let mut transport = self.transport.lock().await;
+                let mut stream = transport.receive_messages();
+                if self.is_ready_for_input() {
+                    match tokio::time::timeout(DRAIN_IDLE_TIMEOUT, stream.next()).await {
+                        Ok(next) => next,
+                        Err(_) => break,
+                    }
+                } else {
+                    // Bounded, so a stuck turn doesn't hold the transport lock forever
+                    tokio::time::timeout(DRAIN_TURN_TIMEOUT, stream.next())
+                        .await
+                        .map_err(|_| SdkError::timeout(DRAIN_TURN_TIMEOUT.as_secs()))?
+                }
+            }; // Lock released here
+
+            match next {
+                Some(Ok(msg)) => {
+                    debug!("Draining message: {:?}", msg);
+                    discarded += 1;
+                    self.history.lock().await.record(&msg, self.result_policy);
+                    update_token_rate(&self.token_rate, |m| m.observe(&msg));
+                    end_turn_on_result(self.result_policy, &self.turn_in_progress, &msg);
+                    let is_result = self.result_policy.ends_turn(&msg);
+                    kept.push(msg);
+                    if is_result {
+                        break;
+                    }
+                },
+                Some(Err(e)) => {
+                    debug!("Discarding stale message error: {}", e);
+                    discarded += 1;
+                },
+                None => break,
+            }
+        }
+
+        info!("Drained {} stale messages", discarded);
+        Ok(discarded)
+    }
+
+    /// Follow the output of a session running in another process
+    ///
+    /// Not supported: the Claude CLI has no read-only follow mode. `--resume`
+    /// continues a session as a new participant that must be sent a prompt,
+    /// so it cannot observe a run owned by someone else. The stream yields a
+    /// single `SdkError::NotSupported` and nothing is sent to the CLI. To
+    /// continue a session yourself, set `ClaudeCodeOptions::resume`.
+    pub fn attach(&self, session_id: impl Into<String>) -> impl Stream<Item = Result<Message>> {
+        let session_id = session_id.into();
+        futures::stream::once(async move {
+            Err(SdkError::NotSupported {
+                feature: format!(
+                    "attaching to session {session_id}: the CLI has no read-only follow mode"
+                ),
+            })
+        })
     }
 
     /// Build the JSON string for an interrupt control request.
@@ -816,21 +1808,139 @@ impl InteractiveClient {
     }
 
     /// Disconnect
+    ///
+    /// With the `graceful_shutdown_timeout` option set, the CLI is first
+    /// given that long to finish on its own (see
+    /// `Transport::disconnect_graceful`), so in-flight output and checkpoint
+    /// state are flushed; otherwise it is stopped right away.
     pub async fn disconnect(&mut self) -> Result<()> {
         if !self.connected {
             return Ok(());
         }
 
         let mut transport = self.transport.lock().await;
-        transport.disconnect().await?;
+        match self.graceful_shutdown_timeout {
+            Some(timeout) => transport.disconnect_graceful(timeout).await?,
+            None => transport.disconnect().await?,
+        }
         drop(transport);
 
         self.connected = false;
+        self.input_ended = false;
+        self.pending_interrupt = None;
+        self.turn_in_progress.send_replace(false);
         info!("Disconnected from Claude CLI");
         Ok(())
     }
 }
 
+/// Text of a batched user prompt, or `None` for tool results and other input
+fn prompt_text(message: &InputMessage) -> Option<String> {
+    if message.r#type != "user" || message.parent_tool_use_id.is_some() {
+        return None;
+    }
+    message.message["content"].as_str().map(str::to_string)
+}
+
+/// Send the auto-responder's tool results for `message` through `transport`
+///
+/// Each answered id is recorded with the duplicate tool-use guard first, so
+/// a later manual `send_tool_result` for the same tool use is reported.
+async fn auto_respond(
+    responder: &AutoResponder,
+    tool_use_guard: Option<&Arc<Mutex<ToolUseGuard>>>,
+    transport: &mut (dyn Transport + Send),
+    message: &Message,
+) -> Result<()> {
+    for response in responder.responses(message) {
+        if let (Some(guard), Some(tool_use_id)) =
+            (tool_use_guard, response.parent_tool_use_id.as_deref())
+        {
+            guard.lock().await.record_result(tool_use_id)?;
+        }
+        debug!(
+            "Auto-responding to tool use {:?}",
+            response.parent_tool_use_id
+        );
+        transport.send_message(response).await?;
+    }
+    Ok(())
+}
+
+/// Apply `update` to the client's token-rate meter, even after a panic in
+/// another update
+fn update_token_rate(
+    meter: &std::sync::Mutex<TokenRateMeter>,
+    update: impl FnOnce(&mut TokenRateMeter),
+) {
+    update(&mut meter.lock().unwrap_or_else(|e| e.into_inner()));
+}
+
+/// Mark the current turn finished once the Result message ending it under
+/// `policy` arrives
+fn end_turn_on_result(
+    policy: ResultPolicy,
+    turn_in_progress: &watch::Sender<bool>,
+    message: &Message,
+) {
+    if policy.ends_turn(message) {
+        turn_in_progress.send_replace(false);
+    }
+}
+
+/// Client-side limit on the model turns of one prompt
+///
+/// A model turn is one response of the main agent, which the CLI may split
+/// over several Assistant messages (one per content block); it ends when the
+/// results of the tools it requested arrive. Subagent messages are ignored.
+struct TurnLimit {
+    max_turns: u32,
+    turns: u32,
+    in_response: bool,
+}
+
+impl TurnLimit {
+    fn new(max_turns: u32) -> Self {
+        Self {
+            max_turns: max_turns.max(1),
+            turns: 0,
+            in_response: false,
+        }
+    }
+
+    /// Count `message`; true once the limit is reached and the model would
+    /// start another turn
+    fn observe(&mut self, message: &Message) -> bool {
+        match message {
+            Message::Assistant {
+                parent_tool_use_id: None,
+                ..
+            } => {
+                if !self.in_response {
+                    self.in_response = true;
+                    self.turns += 1;
+                }
+                false
+            },
+            Message::User {
+                message,
+                parent_tool_use_id: None,
+            } => {
+                let tool_results = message.content_blocks.as_ref().is_some_and(|blocks| {
+                    blocks
+                        .iter()
+                        .any(|block| matches!(block, ContentBlock::ToolResult(_)))
+                });
+                if tool_results {
+                    self.in_response = false;
+                }
+                tool_results && self.turns >= self.max_turns
+            },
+            _ => false,
+        }
+    }
+}
+
 // ============================================================================
 // Standalone hook helpers (for use without client lock)
 // ============================================================================
@@ -900,7 +2010,7 @@ pub async fn dispatch_hook_from_registry(
 
     // Execute
     let context = HookContext { signal: None };
-    let result = match serde_json::from_value::<HookInput>(input.clone()) {
+    let result = match crate::message_parser::parse_hook_input(&input) {
         Ok(hook_input) => {
             callback
                 .execute(&hook_input, tool_use_id.as_deref(), &context)
@@ -908,10 +2018,7 @@ pub async fn dispatch_hook_from_registry(
         },
         Err(parse_err) => {
             error!("Failed to parse hook input: {}", parse_err);
-            Err(SdkError::MessageParseError {
-                error: format!("Invalid hook input: {parse_err}"),
-                raw: input.to_string(),
-            })
+            Err(parse_err)
         },
     };
 
@@ -928,7 +2035,7 @@ pub fn build_hook_response_json(
 ) -> String {
     let response_json = match output {
         Ok(hook_output) => {
-            let output_value = serde_json::to_value(hook_output).unwrap_or_else(|e| {
+            let output_value = hook_output.to_cli_value().unwrap_or_else(|e| {
                 error!("Failed to serialize hook output: {}", e);
                 serde_json::json!({})
             });
@@ -958,9 +2065,12 @@ pub fn build_hook_response_json(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::transport::mock::MockTransport;
+    use crate::transport::mock::{MockTransport, MockTransportHandle};
+    #[cfg(all(unix, feature = "subprocess"))]
+    use crate::transport::subprocess::tests::fake_cli;
     use crate::types::{
-        HookCallback, HookContext, HookInput, HookJSONOutput, HookMatcher, SyncHookJSONOutput,
+        HookCallback, HookContext, HookInput, HookJSONOutput, HookMatcher, HookSpecificOutput,
+        SessionStartHookSpecificOutput, SyncHookJSONOutput,
     };
     use std::sync::Arc;
 
@@ -1019,6 +2129,72 @@ mod tests {
         hooks
     }
 
+    /// Play the CLI: acknowledge the next initialize request and return it
+    async fn ack_initialize(handle: &mut MockTransportHandle) -> serde_json::Value {
+        let msg = handle
+            .outbound_control_request_rx
+            .recv()
+            .await
+            .expect("Should have received init message");
+        handle
+            .sdk_control_tx
+            .send(serde_json::json!({
+                "type": "control_response",
+                "response": {
+                    "subtype": "success",
+                    "request_id": msg["request_id"],
+                }
+            }))
+            .await
+            .unwrap();
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_control_router_routes_responses_when_forwarded_receiver_is_idle() {
+        let (inbound_tx, inbound_rx) = mpsc::channel(256);
+        let (transport, _handle) = MockTransport::pair();
+        let mut router = ControlRouter::spawn(inbound_rx, Arc::new(Mutex::new(transport)));
+        let (waiter_tx, waiter_rx) = oneshot::channel();
+        router
+            .pending
+            .lock()
+            .await
+            .insert("req-1".into(), waiter_tx);
+
+        // More unsolicited messages than the forwarded buffer holds, never drained
+        for i in 0..150 {
+            inbound_tx
+                .send(serde_json::json!({"type": "control_request", "request_id": i}))
+                .await
+                .unwrap();
+        }
+        inbound_tx
+            .send(serde_json::json!({
+                "type": "control_response",
+                "response": {"subtype": "success", "request_id": "req-1"}
+            }))
+            .await
+            .unwrap();
+
+        let response = tokio::time::timeout(Duration::from_secs(5), waiter_rx)
+            .await
+            .expect("the response must not wait on the forwarded receiver")
+            .unwrap();
+        assert_eq!(response["request_id"], "req-1");
+
+        // Nothing was dropped while the receiver was idle
+        let mut forwarded = router.forwarded_rx.take().unwrap();
+        for i in 0..150 {
+            let message = tokio::time::timeout(Duration::from_secs(5), forwarded.recv())
+                .await
+                .expect("every request must be delivered once the receiver drains")
+                .unwrap();
+            assert_eq!(message["request_id"], i);
+        }
+        assert!(forwarded.try_recv().is_err());
+    }
+
     #[tokio::test]
     async fn test_initialize_hooks_sends_init_message() {
         let (transport, mut handle) = MockTransport::pair();
@@ -1027,15 +2203,10 @@ mod tests {
 
         let client = InteractiveClient::from_transport_with_hooks(transport, hooks);
 
-        // initialize_hooks should send a control_request via the transport
-        client.initialize_hooks().await.unwrap();
-
-        // The init message should be observable via outbound_control_request_rx
-        let msg = handle
-            .outbound_control_request_rx
-            .recv()
-            .await
-            .expect("Should have received init message");
+        // initialize_hooks should send a control_request via the transport;
+        // the init message is observable via outbound_control_request_rx
+        let (result, msg) = tokio::join!(client.initialize_hooks(), ack_initialize(&mut handle));
+        result.unwrap();
 
         // Verify structure
         assert_eq!(msg["type"], "control_request");
@@ -1084,15 +2255,173 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_dispatch_hook_callback_executes_callback() {
+    async fn test_initialize_hooks_waits_for_init_response() {
+        let (transport, mut handle) = MockTransport::pair();
+        let callback = Arc::new(TestHookCallback::new());
+        let hooks = make_hooks_with_callback("PreCompact", callback);
+        let client = Arc::new(InteractiveClient::from_transport_with_hooks(
+            transport, hooks,
+        ));
+
+        let init = tokio::spawn({
+            let client = client.clone();
+            async move { client.initialize_hooks().await }
+        });
+
+        let msg = handle.outbound_control_request_rx.recv().await.unwrap();
+        // A control request from the CLI arrives first and must be passed through
+        handle
+            .sdk_control_tx
+            .send(serde_json::json!({"type": "control_request", "request_id": "cli-1"}))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!init.is_finished(), "must wait for the init acknowledgment");
+
+        handle
+            .sdk_control_tx
+            .send(serde_json::json!({
+                "type": "control_response",
+                "response": {"subtype": "success", "request_id": msg["request_id"]}
+            }))
+            .await
+            .unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(1), init)
+            .await
+            .expect("initialize_hooks should return once acknowledged")
+            .unwrap()
+            .unwrap();
+
+        let mut control_rx = client.take_sdk_control_receiver().await.unwrap();
+        let forwarded = control_rx.recv().await.unwrap();
+        assert_eq!(forwarded["request_id"], "cli-1");
+    }
+
+    #[tokio::test]
+    async fn test_initialize_hooks_reports_rejected_init() {
+        let (transport, mut handle) = MockTransport::pair();
+        let callback = Arc::new(TestHookCallback::new());
+        let hooks = make_hooks_with_callback("PreCompact", callback);
+        let client = InteractiveClient::from_transport_with_hooks(transport, hooks);
+
+        let reject = async {
+            let msg = handle.outbound_control_request_rx.recv().await.unwrap();
+            handle
+                .sdk_control_tx
+                .send(serde_json::json!({
+                    "type": "control_response",
+                    "response": {
+                        "subtype": "error",
+                        "request_id": msg["request_id"],
+                        "error": "unknown hook event",
+                    }
+                }))
+                .await
+                .unwrap();
+        };
+        let (result, _) = tokio::join!(client.initialize_hooks(), reject);
+
+        match result {
+            Err(SdkError::ControlRequestError(msg)) => assert_eq!(msg, "unknown hook event"),
+            other => panic!("expected ControlRequestError, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_initialize_hooks_fails_when_cli_restarts() {
+        let (transport, mut handle) = MockTransport::pair();
+        let callback = Arc::new(TestHookCallback::new());
+        let hooks = make_hooks_with_callback("PreCompact", callback);
+        let client = InteractiveClient::from_transport_with_hooks(transport, hooks);
+
+        // The CLI dies before answering and the transport restarts it
+        let restart = async {
+            handle.outbound_control_request_rx.recv().await.unwrap();
+            handle
+                .sdk_control_tx
+                .send(serde_json::json!({
+                    "type": "system",
+                    "subtype": crate::transport::RECONNECTED_CONTROL_SUBTYPE,
+                }))
+                .await
+                .unwrap();
+        };
+        let (result, _) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(client.initialize_hooks(), restart)
+        })
+        .await
+        .expect("the pending request must fail instead of waiting for the timeout");
+
+        assert!(matches!(result, Err(SdkError::ConnectionError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_restarted_cli_gets_hooks_registered_again() {
+        let (transport, mut handle) = MockTransport::pair();
+        let callback = Arc::new(TestHookCallback::new());
+        let hooks = make_hooks_with_callback("PreToolUse", callback);
+        let client = InteractiveClient::from_transport_with_hooks(transport, hooks);
+
+        // Acknowledge every control request, passing each one on to the test
+        let sdk_control_tx = handle.sdk_control_tx.clone();
+        let (request_tx, mut request_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(request) = handle.outbound_control_request_rx.recv().await {
+                let _ = sdk_control_tx
+                    .send(serde_json::json!({
+                        "type": "control_response",
+                        "response": {"subtype": "success", "request_id": request["request_id"]}
+                    }))
+                    .await;
+                let _ = request_tx.send(request);
+            }
+        });
+
+        client.initialize_hooks().await.unwrap();
+        let first = request_rx.recv().await.unwrap();
+        assert_eq!(first["request"]["subtype"], "initialize");
+
+        handle
+            .sdk_control_tx
+            .send(serde_json::json!({
+                "type": "system",
+                "subtype": crate::transport::RECONNECTED_CONTROL_SUBTYPE,
+            }))
+            .await
+            .unwrap();
+        let again = tokio::time::timeout(Duration::from_secs(5), request_rx.recv())
+            .await
+            .expect("the restarted CLI must get the hooks again")
+            .unwrap();
+        assert_eq!(again["request"]["subtype"], "initialize");
+        // The same callback IDs, so dispatch keeps working
+        assert_eq!(again["request"]["hooks"], first["request"]["hooks"]);
+        assert_eq!(client.hook_callbacks().read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_initialize_hooks_requires_control_receiver() {
         let (transport, _handle) = MockTransport::pair();
         let callback = Arc::new(TestHookCallback::new());
+        let hooks = make_hooks_with_callback("PreCompact", callback);
+        let client = InteractiveClient::from_transport_with_hooks(transport, hooks);
+
+        let _taken = client.take_sdk_control_receiver().await.unwrap();
+        let err = client.initialize_hooks().await.unwrap_err();
+        assert!(matches!(err, SdkError::InvalidState { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_hook_callback_executes_callback() {
+        let (transport, mut handle) = MockTransport::pair();
+        let callback = Arc::new(TestHookCallback::new());
         let hooks = make_hooks_with_callback("PreCompact", callback.clone());
 
         let client = InteractiveClient::from_transport_with_hooks(transport, hooks);
 
         // First, initialize to populate callback IDs
-        client.initialize_hooks().await.unwrap();
+        let (result, _) = tokio::join!(client.initialize_hooks(), ack_initialize(&mut handle));
+        result.unwrap();
 
         // Get the registered callback ID
         let callbacks = client.hook_callbacks.read().await;
@@ -1135,14 +2464,80 @@ mod tests {
         }
     }
 
+    /// Adds the session source as context, like a SessionStart hook would
+    struct SessionStartContext;
+
+    #[async_trait::async_trait]
+    impl HookCallback for SessionStartContext {
+        async fn execute(
+            &self,
+            input: &HookInput,
+            _tool_use_id: Option<&str>,
+            _context: &HookContext,
+        ) -> std::result::Result<HookJSONOutput, SdkError> {
+            let HookInput::SessionStart(start) = input else {
+                return Err(SdkError::invalid_state("expected a SessionStart input"));
+            };
+            Ok(HookJSONOutput::Sync(SyncHookJSONOutput {
+                hook_specific_output: Some(HookSpecificOutput::SessionStart(
+                    SessionStartHookSpecificOutput {
+                        additional_context: Some(format!("started by {}", start.source)),
+                    },
+                )),
+                ..Default::default()
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_start_hook_returns_specific_output() {
+        let (transport, mut handle) = MockTransport::pair();
+        let hooks = make_hooks_with_callback("SessionStart", Arc::new(SessionStartContext));
+        let client = InteractiveClient::from_transport_with_hooks(transport, hooks);
+        let (result, _) = tokio::join!(client.initialize_hooks(), ack_initialize(&mut handle));
+        result.unwrap();
+
+        let cb_id = client
+            .hook_callbacks
+            .read()
+            .await
+            .keys()
+            .next()
+            .unwrap()
+            .clone();
+        let control_msg = serde_json::json!({
+            "type": "control_request",
+            "request_id": "req-start",
+            "request": {
+                "subtype": "hook_callback",
+                "callback_id": cb_id,
+                "input": {
+                    "hook_event_name": "SessionStart",
+                    "session_id": "sess-1",
+                    "transcript_path": "/tmp/transcript.json",
+                    "cwd": "/home/user",
+                    "source": "clear"
+                }
+            }
+        });
+
+        let output = client.dispatch_hook_callback(&control_msg).await.unwrap();
+        let response: serde_json::Value =
+            serde_json::from_str(&build_hook_response_json("req-start", &output)).unwrap();
+        let specific = &response["response"]["response"]["hookSpecificOutput"];
+        assert_eq!(specific["hookEventName"], "SessionStart");
+        assert_eq!(specific["additionalContext"], "started by clear");
+    }
+
     #[tokio::test]
     async fn test_dispatch_unknown_callback_returns_none() {
-        let (transport, _handle) = MockTransport::pair();
+        let (transport, mut handle) = MockTransport::pair();
         let callback = Arc::new(TestHookCallback::new());
         let hooks = make_hooks_with_callback("PreCompact", callback.clone());
 
         let client = InteractiveClient::from_transport_with_hooks(transport, hooks);
-        client.initialize_hooks().await.unwrap();
+        let (result, _) = tokio::join!(client.initialize_hooks(), ack_initialize(&mut handle));
+        result.unwrap();
 
         // Send a hook_callback with an unknown callback_id
         let control_msg = serde_json::json!({
@@ -1278,9 +2673,8 @@ mod tests {
         );
 
         let client = InteractiveClient::from_transport_with_hooks(transport, hooks);
-        client.initialize_hooks().await.unwrap();
-
-        let msg = handle.outbound_control_request_rx.recv().await.unwrap();
+        let (result, msg) = tokio::join!(client.initialize_hooks(), ack_initialize(&mut handle));
+        result.unwrap();
 
         let hooks_json = msg["request"]["hooks"].as_object().unwrap();
         assert!(hooks_json.contains_key("PreCompact"));
@@ -1306,6 +2700,847 @@ mod tests {
         assert_eq!(callbacks.len(), 3);
     }
 
+    #[tokio::test]
+    async fn test_send_tool_result_rejects_duplicate_answer() {
+        let (transport, mut handle) = MockTransport::pair();
+        let options = ClaudeCodeOptions::builder()
+            .duplicate_tool_use_policy(crate::types::DuplicateToolUsePolicy::Error)
+            .build();
+        let mut client = InteractiveClient::from_transport_with_options(transport, &options);
+        client.connect().await.unwrap();
+
+        client
+            .send_tool_result("toolu_1".into(), "ok".into(), false)
+            .await
+            .unwrap();
+        let sent = handle.sent_input_rx.recv().await.unwrap();
+        assert_eq!(sent.parent_tool_use_id.as_deref(), Some("toolu_1"));
+
+        let err = client
+            .send_tool_result("toolu_1".into(), "again".into(), false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SdkError::DuplicateToolUseId { .. }));
+        // The duplicate must not reach the CLI
+        assert!(handle.sent_input_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_end_input_closes_input_and_keeps_draining() {
+        let (transport, mut handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+        client.send_message("last prompt".into()).await.unwrap();
+
+        client.end_input().await.unwrap();
+        assert_eq!(handle.end_input_rx.recv().await, Some(true));
+        client.end_input().await.unwrap();
+        assert!(handle.end_input_rx.try_recv().is_err());
+
+        let err = client.send_message("more".into()).await.unwrap_err();
+        assert!(matches!(err, SdkError::InvalidState { .. }));
+        let err = client
+            .send_tool_result("toolu_1".into(), "ok".into(), false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SdkError::InvalidState { .. }));
+
+        // Output of the last turn can still be received
+        let inbound = handle.inbound_message_tx.clone();
+        let (messages, _) = tokio::join!(client.receive_response(), async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            inbound.send(Message::result_success("test")).unwrap();
+        });
+        assert_eq!(messages.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_receive_structured_deserializes_result() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        struct Capital {
+            city: String,
+        }
+
+        fn result(structured_output: serde_json::Value) -> Message {
+            let mut message = Message::result_success("test");
+            if let Message::Result {
+                structured_output: output,
+                ..
+            } = &mut message
+            {
+                *output = Some(structured_output);
+            }
+            message
+        }
+
+        let (transport, handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+
+        let inbound = handle.inbound_message_tx.clone();
+        let (capital, _) = tokio::join!(client.receive_structured::<Capital>(), async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            inbound
+                .send(result(serde_json::json!({"city": "Paris"})))
+                .unwrap();
+        });
+        assert_eq!(
+            capital.unwrap(),
+            Capital {
+                city: "Paris".to_string()
+            }
+        );
+
+        let inbound = handle.inbound_message_tx.clone();
+        let (mismatch, _) = tokio::join!(client.receive_structured::<Capital>(), async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            inbound
+                .send(result(serde_json::json!({"city": 3})))
+                .unwrap();
+        });
+        match mismatch.unwrap_err() {
+            SdkError::MessageParseError { path, raw, .. } => {
+                assert_eq!(path.as_deref(), Some("structured_output.city"));
+                assert_eq!(raw, r#"{"city":3}"#);
+            },
+            other => panic!("expected MessageParseError, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subagent_result_does_not_end_turn() {
+        fn result(parent_tool_use_id: Option<&str>) -> Message {
+            let mut message = Message::result_success("test");
+            if let Message::Result {
+                parent_tool_use_id: parent,
+                ..
+            } = &mut message
+            {
+                *parent = parent_tool_use_id.map(String::from);
+            }
+            message
+        }
+
+        async fn turn(
+            client: &mut InteractiveClient,
+            handle: &MockTransportHandle,
+        ) -> Vec<Message> {
+            let stream = client
+                .send_and_receive_stream("Delegate".to_string())
+                .await
+                .unwrap();
+            let inbound = handle.inbound_message_tx.clone();
+            let (messages, _) = tokio::join!(stream.collect::<Vec<_>>(), async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                inbound.send(result(Some("toolu_task"))).unwrap();
+                inbound.send(result(None)).unwrap();
+            });
+            messages.into_iter().map(|m| m.unwrap()).collect()
+        }
+
+        let (transport, handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+
+        // Default: the subagent's result is passed through, the main one ends the turn
+        let messages = turn(&mut client, &handle).await;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].parent_tool_use_id(), Some("toolu_task"));
+        assert!(messages[1].is_top_level());
+        assert!(client.is_ready_for_input());
+        let history = client.conversation_history().await;
+        assert_eq!(history[0].messages.len(), 2);
+        assert!(history[0].is_complete());
+
+        // First: the subagent's result ends the turn
+        client.set_result_policy(ResultPolicy::First);
+        let messages = turn(&mut client, &handle).await;
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].is_sidechain());
+        assert!(client.is_ready_for_input());
+        let history = client.conversation_history().await;
+        assert_eq!(history[1].messages.len(), 1);
+        assert!(history[1].is_complete());
+    }
+
+    #[cfg(feature = "subprocess")]
+    #[tokio::test]
+    async fn test_session_getters_track_config_and_changes() {
+        let options = ClaudeCodeOptions::builder()
+            .cli_path("/usr/bin/true")
+            .model("claude-sonnet-4-5")
+            .cwd("/srv/project")
+            .permission_mode(PermissionMode::Plan)
+            .build();
+        let client = InteractiveClient::new(options).unwrap();
+        assert_eq!(client.model(), Some("claude-sonnet-4-5"));
+        assert_eq!(client.cwd(), Some(Path::new("/srv/project")));
+        assert_eq!(client.permission_mode(), PermissionMode::Plan);
+
+        let (transport, mut handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+        assert_eq!(client.model(), None);
+        assert_eq!(client.cwd(), None);
+        assert_eq!(client.permission_mode(), PermissionMode::Default);
+
+        client
+            .set_model(Some("claude-opus-4-1".into()))
+            .await
+            .unwrap();
+        client.set_permission_mode("acceptEdits").await.unwrap();
+        assert_eq!(client.model(), Some("claude-opus-4-1"));
+        assert_eq!(client.permission_mode(), PermissionMode::AcceptEdits);
+        let request = handle.outbound_control_request_rx.recv().await.unwrap();
+        assert_eq!(request["request"]["subtype"], "set_model");
+        assert_eq!(request["request"]["model"], "claude-opus-4-1");
+
+        // Rejected changes leave the getters untouched
+        assert!(client.set_permission_mode("yolo").await.is_err());
+        assert_eq!(client.permission_mode(), PermissionMode::AcceptEdits);
+
+        client.set_model(None).await.unwrap();
+        assert_eq!(client.model(), None);
+    }
+
+    #[tokio::test]
+    async fn test_attach_is_not_supported_and_sends_nothing() {
+        let (transport, mut handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+
+        let results: Vec<_> = client.attach("session-123").collect().await;
+        assert_eq!(results.len(), 1);
+        match &results[0] {
+            Err(SdkError::NotSupported { feature }) => assert!(feature.contains("session-123")),
+            other => panic!("expected NotSupported, got {other:?}"),
+        }
+
+        // Attaching is read-only: no prompt or control request reaches the CLI
+        assert!(handle.sent_input_rx.try_recv().is_err());
+        assert!(handle.outbound_control_request_rx.try_recv().is_err());
+        assert!(client.is_ready_for_input());
+    }
+
+    #[cfg(all(unix, feature = "subprocess"))]
+    #[tokio::test]
+    async fn test_drain_discards_interrupted_turn() {
+        // Fake CLI: the first prompt starts a turn that only finishes (with
+        // more output) once interrupted; the second prompt gets a clean turn
+        let dir = tempfile::tempdir().unwrap();
+        let path = fake_cli(
+            dir.path(),
+            r#"say() { echo '{"type":"assistant","message":{"content":[{"type":"text","text":"'$1'"}]}}'; }
+result() { echo '{"type":"result","subtype":"'$1'","duration_ms":1,"duration_api_ms":1,"is_error":false,"num_turns":1,"session_id":"s"}'; }
+while read -r line; do
+  case "$line" in
+    *interrupt*) say stale-2; result error_during_execution ;;
+    *second*) say fresh; result success ;;
+    *) say stale-1 ;;
+  esac
+done
+"#,
+        );
+
+        let options = ClaudeCodeOptions::builder().cli_path(&path).build();
+        let mut client = InteractiveClient::new(options).unwrap();
+        client.connect().await.unwrap();
+        client.send_message("first".into()).await.unwrap();
+        client.interrupt().await.unwrap();
+
+        let discarded = tokio::time::timeout(Duration::from_secs(10), client.drain())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(discarded, 3);
+        assert!(client.is_ready_for_input());
+        // Nothing buffered any more
+        assert_eq!(client.drain().await.unwrap(), 0);
+
+        let messages = tokio::time::timeout(
+            Duration::from_secs(10),
+            client.send_and_receive("second".into()),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let texts: Vec<String> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                Message::Assistant { message, .. } => Some(message.text()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, vec!["fresh"]);
+        client.disconnect().await.unwrap();
+    }
+
+    #[cfg(all(unix, feature = "subprocess"))]
+    #[tokio::test]
+    async fn test_rate_limited_turn_fails_after_its_result() {
+        // Fake CLI: the first prompt is rate limited, the second succeeds
+        let dir = tempfile::tempdir().unwrap();
+        let path = fake_cli(
+            dir.path(),
+            r#"result() { echo '{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":'$1',"num_turns":1,"session_id":"s","result":"'"$2"'"}'; }
+while read -r line; do
+  case "$line" in
+    *first*) result true 'API Error: 429' ;;
+    *) result false done ;;
+  esac
+done
+"#,
+        );
+
+        let options = ClaudeCodeOptions::builder().cli_path(&path).build();
+        let mut client = InteractiveClient::new(options).unwrap();
+        client.connect().await.unwrap();
+
+        let err = tokio::time::timeout(
+            Duration::from_secs(10),
+            client.send_and_receive("first".into()),
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+        assert!(matches!(err, SdkError::RateLimited { .. }));
+        // The Result was consumed, so the next turn starts clean
+        assert!(client.is_ready_for_input());
+
+        let messages = tokio::time::timeout(
+            Duration::from_secs(10),
+            client.send_and_receive("second".into()),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(matches!(
+            messages.as_slice(),
+            [Message::Result {
+                is_error: false,
+                ..
+            }]
+        ));
+        client.disconnect().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_times_out_when_turn_goes_quiet() {
+        let (transport, _handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+        client.send_message("hello".into()).await.unwrap();
+        assert!(!client.is_ready_for_input());
+
+        let err = client.drain().await.unwrap_err();
+        assert!(matches!(err, SdkError::Timeout { .. }));
+        // The transport lock was released
+        client.send_message("again".into()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_messages_delivers_batch_in_order() {
+        let (transport, mut handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+
+        let batch = vec![
+            InputMessage::user("first".into(), "default".into()),
+            InputMessage::tool_result("toolu_1".into(), "done".into(), "default".into(), false),
+            InputMessage::user("second".into(), "default".into()),
+        ];
+        client.send_messages(batch).await.unwrap();
+
+        let first = handle.sent_input_rx.recv().await.unwrap();
+        let second = handle.sent_input_rx.recv().await.unwrap();
+        let third = handle.sent_input_rx.recv().await.unwrap();
+        assert_eq!(first.message["content"], "first");
+        assert_eq!(second.parent_tool_use_id.as_deref(), Some("toolu_1"));
+        assert_eq!(third.message["content"], "second");
+        assert!(!client.is_ready_for_input());
+
+        // The batch is a single turn, answered by a single Result
+        let history = client.conversation_history().await;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].prompt, "first\n\nsecond");
+
+        // Tool results alone continue that turn
+        let results = vec![InputMessage::tool_result(
+            "toolu_2".into(),
+            "done".into(),
+            "default".into(),
+            false,
+        )];
+        client.send_messages(results).await.unwrap();
+        assert_eq!(client.conversation_history().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_and_receive_with_context_applies_to_one_turn() {
+        let (transport, script) = MockTransport::builder()
+            .expect_user_message()
+            .then_reply(vec![Message::result_success("test")])
+            .expect_user_message()
+            .then_reply(vec![Message::result_success("test")])
+            .build();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+
+        client
+            .send_and_receive_with_context("Plan my day".into(), "The user is on mobile")
+            .await
+            .unwrap();
+        client
+            .send_and_receive("And tomorrow?".into())
+            .await
+            .unwrap();
+
+        let sent = script.sent_messages();
+        assert_eq!(
+            sent[0].message["content"],
+            serde_json::json!([
+                { "type": "text", "text": "The user is on mobile" },
+                { "type": "text", "text": "Plan my day" }
+            ])
+        );
+        assert_eq!(sent[1].message["content"], "And tomorrow?");
+        let prompts: Vec<String> = client
+            .conversation_history()
+            .await
+            .into_iter()
+            .map(|turn| turn.prompt)
+            .collect();
+        assert_eq!(prompts, vec!["Plan my day", "And tomorrow?"]);
+        script.assert_expectations_met();
+    }
+
+    /// Extract the request ID of the next interrupt sent through the mock
+    async fn sent_interrupt_id(handle: &mut MockTransportHandle) -> String {
+        let request = handle.outbound_control_request_rx.recv().await.unwrap();
+        assert_eq!(request["request"]["type"], "interrupt");
+        request["request"]["request_id"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_and_confirm_awaits_ack() {
+        let (transport, mut handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+        client.send_message("long task".into()).await.unwrap();
+
+        let (outcome, _) = tokio::join!(client.interrupt_and_confirm(), async {
+            let request_id = sent_interrupt_id(&mut handle).await;
+            // A stale ack for another request must not be mistaken for ours
+            for (request_id, success) in [("stale".to_string(), false), (request_id, true)] {
+                handle
+                    .control_response_tx
+                    .send(ControlResponse::InterruptAck {
+                        request_id,
+                        success,
+                    })
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let outcome = outcome.unwrap();
+        assert!(outcome.success);
+        assert!(outcome.turn_in_progress);
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_and_confirm_reports_idle_and_rejected() {
+        let (transport, mut handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+
+        let (outcome, _) = tokio::join!(client.interrupt_and_confirm(), async {
+            let request_id = sent_interrupt_id(&mut handle).await;
+            handle
+                .control_response_tx
+                .send(ControlResponse::InterruptAck {
+                    request_id,
+                    success: false,
+                })
+                .await
+                .unwrap();
+        });
+
+        let outcome = outcome.unwrap();
+        assert!(!outcome.success);
+        assert!(!outcome.turn_in_progress);
+    }
+
+    #[tokio::test]
+    async fn test_interrupts_coalesce_until_answered() {
+        let (transport, mut handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+        client.send_message("long task".into()).await.unwrap();
+
+        for _ in 0..3 {
+            client.interrupt().await.unwrap();
+        }
+        let request_id = sent_interrupt_id(&mut handle).await;
+        assert!(
+            handle.outbound_control_request_rx.try_recv().is_err(),
+            "interrupts were not coalesced"
+        );
+
+        // Once the CLI answers, the next interrupt is sent again
+        handle
+            .sdk_control_tx
+            .send(serde_json::json!({
+                "type": "control_response",
+                "response": {"subtype": "success", "request_id": request_id}
+            }))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.interrupt().await.unwrap();
+        assert_ne!(sent_interrupt_id(&mut handle).await, request_id);
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_with_result_distinguishes_idle_and_active() {
+        let (transport, mut handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+
+        // Idle: the CLI acknowledges, but there was nothing to interrupt
+        let (result, _) = tokio::join!(client.interrupt_with_result(), async {
+            let request_id = sent_interrupt_id(&mut handle).await;
+            handle
+                .control_response_tx
+                .send(ControlResponse::InterruptAck {
+                    request_id,
+                    success: true,
+                })
+                .await
+                .unwrap();
+        });
+        assert_eq!(result.unwrap(), InterruptResult::NoActiveTurn);
+
+        // Active: a prompt is in flight
+        client.send_message("long task".into()).await.unwrap();
+        let (result, _) = tokio::join!(client.interrupt_with_result(), async {
+            let request_id = sent_interrupt_id(&mut handle).await;
+            handle
+                .control_response_tx
+                .send(ControlResponse::InterruptAck {
+                    request_id,
+                    success: true,
+                })
+                .await
+                .unwrap();
+        });
+        assert_eq!(result.unwrap(), InterruptResult::TurnInterrupted);
+
+        // Still active: the CLI refuses, so the turn is not reported idle
+        client.send_message("another task".into()).await.unwrap();
+        let (result, _) = tokio::join!(client.interrupt_with_result(), async {
+            let request_id = sent_interrupt_id(&mut handle).await;
+            handle
+                .control_response_tx
+                .send(ControlResponse::InterruptAck {
+                    request_id,
+                    success: false,
+                })
+                .await
+                .unwrap();
+        });
+        assert_eq!(result.unwrap(), InterruptResult::Rejected);
+    }
+
+    #[tokio::test]
+    async fn test_send_and_receive_cancellable_interrupts_and_drains() {
+        use crate::types::{AssistantMessage, ContentBlock, TextContent};
+
+        let (transport, mut handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+        let cancel = CancellationToken::new();
+
+        let inbound = handle.inbound_message_tx.clone();
+        let (result, _) = tokio::join!(
+            tokio::time::timeout(
+                Duration::from_secs(5),
+                client.send_and_receive_cancellable("long task".into(), cancel.clone()),
+            ),
+            async {
+                handle.sent_input_rx.recv().await.unwrap();
+                // Give the receive loop time to subscribe to the mock broadcast
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                inbound
+                    .send(Message::Assistant {
+                        message: AssistantMessage {
+                            content: vec![ContentBlock::Text(TextContent {
+                                text: "working".to_string(),
+                            })],
+                        },
+                        parent_tool_use_id: None,
+                    })
+                    .unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                cancel.cancel();
+                sent_interrupt_id(&mut handle).await;
+                // Give drain time to subscribe to the mock broadcast
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                inbound.send(Message::result_success("test")).unwrap();
+            }
+        );
+
+        let result = result.expect("cancellation must end the call promptly");
+        match result {
+            Err(SdkError::Cancelled { partial }) => {
+                // The message received before cancelling and the drained Result
+                assert_eq!(partial.len(), 2);
+                assert!(matches!(partial[0], Message::Assistant { .. }));
+                assert!(matches!(partial[1], Message::Result { .. }));
+            },
+            other => panic!("expected Cancelled, got {other:?}"),
+        }
+        assert!(client.is_ready_for_input());
+
+        // An already-cancelled token sends nothing
+        let result = client
+            .send_and_receive_cancellable("ignored".into(), cancel)
+            .await;
+        assert!(matches!(result, Err(SdkError::Cancelled { partial }) if partial.is_empty()));
+        assert!(handle.sent_input_rx.try_recv().is_err());
+        assert!(handle.outbound_control_request_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_and_receive_with_max_turns_interrupts_at_limit() {
+        use crate::types::{AssistantMessage, ToolResultContent, ToolUseContent, UserMessage};
+
+        let tool_round = |id: &str| {
+            [
+                Message::Assistant {
+                    message: AssistantMessage {
+                        content: vec![ContentBlock::ToolUse(ToolUseContent {
+                            id: id.to_string(),
+                            name: "Read".to_string(),
+                            input: serde_json::json!({}),
+                        })],
+                    },
+                    parent_tool_use_id: None,
+                },
+                Message::User {
+                    message: UserMessage {
+                        content: String::new(),
+                        content_blocks: Some(vec![ContentBlock::ToolResult(
+                            ToolResultContent::from_ok(id, "contents"),
+                        )]),
+                    },
+                    parent_tool_use_id: None,
+                },
+            ]
+        };
+
+        let (transport, mut handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+
+        let inbound = handle.inbound_message_tx.clone();
+        let (result, _) = tokio::join!(
+            tokio::time::timeout(
+                Duration::from_secs(5),
+                client.send_and_receive_with_max_turns("multi-step task".into(), 2),
+            ),
+            async {
+                handle.sent_input_rx.recv().await.unwrap();
+                // Give the receive loop time to subscribe to the mock broadcast
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                for id in ["toolu_1", "toolu_2"] {
+                    // Nothing is interrupted before the limit is reached
+                    assert!(handle.outbound_control_request_rx.try_recv().is_err());
+                    for message in tool_round(id) {
+                        inbound.send(message).unwrap();
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                    }
+                }
+                // The second round of tool results reaches the limit
+                sent_interrupt_id(&mut handle).await;
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                inbound.send(Message::result_success("test")).unwrap();
+            }
+        );
+
+        let messages = result.expect("the limit must end the turn").unwrap();
+        assert_eq!(messages.len(), 5);
+        assert!(matches!(messages[4], Message::Result { .. }));
+        assert!(client.is_ready_for_input());
+    }
+
+    #[tokio::test]
+    async fn test_turn_state_tracks_prompt_until_result() {
+        let (transport, mut handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+        assert!(client.is_ready_for_input());
+
+        client.send_message("hello".into()).await.unwrap();
+        handle.sent_input_rx.recv().await.unwrap();
+        assert!(!client.is_ready_for_input());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), client.await_ready())
+                .await
+                .is_err(),
+            "await_ready must block while the turn is in progress"
+        );
+
+        let result = Message::result_success("test");
+        let inbound = handle.inbound_message_tx.clone();
+        let (messages, _) = tokio::join!(client.receive_response(), async move {
+            // Give receive_response time to subscribe to the mock broadcast
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            inbound.send(result).unwrap();
+        });
+        assert_eq!(messages.unwrap().len(), 1);
+
+        assert!(client.is_ready_for_input());
+        tokio::time::timeout(Duration::from_millis(20), client.await_ready())
+            .await
+            .expect("await_ready must complete once the Result arrived");
+    }
+
+    #[tokio::test]
+    async fn test_conversation_history_reconstructs_turns() {
+        use crate::types::{AssistantMessage, ContentBlock, TextContent};
+
+        let assistant = |text: &str| Message::Assistant {
+            message: AssistantMessage {
+                content: vec![ContentBlock::Text(TextContent {
+                    text: text.to_string(),
+                })],
+            },
+            parent_tool_use_id: None,
+        };
+
+        let (transport, handle) = MockTransport::pair();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+
+        for (prompt, replies) in [
+            ("hello", vec!["hi", "how can I help?"]),
+            ("bye", vec!["bye!"]),
+        ] {
+            // The stream subscribes before sending, so nothing scripted is lost
+            let stream = client.send_and_receive_stream(prompt.into()).await.unwrap();
+            for reply in replies {
+                handle.inbound_message_tx.send(assistant(reply)).unwrap();
+            }
+            handle
+                .inbound_message_tx
+                .send(Message::result_success("test"))
+                .unwrap();
+            let received: Vec<_> = stream.collect().await;
+            assert!(received.iter().all(|msg| msg.is_ok()));
+        }
+
+        let history = client.conversation_history().await;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].prompt, "hello");
+        assert_eq!(history[0].assistant_text(), "hi\nhow can I help?");
+        assert!(history[0].is_complete());
+        assert_eq!(history[1].prompt, "bye");
+        assert_eq!(history[1].messages.len(), 2);
+        assert_eq!(history[1].assistant_text(), "bye!");
+
+        // A compaction announced on the stream counts everything before it
+        let stream = client
+            .send_and_receive_stream("/compact".into())
+            .await
+            .unwrap();
+        handle
+            .inbound_message_tx
+            .send(Message::System {
+                subtype: "compact_boundary".to_string(),
+                data: serde_json::json!({"compact_metadata": {"trigger": "manual", "pre_tokens": 1200}}),
+            })
+            .unwrap();
+        handle
+            .inbound_message_tx
+            .send(Message::User {
+                message: crate::types::UserMessage {
+                    content: "Summary of the chat".to_string(),
+                    content_blocks: None,
+                },
+                parent_tool_use_id: None,
+            })
+            .unwrap();
+        handle
+            .inbound_message_tx
+            .send(Message::result_success("test"))
+            .unwrap();
+        let _: Vec<_> = stream.collect().await;
+
+        let compactions = client.compactions().await;
+        assert_eq!(compactions.len(), 1);
+        assert_eq!(compactions[0].removed_message_count, 6);
+        assert_eq!(compactions[0].summary, "Summary of the chat");
+        assert_eq!(compactions[0].trigger.as_deref(), Some("manual"));
+        assert_eq!(compactions[0].pre_tokens, Some(1200));
+    }
+
+    #[tokio::test]
+    async fn test_auto_responder_answers_whitelisted_tools_only() {
+        use crate::types::{AssistantMessage, ContentBlock, ToolUseContent};
+
+        let tool_use = |id: &str, name: &str| {
+            ContentBlock::ToolUse(ToolUseContent {
+                id: id.to_string(),
+                name: name.to_string(),
+                input: serde_json::json!({}),
+            })
+        };
+
+        let (transport, mut handle) = MockTransport::pair();
+        let options = ClaudeCodeOptions::builder()
+            .auto_responder(AutoResponder::new().register("get_time", |_| Ok("12:00".to_string())))
+            .build();
+        let mut client = InteractiveClient::from_transport_with_options(transport, &options);
+        client.connect().await.unwrap();
+
+        let mut stream = Box::pin(
+            client
+                .send_and_receive_stream("time?".into())
+                .await
+                .unwrap(),
+        );
+        let prompt = handle.sent_input_rx.recv().await.unwrap();
+        assert_eq!(prompt.parent_tool_use_id, None);
+
+        handle
+            .inbound_message_tx
+            .send(Message::Assistant {
+                message: AssistantMessage {
+                    content: vec![
+                        tool_use("toolu_time", "get_time"),
+                        tool_use("toolu_bash", "Bash"),
+                    ],
+                },
+                parent_tool_use_id: None,
+            })
+            .unwrap();
+        // The caller still sees both tool uses
+        assert!(matches!(
+            stream.next().await,
+            Some(Ok(Message::Assistant { message, .. })) if message.content.len() == 2
+        ));
+
+        // Only the whitelisted tool was answered
+        let answer = handle.sent_input_rx.recv().await.unwrap();
+        assert_eq!(answer.parent_tool_use_id.as_deref(), Some("toolu_time"));
+        assert_eq!(answer.message["content"][0]["content"], "12:00");
+        assert!(handle.sent_input_rx.try_recv().is_err());
+    }
+
     // ================================================================
     // Tests for build_interrupt_json()
     // ================================================================
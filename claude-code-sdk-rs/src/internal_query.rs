@@ -4,13 +4,14 @@
 //! permissions, hooks, and MCP server integration.
 
 use crate::{
+    cli_features::CliFeatures,
     errors::{Result, SdkError},
-    transport::{InputMessage, Transport},
+    transport::{InputMessage, Transport, is_reconnect_marker},
     types::{
         CanUseTool, HookCallback, HookContext, HookMatcher, Message, PermissionResult,
-        PermissionUpdate, SDKControlInitializeRequest, SDKControlInterruptRequest,
-        SDKControlPermissionRequest, SDKControlRequest, SDKControlSetPermissionModeRequest,
-        SDKHookCallbackRequest, ToolPermissionContext,
+        PermissionResultDeny, PermissionUpdate, SDKControlInitializeRequest,
+        SDKControlInterruptRequest, SDKControlPermissionRequest, SDKControlRequest,
+        SDKControlSetPermissionModeRequest, SDKHookCallbackRequest, ToolPermissionContext,
     },
 };
 use futures::StreamExt;
@@ -19,9 +20,24 @@ use serde_json::Value as JsonValue;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock, mpsc};
-use tokio::time::{Duration, timeout};
+use tokio::time::{Duration, Instant, timeout};
 use tracing::{debug, error, warn};
 
+/// Denial reason sent when a permission callback exceeds its timeout
+const PERMISSION_TIMEOUT_REASON: &str = "permission callback timed out";
+
+/// Waiters for the responses to control requests, keyed by request ID
+type PendingResponses = Arc<RwLock<HashMap<String, tokio::sync::oneshot::Sender<JsonValue>>>>;
+
+/// Outcome of the most recent interrupt request
+struct LastInterrupt {
+    /// When the CLI answered (or the request failed); callers that asked
+    /// before this were waiting on the request in flight
+    finished: Instant,
+    /// Error message if the interrupt failed
+    error: Option<String>,
+}
+
 /// Internal query handler with control protocol support
 pub struct Query {
     /// Transport layer (shared with client)
@@ -31,6 +47,8 @@ pub struct Query {
     is_streaming_mode: bool,
     /// Tool permission callback
     can_use_tool: Option<Arc<dyn CanUseTool>>,
+    /// Upper bound on a single `can_use_tool` call (unbounded when `None`)
+    permission_callback_timeout: Option<Duration>,
     /// Hook configurations
     hooks: Option<HashMap<String, Vec<HookMatcher>>>,
     /// SDK MCP servers
@@ -50,7 +68,11 @@ pub struct Query {
     /// Request counter for generating unique IDs
     request_counter: Arc<Mutex<u64>>,
     /// Pending control request responses
-    pending_responses: Arc<RwLock<HashMap<String, tokio::sync::oneshot::Sender<JsonValue>>>>,
+    pending_responses: PendingResponses,
+    /// Held while an interrupt is in flight; callers queued behind it reuse its outcome
+    last_interrupt: Arc<Mutex<Option<LastInterrupt>>>,
+    /// Initialize request sent by `initialize`, re-sent when the CLI restarts
+    init_request: Arc<Mutex<Option<SDKControlRequest>>>,
 }
 
 impl Query {
@@ -68,6 +90,7 @@ impl Query {
             transport,
             is_streaming_mode,
             can_use_tool,
+            permission_callback_timeout: None,
             hooks,
             sdk_mcp_servers,
             message_tx: tx,
@@ -77,9 +100,20 @@ impl Query {
             callback_counter: Arc::new(Mutex::new(0)),
             request_counter: Arc::new(Mutex::new(0)),
             pending_responses: Arc::new(RwLock::new(HashMap::new())),
+            last_interrupt: Arc::new(Mutex::new(None)),
+            init_request: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Deny tool uses whose `can_use_tool` callback outlasts `timeout`
+    ///
+    /// The CLI gets `{"allow": false, "reason": "permission callback timed
+    /// out"}` and a warning naming the tool is logged. `None` (the default)
+    /// waits for the callback indefinitely.
+    pub fn set_permission_callback_timeout(&mut self, timeout: Option<Duration>) {
+        self.permission_callback_timeout = timeout;
+    }
+
     /// Test helper to register a hook callback with a known ID
     ///
     /// This is intended for E2E tests to inject a callback ID that can be
@@ -182,6 +216,7 @@ impl Query {
         });
 
         // Send control request and save result
+        *self.init_request.lock().await = Some(init_request.clone());
         let result = self.send_control_request(init_request).await?;
         self.initialization_result = Some(result);
 
@@ -190,69 +225,14 @@ impl Query {
     }
 
     /// Send a control request and wait for response
-    async fn send_control_request(&mut self, request: SDKControlRequest) -> Result<JsonValue> {
-        // Generate unique request ID
-        let request_id = {
-            let mut counter = self.request_counter.lock().await;
-            *counter += 1;
-            format!("req_{}_{}", *counter, uuid::Uuid::new_v4().simple())
-        };
-
-        // Create oneshot channel for response
-        let (tx, rx) = tokio::sync::oneshot::channel();
-
-        // Register pending response
-        {
-            let mut pending = self.pending_responses.write().await;
-            pending.insert(request_id.clone(), tx);
-        }
-
-        // Build control request with request_id (snake_case for CLI compatibility)
-        let control_request = serde_json::json!({
-            "type": "control_request",
-            "request_id": request_id,
-            "request": request
-        });
-
-        debug!("Sending control request: {:?}", control_request);
-
-        // Send via transport
-        {
-            let mut transport = self.transport.lock().await;
-            transport.send_sdk_control_request(control_request).await?;
-        }
-
-        // Wait for response with timeout
-        match timeout(Duration::from_secs(60), rx).await {
-            Ok(Ok(response)) => {
-                debug!("Received control response for {}", request_id);
-
-                // Python parity: treat subtype=error as an error, and return only
-                // the payload from `response` (or legacy `data`) on success.
-                if response.get("subtype").and_then(|v| v.as_str()) == Some("error") {
-                    let msg = response
-                        .get("error")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("Unknown control request error");
-                    return Err(SdkError::ControlRequestError(msg.to_string()));
-                }
-
-                Ok(response
-                    .get("response")
-                    .or_else(|| response.get("data"))
-                    .cloned()
-                    .unwrap_or_else(|| serde_json::json!({})))
-            },
-            Ok(Err(_)) => Err(SdkError::ControlRequestError(
-                "Response channel closed".to_string(),
-            )),
-            Err(_) => {
-                // Clean up pending response
-                let mut pending = self.pending_responses.write().await;
-                pending.remove(&request_id);
-                Err(SdkError::Timeout { seconds: 60 })
-            },
-        }
+    async fn send_control_request(&self, request: SDKControlRequest) -> Result<JsonValue> {
+        send_control_request(
+            &self.transport,
+            &self.request_counter,
+            &self.pending_responses,
+            request,
+        )
+        .await
     }
 
     /// Handle permission request
@@ -267,9 +247,14 @@ impl Query {
                 suggestions: request.permission_suggestions.unwrap_or_default(),
             };
 
-            let result = can_use_tool
-                .can_use_tool(&request.tool_name, &request.input, &context)
-                .await;
+            let result = decide_permission(
+                can_use_tool.as_ref(),
+                &request.tool_name,
+                &request.input,
+                &context,
+                self.permission_callback_timeout,
+            )
+            .await;
 
             // Send response back (CLI expects: { allow: bool, input?, reason? })
             let response = match result {
@@ -309,9 +294,12 @@ impl Query {
     async fn start_control_handler(&mut self) {
         let transport = self.transport.clone();
         let can_use_tool = self.can_use_tool.clone();
+        let permission_callback_timeout = self.permission_callback_timeout;
         let hook_callbacks = self.hook_callbacks.clone();
         let sdk_mcp_servers = self.sdk_mcp_servers.clone();
         let pending_responses = self.pending_responses.clone();
+        let request_counter = self.request_counter.clone();
+        let init_request = self.init_request.clone();
 
         // Take ownership of the SDK control receiver to avoid holding locks
         let sdk_control_rx = {
@@ -335,6 +323,46 @@ impl Query {
                     if let Some(control_message) = control_message {
                         debug!("Received control message: {:?}", control_message);
 
+                        if is_reconnect_marker(&control_message) {
+                            // Requests sent before the CLI restarted will never be answered
+                            let dropped = {
+                                let mut pending = pending_responses_clone.write().await;
+                                let count = pending.len();
+                                pending.clear();
+                                count
+                            };
+                            warn!(
+                                "CLI restarted, failing {} pending control requests",
+                                dropped
+                            );
+                            // The new process knows nothing of the hooks
+                            // registered with the old one
+                            if let Some(request) = init_request.lock().await.clone() {
+                                let transport = transport_for_control.clone();
+                                let request_counter = request_counter.clone();
+                                let pending_responses = pending_responses_clone.clone();
+                                tokio::spawn(async move {
+                                    match send_control_request(
+                                        &transport,
+                                        &request_counter,
+                                        &pending_responses,
+                                        request,
+                                    )
+                                    .await
+                                    {
+                                        Ok(_) => debug!("Re-initialized the restarted CLI"),
+                                        Err(e) => {
+                                            warn!(
+                                                "Failed to re-initialize the restarted CLI: {}",
+                                                e
+                                            )
+                                        },
+                                    }
+                                });
+                            }
+                            continue;
+                        }
+
                         // Check if this is a control response (from CLI to SDK)
                         if control_message.get("type").and_then(|v| v.as_str())
                             == Some("control_response")
@@ -409,13 +437,14 @@ impl Query {
                                                     .unwrap_or_default(),
                                             };
 
-                                            let result = can_use_tool
-                                                .can_use_tool(
-                                                    &request.tool_name,
-                                                    &request.input,
-                                                    &context,
-                                                )
-                                                .await;
+                                            let result = decide_permission(
+                                                can_use_tool.as_ref(),
+                                                &request.tool_name,
+                                                &request.input,
+                                                &context,
+                                                permission_callback_timeout,
+                                            )
+                                            .await;
 
                                             // CLI expects: {"allow": true, "input": ...} or {"allow": false, "reason": ...}
                                             let permission_response = match result {
@@ -488,9 +517,14 @@ impl Query {
                                                 signal: None,
                                                 suggestions,
                                             };
-                                            let result = can_use_tool
-                                                .can_use_tool(tool_name, &input_val, &context)
-                                                .await;
+                                            let result = decide_permission(
+                                                can_use_tool.as_ref(),
+                                                tool_name,
+                                                &input_val,
+                                                &context,
+                                                permission_callback_timeout,
+                                            )
+                                            .await;
 
                                             let permission_response = match result {
                                                 PermissionResult::Allow(allow) => {
@@ -551,11 +585,10 @@ impl Query {
                                             let context = HookContext { signal: None };
 
                                             // Try to deserialize input as HookInput
-                                            let hook_result = match serde_json::from_value::<
-                                                crate::types::HookInput,
-                                            >(
-                                                request.input.clone()
-                                            ) {
+                                            let parsed = crate::message_parser::parse_hook_input(
+                                                &request.input,
+                                            );
+                                            let hook_result = match parsed {
                                                 Ok(hook_input) => {
                                                     // Call the hook with strongly-typed input
                                                     callback
@@ -571,11 +604,8 @@ impl Query {
                                                         "Failed to parse hook input: {}",
                                                         parse_err
                                                     );
-                                                    // Return error using MessageParseError
-                                                    Err(crate::errors::SdkError::MessageParseError {
-                                                        error: format!("Invalid hook input: {parse_err}"),
-                                                        raw: request.input.to_string(),
-                                                    })
+                                                    // The error carries the path of the bad field
+                                                    Err(parse_err)
                                                 },
                                             };
 
@@ -659,11 +689,9 @@ impl Query {
                                                 let context = HookContext { signal: None };
 
                                                 // Try to parse as HookInput
-                                                let hook_result = match serde_json::from_value::<
-                                                    crate::types::HookInput,
-                                                >(
-                                                    input.clone()
-                                                ) {
+                                                let parsed =
+                                                    crate::message_parser::parse_hook_input(&input);
+                                                let hook_result = match parsed {
                                                     Ok(hook_input) => {
                                                         callback
                                                             .execute(
@@ -678,16 +706,13 @@ impl Query {
                                                             "Failed to parse hook input (fallback): {}",
                                                             parse_err
                                                         );
-                                                        Err(crate::errors::SdkError::MessageParseError {
-                                                            error: format!("Invalid hook input: {parse_err}"),
-                                                            raw: input.to_string(),
-                                                        })
+                                                        Err(parse_err)
                                                     },
                                                 };
 
                                                 let response_json = match hook_result {
                                                     Ok(hook_output) => {
-                                                        let output_value = serde_json::to_value(&hook_output)
+                                                        let output_value = hook_output.to_cli_value()
                                                             .unwrap_or_else(|e| {
                                                                 error!("Failed to serialize hook output (fallback): {}", e);
                                                                 serde_json::json!({})
@@ -837,6 +862,11 @@ impl Query {
                                 },
                             }
                         }
+                    } else {
+                        // Transport gone: nothing will answer pending requests
+                        pending_responses_clone.write().await.clear();
+                        debug!("SDK control channel closed, stopping control handler");
+                        break;
                     }
                 }
             });
@@ -884,13 +914,36 @@ impl Query {
     }
 
     /// Send interrupt request
-    pub async fn interrupt(&mut self) -> Result<()> {
+    ///
+    /// Interrupts are coalesced: callers arriving while one is in flight get
+    /// its outcome instead of sending another request. Hammering interrupt
+    /// therefore keeps at most one interrupt pending, while an interrupt
+    /// sent after the previous one was answered always reaches the CLI.
+    pub async fn interrupt(&self) -> Result<()> {
+        let requested = Instant::now();
+        let mut last = self.last_interrupt.lock().await;
+        if let Some(prev) = last.as_ref()
+            && prev.finished > requested
+        {
+            debug!("Coalescing interrupt with the previous one");
+            return match &prev.error {
+                None => Ok(()),
+                Some(error) => Err(SdkError::ControlRequestError(error.clone())),
+            };
+        }
+
         let interrupt_request = SDKControlRequest::Interrupt(SDKControlInterruptRequest {
             subtype: "interrupt".to_string(),
         });
-
-        self.send_control_request(interrupt_request).await?;
-        Ok(())
+        let result = self
+            .send_control_request(interrupt_request)
+            .await
+            .map(|_| ());
+        *last = Some(LastInterrupt {
+            finished: Instant::now(),
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+        result
     }
 
     /// Set permission mode via control protocol
@@ -941,6 +994,10 @@ impl Query {
     /// # }
     /// ```
     pub async fn rewind_files(&mut self, user_message_id: &str) -> Result<()> {
+        let version = self.transport.lock().await.cli_version();
+        let features = CliFeatures::detect(version.as_ref(), self.initialization_result.as_ref());
+        features.require(features.rewind_files, "rewind_files")?;
+
         let req = SDKControlRequest::RewindFiles(crate::types::SDKControlRewindFilesRequest::new(
             user_message_id,
         ));
@@ -1037,6 +1094,113 @@ impl Query {
     }
 }
 
+/// Ask the permission callback about a tool use, denying it if the callback
+/// outlasts `limit`
+async fn decide_permission(
+    can_use_tool: &dyn CanUseTool,
+    tool_name: &str,
+    input: &JsonValue,
+    context: &ToolPermissionContext,
+    limit: Option<Duration>,
+) -> PermissionResult {
+    let decision = can_use_tool.can_use_tool(tool_name, input, context);
+    let Some(limit) = limit else {
+        return decision.await;
+    };
+    match timeout(limit, decision).await {
+        Ok(result) => result,
+        Err(_) => {
+            warn!(
+                "Permission callback for tool {} timed out after {:?}, denying",
+                tool_name, limit
+            );
+            PermissionResult::Deny(PermissionResultDeny {
+                message: PERMISSION_TIMEOUT_REASON.to_string(),
+                interrupt: false,
+            })
+        },
+    }
+}
+
+/// Send a control request through `transport` and wait for its response
+///
+/// The response is delivered through `pending_responses` by the control
+/// handler started in `Query::start`.
+async fn send_control_request(
+    transport: &Arc<Mutex<Box<dyn Transport + Send>>>,
+    request_counter: &Arc<Mutex<u64>>,
+    pending_responses: &PendingResponses,
+    request: SDKControlRequest,
+) -> Result<JsonValue> {
+    // Generate unique request ID
+    let request_id = {
+        let mut counter = request_counter.lock().await;
+        *counter += 1;
+        format!("req_{}_{}", *counter, uuid::Uuid::new_v4().simple())
+    };
+
+    // Create oneshot channel for response
+    let (tx, rx) = tokio::sync::oneshot::channel();
+
+    // Register pending response
+    {
+        let mut pending = pending_responses.write().await;
+        pending.insert(request_id.clone(), tx);
+    }
+
+    // Build control request with request_id (snake_case for CLI compatibility)
+    let control_request = serde_json::json!({
+        "type": "control_request",
+        "request_id": request_id,
+        "request": request
+    });
+
+    debug!("Sending control request: {:?}", control_request);
+
+    // Send via transport
+    let sent = {
+        let mut transport = transport.lock().await;
+        transport.send_sdk_control_request(control_request).await
+    };
+    if let Err(e) = sent {
+        // No response will ever arrive for a request that was not sent
+        pending_responses.write().await.remove(&request_id);
+        return Err(e);
+    }
+
+    // Wait for response with timeout
+    match timeout(Duration::from_secs(60), rx).await {
+        Ok(Ok(response)) => {
+            debug!("Received control response for {}", request_id);
+
+            // Python parity: treat subtype=error as an error, and return only
+            // the payload from `response` (or legacy `data`) on success.
+            if response.get("subtype").and_then(|v| v.as_str()) == Some("error") {
+                let msg = response
+                    .get("error")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("Unknown control request error");
+                return Err(SdkError::ControlRequestError(msg.to_string()));
+            }
+
+            Ok(response
+                .get("response")
+                .or_else(|| response.get("data"))
+                .cloned()
+                .unwrap_or_else(|| serde_json::json!({})))
+        },
+        Ok(Err(_)) => Err(SdkError::ConnectionError(
+            "CLI connection lost before the control response arrived".to_string(),
+        )),
+        Err(_) => {
+            // Clean up pending response
+            let mut pending = pending_responses.write().await;
+            pending.remove(&request_id);
+            Err(SdkError::Timeout { seconds: 60 })
+        },
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1085,4 +1249,189 @@ mod tests {
         assert_eq!(im.message["role"].as_str().unwrap(), "user");
         assert_eq!(im.message["content"].as_str().unwrap(), "Hi");
     }
+
+    #[tokio::test]
+    async fn test_slow_permission_callback_is_denied_after_timeout() {
+        use crate::types::PermissionResultAllow;
+
+        /// Never answers for "Slow", allows everything else
+        struct SlowForOneTool;
+
+        #[async_trait::async_trait]
+        impl CanUseTool for SlowForOneTool {
+            async fn can_use_tool(
+                &self,
+                tool_name: &str,
+                _input: &JsonValue,
+                _context: &ToolPermissionContext,
+            ) -> PermissionResult {
+                if tool_name == "Slow" {
+                    std::future::pending::<()>().await;
+                }
+                PermissionResult::Allow(PermissionResultAllow {
+                    updated_input: None,
+                    updated_permissions: None,
+                })
+            }
+        }
+
+        let (transport, mut handle) = crate::transport::mock::MockTransport::pair();
+        let transport = Arc::new(Mutex::new(transport));
+        let mut query = Query::new(
+            transport,
+            true,
+            Some(Arc::new(SlowForOneTool)),
+            None,
+            HashMap::new(),
+        );
+        query.set_permission_callback_timeout(Some(Duration::from_millis(50)));
+        query.start().await.unwrap();
+
+        for (id, tool) in [("req_1", "Slow"), ("req_2", "Fast")] {
+            handle
+                .sdk_control_tx
+                .send(serde_json::json!({
+                    "type": "control_request",
+                    "request_id": id,
+                    "request": {"subtype": "can_use_tool", "tool_name": tool, "input": {}}
+                }))
+                .await
+                .unwrap();
+        }
+
+        let mut next_response = async || {
+            timeout(Duration::from_secs(5), handle.outbound_control_rx.recv())
+                .await
+                .unwrap()
+                .unwrap()
+        };
+        let denied = next_response().await;
+        assert_eq!(denied["response"]["request_id"], "req_1");
+        assert_eq!(
+            denied["response"]["response"],
+            serde_json::json!({"allow": false, "reason": PERMISSION_TIMEOUT_REASON})
+        );
+        // The handler is free again for the next request
+        let allowed = next_response().await;
+        assert_eq!(allowed["response"]["request_id"], "req_2");
+        assert_eq!(allowed["response"]["response"]["allow"], true);
+    }
+
+    struct NoopHook;
+
+    #[async_trait::async_trait]
+    impl HookCallback for NoopHook {
+        async fn execute(
+            &self,
+            _input: &crate::types::HookInput,
+            _tool_use_id: Option<&str>,
+            _context: &HookContext,
+        ) -> std::result::Result<crate::types::HookJSONOutput, SdkError> {
+            Ok(crate::types::HookJSONOutput::from(
+                crate::types::SyncHookJSONOutput::default(),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restarted_cli_is_initialized_again() {
+        let (transport, mut handle) = crate::transport::mock::MockTransport::pair();
+        let transport = Arc::new(Mutex::new(transport));
+        let mut hooks = HashMap::new();
+        hooks.insert(
+            "PreToolUse".to_string(),
+            vec![HookMatcher {
+                matcher: Some(serde_json::json!("Bash")),
+                hooks: vec![Arc::new(NoopHook) as Arc<dyn HookCallback>],
+            }],
+        );
+        let mut query = Query::new(transport, true, None, Some(hooks), HashMap::new());
+        query.start().await.unwrap();
+
+        // Acknowledge every control request, passing each one on to the test
+        let sdk_control_tx = handle.sdk_control_tx.clone();
+        let (request_tx, mut request_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(request) = handle.outbound_control_request_rx.recv().await {
+                let _ = sdk_control_tx
+                    .send(serde_json::json!({
+                        "type": "control_response",
+                        "response": {"subtype": "success", "request_id": request["request_id"]}
+                    }))
+                    .await;
+                let _ = request_tx.send(request);
+            }
+        });
+
+        query.initialize().await.unwrap();
+        let first = request_rx.recv().await.unwrap();
+        assert_eq!(first["request"]["subtype"], "initialize");
+
+        handle
+            .sdk_control_tx
+            .send(serde_json::json!({
+                "type": "system",
+                "subtype": crate::transport::RECONNECTED_CONTROL_SUBTYPE,
+            }))
+            .await
+            .unwrap();
+        let again = tokio::time::timeout(Duration::from_secs(5), request_rx.recv())
+            .await
+            .expect("the restarted CLI must be initialized again")
+            .unwrap();
+        assert_eq!(again["request"]["subtype"], "initialize");
+        // The same callback IDs, so the registered hooks keep working
+        assert_eq!(again["request"]["hooks"], first["request"]["hooks"]);
+        assert_ne!(again["request_id"], first["request_id"]);
+    }
+
+    #[tokio::test]
+    async fn test_rapid_interrupts_share_one_request() {
+        let (transport, mut handle) = crate::transport::mock::MockTransport::pair();
+        let transport = Arc::new(Mutex::new(transport));
+        let mut query = Query::new(transport, true, None, None, HashMap::new());
+        query.start().await.unwrap();
+
+        // Answer every interrupt after a short delay, counting the requests
+        let sdk_control_tx = handle.sdk_control_tx.clone();
+        let (count_tx, mut count_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(request) = handle.outbound_control_request_rx.recv().await {
+                let _ = count_tx.send(());
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                let _ = sdk_control_tx
+                    .send(serde_json::json!({
+                        "type": "control_response",
+                        "response": {"subtype": "success", "request_id": request["request_id"]}
+                    }))
+                    .await;
+            }
+        });
+
+        let watcher_pending = query.pending_responses.clone();
+        let max_pending = tokio::spawn(async move {
+            let mut max = 0;
+            for _ in 0..20 {
+                max = max.max(watcher_pending.read().await.len());
+                tokio::time::sleep(Duration::from_millis(2)).await;
+            }
+            max
+        });
+
+        let results = futures::future::join_all((0..100).map(|_| query.interrupt())).await;
+        assert!(results.iter().all(|r| r.is_ok()));
+
+        assert!(max_pending.await.unwrap() <= 1);
+        assert!(query.pending_responses.read().await.is_empty());
+        assert!(count_rx.try_recv().is_ok());
+        assert!(
+            count_rx.try_recv().is_err(),
+            "interrupts were not coalesced"
+        );
+
+        // Once answered, the next interrupt is sent again
+        query.interrupt().await.unwrap();
+        assert!(count_rx.try_recv().is_ok());
+        assert!(count_rx.try_recv().is_err());
+    }
 }
@@ -11,6 +11,18 @@
 //! - **Type Safety**: Strongly typed messages and errors
 //! - **Flexible Configuration**: Extensive options for customization
 //!
+//! ## Feature Flags
+//!
+//! - `subprocess` (default): run the Claude CLI as a subprocess. Provides
+//!   `SubprocessTransport`, `query`, CLI discovery and the constructors that
+//!   spawn the CLI (`InteractiveClient::new`, `ClaudeSDKClient::new`).
+//!   Without it, clients are built from a custom [`Transport`](transport::Transport)
+//!   with `InteractiveClient::from_transport_with_options` or `ClaudeSDKClient::with_transport`.
+//! - `auto-download` (default): download the CLI when it is not installed.
+//! - `memory`: persistent memory backed by Meilisearch.
+//! - `websocket`: `WebSocketTransport`, for a CLI running behind a remote
+//!   gateway that speaks stream-json over a WebSocket.
+//!
 //! ## Quick Start
 //!
 //! ```rust,no_run
@@ -32,22 +44,37 @@
 #![warn(missing_docs)]
 #![warn(rustdoc::missing_crate_level_docs)]
 
+mod auto_responder;
 /// CLI download and management utilities
+#[cfg(feature = "subprocess")]
 pub mod cli_download;
+mod cli_features;
 mod client;
 // mod client_v2;  // Has compilation errors
 // mod client_final;  // Has compilation errors
+#[cfg(feature = "subprocess")]
 mod client_working;
+mod conversation_history;
+#[cfg(feature = "subprocess")]
+mod env_file;
 mod errors;
+mod fan_out;
 mod interactive;
 mod internal_query;
+mod log_sink;
 mod message_parser;
 pub mod model_recommendation;
+#[cfg(feature = "subprocess")]
 mod optimized_client;
 mod perf_utils;
+#[cfg(feature = "subprocess")]
 mod query;
 mod sdk_mcp;
+mod subagents;
+mod text_deltas;
 pub mod token_tracker;
+mod tool_use_guard;
+mod transcript;
 pub mod transport;
 mod types;
 
@@ -55,21 +82,44 @@ mod types;
 pub mod memory;
 
 // Re-export main types and functions
+pub use auto_responder::{AutoResponder, AutoResponseHandler};
+pub use cli_features::{CliFeatures, SemVer};
 pub use client::ClaudeSDKClient;
 // pub use client_v2::ClaudeSDKClientV2;  // Has compilation errors
 // pub use client_final::ClaudeSDKClientFinal;  // Has compilation errors
+#[cfg(feature = "subprocess")]
 pub use client_working::ClaudeSDKClientWorking;
+pub use conversation_history::{ConversationHistory, DEFAULT_HISTORY_LIMIT, Turn};
 pub use errors::{Result, SdkError};
-pub use interactive::InteractiveClient;
+pub use fan_out::{MessageFilter, Route, fan_out};
+pub use interactive::{InteractiveClient, InterruptOutcome, InterruptResult};
 pub use interactive::{build_hook_response_json, dispatch_hook_from_registry, is_hook_callback};
 pub use internal_query::Query;
-pub use query::query;
+pub use log_sink::{DiagnosticEvent, LogSink};
+pub use message_parser::MessageAggregator;
+#[cfg(feature = "subprocess")]
+pub use query::{
+    CacheUsage, QueryHandle, QueryOutcome, query, query_collect, query_interruptible,
+    query_structured, query_with_retry, warmup,
+};
 // Keep the old name as an alias for backward compatibility
 pub use interactive::InteractiveClient as SimpleInteractiveClient;
 pub use model_recommendation::ModelRecommendation;
+#[cfg(feature = "subprocess")]
 pub use optimized_client::{ClientMode, OptimizedClient};
-pub use perf_utils::{MessageBatcher, PerformanceMetrics, RetryConfig};
-pub use token_tracker::{BudgetLimit, BudgetManager, BudgetStatus, TokenUsageTracker};
+pub use perf_utils::{
+    DEFAULT_TOKEN_RATE_WINDOW, MAX_PHASE_SAMPLES, MessageBatcher, PerformanceMetrics,
+    PhaseLatencies, RetryConfig, TokenRateMeter, TurnPhase,
+};
+pub use subagents::{MessageGroups, SubagentInfo, SubagentMessages, SubagentTracker};
+pub use text_deltas::{TextDeltaTracker, text_deltas};
+pub use token_tracker::{
+    BudgetLimit, BudgetManager, BudgetStatus, ContextMonitor, ContextUsage,
+    DEFAULT_EXPECTED_OUTPUT_TOKENS, InputRate, OutputRate, PricingTable, TokenUsageTracker,
+    estimate_cost, estimate_cost_with, estimate_tokens,
+};
+pub use tool_use_guard::ToolUseGuard;
+pub use transcript::{CompactionResult, CompactionTracker, Transcript, TranscriptEntry};
 /// Default interactive client - the recommended client for interactive use
 pub type ClaudeSDKClientDefault = InteractiveClient;
 pub use types::{
@@ -81,11 +131,13 @@ pub use types::{
     BaseHookInput,
     CanUseTool,
     ClaudeCodeOptions,
+    ClaudeCodeOptionsSnapshot,
     ContentBlock,
     ContentValue,
     ControlProtocolFormat,
     ControlRequest,
     ControlResponse,
+    DuplicateToolUsePolicy,
     // Hook types (v0.3.0 - strongly-typed hooks)
     HookCallback,
     HookContext,
@@ -94,14 +146,20 @@ pub use types::{
     HookJSONOutput,
     HookMatcher,
     HookSpecificOutput,
+    InputFormat,
     McpServerConfig,
     Message,
+    MessageSource,
+    ModelInfo,
+    NotificationHookInput,
     // Permission types
     PermissionBehavior,
+    PermissionDenial,
     PermissionMode,
     PermissionResult,
     PermissionResultAllow,
     PermissionResultDeny,
+    PermissionRuleMatcher,
     PermissionRuleValue,
     PermissionUpdate,
     PermissionUpdateDestination,
@@ -111,7 +169,12 @@ pub use types::{
     PreCompactHookInput,
     PreToolUseHookInput,
     PreToolUseHookSpecificOutput,
+    REQUEST_ID_ENV_VAR,
+    ReconnectBackoff,
+    ResourceLimits,
     ResultMessage,
+    ResultPolicy,
+    RuleBasedPermissions,
     // SDK Control Protocol types
     SDKControlInitializeRequest,
     SDKControlInterruptRequest,
@@ -126,6 +189,7 @@ pub use types::{
     SandboxSettings,
     SdkBeta,
     SdkPluginConfig,
+    SessionStartHookInput,
     SessionStartHookSpecificOutput,
     // Phase 2 enhancements
     SettingSource,
@@ -139,8 +203,11 @@ pub use types::{
     SystemPrompt,
     TextContent,
     ThinkingContent,
+    TokenUsage,
     ToolPermissionContext,
     ToolResultContent,
+    ToolResultStatus,
+    ToolUseCallback,
     ToolUseContent,
     // Phase 3 enhancements (Python SDK v0.1.12+ sync)
     ToolsConfig,
@@ -161,12 +228,22 @@ pub type ClaudeAgentOptionsBuilder = ClaudeCodeOptionsBuilder;
 pub use types::ClaudeCodeOptionsBuilder;
 
 // Re-export transport types for convenience
+#[cfg(feature = "subprocess")]
 pub use transport::SubprocessTransport;
-pub use transport::subprocess::{SemVer, find_claude_cli, get_cli_version};
+#[cfg(feature = "subprocess")]
+pub use transport::subprocess::{available_models, find_claude_cli, get_cli_version};
+pub use transport::{ControlDirection, ControlLog, ControlLogEntry, RecordingTransport};
+#[cfg(feature = "websocket")]
+pub use transport::{WebSocketReconnectPolicy, WebSocketTransport, WebSocketTransportBuilder};
 
 // Re-export CLI download utilities
+#[cfg(feature = "auto-download")]
+pub use cli_download::check_latest_npm_version;
+#[cfg(feature = "subprocess")]
 pub use cli_download::{
-    check_latest_npm_version, download_cli, get_cached_cli_path, is_cli_cached,
+    DownloadConfig, download_cli, download_cli_with_config, download_cli_with_sha256,
+    get_cached_cli_path, is_cli_cached, platform_key, verify_cached_cli,
+    verify_cached_cli_with_config,
 };
 
 // Re-export SDK MCP types
@@ -176,9 +253,48 @@ pub use sdk_mcp::{
 };
 
 /// Prelude module for convenient imports
+///
+/// Covers the entry points and message types most applications need,
+/// including `futures::StreamExt` for consuming response streams.
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "subprocess")]
+/// # mod example {
+/// use nexus_claude::prelude::*;
+///
+/// # async fn run() -> Result<()> {
+/// let options = ClaudeCodeOptions::builder()
+///     .permission_mode(PermissionMode::AcceptEdits)
+///     .build();
+///
+/// // One-shot query
+/// let mut messages = query("What is 2 + 2?", Some(options.clone())).await?;
+/// while let Some(message) = messages.next().await {
+///     if let Message::Assistant { message, .. } = message? {
+///         println!("{}", message.text());
+///     }
+/// }
+///
+/// // Multi-turn conversation
+/// let mut client = InteractiveClient::new(options)?;
+/// client.connect().await?;
+/// for message in client.send_and_receive("Now double it".into()).await? {
+///     if let Message::Result { total_cost_usd, .. } = message {
+///         println!("cost: {total_cost_usd:?}");
+///     }
+/// }
+/// client.disconnect().await?;
+/// # Ok(())
+/// # }
+/// # }
+/// ```
 pub mod prelude {
     pub use crate::{
-        ClaudeCodeOptions, ClaudeSDKClient, ClaudeSDKClientWorking, Message, PermissionMode,
-        Result, SdkError, query,
+        AssistantMessage, ClaudeCodeOptions, ClaudeCodeOptionsBuilder, ClaudeSDKClient,
+        ContentBlock, InteractiveClient, Message, PermissionMode, Result, ResultMessage, SdkError,
+        text_deltas,
     };
+    #[cfg(feature = "subprocess")]
+    pub use crate::{ClaudeSDKClientWorking, QueryHandle, query, query_interruptible};
+    pub use futures::StreamExt;
 }
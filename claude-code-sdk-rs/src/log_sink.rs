@@ -0,0 +1,69 @@
+//! Pluggable diagnostics sink
+//!
+//! The SDK reports diagnostics through `tracing`. Applications that log through
+//! something else can set [`ClaudeCodeOptions::log_sink`](crate::ClaudeCodeOptions)
+//! to also receive the key events as structured values; `tracing` output is
+//! unaffected.
+
+use std::path::PathBuf;
+
+/// Structured diagnostic event reported by the subprocess transport
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticEvent {
+    /// The CLI subprocess was spawned
+    Spawned {
+        /// Path of the CLI binary
+        cli_path: PathBuf,
+        /// Process ID, if still available
+        pid: Option<u32>,
+    },
+    /// The transport is connected and ready to exchange messages
+    Connected,
+    /// A line of CLI output could not be parsed and was dropped
+    ParseError {
+        /// Description of the parse failure
+        error: String,
+        /// The offending output line
+        line: String,
+    },
+    /// An interrupt request was sent to the CLI
+    InterruptSent {
+        /// ID of the interrupt control request
+        request_id: String,
+    },
+    /// The CLI finished a turn and reported its cost
+    Cost {
+        /// Session the turn belongs to
+        session_id: String,
+        /// Total cost of the session so far, if reported
+        total_cost_usd: Option<f64>,
+        /// Wall-clock duration of the turn
+        duration_ms: i64,
+    },
+    /// A configured MCP server failed to start or connect
+    McpServerFailed {
+        /// Name of the server
+        name: String,
+        /// The error or status the CLI reported
+        detail: String,
+    },
+}
+
+/// Receiver for [`DiagnosticEvent`]s
+///
+/// Called synchronously from the transport's I/O tasks, so implementations
+/// should hand events off quickly. Any `Fn(&DiagnosticEvent)` closure is a
+/// `LogSink`.
+pub trait LogSink: Send + Sync {
+    /// Handle one diagnostic event
+    fn log(&self, event: &DiagnosticEvent);
+}
+
+impl<F> LogSink for F
+where
+    F: Fn(&DiagnosticEvent) + Send + Sync,
+{
+    fn log(&self, event: &DiagnosticEvent) {
+        self(event)
+    }
+}
@@ -6,14 +6,19 @@
 use crate::{
     errors::{Result, SdkError},
     types::{
-        AssistantMessage, ContentBlock, ContentValue, Message, StreamDelta, StreamEventData,
-        TextContent, ThinkingContent, ToolResultContent, ToolUseContent, UserMessage,
+        AssistantMessage, ContentBlock, ContentValue, HookInput, Message, StreamDelta,
+        StreamEventData, TextContent, ThinkingContent, ToolResultContent, ToolUseContent,
+        UserMessage,
     },
 };
+use futures::stream::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
 use tracing::{debug, trace};
 
 /// Parse a JSON value into a Message
+#[cfg_attr(not(feature = "subprocess"), allow(dead_code))] // only the subprocess paths parse CLI output
 pub fn parse_message(json: Value) -> Result<Option<Message>> {
     // Get message type
     let msg_type = json
@@ -50,8 +55,10 @@ fn parse_user_message(json: Value) -> Result<Option<Message>> {
         } else if let Some(content_array) = message.get("content").and_then(|v| v.as_array()) {
             // Array content — parse each item as a content block (tool_result, text, etc.)
             let mut blocks = Vec::new();
-            for item in content_array {
-                if let Some(block) = parse_content_block(item)? {
+            for (index, item) in content_array.iter().enumerate() {
+                let block = parse_content_block(item)
+                    .map_err(|e| e.within(&format!("message.content[{index}]")))?;
+                if let Some(block) = block {
                     blocks.push(block);
                 }
             }
@@ -101,8 +108,10 @@ fn parse_assistant_message(json: Value) -> Result<Option<Message>> {
 
     let mut content_blocks = Vec::new();
 
-    for content_item in content_array {
-        if let Some(block) = parse_content_block(content_item)? {
+    for (index, content_item) in content_array.iter().enumerate() {
+        let block = parse_content_block(content_item)
+            .map_err(|e| e.within(&format!("message.content[{index}]")))?;
+        if let Some(block) = block {
             content_blocks.push(block);
         }
     }
@@ -127,7 +136,11 @@ fn parse_content_block(json: &Value) -> Result<Option<ContentBlock>> {
         match block_type {
             "text" => {
                 let text = json.get("text").and_then(|v| v.as_str()).ok_or_else(|| {
-                    SdkError::parse_error("Missing 'text' field in text block", json.to_string())
+                    SdkError::parse_error_at(
+                        "text",
+                        "Missing 'text' field in text block",
+                        json.to_string(),
+                    )
                 })?;
                 Ok(Some(ContentBlock::Text(TextContent {
                     text: text.to_string(),
@@ -138,7 +151,8 @@ fn parse_content_block(json: &Value) -> Result<Option<ContentBlock>> {
                     .get("thinking")
                     .and_then(|v| v.as_str())
                     .ok_or_else(|| {
-                        SdkError::parse_error(
+                        SdkError::parse_error_at(
+                            "thinking",
                             "Missing 'thinking' field in thinking block",
                             json.to_string(),
                         )
@@ -147,7 +161,8 @@ fn parse_content_block(json: &Value) -> Result<Option<ContentBlock>> {
                     json.get("signature")
                         .and_then(|v| v.as_str())
                         .ok_or_else(|| {
-                            SdkError::parse_error(
+                            SdkError::parse_error_at(
+                                "signature",
                                 "Missing 'signature' field in thinking block",
                                 json.to_string(),
                             )
@@ -159,10 +174,15 @@ fn parse_content_block(json: &Value) -> Result<Option<ContentBlock>> {
             },
             "tool_use" => {
                 let id = json.get("id").and_then(|v| v.as_str()).ok_or_else(|| {
-                    SdkError::parse_error("Missing 'id' field in tool_use block", json.to_string())
+                    SdkError::parse_error_at(
+                        "id",
+                        "Missing 'id' field in tool_use block",
+                        json.to_string(),
+                    )
                 })?;
                 let name = json.get("name").and_then(|v| v.as_str()).ok_or_else(|| {
-                    SdkError::parse_error(
+                    SdkError::parse_error_at(
+                        "name",
                         "Missing 'name' field in tool_use block",
                         json.to_string(),
                     )
@@ -183,7 +203,8 @@ fn parse_content_block(json: &Value) -> Result<Option<ContentBlock>> {
                     .get("tool_use_id")
                     .and_then(|v| v.as_str())
                     .ok_or_else(|| {
-                        SdkError::parse_error(
+                        SdkError::parse_error_at(
+                            "tool_use_id",
                             "Missing 'tool_use_id' field in tool_result block",
                             json.to_string(),
                         )
@@ -267,9 +288,14 @@ fn parse_system_message(json: Value) -> Result<Option<Message>> {
 /// Parse a result message
 fn parse_result_message(json: Value) -> Result<Option<Message>> {
     // Use serde to parse the full result message
-    match serde_json::from_value::<Message>(json.clone()) {
+    match serde_path_to_error::deserialize::<_, Message>(json.clone()) {
         Ok(msg) => Ok(Some(msg)),
-        Err(_e) => {
+        Err(e) => {
+            debug!(
+                "Result message failed to deserialize at `{}` ({}), using fallback",
+                e.path(),
+                e.inner()
+            );
             // Fallback: create a minimal result message
             let subtype = json
                 .get("subtype")
@@ -302,7 +328,16 @@ fn parse_result_message(json: Value) -> Result<Option<Message>> {
                 num_turns: json.get("num_turns").and_then(|v| v.as_i64()).unwrap_or(0) as i32,
                 session_id,
                 total_cost_usd: json.get("total_cost_usd").and_then(|v| v.as_f64()),
-                usage: json.get("usage").cloned(),
+                // Malformed usage must not cost the turn its Result message
+                usage: json.get("usage").filter(|v| !v.is_null()).and_then(|v| {
+                    match serde_json::from_value(v.clone()) {
+                        Ok(usage) => Some(usage),
+                        Err(e) => {
+                            debug!("Ignoring malformed result usage: {}", e);
+                            None
+                        },
+                    }
+                }),
                 result: json
                     .get("result")
                     .and_then(|v| v.as_str())
@@ -311,11 +346,89 @@ fn parse_result_message(json: Value) -> Result<Option<Message>> {
                     .get("structured_output")
                     .or_else(|| json.get("structuredOutput"))
                     .and_then(|v| (!v.is_null()).then(|| v.clone())),
+                parent_tool_use_id: json
+                    .get("parent_tool_use_id")
+                    .and_then(|v| v.as_str())
+                    .map(String::from),
+                permission_denials: json
+                    .get("permission_denials")
+                    .and_then(|v| serde_json::from_value(v.clone()).ok())
+                    .unwrap_or_default(),
             }))
         },
     }
 }
 
+/// Parse the input of a hook callback into a strongly-typed [`HookInput`]
+///
+/// Deserializing the internally tagged enum directly buffers the payload and
+/// loses the position of a bad field, so the variant is selected from
+/// `hook_event_name` first and its struct deserialized with path tracking.
+pub(crate) fn parse_hook_input(input: &Value) -> Result<HookInput> {
+    fn variant<T: DeserializeOwned>(input: &Value, wrap: fn(T) -> HookInput) -> Result<HookInput> {
+        serde_path_to_error::deserialize(input.clone())
+            .map(wrap)
+            .map_err(|e| SdkError::from_path_error("Invalid hook input", e, input.to_string()))
+    }
+
+    match input.get("hook_event_name").and_then(|v| v.as_str()) {
+        Some("PreToolUse") => variant(input, HookInput::PreToolUse),
+        Some("PostToolUse") => variant(input, HookInput::PostToolUse),
+        Some("UserPromptSubmit") => variant(input, HookInput::UserPromptSubmit),
+        Some("Stop") => variant(input, HookInput::Stop),
+        Some("SubagentStop") => variant(input, HookInput::SubagentStop),
+        Some("PreCompact") => variant(input, HookInput::PreCompact),
+        Some("SessionStart") => variant(input, HookInput::SessionStart),
+        Some("Notification") => variant(input, HookInput::Notification),
+        // Missing or unknown tag: let serde report it
+        _ => variant(input, std::convert::identity),
+    }
+}
+
+/// Result subtype of a turn whose answer kept failing the `--json-schema` check
+const STRUCTURED_OUTPUT_RETRIES_SUBTYPE: &str = "error_max_structured_output_retries";
+
+/// Deserialize the `structured_output` of a `Result` message into `T`
+///
+/// Shared by `query_structured` and `InteractiveClient::receive_structured`.
+pub(crate) fn parse_structured_output<T: DeserializeOwned>(message: &Message) -> Result<T> {
+    let Message::Result {
+        is_error,
+        subtype,
+        structured_output,
+        ..
+    } = message
+    else {
+        return Err(SdkError::invalid_state(
+            "structured output is only carried by Result messages",
+        ));
+    };
+    if subtype == STRUCTURED_OUTPUT_RETRIES_SUBTYPE {
+        return Err(SdkError::cli_error(
+            "Structured output did not match the output_format schema",
+            Some(subtype.clone()),
+        ));
+    }
+    if *is_error {
+        return Err(SdkError::cli_error(format!("Turn failed: {subtype}"), None));
+    }
+    let Some(output) = structured_output else {
+        return Err(SdkError::parse_error_at(
+            "structured_output",
+            "Result message has no structured output; was output_format set?",
+            serde_json::to_string(message).unwrap_or_default(),
+        ));
+    };
+    serde_path_to_error::deserialize(output).map_err(|err| {
+        SdkError::from_path_error(
+            "Structured output does not match the requested type",
+            err,
+            output.to_string(),
+        )
+        .within("structured_output")
+    })
+}
+
 /// Parse a stream event message (for real-time token streaming)
 fn parse_stream_event(json: Value) -> Result<Option<Message>> {
     let event = json.get("event").ok_or_else(|| {
@@ -422,9 +535,285 @@ fn parse_stream_event(json: Value) -> Result<Option<Message>> {
     }))
 }
 
+/// A content block whose stream events are still arriving
+#[derive(Debug)]
+enum PendingBlock {
+    Text(String),
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        /// Input from `content_block_start`, used when no JSON delta follows
+        input: Value,
+        partial_json: String,
+    },
+}
+
+impl PendingBlock {
+    fn start(content_block: &Value) -> Option<Self> {
+        let str_field = |key: &str| {
+            content_block
+                .get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        match content_block.get("type").and_then(|v| v.as_str())? {
+            "text" => Some(Self::Text(str_field("text"))),
+            "thinking" => Some(Self::Thinking {
+                thinking: str_field("thinking"),
+                signature: str_field("signature"),
+            }),
+            "tool_use" => Some(Self::ToolUse {
+                id: str_field("id"),
+                name: str_field("name"),
+                input: content_block
+                    .get("input")
+                    .cloned()
+                    .unwrap_or_else(|| Value::Object(Default::default())),
+                partial_json: String::new(),
+            }),
+            other => {
+                debug!("Not aggregating content block of type {}", other);
+                None
+            },
+        }
+    }
+
+    fn push(&mut self, delta: &StreamDelta) {
+        match (self, delta) {
+            (Self::Text(text), StreamDelta::TextDelta { text: more }) => text.push_str(more),
+            (Self::Thinking { thinking, .. }, StreamDelta::ThinkingDelta { thinking: more }) => {
+                thinking.push_str(more)
+            },
+            (
+                Self::ToolUse { partial_json, .. },
+                StreamDelta::InputJsonDelta { partial_json: more },
+            ) => partial_json.push_str(more),
+            (block, delta) => trace!("Ignoring {:?} for pending {:?}", delta, block),
+        }
+    }
+
+    /// Assemble the block, failing if the tool input is not valid JSON
+    fn finish(self) -> Result<ContentBlock> {
+        match self {
+            Self::Text(text) => Ok(ContentBlock::Text(TextContent { text })),
+            Self::Thinking {
+                thinking,
+                signature,
+            } => Ok(ContentBlock::Thinking(ThinkingContent {
+                thinking,
+                signature,
+            })),
+            Self::ToolUse {
+                id,
+                name,
+                input,
+                partial_json,
+            } => {
+                if partial_json.trim().is_empty() {
+                    return Ok(ContentBlock::ToolUse(ToolUseContent { id, name, input }));
+                }
+                match serde_json::from_str(&partial_json) {
+                    Ok(input) => Ok(ContentBlock::ToolUse(ToolUseContent { id, name, input })),
+                    Err(e) => Err(SdkError::IncompleteContentBlock {
+                        partial: Box::new(ContentBlock::ToolUse(ToolUseContent {
+                            id,
+                            name,
+                            input: Value::String(partial_json),
+                        })),
+                        reason: format!("tool input is not valid JSON: {e}"),
+                    }),
+                }
+            },
+        }
+    }
+
+    /// Flush the block after its stream was cut off by a `Result`
+    fn abandon(self) -> SdkError {
+        let reason = "result arrived before the content block finished".to_string();
+        match self.finish() {
+            Ok(block) => SdkError::IncompleteContentBlock {
+                partial: Box::new(block),
+                reason,
+            },
+            Err(SdkError::IncompleteContentBlock { partial, .. }) => {
+                SdkError::IncompleteContentBlock { partial, reason }
+            },
+            Err(e) => e,
+        }
+    }
+}
+
+/// Reassembles streamed content into complete content blocks
+///
+/// With `include_partial_messages` the CLI streams each assistant content
+/// block as `stream_event` fragments before sending the whole assistant
+/// message. The aggregator merges text and thinking deltas and accumulates
+/// tool_use input JSON until the block's `content_block_stop`, so consumers
+/// only ever see complete blocks:
+///
+/// - Once stream events have been seen in a turn, the whole assistant
+///   messages that follow them are skipped, so no block is yielded twice.
+///   Without partial messages, assistant blocks are yielded as they arrive.
+/// - Tool results from user messages are yielded as they arrive.
+/// - A `Result` that arrives while blocks are still open flushes each of them
+///   as `SdkError::IncompleteContentBlock` carrying what was assembled, with
+///   unparsable tool input kept as a JSON string.
+///
+/// Subagent (sidechain) blocks are tracked separately from the main agent's.
+#[derive(Debug, Default)]
+pub struct MessageAggregator {
+    /// Open blocks by parent tool use ID and block index
+    pending: BTreeMap<(Option<String>, usize), PendingBlock>,
+    /// Agents that streamed content in the current turn
+    streamed: HashSet<Option<String>>,
+}
+
+impl MessageAggregator {
+    /// Create a new aggregator
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a message and return the content blocks it completes, if any
+    pub fn observe(&mut self, message: &Message) -> Vec<Result<ContentBlock>> {
+        match message {
+            Message::StreamEvent {
+                event,
+                parent_tool_use_id,
+                ..
+            } => {
+                self.streamed.insert(parent_tool_use_id.clone());
+                self.observe_event(parent_tool_use_id, event)
+            },
+            Message::Assistant {
+                message,
+                parent_tool_use_id,
+            } => {
+                if self.streamed.contains(parent_tool_use_id) {
+                    Vec::new()
+                } else {
+                    message.content.iter().cloned().map(Ok).collect()
+                }
+            },
+            Message::User { message, .. } => message
+                .content_blocks
+                .iter()
+                .flatten()
+                .cloned()
+                .map(Ok)
+                .collect(),
+            Message::Result {
+                parent_tool_use_id, ..
+            } => self.flush(parent_tool_use_id.as_deref()),
+            Message::System { .. } => Vec::new(),
+        }
+    }
+
+    fn observe_event(
+        &mut self,
+        parent_tool_use_id: &Option<String>,
+        event: &StreamEventData,
+    ) -> Vec<Result<ContentBlock>> {
+        match event {
+            StreamEventData::ContentBlockStart {
+                index,
+                content_block,
+            } => {
+                if let Some(block) = PendingBlock::start(content_block) {
+                    self.pending
+                        .insert((parent_tool_use_id.clone(), *index), block);
+                }
+                Vec::new()
+            },
+            StreamEventData::ContentBlockDelta { index, delta } => {
+                if let Some(block) = self.pending.get_mut(&(parent_tool_use_id.clone(), *index)) {
+                    block.push(delta);
+                }
+                Vec::new()
+            },
+            StreamEventData::ContentBlockStop { index } => self
+                .pending
+                .remove(&(parent_tool_use_id.clone(), *index))
+                .map(|block| vec![block.finish()])
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// End a turn: the main agent's result ends every open block, a
+    /// subagent's only its own
+    fn flush(&mut self, parent_tool_use_id: Option<&str>) -> Vec<Result<ContentBlock>> {
+        let keys: Vec<_> = self
+            .pending
+            .keys()
+            .filter(|(parent, _)| {
+                parent_tool_use_id.is_none() || parent.as_deref() == parent_tool_use_id
+            })
+            .cloned()
+            .collect();
+        match parent_tool_use_id {
+            None => self.streamed.clear(),
+            Some(id) => {
+                self.streamed.remove(&Some(id.to_string()));
+            },
+        }
+        keys.into_iter()
+            .filter_map(|key| self.pending.remove(&key))
+            .map(|block| Err(block.abandon()))
+            .collect()
+    }
+
+    /// Adapt a message stream into a stream of complete content blocks
+    ///
+    /// Errors from the underlying stream are passed through unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use nexus_claude::{ClaudeCodeOptions, ContentBlock, InteractiveClient, MessageAggregator};
+    /// # use futures::StreamExt;
+    /// # async fn example() -> nexus_claude::Result<()> {
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .include_partial_messages(true)
+    ///     .build();
+    /// let mut client = InteractiveClient::new(options)?;
+    /// client.connect().await?;
+    /// client.send_message("List the files here".to_string()).await?;
+    ///
+    /// let stream = client.receive_response_stream().await;
+    /// let mut blocks = Box::pin(MessageAggregator::aggregate(stream));
+    /// while let Some(block) = blocks.next().await {
+    ///     if let ContentBlock::ToolUse(tool_use) = block? {
+    ///         println!("{} {}", tool_use.name, tool_use.input);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn aggregate<S>(messages: S) -> impl Stream<Item = Result<ContentBlock>>
+    where
+        S: Stream<Item = Result<Message>>,
+    {
+        let mut aggregator = Self::new();
+        messages.flat_map(move |item| {
+            let blocks = match item {
+                Ok(message) => aggregator.observe(&message),
+                Err(e) => vec![Err(e)],
+            };
+            futures::stream::iter(blocks)
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::ResultPolicy;
     use serde_json::json;
 
     #[test]
@@ -583,6 +972,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_subagent_result_message() {
+        let json = json!({
+            "type": "result",
+            "subtype": "success",
+            "is_error": false,
+            "session_id": "test_session",
+            "parent_tool_use_id": "toolu_task"
+        });
+
+        let message = parse_message(json).unwrap().unwrap();
+        assert_eq!(message.parent_tool_use_id(), Some("toolu_task"));
+        assert!(!ResultPolicy::TopLevel.ends_turn(&message));
+        assert!(ResultPolicy::First.ends_turn(&message));
+    }
+
     #[test]
     fn test_parse_result_message_structured_output_alias() {
         let json = json!({
@@ -732,6 +1137,8 @@ mod tests {
             usage: None,
             result: None,
             structured_output: None,
+            parent_tool_use_id: None,
+            permission_denials: vec![],
         };
         assert!(!result.is_sidechain());
         assert!(result.is_top_level());
@@ -1157,6 +1564,90 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_result_typed_usage_keeps_unknown_keys() {
+        let usage = json!({
+            "input_tokens": 12,
+            "output_tokens": 34,
+            "cache_creation_input_tokens": 2048,
+            "cache_read_input_tokens": 0,
+            "server_tool_use": {"web_search_requests": 1},
+            "service_tier": "standard"
+        });
+        let json = json!({
+            "type": "result",
+            "subtype": "success",
+            "duration_ms": 1,
+            "duration_api_ms": 1,
+            "is_error": false,
+            "num_turns": 1,
+            "session_id": "s",
+            "usage": usage
+        });
+
+        let message = parse_message(json).unwrap().unwrap();
+        let Message::Result {
+            usage: Some(ref typed),
+            ..
+        } = message
+        else {
+            panic!("Expected Result message with usage");
+        };
+        assert_eq!(typed.input_tokens, 12);
+        assert_eq!(typed.output_tokens, 34);
+        assert_eq!(typed.cache_creation_input_tokens, Some(2048));
+        assert_eq!(typed.cache_read_input_tokens, Some(0));
+        assert_eq!(typed.extra["service_tier"], "standard");
+        assert_eq!(typed.total_tokens(), 12 + 34 + 2048);
+        assert_eq!(message.usage_raw(), Some(usage));
+    }
+
+    #[test]
+    fn test_parse_result_ignores_malformed_usage() {
+        let usage = json!({"input_tokens": "many", "output_tokens": 34});
+        let json = json!({
+            "type": "result",
+            "subtype": "success",
+            "duration_ms": 1,
+            "duration_api_ms": 1,
+            "is_error": false,
+            "num_turns": 1,
+            "session_id": "s",
+            "usage": usage
+        });
+
+        let message = parse_message(json).unwrap().unwrap();
+        assert!(matches!(
+            message,
+            Message::Result {
+                usage: None,
+                num_turns: 1,
+                ..
+            }
+        ));
+        assert_eq!(message.usage_raw(), None);
+    }
+
+    #[test]
+    fn test_parsed_result_matches_deserialized_result() {
+        let usage = json!({"input_tokens": 12, "output_tokens": 34, "service_tier": "standard"});
+        let json = json!({
+            "type": "result",
+            "subtype": "success",
+            "duration_ms": 1,
+            "duration_api_ms": 1,
+            "is_error": false,
+            "num_turns": 1,
+            "session_id": "s",
+            "usage": usage
+        });
+
+        let parsed = parse_message(json.clone()).unwrap().unwrap();
+        let deserialized: Message = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, deserialized);
+        assert_eq!(deserialized.usage_raw(), Some(usage));
+    }
+
     #[test]
     fn test_parse_result_message_fallback_with_minimal_fields() {
         // Force fallback by providing num_turns as a string (serde will reject it)
@@ -1478,4 +1969,213 @@ mod tests {
             panic!("Expected StreamEvent");
         }
     }
+
+    fn parse_path(err: &SdkError) -> Option<&str> {
+        match err {
+            SdkError::MessageParseError { path, .. } => path.as_deref(),
+            other => panic!("expected MessageParseError, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_error_reports_nested_content_path() {
+        let json = json!({
+            "type": "assistant",
+            "message": {
+                "content": [
+                    {"type": "text", "text": "Let me check"},
+                    {"type": "tool_use", "id": "toolu_1", "input": {}}
+                ]
+            }
+        });
+
+        let err = parse_message(json).unwrap_err();
+        assert_eq!(parse_path(&err), Some("message.content[1].name"));
+        assert!(
+            err.to_string().contains("at `message.content[1].name`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_parse_hook_input_reports_field_path() {
+        let input = json!({
+            "hook_event_name": "PreToolUse",
+            "session_id": "sess",
+            "transcript_path": "/tmp/t.jsonl",
+            "cwd": "/tmp",
+            "tool_name": 42,
+            "tool_input": {}
+        });
+
+        let err = parse_hook_input(&input).unwrap_err();
+        assert_eq!(parse_path(&err), Some("tool_name"));
+        assert!(err.to_string().contains("Invalid hook input"), "{err}");
+
+        let valid = json!({
+            "hook_event_name": "Stop",
+            "session_id": "sess",
+            "transcript_path": "/tmp/t.jsonl",
+            "cwd": "/tmp",
+            "stop_hook_active": false
+        });
+        assert!(matches!(parse_hook_input(&valid), Ok(HookInput::Stop(_))));
+    }
+
+    #[test]
+    fn test_parse_hook_input_session_start_and_notification() {
+        let session_start = json!({
+            "hook_event_name": "SessionStart",
+            "session_id": "sess",
+            "transcript_path": "/tmp/t.jsonl",
+            "cwd": "/tmp",
+            "source": "startup"
+        });
+        assert!(matches!(
+            parse_hook_input(&session_start),
+            Ok(HookInput::SessionStart(input)) if input.source == "startup"
+        ));
+
+        let notification = json!({
+            "hook_event_name": "Notification",
+            "session_id": "sess",
+            "transcript_path": "/tmp/t.jsonl",
+            "cwd": "/tmp",
+            "message": "Claude is waiting for your input"
+        });
+        assert!(matches!(
+            parse_hook_input(&notification),
+            Ok(HookInput::Notification(input)) if input.message == "Claude is waiting for your input"
+        ));
+    }
+
+    #[test]
+    fn test_parse_hook_input_unknown_event() {
+        let err = parse_hook_input(&json!({"hook_event_name": "Nope"})).unwrap_err();
+        assert_eq!(parse_path(&err), Some("hook_event_name"));
+        assert!(err.to_string().contains("Nope"), "{err}");
+    }
+
+    fn stream_event(event: Value) -> Message {
+        parse_message(json!({"type": "stream_event", "session_id": "s", "event": event}))
+            .unwrap()
+            .unwrap()
+    }
+
+    fn result_message() -> Message {
+        parse_message(json!({
+            "type": "result", "subtype": "success", "duration_ms": 1,
+            "duration_api_ms": 1, "is_error": false, "num_turns": 1,
+            "session_id": "s"
+        }))
+        .unwrap()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_assembles_streamed_blocks() {
+        let tool_use = json!({"type": "tool_use", "id": "toolu_1", "name": "Bash", "input": {}});
+        let messages = vec![
+            stream_event(json!({"type": "message_start", "message": {}})),
+            stream_event(json!({"type": "content_block_start", "index": 0,
+                "content_block": {"type": "text", "text": ""}})),
+            stream_event(json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": "Let me "}})),
+            stream_event(json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": "check."}})),
+            stream_event(json!({"type": "content_block_stop", "index": 0})),
+            stream_event(json!({"type": "content_block_start", "index": 1,
+                "content_block": tool_use})),
+            stream_event(json!({"type": "content_block_delta", "index": 1,
+                "delta": {"type": "input_json_delta", "partial_json": "{\"command\": "}})),
+            stream_event(json!({"type": "content_block_delta", "index": 1,
+                "delta": {"type": "input_json_delta", "partial_json": "\"ls\"}"}})),
+            stream_event(json!({"type": "content_block_stop", "index": 1})),
+            // The whole message repeats the streamed blocks
+            parse_message(json!({"type": "assistant", "message": {"content": [
+                {"type": "text", "text": "Let me check."}, tool_use
+            ]}}))
+            .unwrap()
+            .unwrap(),
+            parse_message(json!({"type": "user", "message": {"content": [
+                {"type": "tool_result", "tool_use_id": "toolu_1", "content": "a.txt"}
+            ]}}))
+            .unwrap()
+            .unwrap(),
+            result_message(),
+        ];
+
+        let blocks: Vec<_> =
+            MessageAggregator::aggregate(futures::stream::iter(messages.into_iter().map(Ok)))
+                .collect()
+                .await;
+        let blocks: Vec<ContentBlock> = blocks.into_iter().map(|b| b.unwrap()).collect();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(
+            blocks[0],
+            ContentBlock::Text(TextContent {
+                text: "Let me check.".into()
+            })
+        );
+        assert_eq!(
+            blocks[1],
+            ContentBlock::ToolUse(ToolUseContent {
+                id: "toolu_1".into(),
+                name: "Bash".into(),
+                input: json!({"command": "ls"}),
+            })
+        );
+        assert!(
+            matches!(&blocks[2], ContentBlock::ToolResult(result) if result.tool_use_id == "toolu_1")
+        );
+    }
+
+    #[test]
+    fn test_aggregator_passes_whole_messages_without_streaming() {
+        let mut aggregator = MessageAggregator::new();
+        let assistant = parse_message(json!({"type": "assistant", "message": {"content": [
+            {"type": "text", "text": "Hi"}
+        ]}}))
+        .unwrap()
+        .unwrap();
+
+        let blocks = aggregator.observe(&assistant);
+        assert_eq!(blocks.len(), 1);
+        assert!(matches!(&blocks[0], Ok(ContentBlock::Text(text)) if text.text == "Hi"));
+        assert!(aggregator.observe(&result_message()).is_empty());
+    }
+
+    #[test]
+    fn test_aggregator_flushes_open_tool_use_on_result() {
+        let mut aggregator = MessageAggregator::new();
+        for event in [
+            json!({"type": "content_block_start", "index": 0,
+                "content_block": {"type": "tool_use", "id": "toolu_1", "name": "Read", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "input_json_delta", "partial_json": "{\"file_path\": \"/tm"}}),
+        ] {
+            assert!(aggregator.observe(&stream_event(event)).is_empty());
+        }
+
+        let flushed = aggregator.observe(&result_message());
+        assert_eq!(flushed.len(), 1);
+        match &flushed[0] {
+            Err(SdkError::IncompleteContentBlock { partial, .. }) => match partial.as_ref() {
+                ContentBlock::ToolUse(tool_use) => {
+                    assert_eq!(tool_use.id, "toolu_1");
+                    assert_eq!(tool_use.input, json!("{\"file_path\": \"/tm"));
+                },
+                other => panic!("expected ToolUse, got {other:?}"),
+            },
+            other => panic!("expected IncompleteContentBlock, got {other:?}"),
+        }
+
+        // The next turn starts clean and whole messages pass through again
+        let assistant = parse_message(json!({"type": "assistant", "message": {"content": [
+            {"type": "text", "text": "Done"}
+        ]}}))
+        .unwrap()
+        .unwrap();
+        assert_eq!(aggregator.observe(&assistant).len(), 1);
+    }
 }
@@ -136,6 +136,10 @@ impl OptimizedClient {
     }
 
     /// Execute a query with custom retry configuration
+    ///
+    /// An attempt that failed after Claude requested a tool is not retried:
+    /// the tool may already have run, and running the prompt again could
+    /// repeat its side effects. The error of that attempt is returned instead.
     pub async fn query_with_retry(
         &self,
         prompt: String,
@@ -146,8 +150,16 @@ impl OptimizedClient {
         let mut delay = initial_delay;
 
         loop {
-            match self.execute_query(&prompt).await {
+            let mut tool_started = false;
+            match self.execute_query(&prompt, &mut tool_started).await {
                 Ok(messages) => return Ok(messages),
+                Err(e) if tool_started => {
+                    warn!(
+                        "Query failed after tool execution began, not retrying: {}",
+                        e
+                    );
+                    return Err(e);
+                },
                 Err(e) if retries < max_retries => {
                     warn!("Query failed, retrying in {:?}: {}", delay, e);
                     tokio::time::sleep(delay).await;
@@ -160,7 +172,10 @@ impl OptimizedClient {
     }
 
     /// Internal query execution
-    async fn execute_query(&self, prompt: &str) -> Result<Vec<Message>> {
+    ///
+    /// Sets `tool_started` as soon as a tool use is received, even if the
+    /// query then fails.
+    async fn execute_query(&self, prompt: &str, tool_started: &mut bool) -> Result<Vec<Message>> {
         let mut transport = self.pool.acquire().await?;
 
         // Send message
@@ -169,9 +184,12 @@ impl OptimizedClient {
 
         // Collect response with timeout
         let timeout_duration = Duration::from_secs(120);
-        let messages = timeout(timeout_duration, self.collect_messages(&mut *transport))
-            .await
-            .map_err(|_| SdkError::Timeout { seconds: 120 })??;
+        let messages = timeout(
+            timeout_duration,
+            self.collect_messages(&mut *transport, tool_started),
+        )
+        .await
+        .map_err(|_| SdkError::Timeout { seconds: 120 })??;
 
         // Return transport to pool
         self.pool.release(transport).await;
@@ -183,6 +201,7 @@ impl OptimizedClient {
     async fn collect_messages<T: Transport + Send + ?Sized>(
         &self,
         transport: &mut T,
+        tool_started: &mut bool,
     ) -> Result<Vec<Message>> {
         let mut messages = Vec::new();
         let mut stream = transport.receive_messages();
@@ -191,33 +210,17 @@ impl OptimizedClient {
             match result {
                 Ok(msg) => {
                     debug!("Received: {:?}", msg);
-                    let is_result = matches!(msg, Message::Result { .. });
-
-                    // Update budget/usage on result messages
-                    if let Message::Result {
-                        usage,
-                        total_cost_usd,
-                        ..
-                    } = &msg
+                    if let Message::Assistant { message, .. } = &msg
+                        && message.has_tool_uses()
                     {
-                        let (input_tokens, output_tokens) = if let Some(usage_json) = usage {
-                            let input = usage_json
-                                .get("input_tokens")
-                                .and_then(|v| v.as_u64())
-                                .unwrap_or(0);
-                            let output = usage_json
-                                .get("output_tokens")
-                                .and_then(|v| v.as_u64())
-                                .unwrap_or(0);
-                            (input, output)
-                        } else {
-                            (0, 0)
-                        };
-                        let cost = total_cost_usd.unwrap_or(0.0);
-                        self.budget_manager
-                            .update_usage(input_tokens, output_tokens, cost)
-                            .await;
+                        *tool_started = true;
                     }
+                    let is_result = self.pool.base_options.result_policy.ends_turn(&msg);
+
+                    // Update budget/usage on result messages
+                    self.budget_manager
+                        .record_result(self.pool.base_options.model.as_deref(), &msg)
+                        .await;
                     messages.push(msg);
                     if is_result {
                         break;
@@ -367,7 +370,7 @@ impl OptimizedClient {
 
             // Collect messages until Result
             while let Some(msg) = rx.recv().await {
-                let is_result = matches!(msg, Message::Result { .. });
+                let is_result = self.pool.base_options.result_policy.ends_turn(&msg);
                 messages.push(msg);
                 if is_result {
                     break;
@@ -478,6 +481,8 @@ impl Clone for OptimizedClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(all(unix, feature = "subprocess"))]
+    use crate::transport::subprocess::tests::fake_cli;
 
     #[test]
     fn test_client_mode_creation() {
@@ -504,6 +509,35 @@ mod tests {
         assert_eq!(pool.max_connections, 10);
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_query_with_retry_refuses_retry_after_tool_use() {
+        // Fake CLI: every attempt starts a Bash tool, then gets rate limited,
+        // which would normally be retried. Each attempt is logged.
+        let dir = tempfile::tempdir().unwrap();
+        let attempts = dir.path().join("attempts");
+        let script = format!(
+            r#"read -r line
+echo attempt >> {}
+echo '{{"type":"assistant","message":{{"content":[{{"type":"tool_use","id":"toolu_1","name":"Bash","input":{{"command":"touch done"}}}}]}}}}'
+echo '{{"type":"result","subtype":"error_during_execution","duration_ms":1,"duration_api_ms":1,"is_error":true,"num_turns":1,"session_id":"s","result":"API Error: 429 rate limit exceeded"}}'
+exec cat > /dev/null
+"#,
+            attempts.display()
+        );
+        let cli = fake_cli(dir.path(), &script);
+
+        let options = ClaudeCodeOptions::builder().cli_path(&cli).build();
+        let client = OptimizedClient::new(options, ClientMode::OneShot).unwrap();
+        let result = client
+            .query_with_retry("run it".into(), 3, Duration::from_millis(1))
+            .await;
+
+        assert!(matches!(result, Err(SdkError::RateLimited { .. })));
+        let attempts = std::fs::read_to_string(&attempts).unwrap();
+        assert_eq!(attempts.lines().count(), 1, "the query must not be retried");
+    }
+
     #[tokio::test]
     async fn test_client_cloning() {
         let options = ClaudeCodeOptions::builder().build();
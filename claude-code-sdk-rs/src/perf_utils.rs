@@ -1,8 +1,11 @@
 //! Performance utilities for the Claude Code SDK
 
-use crate::{errors::Result, types::Message};
+use crate::{
+    errors::Result,
+    types::{ContentBlock, Message, StreamDelta, StreamEventData},
+};
 use std::collections::VecDeque;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio::time::{sleep, timeout};
 use tracing::{debug, warn};
@@ -50,33 +53,36 @@ impl RetryConfig {
                 Err(e) if retries < self.max_retries => {
                     retries += 1;
 
-                    // Add jitter to delay
-                    let jitter = if self.jitter_factor > 0.0 {
-                        let jitter_range = delay.as_secs_f64() * self.jitter_factor;
-                        let jitter = rand::random::<f64>() * jitter_range - (jitter_range / 2.0);
-                        Duration::from_secs_f64(jitter.abs())
-                    } else {
-                        Duration::ZERO
-                    };
-
-                    let actual_delay = delay + jitter;
+                    let actual_delay = self.with_jitter(delay);
                     warn!(
                         "Attempt {} failed, retrying in {:?}: {}",
                         retries, actual_delay, e
                     );
 
                     sleep(actual_delay).await;
-
-                    // Calculate next delay with exponential backoff
-                    delay = Duration::from_secs_f64(
-                        (delay.as_secs_f64() * self.backoff_multiplier)
-                            .min(self.max_delay.as_secs_f64()),
-                    );
+                    delay = self.next_delay(delay);
                 },
                 Err(e) => return Err(e),
             }
         }
     }
+
+    /// Add random jitter to a delay
+    pub(crate) fn with_jitter(&self, delay: Duration) -> Duration {
+        if self.jitter_factor <= 0.0 {
+            return delay;
+        }
+        let jitter_range = delay.as_secs_f64() * self.jitter_factor;
+        let jitter = rand::random::<f64>() * jitter_range - (jitter_range / 2.0);
+        delay + Duration::from_secs_f64(jitter.abs())
+    }
+
+    /// Delay following `delay` under exponential backoff
+    pub(crate) fn next_delay(&self, delay: Duration) -> Duration {
+        Duration::from_secs_f64(
+            (delay.as_secs_f64() * self.backoff_multiplier).min(self.max_delay.as_secs_f64()),
+        )
+    }
 }
 
 /// Message batcher for efficient processing
@@ -175,6 +181,237 @@ pub struct PerformanceMetrics {
     pub max_latency_ms: u64,
     /// Minimum latency in milliseconds
     pub min_latency_ms: u64,
+    /// Recent per-phase latency samples, see [`PerformanceMetrics::record_phase`]
+    pub phase_latencies: PhaseLatencies,
+    /// Output rate of the current turn, see [`PerformanceMetrics::observe_tokens`]
+    pub token_rate: TokenRateMeter,
+}
+
+/// A phase of a turn, measured from the start of the turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnPhase {
+    /// Until the CLI process was spawned and ready
+    Spawn,
+    /// Until the first assistant token arrived
+    FirstToken,
+    /// Until the Result message arrived
+    FullTurn,
+}
+
+/// Samples kept per phase; older samples are dropped first
+pub const MAX_PHASE_SAMPLES: usize = 1024;
+
+/// The most recent latency samples in milliseconds for each [`TurnPhase`]
+///
+/// Each phase keeps at most [`MAX_PHASE_SAMPLES`] samples, so a long-lived
+/// client's metrics stay bounded and percentiles reflect recent turns.
+#[derive(Debug, Default, Clone)]
+pub struct PhaseLatencies {
+    spawn_ms: VecDeque<u64>,
+    first_token_ms: VecDeque<u64>,
+    full_turn_ms: VecDeque<u64>,
+}
+
+impl PhaseLatencies {
+    /// Samples recorded for a phase, oldest first
+    pub fn samples(&self, phase: TurnPhase) -> &VecDeque<u64> {
+        match phase {
+            TurnPhase::Spawn => &self.spawn_ms,
+            TurnPhase::FirstToken => &self.first_token_ms,
+            TurnPhase::FullTurn => &self.full_turn_ms,
+        }
+    }
+
+    fn push(&mut self, phase: TurnPhase, millis: u64) {
+        let samples = match phase {
+            TurnPhase::Spawn => &mut self.spawn_ms,
+            TurnPhase::FirstToken => &mut self.first_token_ms,
+            TurnPhase::FullTurn => &mut self.full_turn_ms,
+        };
+        if samples.len() == MAX_PHASE_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(millis);
+    }
+}
+
+/// Window [`TokenRateMeter::current_tps`] averages over by default
+pub const DEFAULT_TOKEN_RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Shortest span a rate is computed over, so a burst right after the first
+/// token does not read as a huge rate
+const MIN_TOKEN_RATE_SPAN: Duration = Duration::from_secs(1);
+
+/// Rolling output tokens-per-second of a streaming turn
+///
+/// Feed it every received message with [`observe`](Self::observe) and
+/// [`reset`](Self::reset) it when a turn starts. Content deltas (and whole
+/// assistant messages when partial messages are off) are counted at ~4
+/// characters per token; the `output_tokens` a `message_delta` or `Result`
+/// reports take over from the estimate. Updates are O(1) amortized.
+#[derive(Debug, Clone)]
+pub struct TokenRateMeter {
+    window: Duration,
+    started: Instant,
+    first_token: Option<Instant>,
+    /// Tokens received per instant, within `window`
+    samples: VecDeque<(Instant, u64)>,
+    window_tokens: u64,
+    peak_tps: f64,
+    output_tokens: u64,
+    /// Characters not yet counted as a token
+    pending_chars: u64,
+    /// Tokens counted for the API message being streamed
+    message_tokens: u64,
+    /// Whether the turn streams deltas, which whole assistant messages repeat
+    saw_deltas: bool,
+}
+
+impl Default for TokenRateMeter {
+    fn default() -> Self {
+        Self::new(DEFAULT_TOKEN_RATE_WINDOW)
+    }
+}
+
+impl TokenRateMeter {
+    /// Meter averaging the current rate over `window` (at least one second)
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: window.max(MIN_TOKEN_RATE_SPAN),
+            started: Instant::now(),
+            first_token: None,
+            samples: VecDeque::new(),
+            window_tokens: 0,
+            peak_tps: 0.0,
+            output_tokens: 0,
+            pending_chars: 0,
+            message_tokens: 0,
+            saw_deltas: false,
+        }
+    }
+
+    /// Start measuring a new turn now
+    pub fn reset(&mut self) {
+        *self = Self::new(self.window);
+    }
+
+    /// Count the output carried by a received message
+    pub fn observe(&mut self, message: &Message) {
+        match message {
+            Message::StreamEvent { event, .. } => match event {
+                StreamEventData::MessageStart { .. } => self.message_tokens = 0,
+                StreamEventData::ContentBlockDelta { delta, .. } => {
+                    self.saw_deltas = true;
+                    let text = match delta {
+                        StreamDelta::TextDelta { text } => text,
+                        StreamDelta::ThinkingDelta { thinking } => thinking,
+                        StreamDelta::InputJsonDelta { partial_json } => partial_json,
+                    };
+                    self.record_chars(text.chars().count() as u64);
+                },
+                StreamEventData::MessageDelta {
+                    usage: Some(usage), ..
+                } => {
+                    if let Some(reported) = usage.get("output_tokens").and_then(|v| v.as_u64()) {
+                        // Only top up: the estimate may already be ahead
+                        self.record_tokens(reported.saturating_sub(self.message_tokens));
+                        self.message_tokens = reported;
+                    }
+                },
+                _ => {},
+            },
+            Message::Assistant { message, .. } if !self.saw_deltas => {
+                let chars: usize = message
+                    .content
+                    .iter()
+                    .map(|block| match block {
+                        ContentBlock::Text(text) => text.text.chars().count(),
+                        ContentBlock::Thinking(thinking) => thinking.thinking.chars().count(),
+                        ContentBlock::ToolUse(tool_use) => tool_use.input.to_string().len(),
+                        ContentBlock::ToolResult(_) => 0,
+                    })
+                    .sum();
+                self.record_chars(chars as u64);
+            },
+            Message::Result {
+                usage: Some(usage),
+                parent_tool_use_id: None,
+                ..
+            } => self.output_tokens = usage.output_tokens,
+            _ => {},
+        }
+    }
+
+    fn record_chars(&mut self, chars: u64) {
+        if chars > 0 {
+            self.first_token.get_or_insert_with(Instant::now);
+        }
+        self.pending_chars += chars;
+        let tokens = self.pending_chars / 4;
+        self.pending_chars %= 4;
+        self.record_tokens(tokens);
+    }
+
+    /// Count `tokens` output tokens as received now
+    pub fn record_tokens(&mut self, tokens: u64) {
+        if tokens == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let first_token = *self.first_token.get_or_insert(now);
+        self.output_tokens += tokens;
+        self.message_tokens += tokens;
+        self.window_tokens += tokens;
+        self.samples.push_back((now, tokens));
+        while let Some(&(at, oldest)) = self.samples.front() {
+            if now.duration_since(at) <= self.window {
+                break;
+            }
+            self.window_tokens -= oldest;
+            self.samples.pop_front();
+        }
+        self.peak_tps = self
+            .peak_tps
+            .max(self.rate(self.window_tokens, now, first_token));
+    }
+
+    /// `tokens` received within the window ending at `now`, per second
+    fn rate(&self, tokens: u64, now: Instant, first_token: Instant) -> f64 {
+        let span = now
+            .duration_since(first_token)
+            .clamp(MIN_TOKEN_RATE_SPAN, self.window);
+        tokens as f64 / span.as_secs_f64()
+    }
+
+    /// Output tokens per second over the last window
+    pub fn current_tps(&self) -> f64 {
+        let Some(first_token) = self.first_token else {
+            return 0.0;
+        };
+        let now = Instant::now();
+        let recent = self
+            .samples
+            .iter()
+            .filter(|(at, _)| now.duration_since(*at) <= self.window)
+            .map(|(_, tokens)| tokens)
+            .sum();
+        self.rate(recent, now, first_token)
+    }
+
+    /// Highest rate seen since the last reset
+    pub fn peak_tps(&self) -> f64 {
+        self.peak_tps
+    }
+
+    /// Time from the last reset to the first output token
+    pub fn time_to_first_token(&self) -> Option<Duration> {
+        self.first_token.map(|at| at.duration_since(self.started))
+    }
+
+    /// Output tokens of the turn so far (exact once the `Result` arrived)
+    pub fn output_tokens(&self) -> u64 {
+        self.output_tokens
+    }
 }
 
 impl PerformanceMetrics {
@@ -214,6 +451,69 @@ impl PerformanceMetrics {
             self.successful_requests as f64 / self.total_requests as f64
         }
     }
+
+    /// Record how long one phase of a turn took
+    pub fn record_phase(&mut self, phase: TurnPhase, latency: Duration) {
+        let millis = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+        self.phase_latencies.push(phase, millis);
+    }
+
+    /// Count the output of a received message, see [`TokenRateMeter::observe`]
+    pub fn observe_tokens(&mut self, message: &Message) {
+        self.token_rate.observe(message);
+    }
+
+    /// Start measuring the token rate of a new turn
+    pub fn reset_token_rate(&mut self) {
+        self.token_rate.reset();
+    }
+
+    /// Output tokens per second of the current turn over the last few seconds
+    pub fn current_tps(&self) -> f64 {
+        self.token_rate.current_tps()
+    }
+
+    /// Highest output rate of the current turn
+    pub fn peak_tps(&self) -> f64 {
+        self.token_rate.peak_tps()
+    }
+
+    /// Time from the start of the current turn to its first output token
+    pub fn time_to_first_token(&self) -> Option<Duration> {
+        self.token_rate.time_to_first_token()
+    }
+
+    /// Latency percentile of a phase in milliseconds (nearest-rank)
+    ///
+    /// Computed over the last [`MAX_PHASE_SAMPLES`] samples of the phase.
+    /// `percentile` is clamped to 0-100. Returns `None` when the phase has no
+    /// samples.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::{PerformanceMetrics, TurnPhase};
+    /// # use std::time::Duration;
+    /// let mut metrics = PerformanceMetrics::default();
+    /// for ms in [120, 80, 100] {
+    ///     metrics.record_phase(TurnPhase::Spawn, Duration::from_millis(ms));
+    /// }
+    /// assert_eq!(metrics.phase_percentile(TurnPhase::Spawn, 50.0), Some(100));
+    /// ```
+    pub fn phase_percentile(&self, phase: TurnPhase, percentile: f64) -> Option<u64> {
+        let mut samples: Vec<u64> = self
+            .phase_latencies
+            .samples(phase)
+            .iter()
+            .copied()
+            .collect();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * samples.len() as f64).ceil() as usize;
+        Some(samples[rank.saturating_sub(1)])
+    }
 }
 
 #[cfg(test)]
@@ -292,6 +592,58 @@ mod tests {
         assert_eq!(metrics.max_latency_ms, 500);
     }
 
+    #[test]
+    fn test_phase_percentiles() {
+        let mut metrics = PerformanceMetrics::default();
+        for ms in 1..=100 {
+            metrics.record_phase(TurnPhase::FullTurn, Duration::from_millis(ms * 10));
+        }
+        for ms in [300, 100, 200] {
+            metrics.record_phase(TurnPhase::FirstToken, Duration::from_millis(ms));
+        }
+
+        assert_eq!(
+            metrics.phase_percentile(TurnPhase::FullTurn, 50.0),
+            Some(500)
+        );
+        assert_eq!(
+            metrics.phase_percentile(TurnPhase::FullTurn, 95.0),
+            Some(950)
+        );
+        assert_eq!(
+            metrics.phase_percentile(TurnPhase::FullTurn, 99.0),
+            Some(990)
+        );
+        assert_eq!(
+            metrics.phase_percentile(TurnPhase::FullTurn, 100.0),
+            Some(1000)
+        );
+        assert_eq!(metrics.phase_percentile(TurnPhase::FullTurn, 0.0), Some(10));
+
+        assert_eq!(
+            metrics.phase_percentile(TurnPhase::FirstToken, 50.0),
+            Some(200)
+        );
+        assert_eq!(
+            metrics.phase_percentile(TurnPhase::FirstToken, 90.0),
+            Some(300)
+        );
+        assert_eq!(metrics.phase_percentile(TurnPhase::Spawn, 50.0), None);
+    }
+
+    #[test]
+    fn test_phase_samples_are_bounded() {
+        let mut metrics = PerformanceMetrics::default();
+        for ms in 0..MAX_PHASE_SAMPLES as u64 + 10 {
+            metrics.record_phase(TurnPhase::Spawn, Duration::from_millis(ms));
+        }
+
+        let samples = metrics.phase_latencies.samples(TurnPhase::Spawn);
+        assert_eq!(samples.len(), MAX_PHASE_SAMPLES);
+        assert_eq!(samples.front(), Some(&10));
+        assert_eq!(metrics.phase_percentile(TurnPhase::Spawn, 0.0), Some(10));
+    }
+
     #[tokio::test]
     async fn test_retry_succeeds_on_first_try() {
         let config = RetryConfig {
@@ -396,4 +748,71 @@ mod tests {
         let batch = rx.recv().await.unwrap();
         assert_eq!(batch.len(), 2);
     }
+
+    #[test]
+    fn test_token_rate_meter() {
+        use crate::types::{AssistantMessage, TextContent, TokenUsage};
+
+        let event = |event| Message::StreamEvent {
+            event,
+            session_id: None,
+            parent_tool_use_id: None,
+        };
+        let delta = |text: &str| {
+            event(StreamEventData::ContentBlockDelta {
+                index: 0,
+                delta: StreamDelta::TextDelta { text: text.into() },
+            })
+        };
+
+        let mut metrics = PerformanceMetrics::default();
+        metrics.reset_token_rate();
+        assert_eq!(metrics.current_tps(), 0.0);
+        assert_eq!(metrics.time_to_first_token(), None);
+
+        metrics.observe_tokens(&event(StreamEventData::MessageStart {
+            message: serde_json::json!({}),
+        }));
+        // 8 characters each, estimated at 2 tokens
+        for _ in 0..3 {
+            metrics.observe_tokens(&delta("abcdefgh"));
+        }
+        assert!(metrics.time_to_first_token().is_some());
+        assert_eq!(metrics.token_rate.output_tokens(), 6);
+        // Within the first second the rate is taken over one second
+        assert_eq!(metrics.current_tps(), 6.0);
+
+        // The reported count tops up the estimate
+        metrics.observe_tokens(&event(StreamEventData::MessageDelta {
+            delta: serde_json::json!({"stop_reason": "end_turn"}),
+            usage: Some(serde_json::json!({"output_tokens": 10})),
+        }));
+        assert_eq!(metrics.current_tps(), 10.0);
+        assert_eq!(metrics.peak_tps(), 10.0);
+
+        // The whole message repeats the deltas and is not counted again
+        metrics.observe_tokens(&Message::Assistant {
+            message: AssistantMessage {
+                content: vec![ContentBlock::Text(TextContent {
+                    text: "abcdefgh".repeat(3),
+                })],
+            },
+            parent_tool_use_id: None,
+        });
+        let mut result = Message::result_success("s1");
+        if let Message::Result { usage, .. } = &mut result {
+            *usage = Some(TokenUsage {
+                output_tokens: 12,
+                ..Default::default()
+            });
+        }
+        metrics.observe_tokens(&result);
+        assert_eq!(metrics.token_rate.output_tokens(), 12);
+        assert_eq!(metrics.current_tps(), 10.0);
+
+        metrics.reset_token_rate();
+        assert_eq!(metrics.peak_tps(), 0.0);
+        assert_eq!(metrics.token_rate.output_tokens(), 0);
+        assert_eq!(metrics.time_to_first_token(), None);
+    }
 }
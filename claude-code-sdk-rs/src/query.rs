@@ -4,13 +4,17 @@
 //! with Claude Code CLI.
 
 use crate::{
-    errors::Result,
+    errors::{Result, SdkError},
+    message_parser::parse_structured_output,
+    perf_utils::RetryConfig,
     transport::InputMessage,
-    types::{ClaudeCodeOptions, Message, PermissionMode},
+    types::{ClaudeCodeOptions, Message, PermissionMode, ResultPolicy},
 };
 use futures::stream::Stream;
+use serde::de::DeserializeOwned;
 use std::pin::Pin;
-use tokio::sync::mpsc;
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::{debug, info, warn};
 
@@ -34,6 +38,41 @@ impl From<&str> for QueryInput {
     }
 }
 
+/// Handle to interrupt a query started with [`query_interruptible`]
+#[derive(Debug, Clone)]
+pub struct QueryHandle {
+    interrupted: Arc<watch::Sender<bool>>,
+}
+
+impl QueryHandle {
+    fn new() -> Self {
+        let (interrupted, _) = watch::channel(false);
+        Self {
+            interrupted: Arc::new(interrupted),
+        }
+    }
+
+    /// Interrupt the query
+    ///
+    /// The CLI process is killed and the message stream ends without an error.
+    /// Calling this after the query has finished has no effect.
+    pub fn interrupt(&self) {
+        self.interrupted.send_replace(true);
+    }
+
+    /// Whether [`interrupt`](Self::interrupt) has been called
+    pub fn is_interrupted(&self) -> bool {
+        *self.interrupted.borrow()
+    }
+
+    /// Resolve once the query is interrupted
+    async fn interrupted(&self) {
+        let mut rx = self.interrupted.subscribe();
+        // The sender lives as long as `self`, so this only returns on interrupt
+        let _ = rx.wait_for(|&interrupted| interrupted).await;
+    }
+}
+
 /// Query Claude Code for one-shot or unidirectional streaming interactions.
 ///
 /// This function is ideal for simple, stateless queries where you don't need
@@ -44,7 +83,8 @@ impl From<&str> for QueryInput {
 /// - **Unidirectional**: Send all messages upfront, receive all responses
 /// - **Stateless**: Each query is independent, no conversation state
 /// - **Simple**: Fire-and-forget style, no connection management
-/// - **No interrupts**: Cannot interrupt or send follow-up messages
+/// - **No follow-ups**: Cannot send follow-up messages (use
+///   [`query_interruptible`] to be able to cancel a query)
 ///
 /// # When to use query():
 /// - Simple one-off questions ("What is 2+2?")
@@ -116,6 +156,44 @@ pub async fn query(
     prompt: impl Into<QueryInput>,
     options: Option<ClaudeCodeOptions>,
 ) -> Result<impl Stream<Item = Result<Message>>> {
+    let (stream, _handle) = query_interruptible(prompt, options).await?;
+    Ok(stream)
+}
+
+/// Like [`query`], but also returns a [`QueryHandle`] to cancel the query.
+///
+/// Interrupting the handle kills the CLI process and ends the stream. Dropping
+/// the stream has the same effect, so the handle is only needed when the
+/// stream is owned elsewhere (e.g. a UI cancel button).
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use nexus_claude::{query_interruptible, Result};
+/// use futures::StreamExt;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<()> {
+///     let (messages, handle) = query_interruptible("Refactor the parser", None).await?;
+///
+///     // Cancel after 30 seconds
+///     tokio::spawn(async move {
+///         tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+///         handle.interrupt();
+///     });
+///
+///     let mut messages = Box::pin(messages);
+///     while let Some(msg) = messages.next().await {
+///         println!("{:?}", msg?);
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+pub async fn query_interruptible(
+    prompt: impl Into<QueryInput>,
+    options: Option<ClaudeCodeOptions>,
+) -> Result<(impl Stream<Item = Result<Message>>, QueryHandle)> {
     let options = options.unwrap_or_default();
     let prompt = prompt.into();
 
@@ -127,7 +205,9 @@ pub async fn query(
     match prompt {
         QueryInput::Text(text) => {
             // For simple text queries, use --print mode like Python SDK
-            query_print_mode(text, options).await
+            let handle = QueryHandle::new();
+            let stream = query_print_mode(text, options, handle.clone()).await?;
+            Ok((stream, handle))
         },
         QueryInput::Stream(_stream) => {
             // For streaming, use the interactive mode
@@ -139,18 +219,385 @@ pub async fn query(
     }
 }
 
+/// Prompt cache token counts from a turn's `Result` usage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheUsage {
+    /// Tokens written to the prompt cache
+    pub cache_creation_input_tokens: u64,
+    /// Tokens served from the prompt cache
+    pub cache_read_input_tokens: u64,
+}
+
+impl CacheUsage {
+    /// Read the cache token counts from a `Result` message
+    ///
+    /// Returns `None` for other messages and for results without usage.
+    pub fn from_result(message: &Message) -> Option<Self> {
+        let Message::Result {
+            usage: Some(usage), ..
+        } = message
+        else {
+            return None;
+        };
+        Some(Self {
+            cache_creation_input_tokens: usage.cache_creation_input_tokens.unwrap_or(0),
+            cache_read_input_tokens: usage.cache_read_input_tokens.unwrap_or(0),
+        })
+    }
+}
+
+/// Prompt used by [`warmup`]; its answer is discarded
+const WARMUP_PROMPT: &str = "Reply with OK.";
+
+/// Prime the prompt cache with the configured system prompt
+///
+/// Runs a minimal single-turn query with `options` and discards the answer,
+/// so later queries sharing the same system prompt, tools and model read
+/// the prefix from the cache instead of paying for it again. `max_turns` is
+/// forced to 1; everything else is left as given, since any difference would
+/// change the cached prefix.
+///
+/// Returns the cache usage of the warmup turn: a non-zero
+/// `cache_creation_input_tokens` (or `cache_read_input_tokens`, if the cache
+/// was already warm) confirms the prefix is cached. Fails with
+/// `SdkError::CliError` if the turn ends in an error.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use nexus_claude::{warmup, ClaudeCodeOptions, Result};
+///
+/// #[tokio::main]
+/// async fn main() -> Result<()> {
+///     let options = ClaudeCodeOptions::builder()
+///         .system_prompt(std::fs::read_to_string("style_guide.md")?)
+///         .build();
+///     let usage = warmup(options).await?;
+///     println!("cached {} tokens", usage.cache_creation_input_tokens);
+///     Ok(())
+/// }
+/// ```
+pub async fn warmup(mut options: ClaudeCodeOptions) -> Result<CacheUsage> {
+    use futures::StreamExt;
+
+    options.max_turns = Some(1);
+    let messages = query(WARMUP_PROMPT, Some(options)).await?;
+    let mut messages = std::pin::pin!(messages);
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        if let Message::Result {
+            is_error,
+            ref subtype,
+            ..
+        } = message
+        {
+            if is_error {
+                return Err(SdkError::cli_error(
+                    format!("Warmup turn failed: {subtype}"),
+                    None,
+                ));
+            }
+            let usage = CacheUsage::from_result(&message).unwrap_or_default();
+            info!(
+                "Warmup done: {} tokens written to cache, {} read",
+                usage.cache_creation_input_tokens, usage.cache_read_input_tokens
+            );
+            return Ok(usage);
+        }
+    }
+    Err(SdkError::UnexpectedStreamEnd)
+}
+
+/// Run a query and deserialize its structured output into `T`
+///
+/// `options.output_format` must be a `json_schema` format; the
+/// CLI validates the answer and returns it as the `Result` message's
+/// `structured_output`, which is then deserialized into `T`.
+///
+/// Schema validation is left to the CLI, which retries until the answer
+/// matches the `json_schema` and gives up after a few attempts.
+///
+/// Fails with:
+/// - `SdkError::ConfigError` if `output_format` is unset or `text`
+/// - `SdkError::CliError` if the turn ends in an error; when the CLI gave up
+///   on matching the schema, its `code` is `error_max_structured_output_retries`
+/// - `SdkError::MessageParseError` if the result carries no structured
+///   output or it does not match `T` (the error includes the raw JSON)
+/// - `SdkError::UnexpectedStreamEnd` if the CLI exits without a result
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use nexus_claude::{query_structured, ClaudeCodeOptions, Result};
+///
+/// #[derive(serde::Deserialize)]
+/// struct Capital {
+///     city: String,
+/// }
+///
+/// #[tokio::main]
+/// async fn main() -> Result<()> {
+///     let options = ClaudeCodeOptions::builder()
+///         .output_format(serde_json::json!({
+///             "type": "json_schema",
+///             "schema": {
+///                 "type": "object",
+///                 "properties": {"city": {"type": "string"}},
+///                 "required": ["city"]
+///             }
+///         }))
+///         .build();
+///     let capital: Capital = query_structured("What is the capital of France?", options).await?;
+///     println!("{}", capital.city);
+///     Ok(())
+/// }
+/// ```
+pub async fn query_structured<T: DeserializeOwned>(
+    prompt: impl Into<QueryInput>,
+    options: ClaudeCodeOptions,
+) -> Result<T> {
+    use futures::StreamExt;
+
+    match options
+        .output_format
+        .as_ref()
+        .map(|format| format.get("type").and_then(|v| v.as_str()))
+    {
+        None => {
+            return Err(SdkError::ConfigError(
+                "query_structured requires output_format to be set".to_string(),
+            ));
+        },
+        Some(Some("text")) => {
+            return Err(SdkError::ConfigError(
+                "query_structured requires a json_schema output_format, not text".to_string(),
+            ));
+        },
+        _ => {},
+    }
+
+    let result_policy = options.result_policy;
+    let messages = query(prompt, Some(options)).await?;
+    let mut messages = std::pin::pin!(messages);
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        if result_policy.ends_turn(&message) {
+            return parse_structured_output(&message);
+        }
+    }
+    Err(SdkError::UnexpectedStreamEnd)
+}
+
+/// Every message of a finished query, see [`query_collect`]
+#[derive(Debug, Clone)]
+pub struct QueryOutcome {
+    messages: Vec<Message>,
+    /// Index of the top-level result in `messages`
+    result: usize,
+}
+
+impl QueryOutcome {
+    /// Wrap collected messages, failing if none ends the conversation
+    fn from_messages(messages: Vec<Message>) -> Result<Self> {
+        let result = messages
+            .iter()
+            .rposition(|message| ResultPolicy::TopLevel.ends_turn(message))
+            .ok_or(SdkError::UnexpectedStreamEnd)?;
+        Ok(Self { messages, result })
+    }
+
+    /// All messages in the order they were received
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// Take ownership of the collected messages
+    pub fn into_messages(self) -> Vec<Message> {
+        self.messages
+    }
+
+    /// The top-level `Message::Result` that ended the query
+    pub fn result(&self) -> &Message {
+        &self.messages[self.result]
+    }
+
+    /// Session the query ran in
+    pub fn session_id(&self) -> &str {
+        match self.result() {
+            Message::Result { session_id, .. } => session_id,
+            _ => unreachable!("result index always points at a Message::Result"),
+        }
+    }
+
+    /// Total cost reported by the CLI, if any
+    pub fn total_cost_usd(&self) -> Option<f64> {
+        match self.result() {
+            Message::Result { total_cost_usd, .. } => *total_cost_usd,
+            _ => None,
+        }
+    }
+
+    /// `base` with `resume` set to this query's session
+    ///
+    /// `continue_conversation` is cleared, since it would pick the most
+    /// recent session instead of this one.
+    pub fn resume_options(&self, base: ClaudeCodeOptions) -> ClaudeCodeOptions {
+        let mut options = base;
+        options.resume = Some(self.session_id().to_string());
+        options.continue_conversation = false;
+        options
+    }
+}
+
+/// Run a query to completion and collect every message
+///
+/// Unlike [`query`], the stream is drained before returning, so the caller
+/// gets the whole conversation at once along with the session it ran in.
+/// Use [`QueryOutcome::resume_options`] to follow up in the same session.
+///
+/// # Errors
+///
+/// Any error yielded by the stream is returned as is, and
+/// `SdkError::UnexpectedStreamEnd` if the CLI exits without a top-level
+/// result.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use nexus_claude::{query_collect, ClaudeCodeOptions, Result};
+///
+/// #[tokio::main]
+/// async fn main() -> Result<()> {
+///     let base = ClaudeCodeOptions::default();
+///     let first = query_collect("Pick a number between 1 and 10", base.clone()).await?;
+///     println!("cost: {:?}", first.total_cost_usd());
+///
+///     let next = query_collect("Double it", first.resume_options(base)).await?;
+///     println!("{:?}", next.result());
+///     Ok(())
+/// }
+/// ```
+pub async fn query_collect(
+    prompt: impl Into<QueryInput>,
+    options: ClaudeCodeOptions,
+) -> Result<QueryOutcome> {
+    use futures::StreamExt;
+
+    let messages = query(prompt, Some(options)).await?;
+    let mut messages = std::pin::pin!(messages);
+    let mut collected = Vec::new();
+    while let Some(message) = messages.next().await {
+        collected.push(message?);
+    }
+    QueryOutcome::from_messages(collected)
+}
+
+/// Like [`query`], but retries transient failures to start the query
+///
+/// The whole query is run again, after the jittered exponential backoff of
+/// `retry`, when it fails with an error for which
+/// [`SdkError::is_retryable`] holds (e.g. the CLI could not be spawned or
+/// crashed before sending any message). Permanent errors such as a missing
+/// CLI, invalid configuration or the CLI exiting on rejected credentials are
+/// returned right away. Once the first
+/// message has arrived the query is never restarted, since that would
+/// repeat work; later errors are yielded by the stream as with [`query`].
+///
+/// At most `retry.max_retries` retries are made. When more than one
+/// attempt was made, the final error is `SdkError::RetryFailed` carrying
+/// the attempt count and the last attempt's error.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use nexus_claude::{query_with_retry, ClaudeCodeOptions, RetryConfig, Result};
+/// use futures::StreamExt;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<()> {
+///     let messages =
+///         query_with_retry("Summarize the changelog", ClaudeCodeOptions::default(), RetryConfig::default())
+///             .await?;
+///     let mut messages = std::pin::pin!(messages);
+///     while let Some(msg) = messages.next().await {
+///         println!("{:?}", msg?);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn query_with_retry(
+    prompt: impl Into<String>,
+    options: ClaudeCodeOptions,
+    retry: RetryConfig,
+) -> Result<impl Stream<Item = Result<Message>>> {
+    let prompt = prompt.into();
+    let mut attempts = 0;
+    let mut delay = retry.initial_delay;
+    loop {
+        attempts += 1;
+        let error = match start_query(prompt.clone(), options.clone()).await {
+            Ok(messages) => return Ok(messages),
+            Err(e) => e,
+        };
+        if !error.is_retryable() || attempts > retry.max_retries {
+            return Err(if attempts > 1 {
+                SdkError::RetryFailed {
+                    attempts,
+                    last_error: Box::new(error),
+                }
+            } else {
+                error
+            });
+        }
+        let actual_delay = retry.with_jitter(delay);
+        warn!(
+            "Query attempt {} failed, retrying in {:?}: {}",
+            attempts, actual_delay, error
+        );
+        tokio::time::sleep(actual_delay).await;
+        delay = retry.next_delay(delay);
+    }
+}
+
+/// Run a query until its first message, so failures to start surface as errors
+async fn start_query(
+    prompt: String,
+    options: ClaudeCodeOptions,
+) -> Result<impl Stream<Item = Result<Message>>> {
+    use futures::StreamExt;
+
+    let mut messages = Box::pin(query(prompt, Some(options)).await?);
+    match messages.next().await {
+        Some(Ok(first)) => Ok(futures::stream::once(async { Ok(first) }).chain(messages)),
+        Some(Err(e)) => Err(e),
+        None => Err(SdkError::UnexpectedStreamEnd),
+    }
+}
+
 /// Execute a simple query using --print mode
 #[allow(deprecated)]
 async fn query_print_mode(
     prompt: String,
     options: ClaudeCodeOptions,
+    handle: QueryHandle,
 ) -> Result<impl Stream<Item = Result<Message>>> {
-    use std::sync::Arc;
     use tokio::io::{AsyncBufReadExt, BufReader};
     use tokio::process::Command;
     use tokio::sync::Mutex;
 
-    let cli_path = crate::transport::subprocess::find_claude_cli()?;
+    // --print with stream-json output is only accepted together with --verbose
+    if options.verbose == Some(false) {
+        return Err(SdkError::ConfigError(
+            "verbose(false) is not supported by query(): the CLI requires --verbose \
+             for stream-json output in --print mode"
+                .to_string(),
+        ));
+    }
+
+    let cli_path = match options.cli_path {
+        Some(ref explicit_path) => explicit_path.clone(),
+        None => crate::transport::subprocess::find_claude_cli()?,
+    };
     let mut cmd = Command::new(&cli_path);
 
     // Build command with --print mode
@@ -159,7 +606,8 @@ async fn query_print_mode(
 
     // System prompts (match Python SDK behavior)
     //
-    // Python always passes `--system-prompt ""` when `system_prompt` is None.
+    // Python always passes `--system-prompt ""` when `system_prompt` is None,
+    // unless the caller opted to inherit the prompt from the CLI's settings.
     if let Some(ref prompt_v2) = options.system_prompt_v2 {
         match prompt_v2 {
             crate::types::SystemPrompt::String(s) => {
@@ -170,6 +618,13 @@ async fn query_print_mode(
                     cmd.arg("--append-system-prompt").arg(append_text);
                 }
             },
+            crate::types::SystemPrompt::File { path, append } => {
+                cmd.arg("--system-prompt")
+                    .arg(crate::transport::subprocess::read_system_prompt_file(path)?);
+                if let Some(append_text) = append {
+                    cmd.arg("--append-system-prompt").arg(append_text);
+                }
+            },
         }
     } else {
         #[allow(deprecated)]
@@ -177,6 +632,7 @@ async fn query_print_mode(
             Some(prompt) => {
                 cmd.arg("--system-prompt").arg(prompt);
             },
+            None if options.inherit_cli_system_prompt => {},
             None => {
                 cmd.arg("--system-prompt").arg("");
             },
@@ -240,25 +696,9 @@ async fn query_print_mode(
         cmd.arg("--resume").arg(resume_id);
     }
 
-    if !options.mcp_servers.is_empty() {
-        let mcp_config = serde_json::json!({
-            "mcpServers": options.mcp_servers
-        });
-        cmd.arg("--mcp-config").arg(mcp_config.to_string());
-    }
+    crate::transport::subprocess::apply_mcp_config(&mut cmd, &options)?;
 
-    // Extra arguments
-    for (key, value) in &options.extra_args {
-        let flag = if key.starts_with("--") || key.starts_with("-") {
-            key.clone()
-        } else {
-            format!("--{key}")
-        };
-        cmd.arg(&flag);
-        if let Some(val) = value {
-            cmd.arg(val);
-        }
-    }
+    crate::transport::subprocess::apply_extra_args(&mut cmd, &options);
 
     // Add the prompt with --print
     cmd.arg("--print").arg("--").arg(&prompt);
@@ -268,40 +708,42 @@ async fn query_print_mode(
         .stderr(std::process::Stdio::piped());
 
     // Handle max_output_tokens (priority: option > env var)
-    // Maximum safe value is 32000, values above this may cause issues
-    if let Some(max_tokens) = options.max_output_tokens {
-        // Option takes priority - validate and cap at 32000
-        let capped = max_tokens.clamp(1, 32000);
-        cmd.env("CLAUDE_CODE_MAX_OUTPUT_TOKENS", capped.to_string());
-        debug!("Setting max_output_tokens from option: {}", capped);
-    } else {
-        // Fall back to environment variable handling
-        if let Ok(current_value) = std::env::var("CLAUDE_CODE_MAX_OUTPUT_TOKENS") {
-            if let Ok(tokens) = current_value.parse::<u32>() {
-                if tokens > 32000 {
-                    warn!(
-                        "CLAUDE_CODE_MAX_OUTPUT_TOKENS={} exceeds maximum safe value of 32000, overriding to 32000",
-                        tokens
-                    );
-                    cmd.env("CLAUDE_CODE_MAX_OUTPUT_TOKENS", "32000");
-                }
-            } else {
-                warn!(
-                    "Invalid CLAUDE_CODE_MAX_OUTPUT_TOKENS value: {}, setting to 8192",
-                    current_value
-                );
-                cmd.env("CLAUDE_CODE_MAX_OUTPUT_TOKENS", "8192");
-            }
-        }
+    let mut warnings = crate::transport::subprocess::ConfigWarnings::new(&options);
+    crate::transport::subprocess::apply_max_output_tokens(&mut cmd, &options, &mut warnings);
+    warnings.finish()?;
+
+    if let Some(ref format) = options.output_format {
+        crate::transport::subprocess::apply_output_format(&mut cmd, format)?;
+    }
+
+    crate::transport::subprocess::apply_sdk_identity(&mut cmd, &options);
+
+    if let Some(ref request_id) = options.request_id {
+        cmd.env(crate::types::REQUEST_ID_ENV_VAR, request_id);
+    }
+
+    for (key, value) in &options.env {
+        cmd.env(key, value);
+    }
+    if let Some(ref path) = options.extra_env_file {
+        crate::env_file::apply_env_file(&mut cmd, path, &options.env)?;
     }
 
     info!("Starting Claude CLI with --print mode");
-    debug!("Command: {:?}", cmd);
+    debug!(
+        "Command: {:?} {:?}",
+        cmd.as_std().get_program(),
+        cmd.as_std().get_args().collect::<Vec<_>>()
+    );
 
     if let Some(user) = options.user.as_deref() {
         crate::transport::subprocess::apply_process_user(&mut cmd, user)?;
     }
 
+    if let Some(ref limits) = options.resource_limits {
+        crate::transport::subprocess::apply_resource_limits(&mut cmd, limits)?;
+    }
+
     let mut child = cmd.spawn().map_err(crate::SdkError::ProcessError)?;
 
     let stdout = child
@@ -333,13 +775,23 @@ async fn query_print_mode(
 
     // Clone tx for cleanup task
     let tx_cleanup = tx.clone();
+    let stdout_handle = handle.clone();
+    let on_tool_use = options.on_tool_use.clone();
+    // Dropped when the stdout handler finishes, so the cleanup task releases
+    // its sender and the stream ends once the CLI exits
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
 
     // Spawn stdout handler
     tokio::spawn(async move {
+        let _done = done_tx;
         let reader = BufReader::new(stdout);
         let mut lines = reader.lines();
 
-        while let Ok(Some(line)) = lines.next_line().await {
+        // Stop reading on interrupt rather than waiting for the pipe to close
+        while let Ok(Some(line)) = tokio::select! {
+            line = lines.next_line() => line,
+            _ = stdout_handle.interrupted() => Ok(None),
+        } {
             if line.trim().is_empty() {
                 continue;
             }
@@ -351,6 +803,13 @@ async fn query_print_mode(
                 Ok(json) => {
                     match crate::message_parser::parse_message(json) {
                         Ok(Some(message)) => {
+                            crate::transport::subprocess::observe_tool_uses(&on_tool_use, &message);
+                            // A rate-limited Result still follows its error
+                            if let Some(err) = SdkError::rate_limited(&message)
+                                && tx.send(Err(err)).await.is_err()
+                            {
+                                break;
+                            }
                             if tx.send(Ok(message)).await.is_err() {
                                 break;
                             }
@@ -371,6 +830,11 @@ async fn query_print_mode(
             }
         }
 
+        // An interrupted query ends quietly; the cleanup task kills the process
+        if stdout_handle.is_interrupted() {
+            return;
+        }
+
         // Wait for process to complete and ensure cleanup
         let mut child = child_clone.lock().await;
         match child.wait().await {
@@ -390,9 +854,16 @@ async fn query_print_mode(
     });
 
     // Spawn cleanup task that will ensure process is killed when stream is dropped
+    // or the query is interrupted
     tokio::spawn(async move {
-        // Wait for the channel to be closed (all receivers dropped)
-        tx_cleanup.closed().await;
+        tokio::select! {
+            // Wait for the channel to be closed (all receivers dropped)
+            _ = tx_cleanup.closed() => {},
+            _ = done_rx => {},
+            _ = handle.interrupted() => {
+                info!("Interrupting Claude CLI query");
+            },
+        }
 
         // Kill the process if it's still running
         let mut child = child.lock().await;
@@ -403,7 +874,7 @@ async fn query_print_mode(
             },
             Ok(None) => {
                 // Process still running, kill it
-                info!("Killing Claude CLI process on stream drop");
+                info!("Killing Claude CLI process");
                 if let Err(e) = child.kill().await {
                     warn!("Failed to kill Claude CLI process: {}", e);
                 } else {
@@ -425,6 +896,8 @@ async fn query_print_mode(
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(unix)]
+    use crate::transport::subprocess::tests::fake_cli;
 
     #[test]
     fn test_query_input_from_string() {
@@ -444,6 +917,286 @@ mod tests {
         }
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_warmup_reports_cache_creation() {
+        // Fake CLI: answers with cache usage only for a single-turn query
+        // carrying the configured system prompt
+        let dir = tempfile::tempdir().unwrap();
+        let path = fake_cli(
+            dir.path(),
+            r#"case "$*" in
+  *"--system-prompt long house rules"*"--max-turns 1"*)
+    echo '{"type":"assistant","message":{"content":[{"type":"text","text":"OK"}]}}'
+    echo '{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":false,"num_turns":1,"session_id":"s","usage":{"input_tokens":4,"cache_creation_input_tokens":2048,"cache_read_input_tokens":0}}' ;;
+  *)
+    echo '{"type":"result","subtype":"error_during_execution","duration_ms":1,"duration_api_ms":1,"is_error":true,"num_turns":1,"session_id":"s"}' ;;
+esac
+"#,
+        );
+
+        let options = ClaudeCodeOptions::builder()
+            .cli_path(&path)
+            .system_prompt("long house rules")
+            .build();
+        let usage = warmup(options).await.unwrap();
+        assert_eq!(
+            usage,
+            CacheUsage {
+                cache_creation_input_tokens: 2048,
+                cache_read_input_tokens: 0,
+            }
+        );
+
+        let other_prompt = ClaudeCodeOptions::builder()
+            .cli_path(&path)
+            .system_prompt("something else")
+            .build();
+        assert!(matches!(
+            warmup(other_prompt).await,
+            Err(SdkError::CliError { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_query_structured() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        struct Capital {
+            city: String,
+        }
+
+        // Fake CLI: structured output only when a JSON schema was requested
+        let dir = tempfile::tempdir().unwrap();
+        let path = fake_cli(
+            dir.path(),
+            r#"case "$*" in
+  *'"required":["country"]'*)
+    echo '{"type":"result","subtype":"error_max_structured_output_retries","duration_ms":1,"duration_api_ms":1,"is_error":true,"num_turns":3,"session_id":"s"}' ;;
+  *"--json-schema"*)
+    echo '{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":false,"num_turns":1,"session_id":"s","structured_output":{"city":"Paris"}}' ;;
+  *)
+    echo '{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":false,"num_turns":1,"session_id":"s","result":"Paris"}' ;;
+esac
+"#,
+        );
+
+        let options = ClaudeCodeOptions::builder()
+            .cli_path(&path)
+            .output_format(serde_json::json!({
+                "type": "json_schema",
+                "schema": {"type": "object", "required": ["city"]}
+            }))
+            .build();
+        let capital: Capital = query_structured("Capital of France?", options.clone())
+            .await
+            .unwrap();
+        assert_eq!(capital.city, "Paris");
+
+        // Valid JSON that does not match the target type
+        let err = query_structured::<Vec<String>>("Capital of France?", options)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SdkError::MessageParseError { ref raw, .. } if raw == r#"{"city":"Paris"}"#
+        ));
+
+        // The CLI gives up on an answer that never matches the schema
+        let schema = ClaudeCodeOptions::builder()
+            .cli_path(&path)
+            .output_format(serde_json::json!({
+                "type": "json_schema",
+                "schema": {"type": "object", "required": ["country"]}
+            }))
+            .build();
+        let err = query_structured::<Capital>("Capital of France?", schema)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SdkError::CliError { code: Some(ref code), .. }
+                if code == "error_max_structured_output_retries"
+        ));
+
+        let unset = ClaudeCodeOptions::builder().cli_path(&path).build();
+        assert!(matches!(
+            query_structured::<Capital>("Capital of France?", unset).await,
+            Err(SdkError::ConfigError(_))
+        ));
+
+        // Plain text output is rejected before spawning the CLI
+        let text = ClaudeCodeOptions::builder()
+            .cli_path(&path)
+            .output_format(serde_json::json!({"type": "text"}))
+            .build();
+        assert!(matches!(
+            query_structured::<Capital>("Capital of France?", text).await,
+            Err(SdkError::ConfigError(_))
+        ));
+
+        // A result without structured output names the missing field
+        let missing: Message = serde_json::from_str(
+            r#"{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":false,"num_turns":1,"session_id":"s"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            parse_structured_output::<Capital>(&missing),
+            Err(SdkError::MessageParseError { path: Some(ref path), .. }) if path == "structured_output"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_query_collect() {
+        // Fake CLI: a subagent result followed by the top-level one, or no
+        // result at all when resuming session "gone"
+        let dir = tempfile::tempdir().unwrap();
+        let path = fake_cli(
+            dir.path(),
+            r#"case "$*" in
+  *"--resume gone"*)
+    echo '{"type":"assistant","message":{"role":"assistant","content":[{"type":"text","text":"Hi"}]}}' ;;
+  *)
+    echo '{"type":"assistant","message":{"role":"assistant","content":[{"type":"text","text":"Hi"}]}}'
+    echo '{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":false,"num_turns":1,"session_id":"sub","parent_tool_use_id":"toolu_1"}'
+    echo '{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":false,"num_turns":1,"session_id":"sess-1","total_cost_usd":0.25,"result":"Hi"}' ;;
+esac
+"#,
+        );
+
+        let base = ClaudeCodeOptions::builder()
+            .cli_path(&path)
+            .continue_conversation(true)
+            .build();
+        let outcome = query_collect("Hello", base.clone()).await.unwrap();
+        assert_eq!(outcome.messages().len(), 3);
+        assert_eq!(outcome.session_id(), "sess-1");
+        assert_eq!(outcome.total_cost_usd(), Some(0.25));
+        assert!(matches!(
+            outcome.result(),
+            Message::Result { result: Some(r), .. } if r == "Hi"
+        ));
+
+        let resumed = outcome.resume_options(base);
+        assert_eq!(resumed.resume.as_deref(), Some("sess-1"));
+        assert!(!resumed.continue_conversation);
+        assert_eq!(resumed.cli_path.as_deref(), Some(path.as_path()));
+
+        let gone = ClaudeCodeOptions::builder()
+            .cli_path(&path)
+            .resume("gone")
+            .build();
+        assert!(matches!(
+            query_collect("Hello", gone).await,
+            Err(SdkError::UnexpectedStreamEnd)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_print_mode_applies_env_and_env_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fake_cli(
+            dir.path(),
+            r#"echo '{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":false,"num_turns":1,"session_id":"s","result":"'"$FROM_FILE $OVERRIDDEN"'"}'
+"#,
+        );
+        let env_file = dir.path().join("claude.env");
+        std::fs::write(&env_file, "FROM_FILE=file\nOVERRIDDEN=file\n").unwrap();
+
+        let options = ClaudeCodeOptions::builder()
+            .cli_path(&path)
+            .extra_env_from_file(&env_file)
+            .env("OVERRIDDEN", "explicit")
+            .build();
+        let outcome = query_collect("Hello", options).await.unwrap();
+        assert!(matches!(
+            outcome.result(),
+            Message::Result { result: Some(r), .. } if r == "file explicit"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_query_with_retry() {
+        use futures::StreamExt;
+
+        // Fake CLI: crashes without output on its first two runs
+        let dir = tempfile::tempdir().unwrap();
+        let runs = dir.path().join("runs");
+        let script = format!(
+            r#"echo run >> {runs}
+if [ "$(wc -l < {runs})" -le 2 ]; then kill -KILL $$; fi
+echo '{{"type":"assistant","message":{{"role":"assistant","content":[{{"type":"text","text":"Hi"}}]}}}}'
+"#,
+            runs = runs.display()
+        );
+        let path = fake_cli(dir.path(), &script);
+
+        let retry = |max_retries| RetryConfig {
+            max_retries,
+            initial_delay: std::time::Duration::from_millis(1),
+            max_delay: std::time::Duration::from_millis(10),
+            backoff_multiplier: 2.0,
+            jitter_factor: 0.5,
+        };
+        let options = ClaudeCodeOptions::builder().cli_path(&path).build();
+
+        match query_with_retry("Hello", options.clone(), retry(1)).await {
+            Err(SdkError::RetryFailed {
+                attempts,
+                last_error,
+            }) => {
+                assert_eq!(attempts, 2);
+                assert!(matches!(
+                    *last_error,
+                    SdkError::ProcessExited { code: None }
+                ));
+            },
+            Err(other) => panic!("expected RetryFailed, got {other:?}"),
+            Ok(_) => panic!("expected RetryFailed"),
+        }
+
+        std::fs::remove_file(&runs).unwrap();
+        let messages = query_with_retry("Hello", options, retry(3)).await.unwrap();
+        let messages: Vec<_> = messages.collect().await;
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(messages[0], Ok(Message::Assistant { .. })));
+        assert_eq!(std::fs::read_to_string(&runs).unwrap().lines().count(), 3);
+
+        // Permanent errors are not retried, including the CLI exiting on an
+        // error of its own such as rejected credentials
+        let rejected = fake_cli(dir.path(), "echo 'Invalid API key' >&2\nexit 1\n");
+        let options = ClaudeCodeOptions::builder().cli_path(&rejected).build();
+        match query_with_retry("Hello", options, retry(3)).await {
+            Err(err) => assert!(
+                matches!(err, SdkError::ProcessExited { code: Some(1) }),
+                "{err:?}"
+            ),
+            Ok(_) => panic!("expected the exit to be reported"),
+        }
+
+        let missing = ClaudeCodeOptions::builder()
+            .cli_path(dir.path().join("missing"))
+            .build();
+        match query_with_retry("Hello", missing, retry(3)).await {
+            Err(err) => assert!(matches!(err, SdkError::ProcessError(_)), "{err:?}"),
+            Ok(_) => panic!("expected a spawn error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_rejects_non_verbose() {
+        // Fails before the CLI is looked up or spawned
+        let options = ClaudeCodeOptions::builder()
+            .cli_path("/nonexistent/claude")
+            .verbose(false)
+            .build();
+        let result = query_interruptible("Hello", Some(options)).await;
+        assert!(matches!(result, Err(SdkError::ConfigError(msg)) if msg.contains("--verbose")));
+    }
+
     #[test]
     fn test_extra_args_formatting() {
         use std::collections::HashMap;
@@ -465,4 +1218,76 @@ mod tests {
         assert!(options.extra_args.contains_key("--already-dashed"));
         assert!(options.extra_args.contains_key("-s"));
     }
+
+    /// Writes a fake CLI that emits one assistant message and then hangs
+    #[cfg(unix)]
+    fn hanging_cli(dir: &std::path::Path) -> std::path::PathBuf {
+        fake_cli(
+            dir,
+            r#"echo "$$" > "$(dirname "$0")/pid"
+echo '{"type":"assistant","message":{"content":[{"type":"text","text":"working"}]}}'
+exec sleep 30
+"#,
+        )
+    }
+
+    #[cfg(unix)]
+    fn process_alive(dir: &std::path::Path) -> bool {
+        let pid: i32 = std::fs::read_to_string(dir.join("pid"))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        // Signal 0 only checks that the process exists
+        unsafe { libc::kill(pid, 0) == 0 }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_interrupt_ends_stream_promptly() {
+        use futures::StreamExt;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = ClaudeCodeOptions::builder()
+            .cli_path(hanging_cli(dir.path()))
+            .build();
+
+        let (stream, handle) = query_interruptible("hello", Some(options)).await.unwrap();
+        let mut stream = Box::pin(stream);
+        let first = stream.next().await.unwrap().unwrap();
+        assert!(matches!(first, Message::Assistant { .. }));
+
+        handle.interrupt();
+        assert!(handle.is_interrupted());
+        let next = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .expect("stream should end promptly after interrupt");
+        assert!(next.is_none(), "interrupt should end the stream quietly");
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!process_alive(dir.path()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_dropping_stream_kills_process() {
+        use futures::StreamExt;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = ClaudeCodeOptions::builder()
+            .cli_path(hanging_cli(dir.path()))
+            .build();
+
+        let (stream, handle) = query_interruptible("hello", Some(options)).await.unwrap();
+        let mut stream = Box::pin(stream);
+        stream.next().await.unwrap().unwrap();
+        assert!(process_alive(dir.path()));
+
+        drop(stream);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!process_alive(dir.path()));
+        assert!(!handle.is_interrupted());
+    }
 }
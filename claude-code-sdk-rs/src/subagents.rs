@@ -0,0 +1,283 @@
+//! Attribution of messages to subagents
+//!
+//! Messages produced by a subagent carry the `parent_tool_use_id` of the Task
+//! tool call that spawned it. [`SubagentTracker`] follows those Task calls so
+//! each sidechain message can be tied back to the subagent type and description
+//! it was launched with, and [`SubagentTracker::group`] splits a transcript
+//! into main-agent and per-subagent messages.
+
+use crate::types::{ContentBlock, Message, MessageSource};
+use std::collections::HashMap;
+
+/// Tool names the CLI uses to launch a subagent
+const SUBAGENT_TOOLS: &[&str] = &["Task", "Agent"];
+
+/// A subagent launched during the conversation
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubagentInfo {
+    /// ID of the Task tool use that spawned the subagent
+    pub tool_use_id: String,
+    /// Subagent type requested in the Task input (e.g. `general-purpose`)
+    pub subagent_type: Option<String>,
+    /// Short task description from the Task input
+    pub description: Option<String>,
+    /// Number of sidechain messages attributed to the subagent so far
+    pub message_count: usize,
+    /// Whether the Task tool result has been returned to the parent agent
+    pub finished: bool,
+}
+
+impl SubagentInfo {
+    fn new(tool_use_id: &str) -> Self {
+        Self {
+            tool_use_id: tool_use_id.to_string(),
+            subagent_type: None,
+            description: None,
+            message_count: 0,
+            finished: false,
+        }
+    }
+}
+
+/// Messages produced by one subagent
+#[derive(Debug, Clone)]
+pub struct SubagentMessages {
+    /// The subagent, as known when grouping finished
+    pub info: SubagentInfo,
+    /// Messages in arrival order
+    pub messages: Vec<Message>,
+}
+
+/// Messages split by the agent that produced them
+#[derive(Debug, Clone, Default)]
+pub struct MessageGroups {
+    /// Messages of the main agent, including System and Result messages
+    pub main: Vec<Message>,
+    /// Subagent messages, in the order the subagents were launched
+    pub subagents: Vec<SubagentMessages>,
+}
+
+/// Follows Task tool calls to attribute messages to subagents
+///
+/// # Example
+///
+/// ```rust
+/// # use nexus_claude::{Message, MessageSource, SubagentTracker};
+/// # fn example(messages: Vec<Message>) {
+/// let mut tracker = SubagentTracker::new();
+/// for message in &messages {
+///     if let MessageSource::Subagent { parent_tool_use_id } = tracker.observe(message) {
+///         let info = tracker.subagent(&parent_tool_use_id).unwrap();
+///         println!("[{}] {:?}", info.subagent_type.as_deref().unwrap_or("subagent"), message);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SubagentTracker {
+    subagents: Vec<SubagentInfo>,
+    index: HashMap<String, usize>,
+}
+
+impl SubagentTracker {
+    /// Create a new tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a message and return the agent that produced it
+    pub fn observe(&mut self, message: &Message) -> MessageSource {
+        let blocks: &[ContentBlock] = match message {
+            Message::Assistant { message, .. } => &message.content,
+            Message::User { message, .. } => message.content_blocks.as_deref().unwrap_or(&[]),
+            _ => &[],
+        };
+
+        for block in blocks {
+            match block {
+                ContentBlock::ToolUse(tool_use)
+                    if SUBAGENT_TOOLS.contains(&tool_use.name.as_str()) =>
+                {
+                    let field = |name: &str| {
+                        tool_use
+                            .input
+                            .get(name)
+                            .and_then(|v| v.as_str())
+                            .map(String::from)
+                    };
+                    let info = self.entry(&tool_use.id);
+                    info.subagent_type = field("subagent_type");
+                    info.description = field("description");
+                },
+                ContentBlock::ToolResult(result) => {
+                    if let Some(&i) = self.index.get(&result.tool_use_id) {
+                        self.subagents[i].finished = true;
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        let source = message.source();
+        if let MessageSource::Subagent { parent_tool_use_id } = &source {
+            // Subagents can be seen before their Task call (e.g. mid-stream reconnects)
+            self.entry(parent_tool_use_id).message_count += 1;
+        }
+        source
+    }
+
+    /// Look up a subagent by the ID of the Task tool use that spawned it
+    pub fn subagent(&self, tool_use_id: &str) -> Option<&SubagentInfo> {
+        self.index.get(tool_use_id).map(|&i| &self.subagents[i])
+    }
+
+    /// All subagents seen so far, in launch order
+    pub fn subagents(&self) -> &[SubagentInfo] {
+        &self.subagents
+    }
+
+    /// Split messages by the agent that produced them
+    pub fn group(messages: impl IntoIterator<Item = Message>) -> MessageGroups {
+        let mut tracker = Self::new();
+        let mut main = Vec::new();
+        let mut by_subagent: HashMap<String, Vec<Message>> = HashMap::new();
+
+        for message in messages {
+            match tracker.observe(&message) {
+                MessageSource::Main => main.push(message),
+                MessageSource::Subagent { parent_tool_use_id } => by_subagent
+                    .entry(parent_tool_use_id)
+                    .or_default()
+                    .push(message),
+            }
+        }
+
+        let subagents = tracker
+            .subagents
+            .into_iter()
+            .map(|info| SubagentMessages {
+                messages: by_subagent.remove(&info.tool_use_id).unwrap_or_default(),
+                info,
+            })
+            .collect();
+
+        MessageGroups { main, subagents }
+    }
+
+    fn entry(&mut self, tool_use_id: &str) -> &mut SubagentInfo {
+        let i = match self.index.get(tool_use_id) {
+            Some(&i) => i,
+            None => {
+                self.subagents.push(SubagentInfo::new(tool_use_id));
+                self.index
+                    .insert(tool_use_id.to_string(), self.subagents.len() - 1);
+                self.subagents.len() - 1
+            },
+        };
+        &mut self.subagents[i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        AssistantMessage, ContentValue, TextContent, ToolResultContent, ToolUseContent, UserMessage,
+    };
+    use serde_json::json;
+
+    fn assistant(parent: Option<&str>, content: Vec<ContentBlock>) -> Message {
+        Message::Assistant {
+            message: AssistantMessage { content },
+            parent_tool_use_id: parent.map(String::from),
+        }
+    }
+
+    fn text(text: &str) -> ContentBlock {
+        ContentBlock::Text(TextContent {
+            text: text.to_string(),
+        })
+    }
+
+    fn transcript() -> Vec<Message> {
+        vec![
+            assistant(
+                None,
+                vec![
+                    text("Delegating the search"),
+                    ContentBlock::ToolUse(ToolUseContent {
+                        id: "toolu_task".to_string(),
+                        name: "Task".to_string(),
+                        input: json!({
+                            "subagent_type": "Explore",
+                            "description": "Find config loaders",
+                            "prompt": "Look for config loading code",
+                        }),
+                    }),
+                ],
+            ),
+            assistant(Some("toolu_task"), vec![text("Searching src/")]),
+            assistant(Some("toolu_task"), vec![text("Found src/config.rs")]),
+            Message::User {
+                message: UserMessage {
+                    content: String::new(),
+                    content_blocks: Some(vec![ContentBlock::ToolResult(ToolResultContent {
+                        tool_use_id: "toolu_task".to_string(),
+                        content: Some(ContentValue::Text("src/config.rs".to_string())),
+                        is_error: None,
+                    })]),
+                },
+                parent_tool_use_id: None,
+            },
+            assistant(None, vec![text("The loader lives in src/config.rs")]),
+        ]
+    }
+
+    #[test]
+    fn test_messages_are_attributed_to_subagent_or_main() {
+        let mut tracker = SubagentTracker::new();
+        let sources: Vec<_> = transcript().iter().map(|m| tracker.observe(m)).collect();
+
+        let subagent = MessageSource::Subagent {
+            parent_tool_use_id: "toolu_task".to_string(),
+        };
+        assert_eq!(
+            sources,
+            vec![
+                MessageSource::Main,
+                subagent.clone(),
+                subagent,
+                MessageSource::Main,
+                MessageSource::Main,
+            ]
+        );
+
+        let info = tracker.subagent("toolu_task").unwrap();
+        assert_eq!(info.subagent_type.as_deref(), Some("Explore"));
+        assert_eq!(info.description.as_deref(), Some("Find config loaders"));
+        assert_eq!(info.message_count, 2);
+        assert!(info.finished);
+    }
+
+    #[test]
+    fn test_group_splits_main_and_subagent_messages() {
+        let groups = SubagentTracker::group(transcript());
+
+        assert_eq!(groups.main.len(), 3);
+        assert!(groups.main.iter().all(Message::is_top_level));
+        assert_eq!(groups.subagents.len(), 1);
+        assert_eq!(groups.subagents[0].info.tool_use_id, "toolu_task");
+        assert_eq!(groups.subagents[0].messages.len(), 2);
+    }
+
+    #[test]
+    fn test_sidechain_without_task_call_is_still_tracked() {
+        let mut tracker = SubagentTracker::new();
+        tracker.observe(&assistant(Some("toolu_unknown"), vec![text("hi")]));
+
+        let info = tracker.subagent("toolu_unknown").unwrap();
+        assert_eq!(info.subagent_type, None);
+        assert_eq!(info.message_count, 1);
+        assert!(!info.finished);
+    }
+}
@@ -0,0 +1,197 @@
+//! Append-only text deltas from assistant messages
+//!
+//! Without `include_partial_messages` the CLI only emits whole assistant
+//! messages, and may resend a message with more text appended. [`text_deltas`]
+//! turns such a stream into the text that is new since the last emit, which is
+//! what an append-only UI needs for a typing effect.
+
+use crate::{
+    errors::Result,
+    types::{ContentBlock, Message},
+};
+use futures::stream::{Stream, StreamExt};
+
+/// Tracks assistant text and computes the newly appended part of each message
+#[derive(Debug, Clone, Default)]
+pub struct TextDeltaTracker {
+    last_text: String,
+}
+
+impl TextDeltaTracker {
+    /// Create a new tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a message and return the text to append, if any
+    ///
+    /// When a message repeats the previous one with more text, only the suffix
+    /// is returned; an exact resend yields nothing. A top-level `Result` message
+    /// ends the turn and resets the tracker. Subagent (sidechain) messages,
+    /// including their `Result`, are ignored.
+    pub fn observe(&mut self, message: &Message) -> Option<String> {
+        match message {
+            Message::Assistant {
+                message,
+                parent_tool_use_id: None,
+            } => {
+                let text: String = message
+                    .content
+                    .iter()
+                    .filter_map(|block| match block {
+                        ContentBlock::Text(text) => Some(text.text.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                self.push_text(text)
+            },
+            Message::Result {
+                parent_tool_use_id: None,
+                ..
+            } => {
+                self.reset();
+                None
+            },
+            _ => None,
+        }
+    }
+
+    /// Feed the full text of a message and return the text to append, if any
+    pub fn push_text(&mut self, text: String) -> Option<String> {
+        let delta = match text.strip_prefix(self.last_text.as_str()) {
+            // Same message resent with (possibly) more text appended
+            Some(suffix) if !self.last_text.is_empty() => suffix.to_string(),
+            // A new message: everything is new
+            _ => text.clone(),
+        };
+        self.last_text = text;
+
+        (!delta.is_empty()).then_some(delta)
+    }
+
+    /// Forget the tracked text (e.g. at the start of a new turn)
+    pub fn reset(&mut self) {
+        self.last_text.clear();
+    }
+}
+
+/// Adapt a message stream into a stream of newly appended assistant text
+///
+/// Errors from the underlying stream are passed through unchanged.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use nexus_claude::{InteractiveClient, ClaudeCodeOptions, text_deltas};
+/// # use futures::StreamExt;
+/// # async fn example() -> nexus_claude::Result<()> {
+/// let mut client = InteractiveClient::new(ClaudeCodeOptions::default())?;
+/// client.connect().await?;
+/// client.send_message("Tell me a story".to_string()).await?;
+///
+/// let stream = client.receive_response_stream().await;
+/// let mut deltas = Box::pin(text_deltas(stream));
+/// while let Some(delta) = deltas.next().await {
+///     print!("{}", delta?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn text_deltas<S>(messages: S) -> impl Stream<Item = Result<String>>
+where
+    S: Stream<Item = Result<Message>>,
+{
+    let mut tracker = TextDeltaTracker::new();
+    messages.filter_map(move |item| {
+        let delta = match item {
+            Ok(message) => tracker.observe(&message).map(Ok),
+            Err(e) => Some(Err(e)),
+        };
+        futures::future::ready(delta)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::SdkError;
+
+    #[test]
+    fn test_growing_messages_yield_suffixes() {
+        let mut tracker = TextDeltaTracker::new();
+        let deltas: Vec<_> = ["Hel", "Hello", "Hello, wörld", "Hello, wörld!"]
+            .iter()
+            .map(|text| tracker.observe(&Message::assistant(*text)))
+            .collect();
+
+        assert_eq!(
+            deltas,
+            vec![
+                Some("Hel".to_string()),
+                Some("lo".to_string()),
+                Some(", wörld".to_string()),
+                Some("!".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_full_resend_yields_nothing_and_new_message_yields_all() {
+        let mut tracker = TextDeltaTracker::new();
+        assert_eq!(
+            tracker.observe(&Message::assistant("First answer")),
+            Some("First answer".to_string())
+        );
+        assert_eq!(tracker.observe(&Message::assistant("First answer")), None);
+        assert_eq!(
+            tracker.observe(&Message::assistant("Unrelated")),
+            Some("Unrelated".to_string())
+        );
+
+        tracker.observe(&Message::result_success("s"));
+        assert_eq!(
+            tracker.observe(&Message::assistant("Unrelated")),
+            Some("Unrelated".to_string())
+        );
+    }
+
+    #[test]
+    fn test_subagent_result_does_not_reset() {
+        let mut subagent_result = Message::result_success("s");
+        if let Message::Result {
+            parent_tool_use_id, ..
+        } = &mut subagent_result
+        {
+            *parent_tool_use_id = Some("toolu_task".to_string());
+        }
+
+        let mut tracker = TextDeltaTracker::new();
+        assert_eq!(
+            tracker.observe(&Message::assistant("Delegating")),
+            Some("Delegating".to_string())
+        );
+        assert_eq!(tracker.observe(&subagent_result), None);
+        assert_eq!(
+            tracker.observe(&Message::assistant("Delegating, done")),
+            Some(", done".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_text_deltas_stream() {
+        let messages = futures::stream::iter(vec![
+            Ok(Message::assistant("The")),
+            Ok(Message::assistant("The quick")),
+            Err(SdkError::Timeout { seconds: 1 }),
+            Ok(Message::assistant("The quick fox")),
+            Ok(Message::result_success("s")),
+        ]);
+
+        let deltas: Vec<_> = text_deltas(messages).collect().await;
+        assert_eq!(deltas.len(), 4);
+        assert_eq!(deltas[0].as_ref().unwrap(), "The");
+        assert_eq!(deltas[1].as_ref().unwrap(), " quick");
+        assert!(deltas[2].is_err());
+        assert_eq!(deltas[3].as_ref().unwrap(), " fox");
+    }
+}
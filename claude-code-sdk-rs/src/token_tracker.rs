@@ -3,6 +3,8 @@
 //! This module provides utilities for monitoring token consumption and managing budgets
 //! to help control costs when using Claude Code.
 
+use crate::types::{ClaudeCodeOptions, Message, ResultPolicy, SystemPrompt, TokenUsage};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::warn;
@@ -14,6 +16,10 @@ pub struct TokenUsageTracker {
     pub total_input_tokens: u64,
     /// Total output tokens consumed
     pub total_output_tokens: u64,
+    /// Total input tokens written to the prompt cache
+    pub total_cache_creation_tokens: u64,
+    /// Total input tokens served from the prompt cache
+    pub total_cache_read_tokens: u64,
     /// Total cost in USD
     pub total_cost_usd: f64,
     /// Number of sessions/queries completed
@@ -57,15 +63,263 @@ impl TokenUsageTracker {
         self.session_count += 1;
     }
 
+    /// Update statistics with a turn's reported usage
+    ///
+    /// Counts `input_tokens` and `output_tokens`, like [`update`](Self::update);
+    /// prompt cache reads and writes are not added to the input total but
+    /// kept in their own totals.
+    pub fn record(&mut self, usage: &TokenUsage, cost_usd: f64) {
+        self.total_cache_creation_tokens += usage.cache_creation_input_tokens.unwrap_or(0);
+        self.total_cache_read_tokens += usage.cache_read_input_tokens.unwrap_or(0);
+        self.update(usage.input_tokens, usage.output_tokens, cost_usd);
+    }
+
+    /// Estimated cost of the accumulated tokens at `model`'s list prices
+    ///
+    /// Uses [`PricingTable::default`]; returns `None` for unknown models.
+    pub fn estimated_cost(&self, model: &str) -> Option<f64> {
+        self.estimated_cost_with(model, &PricingTable::default())
+    }
+
+    /// Estimated cost of the accumulated tokens at the rates in `pricing`
+    pub fn estimated_cost_with(&self, model: &str, pricing: &PricingTable) -> Option<f64> {
+        let usage = TokenUsage {
+            input_tokens: self.total_input_tokens,
+            output_tokens: self.total_output_tokens,
+            cache_creation_input_tokens: Some(self.total_cache_creation_tokens),
+            cache_read_input_tokens: Some(self.total_cache_read_tokens),
+            ..Default::default()
+        };
+        pricing.cost(model, &usage)
+    }
+
     /// Reset all statistics to zero
     pub fn reset(&mut self) {
         self.total_input_tokens = 0;
         self.total_output_tokens = 0;
+        self.total_cache_creation_tokens = 0;
+        self.total_cache_read_tokens = 0;
         self.total_cost_usd = 0.0;
         self.session_count = 0;
     }
 }
 
+/// Input token prices of a model, in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputRate {
+    /// Uncached input tokens
+    pub base: f64,
+    /// Tokens written to the prompt cache
+    pub cache_write: f64,
+    /// Tokens served from the prompt cache
+    pub cache_read: f64,
+}
+
+impl InputRate {
+    /// Rate with the usual cache pricing: writes at 1.25x, reads at 0.1x
+    pub fn new(per_million: f64) -> Self {
+        Self {
+            base: per_million,
+            cache_write: per_million * 1.25,
+            cache_read: per_million * 0.1,
+        }
+    }
+}
+
+/// Output token price of a model, in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputRate(pub f64);
+
+/// Per-model token prices
+///
+/// Rates are keyed by model id or id prefix: a key matches a model that
+/// equals it or continues it after a `-`, and the longest matching key wins,
+/// so `claude-sonnet-4-5` covers `claude-sonnet-4-5-20250929`. The default
+/// table holds the list prices of the current Claude models and the
+/// `opus`/`sonnet`/`haiku` aliases.
+///
+/// # Example
+///
+/// ```rust
+/// # use nexus_claude::{InputRate, OutputRate, PricingTable};
+/// let pricing = PricingTable::default()
+///     .with_rate("my-fine-tune", InputRate::new(2.0), OutputRate(8.0));
+/// assert!(pricing.rate("claude-sonnet-4-5-20250929").is_some());
+/// assert!(pricing.rate("my-fine-tune").is_some());
+/// ```
+#[derive(Debug, Clone)]
+pub struct PricingTable {
+    rates: HashMap<String, (InputRate, OutputRate)>,
+}
+
+impl PricingTable {
+    /// Create a table without any rates
+    pub fn empty() -> Self {
+        Self {
+            rates: HashMap::new(),
+        }
+    }
+
+    /// Set the rates of `model`, replacing any previous ones
+    pub fn with_rate(
+        mut self,
+        model: impl Into<String>,
+        input: InputRate,
+        output: OutputRate,
+    ) -> Self {
+        self.rates.insert(model.into(), (input, output));
+        self
+    }
+
+    /// Rates applying to `model`, if any key matches it
+    pub fn rate(&self, model: &str) -> Option<(InputRate, OutputRate)> {
+        self.rates
+            .iter()
+            .filter(|(key, _)| {
+                model
+                    .strip_prefix(key.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+            })
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, rates)| *rates)
+    }
+
+    /// Cost in USD of `usage` at `model`'s rates
+    pub fn cost(&self, model: &str, usage: &TokenUsage) -> Option<f64> {
+        let (input, OutputRate(output)) = self.rate(model)?;
+        let per_token = |tokens: u64, rate: f64| tokens as f64 * rate / 1_000_000.0;
+        Some(
+            per_token(usage.input_tokens, input.base)
+                + per_token(
+                    usage.cache_creation_input_tokens.unwrap_or(0),
+                    input.cache_write,
+                )
+                + per_token(usage.cache_read_input_tokens.unwrap_or(0), input.cache_read)
+                + per_token(usage.output_tokens, output),
+        )
+    }
+}
+
+impl Default for PricingTable {
+    fn default() -> Self {
+        let table = [
+            ("claude-opus-4-7", 5.0, 25.0),
+            ("claude-opus-4-6", 5.0, 25.0),
+            ("claude-opus-4-5", 5.0, 25.0),
+            ("claude-opus-4-1", 15.0, 75.0),
+            ("claude-opus-4", 15.0, 75.0),
+            ("claude-sonnet-4-5", 3.0, 15.0),
+            ("claude-sonnet-4", 3.0, 15.0),
+            ("claude-3-7-sonnet", 3.0, 15.0),
+            ("claude-3-5-sonnet", 3.0, 15.0),
+            ("claude-haiku-4-5", 1.0, 5.0),
+            ("claude-3-5-haiku", 0.8, 4.0),
+            ("opus", 5.0, 25.0),
+            ("sonnet", 3.0, 15.0),
+            ("haiku", 1.0, 5.0),
+        ];
+        table
+            .into_iter()
+            .fold(Self::empty(), |pricing, (model, input, output)| {
+                pricing.with_rate(model, InputRate::new(input), OutputRate(output))
+            })
+    }
+}
+
+/// Answer length assumed by [`estimate_cost`], in tokens
+pub const DEFAULT_EXPECTED_OUTPUT_TOKENS: u64 = 1_000;
+
+/// Model priced by [`estimate_cost`] when none is set or its price is unknown
+const DEFAULT_ESTIMATE_MODEL: &str = "sonnet";
+
+/// Rough token count of `text`, at about four characters per token
+pub fn estimate_tokens(text: &str) -> u64 {
+    text.chars().count().div_ceil(4) as u64
+}
+
+/// Estimate the cost in USD of sending `prompt` with `options`, before sending it
+///
+/// **This is only an estimate.** Input tokens are approximated from the
+/// length of the prompt and the configured system prompt, and the answer is
+/// assumed to be [`DEFAULT_EXPECTED_OUTPUT_TOKENS`] long. The CLI's own
+/// system prompt, tool definitions, conversation history and any tool calls
+/// are not counted, so the actual cost is usually higher. Prices come from
+/// [`PricingTable::default`]; a model without a known price (or no model) is
+/// priced as Sonnet.
+///
+/// # Example
+///
+/// ```rust
+/// # use nexus_claude::{ClaudeCodeOptions, estimate_cost};
+/// let options = ClaudeCodeOptions::builder().max_budget_usd(0.05).build();
+/// let prompt = "Review this diff";
+/// if estimate_cost(prompt, &options) > options.max_budget_usd.unwrap() {
+///     println!("prompt is likely over budget");
+/// }
+/// ```
+pub fn estimate_cost(prompt: &str, options: &ClaudeCodeOptions) -> f64 {
+    let pricing = PricingTable::default();
+    let usage = estimated_usage(prompt, options, DEFAULT_EXPECTED_OUTPUT_TOKENS);
+    let model = options.model.as_deref().unwrap_or(DEFAULT_ESTIMATE_MODEL);
+    pricing
+        .cost(model, &usage)
+        .or_else(|| pricing.cost(DEFAULT_ESTIMATE_MODEL, &usage))
+        .unwrap_or_default()
+}
+
+/// Like [`estimate_cost`], with the expected answer length and prices given
+///
+/// Returns `None` when `pricing` has no rate for the configured model (or
+/// for Sonnet, when no model is set).
+pub fn estimate_cost_with(
+    prompt: &str,
+    options: &ClaudeCodeOptions,
+    expected_output_tokens: u64,
+    pricing: &PricingTable,
+) -> Option<f64> {
+    let usage = estimated_usage(prompt, options, expected_output_tokens);
+    pricing.cost(
+        options.model.as_deref().unwrap_or(DEFAULT_ESTIMATE_MODEL),
+        &usage,
+    )
+}
+
+/// Token usage expected for sending `prompt` with `options`
+#[allow(deprecated)]
+fn estimated_usage(
+    prompt: &str,
+    options: &ClaudeCodeOptions,
+    expected_output_tokens: u64,
+) -> TokenUsage {
+    let mut input_tokens = estimate_tokens(prompt);
+    match options.system_prompt_v2 {
+        Some(SystemPrompt::String(ref text)) => input_tokens += estimate_tokens(text),
+        Some(SystemPrompt::Preset { ref append, .. }) => {
+            input_tokens += append.as_deref().map_or(0, estimate_tokens);
+        },
+        Some(SystemPrompt::File {
+            ref path,
+            ref append,
+        }) => {
+            // A missing file fails the query later; count nothing for it here
+            let text = std::fs::read_to_string(path).unwrap_or_default();
+            input_tokens += estimate_tokens(&text) + append.as_deref().map_or(0, estimate_tokens);
+        },
+        None => {
+            input_tokens += options.system_prompt.as_deref().map_or(0, estimate_tokens)
+                + options
+                    .append_system_prompt
+                    .as_deref()
+                    .map_or(0, estimate_tokens);
+        },
+    }
+    TokenUsage {
+        input_tokens,
+        output_tokens: expected_output_tokens,
+        ..Default::default()
+    }
+}
+
 /// Budget limits and alerts
 #[derive(Debug, Clone)]
 pub struct BudgetLimit {
@@ -194,6 +448,8 @@ pub struct BudgetManager {
     limit: Arc<RwLock<Option<BudgetLimit>>>,
     on_warning: Arc<RwLock<Option<BudgetWarningCallback>>>,
     warning_fired: Arc<RwLock<bool>>,
+    pricing: Arc<RwLock<Option<PricingTable>>>,
+    cost_by_model: Arc<RwLock<HashMap<String, f64>>>,
 }
 
 impl BudgetManager {
@@ -204,6 +460,8 @@ impl BudgetManager {
             limit: Arc::new(RwLock::new(None)),
             on_warning: Arc::new(RwLock::new(None)),
             warning_fired: Arc::new(RwLock::new(false)),
+            pricing: Arc::new(RwLock::new(None)),
+            cost_by_model: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -224,20 +482,114 @@ impl BudgetManager {
         *self.warning_fired.write().await = false;
     }
 
+    /// Price turns that report no cost with `pricing`
+    ///
+    /// Without a pricing table such turns count as free.
+    pub async fn set_pricing(&self, pricing: PricingTable) {
+        *self.pricing.write().await = Some(pricing);
+    }
+
+    /// Stop estimating the cost of turns that report none
+    pub async fn clear_pricing(&self) {
+        *self.pricing.write().await = None;
+    }
+
     /// Get current usage statistics
     pub async fn get_usage(&self) -> TokenUsageTracker {
         self.tracker.read().await.clone()
     }
 
+    /// Spend recorded per model through [`record_model_usage`](Self::record_model_usage)
+    pub async fn cost_by_model(&self) -> HashMap<String, f64> {
+        self.cost_by_model.read().await.clone()
+    }
+
+    /// Update usage from a turn's reported usage and check limits
+    ///
+    /// See [`TokenUsageTracker::record`] for which counts are added.
+    pub async fn record_usage(&self, usage: &TokenUsage, cost_usd: f64) {
+        self.tracker.write().await.record(usage, cost_usd);
+        self.check_limits().await;
+    }
+
+    /// Update usage from a turn of `model` and check limits
+    ///
+    /// When the turn reported no cost, it is estimated from the pricing
+    /// table set with [`set_pricing`](Self::set_pricing), if any covers the
+    /// model. The spend is also attributed to `model` in
+    /// [`cost_by_model`](Self::cost_by_model).
+    pub async fn record_model_usage(
+        &self,
+        model: Option<&str>,
+        usage: &TokenUsage,
+        cost_usd: Option<f64>,
+    ) {
+        let cost_usd = match (cost_usd, model) {
+            (Some(cost), _) => cost,
+            (None, Some(model)) => self
+                .pricing
+                .read()
+                .await
+                .as_ref()
+                .and_then(|pricing| pricing.cost(model, usage))
+                .unwrap_or(0.0),
+            (None, None) => 0.0,
+        };
+        if let Some(model) = model {
+            *self
+                .cost_by_model
+                .write()
+                .await
+                .entry(model.to_string())
+                .or_default() += cost_usd;
+        }
+        self.record_usage(usage, cost_usd).await;
+    }
+
+    /// Record a turn's `Result` message and return the budget status after it
+    ///
+    /// Only the top-level result of a turn is counted: subagent results and
+    /// other messages leave the usage unchanged, so every message of a
+    /// stream can be passed in. `model` prices a result that reports no
+    /// cost, as in [`record_model_usage`](Self::record_model_usage).
+    ///
+    /// The status is `Warning` once usage reaches the limit's
+    /// `warning_threshold` fraction of either cap and `Exceeded` at the cap,
+    /// so callers can stop sending turns as soon as the budget runs out.
+    /// Without a limit it is always `Ok`.
+    pub async fn record_result(&self, model: Option<&str>, message: &Message) -> BudgetStatus {
+        if let Message::Result {
+            usage,
+            total_cost_usd,
+            ..
+        } = message
+            && ResultPolicy::TopLevel.ends_turn(message)
+        {
+            let usage = usage.clone().unwrap_or_default();
+            self.record_model_usage(model, &usage, *total_cost_usd)
+                .await;
+        }
+        self.status().await
+    }
+
+    /// Budget status of the usage recorded so far (`Ok` without a limit)
+    pub async fn status(&self) -> BudgetStatus {
+        match self.limit.read().await.as_ref() {
+            Some(limit) => limit.check_limits(&*self.tracker.read().await),
+            None => BudgetStatus::Ok,
+        }
+    }
+
     /// Update usage and check limits
     pub async fn update_usage(&self, input_tokens: u64, output_tokens: u64, cost_usd: f64) {
-        // Update tracker
         self.tracker
             .write()
             .await
             .update(input_tokens, output_tokens, cost_usd);
+        self.check_limits().await;
+    }
 
-        // Check limits
+    async fn check_limits(&self) {
         if let Some(limit) = self.limit.read().await.as_ref() {
             let usage = self.tracker.read().await.clone();
             let status = limit.check_limits(&usage);
@@ -276,17 +628,13 @@ impl BudgetManager {
     /// Reset usage statistics
     pub async fn reset_usage(&self) {
         self.tracker.write().await.reset();
+        self.cost_by_model.write().await.clear();
         *self.warning_fired.write().await = false;
     }
 
     /// Check if budget is exceeded
     pub async fn is_exceeded(&self) -> bool {
-        if let Some(limit) = self.limit.read().await.as_ref() {
-            let usage = self.tracker.read().await.clone();
-            matches!(limit.check_limits(&usage), BudgetStatus::Exceeded)
-        } else {
-            false
-        }
+        matches!(self.status().await, BudgetStatus::Exceeded)
     }
 }
 
@@ -296,6 +644,146 @@ impl Default for BudgetManager {
     }
 }
 
+/// Context size of a session after its latest turn
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextUsage {
+    /// Session the turn belongs to
+    pub session_id: String,
+    /// Tokens the next turn will carry: the latest prompt (cached or not)
+    /// plus the latest output
+    pub context_tokens: u64,
+    /// Size of the model's context window
+    pub context_window: u64,
+}
+
+impl ContextUsage {
+    /// Fraction of the context window in use
+    pub fn ratio(&self) -> f64 {
+        if self.context_window == 0 {
+            1.0
+        } else {
+            self.context_tokens as f64 / self.context_window as f64
+        }
+    }
+}
+
+/// Callback fired when a session's context crosses the threshold
+pub type ContextThresholdCallback = Arc<dyn Fn(&ContextUsage) + Send + Sync>;
+
+/// Watches each session's context size against the model's context window
+///
+/// Feed it every message (or just the `Result` messages) of a conversation.
+/// Each turn re-sends the whole conversation, so the context size is read
+/// from the latest turn's usage rather than summed across turns. A turn
+/// making several API calls reports their sum, so the estimate errs on the
+/// side of warning early. Subagents run in their own context and are
+/// ignored.
+///
+/// When a session first reaches `threshold` of the window, the callback
+/// fires once; it is re-armed when the context drops below the threshold
+/// again, e.g. after the conversation was compacted. Call
+/// [`end_session`](Self::end_session) when a conversation is closed.
+///
+/// # Example
+///
+/// ```rust
+/// # use nexus_claude::ContextMonitor;
+/// # use std::sync::Arc;
+/// let mut monitor = ContextMonitor::new(200_000)
+///     .with_threshold(0.9)
+///     .on_threshold(Arc::new(|usage| {
+///         eprintln!("session {} is {:.0}% full", usage.session_id, usage.ratio() * 100.0);
+///     }));
+/// ```
+#[derive(Clone)]
+pub struct ContextMonitor {
+    context_window: u64,
+    threshold: f64,
+    on_threshold: Option<ContextThresholdCallback>,
+    sessions: HashMap<String, u64>,
+    fired: HashSet<String>,
+}
+
+impl ContextMonitor {
+    /// Monitor a model with the given context window, warning at 80%
+    pub fn new(context_window: u64) -> Self {
+        Self {
+            context_window,
+            threshold: 0.8,
+            on_threshold: None,
+            sessions: HashMap::new(),
+            fired: HashSet::new(),
+        }
+    }
+
+    /// Set the fraction of the window (0.0-1.0) at which to fire
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the callback fired when a session crosses the threshold
+    pub fn on_threshold(mut self, callback: ContextThresholdCallback) -> Self {
+        self.on_threshold = Some(callback);
+        self
+    }
+
+    /// Record a message, firing the callback if its session crossed the threshold
+    ///
+    /// Only top-level `Result` messages with usage are counted. Returns the
+    /// session's usage when the threshold was crossed by this message.
+    pub fn observe(&mut self, message: &Message) -> Option<ContextUsage> {
+        let Message::Result {
+            session_id,
+            usage: Some(usage),
+            parent_tool_use_id: None,
+            ..
+        } = message
+        else {
+            return None;
+        };
+        self.sessions
+            .insert(session_id.clone(), usage.total_tokens());
+
+        let usage = self.usage(session_id)?;
+        if usage.ratio() < self.threshold {
+            self.fired.remove(session_id);
+            return None;
+        }
+        if !self.fired.insert(session_id.clone()) {
+            return None;
+        }
+        warn!(
+            "Context of session {} at {:.1}% ({}/{} tokens)",
+            session_id,
+            usage.ratio() * 100.0,
+            usage.context_tokens,
+            usage.context_window
+        );
+        if let Some(callback) = &self.on_threshold {
+            callback(&usage);
+        }
+        Some(usage)
+    }
+
+    /// Forget a closed session
+    pub fn end_session(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+        self.fired.remove(session_id);
+    }
+
+    /// Latest context usage of a session, if it has completed a turn
+    pub fn usage(&self, session_id: &str) -> Option<ContextUsage> {
+        self.sessions
+            .get(session_id)
+            .map(|&context_tokens| ContextUsage {
+                session_id: session_id.to_string(),
+                context_tokens,
+                context_window: self.context_window,
+            })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -560,6 +1048,243 @@ mod tests {
         assert_eq!(*message.lock().unwrap(), "Budget limit exceeded");
     }
 
+    fn turn_result(session_id: &str, input_tokens: u64, cache_read: u64) -> Message {
+        let mut result = Message::result_success(session_id);
+        if let Message::Result { usage, .. } = &mut result {
+            *usage = Some(TokenUsage {
+                input_tokens,
+                output_tokens: 100,
+                cache_read_input_tokens: Some(cache_read),
+                ..Default::default()
+            });
+        }
+        result
+    }
+
+    #[test]
+    fn test_context_monitor_fires_at_threshold() {
+        let crossings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = crossings.clone();
+        let mut monitor = ContextMonitor::new(10_000)
+            .with_threshold(0.8)
+            .on_threshold(Arc::new(move |usage| {
+                recorded.lock().unwrap().push(usage.clone());
+            }));
+
+        // 5_100 tokens: below 80%
+        assert_eq!(monitor.observe(&turn_result("a", 1_000, 4_000)), None);
+        assert_eq!(monitor.usage("a").unwrap().context_tokens, 5_100);
+        // 8_100 tokens: crosses the threshold
+        let crossed = monitor.observe(&turn_result("a", 1_000, 7_000)).unwrap();
+        assert_eq!(crossed.context_tokens, 8_100);
+        assert!((crossed.ratio() - 0.81).abs() < 1e-9);
+        // Still above: no second notification
+        assert_eq!(monitor.observe(&turn_result("a", 500, 8_500)), None);
+        // Other sessions are tracked separately
+        assert_eq!(monitor.observe(&turn_result("b", 100, 0)), None);
+        assert_eq!(monitor.usage("b").unwrap().context_tokens, 200);
+
+        // Compaction shrinks the context, which re-arms the monitor
+        assert_eq!(monitor.observe(&turn_result("a", 2_000, 0)), None);
+        assert!(monitor.observe(&turn_result("a", 9_000, 0)).is_some());
+
+        let crossings = crossings.lock().unwrap();
+        assert_eq!(crossings.len(), 2);
+        assert!(crossings.iter().all(|usage| usage.session_id == "a"));
+    }
+
+    #[test]
+    fn test_context_monitor_forgets_ended_sessions() {
+        let mut monitor = ContextMonitor::new(10_000).with_threshold(0.8);
+        assert!(monitor.observe(&turn_result("a", 9_000, 0)).is_some());
+
+        monitor.end_session("a");
+        assert_eq!(monitor.usage("a"), None);
+        // A session reusing the ID starts over and is warned about again
+        assert!(monitor.observe(&turn_result("a", 9_000, 0)).is_some());
+    }
+
+    #[test]
+    fn test_pricing_table_matches_longest_prefix() {
+        let pricing = PricingTable::default();
+        let (input, output) = pricing.rate("claude-opus-4-1-20250805").unwrap();
+        assert_eq!((input.base, output), (15.0, OutputRate(75.0)));
+        let (input, output) = pricing.rate("claude-opus-4-5-20251101").unwrap();
+        assert_eq!((input.base, output), (5.0, OutputRate(25.0)));
+        assert_eq!(pricing.rate("sonnet").unwrap().1, OutputRate(15.0));
+        assert!(pricing.rate("claude-3-5-sonnetx").is_none());
+        assert!(pricing.rate("gpt-4").is_none());
+
+        let pricing = pricing.with_rate("sonnet", InputRate::new(1.0), OutputRate(2.0));
+        assert_eq!(pricing.rate("sonnet").unwrap().1, OutputRate(2.0));
+    }
+
+    #[test]
+    fn test_estimated_cost_includes_cache_tokens() {
+        let mut tracker = TokenUsageTracker::new();
+        tracker.record(
+            &TokenUsage {
+                input_tokens: 1_000_000,
+                output_tokens: 100_000,
+                cache_creation_input_tokens: Some(1_000_000),
+                cache_read_input_tokens: Some(1_000_000),
+                ..Default::default()
+            },
+            0.0,
+        );
+        // 3.00 input + 3.75 cache write + 0.30 cache read + 1.50 output
+        let cost = tracker
+            .estimated_cost("claude-sonnet-4-5-20250929")
+            .unwrap();
+        assert!((cost - 8.55).abs() < 1e-9);
+        assert_eq!(tracker.estimated_cost("unknown-model"), None);
+    }
+
+    #[test]
+    fn test_estimate_cost_from_prompt_length() {
+        // 4000 characters is about 1000 input tokens
+        let prompt = "abcd".repeat(1000);
+        assert_eq!(estimate_tokens(&prompt), 1000);
+        assert_eq!(estimate_tokens("abcde"), 2);
+
+        // 1000 input tokens at $3/M plus 1000 output tokens at $15/M
+        let sonnet = ClaudeCodeOptions::builder()
+            .model("claude-sonnet-4-5-20250929")
+            .build();
+        assert!((estimate_cost(&prompt, &sonnet) - 0.018).abs() < 1e-9);
+
+        // The system prompt counts as input; no model is priced as Sonnet
+        let with_system = ClaudeCodeOptions::builder()
+            .system_prompt("abcd".repeat(1000))
+            .build();
+        assert!((estimate_cost(&prompt, &with_system) - 0.021).abs() < 1e-9);
+
+        // 1000 input tokens at $15/M plus 2000 output tokens at $75/M
+        let opus = ClaudeCodeOptions::builder()
+            .model("claude-opus-4-1-20250805")
+            .build();
+        let cost = estimate_cost_with(&prompt, &opus, 2000, &PricingTable::default()).unwrap();
+        assert!((cost - 0.165).abs() < 1e-9);
+
+        let unknown = ClaudeCodeOptions::builder().model("gpt-4").build();
+        assert_eq!(
+            estimate_cost_with(&prompt, &unknown, 0, &PricingTable::default()),
+            None
+        );
+        assert!((estimate_cost(&prompt, &unknown) - 0.018).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_budget_manager_prices_turns_without_cost() {
+        let manager = BudgetManager::new();
+        let usage = TokenUsage {
+            input_tokens: 1_000_000,
+            output_tokens: 0,
+            ..Default::default()
+        };
+
+        // Without a pricing table an unreported cost counts as free
+        manager
+            .record_model_usage(Some("claude-opus-4-1"), &usage, None)
+            .await;
+        assert_eq!(manager.get_usage().await.total_cost_usd, 0.0);
+
+        manager.set_pricing(PricingTable::default()).await;
+        manager
+            .record_model_usage(Some("claude-opus-4-1"), &usage, None)
+            .await;
+        manager
+            .record_model_usage(Some("claude-haiku-4-5"), &usage, Some(0.5))
+            .await;
+        manager.record_model_usage(None, &usage, None).await;
+
+        let usage = manager.get_usage().await;
+        assert!((usage.total_cost_usd - 15.5).abs() < 1e-9);
+        assert_eq!(usage.session_count, 4);
+        let by_model = manager.cost_by_model().await;
+        assert_eq!(by_model.len(), 2);
+        assert!((by_model["claude-opus-4-1"] - 15.0).abs() < 1e-9);
+        assert!((by_model["claude-haiku-4-5"] - 0.5).abs() < 1e-9);
+
+        manager.reset_usage().await;
+        assert!(manager.cost_by_model().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_budget_manager_record_result_transitions() {
+        let costing = |cost: f64| {
+            let mut message = turn_result("s", 1_000, 0);
+            if let Message::Result { total_cost_usd, .. } = &mut message {
+                *total_cost_usd = Some(cost);
+            }
+            message
+        };
+
+        let manager = BudgetManager::new();
+        assert_eq!(
+            manager.record_result(None, &costing(5.0)).await,
+            BudgetStatus::Ok
+        );
+
+        manager.reset_usage().await;
+        manager
+            .set_limit(BudgetLimit::with_cost(1.0).with_warning_threshold(0.75))
+            .await;
+
+        assert_eq!(
+            manager.record_result(None, &costing(0.5)).await,
+            BudgetStatus::Ok
+        );
+        assert!(matches!(
+            manager.record_result(None, &costing(0.3)).await,
+            BudgetStatus::Warning { current_ratio, .. } if (current_ratio - 0.8).abs() < 1e-9
+        ));
+
+        // Subagent results and other messages do not count
+        let mut subagent = costing(1.0);
+        if let Message::Result {
+            parent_tool_use_id, ..
+        } = &mut subagent
+        {
+            *parent_tool_use_id = Some("toolu_1".into());
+        }
+        assert!(matches!(
+            manager.record_result(None, &subagent).await,
+            BudgetStatus::Warning { .. }
+        ));
+        let system = Message::System {
+            subtype: "init".into(),
+            data: serde_json::json!({}),
+        };
+        assert!(matches!(
+            manager.record_result(None, &system).await,
+            BudgetStatus::Warning { .. }
+        ));
+
+        assert_eq!(
+            manager.record_result(None, &costing(0.2)).await,
+            BudgetStatus::Exceeded
+        );
+        assert_eq!(manager.status().await, BudgetStatus::Exceeded);
+        assert_eq!(manager.get_usage().await.session_count, 3);
+
+        // Token caps count the tokens each result reports
+        let manager = BudgetManager::new();
+        manager.set_limit(BudgetLimit::with_tokens(2_500)).await;
+        assert_eq!(
+            manager.record_result(None, &costing(0.0)).await,
+            BudgetStatus::Ok
+        );
+        assert!(matches!(
+            manager.record_result(None, &costing(0.0)).await,
+            BudgetStatus::Warning { .. }
+        ));
+        assert_eq!(
+            manager.record_result(None, &costing(0.0)).await,
+            BudgetStatus::Exceeded
+        );
+    }
+
     #[tokio::test]
     async fn test_budget_manager_is_exceeded_no_limit() {
         let manager = BudgetManager::new();
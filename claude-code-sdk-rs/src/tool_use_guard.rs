@@ -0,0 +1,157 @@
+//! Duplicate tool-use detection for agent loops
+//!
+//! Tracks the `tool_use_id`s that Claude emits and the ones the client answers
+//! with a tool result, so that agent-logic bugs (answering the same tool use
+//! twice, or Claude reusing an id) surface early instead of confusing the CLI.
+
+use crate::{
+    errors::{Result, SdkError},
+    types::{ContentBlock, DuplicateToolUsePolicy, Message},
+};
+use std::collections::HashSet;
+use tracing::warn;
+
+/// Tracks requested and answered tool-use ids for a single conversation
+#[derive(Debug, Clone)]
+pub struct ToolUseGuard {
+    policy: DuplicateToolUsePolicy,
+    requested: HashSet<String>,
+    answered: HashSet<String>,
+}
+
+impl ToolUseGuard {
+    /// Create a new guard with the given policy
+    pub fn new(policy: DuplicateToolUsePolicy) -> Self {
+        Self {
+            policy,
+            requested: HashSet::new(),
+            answered: HashSet::new(),
+        }
+    }
+
+    /// The policy applied when a duplicate is detected
+    pub fn policy(&self) -> DuplicateToolUsePolicy {
+        self.policy
+    }
+
+    /// Record every tool use contained in an assistant message
+    ///
+    /// Non-assistant messages are ignored. Returns an error under
+    /// [`DuplicateToolUsePolicy::Error`] if a tool-use id was already seen.
+    pub fn observe_message(&mut self, message: &Message) -> Result<()> {
+        if let Message::Assistant { message, .. } = message {
+            for block in &message.content {
+                if let ContentBlock::ToolUse(tool_use) = block
+                    && !self.requested.insert(tool_use.id.clone())
+                {
+                    self.report(&tool_use.id, "requested more than once by Claude")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that a tool result is being sent for `tool_use_id`
+    ///
+    /// Returns an error under [`DuplicateToolUsePolicy::Error`] if the id
+    /// was already answered.
+    pub fn record_result(&mut self, tool_use_id: &str) -> Result<()> {
+        if !self.answered.insert(tool_use_id.to_string()) {
+            self.report(tool_use_id, "tool result already sent")?;
+        }
+        Ok(())
+    }
+
+    /// Whether a tool result has already been sent for `tool_use_id`
+    pub fn is_answered(&self, tool_use_id: &str) -> bool {
+        self.answered.contains(tool_use_id)
+    }
+
+    /// Forget all tracked ids (e.g. when starting a new conversation)
+    pub fn reset(&mut self) {
+        self.requested.clear();
+        self.answered.clear();
+    }
+
+    fn report(&self, tool_use_id: &str, reason: &str) -> Result<()> {
+        match self.policy {
+            DuplicateToolUsePolicy::Warn => {
+                warn!("Duplicate tool_use_id {}: {}", tool_use_id, reason);
+                Ok(())
+            },
+            DuplicateToolUsePolicy::Error => Err(SdkError::DuplicateToolUseId {
+                tool_use_id: tool_use_id.to_string(),
+                reason: reason.to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AssistantMessage, ToolUseContent};
+
+    fn tool_use_message(ids: &[&str]) -> Message {
+        Message::Assistant {
+            message: AssistantMessage {
+                content: ids
+                    .iter()
+                    .map(|id| {
+                        ContentBlock::ToolUse(ToolUseContent {
+                            id: id.to_string(),
+                            name: "Bash".to_string(),
+                            input: serde_json::json!({}),
+                        })
+                    })
+                    .collect(),
+            },
+            parent_tool_use_id: None,
+        }
+    }
+
+    #[test]
+    fn test_duplicate_answer_is_an_error() {
+        let mut guard = ToolUseGuard::new(DuplicateToolUsePolicy::Error);
+        guard.record_result("toolu_1").unwrap();
+        assert!(guard.is_answered("toolu_1"));
+
+        match guard.record_result("toolu_1") {
+            Err(SdkError::DuplicateToolUseId { tool_use_id, .. }) => {
+                assert_eq!(tool_use_id, "toolu_1")
+            },
+            other => panic!("expected DuplicateToolUseId, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_request_is_an_error() {
+        let mut guard = ToolUseGuard::new(DuplicateToolUsePolicy::Error);
+        guard
+            .observe_message(&tool_use_message(&["toolu_a"]))
+            .unwrap();
+
+        let err = guard
+            .observe_message(&tool_use_message(&["toolu_b", "toolu_a"]))
+            .unwrap_err();
+        assert!(err.to_string().contains("toolu_a"));
+    }
+
+    #[test]
+    fn test_warn_policy_does_not_fail() {
+        let mut guard = ToolUseGuard::new(DuplicateToolUsePolicy::Warn);
+        guard.observe_message(&tool_use_message(&["x"])).unwrap();
+        guard.observe_message(&tool_use_message(&["x"])).unwrap();
+        guard.record_result("x").unwrap();
+        guard.record_result("x").unwrap();
+    }
+
+    #[test]
+    fn test_reset_clears_tracking() {
+        let mut guard = ToolUseGuard::new(DuplicateToolUsePolicy::Error);
+        guard.record_result("toolu_1").unwrap();
+        guard.reset();
+        assert!(!guard.is_answered("toolu_1"));
+        guard.record_result("toolu_1").unwrap();
+    }
+}
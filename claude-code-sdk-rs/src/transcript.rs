@@ -0,0 +1,410 @@
+//! Reading the CLI's session transcripts
+//!
+//! The CLI appends every message of a session to a JSONL transcript (its path
+//! is the `transcript_path` of hook inputs). [`Transcript::load`] parses such
+//! a file into [`Message`]s for offline analysis, replay or memory backfill.
+//!
+//! Transcript lines wrap the same `user` / `assistant` / `system` payloads as
+//! the stream format, with extra metadata (`uuid`, `parentUuid`, `timestamp`,
+//! `isSidechain`, ...) and no `parent_tool_use_id`. They also contain entries
+//! that never appear on the stream, such as `summary` lines; summaries are
+//! collected separately and other unknown entries are skipped.
+//!
+//! A compaction shows up as a `compact_boundary` system entry followed by a
+//! user entry flagged `isCompactSummary` that holds the summary the
+//! conversation continues from; [`Transcript::compactions`] pairs them up.
+//! [`CompactionTracker`] does the same for messages arriving on the stream.
+
+use crate::{
+    errors::{Result, SdkError},
+    message_parser::parse_message,
+    types::Message,
+};
+use serde_json::Value;
+use std::path::Path;
+use tracing::debug;
+
+/// One message of a transcript with its metadata
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptEntry {
+    /// The parsed message
+    pub message: Message,
+    /// ID of this entry
+    pub uuid: Option<String>,
+    /// ID of the entry this one follows
+    pub parent_uuid: Option<String>,
+    /// Session the entry belongs to
+    pub session_id: Option<String>,
+    /// When the entry was written (RFC 3339)
+    pub timestamp: Option<String>,
+    /// Whether the entry belongs to a subagent rather than the main agent
+    pub is_sidechain: bool,
+    /// Whether the CLI injected the entry itself (e.g. command output)
+    /// rather than it being part of the conversation
+    pub is_meta: bool,
+    /// Whether the entry is the summary written by a compaction
+    pub is_compact_summary: bool,
+}
+
+/// Outcome of a conversation compaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionResult {
+    /// Summary the conversation continues from (empty if the transcript
+    /// ends before the summary was written)
+    pub summary: String,
+    /// Number of user and assistant messages replaced by the summary
+    pub removed_message_count: usize,
+    /// What started the compaction (`"manual"` or `"auto"`)
+    pub trigger: Option<String>,
+    /// Context size in tokens before the compaction
+    pub pre_tokens: Option<u64>,
+}
+
+/// Pairs compaction boundaries with their summaries as messages arrive
+///
+/// The interactive client feeds it every message it receives, see
+/// `InteractiveClient::compactions`. The stream does not echo prompts, so
+/// the sender reports them with [`record_prompt`](Self::record_prompt).
+#[derive(Debug, Clone, Default)]
+pub struct CompactionTracker {
+    compactions: Vec<CompactionResult>,
+    since_boundary: usize,
+    awaiting_summary: bool,
+}
+
+impl CompactionTracker {
+    /// Create a tracker that has seen no messages
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a prompt sent to the CLI as a removable user message
+    pub fn record_prompt(&mut self) {
+        self.since_boundary += 1;
+    }
+
+    /// Feed the next message received from the stream
+    ///
+    /// Returns the compaction a `compact_boundary` message starts, with an
+    /// empty summary. Stream messages carry no `isCompactSummary` flag, so
+    /// the first main-agent user message with text that follows the
+    /// boundary, before any assistant message, is taken as its summary and
+    /// filled into [`compactions`](Self::compactions).
+    pub fn observe(&mut self, message: &Message) -> Option<CompactionResult> {
+        let top_level = message.is_top_level();
+        let is_summary = top_level
+            && matches!(message, Message::User { message, .. } if !message.content.is_empty());
+        let started = self.observe_entry(message, top_level, is_summary);
+        if top_level && matches!(message, Message::Assistant { .. }) {
+            self.awaiting_summary = false;
+        }
+        started
+    }
+
+    /// Record one message; `counted` says whether a user or assistant
+    /// message belongs to the main conversation
+    fn observe_entry(
+        &mut self,
+        message: &Message,
+        counted: bool,
+        is_summary: bool,
+    ) -> Option<CompactionResult> {
+        match message {
+            Message::System { subtype, data } if subtype == "compact_boundary" => {
+                let metadata = data.get("compact_metadata").or(data.get("compactMetadata"));
+                let metadata_field =
+                    |snake: &str, camel: &str| metadata.and_then(|m| m.get(snake).or(m.get(camel)));
+                let compaction = CompactionResult {
+                    summary: String::new(),
+                    removed_message_count: self.since_boundary,
+                    trigger: metadata_field("trigger", "trigger")
+                        .and_then(Value::as_str)
+                        .map(String::from),
+                    pre_tokens: metadata_field("pre_tokens", "preTokens").and_then(Value::as_u64),
+                };
+                self.compactions.push(compaction.clone());
+                self.since_boundary = 0;
+                self.awaiting_summary = true;
+                return Some(compaction);
+            },
+            Message::User { message, .. } if is_summary && self.awaiting_summary => {
+                if let Some(compaction) = self.compactions.last_mut() {
+                    compaction.summary = message.content.clone();
+                }
+                self.awaiting_summary = false;
+            },
+            Message::User { .. } if is_summary => {},
+            Message::User { .. } | Message::Assistant { .. } if counted => {
+                self.since_boundary += 1;
+            },
+            _ => {},
+        }
+        None
+    }
+
+    /// The compactions seen so far, oldest first
+    ///
+    /// The summary of the last one stays empty until it arrives.
+    pub fn compactions(&self) -> &[CompactionResult] {
+        &self.compactions
+    }
+}
+
+/// A parsed CLI session transcript
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use nexus_claude::Transcript;
+/// # fn example() -> nexus_claude::Result<()> {
+/// let transcript = Transcript::load("/path/to/session.jsonl")?;
+/// for message in transcript.messages() {
+///     println!("{message:?}");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transcript {
+    /// Messages in file order
+    pub entries: Vec<TranscriptEntry>,
+    /// Conversation summaries written by the CLI
+    pub summaries: Vec<String>,
+}
+
+impl Transcript {
+    /// Read and parse the transcript at `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            SdkError::ConfigError(format!(
+                "Failed to read transcript {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Self::parse(&text)
+    }
+
+    /// Parse transcript JSONL text
+    ///
+    /// Blank lines are ignored. A truncated last line (a transcript still
+    /// being written) is skipped; any other invalid line is an error naming
+    /// its line number.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut transcript = Self::default();
+        let complete = text.ends_with('\n');
+        let lines: Vec<&str> = text.lines().collect();
+
+        for (index, line) in lines.iter().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let json: Value = match serde_json::from_str(line) {
+                Ok(json) => json,
+                Err(_) if !complete && index + 1 == lines.len() => {
+                    debug!("Skipping truncated last transcript line");
+                    break;
+                },
+                Err(e) => {
+                    return Err(SdkError::parse_error(
+                        format!("Invalid JSON on transcript line {}: {}", index + 1, e),
+                        line.to_string(),
+                    ));
+                },
+            };
+            transcript
+                .push_line(json)
+                .map_err(|e| e.within(&format!("line {}", index + 1)))?;
+        }
+        Ok(transcript)
+    }
+
+    fn push_line(&mut self, json: Value) -> Result<()> {
+        if json.get("type").and_then(Value::as_str) == Some("summary") {
+            if let Some(summary) = json.get("summary").and_then(Value::as_str) {
+                self.summaries.push(summary.to_string());
+            }
+            return Ok(());
+        }
+
+        let field = |name: &str| json.get(name).and_then(Value::as_str).map(String::from);
+        let flag = |name: &str| json.get(name).and_then(Value::as_bool).unwrap_or(false);
+        let uuid = field("uuid");
+        let parent_uuid = field("parentUuid");
+        let session_id = field("sessionId");
+        let timestamp = field("timestamp");
+        let is_sidechain = flag("isSidechain");
+        let is_meta = flag("isMeta");
+        let is_compact_summary = flag("isCompactSummary");
+
+        if let Some(message) = parse_message(json)? {
+            self.entries.push(TranscriptEntry {
+                message,
+                uuid,
+                parent_uuid,
+                session_id,
+                timestamp,
+                is_sidechain,
+                is_meta,
+                is_compact_summary,
+            });
+        }
+        Ok(())
+    }
+
+    /// The messages of the transcript, in file order
+    pub fn messages(&self) -> Vec<Message> {
+        self.entries
+            .iter()
+            .map(|entry| entry.message.clone())
+            .collect()
+    }
+
+    /// The compactions recorded in the transcript, oldest first
+    ///
+    /// The removed messages are the main-agent user and assistant messages
+    /// since the start of the transcript or the previous compaction.
+    pub fn compactions(&self) -> Vec<CompactionResult> {
+        let mut tracker = CompactionTracker::new();
+        for entry in &self.entries {
+            tracker.observe_entry(
+                &entry.message,
+                !entry.is_sidechain && !entry.is_meta,
+                entry.is_compact_summary,
+            );
+        }
+        tracker.compactions
+    }
+
+    /// Consume the transcript, keeping only its messages
+    pub fn into_messages(self) -> Vec<Message> {
+        self.entries
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ContentBlock;
+
+    const SAMPLE: &str = r##"{"type":"summary","summary":"Reading the README","leafUuid":"u4"}
+{"parentUuid":null,"isSidechain":false,"userType":"external","cwd":"/repo","sessionId":"s1","version":"2.0.30","type":"user","message":{"role":"user","content":"Summarize the README"},"uuid":"u1","timestamp":"2025-10-01T10:00:00.000Z"}
+{"parentUuid":"u1","isSidechain":false,"sessionId":"s1","type":"assistant","message":{"id":"msg_1","type":"message","role":"assistant","model":"claude-sonnet-4-5","content":[{"type":"tool_use","id":"toolu_1","name":"Read","input":{"file_path":"README.md"}}],"stop_reason":"tool_use","usage":{"input_tokens":10,"output_tokens":5}},"requestId":"req_1","uuid":"u2","timestamp":"2025-10-01T10:00:01.000Z"}
+{"parentUuid":"u2","isSidechain":false,"sessionId":"s1","type":"user","message":{"role":"user","content":[{"tool_use_id":"toolu_1","type":"tool_result","content":"# Project"}]},"toolUseResult":{"type":"text"},"uuid":"u3","timestamp":"2025-10-01T10:00:02.000Z"}
+{"type":"file-history-snapshot","messageId":"u3","snapshot":{}}
+
+{"parentUuid":"u3","isSidechain":false,"sessionId":"s1","type":"assistant","message":{"id":"msg_2","type":"message","role":"assistant","content":[{"type":"text","text":"It describes the project."}]},"uuid":"u4","timestamp":"2025-10-01T10:00:03.000Z"}
+{"parentUuid":"u4","isSidechain":false,"isMeta":true,"sessionId":"s1","type":"system","subtype":"compact_boundary","content":"Conversation compacted","level":"info","uuid":"u5","timestamp":"2025-10-01T10:00:04.000Z"}
+{"parentUuid":"u5","type":"assistant","message":{"content":[{"type":"te"##;
+
+    #[test]
+    fn test_load_parses_sample_transcript() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("s1.jsonl");
+        std::fs::write(&path, SAMPLE).unwrap();
+
+        let transcript = Transcript::load(&path).unwrap();
+        assert_eq!(transcript.summaries, ["Reading the README"]);
+        assert_eq!(transcript.entries.len(), 5);
+
+        let first = &transcript.entries[0];
+        assert_eq!(first.uuid.as_deref(), Some("u1"));
+        assert_eq!(first.parent_uuid, None);
+        assert_eq!(first.session_id.as_deref(), Some("s1"));
+        assert_eq!(first.timestamp.as_deref(), Some("2025-10-01T10:00:00.000Z"));
+        assert_eq!(transcript.entries[1].parent_uuid.as_deref(), Some("u1"));
+        assert!(transcript.entries[4].is_meta);
+
+        let messages = transcript.into_messages();
+        assert!(
+            matches!(&messages[0], Message::User { message, .. } if message.content == "Summarize the README")
+        );
+        assert!(matches!(
+            &messages[1],
+            Message::Assistant { message, .. } if message.tool_uses()[0].name == "Read"
+        ));
+        let Message::User { message, .. } = &messages[2] else {
+            panic!("expected the tool result, got {:?}", messages[2]);
+        };
+        assert!(matches!(
+            message.content_blocks.as_deref(),
+            Some([ContentBlock::ToolResult(result)]) if result.tool_use_id == "toolu_1"
+        ));
+        assert!(
+            matches!(&messages[4], Message::System { subtype, .. } if subtype == "compact_boundary")
+        );
+    }
+
+    #[test]
+    fn test_compactions_extract_summary() {
+        let text = r#"{"type":"user","message":{"role":"user","content":"Fix the build"},"uuid":"u1"}
+{"type":"assistant","message":{"content":[{"type":"text","text":"Done."}]},"uuid":"u2"}
+{"type":"user","isSidechain":true,"message":{"role":"user","content":"Subagent prompt"},"uuid":"u3"}
+{"type":"user","isMeta":true,"message":{"role":"user","content":"<command-name>/compact</command-name>"},"uuid":"u4"}
+{"type":"system","subtype":"compact_boundary","content":"Conversation compacted","compactMetadata":{"trigger":"manual","preTokens":48210},"uuid":"u5"}
+{"type":"user","isCompactSummary":true,"message":{"role":"user","content":"This session is being continued from a previous conversation. The build was fixed."},"uuid":"u6"}
+{"type":"assistant","message":{"content":[{"type":"text","text":"Continuing."}]},"uuid":"u7"}
+"#;
+        let transcript = Transcript::parse(text).unwrap();
+        assert!(transcript.entries[5].is_compact_summary);
+        assert_eq!(
+            transcript.compactions(),
+            [CompactionResult {
+                summary: "This session is being continued from a previous conversation. \
+                          The build was fixed."
+                    .to_string(),
+                removed_message_count: 2,
+                trigger: Some("manual".to_string()),
+                pre_tokens: Some(48210),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_tracker_reads_compactions_from_the_stream() {
+        let message = |json: Value| parse_message(json).unwrap().unwrap();
+        let boundary = message(serde_json::json!({
+            "type": "system", "subtype": "compact_boundary",
+            "compact_metadata": {"trigger": "auto", "pre_tokens": 150000}
+        }));
+        let assistant = message(serde_json::json!({
+            "type": "assistant", "message": {"content": [{"type": "text", "text": "Done."}]}
+        }));
+        let summary = message(serde_json::json!({
+            "type": "user", "message": {"role": "user", "content": "The build was fixed."}
+        }));
+
+        let mut tracker = CompactionTracker::new();
+        tracker.record_prompt();
+        assert_eq!(tracker.observe(&assistant), None);
+        let started = tracker.observe(&boundary).unwrap();
+        assert_eq!(started.removed_message_count, 2);
+        assert_eq!(started.summary, "");
+        tracker.observe(&summary);
+        assert_eq!(tracker.compactions()[0].summary, "The build was fixed.");
+
+        // Without a summary before the next assistant message none is taken
+        tracker.observe(&boundary);
+        tracker.observe(&assistant);
+        tracker.observe(&summary);
+        assert_eq!(tracker.compactions()[1].summary, "");
+        assert_eq!(tracker.compactions()[1].trigger.as_deref(), Some("auto"));
+        assert_eq!(tracker.compactions()[1].pre_tokens, Some(150000));
+    }
+
+    #[test]
+    fn test_parse_reports_invalid_line() {
+        let text = "{\"type\":\"user\",\"message\":{\"content\":\"hi\"}}\nnot json\n";
+        match Transcript::parse(text) {
+            Err(SdkError::MessageParseError { error, .. }) => {
+                assert!(error.contains("line 2"), "{error}")
+            },
+            other => panic!("expected MessageParseError, got {other:?}"),
+        }
+    }
+}
@@ -1,13 +1,46 @@
 //! In-memory mock transport for testing and E2E simulations
-use super::{InputMessage, Transport};
+//!
+//! [`MockTransport::pair`] gives a transport and a handle of raw channels for
+//! injecting CLI output and observing what the SDK sends.
+//!
+//! [`MockTransport::builder`] scripts a conversation instead: each expected
+//! user message is answered with a fixed list of messages, everything the SDK
+//! sends is recorded, and the [`MockScript`] handle asserts at the end that
+//! every expectation was met.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use nexus_claude::{InteractiveClient, Message, transport::mock::MockTransport};
+//! # #[tokio::main]
+//! # async fn main() -> nexus_claude::Result<()> {
+//! # let reply = Message::System { subtype: "status".into(), data: serde_json::json!({}) };
+//! let (transport, script) = MockTransport::builder()
+//!     .expect_user_message()
+//!     .then_reply(vec![reply, Message::result_success("s")])
+//!     .build();
+//!
+//! let mut client = InteractiveClient::from_transport(transport);
+//! client.connect().await?;
+//! let messages = client.send_and_receive("Hello".to_string()).await?;
+//! assert_eq!(messages.len(), 2);
+//! assert_eq!(script.sent_messages().len(), 1);
+//! script.assert_expectations_met();
+//! # Ok(())
+//! # }
+//! ```
+use super::{InputMessage, Transport, TransportCapabilities, control_request_json};
 use crate::{
+    cli_features::SemVer,
     errors::Result,
     types::{ControlRequest, ControlResponse, Message},
 };
 use async_trait::async_trait;
 use futures::stream::{Stream, StreamExt};
+use std::collections::VecDeque;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::sync::{broadcast, mpsc};
 
 /// Handle for interacting with the mock transport in tests
@@ -16,6 +49,8 @@ pub struct MockTransportHandle {
     pub inbound_message_tx: broadcast::Sender<Message>,
     /// Inject inbound SDK control JSON (as if coming from CLI)
     pub sdk_control_tx: mpsc::Sender<serde_json::Value>,
+    /// Inject legacy control responses such as interrupt acks (as if coming from CLI)
+    pub control_response_tx: mpsc::Sender<ControlResponse>,
     /// Observe outbound SDK control responses sent by SDK
     pub outbound_control_rx: mpsc::Receiver<serde_json::Value>,
     /// Observe outbound SDK control requests sent by SDK
@@ -47,6 +82,7 @@ impl MockTransport {
     pub fn pair() -> (Box<dyn Transport + Send>, MockTransportHandle) {
         let (message_tx, _rx) = broadcast::channel(100);
         let (sdk_control_tx, sdk_control_rx) = mpsc::channel(100);
+        let (control_response_tx, control_resp_rx) = mpsc::channel(100);
         let (outbound_control_tx, outbound_control_rx) = mpsc::channel(100);
         let (outbound_control_request_tx, outbound_control_request_rx) = mpsc::channel(100);
         let (sent_input_tx, sent_input_rx) = mpsc::channel(100);
@@ -55,7 +91,7 @@ impl MockTransport {
         let transport = MockTransport {
             connected: AtomicBool::new(false),
             message_tx: message_tx.clone(),
-            control_resp_rx: None,
+            control_resp_rx: Some(control_resp_rx),
             sdk_control_rx: Some(sdk_control_rx),
             outbound_control_tx: outbound_control_tx.clone(),
             outbound_control_request_tx: outbound_control_request_tx.clone(),
@@ -66,6 +102,7 @@ impl MockTransport {
         let handle = MockTransportHandle {
             inbound_message_tx: message_tx,
             sdk_control_tx,
+            control_response_tx,
             outbound_control_rx,
             outbound_control_request_rx,
             sent_input_rx,
@@ -74,6 +111,259 @@ impl MockTransport {
 
         (Box::new(transport), handle)
     }
+
+    /// Start scripting a mock conversation
+    pub fn builder() -> MockTransportBuilder {
+        MockTransportBuilder::default()
+    }
+}
+
+/// Builder for a scripted mock transport, see [`MockTransport::builder`]
+#[derive(Debug, Default)]
+pub struct MockTransportBuilder {
+    replies: VecDeque<Vec<Message>>,
+    cli_version: Option<SemVer>,
+}
+
+impl MockTransportBuilder {
+    /// Expect the next user message the SDK sends
+    ///
+    /// Expectations are consumed in order, one per message sent with
+    /// `send_message` or `send_messages`.
+    pub fn expect_user_message(self) -> MockExpectation {
+        MockExpectation { builder: self }
+    }
+
+    /// Report `version` as the CLI version, as the subprocess transport does
+    pub fn cli_version(mut self, version: SemVer) -> Self {
+        self.cli_version = Some(version);
+        self
+    }
+
+    /// Build the transport and the handle used to drive and inspect it
+    pub fn build(self) -> (Box<dyn Transport + Send>, MockScript) {
+        let (message_tx, message_rx) = mpsc::unbounded_channel();
+        let (sdk_control_tx, sdk_control_rx) = mpsc::channel(100);
+        let state = Arc::new(Mutex::new(ScriptState {
+            replies: self.replies,
+            ..ScriptState::default()
+        }));
+
+        let transport = ScriptedTransport {
+            connected: AtomicBool::new(false),
+            state: state.clone(),
+            message_tx: message_tx.clone(),
+            message_rx: Arc::new(tokio::sync::Mutex::new(message_rx)),
+            sdk_control_rx: Some(sdk_control_rx),
+            cli_version: self.cli_version,
+        };
+        let script = MockScript {
+            state,
+            message_tx,
+            sdk_control_tx,
+        };
+        (Box::new(transport), script)
+    }
+}
+
+/// An expected user message waiting for its reply
+#[derive(Debug)]
+pub struct MockExpectation {
+    builder: MockTransportBuilder,
+}
+
+impl MockExpectation {
+    /// Answer the expected message with `messages`, in order
+    pub fn then_reply(mut self, messages: Vec<Message>) -> MockTransportBuilder {
+        self.builder.replies.push_back(messages);
+        self.builder
+    }
+}
+
+/// What a scripted transport has seen, shared with its [`MockScript`]
+#[derive(Debug, Default)]
+struct ScriptState {
+    replies: VecDeque<Vec<Message>>,
+    sent_messages: Vec<InputMessage>,
+    unexpected_messages: usize,
+    sent_control_requests: Vec<serde_json::Value>,
+    sent_control_responses: Vec<serde_json::Value>,
+    input_ended: bool,
+}
+
+/// Handle driving and inspecting a scripted mock transport
+#[derive(Debug)]
+pub struct MockScript {
+    state: Arc<Mutex<ScriptState>>,
+    message_tx: mpsc::UnboundedSender<Message>,
+    sdk_control_tx: mpsc::Sender<serde_json::Value>,
+}
+
+impl MockScript {
+    fn state(&self) -> std::sync::MutexGuard<'_, ScriptState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Every user message the SDK sent, oldest first
+    pub fn sent_messages(&self) -> Vec<InputMessage> {
+        self.state().sent_messages.clone()
+    }
+
+    /// Every control request the SDK sent, as JSON, oldest first
+    pub fn sent_control_requests(&self) -> Vec<serde_json::Value> {
+        self.state().sent_control_requests.clone()
+    }
+
+    /// Every control response the SDK sent, in the wire envelope, oldest first
+    pub fn sent_control_responses(&self) -> Vec<serde_json::Value> {
+        self.state().sent_control_responses.clone()
+    }
+
+    /// Whether the SDK closed its input with `end_input`
+    pub fn input_ended(&self) -> bool {
+        self.state().input_ended
+    }
+
+    /// Expected user messages that have not arrived yet
+    pub fn remaining_expectations(&self) -> usize {
+        self.state().replies.len()
+    }
+
+    /// Deliver a message outside the script, as if the CLI sent it unprompted
+    pub fn push_message(&self, message: Message) {
+        let _ = self.message_tx.send(message);
+    }
+
+    /// Channel for inbound control requests, as if the CLI sent them
+    ///
+    /// The SDK reads them from `take_sdk_control_receiver`.
+    pub fn sdk_control_tx(&self) -> mpsc::Sender<serde_json::Value> {
+        self.sdk_control_tx.clone()
+    }
+
+    /// Panic unless every expected message arrived and no other did
+    #[track_caller]
+    pub fn assert_expectations_met(&self) {
+        let state = self.state();
+        assert!(
+            state.replies.is_empty(),
+            "{} expected user message(s) never sent",
+            state.replies.len()
+        );
+        assert_eq!(
+            state.unexpected_messages, 0,
+            "{} user message(s) sent beyond the script",
+            state.unexpected_messages
+        );
+    }
+}
+
+/// Transport replaying a [`MockTransportBuilder`] script
+struct ScriptedTransport {
+    connected: AtomicBool,
+    state: Arc<Mutex<ScriptState>>,
+    message_tx: mpsc::UnboundedSender<Message>,
+    // Shared like SubprocessTransport's queue: streams created between two
+    // reads pick up where the previous one stopped
+    message_rx: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<Message>>>,
+    sdk_control_rx: Option<mpsc::Receiver<serde_json::Value>>,
+    cli_version: Option<SemVer>,
+}
+
+impl ScriptedTransport {
+    fn state(&self) -> std::sync::MutexGuard<'_, ScriptState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl Transport for ScriptedTransport {
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+
+    async fn connect(&mut self) -> Result<()> {
+        self.connected.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn send_message(&mut self, message: InputMessage) -> Result<()> {
+        let mut state = self.state();
+        state.sent_messages.push(message);
+        match state.replies.pop_front() {
+            Some(replies) => {
+                for reply in replies {
+                    let _ = self.message_tx.send(reply);
+                }
+            },
+            None => state.unexpected_messages += 1,
+        }
+        Ok(())
+    }
+
+    fn receive_messages(
+        &mut self,
+    ) -> Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>> {
+        Box::pin(futures::stream::unfold(
+            self.message_rx.clone(),
+            |rx| async move {
+                let message = rx.lock().await.recv().await?;
+                Some((Ok(message), rx))
+            },
+        ))
+    }
+
+    async fn send_control_request(&mut self, request: ControlRequest) -> Result<()> {
+        self.state()
+            .sent_control_requests
+            .push(control_request_json(&request));
+        Ok(())
+    }
+
+    async fn receive_control_response(&mut self) -> Result<Option<ControlResponse>> {
+        Ok(None)
+    }
+
+    async fn send_sdk_control_request(&mut self, request: serde_json::Value) -> Result<()> {
+        self.state().sent_control_requests.push(request);
+        Ok(())
+    }
+
+    async fn send_sdk_control_response(&mut self, response: serde_json::Value) -> Result<()> {
+        self.state()
+            .sent_control_responses
+            .push(serde_json::json!({"type": "control_response", "response": response}));
+        Ok(())
+    }
+
+    fn take_sdk_control_receiver(&mut self) -> Option<mpsc::Receiver<serde_json::Value>> {
+        self.sdk_control_rx.take()
+    }
+
+    fn cli_version(&self) -> Option<SemVer> {
+        self.cli_version.clone()
+    }
+
+    fn capabilities(&self) -> TransportCapabilities {
+        TransportCapabilities {
+            supports_inbound_control: true,
+            ..TransportCapabilities::default()
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        self.connected.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn end_input(&mut self) -> Result<()> {
+        self.state().input_ended = true;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -134,15 +424,7 @@ impl Transport for MockTransport {
 
     async fn send_control_request(&mut self, request: ControlRequest) -> Result<()> {
         // Record as JSON for tests — must match SubprocessTransport wire format exactly
-        let json = match request {
-            ControlRequest::Interrupt { request_id } => serde_json::json!({
-                "type": "control_request",
-                "request": {
-                    "type": "interrupt",
-                    "request_id": request_id
-                }
-            }),
-        };
+        let json = control_request_json(&request);
         let _ = self.outbound_control_request_tx.send(json).await;
         Ok(())
     }
@@ -175,6 +457,13 @@ impl Transport for MockTransport {
         self.sdk_control_rx.take()
     }
 
+    fn capabilities(&self) -> TransportCapabilities {
+        TransportCapabilities {
+            supports_inbound_control: true,
+            ..TransportCapabilities::default()
+        }
+    }
+
     fn is_connected(&self) -> bool {
         self.connected.load(Ordering::SeqCst)
     }
@@ -189,3 +478,87 @@ impl Transport for MockTransport {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InteractiveClient;
+
+    #[tokio::test]
+    async fn test_scripted_turns_are_replayed_and_recorded() {
+        let (transport, script) = MockTransport::builder()
+            .expect_user_message()
+            .then_reply(vec![
+                Message::assistant("Hi"),
+                Message::result_success("scripted"),
+            ])
+            .expect_user_message()
+            .then_reply(vec![
+                Message::assistant("Bye"),
+                Message::result_success("scripted"),
+            ])
+            .build();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+
+        let first = client.send_and_receive("Hello".to_string()).await.unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(script.remaining_expectations(), 1);
+        let second = client.send_and_receive("Later".to_string()).await.unwrap();
+        match &second[0] {
+            Message::Assistant { message, .. } => assert_eq!(message.text(), "Bye"),
+            other => panic!("expected Assistant, got {other:?}"),
+        }
+
+        client.interrupt().await.unwrap();
+        client.end_input().await.unwrap();
+
+        let sent = script.sent_messages();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].message["content"], "Later");
+        assert_eq!(
+            script.sent_control_requests()[0]["request"]["type"],
+            "interrupt"
+        );
+        assert!(script.input_ended());
+        script.assert_expectations_met();
+    }
+
+    #[tokio::test]
+    async fn test_scripted_control_requests_reach_the_client() {
+        let (transport, script) = MockTransport::builder().build();
+        let mut client = InteractiveClient::from_transport(transport);
+        client.connect().await.unwrap();
+        let mut control_rx = client.take_sdk_control_receiver().await.unwrap();
+
+        let request = serde_json::json!({
+            "type": "control_request",
+            "request_id": "req_1",
+            "request": {"subtype": "can_use_tool", "tool_name": "Bash", "input": {}}
+        });
+        script.sdk_control_tx().send(request.clone()).await.unwrap();
+        assert_eq!(control_rx.recv().await.unwrap(), request);
+
+        client
+            .send_control_response(serde_json::json!({
+                "subtype": "success",
+                "request_id": "req_1",
+                "response": {"behavior": "deny", "message": "no"}
+            }))
+            .await
+            .unwrap();
+        let responses = script.sent_control_responses();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0]["response"]["request_id"], "req_1");
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "1 expected user message(s) never sent")]
+    async fn test_unmet_expectation_fails_assertion() {
+        let (_transport, script) = MockTransport::builder()
+            .expect_user_message()
+            .then_reply(vec![Message::result_success("scripted")])
+            .build();
+        script.assert_expectations_met();
+    }
+}
@@ -4,6 +4,7 @@
 //! communicating with the Claude CLI.
 
 use crate::{
+    cli_features::SemVer,
     errors::Result,
     types::{ControlRequest, ControlResponse, Message},
 };
@@ -14,9 +15,17 @@ use std::pin::Pin;
 use tokio::sync::mpsc::Receiver;
 
 pub mod mock;
+pub mod recording;
+#[cfg(feature = "subprocess")]
 pub mod subprocess;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
+pub use recording::{ControlDirection, ControlLog, ControlLogEntry, RecordingTransport};
+#[cfg(feature = "subprocess")]
 pub use subprocess::SubprocessTransport;
+#[cfg(feature = "websocket")]
+pub use websocket::{WebSocketReconnectPolicy, WebSocketTransport, WebSocketTransportBuilder};
 
 /// Input message structure for sending to Claude
 #[derive(Debug, Clone, serde::Serialize)]
@@ -46,6 +55,27 @@ impl InputMessage {
         }
     }
 
+    /// Create a user message with context prepended to the prompt
+    ///
+    /// The context and the prompt are sent as two text blocks of the same
+    /// message, context first. It is part of the conversation rather than
+    /// the system prompt: the CLI keeps it in the session transcript like
+    /// any other user message.
+    pub fn user_with_context(content: String, context: String, session_id: String) -> Self {
+        Self {
+            r#type: "user".to_string(),
+            message: serde_json::json!({
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": context },
+                    { "type": "text", "text": content }
+                ]
+            }),
+            parent_tool_use_id: None,
+            session_id,
+        }
+    }
+
     /// Create a tool result message
     pub fn tool_result(
         tool_use_id: String,
@@ -68,6 +98,101 @@ impl InputMessage {
             session_id,
         }
     }
+
+    /// Create a tool result message from the outcome of a local tool handler
+    ///
+    /// `Ok` content is sent as a normal result; an `Err` is sent as its
+    /// `Display` text with `is_error` set, so Claude can react or retry.
+    pub fn tool_result_from<E: std::fmt::Display>(
+        tool_use_id: String,
+        result: std::result::Result<String, E>,
+        session_id: String,
+    ) -> Self {
+        match result {
+            Ok(content) => Self::tool_result(tool_use_id, content, session_id, false),
+            Err(err) => Self::tool_result(tool_use_id, err.to_string(), session_id, true),
+        }
+    }
+}
+
+/// A message tagged with its position in the transport's output
+///
+/// Sequence numbers start at 0 and grow by one for every message the
+/// transport emits, including across reconnects of the same transport, so
+/// consumers get a stable ordering key for persistence and can detect gaps.
+#[derive(Debug, Clone)]
+pub struct SequencedMessage {
+    /// Position of the message in the transport's output
+    pub seq: u64,
+    /// The message itself
+    pub message: Message,
+}
+
+/// Result message held back after its rate limit was yielded
+///
+/// Shared by every stream a transport hands out over the same queue, see
+/// [`surface_rate_limits`].
+#[cfg(any(feature = "subprocess", feature = "websocket"))]
+pub(crate) type HeldResult = std::sync::Arc<std::sync::Mutex<Option<Message>>>;
+
+/// Yield `SdkError::RateLimited` just before each rate-limited Result
+///
+/// The Result itself is still delivered, as the next item. Between the two
+/// it waits in `held`, so a consumer that drops the stream after the error
+/// gets the Result from the next stream over the same queue.
+#[cfg(any(feature = "subprocess", feature = "websocket"))]
+pub(crate) fn surface_rate_limits<S>(
+    stream: S,
+    held: HeldResult,
+) -> impl Stream<Item = Result<Message>> + Send + 'static
+where
+    S: Stream<Item = Result<Message>> + Send + Unpin + 'static,
+{
+    use futures::StreamExt;
+
+    futures::stream::unfold((stream, held), |(mut stream, held)| async move {
+        let pending = held.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(message) = pending {
+            return Some((Ok(message), (stream, held)));
+        }
+        let result = match stream.next().await? {
+            Ok(message) => match crate::errors::SdkError::rate_limited(&message) {
+                Some(err) => {
+                    *held.lock().unwrap_or_else(|e| e.into_inner()) = Some(message);
+                    Err(err)
+                },
+                None => Ok(message),
+            },
+            Err(e) => Err(e),
+        };
+        Some((result, (stream, held)))
+    })
+}
+
+/// Optional operations a transport supports, see [`Transport::capabilities`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportCapabilities {
+    /// `send_control_request` and the SDK control methods reach the CLI
+    pub supports_control: bool,
+    /// `send_control_request` can interrupt a running turn
+    pub supports_interrupt: bool,
+    /// `clone_stdin_sender` returns a sender while connected
+    pub supports_stdin_clone: bool,
+    /// `take_sdk_control_receiver` yields control requests from the CLI
+    pub supports_inbound_control: bool,
+}
+
+impl Default for TransportCapabilities {
+    /// What the default `Transport` methods provide: the required control
+    /// methods, but no stdin cloning or inbound control channel
+    fn default() -> Self {
+        Self {
+            supports_control: true,
+            supports_interrupt: true,
+            supports_stdin_clone: false,
+            supports_inbound_control: false,
+        }
+    }
 }
 
 /// Transport trait for communicating with Claude CLI
@@ -82,6 +207,17 @@ pub trait Transport: Send + Sync {
     /// Send a message to Claude
     async fn send_message(&mut self, message: InputMessage) -> Result<()>;
 
+    /// Send several messages to Claude, in order
+    ///
+    /// The default implementation sends them one by one; transports can
+    /// override it to deliver the whole batch at once.
+    async fn send_messages(&mut self, messages: Vec<InputMessage>) -> Result<()> {
+        for message in messages {
+            self.send_message(message).await?;
+        }
+        Ok(())
+    }
+
     /// Receive messages from Claude as a stream
     fn receive_messages(&mut self)
     -> Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>>;
@@ -105,6 +241,26 @@ pub trait Transport: Send + Sync {
         None
     }
 
+    /// Like `receive_messages`, with each message's sequence number
+    ///
+    /// Returns `None` for transports that don't number their output (e.g.
+    /// mock and websocket transports).
+    fn receive_sequenced_messages(
+        &mut self,
+    ) -> Option<Pin<Box<dyn Stream<Item = Result<SequencedMessage>> + Send + 'static>>> {
+        None
+    }
+
+    /// Like `subscribe_messages`, with each message's sequence number
+    ///
+    /// Returns `None` when `subscribe_messages` would, and for transports
+    /// that don't number their output.
+    fn subscribe_sequenced_messages(
+        &self,
+    ) -> Option<Pin<Box<dyn Stream<Item = Result<SequencedMessage>> + Send + 'static>>> {
+        None
+    }
+
     /// Send a control request (e.g., interrupt)
     async fn send_control_request(&mut self, request: ControlRequest) -> Result<()>;
 
@@ -145,6 +301,23 @@ pub trait Transport: Send + Sync {
         None
     }
 
+    /// Version of the CLI behind this transport, if known
+    ///
+    /// The subprocess transport reports the `--version` it saw at connect
+    /// time; other transports return `None`.
+    fn cli_version(&self) -> Option<SemVer> {
+        None
+    }
+
+    /// Report which optional operations this transport supports
+    ///
+    /// Lets transport-agnostic code skip calls that would be no-ops or
+    /// return `None`. Transports overriding `clone_stdin_sender` or
+    /// `take_sdk_control_receiver` should override this too.
+    fn capabilities(&self) -> TransportCapabilities {
+        TransportCapabilities::default()
+    }
+
     /// Check if the transport is connected
     #[allow(dead_code)]
     fn is_connected(&self) -> bool;
@@ -152,6 +325,15 @@ pub trait Transport: Send + Sync {
     /// Disconnect from the Claude CLI
     async fn disconnect(&mut self) -> Result<()>;
 
+    /// Disconnect after letting the CLI finish on its own for up to `timeout`
+    ///
+    /// Gives the CLI a chance to flush its output and transcript before it
+    /// is stopped. The default just calls `disconnect`.
+    async fn disconnect_graceful(&mut self, timeout: std::time::Duration) -> Result<()> {
+        let _ = timeout;
+        self.disconnect().await
+    }
+
     /// Signal end of input stream (default: no-op)
     async fn end_input(&mut self) -> Result<()> {
         Ok(())
@@ -167,17 +349,80 @@ pub enum TransportState {
     Connecting,
     /// Connected and ready
     Connected,
+    /// The CLI exited unexpectedly and is being restarted
+    Reconnecting,
     /// Disconnecting
     Disconnecting,
-    /// Error state
-    #[allow(dead_code)]
+    /// Error state (e.g. the CLI could not be restarted)
     Error,
 }
 
+/// Transport state, shared with the reader task that drives reconnects
+#[cfg(any(feature = "subprocess", feature = "websocket"))]
+#[derive(Clone)]
+pub(crate) struct SharedState(std::sync::Arc<std::sync::Mutex<TransportState>>);
+
+#[cfg(any(feature = "subprocess", feature = "websocket"))]
+impl SharedState {
+    pub(crate) fn new(state: TransportState) -> Self {
+        Self(std::sync::Arc::new(std::sync::Mutex::new(state)))
+    }
+
+    pub(crate) fn get(&self) -> TransportState {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn set(&self, state: TransportState) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = state;
+    }
+}
+
+/// Subtype of the SDK control message sent after the CLI was restarted or
+/// the connection to it was re-established
+///
+/// Control requests written to the previous process or connection will never
+/// be answered; consumers of the SDK control channel fail them when they see
+/// this marker.
+pub(crate) const RECONNECTED_CONTROL_SUBTYPE: &str = "sdk_control:reconnected";
+
+/// Wire form of a legacy control request, as transports write it to the CLI
+pub(crate) fn control_request_json(request: &ControlRequest) -> JsonValue {
+    match request {
+        ControlRequest::Interrupt { request_id } => serde_json::json!({
+            "type": "control_request",
+            "request": {
+                "type": "interrupt",
+                "request_id": request_id
+            }
+        }),
+    }
+}
+
+/// Whether an SDK control message is the marker for a restarted CLI
+pub(crate) fn is_reconnect_marker(message: &serde_json::Value) -> bool {
+    message.get("type").and_then(|v| v.as_str()) == Some("system")
+        && message.get("subtype").and_then(|v| v.as_str()) == Some(RECONNECTED_CONTROL_SUBTYPE)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_mock_transport_capabilities() {
+        let (transport, _handle) = mock::MockTransport::pair();
+        assert_eq!(
+            transport.capabilities(),
+            TransportCapabilities {
+                supports_control: true,
+                supports_interrupt: true,
+                supports_stdin_clone: false,
+                supports_inbound_control: true,
+            }
+        );
+        assert!(transport.clone_stdin_sender().is_none());
+    }
+
     #[test]
     fn test_input_message_user() {
         let msg = InputMessage::user("Hello".to_string(), "session-123".to_string());
@@ -205,4 +450,27 @@ mod tests {
         assert!(json.contains(r#""tool_use_id":"tool-123""#));
         assert!(json.contains(r#""is_error":false"#));
     }
+
+    #[test]
+    fn test_input_message_tool_result_from() {
+        let ok = InputMessage::tool_result_from(
+            "tool-ok".to_string(),
+            Ok::<_, std::io::Error>("done".to_string()),
+            "session".to_string(),
+        );
+        let block = &ok.message["content"][0];
+        assert_eq!(block["content"], "done");
+        assert_eq!(block["is_error"], false);
+
+        let err = InputMessage::tool_result_from(
+            "tool-err".to_string(),
+            Err::<String, _>(std::io::Error::other("permission denied")),
+            "session".to_string(),
+        );
+        let block = &err.message["content"][0];
+        assert_eq!(block["tool_use_id"], "tool-err");
+        assert_eq!(block["content"], "permission denied");
+        assert_eq!(block["is_error"], true);
+        assert_eq!(err.parent_tool_use_id, Some("tool-err".to_string()));
+    }
 }
@@ -0,0 +1,313 @@
+//! Transport wrapper recording the control protocol
+//!
+//! [`RecordingTransport`] delegates to another transport and appends every
+//! control message it sees, in either direction, to a shared [`ControlLog`].
+//! Regular conversation messages are not recorded. Clients wrap their
+//! transport in it when `record_control_protocol` is set; it can also wrap a
+//! custom transport directly.
+
+use super::{
+    InputMessage, SequencedMessage, Transport, TransportCapabilities, control_request_json,
+};
+use crate::{
+    cli_features::SemVer,
+    errors::Result,
+    types::{ControlRequest, ControlResponse, Message},
+};
+use async_trait::async_trait;
+use futures::stream::Stream;
+use serde_json::Value as JsonValue;
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tokio::sync::mpsc;
+
+/// Which way a recorded control message travelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlDirection {
+    /// Sent by the SDK to the CLI
+    Outbound,
+    /// Received by the SDK from the CLI
+    Inbound,
+}
+
+/// A control message recorded by [`RecordingTransport`]
+#[derive(Debug, Clone)]
+pub struct ControlLogEntry {
+    /// When the message passed through the transport
+    pub timestamp: SystemTime,
+    /// Which way it travelled
+    pub direction: ControlDirection,
+    /// The message as it appears on the wire
+    pub message: JsonValue,
+}
+
+/// Shared, append-only record of control messages
+#[derive(Debug, Clone, Default)]
+pub struct ControlLog(Arc<Mutex<Vec<ControlLogEntry>>>);
+
+impl ControlLog {
+    /// Recorded messages, oldest first
+    pub fn entries(&self) -> Vec<ControlLogEntry> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Forget all recorded messages
+    pub fn clear(&self) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    fn record(&self, direction: ControlDirection, message: JsonValue) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(ControlLogEntry {
+                timestamp: SystemTime::now(),
+                direction,
+                message,
+            });
+    }
+}
+
+/// Parse a raw stdin line if it is a control protocol message
+fn parse_control_line(line: &str) -> Option<JsonValue> {
+    let json: JsonValue = serde_json::from_str(line).ok()?;
+    let msg_type = json.get("type")?.as_str()?;
+    matches!(msg_type, "control_request" | "control_response").then_some(json)
+}
+
+/// Transport recording every control message passing through it
+pub struct RecordingTransport {
+    inner: Box<dyn Transport + Send>,
+    log: ControlLog,
+}
+
+impl RecordingTransport {
+    /// Wrap `inner`, recording into a fresh log
+    pub fn new(inner: Box<dyn Transport + Send>) -> Self {
+        Self {
+            inner,
+            log: ControlLog::default(),
+        }
+    }
+
+    /// Handle to the log this transport records into
+    pub fn control_log(&self) -> ControlLog {
+        self.log.clone()
+    }
+}
+
+#[async_trait]
+impl Transport for RecordingTransport {
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+
+    async fn connect(&mut self) -> Result<()> {
+        self.inner.connect().await
+    }
+
+    async fn send_message(&mut self, message: InputMessage) -> Result<()> {
+        self.inner.send_message(message).await
+    }
+
+    async fn send_messages(&mut self, messages: Vec<InputMessage>) -> Result<()> {
+        self.inner.send_messages(messages).await
+    }
+
+    fn receive_messages(
+        &mut self,
+    ) -> Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>> {
+        self.inner.receive_messages()
+    }
+
+    fn subscribe_messages(
+        &self,
+    ) -> Option<Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>>> {
+        self.inner.subscribe_messages()
+    }
+
+    fn receive_sequenced_messages(
+        &mut self,
+    ) -> Option<Pin<Box<dyn Stream<Item = Result<SequencedMessage>> + Send + 'static>>> {
+        self.inner.receive_sequenced_messages()
+    }
+
+    fn subscribe_sequenced_messages(
+        &self,
+    ) -> Option<Pin<Box<dyn Stream<Item = Result<SequencedMessage>> + Send + 'static>>> {
+        self.inner.subscribe_sequenced_messages()
+    }
+
+    async fn send_control_request(&mut self, request: ControlRequest) -> Result<()> {
+        self.log
+            .record(ControlDirection::Outbound, control_request_json(&request));
+        self.inner.send_control_request(request).await
+    }
+
+    async fn receive_control_response(&mut self) -> Result<Option<ControlResponse>> {
+        // Not recorded: the CLI's `control_response` is recorded as it comes
+        // in on the SDK control channel, this is only a parsed copy of it
+        self.inner.receive_control_response().await
+    }
+
+    async fn send_sdk_control_request(&mut self, request: JsonValue) -> Result<()> {
+        self.log.record(ControlDirection::Outbound, request.clone());
+        self.inner.send_sdk_control_request(request).await
+    }
+
+    async fn send_sdk_control_response(&mut self, response: JsonValue) -> Result<()> {
+        // Record the envelope transports put on the wire
+        self.log.record(
+            ControlDirection::Outbound,
+            serde_json::json!({"type": "control_response", "response": response.clone()}),
+        );
+        self.inner.send_sdk_control_response(response).await
+    }
+
+    fn take_sdk_control_receiver(&mut self) -> Option<mpsc::Receiver<JsonValue>> {
+        let mut inner_rx = self.inner.take_sdk_control_receiver()?;
+        let (tx, rx) = mpsc::channel(100);
+        let log = self.log.clone();
+        tokio::spawn(async move {
+            while let Some(message) = inner_rx.recv().await {
+                log.record(ControlDirection::Inbound, message.clone());
+                if tx.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Some(rx)
+    }
+
+    fn clone_stdin_sender(&self) -> Option<mpsc::Sender<String>> {
+        let inner_tx = self.inner.clone_stdin_sender()?;
+        let (tx, mut rx) = mpsc::channel::<String>(100);
+        let log = self.log.clone();
+        tokio::spawn(async move {
+            while let Some(line) = rx.recv().await {
+                if let Some(message) = parse_control_line(&line) {
+                    log.record(ControlDirection::Outbound, message);
+                }
+                if inner_tx.send(line).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Some(tx)
+    }
+
+    fn child_pid(&self) -> Option<u32> {
+        self.inner.child_pid()
+    }
+
+    fn cli_version(&self) -> Option<SemVer> {
+        self.inner.cli_version()
+    }
+
+    fn capabilities(&self) -> TransportCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        self.inner.disconnect().await
+    }
+
+    async fn disconnect_graceful(&mut self, timeout: std::time::Duration) -> Result<()> {
+        self.inner.disconnect_graceful(timeout).await
+    }
+
+    async fn end_input(&mut self) -> Result<()> {
+        self.inner.end_input().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[tokio::test]
+    async fn test_records_both_directions() {
+        let (inner, mut handle) = MockTransport::pair();
+        let mut transport = RecordingTransport::new(inner);
+        let log = transport.control_log();
+        let mut inbound = transport.take_sdk_control_receiver().unwrap();
+
+        let request = serde_json::json!({"type": "control_request", "request_id": "req_1"});
+        handle.sdk_control_tx.send(request.clone()).await.unwrap();
+        assert_eq!(inbound.recv().await.unwrap(), request);
+
+        transport
+            .send_sdk_control_response(serde_json::json!({"request_id": "req_1"}))
+            .await
+            .unwrap();
+        let response = handle.outbound_control_rx.recv().await.unwrap();
+
+        let entries = log.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].direction, ControlDirection::Inbound);
+        assert_eq!(entries[0].message, request);
+        assert_eq!(entries[1].direction, ControlDirection::Outbound);
+        assert_eq!(entries[1].message, response);
+        assert!(entries[0].timestamp <= entries[1].timestamp);
+
+        log.clear();
+        assert!(log.entries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_records_interrupt_round_trip_once() {
+        let (inner, mut handle) = MockTransport::pair();
+        let mut transport = RecordingTransport::new(inner);
+        let log = transport.control_log();
+        let mut inbound = transport.take_sdk_control_receiver().unwrap();
+
+        transport
+            .send_control_request(ControlRequest::Interrupt {
+                request_id: "int_1".to_string(),
+            })
+            .await
+            .unwrap();
+        let sent = handle.outbound_control_request_rx.recv().await.unwrap();
+
+        // The CLI's answer reaches both the SDK control channel and the
+        // legacy interrupt acknowledgement
+        let answer = serde_json::json!({
+            "type": "control_response",
+            "response": {"subtype": "success", "request_id": "int_1"}
+        });
+        handle.sdk_control_tx.send(answer.clone()).await.unwrap();
+        handle
+            .control_response_tx
+            .send(ControlResponse::InterruptAck {
+                request_id: "int_1".to_string(),
+                success: true,
+            })
+            .await
+            .unwrap();
+        assert_eq!(inbound.recv().await.unwrap(), answer);
+        assert!(
+            transport
+                .receive_control_response()
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        let entries = log.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].direction, ControlDirection::Outbound);
+        assert_eq!(entries[0].message, sent);
+        assert_eq!(entries[0].message["type"], "control_request");
+        assert_eq!(entries[0].message["request"]["type"], "interrupt");
+        assert_eq!(entries[1].direction, ControlDirection::Inbound);
+        assert_eq!(entries[1].message, answer);
+    }
+}
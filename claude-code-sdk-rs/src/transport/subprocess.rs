@@ -2,20 +2,33 @@
 //!
 //! This module implements the Transport trait using a subprocess to run the Claude CLI.
 
-use super::{InputMessage, Transport, TransportState};
+pub use crate::cli_features::SemVer;
+
+use super::{
+    HeldResult, InputMessage, RECONNECTED_CONTROL_SUBTYPE, SequencedMessage, SharedState,
+    Transport, TransportCapabilities, TransportState, control_request_json, surface_rate_limits,
+};
 use crate::{
+    cli_features::CliFeatures,
     errors::{Result, SdkError},
-    types::{ClaudeCodeOptions, ControlRequest, ControlResponse, Message, PermissionMode},
+    log_sink::{DiagnosticEvent, LogSink},
+    types::{
+        ClaudeCodeOptions, ControlRequest, ControlResponse, InputFormat, McpServerConfig, Message,
+        ModelInfo, PermissionMode, REQUEST_ID_ENV_VAR, ResourceLimits, StderrCallback,
+        ToolUseCallback,
+    },
 };
 use async_trait::async_trait;
 use futures::stream::{Stream, StreamExt};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::process::Stdio;
+use std::sync::Arc;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::{Child, Command};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
 use tokio::sync::mpsc;
-use tracing::{debug, error, info, warn};
+use tracing::{Instrument, debug, error, info, trace, warn};
 
 /// Default buffer size for channels
 const CHANNEL_BUFFER_SIZE: usize = 100;
@@ -23,57 +36,6 @@ const CHANNEL_BUFFER_SIZE: usize = 100;
 /// Minimum required CLI version
 const MIN_CLI_VERSION: (u32, u32, u32) = (2, 0, 0);
 
-/// Simple semantic version struct
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct SemVer {
-    /// Major version number.
-    pub major: u32,
-    /// Minor version number.
-    pub minor: u32,
-    /// Patch version number.
-    pub patch: u32,
-}
-
-impl std::fmt::Display for SemVer {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
-    }
-}
-
-impl SemVer {
-    /// Create a new `SemVer` from major, minor, patch components.
-    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
-        Self {
-            major,
-            minor,
-            patch,
-        }
-    }
-
-    /// Parse semantic version from string (e.g., "2.0.0" or "v2.0.0")
-    pub fn parse(version: &str) -> Option<Self> {
-        let version = version.trim().trim_start_matches('v');
-
-        // Handle versions like "@anthropic-ai/claude-code/2.0.0"
-        let version = if let Some(v) = version.split('/').next_back() {
-            v
-        } else {
-            version
-        };
-
-        let parts: Vec<&str> = version.split('.').collect();
-        if parts.len() < 2 {
-            return None;
-        }
-
-        Some(Self {
-            major: parts[0].parse().ok()?,
-            minor: parts.get(1)?.parse().ok()?,
-            patch: parts.get(2).and_then(|p| p.parse().ok()).unwrap_or(0),
-        })
-    }
-}
-
 /// Get the installed Claude CLI version by running `<cli_path> --version`.
 ///
 /// Returns `None` if the CLI is not found, the command fails, or the version
@@ -107,1447 +69,3813 @@ pub async fn get_cli_version(cli_path: &std::path::Path) -> Option<SemVer> {
     SemVer::parse(version_str.trim())
 }
 
-/// Subprocess-based transport for Claude CLI
-pub struct SubprocessTransport {
-    /// Configuration options
-    options: ClaudeCodeOptions,
-    /// CLI binary path
-    cli_path: PathBuf,
-    /// Child process
-    child: Option<Child>,
-    /// Sender for stdin
-    stdin_tx: Option<mpsc::Sender<String>>,
-    /// Sender for broadcasting messages to multiple receivers
-    message_broadcast_tx: Option<tokio::sync::broadcast::Sender<Message>>,
-    /// Receiver for control responses
-    control_rx: Option<mpsc::Receiver<ControlResponse>>,
-    /// Receiver for SDK control requests
-    sdk_control_rx: Option<mpsc::Receiver<serde_json::Value>>,
-    /// Transport state
-    state: TransportState,
-    /// Request counter for control requests
-    request_counter: u64,
-    /// Whether to close stdin after initial prompt
-    #[allow(dead_code)]
-    close_stdin_after_prompt: bool,
-}
+/// How long `list_models` waits for the CLI's initialize response
+const LIST_MODELS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
 
-impl SubprocessTransport {
-    /// Create a new subprocess transport
-    pub fn new(options: ClaudeCodeOptions) -> Result<Self> {
-        let cli_path = if let Some(ref explicit_path) = options.cli_path {
-            debug!("Using explicit CLI path: {:?}", explicit_path);
-            explicit_path.clone()
-        } else {
-            find_claude_cli()?
-        };
-        Ok(Self {
-            options,
-            cli_path,
-            child: None,
-            stdin_tx: None,
-            message_broadcast_tx: None,
-            control_rx: None,
-            sdk_control_rx: None,
-            state: TransportState::Disconnected,
-            request_counter: 0,
-            close_stdin_after_prompt: false,
-        })
+/// List the models available to the account, using a short-lived CLI process
+///
+/// Spawns the CLI configured by `options`, asks it for its model list (see
+/// [`SubprocessTransport::list_models`]) and shuts it down again.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use nexus_claude::{ClaudeCodeOptions, available_models};
+/// # async fn example() -> nexus_claude::Result<()> {
+/// for model in available_models(ClaudeCodeOptions::default()).await? {
+///     println!("{}: {}", model.value, model.description);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn available_models(options: ClaudeCodeOptions) -> Result<Vec<ModelInfo>> {
+    let mut transport = match options.cli_path.clone() {
+        Some(cli_path) => SubprocessTransport::with_cli_path(options, cli_path),
+        None => SubprocessTransport::new(options)?,
+    };
+    transport.connect().await?;
+    let models = transport.list_models().await;
+    if let Err(e) = transport.disconnect().await {
+        debug!("Error disconnecting after listing models: {}", e);
     }
+    models
+}
 
-    /// Create a new subprocess transport with async initialization
-    ///
-    /// This version supports auto-downloading the CLI if `auto_download_cli` is enabled
-    /// in the options and the CLI is not found.
-    pub async fn new_async(options: ClaudeCodeOptions) -> Result<Self> {
-        let cli_path = if let Some(ref explicit_path) = options.cli_path {
-            debug!("Using explicit CLI path: {:?}", explicit_path);
-            explicit_path.clone()
-        } else {
-            match find_claude_cli() {
-                Ok(path) => path,
-                Err(_) if options.auto_download_cli => {
-                    info!("Claude CLI not found, attempting automatic download...");
-                    crate::cli_download::download_cli(None, None).await?
-                },
-                Err(e) => return Err(e),
-            }
-        };
+/// Extract the model list from the payload of an initialize response
+fn parse_models(init_response: &serde_json::Value) -> Result<Vec<ModelInfo>> {
+    let models = init_response
+        .get("models")
+        .ok_or_else(|| SdkError::NotSupported {
+            feature: "listing models (this CLI version does not report them)".into(),
+        })?;
+    serde_json::from_value(models.clone()).map_err(|e| {
+        SdkError::parse_error(
+            format!("Invalid model list: {e}"),
+            init_response.to_string(),
+        )
+    })
+}
 
-        Ok(Self {
-            options,
-            cli_path,
-            child: None,
-            stdin_tx: None,
-            message_broadcast_tx: None,
-            control_rx: None,
-            sdk_control_rx: None,
-            state: TransportState::Disconnected,
-            request_counter: 0,
-            close_stdin_after_prompt: false,
-        })
-    }
+/// Where the stdout reader delivers parsed messages
+#[derive(Clone)]
+struct MessageSink {
+    channel: MessageChannel,
+    /// Sequence number of the next message
+    next_seq: Arc<tokio::sync::Mutex<u64>>,
+}
 
-    fn build_settings_value(&self) -> Option<String> {
-        let has_settings = self.options.settings.is_some();
-        let has_sandbox = self.options.sandbox.is_some();
+#[derive(Clone)]
+enum MessageChannel {
+    /// Single consumer: lossless; messages wait in memory while it lags
+    Queue(mpsc::Sender<SequencedMessage>),
+    /// Any number of subscribers; a lagging subscriber loses messages
+    Broadcast(tokio::sync::broadcast::Sender<SequencedMessage>),
+}
 
-        if !has_settings && !has_sandbox {
-            return None;
+impl MessageSink {
+    async fn send(&self, message: Message) {
+        // Hold the counter while sending so sequence order matches delivery
+        // order even with the stdout and stderr tasks both sending
+        let mut next_seq = self.next_seq.lock().await;
+        let message = SequencedMessage {
+            seq: *next_seq,
+            message,
+        };
+        *next_seq += 1;
+        match &self.channel {
+            // Only fails once the transport, which owns the receiver, is gone
+            MessageChannel::Queue(tx) => {
+                let _ = tx.send(message).await;
+            },
+            // Only fails when there are no subscribers
+            MessageChannel::Broadcast(tx) => {
+                let _ = tx.send(message);
+            },
         }
+    }
+}
 
-        // If only settings path and no sandbox, pass through as-is
-        if has_settings && !has_sandbox {
-            return self.options.settings.clone();
+/// Pass each tool-use block of an assistant message to `on_tool_use`
+pub(crate) fn observe_tool_uses(observer: &Option<ToolUseCallback>, message: &Message) {
+    if let Some(observer) = observer
+        && let Message::Assistant { message, .. } = message
+    {
+        for tool_use in message.tool_uses() {
+            observer(tool_use);
         }
+    }
+}
 
-        // If we have sandbox settings, merge into a JSON object (Python parity)
-        let mut settings_obj = serde_json::Map::new();
-
-        if let Some(ref settings) = self.options.settings {
-            let settings_str = settings.trim();
+/// How many recent output lines `dedup_messages` remembers
+const DEDUP_WINDOW: usize = 256;
 
-            let load_as_json_string =
-                |s: &str| -> Option<serde_json::Map<String, serde_json::Value>> {
-                    match serde_json::from_str::<serde_json::Value>(s) {
-                        Ok(serde_json::Value::Object(map)) => Some(map),
-                        Ok(_) => {
-                            warn!(
-                                "Settings JSON must be an object; ignoring provided JSON settings"
-                            );
-                            None
-                        },
-                        Err(_) => None,
-                    }
-                };
+/// Hashes of recently delivered CLI output lines
+#[derive(Default)]
+struct RecentLines {
+    order: std::collections::VecDeque<u64>,
+    seen: std::collections::HashSet<u64>,
+}
 
-            let load_from_file =
-                |path: &Path| -> Option<serde_json::Map<String, serde_json::Value>> {
-                    let content = std::fs::read_to_string(path).ok()?;
-                    match serde_json::from_str::<serde_json::Value>(&content) {
-                        Ok(serde_json::Value::Object(map)) => Some(map),
-                        Ok(_) => {
-                            warn!("Settings file JSON must be an object: {}", path.display());
-                            None
-                        },
-                        Err(e) => {
-                            warn!("Failed to parse settings file {}: {}", path.display(), e);
-                            None
-                        },
-                    }
-                };
+impl RecentLines {
+    /// Remember a line; returns false if it was already seen in the window
+    fn insert(&mut self, line: &str) -> bool {
+        use std::hash::{Hash, Hasher};
 
-            if settings_str.starts_with('{') && settings_str.ends_with('}') {
-                if let Some(map) = load_as_json_string(settings_str) {
-                    settings_obj = map;
-                } else {
-                    warn!(
-                        "Failed to parse settings as JSON, treating as file path: {}",
-                        settings_str
-                    );
-                    let settings_path = Path::new(settings_str);
-                    if settings_path.exists() {
-                        if let Some(map) = load_from_file(settings_path) {
-                            settings_obj = map;
-                        }
-                    } else {
-                        warn!("Settings file not found: {}", settings_path.display());
-                    }
-                }
-            } else {
-                let settings_path = Path::new(settings_str);
-                if settings_path.exists() {
-                    if let Some(map) = load_from_file(settings_path) {
-                        settings_obj = map;
-                    }
-                } else {
-                    warn!("Settings file not found: {}", settings_path.display());
-                }
-            }
+        let mut hasher = std::hash::DefaultHasher::new();
+        line.hash(&mut hasher);
+        let hash = hasher.finish();
+        if !self.seen.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > DEDUP_WINDOW
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.seen.remove(&oldest);
         }
+        true
+    }
+}
 
-        if let Some(ref sandbox) = self.options.sandbox {
-            match serde_json::to_value(sandbox) {
-                Ok(value) => {
-                    settings_obj.insert("sandbox".to_string(), value);
-                },
-                Err(e) => {
-                    warn!("Failed to serialize sandbox settings: {}", e);
-                },
+/// The running CLI process; replaced when the CLI is restarted
+///
+/// State changes that race with `disconnect` (which takes the process out)
+/// are made while holding this lock.
+type ChildSlot = Arc<std::sync::Mutex<Option<Child>>>;
+
+/// Spawn the CLI, reporting a missing binary as `SdkError::CliNotFound`
+fn spawn_cli(cmd: &mut Command, cli_path: &Path) -> Result<Child> {
+    cmd.spawn().map_err(|e| {
+        error!("Failed to spawn Claude CLI: {}", e);
+        // An explicit `cli_path` is only checked here, when it is used
+        if e.kind() == std::io::ErrorKind::NotFound && !cli_path.exists() {
+            SdkError::CliNotFound {
+                searched_paths: cli_path.display().to_string(),
             }
+        } else {
+            SdkError::ProcessError(e)
         }
+    })
+}
 
-        Some(serde_json::Value::Object(settings_obj).to_string())
-    }
+/// Take the piped stdio handles of a freshly spawned CLI
+fn take_stdio(child: &mut Child) -> Result<(ChildStdin, ChildStdout, ChildStderr)> {
+    let stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| SdkError::ConnectionError("Failed to get stdin".into()))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| SdkError::ConnectionError("Failed to get stdout".into()))?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| SdkError::ConnectionError("Failed to get stderr".into()))?;
+    Ok((stdin, stdout, stderr))
+}
 
-    /// Subscribe to messages without borrowing self (for lock-free consumption)
-    pub fn subscribe_messages(
-        &self,
-    ) -> Option<Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>>> {
-        self.message_broadcast_tx.as_ref().map(|tx| {
-            let rx = tx.subscribe();
-            Box::pin(tokio_stream::wrappers::BroadcastStream::new(rx).filter_map(
-                |result| async move {
-                    match result {
-                        Ok(msg) => Some(Ok(msg)),
-                        Err(tokio_stream::wrappers::errors::BroadcastStreamRecvError::Lagged(
-                            n,
-                        )) => {
-                            warn!("Receiver lagged by {} messages", n);
-                            None
-                        },
-                    }
-                },
-            )) as Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>>
-        })
+/// Kill the CLI and its process group without waiting for it
+fn force_kill(child: &mut Child) {
+    // Kill the entire process group to avoid orphan child processes
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        unsafe {
+            libc::kill(-(pid as i32), libc::SIGKILL);
+        }
     }
+    // Fallback: kill the child directly
+    let _ = child.start_kill();
+}
 
-    /// Receive SDK control requests
-    #[allow(dead_code)]
-    pub async fn receive_sdk_control_request(&mut self) -> Option<serde_json::Value> {
-        if let Some(ref mut rx) = self.sdk_control_rx {
-            rx.recv().await
-        } else {
-            None
+/// Stop the CLI, escalating from SIGINT to SIGTERM to SIGKILL
+async fn terminate(child: &mut Child) {
+    // Graceful shutdown escalation: SIGINT → SIGTERM → SIGKILL
+    // Mirrors the pattern used by Claude Code for MCP servers.
+    // Total max wait: 200ms + 500ms = 700ms before SIGKILL.
+    //
+    // Signals are sent to the PROCESS GROUP (negative PID) so that
+    // child processes (bash, find, sleep, etc.) are also terminated.
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        let pgid = -(pid as i32);
+
+        // Stage 1: SIGINT — give the CLI a chance to handle Ctrl-C gracefully
+        unsafe {
+            libc::kill(pgid, libc::SIGINT);
         }
-    }
+        debug!(
+            "Sent SIGINT to CLI process group (pid={}, pgid={})",
+            pid, pgid
+        );
 
-    /// Take the SDK control receiver (can only be called once)
-    pub fn take_sdk_control_receiver(&mut self) -> Option<mpsc::Receiver<serde_json::Value>> {
-        self.sdk_control_rx.take()
-    }
+        // Wait 200ms for graceful shutdown
+        match tokio::time::timeout(std::time::Duration::from_millis(200), child.wait()).await {
+            Ok(Ok(status)) => {
+                info!(
+                    "CLI process terminated gracefully via SIGINT (pid={}, status={})",
+                    pid, status
+                );
+                return;
+            },
+            Ok(Err(e)) => {
+                warn!("Error waiting for CLI process after SIGINT: {}", e);
+                // Fall through to SIGTERM
+            },
+            Err(_) => {
+                debug!("CLI process did not exit within 200ms after SIGINT, escalating to SIGTERM");
+                // Fall through to SIGTERM
+            },
+        }
 
-    /// Create with a specific CLI path
-    pub fn with_cli_path(options: ClaudeCodeOptions, cli_path: impl Into<PathBuf>) -> Self {
-        Self {
-            options,
-            cli_path: cli_path.into(),
-            child: None,
-            stdin_tx: None,
-            message_broadcast_tx: None,
-            control_rx: None,
-            sdk_control_rx: None,
-            state: TransportState::Disconnected,
-            request_counter: 0,
-            close_stdin_after_prompt: false,
+        // Stage 2: SIGTERM — stronger signal, still allows cleanup
+        unsafe {
+            libc::kill(pgid, libc::SIGTERM);
+        }
+        debug!(
+            "Sent SIGTERM to CLI process group (pid={}, pgid={})",
+            pid, pgid
+        );
+
+        match tokio::time::timeout(std::time::Duration::from_millis(500), child.wait()).await {
+            Ok(Ok(status)) => {
+                info!(
+                    "CLI process terminated via SIGTERM (pid={}, status={})",
+                    pid, status
+                );
+                return;
+            },
+            Ok(Err(e)) => {
+                warn!("Error waiting for CLI process after SIGTERM: {}", e);
+                // Fall through to SIGKILL
+            },
+            Err(_) => {
+                warn!(
+                    "CLI process did not exit within 500ms after SIGTERM, escalating to SIGKILL (pid={})",
+                    pid
+                );
+                // Fall through to SIGKILL
+            },
         }
     }
 
-    /// Set whether to close stdin after sending the initial prompt
-    #[allow(dead_code)]
-    pub fn set_close_stdin_after_prompt(&mut self, close: bool) {
-        self.close_stdin_after_prompt = close;
+    // Stage 3: SIGKILL — last resort
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        let pgid = -(pid as i32);
+        warn!(
+            "Sending SIGKILL to CLI process group (pid={}, pgid={})",
+            pid, pgid
+        );
+        unsafe {
+            libc::kill(pgid, libc::SIGKILL);
+        }
     }
+    // Fallback / non-unix: kill the child directly
+    match child.kill().await {
+        Ok(()) => info!("CLI process terminated via SIGKILL"),
+        Err(e) => warn!("Failed to kill CLI process: {}", e),
+    }
+}
 
-    /// Create transport for simple print mode (one-shot query)
-    #[allow(dead_code)]
-    pub fn for_print_mode(options: ClaudeCodeOptions, _prompt: String) -> Result<Self> {
-        let cli_path = find_claude_cli()?;
-        Ok(Self {
-            options,
-            cli_path,
-            child: None,
-            stdin_tx: None,
-            message_broadcast_tx: None,
-            control_rx: None,
-            sdk_control_rx: None,
-            state: TransportState::Disconnected,
-            request_counter: 0,
-            close_stdin_after_prompt: true,
-        })
-    }
-
-    /// Build the command with all necessary arguments
-    fn build_command(&self) -> Command {
-        let mut cmd = Command::new(&self.cli_path);
-
-        // Always use output-format stream-json and verbose (like Python SDK)
-        cmd.arg("--output-format").arg("stream-json");
-        cmd.arg("--verbose");
-
-        // For streaming/interactive mode, also add input-format stream-json
-        cmd.arg("--input-format").arg("stream-json");
-
-        // Include partial messages if requested
-        if self.options.include_partial_messages {
-            cmd.arg("--include-partial-messages");
-        }
-
-        // Add debug-to-stderr flag if debug_stderr is set
-        if self.options.debug_stderr.is_some() {
-            cmd.arg("--debug-to-stderr");
+/// Write queued input lines to the CLI's stdin
+///
+/// Without `replacements` the first failed write ends the task and is
+/// recorded in `failure`. With them (reconnects enabled) a failed line is
+/// kept until a restarted process's stdin arrives, and only running out of
+/// replacements is a failure, which also moves `state` to `Error`.
+async fn write_stdin_lines(
+    mut lines: mpsc::Receiver<String>,
+    stdin: ChildStdin,
+    mut replacements: Option<mpsc::UnboundedReceiver<ChildStdin>>,
+    failure: Arc<std::sync::Mutex<Option<String>>>,
+    state: SharedState,
+) {
+    let record_failure = |error: String| {
+        // Recorded before the receiver is dropped, so senders see the
+        // cause rather than a bare channel error
+        *failure.lock().unwrap_or_else(|e| e.into_inner()) = Some(error);
+        if matches!(
+            state.get(),
+            TransportState::Connected | TransportState::Reconnecting
+        ) {
+            state.set(TransportState::Error);
         }
+    };
+    let mut stdin = Some(stdin);
+    let mut unsent: Option<String> = None;
+    debug!("Stdin handler started");
+    loop {
+        let line = match unsent.take() {
+            Some(line) => line,
+            None => match lines.recv().await {
+                Some(line) => line,
+                None => break,
+            },
+        };
+        debug!("Received line from channel: {}", line);
 
-        // Handle max_output_tokens (priority: option > env var)
-        // Maximum safe value is 32000, values above this may cause issues
-        if let Some(max_tokens) = self.options.max_output_tokens {
-            // Option takes priority - validate and cap at 32000
-            let capped = max_tokens.clamp(1, 32000);
-            cmd.env("CLAUDE_CODE_MAX_OUTPUT_TOKENS", capped.to_string());
-            debug!("Setting max_output_tokens from option: {}", capped);
-        } else {
-            // Fall back to environment variable handling
-            if let Ok(current_value) = std::env::var("CLAUDE_CODE_MAX_OUTPUT_TOKENS") {
-                if let Ok(tokens) = current_value.parse::<u32>() {
-                    if tokens > 32000 {
-                        warn!(
-                            "CLAUDE_CODE_MAX_OUTPUT_TOKENS={} exceeds maximum safe value of 32000, overriding to 32000",
-                            tokens
-                        );
-                        cmd.env("CLAUDE_CODE_MAX_OUTPUT_TOKENS", "32000");
-                    }
-                    // If it's <= 32000, leave it as is
-                } else {
-                    // Invalid value, set to safe default
-                    warn!(
-                        "Invalid CLAUDE_CODE_MAX_OUTPUT_TOKENS value: {}, setting to 8192",
-                        current_value
-                    );
-                    cmd.env("CLAUDE_CODE_MAX_OUTPUT_TOKENS", "8192");
+        if let Some(ref mut replacements) = replacements {
+            // Switch to the newest restarted process, if any
+            while let Ok(restarted) = replacements.try_recv() {
+                stdin = Some(restarted);
+            }
+            if stdin.is_none() {
+                match replacements.recv().await {
+                    Some(restarted) => stdin = Some(restarted),
+                    None => {
+                        error!("Claude CLI exited and could not be restarted");
+                        record_failure("the CLI exited and could not be restarted".into());
+                        break;
+                    },
                 }
             }
         }
+        let Some(ref mut writer) = stdin else {
+            break;
+        };
 
-        // System prompts (match Python SDK behavior)
-        //
-        // Python always passes `--system-prompt ""` when `system_prompt` is None.
-        if let Some(ref prompt_v2) = self.options.system_prompt_v2 {
-            match prompt_v2 {
-                crate::types::SystemPrompt::String(s) => {
-                    cmd.arg("--system-prompt").arg(s);
-                },
-                crate::types::SystemPrompt::Preset { append, .. } => {
-                    // Python only uses preset prompts to optionally append to the default preset.
-                    // It does not pass a preset selector flag to the CLI.
-                    if let Some(append_text) = append {
-                        cmd.arg("--append-system-prompt").arg(append_text);
-                    }
-                },
+        let written = async {
+            writer.write_all(line.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+            writer.flush().await
+        }
+        .await;
+        match written {
+            Ok(()) => debug!("Successfully sent to Claude process: {}", line),
+            Err(e) if replacements.is_some() => {
+                warn!(
+                    "CLI stdin closed, keeping input for the restarted process: {}",
+                    e
+                );
+                stdin = None;
+                unsent = Some(line);
+            },
+            Err(e) => {
+                error!("Failed to write to stdin: {}", e);
+                record_failure(e.to_string());
+                break;
+            },
+        }
+    }
+    debug!("Stdin handler ended");
+}
+
+/// Forward the CLI's stderr to the debug writer, callback and logs
+///
+/// Actionable errors are collected and, unless `suppress_stderr_errors` is
+/// set, surfaced to message consumers as a `system` message with subtype
+/// `"error"` once stderr closes.
+async fn read_stderr(
+    stderr: ChildStderr,
+    sink: MessageSink,
+    debug_stderr: Option<Arc<tokio::sync::Mutex<dyn std::io::Write + Send + Sync>>>,
+    stderr_callback: Option<StderrCallback>,
+    suppress_errors: bool,
+) {
+    let reader = BufReader::new(stderr);
+    let mut lines = reader.lines();
+    let mut error_buffer = Vec::new();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if !line.trim().is_empty() {
+            // If debug_stderr is set, write to it
+            if let Some(ref debug_output) = debug_stderr {
+                let mut output = debug_output.lock().await;
+                let _ = writeln!(output, "{line}");
+                let _ = output.flush();
             }
-        } else {
-            // Fallback to deprecated fields for backward compatibility
-            #[allow(deprecated)]
-            match self.options.system_prompt.as_deref() {
-                Some(prompt) => {
-                    cmd.arg("--system-prompt").arg(prompt);
-                },
-                None => {
-                    cmd.arg("--system-prompt").arg("");
-                },
+
+            if let Some(ref callback) = stderr_callback {
+                callback.as_ref()(line.as_str());
             }
-            #[allow(deprecated)]
-            if let Some(ref prompt) = self.options.append_system_prompt {
-                cmd.arg("--append-system-prompt").arg(prompt);
+
+            // Filter out non-actionable noise: hook callback errors and
+            // AbortError from minified CLI JS.  These are expected during
+            // session interrupts (the CLI aborts in-flight hooks) and would
+            // otherwise spam ERROR logs + pollute the error_buffer which
+            // triggers a System::error broadcast to consumers.
+            if line.contains("Error in hook callback")
+                || (line.contains("AbortError") && line.len() < 200)
+                || line.starts_with("      at ")
+            {
+                debug!("Claude CLI stderr (filtered — hook abort noise): {}", line);
+                continue;
             }
-        }
 
-        // Tool configuration
-        if !self.options.allowed_tools.is_empty() {
-            cmd.arg("--allowedTools")
-                .arg(self.options.allowed_tools.join(","));
-        }
-        if !self.options.disallowed_tools.is_empty() {
-            cmd.arg("--disallowedTools")
-                .arg(self.options.disallowed_tools.join(","));
-        }
+            // Skip minified JS source lines (long lines from bundled CLI code
+            // that leak into stderr during hook crashes).  These carry zero
+            // diagnostic value and can be thousands of characters long.
+            if line.len() > 500
+                && (line.contains("Symbol.for(\"react.memo_cache_sentinel\")")
+                    || line.contains("/$bunfs/root/src/entrypoints/cli.js"))
+            {
+                debug!(
+                    "Claude CLI stderr (filtered — minified JS, {} bytes)",
+                    line.len()
+                );
+                continue;
+            }
 
-        // Permission mode
-        match self.options.permission_mode {
-            PermissionMode::Default => {
-                cmd.arg("--permission-mode").arg("default");
-            },
-            PermissionMode::AcceptEdits => {
-                cmd.arg("--permission-mode").arg("acceptEdits");
-            },
-            PermissionMode::Plan => {
-                cmd.arg("--permission-mode").arg("plan");
-            },
-            PermissionMode::BypassPermissions => {
-                cmd.arg("--permission-mode").arg("bypassPermissions");
-            },
+            error!("Claude CLI stderr: {}", line);
+            error_buffer.push(line.clone());
+
+            // Check for common error patterns
+            if line.contains("command not found") || line.contains("No such file") {
+                error!("Claude CLI binary not found or not executable");
+            } else if line.contains("ENOENT") || line.contains("spawn") {
+                error!("Failed to spawn Claude CLI process - binary may not be installed");
+            } else if line.contains("authentication")
+                || line.contains("API key")
+                || line.contains("Unauthorized")
+            {
+                error!("Claude CLI authentication error - please run 'claude-code api login'");
+            } else if line.contains("model")
+                && (line.contains("not available") || line.contains("not found"))
+            {
+                error!("Model not available for your account: {}", line);
+            } else if line.contains("Error:") || line.contains("error:") {
+                error!("Claude CLI error detected: {}", line);
+            }
         }
+    }
 
-        // Model
-        if let Some(ref model) = self.options.model {
-            cmd.arg("--model").arg(model);
+    // If we collected any actionable errors (after filtering), log and broadcast them.
+    if !error_buffer.is_empty() {
+        let error_msg = error_buffer.join("\n");
+        error!("Claude CLI stderr output collected:\n{}", error_msg);
+        if suppress_errors {
+            return;
         }
 
-        // Permission prompt tool
-        if let Some(ref tool_name) = self.options.permission_prompt_tool_name {
-            cmd.arg("--permission-prompt-tool").arg(tool_name);
-        }
+        // Surface the errors to message consumers
+        sink.send(Message::System {
+            subtype: "error".to_string(),
+            data: serde_json::json!({
+                "source": "stderr",
+                "error": "Claude CLI error output",
+                "details": error_msg
+            }),
+        })
+        .await;
+    }
+}
 
-        // Max turns
-        if let Some(max_turns) = self.options.max_turns {
-            cmd.arg("--max-turns").arg(max_turns.to_string());
-        }
+/// Routes the CLI's stdout to the message, control and SDK control channels
+struct OutputRouter {
+    /// Sink shared with the stderr reader
+    message_sink: MessageSink,
+    /// Regular messages, handed to [`forward_messages`] so a consumer that
+    /// lags behind never stops control responses from being read
+    messages: mpsc::UnboundedSender<Message>,
+    control_tx: mpsc::Sender<ControlResponse>,
+    sdk_control_tx: mpsc::Sender<serde_json::Value>,
+    log_sink: Option<Arc<dyn LogSink>>,
+    on_tool_use: Option<ToolUseCallback>,
+    recent_lines: Option<RecentLines>,
+    /// Latest session id seen in the output, resumed after a restart
+    session_id: Option<String>,
+    /// Set when a turn finished; a restarted CLI that completes a turn is healthy
+    turn_completed: bool,
+}
 
-        // Max thinking tokens (extended thinking budget)
-        // Only pass if non-zero to match Python SDK behavior
-        if self.options.max_thinking_tokens > 0 {
-            cmd.arg("--max-thinking-tokens")
-                .arg(self.options.max_thinking_tokens.to_string());
+impl OutputRouter {
+    fn emit(&self, event: DiagnosticEvent) {
+        if let Some(ref sink) = self.log_sink {
+            sink.log(&event);
         }
+    }
 
-        // Working directory
-        if let Some(ref cwd) = self.options.cwd {
-            cmd.current_dir(cwd);
-        }
+    /// Route every line of `stdout` until the CLI closes it
+    async fn route(&mut self, stdout: ChildStdout) {
+        debug!("Stdout handler started");
+        let reader = BufReader::new(stdout);
+        let mut lines = reader.lines();
 
-        // Add environment variables
-        for (key, value) in &self.options.env {
-            cmd.env(key, value);
-        }
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
 
-        // MCP servers - use --mcp-config with JSON format like Python SDK
-        if !self.options.mcp_servers.is_empty() {
-            let mcp_config = serde_json::json!({
-                "mcpServers": self.options.mcp_servers
-            });
-            cmd.arg("--mcp-config").arg(mcp_config.to_string());
-        }
+            debug!("Claude output: {}", line);
+
+            // Try to parse as JSON
+            match serde_json::from_str::<serde_json::Value>(&line) {
+                Ok(json) => {
+                    // Check message type
+                    if let Some(msg_type) = json.get("type").and_then(|v| v.as_str()) {
+                        // Handle control responses - these are responses to OUR control requests
+                        if msg_type == "control_response" {
+                            debug!("Received control response: {:?}", json);
+
+                            // Send to sdk_control channel for control protocol mode
+                            let _ = self.sdk_control_tx.send(json.clone()).await;
+
+                            // Also parse and send to legacy control_tx for non-control-protocol mode
+                            // (needed for interrupt functionality when query_handler is None)
+                            // CLI returns: {"type":"control_response","response":{"subtype":"success","request_id":"..."}}
+                            // or: {"type":"control_response","response":{"subtype":"error","request_id":"...","error":"..."}}
+                            if let Some(response_obj) = json.get("response")
+                                && let Some(request_id) = response_obj
+                                    .get("request_id")
+                                    .or_else(|| response_obj.get("requestId"))
+                                    .and_then(|v| v.as_str())
+                            {
+                                // Determine success from subtype
+                                let subtype = response_obj.get("subtype").and_then(|v| v.as_str());
+                                let success = subtype == Some("success");
+
+                                let control_resp = ControlResponse::InterruptAck {
+                                    request_id: request_id.to_string(),
+                                    success,
+                                };
+                                // Only drained outside control protocol mode; never wait on it
+                                if let Err(mpsc::error::TrySendError::Full(_)) =
+                                    self.control_tx.try_send(control_resp)
+                                {
+                                    debug!("Legacy control channel full, dropping ack");
+                                }
+                            }
+                            continue;
+                        }
 
-        // Continue/resume
-        if self.options.continue_conversation {
-            cmd.arg("--continue");
-        }
-        if let Some(ref resume_id) = self.options.resume {
-            cmd.arg("--resume").arg(resume_id);
-        }
+                        // Handle control requests FROM CLI (standard format)
+                        if msg_type == "control_request" {
+                            debug!("Received control request from CLI: {:?}", json);
+                            // Send the FULL message including requestId and request
+                            let _ = self.sdk_control_tx.send(json.clone()).await;
+                            continue;
+                        }
 
-        // Settings value (merge sandbox into settings if provided)
-        if let Some(settings_value) = self.build_settings_value() {
-            cmd.arg("--settings").arg(settings_value);
-        }
+                        // Handle control messages (new format)
+                        if msg_type == "control"
+                            && let Some(control) = json.get("control")
+                        {
+                            debug!("Received control message: {:?}", control);
+                            let _ = self.sdk_control_tx.send(control.clone()).await;
+                            continue;
+                        }
 
-        // Additional directories
-        for dir in &self.options.add_dirs {
-            cmd.arg("--add-dir").arg(dir);
-        }
+                        // Handle SDK control requests FROM CLI (legacy format)
+                        if msg_type == "sdk_control_request" {
+                            // Send the FULL message including requestId
+                            debug!("Received SDK control request (legacy): {:?}", json);
+                            let _ = self.sdk_control_tx.send(json.clone()).await;
+                            continue;
+                        }
 
-        // Fork session if requested
-        if self.options.fork_session {
-            cmd.arg("--fork-session");
-        }
+                        // Check for system messages with SDK control subtypes
+                        if msg_type == "system"
+                            && let Some(subtype) = json.get("subtype").and_then(|v| v.as_str())
+                            && subtype.starts_with("sdk_control:")
+                        {
+                            // This is an SDK control message
+                            debug!("Received SDK control message: {}", subtype);
+                            let _ = self.sdk_control_tx.send(json.clone()).await;
+                            // Still parse as regular message for now
+                        }
+                    }
 
-        // ========== Phase 3 CLI args (Python SDK v0.1.12+ sync) ==========
+                    // Remember the session so a restarted CLI can resume it
+                    if let Some(session_id) = json.get("session_id").and_then(|v| v.as_str()) {
+                        self.session_id = Some(session_id.to_string());
+                    }
 
-        // Tools configuration (base set of tools)
-        if let Some(ref tools) = self.options.tools {
-            match tools {
-                crate::types::ToolsConfig::List(list) => {
-                    if list.is_empty() {
-                        cmd.arg("--tools").arg("");
-                    } else {
-                        cmd.arg("--tools").arg(list.join(","));
+                    // Try to parse as a regular message
+                    match crate::message_parser::parse_message(json) {
+                        Ok(Some(message)) => {
+                            if let Message::Result {
+                                ref session_id,
+                                total_cost_usd,
+                                duration_ms,
+                                ..
+                            } = message
+                            {
+                                self.emit(DiagnosticEvent::Cost {
+                                    session_id: session_id.clone(),
+                                    total_cost_usd,
+                                    duration_ms,
+                                });
+                                self.turn_completed = true;
+                            }
+                            for failure in SdkError::mcp_server_failures(&message) {
+                                warn!("{}", failure);
+                                if let SdkError::McpServerFailed { name, detail } = failure {
+                                    self.emit(DiagnosticEvent::McpServerFailed { name, detail });
+                                }
+                            }
+                            if let Some(ref mut recent) = self.recent_lines
+                                && !recent.insert(&line)
+                            {
+                                debug!("Dropping duplicate message: {}", line);
+                                continue;
+                            }
+                            observe_tool_uses(&self.on_tool_use, &message);
+                            let _ = self.messages.send(message);
+                        },
+                        Ok(None) => {
+                            // Ignore non-message JSON
+                        },
+                        Err(e) => {
+                            warn!("Failed to parse message: {}", e);
+                            self.emit(DiagnosticEvent::ParseError {
+                                error: e.to_string(),
+                                line,
+                            });
+                        },
                     }
                 },
-                crate::types::ToolsConfig::Preset(_preset) => {
-                    // Preset object - 'claude_code' preset maps to 'default'
-                    cmd.arg("--tools").arg("default");
+                Err(e) => {
+                    warn!("Failed to parse JSON: {} - Line: {}", e, line);
+                    self.emit(DiagnosticEvent::ParseError {
+                        error: e.to_string(),
+                        line,
+                    });
                 },
             }
         }
+        info!("Stdout reader ended");
+    }
+}
 
-        // SDK betas
-        if !self.options.betas.is_empty() {
-            let betas: Vec<String> = self.options.betas.iter().map(|b| b.to_string()).collect();
-            cmd.arg("--betas").arg(betas.join(","));
-        }
+/// Deliver the stdout reader's messages to `sink` in order
+///
+/// Runs apart from the reader so that waiting for room in a bounded queue
+/// never delays control responses that arrive behind the queued messages.
+async fn forward_messages(mut messages: mpsc::UnboundedReceiver<Message>, sink: MessageSink) {
+    while let Some(message) = messages.recv().await {
+        sink.send(message).await;
+    }
+}
 
-        // Max budget USD
-        if let Some(budget) = self.options.max_budget_usd {
-            cmd.arg("--max-budget-usd").arg(budget.to_string());
-        }
+/// Default for [`ClaudeCodeOptions::max_reconnect_attempts`]
+const DEFAULT_MAX_RECONNECT_ATTEMPTS: u32 = 3;
 
-        // Fallback model
-        if let Some(ref fallback) = self.options.fallback_model {
-            cmd.arg("--fallback-model").arg(fallback);
+/// Default for [`ClaudeCodeOptions::disconnect_timeout`]
+const DEFAULT_DISCONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Line sent every [`ClaudeCodeOptions::keepalive_interval`]; the CLI ignores it
+const KEEPALIVE_LINE: &str = r#"{"type":"keep_alive"}"#;
+
+/// Send [`KEEPALIVE_LINE`] every `period` until stdin is closed
+///
+/// Holds only a weak sender so that dropping the transport's sender still
+/// closes the CLI's stdin.
+async fn send_keepalives(stdin: mpsc::WeakSender<String>, period: std::time::Duration) {
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        ticks.tick().await;
+        let Some(tx) = stdin.upgrade() else {
+            break;
+        };
+        if tx.send(KEEPALIVE_LINE.to_string()).await.is_err() {
+            break;
         }
+        trace!("Sent keepalive");
+    }
+    debug!("Keepalive stopped");
+}
 
-        // File checkpointing
-        if self.options.enable_file_checkpointing {
-            cmd.env("CLAUDE_CODE_ENABLE_SDK_FILE_CHECKPOINTING", "true");
+/// How long to wait for the CLI to exit once it closed its stdout
+const EXIT_WAIT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Restarts the CLI after an unexpected exit (`reconnect_on_failure`)
+struct Reconnector {
+    options: ClaudeCodeOptions,
+    cli_path: PathBuf,
+    state: SharedState,
+    child: ChildSlot,
+    /// Hands the restarted process's stdin to the stdin handler
+    stdin_swap: mpsc::UnboundedSender<ChildStdin>,
+    span: tracing::Span,
+    /// Restart attempts since the CLI last completed a turn
+    attempts: u32,
+}
+
+impl Reconnector {
+    /// Restart the CLI if its stdout closed because it died
+    ///
+    /// Returns the restarted process's stdout, or `None` if the exit was
+    /// expected (disconnect, clean exit) or every attempt failed.
+    async fn recover(&mut self, router: &mut OutputRouter) -> Option<ChildStdout> {
+        let mut exited = {
+            let mut slot = self.child.lock().unwrap_or_else(|e| e.into_inner());
+            if self.state.get() != TransportState::Connected {
+                return None;
+            }
+            slot.take()?
+        };
+
+        match tokio::time::timeout(EXIT_WAIT, exited.wait()).await {
+            Ok(Ok(status)) if status.success() => {
+                debug!("Claude CLI exited cleanly, not restarting");
+                let mut slot = self.child.lock().unwrap_or_else(|e| e.into_inner());
+                if slot.is_none() {
+                    *slot = Some(exited);
+                }
+                return None;
+            },
+            Ok(Ok(status)) => warn!("Claude CLI exited unexpectedly ({})", status),
+            Ok(Err(e)) => warn!("Failed to wait for the exited Claude CLI: {}", e),
+            Err(_) => {
+                warn!("Claude CLI closed stdout but is still running, killing it");
+                force_kill(&mut exited);
+            },
         }
 
-        // Output format for structured outputs (json_schema only)
-        if let Some(ref format) = self.options.output_format
-            && format.get("type").and_then(|v| v.as_str()) == Some("json_schema")
-            && let Some(schema) = format.get("schema")
-            && let Ok(schema_json) = serde_json::to_string(schema)
         {
-            cmd.arg("--json-schema").arg(schema_json);
+            let _slot = self.child.lock().unwrap_or_else(|e| e.into_inner());
+            if self.state.get() != TransportState::Connected {
+                return None;
+            }
+            self.state.set(TransportState::Reconnecting);
         }
 
-        // Plugin directories
-        for plugin in &self.options.plugins {
-            match plugin {
-                crate::types::SdkPluginConfig::Local { path } => {
-                    cmd.arg("--plugin-dir").arg(path);
+        if std::mem::take(&mut router.turn_completed) {
+            self.attempts = 0;
+        }
+        let mut options = self.options.clone();
+        if let Some(ref session_id) = router.session_id {
+            options.resume = Some(session_id.clone());
+            options.continue_conversation = false;
+            // The session to resume is already the fork
+            options.fork_session = false;
+        }
+        let max_attempts = self
+            .options
+            .max_reconnect_attempts
+            .unwrap_or(DEFAULT_MAX_RECONNECT_ATTEMPTS);
+
+        while self.attempts < max_attempts {
+            self.attempts += 1;
+            let delay = self.options.reconnect_backoff.delay(self.attempts);
+            info!(
+                "Restarting Claude CLI in {:?} (attempt {}/{})",
+                delay, self.attempts, max_attempts
+            );
+            tokio::time::sleep(delay).await;
+            if self.state.get() != TransportState::Reconnecting {
+                return None;
+            }
+
+            match self.respawn(&options) {
+                Ok((mut child, stdin, stdout, stderr)) => {
+                    {
+                        let mut slot = self.child.lock().unwrap_or_else(|e| e.into_inner());
+                        if self.state.get() != TransportState::Reconnecting {
+                            // Disconnected while restarting
+                            force_kill(&mut child);
+                            return None;
+                        }
+                        router.emit(DiagnosticEvent::Spawned {
+                            cli_path: self.cli_path.clone(),
+                            pid: child.id(),
+                        });
+                        *slot = Some(child);
+                        self.state.set(TransportState::Connected);
+                    }
+                    let _ = self.stdin_swap.send(stdin);
+                    tokio::spawn(
+                        read_stderr(
+                            stderr,
+                            router.message_sink.clone(),
+                            self.options.debug_stderr.clone(),
+                            self.options.stderr_callback.clone(),
+                            self.options.suppress_stderr_errors,
+                        )
+                        .instrument(self.span.clone()),
+                    );
+
+                    // Control requests sent to the dead process will never be answered
+                    let _ = router
+                        .sdk_control_tx
+                        .send(serde_json::json!({
+                            "type": "system",
+                            "subtype": RECONNECTED_CONTROL_SUBTYPE,
+                        }))
+                        .await;
+                    let _ = router.messages.send(Message::System {
+                        subtype: "reconnected".to_string(),
+                        data: serde_json::json!({
+                            "session_id": router.session_id,
+                            "attempt": self.attempts,
+                        }),
+                    });
+                    info!("Reconnected to Claude CLI");
+                    return Some(stdout);
                 },
+                Err(e) => warn!(
+                    "Failed to restart Claude CLI (attempt {}/{}): {}",
+                    self.attempts, max_attempts, e
+                ),
             }
         }
 
-        // Programmatic agents
-        if let Some(ref agents) = self.options.agents
-            && !agents.is_empty()
-            && let Ok(json_str) = serde_json::to_string(agents)
-        {
-            cmd.arg("--agents").arg(json_str);
+        error!(
+            "Giving up on restarting Claude CLI after {} attempts",
+            max_attempts
+        );
+        let _slot = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        if self.state.get() == TransportState::Reconnecting {
+            self.state.set(TransportState::Error);
         }
+        None
+    }
 
-        // Setting sources (comma-separated). Always pass a value for SDK parity with Python.
-        let sources_value = self
-            .options
-            .setting_sources
-            .as_ref()
-            .map(|sources| {
-                sources
-                    .iter()
-                    .map(|s| match s {
-                        crate::types::SettingSource::User => "user",
-                        crate::types::SettingSource::Project => "project",
-                        crate::types::SettingSource::Local => "local",
-                    })
-                    .collect::<Vec<_>>()
-                    .join(",")
-            })
-            .unwrap_or_default();
-        cmd.arg("--setting-sources").arg(sources_value);
+    /// Spawn a new CLI process with `options`
+    fn respawn(
+        &self,
+        options: &ClaudeCodeOptions,
+    ) -> Result<(Child, ChildStdin, ChildStdout, ChildStderr)> {
+        // Only used to build the command; it is never connected
+        let launcher = SubprocessTransport::with_cli_path(options.clone(), self.cli_path.clone());
+        let mut cmd = launcher.prepare_command()?;
+        let mut child = spawn_cli(&mut cmd, &self.cli_path)?;
+        match take_stdio(&mut child) {
+            Ok((stdin, stdout, stderr)) => Ok((child, stdin, stdout, stderr)),
+            Err(e) => {
+                force_kill(&mut child);
+                Err(e)
+            },
+        }
+    }
+}
+
+/// Subprocess-based transport for Claude CLI
+pub struct SubprocessTransport {
+    /// Configuration options
+    options: ClaudeCodeOptions,
+    /// CLI binary path
+    cli_path: PathBuf,
+    /// Child process
+    child: ChildSlot,
+    /// Sender for stdin
+    stdin_tx: Option<mpsc::Sender<String>>,
+    /// Write error that ended the stdin task, if any
+    stdin_failure: Arc<std::sync::Mutex<Option<String>>>,
+    /// Rate-limited Result still to be delivered by `receive_messages`
+    held_result: HeldResult,
+    /// Sender for broadcasting messages (only with `allow_multiple_receivers`)
+    message_broadcast_tx: Option<tokio::sync::broadcast::Sender<SequencedMessage>>,
+    /// Shared single-consumer message queue (the default)
+    message_rx: Option<Arc<tokio::sync::Mutex<mpsc::Receiver<SequencedMessage>>>>,
+    /// Sequence number of the next message, kept across reconnects
+    next_message_seq: Arc<tokio::sync::Mutex<u64>>,
+    /// Receiver for control responses
+    control_rx: Option<mpsc::Receiver<ControlResponse>>,
+    /// Receiver for SDK control requests
+    sdk_control_rx: Option<mpsc::Receiver<serde_json::Value>>,
+    /// Transport state
+    state: SharedState,
+    /// Request counter for control requests
+    request_counter: u64,
+    /// Whether to close stdin after initial prompt
+    #[allow(dead_code)]
+    close_stdin_after_prompt: bool,
+    /// CLI version reported by `--version` at connect time
+    cli_version: Option<SemVer>,
+}
+
+impl SubprocessTransport {
+    /// Create a new subprocess transport
+    pub fn new(options: ClaudeCodeOptions) -> Result<Self> {
+        let cli_path = if let Some(ref explicit_path) = options.cli_path {
+            debug!("Using explicit CLI path: {:?}", explicit_path);
+            explicit_path.clone()
+        } else {
+            find_claude_cli()?
+        };
+        Ok(Self {
+            options,
+            cli_path,
+            child: ChildSlot::default(),
+            stdin_tx: None,
+            stdin_failure: Arc::default(),
+            held_result: HeldResult::default(),
+            message_broadcast_tx: None,
+            message_rx: None,
+            next_message_seq: Arc::new(tokio::sync::Mutex::new(0)),
+            control_rx: None,
+            sdk_control_rx: None,
+            state: SharedState::new(TransportState::Disconnected),
+            request_counter: 0,
+            close_stdin_after_prompt: false,
+            cli_version: None,
+        })
+    }
+
+    /// Create a new subprocess transport with async initialization
+    ///
+    /// This version supports auto-downloading the CLI if `auto_download_cli` is enabled
+    /// in the options and the CLI is not found.
+    pub async fn new_async(options: ClaudeCodeOptions) -> Result<Self> {
+        let cli_path = if let Some(ref explicit_path) = options.cli_path {
+            debug!("Using explicit CLI path: {:?}", explicit_path);
+            explicit_path.clone()
+        } else {
+            match find_claude_cli() {
+                Ok(path) => path,
+                Err(_) if options.auto_download_cli => {
+                    info!("Claude CLI not found, attempting automatic download...");
+                    let config = crate::cli_download::DownloadConfig {
+                        proxy: options.cli_download_proxy.clone(),
+                        ..Default::default()
+                    };
+                    crate::cli_download::download_cli_with_config(
+                        options.cli_download_version.as_deref(),
+                        options.cli_download_sha256.as_deref(),
+                        &config,
+                        None,
+                    )
+                    .await?
+                },
+                Err(e) => return Err(e),
+            }
+        };
+
+        Ok(Self {
+            options,
+            cli_path,
+            child: ChildSlot::default(),
+            stdin_tx: None,
+            stdin_failure: Arc::default(),
+            held_result: HeldResult::default(),
+            message_broadcast_tx: None,
+            message_rx: None,
+            next_message_seq: Arc::new(tokio::sync::Mutex::new(0)),
+            control_rx: None,
+            sdk_control_rx: None,
+            state: SharedState::new(TransportState::Disconnected),
+            request_counter: 0,
+            close_stdin_after_prompt: false,
+            cli_version: None,
+        })
+    }
+
+    fn build_settings_value(&self, warnings: &mut ConfigWarnings) -> Option<String> {
+        let has_settings = self.options.settings.is_some();
+        let has_sandbox = self.options.sandbox.is_some();
 
-        // Extra arguments
-        for (key, value) in &self.options.extra_args {
-            let flag = if key.starts_with("--") || key.starts_with("-") {
-                key.clone()
+        if !has_settings && !has_sandbox {
+            return None;
+        }
+
+        // If only settings path and no sandbox, pass through as-is
+        if has_settings && !has_sandbox {
+            return self.options.settings.clone();
+        }
+
+        // If we have sandbox settings, merge into a JSON object (Python parity)
+        let mut settings_obj = serde_json::Map::new();
+
+        if let Some(ref settings) = self.options.settings {
+            let settings_str = settings.trim();
+
+            let load_from_file =
+                |path: &Path,
+                 warnings: &mut ConfigWarnings|
+                 -> Option<serde_json::Map<String, serde_json::Value>> {
+                    let content = std::fs::read_to_string(path).ok()?;
+                    match serde_json::from_str::<serde_json::Value>(&content) {
+                        Ok(serde_json::Value::Object(map)) => Some(map),
+                        Ok(_) => {
+                            warnings.warn(format!(
+                                "Settings file JSON must be an object: {}",
+                                path.display()
+                            ));
+                            None
+                        },
+                        Err(e) => {
+                            warnings.warn(format!(
+                                "Failed to parse settings file {}: {}",
+                                path.display(),
+                                e
+                            ));
+                            None
+                        },
+                    }
+                };
+
+            if settings_str.starts_with('{') && settings_str.ends_with('}') {
+                match serde_json::from_str::<serde_json::Value>(settings_str) {
+                    Ok(serde_json::Value::Object(map)) => settings_obj = map,
+                    parsed => {
+                        if parsed.is_ok() {
+                            warnings.warn(
+                                "Settings JSON must be an object; ignoring provided JSON settings",
+                            );
+                        }
+                        warnings.warn(format!(
+                            "Failed to parse settings as JSON, treating as file path: {}",
+                            settings_str
+                        ));
+                        let settings_path = Path::new(settings_str);
+                        if settings_path.exists() {
+                            if let Some(map) = load_from_file(settings_path, warnings) {
+                                settings_obj = map;
+                            }
+                        } else {
+                            warnings.warn(format!(
+                                "Settings file not found: {}",
+                                settings_path.display()
+                            ));
+                        }
+                    },
+                }
             } else {
-                format!("--{key}")
-            };
-            cmd.arg(&flag);
-            if let Some(val) = value {
-                cmd.arg(val);
+                let settings_path = Path::new(settings_str);
+                if settings_path.exists() {
+                    if let Some(map) = load_from_file(settings_path, warnings) {
+                        settings_obj = map;
+                    }
+                } else {
+                    warnings.warn(format!(
+                        "Settings file not found: {}",
+                        settings_path.display()
+                    ));
+                }
             }
         }
 
-        // Set up process pipes
-        cmd.stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+        if let Some(ref sandbox) = self.options.sandbox {
+            match serde_json::to_value(sandbox) {
+                Ok(value) => {
+                    settings_obj.insert("sandbox".to_string(), value);
+                },
+                Err(e) => {
+                    warnings.warn(format!("Failed to serialize sandbox settings: {}", e));
+                },
+            }
+        }
 
-        // Create a new process group so we can kill the entire tree
-        // (CLI + its child processes like bash, find, sleep, etc.)
-        // Using setpgid(0, 0) makes the child the leader of a new group.
-        #[cfg(unix)]
-        // SAFETY: setpgid is async-signal-safe (POSIX.1-2017)
-        unsafe {
-            cmd.pre_exec(|| {
-                libc::setpgid(0, 0);
-                Ok(())
-            });
+        Some(serde_json::Value::Object(settings_obj).to_string())
+    }
+
+    /// Subscribe to messages without borrowing self (for lock-free consumption)
+    ///
+    /// Only available with `allow_multiple_receivers(true)`; returns `None`
+    /// in the default single-consumer mode.
+    pub fn subscribe_messages(
+        &self,
+    ) -> Option<Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>>> {
+        self.subscribe_sequenced_messages().map(|stream| {
+            Box::pin(surface_rate_limits(
+                stream.map(|result| result.map(|s| s.message)),
+                HeldResult::default(),
+            )) as Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>>
+        })
+    }
+
+    /// Like `subscribe_messages`, with each message's sequence number
+    ///
+    /// A jump in sequence numbers means this subscriber lagged and lost
+    /// messages. Rate-limit failures arrive as messages; check them with
+    /// `SdkError::rate_limited`.
+    pub fn subscribe_sequenced_messages(
+        &self,
+    ) -> Option<Pin<Box<dyn Stream<Item = Result<SequencedMessage>> + Send + 'static>>> {
+        self.message_broadcast_tx.as_ref().map(|tx| {
+            let rx = tx.subscribe();
+            Box::pin(tokio_stream::wrappers::BroadcastStream::new(rx).filter_map(
+                |result| async move {
+                    match result {
+                        Ok(msg) => Some(Ok(msg)),
+                        Err(tokio_stream::wrappers::errors::BroadcastStreamRecvError::Lagged(
+                            n,
+                        )) => {
+                            warn!("Receiver lagged by {} messages", n);
+                            None
+                        },
+                    }
+                },
+            )) as Pin<Box<dyn Stream<Item = Result<SequencedMessage>> + Send + 'static>>
+        })
+    }
+
+    /// Like `Transport::receive_messages`, with each message's sequence number
+    ///
+    /// Rate-limit failures arrive as messages; check them with
+    /// `SdkError::rate_limited`.
+    pub fn receive_sequenced_messages(
+        &mut self,
+    ) -> Pin<Box<dyn Stream<Item = Result<SequencedMessage>> + Send + 'static>> {
+        if let Some(ref rx) = self.message_rx {
+            // Every stream reads from the same queue, so messages that arrive
+            // between two calls are delivered to the next one instead of lost
+            return Box::pin(futures::stream::unfold(rx.clone(), |rx| async move {
+                let message = rx.lock().await.recv().await?;
+                Some((Ok(message), rx))
+            }));
+        }
+
+        self.subscribe_sequenced_messages()
+            .unwrap_or_else(|| Box::pin(futures::stream::empty()))
+    }
+
+    /// Receive SDK control requests
+    #[allow(dead_code)]
+    pub async fn receive_sdk_control_request(&mut self) -> Option<serde_json::Value> {
+        if let Some(ref mut rx) = self.sdk_control_rx {
+            rx.recv().await
+        } else {
+            None
+        }
+    }
+
+    /// Take the SDK control receiver (can only be called once)
+    pub fn take_sdk_control_receiver(&mut self) -> Option<mpsc::Receiver<serde_json::Value>> {
+        self.sdk_control_rx.take()
+    }
+
+    /// List the models available to the account, as reported by the CLI
+    ///
+    /// The CLI reports its models in the response to the control protocol's
+    /// `initialize` request, so this must be called on a connected transport
+    /// before anything else initializes the session or takes the SDK control
+    /// receiver. Returns `SdkError::NotSupported` if the CLI does not include
+    /// a model list.
+    pub async fn list_models(&mut self) -> Result<Vec<ModelInfo>> {
+        let mut rx = self.sdk_control_rx.take().ok_or_else(|| {
+            SdkError::invalid_state(
+                "SDK control receiver not available; list_models() must run before \
+                 take_sdk_control_receiver()",
+            )
+        })?;
+        let request_id = format!("req_models_{}", uuid::Uuid::new_v4().simple());
+        let response = async {
+            self.send_sdk_control_request(serde_json::json!({
+                "type": "control_request",
+                "request_id": request_id,
+                "request": {"subtype": "initialize", "hooks": null}
+            }))
+            .await?;
+
+            tokio::time::timeout(LIST_MODELS_TIMEOUT, async {
+                while let Some(message) = rx.recv().await {
+                    if message.get("type").and_then(|v| v.as_str()) == Some("control_response")
+                        && let Some(response) = message.get("response")
+                        && response.get("request_id").and_then(|v| v.as_str())
+                            == Some(request_id.as_str())
+                    {
+                        return Some(response.clone());
+                    }
+                    debug!("Ignoring control message while listing models: {}", message);
+                }
+                None
+            })
+            .await
+            .map_err(|_| SdkError::timeout(LIST_MODELS_TIMEOUT.as_secs()))?
+            .ok_or_else(|| {
+                SdkError::ControlRequestError("CLI exited before answering initialize".into())
+            })
+        }
+        .await;
+        self.sdk_control_rx = Some(rx);
+        let response = response?;
+
+        if response.get("subtype").and_then(|v| v.as_str()) == Some("error") {
+            let msg = response
+                .get("error")
+                .and_then(|v| v.as_str())
+                .unwrap_or("Unknown control request error");
+            return Err(SdkError::ControlRequestError(msg.to_string()));
+        }
+        parse_models(response.get("response").unwrap_or(&serde_json::Value::Null))
+    }
+
+    /// Create with a specific CLI path
+    pub fn with_cli_path(options: ClaudeCodeOptions, cli_path: impl Into<PathBuf>) -> Self {
+        Self {
+            options,
+            cli_path: cli_path.into(),
+            child: ChildSlot::default(),
+            stdin_tx: None,
+            stdin_failure: Arc::default(),
+            held_result: HeldResult::default(),
+            message_broadcast_tx: None,
+            message_rx: None,
+            next_message_seq: Arc::new(tokio::sync::Mutex::new(0)),
+            control_rx: None,
+            sdk_control_rx: None,
+            state: SharedState::new(TransportState::Disconnected),
+            request_counter: 0,
+            close_stdin_after_prompt: false,
+            cli_version: None,
+        }
+    }
+
+    /// Set whether to close stdin after sending the initial prompt
+    #[allow(dead_code)]
+    pub fn set_close_stdin_after_prompt(&mut self, close: bool) {
+        self.close_stdin_after_prompt = close;
+    }
+
+    /// Create transport for simple print mode (one-shot query)
+    #[allow(dead_code)]
+    pub fn for_print_mode(options: ClaudeCodeOptions, _prompt: String) -> Result<Self> {
+        let cli_path = find_claude_cli()?;
+        Ok(Self {
+            options,
+            cli_path,
+            child: ChildSlot::default(),
+            stdin_tx: None,
+            stdin_failure: Arc::default(),
+            held_result: HeldResult::default(),
+            message_broadcast_tx: None,
+            message_rx: None,
+            next_message_seq: Arc::new(tokio::sync::Mutex::new(0)),
+            control_rx: None,
+            sdk_control_rx: None,
+            state: SharedState::new(TransportState::Disconnected),
+            request_counter: 0,
+            close_stdin_after_prompt: true,
+            cli_version: None,
+        })
+    }
+
+    /// Build the command, logging any configuration warnings
+    #[cfg(test)]
+    fn build_command(&self) -> Command {
+        self.build_command_with(&mut ConfigWarnings::new(&ClaudeCodeOptions::default()))
+    }
+
+    /// Build the command with all necessary arguments
+    fn build_command_with(&self, warnings: &mut ConfigWarnings) -> Command {
+        let mut cmd = Command::new(&self.cli_path);
+
+        // Always use output-format stream-json; verbose unless opted out
+        // (the Python SDK always passes it)
+        cmd.arg("--output-format").arg("stream-json");
+        if self.options.verbose.unwrap_or(true) {
+            cmd.arg("--verbose");
+        }
+
+        // stream-json for streaming/interactive mode, text for one-shot prompts
+        cmd.arg("--input-format")
+            .arg(self.options.input_format.as_cli_arg());
+
+        // Include partial messages if requested
+        if self.options.include_partial_messages {
+            cmd.arg("--include-partial-messages");
+        }
+
+        // Add debug-to-stderr flag if debug_stderr is set
+        if self.options.debug_stderr.is_some() {
+            cmd.arg("--debug-to-stderr");
+        }
+
+        // Handle max_output_tokens (priority: option > env var)
+        apply_max_output_tokens(&mut cmd, &self.options, warnings);
+
+        // System prompts (match Python SDK behavior)
+        //
+        // Python always passes `--system-prompt ""` when `system_prompt` is None,
+        // unless the caller opted to inherit the prompt from the CLI's settings.
+        if let Some(ref prompt_v2) = self.options.system_prompt_v2 {
+            match prompt_v2 {
+                crate::types::SystemPrompt::String(s) => {
+                    cmd.arg("--system-prompt").arg(s);
+                },
+                crate::types::SystemPrompt::Preset { append, .. } => {
+                    // Python only uses preset prompts to optionally append to the default preset.
+                    // It does not pass a preset selector flag to the CLI.
+                    if let Some(append_text) = append {
+                        cmd.arg("--append-system-prompt").arg(append_text);
+                    }
+                },
+                crate::types::SystemPrompt::File { append, .. } => {
+                    // The file itself is read in `prepare_command`, which can fail
+                    if let Some(append_text) = append {
+                        cmd.arg("--append-system-prompt").arg(append_text);
+                    }
+                },
+            }
+        } else {
+            // Fallback to deprecated fields for backward compatibility
+            #[allow(deprecated)]
+            match self.options.system_prompt.as_deref() {
+                Some(prompt) => {
+                    cmd.arg("--system-prompt").arg(prompt);
+                },
+                None if self.options.inherit_cli_system_prompt => {},
+                None => {
+                    cmd.arg("--system-prompt").arg("");
+                },
+            }
+            #[allow(deprecated)]
+            if let Some(ref prompt) = self.options.append_system_prompt {
+                cmd.arg("--append-system-prompt").arg(prompt);
+            }
+        }
+
+        // Tool configuration
+        if !self.options.allowed_tools.is_empty() {
+            cmd.arg("--allowedTools")
+                .arg(self.options.allowed_tools.join(","));
+        }
+        let mut disallowed_tools = self.options.disallowed_tools.clone();
+        if let Some(crate::types::ToolsConfig::Preset(preset)) = &self.options.tools {
+            for tool in &preset.remove {
+                if !disallowed_tools.contains(tool) {
+                    disallowed_tools.push(tool.clone());
+                }
+            }
+        }
+        if !disallowed_tools.is_empty() {
+            cmd.arg("--disallowedTools").arg(disallowed_tools.join(","));
+        }
+
+        // Permission mode
+        match self.options.permission_mode {
+            PermissionMode::Default => {
+                cmd.arg("--permission-mode").arg("default");
+            },
+            PermissionMode::AcceptEdits => {
+                cmd.arg("--permission-mode").arg("acceptEdits");
+            },
+            PermissionMode::Plan => {
+                cmd.arg("--permission-mode").arg("plan");
+            },
+            PermissionMode::BypassPermissions => {
+                cmd.arg("--permission-mode").arg("bypassPermissions");
+            },
+        }
+
+        // Model
+        if let Some(ref model) = self.options.model {
+            cmd.arg("--model").arg(model);
+        }
+
+        // Permission prompt tool
+        if let Some(ref tool_name) = self.options.permission_prompt_tool_name {
+            check_permission_prompt_tool(tool_name, &self.options, warnings);
+            cmd.arg("--permission-prompt-tool").arg(tool_name);
+        }
+
+        // Max turns
+        if let Some(max_turns) = self.options.max_turns {
+            cmd.arg("--max-turns").arg(max_turns.to_string());
+        }
+
+        // Max thinking tokens (extended thinking budget)
+        // Only pass if non-zero to match Python SDK behavior
+        if self.options.max_thinking_tokens > 0 {
+            cmd.arg("--max-thinking-tokens")
+                .arg(self.options.max_thinking_tokens.to_string());
+        }
+
+        // Working directory; a relative path is taken relative to our own
+        if let Some(ref cwd) = self.options.cwd {
+            cmd.current_dir(std::path::absolute(cwd).unwrap_or_else(|_| cwd.clone()));
+        }
+
+        // Add environment variables
+        for (key, value) in &self.options.env {
+            cmd.env(key, value);
+        }
+
+        // MCP servers are added in `prepare_command`, as checking their env vars can fail
+
+        // Continue/resume
+        if self.options.continue_conversation {
+            cmd.arg("--continue");
+        }
+        if let Some(ref resume_id) = self.options.resume {
+            cmd.arg("--resume").arg(resume_id);
+        }
+
+        // Settings value (merge sandbox into settings if provided)
+        if let Some(settings_value) = self.build_settings_value(warnings) {
+            cmd.arg("--settings").arg(settings_value);
+        }
+
+        // Additional directories
+        for dir in &self.options.add_dirs {
+            cmd.arg("--add-dir").arg(dir);
+        }
+
+        // Fork session if requested
+        if self.options.fork_session {
+            cmd.arg("--fork-session");
+        }
+
+        // ========== Phase 3 CLI args (Python SDK v0.1.12+ sync) ==========
+
+        // Tools configuration (base set of tools)
+        if let Some(ref tools) = self.options.tools {
+            match tools {
+                crate::types::ToolsConfig::List(list) => {
+                    if list.is_empty() {
+                        cmd.arg("--tools").arg("");
+                    } else {
+                        cmd.arg("--tools").arg(list.join(","));
+                    }
+                },
+                crate::types::ToolsConfig::Preset(preset) => {
+                    // Preset object - 'claude_code' preset maps to 'default'
+                    if preset.preset != "claude_code" {
+                        warnings.warn(format!(
+                            "Unknown tools preset '{}', using the default tools",
+                            preset.preset
+                        ));
+                    }
+                    cmd.arg("--tools").arg("default");
+                },
+            }
+        }
+
+        // SDK betas
+        if !self.options.betas.is_empty() {
+            let betas: Vec<String> = self.options.betas.iter().map(|b| b.to_string()).collect();
+            cmd.arg("--betas").arg(betas.join(","));
+        }
+
+        // Max budget USD
+        if let Some(budget) = self.options.max_budget_usd {
+            cmd.arg("--max-budget-usd").arg(budget.to_string());
+        }
+
+        // Fallback model
+        if let Some(ref fallback) = self.options.fallback_model {
+            cmd.arg("--fallback-model").arg(fallback);
+        }
+
+        // File checkpointing
+        if self.options.enable_file_checkpointing {
+            cmd.env("CLAUDE_CODE_ENABLE_SDK_FILE_CHECKPOINTING", "true");
+        }
+
+        // Plugin directories
+        for plugin in &self.options.plugins {
+            match plugin {
+                crate::types::SdkPluginConfig::Local { path } => {
+                    cmd.arg("--plugin-dir").arg(path);
+                },
+            }
+        }
+
+        // Programmatic agents
+        if let Some(ref agents) = self.options.agents
+            && !agents.is_empty()
+            && let Ok(json_str) = serde_json::to_string(agents)
+        {
+            cmd.arg("--agents").arg(json_str);
+        }
+
+        // Setting sources (comma-separated). Always pass a value for SDK parity with Python.
+        let sources_value = self
+            .options
+            .setting_sources
+            .as_ref()
+            .map(|sources| {
+                sources
+                    .iter()
+                    .map(|s| match s {
+                        crate::types::SettingSource::User => "user",
+                        crate::types::SettingSource::Project => "project",
+                        crate::types::SettingSource::Local => "local",
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .unwrap_or_default();
+        cmd.arg("--setting-sources").arg(sources_value);
+
+        apply_extra_args(&mut cmd, &self.options);
+
+        // Set up process pipes
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        // Create a new process group so we can kill the entire tree
+        // (CLI + its child processes like bash, find, sleep, etc.)
+        // Using setpgid(0, 0) makes the child the leader of a new group.
+        #[cfg(unix)]
+        // SAFETY: setpgid is async-signal-safe (POSIX.1-2017)
+        unsafe {
+            cmd.pre_exec(|| {
+                libc::setpgid(0, 0);
+                Ok(())
+            });
+        }
+
+        // Set environment variables to indicate SDK usage and version
+        apply_sdk_identity(&mut cmd, &self.options);
+
+        // Propagate the caller's correlation id so CLI logs can be matched up
+        if let Some(ref request_id) = self.options.request_id {
+            cmd.env(REQUEST_ID_ENV_VAR, request_id);
+        }
+
+        // Debug log the full command being executed
+        debug!(
+            "Executing Claude CLI command: {} {:?}",
+            self.cli_path.display(),
+            cmd.as_std().get_args().collect::<Vec<_>>()
+        );
+
+        cmd
+    }
+
+    /// Check CLI version and warn if below minimum required version
+    ///
+    /// Only ever warns, even in `strict_mode`: an old CLI may still run fine.
+    async fn check_cli_version(&mut self) -> Result<()> {
+        self.cli_version = get_cli_version(&self.cli_path).await;
+        if let Some(semver) = self.cli_version.clone() {
+            let min_version = SemVer::new(MIN_CLI_VERSION.0, MIN_CLI_VERSION.1, MIN_CLI_VERSION.2);
+
+            if semver < min_version {
+                warn!(
+                    "⚠️  Claude CLI version {} is below minimum required version {}.{}.{}",
+                    semver, MIN_CLI_VERSION.0, MIN_CLI_VERSION.1, MIN_CLI_VERSION.2
+                );
+                warn!(
+                    "   Some features may not work correctly. Please upgrade with: npm install -g @anthropic-ai/claude-code@latest"
+                );
+            } else {
+                info!("Claude CLI version: {}", semver);
+            }
+        } else {
+            debug!("Could not determine CLI version for {:?}", self.cli_path);
+        }
+
+        Ok(())
+    }
+
+    /// Check an auto-downloaded CLI against `cli_download_sha256`, fetching
+    /// it again if it no longer matches
+    #[cfg(feature = "auto-download")]
+    async fn verify_cached_cli(&mut self) -> Result<()> {
+        let Some(expected) = self.options.cli_download_sha256.as_deref() else {
+            return Ok(());
+        };
+        if crate::cli_download::get_cached_cli_path().as_ref() != Some(&self.cli_path) {
+            return Ok(());
+        }
+        let config = crate::cli_download::DownloadConfig {
+            proxy: self.options.cli_download_proxy.clone(),
+            ..Default::default()
+        };
+        self.cli_path = crate::cli_download::verify_cached_cli_with_config(
+            self.options.cli_download_version.as_deref(),
+            expected,
+            &config,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Reject option combinations the CLI cannot honor
+    fn validate_options(&self) -> Result<()> {
+        if self.options.input_format == InputFormat::Text && self.options.include_partial_messages {
+            return Err(SdkError::ConfigError(
+                "include_partial_messages requires InputFormat::StreamJson".into(),
+            ));
+        }
+        if self.options.fork_session
+            && self.options.resume.is_none()
+            && !self.options.continue_conversation
+        {
+            return Err(SdkError::ConfigError(
+                "fork_session requires resume or continue_conversation".into(),
+            ));
+        }
+        if let Some(ref cwd) = self.options.cwd {
+            // Spawning in a missing directory only reports a bare "not found"
+            match std::fs::metadata(cwd) {
+                Ok(meta) if meta.is_dir() => {},
+                Ok(_) => {
+                    return Err(SdkError::ConfigError(format!(
+                        "cwd {} is not a directory",
+                        cwd.display()
+                    )));
+                },
+                Err(e) => {
+                    return Err(SdkError::ConfigError(format!(
+                        "cwd {} is not accessible: {e}",
+                        cwd.display()
+                    )));
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether input can be sent: connected, or restarting with input queued
+    fn accepts_input(&self) -> bool {
+        matches!(
+            self.state.get(),
+            TransportState::Connected | TransportState::Reconnecting
+        )
+    }
+
+    /// Fail unless input can be sent, reporting a failed stdin write as such
+    fn ensure_accepts_input(&self) -> Result<()> {
+        if let Some(err) = self.stdin_failure_error() {
+            return Err(err);
+        }
+        if !self.accepts_input() {
+            return Err(SdkError::InvalidState {
+                message: "Not connected".into(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Encode a message as the stdin line expected by the configured input format
+    fn encode_input(&self, message: &InputMessage) -> Result<String> {
+        match self.options.input_format {
+            InputFormat::StreamJson => Ok(serde_json::to_string(message)?),
+            InputFormat::Text => message
+                .message
+                .get("content")
+                .and_then(|content| content.as_str())
+                .map(str::to_string)
+                .ok_or_else(|| SdkError::InvalidState {
+                    message: "InputFormat::Text only supports plain-text user messages".into(),
+                }),
+        }
+    }
+
+    /// Queue a line for the CLI's stdin
+    ///
+    /// Fails with `SdkError::TransportError` once a write to stdin has failed
+    /// (e.g. the CLI exited), instead of queueing data nobody will write.
+    async fn write_stdin(&self, line: String) -> Result<()> {
+        let Some(ref tx) = self.stdin_tx else {
+            return Err(SdkError::InvalidState {
+                message: "Stdin channel not available".into(),
+            });
+        };
+        if let Some(err) = self.stdin_failure_error() {
+            return Err(err);
+        }
+        tx.send(line).await.map_err(|_| {
+            self.stdin_failure_error()
+                .unwrap_or(SdkError::ChannelSendError)
+        })
+    }
+
+    fn stdin_failure_error(&self) -> Option<SdkError> {
+        let failure = self.stdin_failure.lock().unwrap_or_else(|e| e.into_inner());
+        failure
+            .as_ref()
+            .map(|e| SdkError::TransportError(format!("CLI stdin is closed: {e}")))
+    }
+
+    /// Stop `child`, giving up after `disconnect_timeout`
+    async fn stop_child(&self, child: &mut Child) {
+        let timeout = self
+            .options
+            .disconnect_timeout
+            .unwrap_or(DEFAULT_DISCONNECT_TIMEOUT);
+        if tokio::time::timeout(timeout, terminate(child))
+            .await
+            .is_err()
+        {
+            // The OS reaps it once it finally dies
+            warn!(
+                "CLI process did not exit within {:?} of disconnect, abandoning it (pid={:?})",
+                timeout,
+                child.id()
+            );
+            force_kill(child);
+        }
+    }
+
+    /// Report a diagnostic event to the configured log sink, if any
+    fn emit(&self, event: DiagnosticEvent) {
+        if let Some(ref sink) = self.options.log_sink {
+            sink.log(&event);
+        }
+    }
+
+    /// Build the command that launches the CLI, applying process settings
+    fn prepare_command(&self) -> Result<Command> {
+        let mut warnings = ConfigWarnings::new(&self.options);
+        let mut cmd = self.build_command_with(&mut warnings);
+        warnings.finish()?;
+        // MCP servers - use --mcp-config with JSON format like Python SDK
+        apply_mcp_config(&mut cmd, &self.options)?;
+        if let Some(ref format) = self.options.output_format {
+            apply_output_format(&mut cmd, format)?;
+        }
+        if let Some(crate::types::SystemPrompt::File { ref path, .. }) =
+            self.options.system_prompt_v2
+        {
+            cmd.arg("--system-prompt")
+                .arg(read_system_prompt_file(path)?);
+        }
+        // The environment may hold secrets; only the program and its args are logged
+        info!(
+            "Starting Claude CLI with command: {:?} {:?}",
+            cmd.as_std().get_program(),
+            cmd.as_std().get_args().collect::<Vec<_>>()
+        );
+
+        if let Some(user) = self.options.user.as_deref() {
+            apply_process_user(&mut cmd, user)?;
+        }
+
+        if let Some(ref limits) = self.options.resource_limits {
+            apply_resource_limits(&mut cmd, limits)?;
+        }
+
+        if let Some(ref path) = self.options.extra_env_file {
+            crate::env_file::apply_env_file(&mut cmd, path, &self.options.env)?;
+        }
+        Ok(cmd)
+    }
+
+    /// Spawn the process and set up communication channels
+    async fn spawn_process(&mut self) -> Result<()> {
+        self.validate_options()?;
+        self.state.set(TransportState::Connecting);
+
+        let mut cmd = self.prepare_command()?;
+        let mut child = spawn_cli(&mut cmd, &self.cli_path)?;
+        self.emit(DiagnosticEvent::Spawned {
+            cli_path: self.cli_path.clone(),
+            pid: child.id(),
+        });
+
+        // Get stdio handles
+        let (stdin, stdout, stderr) = take_stdio(&mut child)?;
+
+        // Determine buffer size from options or use default
+        let buffer_size = self
+            .options
+            .cli_channel_buffer_size
+            .unwrap_or(CHANNEL_BUFFER_SIZE);
+
+        // Span shared by the I/O tasks so their logs carry the request id
+        let span = tracing::info_span!("claude_cli", request_id = tracing::field::Empty);
+        if let Some(ref request_id) = self.options.request_id {
+            span.record("request_id", request_id.as_str());
+        }
+
+        // Create channels
+        let (stdin_tx, stdin_rx) = mpsc::channel::<String>(buffer_size);
+        // A single consumer gets a lossless queue; broadcast (which drops
+        // messages for lagging receivers) only when several were requested
+        let (message_channel, message_broadcast_tx, message_rx) =
+            if self.options.allow_multiple_receivers {
+                let (tx, _) = tokio::sync::broadcast::channel::<SequencedMessage>(buffer_size);
+                (MessageChannel::Broadcast(tx.clone()), Some(tx), None)
+            } else {
+                let (tx, rx) = mpsc::channel::<SequencedMessage>(buffer_size);
+                let rx = Arc::new(tokio::sync::Mutex::new(rx));
+                (MessageChannel::Queue(tx), None, Some(rx))
+            };
+        let message_sink = MessageSink {
+            channel: message_channel,
+            next_seq: self.next_message_seq.clone(),
+        };
+        let (control_tx, control_rx) = mpsc::channel::<ControlResponse>(buffer_size);
+
+        // Create channel for SDK control requests
+        let (sdk_control_tx, sdk_control_rx) = mpsc::channel::<serde_json::Value>(buffer_size);
+
+        // With reconnects enabled, a restarted process's stdin is handed to
+        // the stdin handler so queued input reaches the new process
+        let (stdin_swap_tx, stdin_swap_rx) = if self.options.reconnect_on_failure {
+            let (tx, rx) = mpsc::unbounded_channel::<ChildStdin>();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+
+        // Spawn stdin handler
+        let stdin_failure = Arc::new(std::sync::Mutex::new(None));
+        tokio::spawn(
+            write_stdin_lines(
+                stdin_rx,
+                stdin,
+                stdin_swap_rx,
+                stdin_failure.clone(),
+                self.state.clone(),
+            )
+            .instrument(span.clone()),
+        );
+
+        let (messages_tx, messages_rx) = mpsc::unbounded_channel::<Message>();
+        tokio::spawn(forward_messages(messages_rx, message_sink.clone()).instrument(span.clone()));
+
+        // Spawn stdout handler, which also restarts the CLI if it dies
+        let mut router = OutputRouter {
+            message_sink: message_sink.clone(),
+            messages: messages_tx,
+            control_tx,
+            sdk_control_tx,
+            log_sink: self.options.log_sink.clone(),
+            on_tool_use: self.options.on_tool_use.clone(),
+            recent_lines: self.options.dedup_messages.then(RecentLines::default),
+            session_id: None,
+            turn_completed: false,
+        };
+        let mut reconnector = stdin_swap_tx.map(|stdin_swap| Reconnector {
+            options: self.options.clone(),
+            cli_path: self.cli_path.clone(),
+            state: self.state.clone(),
+            child: self.child.clone(),
+            stdin_swap,
+            span: span.clone(),
+            attempts: 0,
+        });
+        let stdout_handler = async move {
+            let mut stdout = stdout;
+            loop {
+                router.route(stdout).await;
+                let Some(ref mut reconnector) = reconnector else {
+                    break;
+                };
+                match reconnector.recover(&mut router).await {
+                    Some(restarted) => stdout = restarted,
+                    None => break,
+                }
+            }
+        };
+        tokio::spawn(stdout_handler.instrument(span.clone()));
+
+        if let Some(period) = self.options.keepalive_interval.filter(|p| !p.is_zero()) {
+            tokio::spawn(send_keepalives(stdin_tx.downgrade(), period).instrument(span.clone()));
+        }
+
+        // Spawn stderr handler - capture error messages for better diagnostics
+        tokio::spawn(
+            read_stderr(
+                stderr,
+                message_sink,
+                self.options.debug_stderr.clone(),
+                self.options.stderr_callback.clone(),
+                self.options.suppress_stderr_errors,
+            )
+            .instrument(span),
+        );
+
+        // Store handles
+        *self.child.lock().unwrap_or_else(|e| e.into_inner()) = Some(child);
+        self.stdin_tx = Some(stdin_tx);
+        self.stdin_failure = stdin_failure;
+        self.message_broadcast_tx = message_broadcast_tx;
+        self.message_rx = message_rx;
+        self.control_rx = Some(control_rx);
+        self.sdk_control_rx = Some(sdk_control_rx);
+        self.state.set(TransportState::Connected);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transport for SubprocessTransport {
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+
+    async fn connect(&mut self) -> Result<()> {
+        if self.state.get() == TransportState::Connected {
+            return Ok(());
+        }
+
+        // A pinned digest must hold before the cached CLI is first run
+        #[cfg(feature = "auto-download")]
+        self.verify_cached_cli().await?;
+
+        // Check CLI version before connecting
+        if let Err(e) = self.check_cli_version().await {
+            warn!("CLI version check failed: {}", e);
+        }
+        // Older CLIs ignore sandbox settings instead of rejecting them
+        if self.options.sandbox.is_some() {
+            let features = CliFeatures::detect(self.cli_version.as_ref(), None);
+            features.require(features.sandbox, "sandbox settings")?;
+        }
+
+        self.spawn_process().await?;
+        info!("Connected to Claude CLI");
+        self.emit(DiagnosticEvent::Connected);
+        Ok(())
+    }
+
+    async fn send_message(&mut self, message: InputMessage) -> Result<()> {
+        self.ensure_accepts_input()?;
+
+        let json = self.encode_input(&message)?;
+        debug!("Serialized message: {}", json);
+
+        debug!("Sending message to stdin channel");
+        self.write_stdin(json).await?;
+        debug!("Message sent to channel");
+        Ok(())
+    }
+
+    async fn send_messages(&mut self, messages: Vec<InputMessage>) -> Result<()> {
+        self.ensure_accepts_input()?;
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        // A text prompt may span lines, so nothing separates two of them
+        if self.options.input_format == InputFormat::Text && messages.len() > 1 {
+            return Err(SdkError::InvalidState {
+                message: "InputFormat::Text sends one prompt at a time; batches need InputFormat::StreamJson".into(),
+            });
+        }
+
+        // Serialized JSON never contains a raw newline, so the joined lines
+        // reach stdin in one write and flush without interleaving other sends
+        let lines = messages
+            .iter()
+            .map(|message| self.encode_input(message))
+            .collect::<Result<Vec<_>>>()?;
+        debug!("Sending batch of {} messages", lines.len());
+
+        self.write_stdin(lines.join("\n")).await
+    }
+
+    fn receive_messages(
+        &mut self,
+    ) -> Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>> {
+        Box::pin(surface_rate_limits(
+            self.receive_sequenced_messages()
+                .map(|result| result.map(|s| s.message)),
+            self.held_result.clone(),
+        ))
+    }
+
+    fn subscribe_messages(
+        &self,
+    ) -> Option<Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>>> {
+        // Delegate to the inherent method on SubprocessTransport, which
+        // returns a `'static` stream subscribed to the long-lived broadcast.
+        // Fully-qualified path needed to disambiguate from the trait method.
+        SubprocessTransport::subscribe_messages(self)
+    }
+
+    fn receive_sequenced_messages(
+        &mut self,
+    ) -> Option<Pin<Box<dyn Stream<Item = Result<SequencedMessage>> + Send + 'static>>> {
+        Some(SubprocessTransport::receive_sequenced_messages(self))
+    }
+
+    fn subscribe_sequenced_messages(
+        &self,
+    ) -> Option<Pin<Box<dyn Stream<Item = Result<SequencedMessage>> + Send + 'static>>> {
+        SubprocessTransport::subscribe_sequenced_messages(self)
+    }
+
+    async fn send_control_request(&mut self, request: ControlRequest) -> Result<()> {
+        self.ensure_accepts_input()?;
+
+        self.request_counter += 1;
+        let control_msg = control_request_json(&request);
+        let event = match request {
+            ControlRequest::Interrupt { request_id } => {
+                DiagnosticEvent::InterruptSent { request_id }
+            },
+        };
+
+        let json = serde_json::to_string(&control_msg)?;
+
+        self.write_stdin(json).await?;
+        self.emit(event);
+        Ok(())
+    }
+
+    async fn receive_control_response(&mut self) -> Result<Option<ControlResponse>> {
+        if let Some(ref mut rx) = self.control_rx {
+            Ok(rx.recv().await)
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn send_sdk_control_request(&mut self, request: serde_json::Value) -> Result<()> {
+        // The request is already properly formatted as {"type": "control_request", ...}
+        // Just send it directly without wrapping
+        let json = serde_json::to_string(&request)?;
+
+        self.write_stdin(json).await
+    }
+
+    async fn send_sdk_control_response(&mut self, response: serde_json::Value) -> Result<()> {
+        // Wrap the response in control_response format expected by CLI
+        // The response should have: {"type": "control_response", "response": {...}}
+        let control_response = serde_json::json!({
+            "type": "control_response",
+            "response": response
+        });
+
+        let json = serde_json::to_string(&control_response)?;
+
+        self.write_stdin(json).await
+    }
+
+    fn child_pid(&self) -> Option<u32> {
+        let child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        child.as_ref().and_then(|c| c.id())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.accepts_input()
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        if !matches!(
+            self.state.get(),
+            TransportState::Connected | TransportState::Reconnecting | TransportState::Error
+        ) {
+            return Ok(());
+        }
+
+        let child = {
+            let mut slot = self.child.lock().unwrap_or_else(|e| e.into_inner());
+            self.state.set(TransportState::Disconnecting);
+            slot.take()
+        };
+
+        // Close stdin channel — signals EOF to the CLI process
+        self.stdin_tx.take();
+
+        if let Some(mut child) = child {
+            self.stop_child(&mut child).await;
+        }
+
+        self.state.set(TransportState::Disconnected);
+        Ok(())
+    }
+
+    /// Interrupt the current turn, close stdin and wait up to `timeout` for
+    /// the CLI to exit by itself before stopping it like `disconnect`
+    ///
+    /// The interrupt is sent first since nothing can be written once stdin
+    /// is closed. Output the CLI writes while exiting is still delivered.
+    async fn disconnect_graceful(&mut self, timeout: std::time::Duration) -> Result<()> {
+        if self.state.get() != TransportState::Connected {
+            return self.disconnect().await;
+        }
+
+        let request = ControlRequest::Interrupt {
+            request_id: uuid::Uuid::new_v4().to_string(),
+        };
+        if let Err(e) = self.send_control_request(request).await {
+            debug!("Could not interrupt before disconnecting: {}", e);
+        }
+
+        // Disconnecting first, so the exit is not mistaken for a crash
+        let child = {
+            let mut slot = self.child.lock().unwrap_or_else(|e| e.into_inner());
+            self.state.set(TransportState::Disconnecting);
+            slot.take()
+        };
+        self.end_input().await?;
+
+        if let Some(mut child) = child {
+            match tokio::time::timeout(timeout, child.wait()).await {
+                Ok(Ok(status)) => info!("CLI process exited after end of input ({})", status),
+                Ok(Err(e)) => {
+                    warn!("Error waiting for CLI process to exit: {}", e);
+                    self.stop_child(&mut child).await;
+                },
+                Err(_) => {
+                    debug!(
+                        "CLI process did not exit within {:?} of end of input, stopping it",
+                        timeout
+                    );
+                    self.stop_child(&mut child).await;
+                },
+            }
+        }
+
+        self.state.set(TransportState::Disconnected);
+        Ok(())
+    }
+
+    fn take_sdk_control_receiver(
+        &mut self,
+    ) -> Option<tokio::sync::mpsc::Receiver<serde_json::Value>> {
+        self.sdk_control_rx.take()
+    }
+
+    fn clone_stdin_sender(&self) -> Option<tokio::sync::mpsc::Sender<String>> {
+        self.stdin_tx.clone()
+    }
+
+    fn cli_version(&self) -> Option<SemVer> {
+        self.cli_version.clone()
+    }
+
+    fn capabilities(&self) -> TransportCapabilities {
+        TransportCapabilities {
+            supports_control: true,
+            supports_interrupt: true,
+            supports_stdin_clone: true,
+            supports_inbound_control: true,
+        }
+    }
+
+    async fn end_input(&mut self) -> Result<()> {
+        // Close stdin channel to signal end of input
+        self.stdin_tx.take();
+        Ok(())
+    }
+}
+
+impl Drop for SubprocessTransport {
+    fn drop(&mut self) {
+        let mut slot = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        // Also stops a pending restart from spawning an orphan
+        self.state.set(TransportState::Disconnected);
+        if let Some(mut child) = slot.take() {
+            force_kill(&mut child);
+        }
+    }
+}
+
+/// Find the Claude CLI binary
+///
+/// Search order:
+/// 1. System PATH (`claude`, `claude-code`)
+/// 2. SDK cache directory (auto-downloaded CLI)
+/// 3. Common installation locations
+pub fn find_claude_cli() -> Result<PathBuf> {
+    // First check if it's in PATH - try both 'claude' and 'claude-code'
+    #[cfg(unix)]
+    let cmd_names: &[&str] = &["claude", "claude-code"];
+    #[cfg(windows)]
+    let cmd_names: &[&str] = &["claude", "claude.exe", "claude-code", "claude-code.exe"];
+
+    for cmd_name in cmd_names {
+        if let Ok(path) = which::which(cmd_name) {
+            debug!("Found Claude CLI in PATH at: {}", path.display());
+            return Ok(path);
+        }
+    }
+
+    // Check SDK cache directory (for auto-downloaded CLI)
+    if let Some(cached_path) = crate::cli_download::find_cached_cli() {
+        debug!("Found cached Claude CLI at: {}", cached_path.display());
+        return Ok(cached_path);
+    }
+
+    // Check common installation locations
+    let home = dirs::home_dir().ok_or_else(|| SdkError::CliNotFound {
+        searched_paths: "Unable to determine home directory".into(),
+    })?;
+
+    #[cfg(unix)]
+    let locations = vec![
+        // npm global installations
+        home.join(".npm-global/bin/claude"),
+        home.join(".npm-global/bin/claude-code"),
+        PathBuf::from("/usr/local/bin/claude"),
+        PathBuf::from("/usr/local/bin/claude-code"),
+        // Local installations
+        home.join(".local/bin/claude"),
+        home.join(".local/bin/claude-code"),
+        home.join("node_modules/.bin/claude"),
+        home.join("node_modules/.bin/claude-code"),
+        // Yarn installations
+        home.join(".yarn/bin/claude"),
+        home.join(".yarn/bin/claude-code"),
+        // macOS specific npm location
+        PathBuf::from("/opt/homebrew/bin/claude"),
+        PathBuf::from("/opt/homebrew/bin/claude-code"),
+        // Claude local directory
+        home.join(".claude/local/claude"),
+    ];
+
+    #[cfg(windows)]
+    let locations = {
+        let mut locs = vec![
+            // npm global (via %APPDATA%\npm\)
+            home.join("AppData")
+                .join("Roaming")
+                .join("npm")
+                .join("claude.cmd"),
+            home.join("AppData")
+                .join("Roaming")
+                .join("npm")
+                .join("claude"),
+            // Anthropic official installer
+            home.join("AppData")
+                .join("Local")
+                .join("Programs")
+                .join("claude")
+                .join("claude.exe"),
+            // User-local compat
+            home.join(".local").join("bin").join("claude.exe"),
+            home.join(".local").join("bin").join("claude"),
+            // Yarn
+            home.join(".yarn").join("bin").join("claude.cmd"),
+            // Claude local directory
+            home.join(".claude").join("local").join("claude.exe"),
+        ];
+        // Also add via dirs:: for robustness (in case AppData is relocated)
+        if let Some(config_dir) = dirs::config_dir() {
+            locs.push(config_dir.join("npm").join("claude.cmd"));
+        }
+        if let Some(local_dir) = dirs::data_local_dir() {
+            locs.push(local_dir.join("Programs").join("claude").join("claude.exe"));
         }
+        locs
+    };
 
-        // Set environment variables to indicate SDK usage and version
-        cmd.env("CLAUDE_CODE_ENTRYPOINT", "sdk-rust");
-        cmd.env("CLAUDE_AGENT_SDK_VERSION", env!("CARGO_PKG_VERSION"));
+    let mut searched = Vec::new();
+    for path in &locations {
+        searched.push(path.display().to_string());
+        if path.exists() && path.is_file() {
+            debug!("Found Claude CLI at: {}", path.display());
+            return Ok(path.clone());
+        }
+    }
 
-        // Debug log the full command being executed
-        debug!(
-            "Executing Claude CLI command: {} {:?}",
-            self.cli_path.display(),
-            cmd.as_std().get_args().collect::<Vec<_>>()
-        );
+    // Log detailed error information
+    warn!("Claude CLI not found in any standard location");
+    warn!("Searched paths: {:?}", searched);
 
-        cmd
+    // Check if Node.js is installed
+    if which::which("node").is_err() && which::which("npm").is_err() {
+        error!("Node.js/npm not found - Claude CLI requires Node.js");
+        return Err(SdkError::CliNotFound {
+            searched_paths: format!(
+                "Node.js is not installed. Install from https://nodejs.org/\n\n\
+                Alternatively, enable auto_download_cli to automatically download the CLI:\n\
+                ```rust\n\
+                let options = ClaudeCodeOptions::builder()\n\
+                    .auto_download_cli(true)\n\
+                    .build();\n\
+                ```\n\n\
+                Searched in:\n{}",
+                searched.join("\n")
+            ),
+        });
     }
 
-    /// Check CLI version and warn if below minimum required version
-    async fn check_cli_version(&self) -> Result<()> {
-        if let Some(semver) = get_cli_version(&self.cli_path).await {
-            let min_version = SemVer::new(MIN_CLI_VERSION.0, MIN_CLI_VERSION.1, MIN_CLI_VERSION.2);
-
-            if semver < min_version {
-                warn!(
-                    "⚠️  Claude CLI version {} is below minimum required version {}.{}.{}",
-                    semver, MIN_CLI_VERSION.0, MIN_CLI_VERSION.1, MIN_CLI_VERSION.2
-                );
-                warn!(
-                    "   Some features may not work correctly. Please upgrade with: npm install -g @anthropic-ai/claude-code@latest"
-                );
-            } else {
-                info!("Claude CLI version: {}", semver);
-            }
-        } else {
-            debug!("Could not determine CLI version for {:?}", self.cli_path);
-        }
+    Err(SdkError::CliNotFound {
+        searched_paths: format!(
+            "Claude CLI not found.\n\n\
+            Option 1 - Auto-download (recommended):\n\
+            ```rust\n\
+            let options = ClaudeCodeOptions::builder()\n\
+                .auto_download_cli(true)\n\
+                .build();\n\
+            ```\n\n\
+            Option 2 - Manual installation:\n\
+            npm install -g @anthropic-ai/claude-code\n\n\
+            Searched in:\n{}",
+            searched.join("\n")
+        ),
+    })
+}
 
-        Ok(())
+pub(crate) fn apply_process_user(cmd: &mut Command, user: &str) -> Result<()> {
+    let user = user.trim();
+    if user.is_empty() {
+        return Err(SdkError::ConfigError(
+            "options.user must be a non-empty username or uid".into(),
+        ));
     }
 
-    /// Spawn the process and set up communication channels
-    async fn spawn_process(&mut self) -> Result<()> {
-        self.state = TransportState::Connecting;
+    apply_process_user_inner(cmd, user)
+}
 
-        let mut cmd = self.build_command();
-        info!("Starting Claude CLI with command: {:?}", cmd);
+#[cfg(unix)]
+fn apply_process_user_inner(cmd: &mut Command, user: &str) -> Result<()> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::process::CommandExt;
+    use std::ptr;
 
-        if let Some(user) = self.options.user.as_deref() {
-            apply_process_user(&mut cmd, user)?;
+    fn passwd_buf_len() -> usize {
+        let buf_len = unsafe { libc::sysconf(libc::_SC_GETPW_R_SIZE_MAX) };
+        if buf_len <= 0 {
+            16 * 1024
+        } else {
+            buf_len as usize
         }
+    }
 
-        let mut child = cmd.spawn().map_err(|e| {
-            error!("Failed to spawn Claude CLI: {}", e);
-            SdkError::ProcessError(e)
-        })?;
-
-        // Get stdio handles
-        let stdin = child
-            .stdin
-            .take()
-            .ok_or_else(|| SdkError::ConnectionError("Failed to get stdin".into()))?;
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or_else(|| SdkError::ConnectionError("Failed to get stdout".into()))?;
-        let stderr = child
-            .stderr
-            .take()
-            .ok_or_else(|| SdkError::ConnectionError("Failed to get stderr".into()))?;
-
-        // Determine buffer size from options or use default
-        let buffer_size = self
-            .options
-            .cli_channel_buffer_size
-            .unwrap_or(CHANNEL_BUFFER_SIZE);
-
-        // Create channels
-        let (stdin_tx, mut stdin_rx) = mpsc::channel::<String>(buffer_size);
-        // Use broadcast channel for messages to support multiple receivers
-        let (message_broadcast_tx, _) = tokio::sync::broadcast::channel::<Message>(buffer_size);
-        let (control_tx, control_rx) = mpsc::channel::<ControlResponse>(buffer_size);
-
-        // Spawn stdin handler
-        tokio::spawn(async move {
-            let mut stdin = stdin;
-            debug!("Stdin handler started");
-            while let Some(line) = stdin_rx.recv().await {
-                debug!("Received line from channel: {}", line);
-                if let Err(e) = stdin.write_all(line.as_bytes()).await {
-                    error!("Failed to write to stdin: {}", e);
-                    break;
-                }
-                if let Err(e) = stdin.write_all(b"\n").await {
-                    error!("Failed to write newline: {}", e);
-                    break;
-                }
-                if let Err(e) = stdin.flush().await {
-                    error!("Failed to flush stdin: {}", e);
-                    break;
-                }
-                debug!("Successfully sent to Claude process: {}", line);
-            }
-            debug!("Stdin handler ended");
-        });
+    fn lookup_by_name(name: &str) -> Result<(u32, u32)> {
+        let name = CString::new(name)
+            .map_err(|_| SdkError::ConfigError("options.user must not contain NUL bytes".into()))?;
 
-        // Create channel for SDK control requests
-        let (sdk_control_tx, sdk_control_rx) = mpsc::channel::<serde_json::Value>(buffer_size);
+        let mut pwd = MaybeUninit::<libc::passwd>::zeroed();
+        let mut result: *mut libc::passwd = ptr::null_mut();
+        let mut buf = vec![0u8; passwd_buf_len()];
 
-        // Spawn stdout handler
-        let message_broadcast_tx_clone = message_broadcast_tx.clone();
-        let control_tx_clone = control_tx.clone();
-        let sdk_control_tx_clone = sdk_control_tx.clone();
-        tokio::spawn(async move {
-            debug!("Stdout handler started");
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
-
-            while let Ok(Some(line)) = lines.next_line().await {
-                if line.trim().is_empty() {
-                    continue;
-                }
+        let rc = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                pwd.as_mut_ptr(),
+                buf.as_mut_ptr() as *mut libc::c_char,
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc != 0 {
+            return Err(SdkError::ConfigError(format!(
+                "Failed to resolve options.user={}: getpwnam_r returned {}",
+                name.to_string_lossy(),
+                rc
+            )));
+        }
+        if result.is_null() {
+            return Err(SdkError::ConfigError(format!(
+                "User not found: {}",
+                name.to_string_lossy()
+            )));
+        }
 
-                debug!("Claude output: {}", line);
-
-                // Try to parse as JSON
-                match serde_json::from_str::<serde_json::Value>(&line) {
-                    Ok(json) => {
-                        // Check message type
-                        if let Some(msg_type) = json.get("type").and_then(|v| v.as_str()) {
-                            // Handle control responses - these are responses to OUR control requests
-                            if msg_type == "control_response" {
-                                debug!("Received control response: {:?}", json);
-
-                                // Send to sdk_control channel for control protocol mode
-                                let _ = sdk_control_tx_clone.send(json.clone()).await;
-
-                                // Also parse and send to legacy control_tx for non-control-protocol mode
-                                // (needed for interrupt functionality when query_handler is None)
-                                // CLI returns: {"type":"control_response","response":{"subtype":"success","request_id":"..."}}
-                                // or: {"type":"control_response","response":{"subtype":"error","request_id":"...","error":"..."}}
-                                if let Some(response_obj) = json.get("response")
-                                    && let Some(request_id) = response_obj
-                                        .get("request_id")
-                                        .or_else(|| response_obj.get("requestId"))
-                                        .and_then(|v| v.as_str())
-                                {
-                                    // Determine success from subtype
-                                    let subtype =
-                                        response_obj.get("subtype").and_then(|v| v.as_str());
-                                    let success = subtype == Some("success");
-
-                                    let control_resp = ControlResponse::InterruptAck {
-                                        request_id: request_id.to_string(),
-                                        success,
-                                    };
-                                    let _ = control_tx_clone.send(control_resp).await;
-                                }
-                                continue;
-                            }
+        let pwd = unsafe { pwd.assume_init() };
+        #[allow(clippy::unnecessary_cast)]
+        Ok((pwd.pw_uid as u32, pwd.pw_gid as u32))
+    }
 
-                            // Handle control requests FROM CLI (standard format)
-                            if msg_type == "control_request" {
-                                debug!("Received control request from CLI: {:?}", json);
-                                // Send the FULL message including requestId and request
-                                let _ = sdk_control_tx_clone.send(json.clone()).await;
-                                continue;
-                            }
+    fn lookup_by_uid(uid: u32) -> Result<(u32, u32)> {
+        let mut pwd = MaybeUninit::<libc::passwd>::zeroed();
+        let mut result: *mut libc::passwd = ptr::null_mut();
+        let mut buf = vec![0u8; passwd_buf_len()];
 
-                            // Handle control messages (new format)
-                            if msg_type == "control"
-                                && let Some(control) = json.get("control")
-                            {
-                                debug!("Received control message: {:?}", control);
-                                let _ = sdk_control_tx_clone.send(control.clone()).await;
-                                continue;
-                            }
+        let rc = unsafe {
+            libc::getpwuid_r(
+                uid as libc::uid_t,
+                pwd.as_mut_ptr(),
+                buf.as_mut_ptr() as *mut libc::c_char,
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc != 0 {
+            return Err(SdkError::ConfigError(format!(
+                "Failed to resolve options.user={}: getpwuid_r returned {}",
+                uid, rc
+            )));
+        }
+        if result.is_null() {
+            return Err(SdkError::ConfigError(format!(
+                "User not found for uid: {}",
+                uid
+            )));
+        }
 
-                            // Handle SDK control requests FROM CLI (legacy format)
-                            if msg_type == "sdk_control_request" {
-                                // Send the FULL message including requestId
-                                debug!("Received SDK control request (legacy): {:?}", json);
-                                let _ = sdk_control_tx_clone.send(json.clone()).await;
-                                continue;
-                            }
+        let pwd = unsafe { pwd.assume_init() };
+        #[allow(clippy::unnecessary_cast)]
+        Ok((pwd.pw_uid as u32, pwd.pw_gid as u32))
+    }
 
-                            // Check for system messages with SDK control subtypes
-                            if msg_type == "system"
-                                && let Some(subtype) = json.get("subtype").and_then(|v| v.as_str())
-                                && subtype.starts_with("sdk_control:")
-                            {
-                                // This is an SDK control message
-                                debug!("Received SDK control message: {}", subtype);
-                                let _ = sdk_control_tx_clone.send(json.clone()).await;
-                                // Still parse as regular message for now
-                            }
-                        }
+    let (uid, gid) = match user.parse::<u32>() {
+        Ok(uid) => lookup_by_uid(uid)?,
+        Err(_) => lookup_by_name(user)?,
+    };
 
-                        // Try to parse as a regular message
-                        match crate::message_parser::parse_message(json) {
-                            Ok(Some(message)) => {
-                                // Use broadcast send which doesn't fail if no receivers
-                                let _ = message_broadcast_tx_clone.send(message);
-                            },
-                            Ok(None) => {
-                                // Ignore non-message JSON
-                            },
-                            Err(e) => {
-                                warn!("Failed to parse message: {}", e);
-                            },
-                        }
-                    },
-                    Err(e) => {
-                        warn!("Failed to parse JSON: {} - Line: {}", e, line);
-                    },
-                }
-            }
-            info!("Stdout reader ended");
-        });
+    cmd.as_std_mut().uid(uid).gid(gid);
+    Ok(())
+}
 
-        // Spawn stderr handler - capture error messages for better diagnostics
-        let message_broadcast_tx_for_error = message_broadcast_tx.clone();
-        let debug_stderr = self.options.debug_stderr.clone();
-        let stderr_callback = self.options.stderr_callback.clone();
-        tokio::spawn(async move {
-            let reader = BufReader::new(stderr);
-            let mut lines = reader.lines();
-            let mut error_buffer = Vec::new();
-
-            while let Ok(Some(line)) = lines.next_line().await {
-                if !line.trim().is_empty() {
-                    // If debug_stderr is set, write to it
-                    if let Some(ref debug_output) = debug_stderr {
-                        let mut output = debug_output.lock().await;
-                        let _ = writeln!(output, "{line}");
-                        let _ = output.flush();
-                    }
+#[cfg(not(unix))]
+fn apply_process_user_inner(_cmd: &mut Command, _user: &str) -> Result<()> {
+    Err(SdkError::NotSupported {
+        feature: "options.user is only supported on Unix platforms".into(),
+    })
+}
 
-                    if let Some(ref callback) = stderr_callback {
-                        callback.as_ref()(line.as_str());
-                    }
+/// Questionable configuration found while preparing the CLI process
+///
+/// Leniently, each issue is logged with `warn!` and the SDK falls back to a
+/// safe default. With [`ClaudeCodeOptions::strict_mode`] the issues are
+/// collected and [`finish`](Self::finish) fails with `SdkError::ConfigError`.
+pub(crate) struct ConfigWarnings {
+    strict: bool,
+    issues: Vec<String>,
+}
 
-                    // Filter out non-actionable noise: hook callback errors and
-                    // AbortError from minified CLI JS.  These are expected during
-                    // session interrupts (the CLI aborts in-flight hooks) and would
-                    // otherwise spam ERROR logs + pollute the error_buffer which
-                    // triggers a System::error broadcast to consumers.
-                    if line.contains("Error in hook callback")
-                        || (line.contains("AbortError") && line.len() < 200)
-                        || line.starts_with("      at ")
-                    {
-                        debug!("Claude CLI stderr (filtered — hook abort noise): {}", line);
-                        continue;
-                    }
+impl ConfigWarnings {
+    pub(crate) fn new(options: &ClaudeCodeOptions) -> Self {
+        Self {
+            strict: options.strict_mode,
+            issues: Vec::new(),
+        }
+    }
 
-                    // Skip minified JS source lines (long lines from bundled CLI code
-                    // that leak into stderr during hook crashes).  These carry zero
-                    // diagnostic value and can be thousands of characters long.
-                    if line.len() > 500
-                        && (line.contains("Symbol.for(\"react.memo_cache_sentinel\")")
-                            || line.contains("/$bunfs/root/src/entrypoints/cli.js"))
-                    {
-                        debug!(
-                            "Claude CLI stderr (filtered — minified JS, {} bytes)",
-                            line.len()
-                        );
-                        continue;
-                    }
+    /// Report one issue
+    pub(crate) fn warn(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        if self.strict {
+            self.issues.push(message);
+        } else {
+            warn!("{}", message);
+        }
+    }
 
-                    error!("Claude CLI stderr: {}", line);
-                    error_buffer.push(line.clone());
-
-                    // Check for common error patterns
-                    if line.contains("command not found") || line.contains("No such file") {
-                        error!("Claude CLI binary not found or not executable");
-                    } else if line.contains("ENOENT") || line.contains("spawn") {
-                        error!("Failed to spawn Claude CLI process - binary may not be installed");
-                    } else if line.contains("authentication")
-                        || line.contains("API key")
-                        || line.contains("Unauthorized")
-                    {
-                        error!(
-                            "Claude CLI authentication error - please run 'claude-code api login'"
-                        );
-                    } else if line.contains("model")
-                        && (line.contains("not available") || line.contains("not found"))
-                    {
-                        error!("Model not available for your account: {}", line);
-                    } else if line.contains("Error:") || line.contains("error:") {
-                        error!("Claude CLI error detected: {}", line);
-                    }
-                }
-            }
+    /// Fail if strict mode collected any issues
+    pub(crate) fn finish(self) -> Result<()> {
+        if self.issues.is_empty() {
+            Ok(())
+        } else {
+            Err(SdkError::ConfigError(format!(
+                "strict mode: {}",
+                self.issues.join("; ")
+            )))
+        }
+    }
+}
 
-            // If we collected any actionable errors (after filtering), log and broadcast them.
-            if !error_buffer.is_empty() {
-                let error_msg = error_buffer.join("\n");
-                error!("Claude CLI stderr output collected:\n{}", error_msg);
-
-                // Try to send an error message through the broadcast channel
-                let _ = message_broadcast_tx_for_error.send(Message::System {
-                    subtype: "error".to_string(),
-                    data: serde_json::json!({
-                        "source": "stderr",
-                        "error": "Claude CLI error output",
-                        "details": error_msg
-                    }),
-                });
-            }
-        });
+/// Set `CLAUDE_CODE_MAX_OUTPUT_TOKENS` (priority: option > env var)
+///
+/// Maximum safe value is 32000, values above this may cause issues.
+pub(crate) fn apply_max_output_tokens(
+    cmd: &mut Command,
+    options: &ClaudeCodeOptions,
+    warnings: &mut ConfigWarnings,
+) {
+    if let Some(max_tokens) = options.max_output_tokens {
+        // Option takes priority - validate and cap at 32000
+        let capped = max_tokens.clamp(1, 32000);
+        cmd.env("CLAUDE_CODE_MAX_OUTPUT_TOKENS", capped.to_string());
+        debug!("Setting max_output_tokens from option: {}", capped);
+    } else if let Ok(current_value) = std::env::var("CLAUDE_CODE_MAX_OUTPUT_TOKENS") {
+        // Fall back to environment variable handling
+        if let Some(value) = sanitize_max_output_tokens(&current_value, warnings) {
+            cmd.env("CLAUDE_CODE_MAX_OUTPUT_TOKENS", value);
+        }
+    }
+}
 
-        // Store handles
-        self.child = Some(child);
-        self.stdin_tx = Some(stdin_tx);
-        self.message_broadcast_tx = Some(message_broadcast_tx);
-        self.control_rx = Some(control_rx);
-        self.sdk_control_rx = Some(sdk_control_rx);
-        self.state = TransportState::Connected;
+/// Warn when the permission prompt tool is not provided by a configured MCP server
+///
+/// Names take the `mcp__<server>__<tool>` form (or `stdio` for the control
+/// protocol). The CLI silently skips permission prompting when the tool does
+/// not exist. Only SDK servers can be introspected for their tools; for other
+/// servers just the server name is checked. A server missing from
+/// `mcp_servers` is only logged, even in strict mode: the CLI also loads
+/// servers from `.mcp.json`, its settings and the user's config, which the
+/// SDK cannot see.
+fn check_permission_prompt_tool(
+    tool_name: &str,
+    options: &ClaudeCodeOptions,
+    warnings: &mut ConfigWarnings,
+) {
+    if tool_name == "stdio" {
+        return;
+    }
+    let Some((server, tool)) = tool_name
+        .strip_prefix("mcp__")
+        .and_then(|rest| rest.split_once("__"))
+    else {
+        warnings.warn(format!(
+            "Permission prompt tool '{tool_name}' is not an MCP tool name (mcp__<server>__<tool>)"
+        ));
+        return;
+    };
+    match options.mcp_servers.get(server) {
+        None => warn!(
+            "Permission prompt tool '{tool_name}': no MCP server named '{server}' in mcp_servers; \
+             the CLI skips permission prompting unless its own config provides one"
+        ),
+        Some(McpServerConfig::Sdk { instance, .. }) => {
+            let has_tool = instance
+                .downcast_ref::<crate::sdk_mcp::SdkMcpServer>()
+                .is_none_or(|sdk_server| sdk_server.tools.iter().any(|t| t.name == tool));
+            if !has_tool {
+                warnings.warn(format!(
+                    "Permission prompt tool '{tool_name}': MCP server '{server}' has no tool named '{tool}'"
+                ));
+            }
+        },
+        Some(_) => {},
+    }
+}
 
-        Ok(())
+/// Safe replacement for an out-of-range `CLAUDE_CODE_MAX_OUTPUT_TOKENS` value
+fn sanitize_max_output_tokens(value: &str, warnings: &mut ConfigWarnings) -> Option<&'static str> {
+    if let Ok(tokens) = value.parse::<u32>() {
+        if tokens > 32000 {
+            warnings.warn(format!(
+                "CLAUDE_CODE_MAX_OUTPUT_TOKENS={} exceeds maximum safe value of 32000, overriding to 32000",
+                tokens
+            ));
+            return Some("32000");
+        }
+        // If it's <= 32000, leave it as is
+        None
+    } else {
+        // Invalid value, set to safe default
+        warnings.warn(format!(
+            "Invalid CLAUDE_CODE_MAX_OUTPUT_TOKENS value: {}, setting to 8192",
+            value
+        ));
+        Some("8192")
     }
 }
 
-#[async_trait]
-impl Transport for SubprocessTransport {
-    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
-        self
+/// Export the entrypoint and SDK version the CLI reports in its telemetry
+///
+/// With `disable_telemetry_env`, removes them instead and turns the CLI's
+/// telemetry and error reporting off.
+pub(crate) fn apply_sdk_identity(cmd: &mut Command, options: &ClaudeCodeOptions) {
+    if options.disable_telemetry_env {
+        cmd.env_remove("CLAUDE_CODE_ENTRYPOINT")
+            .env_remove("CLAUDE_AGENT_SDK_VERSION")
+            .env("DISABLE_TELEMETRY", "1")
+            .env("DISABLE_ERROR_REPORTING", "1");
+        return;
     }
+    cmd.env(
+        "CLAUDE_CODE_ENTRYPOINT",
+        options.entrypoint.as_deref().unwrap_or("sdk-rust"),
+    );
+    cmd.env(
+        "CLAUDE_AGENT_SDK_VERSION",
+        options
+            .sdk_version_override
+            .as_deref()
+            .unwrap_or(env!("CARGO_PKG_VERSION")),
+    );
+}
 
-    async fn connect(&mut self) -> Result<()> {
-        if self.state == TransportState::Connected {
-            return Ok(());
+/// Pass `options.extra_args` and `options.extra_args_multi` to the CLI
+///
+/// Keys without a leading dash get `--` prepended. Every value is its own argv
+/// entry and is never split on whitespace.
+pub(crate) fn apply_extra_args(cmd: &mut Command, options: &ClaudeCodeOptions) {
+    let flag = |key: &String| {
+        if key.starts_with('-') {
+            key.clone()
+        } else {
+            format!("--{key}")
         }
-
-        // Check CLI version before connecting
-        if let Err(e) = self.check_cli_version().await {
-            warn!("CLI version check failed: {}", e);
+    };
+    for (key, value) in &options.extra_args {
+        cmd.arg(flag(key));
+        if let Some(val) = value {
+            cmd.arg(val);
         }
+    }
+    for (key, values) in &options.extra_args_multi {
+        cmd.arg(flag(key)).args(values);
+    }
+}
 
-        self.spawn_process().await?;
-        info!("Connected to Claude CLI");
-        Ok(())
+/// `User-Agent` sent to HTTP and SSE MCP servers unless the user sets one
+pub(crate) const MCP_USER_AGENT: &str = concat!("nexus-claude/", env!("CARGO_PKG_VERSION"));
+
+/// Pass `options.mcp_servers` to the CLI as `--mcp-config`
+///
+/// `${VAR}` references in the server fields are passed through for the CLI
+/// to expand from its own environment, so secrets never appear in its
+/// command line or in our logs. Each referenced variable must be set for
+/// the CLI, through `env`, `extra_env_file` or the process environment; an
+/// unset one fails with `SdkError::ConfigError`. HTTP and SSE servers get a
+/// default `User-Agent` header identifying the SDK; a user-supplied
+/// `User-Agent` (any casing) is left untouched.
+pub(crate) fn apply_mcp_config(cmd: &mut Command, options: &ClaudeCodeOptions) -> Result<()> {
+    if options.mcp_servers.is_empty() {
+        return Ok(());
     }
+    // A missing or malformed env file is reported when it is applied
+    let env_file: HashMap<String, String> = options
+        .extra_env_file
+        .as_deref()
+        .and_then(|path| {
+            let contents = std::fs::read_to_string(path).ok()?;
+            crate::env_file::parse_env(&contents, &path.display().to_string()).ok()
+        })
+        .map(|vars| vars.into_iter().collect())
+        .unwrap_or_default();
+    let is_set = |name: &str| {
+        options.env.contains_key(name)
+            || env_file.contains_key(name)
+            || std::env::var_os(name).is_some()
+    };
 
-    async fn send_message(&mut self, message: InputMessage) -> Result<()> {
-        if self.state != TransportState::Connected {
-            return Err(SdkError::InvalidState {
-                message: "Not connected".into(),
-            });
+    let mut servers = HashMap::new();
+    for (name, config) in &options.mcp_servers {
+        if let Some((field, var)) = unset_mcp_env_var(config, &is_set) {
+            return Err(SdkError::ConfigError(format!(
+                "MCP server \"{name}\": environment variable {var} used in {field} is not set"
+            )));
         }
+        servers.insert(name, with_default_user_agent(config));
+    }
+    let mcp_config = serde_json::json!({ "mcpServers": servers });
+    cmd.arg("--mcp-config").arg(mcp_config.to_string());
+    Ok(())
+}
 
-        let json = serde_json::to_string(&message)?;
-        debug!("Serialized message: {}", json);
+/// Find a `${VAR}` reference in a server's fields whose variable is not set
+///
+/// Returns the field and the name of the first unset variable.
+fn unset_mcp_env_var(
+    config: &McpServerConfig,
+    is_set: &dyn Fn(&str) -> bool,
+) -> Option<(String, String)> {
+    let map_fields = |field: &str, map: &Option<HashMap<String, String>>| {
+        map.iter()
+            .flatten()
+            .map(|(key, value)| (format!("{field}.{key}"), value.clone()))
+            .collect::<Vec<_>>()
+    };
+    let fields = match config {
+        McpServerConfig::Stdio { command, args, env } => {
+            let mut fields = map_fields("env", env);
+            fields.push(("command".into(), command.clone()));
+            for arg in args.iter().flatten() {
+                fields.push(("args".into(), arg.clone()));
+            }
+            fields
+        },
+        McpServerConfig::Sse { url, headers } | McpServerConfig::Http { url, headers } => {
+            let mut fields = map_fields("headers", headers);
+            fields.push(("url".into(), url.clone()));
+            fields
+        },
+        McpServerConfig::Sdk { .. } => Vec::new(),
+    };
+    fields.into_iter().find_map(|(field, value)| {
+        env_var_refs(&value)
+            .find(|name| !is_set(name))
+            .map(|name| (field, name.to_string()))
+    })
+}
 
-        if let Some(ref tx) = self.stdin_tx {
-            debug!("Sending message to stdin channel");
-            tx.send(json).await?;
-            debug!("Message sent to channel");
-            Ok(())
-        } else {
-            Err(SdkError::InvalidState {
-                message: "Stdin channel not available".into(),
-            })
+/// Names of the `${VAR}` references in `value`
+///
+/// Only braced references with a valid variable name count; anything else,
+/// including `$VAR` and `${VAR:-default}`, is left to the CLI.
+fn env_var_refs(value: &str) -> impl Iterator<Item = &str> {
+    value.split("${").skip(1).filter_map(|after| {
+        let name = &after[..after.find('}')?];
+        let mut chars = name.chars();
+        (chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_'))
+        .then_some(name)
+    })
+}
+
+fn with_default_user_agent(config: &McpServerConfig) -> McpServerConfig {
+    let add_user_agent = |headers: &Option<HashMap<String, String>>| {
+        let mut headers = headers.clone().unwrap_or_default();
+        if !headers
+            .keys()
+            .any(|key| key.eq_ignore_ascii_case("user-agent"))
+        {
+            headers.insert("User-Agent".into(), MCP_USER_AGENT.into());
         }
+        Some(headers)
+    };
+    match config {
+        McpServerConfig::Sse { url, headers } => McpServerConfig::Sse {
+            url: url.clone(),
+            headers: add_user_agent(headers),
+        },
+        McpServerConfig::Http { url, headers } => McpServerConfig::Http {
+            url: url.clone(),
+            headers: add_user_agent(headers),
+        },
+        other => other.clone(),
     }
+}
 
-    fn receive_messages(
-        &mut self,
-    ) -> Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>> {
-        if let Some(ref tx) = self.message_broadcast_tx {
-            // Create a new receiver from the broadcast sender
-            let rx = tx.subscribe();
-            // Convert broadcast receiver to stream
-            Box::pin(tokio_stream::wrappers::BroadcastStream::new(rx).filter_map(
-                |result| async move {
-                    match result {
-                        Ok(msg) => Some(Ok(msg)),
-                        Err(tokio_stream::wrappers::errors::BroadcastStreamRecvError::Lagged(
-                            n,
-                        )) => {
-                            warn!("Receiver lagged by {} messages", n);
-                            None
-                        },
-                    }
-                },
-            ))
-        } else {
-            Box::pin(futures::stream::empty())
-        }
+/// Read the prompt of a [`SystemPrompt::File`](crate::SystemPrompt::File)
+pub(crate) fn read_system_prompt_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| {
+        SdkError::ConfigError(format!(
+            "Failed to read system prompt file {}: {e}",
+            path.display()
+        ))
+    })
+}
+
+/// Map `options.output_format` to the CLI flag requesting that result format
+///
+/// Supported types:
+/// - `text`: plain text result (the CLI default, no flag)
+/// - `json_schema`: JSON validated against the required `schema`
+///
+/// The CLI only returns structured output for a schema it was given, so a
+/// bare `json` type is rejected rather than mapped to a made-up schema.
+pub(crate) fn apply_output_format(cmd: &mut Command, format: &serde_json::Value) -> Result<()> {
+    match format.get("type").and_then(|v| v.as_str()) {
+        Some("text") => {},
+        Some("json_schema") => {
+            let schema = format.get("schema").ok_or_else(|| {
+                SdkError::ConfigError(
+                    "output_format of type \"json_schema\" requires a \"schema\"".into(),
+                )
+            })?;
+            cmd.arg("--json-schema").arg(schema.to_string());
+        },
+        Some("json") => {
+            return Err(SdkError::ConfigError(
+                "output_format of type \"json\" is not supported by the CLI; use \"json_schema\" with a \"schema\"".into(),
+            ));
+        },
+        Some(other) => {
+            return Err(SdkError::ConfigError(format!(
+                "Unsupported output_format type \"{other}\" (expected text or json_schema)"
+            )));
+        },
+        None => {
+            return Err(SdkError::ConfigError(
+                "output_format must have a string \"type\"".into(),
+            ));
+        },
     }
+    Ok(())
+}
 
-    fn subscribe_messages(
-        &self,
-    ) -> Option<Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>>> {
-        // Delegate to the inherent method on SubprocessTransport, which
-        // returns a `'static` stream subscribed to the long-lived broadcast.
-        // Fully-qualified path needed to disambiguate from the trait method.
-        SubprocessTransport::subscribe_messages(self)
+pub(crate) fn apply_resource_limits(cmd: &mut Command, limits: &ResourceLimits) -> Result<()> {
+    if limits.max_memory_bytes.is_none() && limits.max_cpu_seconds.is_none() {
+        return Ok(());
     }
 
-    async fn send_control_request(&mut self, request: ControlRequest) -> Result<()> {
-        if self.state != TransportState::Connected {
-            return Err(SdkError::InvalidState {
-                message: "Not connected".into(),
-            });
+    apply_resource_limits_inner(cmd, *limits)
+}
+
+#[cfg(unix)]
+fn apply_resource_limits_inner(cmd: &mut Command, limits: ResourceLimits) -> Result<()> {
+    fn rlimit(value: u64) -> libc::rlimit {
+        libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: value as libc::rlim_t,
         }
+    }
 
-        self.request_counter += 1;
-        let control_msg = match request {
-            ControlRequest::Interrupt { request_id } => {
-                serde_json::json!({
-                    "type": "control_request",
-                    "request": {
-                        "type": "interrupt",
-                        "request_id": request_id
-                    }
-                })
-            },
-        };
+    // SAFETY: the hook only calls setrlimit, which is async-signal-safe (POSIX.1-2017)
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(bytes) = limits.max_memory_bytes
+                && libc::setrlimit(libc::RLIMIT_AS, &rlimit(bytes)) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            if let Some(seconds) = limits.max_cpu_seconds
+                && libc::setrlimit(libc::RLIMIT_CPU, &rlimit(seconds)) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
 
-        let json = serde_json::to_string(&control_msg)?;
+#[cfg(not(unix))]
+fn apply_resource_limits_inner(_cmd: &mut Command, _limits: ResourceLimits) -> Result<()> {
+    Err(SdkError::NotSupported {
+        feature: "options.resource_limits is only supported on Unix platforms".into(),
+    })
+}
 
-        if let Some(ref tx) = self.stdin_tx {
-            tx.send(json).await?;
-            Ok(())
-        } else {
-            Err(SdkError::InvalidState {
-                message: "Stdin channel not available".into(),
-            })
-        }
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Write an executable stand-in for the CLI into `dir`
+    ///
+    /// The script answers `--version` with 2.0.0, as the version check at
+    /// connect expects, and runs `body` for every other invocation.
+    #[cfg(unix)]
+    pub(crate) fn fake_cli(dir: &Path, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join("claude");
+        let script = format!(
+            "#!/bin/sh\nif [ \"$1\" = \"--version\" ]; then echo \"2.0.0\"; exit 0; fi\n{body}"
+        );
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
     }
 
-    async fn receive_control_response(&mut self) -> Result<Option<ControlResponse>> {
-        if let Some(ref mut rx) = self.control_rx {
-            Ok(rx.recv().await)
-        } else {
-            Ok(None)
-        }
+    fn cmd_args(cmd: &Command) -> Vec<String> {
+        cmd.as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
     }
 
-    async fn send_sdk_control_request(&mut self, request: serde_json::Value) -> Result<()> {
-        // The request is already properly formatted as {"type": "control_request", ...}
-        // Just send it directly without wrapping
-        let json = serde_json::to_string(&request)?;
+    /// Arguments of the command `connect` would spawn for `options`
+    fn command_args(options: ClaudeCodeOptions) -> Vec<String> {
+        let transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        cmd_args(&transport.prepare_command().unwrap())
+    }
 
-        if let Some(ref tx) = self.stdin_tx {
-            tx.send(json).await?;
-            Ok(())
-        } else {
-            Err(SdkError::InvalidState {
-                message: "Stdin channel not available".into(),
-            })
-        }
+    fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+        let i = args.iter().position(|arg| arg == flag)?;
+        args.get(i + 1).map(String::as_str)
     }
 
-    async fn send_sdk_control_response(&mut self, response: serde_json::Value) -> Result<()> {
-        // Wrap the response in control_response format expected by CLI
-        // The response should have: {"type": "control_response", "response": {...}}
-        let control_response = serde_json::json!({
-            "type": "control_response",
-            "response": response
+    #[test]
+    fn test_parse_models_from_initialize_response() {
+        let response = serde_json::json!({
+            "commands": [{"name": "compact", "description": "Compact the conversation"}],
+            "output_style": "default",
+            "models": [
+                {
+                    "value": "default",
+                    "displayName": "Default (recommended)",
+                    "description": "Use the default model"
+                },
+                {
+                    "value": "opus",
+                    "displayName": "Opus",
+                    "description": "Most capable for complex work"
+                },
+                {"value": "haiku"}
+            ]
         });
 
-        let json = serde_json::to_string(&control_response)?;
+        let models = parse_models(&response).unwrap();
+        assert_eq!(models.len(), 3);
+        assert_eq!(
+            models[1],
+            ModelInfo {
+                value: "opus".into(),
+                display_name: "Opus".into(),
+                description: "Most capable for complex work".into(),
+            }
+        );
+        assert_eq!(models[2].value, "haiku");
+        assert!(models[2].display_name.is_empty());
+    }
 
-        if let Some(ref tx) = self.stdin_tx {
-            tx.send(json).await?;
-            Ok(())
-        } else {
-            Err(SdkError::InvalidState {
-                message: "Stdin channel not available".into(),
-            })
-        }
+    #[test]
+    fn test_parse_models_not_supported_without_model_list() {
+        let err = parse_models(&serde_json::json!({"commands": []})).unwrap_err();
+        assert!(matches!(err, SdkError::NotSupported { .. }));
+
+        let err = parse_models(&serde_json::json!({"models": [{"displayName": "x"}]})).unwrap_err();
+        assert!(matches!(err, SdkError::MessageParseError { .. }));
     }
 
-    fn child_pid(&self) -> Option<u32> {
-        self.child.as_ref().and_then(|c| c.id())
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_available_models_queries_cli() {
+        // Fake CLI answering the initialize request with a model list
+        let dir = tempfile::tempdir().unwrap();
+        let cli = fake_cli(
+            dir.path(),
+            r#"read -r line
+id=$(echo "$line" | sed 's/.*"request_id":"\([^"]*\)".*/\1/')
+echo '{"type":"control_response","response":{"subtype":"success","request_id":"'$id'","response":{"models":[{"value":"sonnet","displayName":"Sonnet","description":"Balanced"}]}}}'
+exec cat > /dev/null
+"#,
+        );
+
+        let options = ClaudeCodeOptions::builder().cli_path(&cli).build();
+        let models = available_models(options).await.unwrap();
+        assert_eq!(models.len(), 1);
+        assert_eq!(models[0].value, "sonnet");
+        assert_eq!(models[0].display_name, "Sonnet");
     }
 
-    fn is_connected(&self) -> bool {
-        self.state == TransportState::Connected
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sandbox_rejected_for_old_cli() {
+        // The fake CLI reports 2.0.0, before sandbox settings were honored
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("spawned");
+        let cli = fake_cli(dir.path(), &format!("touch {}\n", marker.display()));
+        let options = ClaudeCodeOptions::builder()
+            .sandbox(crate::types::SandboxSettings::default())
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, cli);
+
+        match transport.connect().await {
+            Err(SdkError::NotSupported { feature }) => {
+                assert_eq!(feature, "sandbox settings (not available in CLI 2.0.0)")
+            },
+            other => panic!("expected NotSupported, got {other:?}"),
+        }
+        assert!(!marker.exists());
     }
 
-    async fn disconnect(&mut self) -> Result<()> {
-        if self.state != TransportState::Connected {
-            return Ok(());
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reconnects_and_resumes_after_crash() {
+        // Fake CLI: the first run reports a session and crashes, later runs
+        // answer every prompt with a result
+        let dir = tempfile::tempdir().unwrap();
+        let runs = dir.path().join("runs");
+        let crashed = dir.path().join("crashed");
+        let script = format!(
+            r#"echo "$*" >> {runs}
+if [ ! -f {crashed} ]; then
+  touch {crashed}
+  echo '{{"type":"system","subtype":"init","session_id":"sess-1"}}'
+  exit 1
+fi
+while read -r line; do
+  echo '{{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":false,"num_turns":1,"session_id":"sess-1"}}'
+done
+"#,
+            runs = runs.display(),
+            crashed = crashed.display()
+        );
+        let cli = fake_cli(dir.path(), &script);
+
+        let options = ClaudeCodeOptions::builder()
+            .cli_path(&cli)
+            .reconnect_on_failure(true)
+            .reconnect_backoff(crate::types::ReconnectBackoff {
+                base_delay: std::time::Duration::from_millis(10),
+                ..Default::default()
+            })
+            .build();
+        let mut transport = SubprocessTransport::new(options).unwrap();
+        transport.connect().await.unwrap();
+        let mut messages = transport.receive_messages();
+
+        let wait = std::time::Duration::from_secs(5);
+        match tokio::time::timeout(wait, messages.next()).await.unwrap() {
+            Some(Ok(Message::System { subtype, .. })) => assert_eq!(subtype, "init"),
+            other => panic!("expected init, got {other:?}"),
+        }
+        match tokio::time::timeout(wait, messages.next()).await.unwrap() {
+            Some(Ok(Message::System { subtype, data })) => {
+                assert_eq!(subtype, "reconnected");
+                assert_eq!(data["session_id"], "sess-1");
+            },
+            other => panic!("expected reconnected marker, got {other:?}"),
         }
+        assert_eq!(transport.state.get(), TransportState::Connected);
+
+        transport
+            .send_message(InputMessage::user("hi".into(), "default".into()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            tokio::time::timeout(wait, messages.next()).await.unwrap(),
+            Some(Ok(Message::Result { .. }))
+        ));
 
-        self.state = TransportState::Disconnecting;
+        let runs = std::fs::read_to_string(&runs).unwrap();
+        let runs: Vec<&str> = runs.lines().collect();
+        assert_eq!(runs.len(), 2);
+        assert!(!runs[0].contains("--resume"));
+        assert!(runs[1].contains("--resume sess-1"));
+        transport.disconnect().await.unwrap();
+    }
 
-        // Close stdin channel — signals EOF to the CLI process
-        self.stdin_tx.take();
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reconnect_gives_up_after_max_attempts() {
+        let dir = tempfile::tempdir().unwrap();
+        let runs = dir.path().join("runs");
+        let script = format!(
+            r#"echo run >> {}
+exit 1
+"#,
+            runs.display()
+        );
+        let cli = fake_cli(dir.path(), &script);
+
+        let options = ClaudeCodeOptions::builder()
+            .cli_path(&cli)
+            .reconnect_on_failure(true)
+            .max_reconnect_attempts(2)
+            .reconnect_backoff(crate::types::ReconnectBackoff {
+                base_delay: std::time::Duration::from_millis(10),
+                ..Default::default()
+            })
+            .build();
+        let mut transport = SubprocessTransport::new(options).unwrap();
+        transport.connect().await.unwrap();
+
+        // Each restart that dies before finishing a turn uses up an attempt;
+        // the stream ends once restarting is abandoned
+        let messages: Vec<_> = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            transport.receive_messages().collect(),
+        )
+        .await
+        .unwrap();
+        let attempts: Vec<_> = messages
+            .iter()
+            .map(|msg| match msg {
+                Ok(Message::System { subtype, data }) if subtype == "reconnected" => {
+                    data["attempt"].clone()
+                },
+                other => panic!("unexpected message {other:?}"),
+            })
+            .collect();
+        assert_eq!(attempts, vec![1, 2]);
+
+        assert_eq!(transport.state.get(), TransportState::Error);
+        assert_eq!(std::fs::read_to_string(&runs).unwrap().lines().count(), 3);
+        let sent = transport
+            .send_message(InputMessage::user("hi".into(), "default".into()))
+            .await;
+        assert!(matches!(sent, Err(SdkError::InvalidState { .. })));
+        transport.disconnect().await.unwrap();
+        assert_eq!(transport.state.get(), TransportState::Disconnected);
+    }
 
-        // Graceful shutdown escalation: SIGINT → SIGTERM → SIGKILL
-        // Mirrors the pattern used by Claude Code for MCP servers.
-        // Total max wait: 200ms + 500ms = 700ms before SIGKILL.
-        //
-        // Signals are sent to the PROCESS GROUP (negative PID) so that
-        // child processes (bash, find, sleep, etc.) are also terminated.
-        if let Some(ref mut child) = self.child {
-            #[cfg(unix)]
-            if let Some(pid) = child.id() {
-                let pgid = -(pid as i32);
-
-                // Stage 1: SIGINT — give the CLI a chance to handle Ctrl-C gracefully
-                unsafe {
-                    libc::kill(pgid, libc::SIGINT);
-                }
-                debug!(
-                    "Sent SIGINT to CLI process group (pid={}, pgid={})",
-                    pid, pgid
-                );
+    #[test]
+    fn test_find_claude_cli_error_message() {
+        // Test error message format without relying on CLI not being found
+        let error = SdkError::CliNotFound {
+            searched_paths: "test paths".to_string(),
+        };
+        let error_msg = error.to_string();
+        assert!(error_msg.contains("npm install -g @anthropic-ai/claude-code"));
+        assert!(error_msg.contains("test paths"));
+    }
 
-                // Wait 200ms for graceful shutdown
-                match tokio::time::timeout(std::time::Duration::from_millis(200), child.wait())
-                    .await
-                {
-                    Ok(Ok(status)) => {
-                        info!(
-                            "CLI process terminated gracefully via SIGINT (pid={}, status={})",
-                            pid, status
-                        );
-                        self.child.take();
-                        self.state = TransportState::Disconnected;
-                        return Ok(());
-                    },
-                    Ok(Err(e)) => {
-                        warn!("Error waiting for CLI process after SIGINT: {}", e);
-                        // Fall through to SIGTERM
-                    },
-                    Err(_) => {
-                        debug!(
-                            "CLI process did not exit within 200ms after SIGINT, escalating to SIGTERM"
-                        );
-                        // Fall through to SIGTERM
-                    },
-                }
+    #[test]
+    fn test_subprocess_transport_capabilities() {
+        let transport =
+            SubprocessTransport::with_cli_path(ClaudeCodeOptions::default(), "/usr/bin/true");
+        let capabilities = transport.capabilities();
+        assert!(capabilities.supports_control);
+        assert!(capabilities.supports_interrupt);
+        assert!(capabilities.supports_stdin_clone);
+        assert!(capabilities.supports_inbound_control);
+    }
 
-                // Stage 2: SIGTERM — stronger signal, still allows cleanup
-                unsafe {
-                    libc::kill(pgid, libc::SIGTERM);
-                }
-                debug!(
-                    "Sent SIGTERM to CLI process group (pid={}, pgid={})",
-                    pid, pgid
-                );
+    #[tokio::test]
+    async fn test_transport_lifecycle() {
+        let options = ClaudeCodeOptions::default();
+        let transport = SubprocessTransport::new(options).unwrap_or_else(|_| {
+            // Use a dummy path for testing
+            SubprocessTransport::with_cli_path(ClaudeCodeOptions::default(), "/usr/bin/true")
+        });
 
-                match tokio::time::timeout(std::time::Duration::from_millis(500), child.wait())
-                    .await
-                {
-                    Ok(Ok(status)) => {
-                        info!(
-                            "CLI process terminated via SIGTERM (pid={}, status={})",
-                            pid, status
-                        );
-                        self.child.take();
-                        self.state = TransportState::Disconnected;
-                        return Ok(());
-                    },
-                    Ok(Err(e)) => {
-                        warn!("Error waiting for CLI process after SIGTERM: {}", e);
-                        // Fall through to SIGKILL
-                    },
-                    Err(_) => {
-                        warn!(
-                            "CLI process did not exit within 500ms after SIGTERM, escalating to SIGKILL (pid={})",
-                            pid
-                        );
-                        // Fall through to SIGKILL
-                    },
-                }
-            }
+        assert!(!transport.is_connected());
+        assert_eq!(transport.state.get(), TransportState::Disconnected);
+    }
 
-            // Stage 3: SIGKILL — last resort
-            if let Some(mut child) = self.child.take() {
-                #[cfg(unix)]
-                if let Some(pid) = child.id() {
-                    let pgid = -(pid as i32);
-                    warn!(
-                        "Sending SIGKILL to CLI process group (pid={}, pgid={})",
-                        pid, pgid
-                    );
-                    unsafe {
-                        libc::kill(pgid, libc::SIGKILL);
-                    }
-                }
-                // Fallback / non-unix: kill the child directly
-                match child.kill().await {
-                    Ok(()) => info!("CLI process terminated via SIGKILL"),
-                    Err(e) => warn!("Failed to kill CLI process: {}", e),
-                }
-            }
+    #[tokio::test]
+    async fn test_new_uses_configured_cli_path() {
+        let path = "/nonexistent/nexus-pinned/claude";
+        let options = ClaudeCodeOptions::builder().cli_path(path).build();
+        let mut transport = SubprocessTransport::new(options).unwrap();
+        assert_eq!(transport.build_command().as_std().get_program(), path);
+
+        // The path is validated when connecting
+        match transport.connect().await {
+            Err(SdkError::CliNotFound { searched_paths }) => assert_eq!(searched_paths, path),
+            other => panic!("expected CliNotFound, got {other:?}"),
         }
+    }
 
-        self.state = TransportState::Disconnected;
-        Ok(())
+    #[test]
+    fn test_build_command_exports_request_id() {
+        let options = ClaudeCodeOptions::builder().request_id("req-42").build();
+        let transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let cmd = transport.build_command();
+
+        let request_id = cmd
+            .as_std()
+            .get_envs()
+            .find(|(key, _)| *key == REQUEST_ID_ENV_VAR)
+            .and_then(|(_, value)| value);
+        assert_eq!(request_id, Some(std::ffi::OsStr::new("req-42")));
     }
 
-    fn take_sdk_control_receiver(
-        &mut self,
-    ) -> Option<tokio::sync::mpsc::Receiver<serde_json::Value>> {
-        self.sdk_control_rx.take()
+    #[test]
+    fn test_build_command_without_request_id() {
+        let transport =
+            SubprocessTransport::with_cli_path(ClaudeCodeOptions::default(), "/usr/bin/true");
+        let cmd = transport.build_command();
+
+        assert!(
+            cmd.as_std()
+                .get_envs()
+                .all(|(key, _)| key != REQUEST_ID_ENV_VAR)
+        );
     }
 
-    fn clone_stdin_sender(&self) -> Option<tokio::sync::mpsc::Sender<String>> {
-        self.stdin_tx.clone()
+    fn env_var(cmd: &Command, key: &str) -> Option<String> {
+        cmd.as_std()
+            .get_envs()
+            .find(|(k, _)| *k == key)
+            .and_then(|(_, value)| value)
+            .map(|value| value.to_string_lossy().into_owned())
     }
 
-    async fn end_input(&mut self) -> Result<()> {
-        // Close stdin channel to signal end of input
-        self.stdin_tx.take();
-        Ok(())
+    #[test]
+    fn test_build_command_reports_sdk_identity() {
+        let transport =
+            SubprocessTransport::with_cli_path(ClaudeCodeOptions::default(), "/usr/bin/true");
+        let cmd = transport.build_command();
+        assert_eq!(
+            env_var(&cmd, "CLAUDE_CODE_ENTRYPOINT").as_deref(),
+            Some("sdk-rust")
+        );
+        assert_eq!(
+            env_var(&cmd, "CLAUDE_AGENT_SDK_VERSION").as_deref(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+
+        let options = ClaudeCodeOptions::builder()
+            .entrypoint("my-framework")
+            .sdk_version_override("2.3.0")
+            .build();
+        let transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let cmd = transport.build_command();
+        assert_eq!(
+            env_var(&cmd, "CLAUDE_CODE_ENTRYPOINT").as_deref(),
+            Some("my-framework")
+        );
+        assert_eq!(
+            env_var(&cmd, "CLAUDE_AGENT_SDK_VERSION").as_deref(),
+            Some("2.3.0")
+        );
     }
-}
 
-impl Drop for SubprocessTransport {
-    fn drop(&mut self) {
-        if let Some(mut child) = self.child.take() {
-            // Kill the entire process group to avoid orphan child processes
-            #[cfg(unix)]
-            if let Some(pid) = child.id() {
-                unsafe {
-                    libc::kill(-(pid as i32), libc::SIGKILL);
-                }
-            }
-            // Fallback: kill the child directly
-            let _ = child.start_kill();
+    #[test]
+    fn test_build_command_disable_telemetry_env() {
+        let options = ClaudeCodeOptions::builder()
+            .entrypoint("my-framework")
+            .disable_telemetry_env(true)
+            .build();
+        let transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let cmd = transport.build_command();
+        // Removed outright, so a value inherited from this process is not passed on
+        for key in ["CLAUDE_CODE_ENTRYPOINT", "CLAUDE_AGENT_SDK_VERSION"] {
+            assert!(
+                cmd.as_std()
+                    .get_envs()
+                    .any(|(k, value)| k == key && value.is_none()),
+                "{key} should be removed"
+            );
         }
+        assert_eq!(env_var(&cmd, "DISABLE_TELEMETRY").as_deref(), Some("1"));
+        assert_eq!(
+            env_var(&cmd, "DISABLE_ERROR_REPORTING").as_deref(),
+            Some("1")
+        );
     }
-}
 
-/// Find the Claude CLI binary
-///
-/// Search order:
-/// 1. System PATH (`claude`, `claude-code`)
-/// 2. SDK cache directory (auto-downloaded CLI)
-/// 3. Common installation locations
-pub fn find_claude_cli() -> Result<PathBuf> {
-    // First check if it's in PATH - try both 'claude' and 'claude-code'
-    #[cfg(unix)]
-    let cmd_names: &[&str] = &["claude", "claude-code"];
-    #[cfg(windows)]
-    let cmd_names: &[&str] = &["claude", "claude.exe", "claude-code", "claude-code.exe"];
+    fn strict_build(options: ClaudeCodeOptions) -> Result<()> {
+        let transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let mut warnings = ConfigWarnings::new(&transport.options);
+        transport.build_command_with(&mut warnings);
+        warnings.finish()
+    }
 
-    for cmd_name in cmd_names {
-        if let Ok(path) = which::which(cmd_name) {
-            debug!("Found Claude CLI in PATH at: {}", path.display());
-            return Ok(path);
+    #[test]
+    fn test_strict_mode_rejects_warned_configuration() {
+        let dir = tempfile::tempdir().unwrap();
+        let not_object = dir.path().join("settings.json");
+        std::fs::write(&not_object, "[1, 2]").unwrap();
+        let missing = dir.path().join("missing");
+        let cases = [
+            ClaudeCodeOptions::builder()
+                .settings("{not json}")
+                .sandbox(crate::types::SandboxSettings::default())
+                .build(),
+            ClaudeCodeOptions::builder()
+                .settings(not_object.display().to_string())
+                .sandbox(crate::types::SandboxSettings::default())
+                .build(),
+            ClaudeCodeOptions::builder()
+                .settings(missing.display().to_string())
+                .sandbox(crate::types::SandboxSettings::default())
+                .build(),
+            ClaudeCodeOptions::builder()
+                .tools(crate::types::ToolsConfig::Preset(
+                    crate::types::ToolsPreset::new("custom"),
+                ))
+                .build(),
+        ];
+        for options in cases {
+            assert!(strict_build(options.clone()).is_ok());
+            let strict = ClaudeCodeOptions {
+                strict_mode: true,
+                ..options
+            };
+            let err = strict_build(strict).unwrap_err();
+            assert!(matches!(err, SdkError::ConfigError(msg) if msg.starts_with("strict mode: ")));
         }
-    }
 
-    // Check SDK cache directory (for auto-downloaded CLI)
-    if let Some(cached_path) = crate::cli_download::get_cached_cli_path()
-        && cached_path.exists()
-        && cached_path.is_file()
-    {
-        debug!("Found cached Claude CLI at: {}", cached_path.display());
-        return Ok(cached_path);
+        // Clean configuration passes in strict mode; the explicit token limit
+        // keeps an ambient CLAUDE_CODE_MAX_OUTPUT_TOKENS out of the picture
+        let clean = ClaudeCodeOptions::builder()
+            .add_dir(&missing)
+            .max_output_tokens(8000)
+            .tools(crate::types::ToolsConfig::claude_code_preset())
+            .strict_mode(true)
+            .build();
+        assert!(strict_build(clean).is_ok());
     }
 
-    // Check common installation locations
-    let home = dirs::home_dir().ok_or_else(|| SdkError::CliNotFound {
-        searched_paths: "Unable to determine home directory".into(),
-    })?;
+    #[test]
+    fn test_strict_mode_rejects_bad_max_output_tokens_env() {
+        let strict = ClaudeCodeOptions::builder().strict_mode(true).build();
+        for (value, replacement) in [("lots", "8192"), ("50000", "32000")] {
+            let mut warnings = ConfigWarnings::new(&ClaudeCodeOptions::default());
+            assert_eq!(
+                sanitize_max_output_tokens(value, &mut warnings),
+                Some(replacement)
+            );
+            assert!(warnings.finish().is_ok());
 
-    #[cfg(unix)]
-    let locations = vec![
-        // npm global installations
-        home.join(".npm-global/bin/claude"),
-        home.join(".npm-global/bin/claude-code"),
-        PathBuf::from("/usr/local/bin/claude"),
-        PathBuf::from("/usr/local/bin/claude-code"),
-        // Local installations
-        home.join(".local/bin/claude"),
-        home.join(".local/bin/claude-code"),
-        home.join("node_modules/.bin/claude"),
-        home.join("node_modules/.bin/claude-code"),
-        // Yarn installations
-        home.join(".yarn/bin/claude"),
-        home.join(".yarn/bin/claude-code"),
-        // macOS specific npm location
-        PathBuf::from("/opt/homebrew/bin/claude"),
-        PathBuf::from("/opt/homebrew/bin/claude-code"),
-        // Claude local directory
-        home.join(".claude/local/claude"),
-    ];
+            let mut warnings = ConfigWarnings::new(&strict);
+            sanitize_max_output_tokens(value, &mut warnings);
+            assert!(matches!(warnings.finish(), Err(SdkError::ConfigError(_))));
+        }
 
-    #[cfg(windows)]
-    let locations = {
-        let mut locs = vec![
-            // npm global (via %APPDATA%\npm\)
-            home.join("AppData")
-                .join("Roaming")
-                .join("npm")
-                .join("claude.cmd"),
-            home.join("AppData")
-                .join("Roaming")
-                .join("npm")
-                .join("claude"),
-            // Anthropic official installer
-            home.join("AppData")
-                .join("Local")
-                .join("Programs")
-                .join("claude")
-                .join("claude.exe"),
-            // User-local compat
-            home.join(".local").join("bin").join("claude.exe"),
-            home.join(".local").join("bin").join("claude"),
-            // Yarn
-            home.join(".yarn").join("bin").join("claude.cmd"),
-            // Claude local directory
-            home.join(".claude").join("local").join("claude.exe"),
-        ];
-        // Also add via dirs:: for robustness (in case AppData is relocated)
-        if let Some(config_dir) = dirs::config_dir() {
-            locs.push(config_dir.join("npm").join("claude.cmd"));
+        let mut warnings = ConfigWarnings::new(&strict);
+        assert_eq!(sanitize_max_output_tokens("4096", &mut warnings), None);
+        assert!(warnings.finish().is_ok());
+    }
+
+    #[test]
+    fn test_strict_mode_checks_permission_prompt_tool() {
+        let mut server = crate::sdk_mcp::SdkMcpServer::new("approvals", "1.0.0");
+        server.add_tool(crate::sdk_mcp::create_simple_tool(
+            "approve",
+            "Decide on a permission request",
+            crate::sdk_mcp::ToolInputSchema {
+                schema_type: "object".into(),
+                properties: HashMap::new(),
+                required: None,
+            },
+            |_| async { Ok(String::new()) },
+        ));
+        let approvals = server.to_config();
+        let with_tool = |name: &str| {
+            ClaudeCodeOptions::builder()
+                .add_mcp_server("approvals", approvals.clone())
+                .add_mcp_server(
+                    "remote",
+                    McpServerConfig::Http {
+                        url: "https://mcp.example.com".into(),
+                        headers: None,
+                    },
+                )
+                .permission_prompt_tool_name(name)
+                .max_output_tokens(8000)
+                .strict_mode(true)
+                .build()
+        };
+
+        // Servers from .mcp.json or the CLI's settings are invisible here,
+        // so an unknown server never fails strict mode
+        for valid in [
+            "mcp__approvals__approve",
+            "mcp__remote__anything",
+            "mcp__from_settings__approve",
+            "stdio",
+        ] {
+            assert!(strict_build(with_tool(valid)).is_ok(), "{valid}");
         }
-        if let Some(local_dir) = dirs::data_local_dir() {
-            locs.push(local_dir.join("Programs").join("claude").join("claude.exe"));
+        for (invalid, reason) in [
+            ("mcp__approvals__deny", "has no tool named 'deny'"),
+            ("approve", "not an MCP tool name"),
+        ] {
+            let err = strict_build(with_tool(invalid)).unwrap_err();
+            assert!(
+                matches!(&err, SdkError::ConfigError(msg) if msg.contains(reason)),
+                "{invalid}: {err:?}"
+            );
+            // Outside strict mode the misconfiguration is only logged
+            let lenient = ClaudeCodeOptions {
+                strict_mode: false,
+                ..with_tool(invalid)
+            };
+            assert!(strict_build(lenient).is_ok());
         }
-        locs
-    };
+    }
 
-    let mut searched = Vec::new();
-    for path in &locations {
-        searched.push(path.display().to_string());
-        if path.exists() && path.is_file() {
-            debug!("Found Claude CLI at: {}", path.display());
-            return Ok(path.clone());
-        }
+    #[tokio::test]
+    async fn test_strict_mode_fails_connect() {
+        let options = ClaudeCodeOptions::builder()
+            .tools(crate::types::ToolsConfig::Preset(
+                crate::types::ToolsPreset::new("custom"),
+            ))
+            .strict_mode(true)
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let err = transport.connect().await.unwrap_err();
+        assert!(matches!(err, SdkError::ConfigError(msg) if msg.contains("tools preset")));
+        assert!(!transport.is_connected());
     }
 
-    // Log detailed error information
-    warn!("Claude CLI not found in any standard location");
-    warn!("Searched paths: {:?}", searched);
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_strict_mode_accepts_old_cli() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("claude");
+        std::fs::write(&path, "#!/bin/sh\necho \"1.0.0\"\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        // Only a warning: strict mode is about the configuration, not the CLI.
+        // The explicit token limit keeps an ambient env var out of the picture
+        let options = ClaudeCodeOptions::builder()
+            .max_output_tokens(8000)
+            .strict_mode(true)
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, &path);
+        transport.connect().await.unwrap();
+        assert_eq!(transport.cli_version(), Some(SemVer::new(1, 0, 0)));
+        transport.disconnect().await.unwrap();
+    }
 
-    // Check if Node.js is installed
-    if which::which("node").is_err() && which::which("npm").is_err() {
-        error!("Node.js/npm not found - Claude CLI requires Node.js");
-        return Err(SdkError::CliNotFound {
-            searched_paths: format!(
-                "Node.js is not installed. Install from https://nodejs.org/\n\n\
-                Alternatively, enable auto_download_cli to automatically download the CLI:\n\
-                ```rust\n\
-                let options = ClaudeCodeOptions::builder()\n\
-                    .auto_download_cli(true)\n\
-                    .build();\n\
-                ```\n\n\
-                Searched in:\n{}",
-                searched.join("\n")
-            ),
-        });
+    fn mcp_config_arg(options: ClaudeCodeOptions) -> serde_json::Value {
+        let args = command_args(options);
+        serde_json::from_str(flag_value(&args, "--mcp-config").unwrap()).unwrap()
     }
 
-    Err(SdkError::CliNotFound {
-        searched_paths: format!(
-            "Claude CLI not found.\n\n\
-            Option 1 - Auto-download (recommended):\n\
-            ```rust\n\
-            let options = ClaudeCodeOptions::builder()\n\
-                .auto_download_cli(true)\n\
-                .build();\n\
-            ```\n\n\
-            Option 2 - Manual installation:\n\
-            npm install -g @anthropic-ai/claude-code\n\n\
-            Searched in:\n{}",
-            searched.join("\n")
-        ),
-    })
-}
+    #[test]
+    fn test_build_command_adds_mcp_user_agent() {
+        let options = ClaudeCodeOptions::builder()
+            .add_mcp_server(
+                "http",
+                McpServerConfig::Http {
+                    url: "https://http.example.com".into(),
+                    headers: None,
+                },
+            )
+            .add_mcp_server(
+                "sse",
+                McpServerConfig::Sse {
+                    url: "https://sse.example.com".into(),
+                    headers: Some(HashMap::from([("user-agent".into(), "my-app/1.0".into())])),
+                },
+            )
+            .add_mcp_server(
+                "stdio",
+                McpServerConfig::Stdio {
+                    command: "server".into(),
+                    args: None,
+                    env: None,
+                },
+            )
+            .build();
+        let config = mcp_config_arg(options);
+        let servers = &config["mcpServers"];
+
+        assert_eq!(servers["http"]["headers"]["User-Agent"], MCP_USER_AGENT);
+        assert!(MCP_USER_AGENT.starts_with("nexus-claude/"));
+        // A user-supplied header wins, whatever its casing
+        let sse_headers = servers["sse"]["headers"].as_object().unwrap();
+        assert_eq!(sse_headers.len(), 1);
+        assert_eq!(sse_headers["user-agent"], "my-app/1.0");
+        assert!(servers["stdio"].get("headers").is_none());
+    }
 
-pub(crate) fn apply_process_user(cmd: &mut Command, user: &str) -> Result<()> {
-    let user = user.trim();
-    if user.is_empty() {
-        return Err(SdkError::ConfigError(
-            "options.user must be a non-empty username or uid".into(),
+    #[test]
+    fn test_mcp_config_checks_env_vars_but_keeps_placeholders() {
+        // Cargo sets CARGO_PKG_NAME for the test process; the token only
+        // exists in the CLI's environment
+        let options = ClaudeCodeOptions::builder()
+            .add_mcp_server(
+                "api",
+                McpServerConfig::Http {
+                    url: "https://${CARGO_PKG_NAME}.example.com/mcp".into(),
+                    headers: Some(HashMap::from([
+                        ("Authorization".into(), "Bearer ${NEXUS_MCP_TOKEN}".into()),
+                        ("X-Literal".into(), "$HOME ${not a var} ${".into()),
+                    ])),
+                },
+            )
+            .env("NEXUS_MCP_TOKEN", "secret-token")
+            .build();
+        let args = command_args(options);
+        assert!(!args.iter().any(|arg| arg.contains("secret-token")));
+        let config: serde_json::Value =
+            serde_json::from_str(flag_value(&args, "--mcp-config").unwrap()).unwrap();
+        let server = &config["mcpServers"]["api"];
+        assert_eq!(server["url"], "https://${CARGO_PKG_NAME}.example.com/mcp");
+        assert_eq!(
+            server["headers"]["Authorization"],
+            "Bearer ${NEXUS_MCP_TOKEN}"
+        );
+        assert_eq!(server["headers"]["X-Literal"], "$HOME ${not a var} ${");
+
+        let options = ClaudeCodeOptions::builder()
+            .add_mcp_server(
+                "api",
+                McpServerConfig::Sse {
+                    url: "https://example.com".into(),
+                    headers: Some(HashMap::from([(
+                        "Authorization".into(),
+                        "Bearer ${NEXUS_TEST_UNSET_MCP_TOKEN}".into(),
+                    )])),
+                },
+            )
+            .build();
+        let transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let err = transport.prepare_command().unwrap_err();
+        assert!(matches!(
+            err,
+            SdkError::ConfigError(ref msg) if msg.contains("NEXUS_TEST_UNSET_MCP_TOKEN")
+                && msg.contains("headers.Authorization")
+                && msg.contains("\"api\"")
         ));
     }
 
-    apply_process_user_inner(cmd, user)
-}
+    fn input_format_arg(options: ClaudeCodeOptions) -> String {
+        let args = command_args(options);
+        flag_value(&args, "--input-format").unwrap().to_string()
+    }
 
-#[cfg(unix)]
-fn apply_process_user_inner(cmd: &mut Command, user: &str) -> Result<()> {
-    use std::ffi::CString;
-    use std::mem::MaybeUninit;
-    use std::os::unix::process::CommandExt;
-    use std::ptr;
+    #[test]
+    fn test_build_command_input_format() {
+        assert_eq!(
+            input_format_arg(ClaudeCodeOptions::default()),
+            "stream-json"
+        );
+        let text = ClaudeCodeOptions::builder()
+            .input_format(InputFormat::Text)
+            .build();
+        assert_eq!(input_format_arg(text), "text");
+    }
 
-    fn passwd_buf_len() -> usize {
-        let buf_len = unsafe { libc::sysconf(libc::_SC_GETPW_R_SIZE_MAX) };
-        if buf_len <= 0 {
-            16 * 1024
-        } else {
-            buf_len as usize
-        }
+    #[test]
+    fn test_build_command_tools_add_and_remove() {
+        let args = command_args(
+            ClaudeCodeOptions::builder()
+                .tools(
+                    crate::types::ToolsConfig::list(vec!["Read".into(), "Bash".into()])
+                        .remove_tool("Bash")
+                        .add_tool("Edit"),
+                )
+                .build(),
+        );
+        assert_eq!(flag_value(&args, "--tools"), Some("Read,Edit"));
+        assert_eq!(flag_value(&args, "--disallowedTools"), None);
+
+        let args = command_args(
+            ClaudeCodeOptions::builder()
+                .tools(crate::types::ToolsConfig::claude_code_preset().remove_tool("Bash"))
+                .disallowed_tools(vec!["WebFetch".into(), "Bash".into()])
+                .build(),
+        );
+        assert_eq!(flag_value(&args, "--tools"), Some("default"));
+        assert_eq!(
+            flag_value(&args, "--disallowedTools"),
+            Some("WebFetch,Bash")
+        );
     }
 
-    fn lookup_by_name(name: &str) -> Result<(u32, u32)> {
-        let name = CString::new(name)
-            .map_err(|_| SdkError::ConfigError("options.user must not contain NUL bytes".into()))?;
+    #[test]
+    fn test_build_command_extra_args_single_and_multi() {
+        let options = ClaudeCodeOptions::builder()
+            .add_extra_arg("single", Some("a b; $(c)".into()))
+            .add_extra_arg_multi("--multi", vec!["x y".into(), "z".into()])
+            .build();
+        let args = command_args(options);
+
+        // A single value stays one argv entry, however it is spelled
+        let i = args.iter().position(|arg| arg == "--single").unwrap();
+        assert_eq!(args[i + 1], "a b; $(c)");
+        assert_eq!(args.iter().filter(|arg| *arg == "--single").count(), 1);
+
+        // Multiple values follow one flag, each as its own entry
+        let i = args.iter().position(|arg| arg == "--multi").unwrap();
+        assert_eq!(args[i + 1..i + 3], ["x y", "z"]);
+        assert_eq!(args.iter().filter(|arg| *arg == "--multi").count(), 1);
+    }
 
-        let mut pwd = MaybeUninit::<libc::passwd>::zeroed();
-        let mut result: *mut libc::passwd = ptr::null_mut();
-        let mut buf = vec![0u8; passwd_buf_len()];
+    #[test]
+    fn test_build_command_verbose_toggle() {
+        let args = command_args(ClaudeCodeOptions::default());
+        assert_eq!(args.iter().filter(|arg| *arg == "--verbose").count(), 1);
 
-        let rc = unsafe {
-            libc::getpwnam_r(
-                name.as_ptr(),
-                pwd.as_mut_ptr(),
-                buf.as_mut_ptr() as *mut libc::c_char,
-                buf.len(),
-                &mut result,
+        let args = command_args(ClaudeCodeOptions::builder().verbose(false).build());
+        assert!(!args.iter().any(|arg| arg == "--verbose"));
+        assert_eq!(flag_value(&args, "--output-format"), Some("stream-json"));
+    }
+
+    #[test]
+    fn test_options_snapshot_round_trip_reproduces_command() {
+        use crate::types::{ClaudeCodeOptionsSnapshot, SandboxSettings, SdkBeta, SettingSource};
+
+        let options = ClaudeCodeOptions::builder()
+            .system_prompt("You review Rust code.")
+            .allowed_tools(vec!["Read".into(), "Grep".into()])
+            .disallowed_tools(vec!["Bash".into()])
+            .permission_mode(PermissionMode::AcceptEdits)
+            .add_mcp_server(
+                "stdio",
+                McpServerConfig::Stdio {
+                    command: "server".into(),
+                    args: Some(vec!["--port".into(), "1".into()]),
+                    env: None,
+                },
             )
+            .max_turns(4)
+            .max_thinking_tokens(2048)
+            .model("sonnet")
+            .fallback_model("haiku")
+            .resume("session-1")
+            .add_dir("/tmp/extra")
+            .add_extra_arg("single", Some("value".into()))
+            .setting_sources(vec![SettingSource::Project])
+            .fork_session(true)
+            .add_beta(SdkBeta::Context1M)
+            .max_budget_usd(2.5)
+            .sandbox(SandboxSettings {
+                enabled: Some(true),
+                ..Default::default()
+            })
+            .include_partial_messages(true)
+            .output_format(serde_json::json!({
+                "type": "json_schema",
+                "schema": {"type": "object", "properties": {"verdict": {"type": "string"}}},
+            }))
+            .build();
+        let expected = command_args(options.clone());
+
+        let json = serde_json::to_string(&ClaudeCodeOptionsSnapshot::from(&options)).unwrap();
+        let snapshot: ClaudeCodeOptionsSnapshot = serde_json::from_str(&json).unwrap();
+        let restored = ClaudeCodeOptions::builder()
+            .apply_snapshot(snapshot)
+            .build();
+
+        assert_eq!(command_args(restored), expected);
+        assert_eq!(flag_value(&expected, "--model"), Some("sonnet"));
+        assert_eq!(flag_value(&expected, "--max-budget-usd"), Some("2.5"));
+        let mcp: serde_json::Value =
+            serde_json::from_str(flag_value(&expected, "--mcp-config").unwrap()).unwrap();
+        assert_eq!(mcp["mcpServers"]["stdio"]["command"], "server");
+        let schema: serde_json::Value =
+            serde_json::from_str(flag_value(&expected, "--json-schema").unwrap()).unwrap();
+        assert_eq!(schema["properties"]["verdict"]["type"], "string");
+    }
+
+    #[test]
+    fn test_system_prompt_file_read_into_argv() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prompt.md");
+        std::fs::write(&path, "You review Rust code.\nBe strict.").unwrap();
+
+        let options = ClaudeCodeOptions {
+            system_prompt_v2: Some(crate::types::SystemPrompt::File {
+                path,
+                append: Some("Reply in French.".into()),
+            }),
+            ..Default::default()
         };
-        if rc != 0 {
-            return Err(SdkError::ConfigError(format!(
-                "Failed to resolve options.user={}: getpwnam_r returned {}",
-                name.to_string_lossy(),
-                rc
-            )));
-        }
-        if result.is_null() {
-            return Err(SdkError::ConfigError(format!(
-                "User not found: {}",
-                name.to_string_lossy()
-            )));
-        }
+        let args = command_args(options);
+        assert_eq!(
+            args.iter().filter(|arg| *arg == "--system-prompt").count(),
+            1
+        );
+        assert_eq!(
+            flag_value(&args, "--system-prompt"),
+            Some("You review Rust code.\nBe strict.")
+        );
+        assert_eq!(
+            flag_value(&args, "--append-system-prompt"),
+            Some("Reply in French.")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_rejects_missing_system_prompt_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.md");
+        let options = ClaudeCodeOptions::builder()
+            .system_prompt_file(&missing)
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let err = transport.connect().await.unwrap_err();
+        assert!(
+            matches!(err, SdkError::ConfigError(ref msg) if msg.contains(&*missing.to_string_lossy())),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_rejects_partial_messages_with_text_input() {
+        let options = ClaudeCodeOptions::builder()
+            .input_format(InputFormat::Text)
+            .include_partial_messages(true)
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let err = transport.connect().await.unwrap_err();
+        assert!(
+            matches!(err, SdkError::ConfigError(msg) if msg.contains("include_partial_messages"))
+        );
+    }
 
-        let pwd = unsafe { pwd.assume_init() };
-        #[allow(clippy::unnecessary_cast)]
-        Ok((pwd.pw_uid as u32, pwd.pw_gid as u32))
+    #[tokio::test]
+    async fn test_connect_rejects_missing_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("gone");
+        let options = ClaudeCodeOptions::builder().cwd(&missing).build();
+        let mut transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let err = transport.connect().await.unwrap_err();
+        assert!(
+            matches!(err, SdkError::ConfigError(ref msg) if msg.contains(&*missing.to_string_lossy())),
+            "unexpected error: {err:?}"
+        );
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        let options = ClaudeCodeOptions::builder().cwd(&file).build();
+        let mut transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let err = transport.connect().await.unwrap_err();
+        assert!(matches!(err, SdkError::ConfigError(msg) if msg.contains("not a directory")));
+
+        // A relative cwd is resolved against the current directory
+        let options = ClaudeCodeOptions::builder().cwd("src").build();
+        let transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        transport.validate_options().unwrap();
+        let cmd = transport.build_command();
+        assert_eq!(
+            cmd.as_std().get_current_dir(),
+            Some(std::env::current_dir().unwrap().join("src").as_path())
+        );
     }
 
-    fn lookup_by_uid(uid: u32) -> Result<(u32, u32)> {
-        let mut pwd = MaybeUninit::<libc::passwd>::zeroed();
-        let mut result: *mut libc::passwd = ptr::null_mut();
-        let mut buf = vec![0u8; passwd_buf_len()];
+    #[tokio::test]
+    async fn test_connect_rejects_fork_session_without_resume() {
+        let options = ClaudeCodeOptions::builder().fork_session(true).build();
+        let mut transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let err = transport.connect().await.unwrap_err();
+        assert!(matches!(err, SdkError::ConfigError(msg) if msg.contains("fork_session")));
+
+        let resumed = ClaudeCodeOptions::builder()
+            .fork_session(true)
+            .resume("session-123")
+            .build();
+        SubprocessTransport::with_cli_path(resumed, "/usr/bin/true")
+            .validate_options()
+            .unwrap();
+        let continued = ClaudeCodeOptions::builder()
+            .fork_session(true)
+            .continue_conversation(true)
+            .build();
+        SubprocessTransport::with_cli_path(continued, "/usr/bin/true")
+            .validate_options()
+            .unwrap();
+    }
 
-        let rc = unsafe {
-            libc::getpwuid_r(
-                uid as libc::uid_t,
-                pwd.as_mut_ptr(),
-                buf.as_mut_ptr() as *mut libc::c_char,
-                buf.len(),
-                &mut result,
-            )
+    #[test]
+    fn test_text_input_sends_plain_prompt() {
+        let options = ClaudeCodeOptions::builder()
+            .input_format(InputFormat::Text)
+            .build();
+        let transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let prompt = InputMessage::user("What is 2 + 2?".into(), "default".into());
+        assert_eq!(transport.encode_input(&prompt).unwrap(), "What is 2 + 2?");
+
+        let tool_result =
+            InputMessage::tool_result("toolu_1".into(), "4".into(), "default".into(), false);
+        assert!(transport.encode_input(&tool_result).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_text_input_rejects_batches() {
+        let options = ClaudeCodeOptions::builder()
+            .input_format(InputFormat::Text)
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        transport.state.set(TransportState::Connected);
+        let prompts = vec![
+            InputMessage::user("first\nline".into(), "default".into()),
+            InputMessage::user("second".into(), "default".into()),
+        ];
+        let err = transport.send_messages(prompts).await.unwrap_err();
+        assert!(
+            matches!(err, SdkError::InvalidState { message } if message.contains("StreamJson"))
+        );
+    }
+
+    fn system_prompt_args(options: ClaudeCodeOptions) -> Vec<String> {
+        let args = command_args(options);
+        args.iter()
+            .position(|arg| arg == "--system-prompt")
+            .map(|i| args[i..=i + 1].to_vec())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_build_command_blanks_system_prompt_by_default() {
+        assert_eq!(
+            system_prompt_args(ClaudeCodeOptions::default()),
+            vec!["--system-prompt", ""]
+        );
+    }
+
+    #[test]
+    fn test_build_command_inherits_cli_system_prompt() {
+        let options = ClaudeCodeOptions::builder()
+            .inherit_cli_system_prompt(true)
+            .build();
+        assert!(system_prompt_args(options).is_empty());
+
+        // An explicit prompt still wins
+        let options = ClaudeCodeOptions::builder()
+            .inherit_cli_system_prompt(true)
+            .system_prompt("Be terse")
+            .build();
+        assert_eq!(
+            system_prompt_args(options),
+            vec!["--system-prompt", "Be terse"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_memory_limit_makes_child_fail_to_allocate() {
+        // Builds a ~64 MiB shell variable, which needs well over the 32 MiB cap
+        let script = "echo started; x=$(head -c 67108864 /dev/zero | tr '\\0' a); echo ${#x}";
+        let run = |limits: Option<ResourceLimits>| async move {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c")
+                .arg(script)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null());
+            if let Some(limits) = limits {
+                apply_resource_limits(&mut cmd, &limits).unwrap();
+            }
+            cmd.output().await
         };
-        if rc != 0 {
-            return Err(SdkError::ConfigError(format!(
-                "Failed to resolve options.user={}: getpwuid_r returned {}",
-                uid, rc
-            )));
-        }
-        if result.is_null() {
-            return Err(SdkError::ConfigError(format!(
-                "User not found for uid: {}",
-                uid
-            )));
-        }
 
-        let pwd = unsafe { pwd.assume_init() };
-        #[allow(clippy::unnecessary_cast)]
-        Ok((pwd.pw_uid as u32, pwd.pw_gid as u32))
+        let unlimited = run(None).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&unlimited.stdout)
+                .lines()
+                .collect::<Vec<_>>(),
+            ["started", "67108864"]
+        );
+
+        // The shell must start under the cap, so the failure is the allocation
+        let limited = run(Some(
+            ResourceLimits::default().max_memory_bytes(32 * 1024 * 1024),
+        ))
+        .await
+        .expect("the limited child should spawn");
+        let stdout = String::from_utf8_lossy(&limited.stdout);
+        assert_eq!(stdout.lines().next(), Some("started"));
+        assert!(!stdout.contains("67108864"));
     }
 
-    let (uid, gid) = match user.parse::<u32>() {
-        Ok(uid) => lookup_by_uid(uid)?,
-        Err(_) => lookup_by_name(user)?,
-    };
+    fn output_format_args(format: serde_json::Value) -> Result<Vec<String>> {
+        let mut cmd = Command::new("claude");
+        apply_output_format(&mut cmd, &format)?;
+        Ok(cmd_args(&cmd))
+    }
 
-    cmd.as_std_mut().uid(uid).gid(gid);
-    Ok(())
-}
+    #[test]
+    fn test_output_format_text_adds_no_flags() {
+        let args = output_format_args(serde_json::json!({"type": "text"})).unwrap();
+        assert!(args.is_empty());
+    }
 
-#[cfg(not(unix))]
-fn apply_process_user_inner(_cmd: &mut Command, _user: &str) -> Result<()> {
-    Err(SdkError::NotSupported {
-        feature: "options.user is only supported on Unix platforms".into(),
-    })
-}
+    #[test]
+    fn test_output_format_json_without_schema_is_rejected() {
+        let err = output_format_args(serde_json::json!({"type": "json"})).unwrap_err();
+        assert!(matches!(err, SdkError::ConfigError(msg) if msg.contains("json_schema")));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_output_format_json_schema_passes_schema() {
+        let args = output_format_args(serde_json::json!({
+            "type": "json_schema",
+            "schema": {"type": "object", "properties": {"answer": {"type": "string"}}}
+        }))
+        .unwrap();
+        assert_eq!(args[0], "--json-schema");
+        let schema: serde_json::Value = serde_json::from_str(&args[1]).unwrap();
+        assert_eq!(schema["properties"]["answer"]["type"], "string");
+    }
 
     #[test]
-    fn test_find_claude_cli_error_message() {
-        // Test error message format without relying on CLI not being found
-        let error = SdkError::CliNotFound {
-            searched_paths: "test paths".to_string(),
-        };
-        let error_msg = error.to_string();
-        assert!(error_msg.contains("npm install -g @anthropic-ai/claude-code"));
-        assert!(error_msg.contains("test paths"));
+    fn test_output_format_json_schema_requires_schema() {
+        let err = output_format_args(serde_json::json!({"type": "json_schema"})).unwrap_err();
+        assert!(matches!(err, SdkError::ConfigError(msg) if msg.contains("schema")));
+    }
+
+    #[test]
+    fn test_output_format_rejects_unknown_type() {
+        let err = output_format_args(serde_json::json!({"type": "yaml"})).unwrap_err();
+        assert!(matches!(err, SdkError::ConfigError(msg) if msg.contains("yaml")));
+
+        let err = output_format_args(serde_json::json!({"schema": {}})).unwrap_err();
+        assert!(matches!(err, SdkError::ConfigError(_)));
     }
 
     #[tokio::test]
-    async fn test_transport_lifecycle() {
-        let options = ClaudeCodeOptions::default();
-        let transport = SubprocessTransport::new(options).unwrap_or_else(|_| {
-            // Use a dummy path for testing
-            SubprocessTransport::with_cli_path(ClaudeCodeOptions::default(), "/usr/bin/true")
-        });
+    async fn test_connect_rejects_unknown_output_format() {
+        let options = ClaudeCodeOptions::builder()
+            .output_format(serde_json::json!({"type": "yaml"}))
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, "/usr/bin/true");
+        let err = transport.connect().await.unwrap_err();
+        assert!(matches!(err, SdkError::ConfigError(_)));
+    }
 
-        assert!(!transport.is_connected());
-        assert_eq!(transport.state, TransportState::Disconnected);
+    #[test]
+    fn test_empty_resource_limits_are_a_no_op() {
+        let mut cmd = Command::new("true");
+        assert!(apply_resource_limits(&mut cmd, &ResourceLimits::default()).is_ok());
     }
 
     #[test]
@@ -1631,4 +3959,641 @@ mod tests {
         let result = get_cli_version(std::path::Path::new("/nonexistent/binary/claude")).await;
         assert!(result.is_none(), "Nonexistent binary should return None");
     }
+
+    /// Fake CLI that emits `count` numbered assistant messages and exits
+    #[cfg(unix)]
+    fn chatty_cli(dir: &Path, count: usize) -> PathBuf {
+        let script = format!(
+            r#"i=0
+while [ $i -lt {count} ]; do
+  echo '{{"type":"assistant","message":{{"content":[{{"type":"text","text":"'$i'"}}]}}}}'
+  i=$((i + 1))
+done
+"#
+        );
+        fake_cli(dir, &script)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_single_consumer_queue_is_lossless_for_slow_consumer() {
+        use crate::types::ContentBlock;
+        use std::time::Duration;
+
+        const COUNT: usize = 200;
+        let dir = tempfile::tempdir().unwrap();
+        let options = ClaudeCodeOptions::builder()
+            .cli_channel_buffer_size(8)
+            .build();
+        let mut transport =
+            SubprocessTransport::with_cli_path(options, chatty_cli(dir.path(), COUNT));
+        transport.connect().await.unwrap();
+        assert!(transport.subscribe_messages().is_none());
+
+        let mut received = Vec::new();
+        while received.len() < COUNT {
+            // A fresh stream per message, like InteractiveClient does between turns
+            let next =
+                tokio::time::timeout(Duration::from_secs(10), transport.receive_messages().next())
+                    .await
+                    .expect("timed out waiting for a message");
+            let Some(Ok(Message::Assistant { message, .. })) = next else {
+                panic!("unexpected item: {next:?}");
+            };
+            let ContentBlock::Text(text) = &message.content[0] else {
+                panic!("unexpected content: {:?}", message.content);
+            };
+            received.push(text.text.parse::<usize>().unwrap());
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        assert_eq!(received, (0..COUNT).collect::<Vec<_>>());
+        transport.disconnect().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_control_response_is_routed_while_message_queue_is_full() {
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"i=0
+while [ $i -lt 50 ]; do
+  echo '{"type":"assistant","message":{"content":[{"type":"text","text":"'$i'"}]}}'
+  i=$((i + 1))
+done
+echo '{"type":"control_response","response":{"subtype":"success","request_id":"req_1"}}'
+sleep 5
+"#;
+        let options = ClaudeCodeOptions::builder()
+            .cli_channel_buffer_size(4)
+            .build();
+        let mut transport =
+            SubprocessTransport::with_cli_path(options, fake_cli(dir.path(), script));
+        transport.connect().await.unwrap();
+
+        // Nobody reads the messages, yet the response behind them arrives
+        let response = tokio::time::timeout(
+            Duration::from_secs(5),
+            transport.receive_sdk_control_request(),
+        )
+        .await
+        .expect("control response blocked behind queued messages")
+        .unwrap();
+        assert_eq!(response["response"]["request_id"], "req_1");
+
+        let first = transport.receive_messages().next().await;
+        assert!(matches!(first, Some(Ok(Message::Assistant { .. }))));
+        transport.disconnect().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_dedup_messages_drops_repeated_lines() {
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let line = |uuid: &str, text: &str| {
+            format!(
+                r#"echo '{{"type":"assistant","uuid":"{uuid}","message":{{"content":[{{"type":"text","text":"{text}"}}]}}}}'"#
+            )
+        };
+        let script = [
+            line("u1", "first"),
+            line("u1", "first"),
+            line("u2", "first"),
+        ]
+        .join("\n");
+        let path = fake_cli(dir.path(), &script);
+
+        for (dedup, expected) in [(false, 3), (true, 2)] {
+            let options = ClaudeCodeOptions::builder().dedup_messages(dedup).build();
+            let mut transport = SubprocessTransport::with_cli_path(options, &path);
+            transport.connect().await.unwrap();
+            let messages: Vec<_> = tokio::time::timeout(
+                Duration::from_secs(10),
+                transport.receive_messages().collect::<Vec<_>>(),
+            )
+            .await
+            .unwrap();
+            // The re-sent line is dropped; a same-content message with its own
+            // ID is still delivered
+            assert_eq!(messages.len(), expected, "dedup_messages({dedup})");
+            transport.disconnect().await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_recent_lines_forgets_beyond_window() {
+        let mut recent = RecentLines::default();
+        assert!(recent.insert("a"));
+        assert!(!recent.insert("a"));
+        for i in 0..DEDUP_WINDOW {
+            assert!(recent.insert(&i.to_string()));
+        }
+        assert!(recent.insert("a"));
+    }
+
+    /// Fake CLI that records everything written to its stdin
+    #[cfg(unix)]
+    fn recording_cli(dir: &Path) -> PathBuf {
+        fake_cli(
+            dir,
+            r#"exec cat > "$(dirname "$0")/stdin.log"
+"#,
+        )
+    }
+
+    /// Run `send` against a recording CLI and return the stdin it received
+    #[cfg(unix)]
+    async fn recorded_stdin<F>(expected_lines: usize, send: F) -> String
+    where
+        F: AsyncFnOnce(&mut SubprocessTransport),
+    {
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut transport = SubprocessTransport::with_cli_path(
+            ClaudeCodeOptions::default(),
+            recording_cli(dir.path()),
+        );
+        transport.connect().await.unwrap();
+        send(&mut transport).await;
+
+        let log = dir.path().join("stdin.log");
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        let recorded = loop {
+            let recorded = std::fs::read_to_string(&log).unwrap_or_default();
+            if recorded.lines().count() >= expected_lines || tokio::time::Instant::now() > deadline
+            {
+                break recorded;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        transport.disconnect().await.unwrap();
+        recorded
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_send_messages_matches_individual_sends() {
+        let messages: Vec<InputMessage> = (0..20)
+            .map(|i| InputMessage::user(format!("line {i}\nwith newline"), "s".into()))
+            .collect();
+
+        let individual = recorded_stdin(messages.len(), {
+            let messages = messages.clone();
+            async move |transport: &mut SubprocessTransport| {
+                for message in messages {
+                    transport.send_message(message).await.unwrap();
+                }
+            }
+        })
+        .await;
+        let batched = recorded_stdin(messages.len(), {
+            let messages = messages.clone();
+            async move |transport: &mut SubprocessTransport| {
+                transport.send_messages(messages).await.unwrap();
+            }
+        })
+        .await;
+
+        assert_eq!(individual.lines().count(), messages.len());
+        assert_eq!(batched, individual);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_disconnect_is_bounded_by_timeout() {
+        use std::time::{Duration, Instant};
+
+        // Fake CLI ignoring SIGINT and SIGTERM, so only SIGKILL stops it
+        let dir = tempfile::tempdir().unwrap();
+        let cli = fake_cli(
+            dir.path(),
+            r#"trap '' INT TERM
+touch "$(dirname "$0")/ready"
+while :; do sleep 1; done
+"#,
+        );
+
+        let options = ClaudeCodeOptions::builder()
+            .disconnect_timeout(Duration::from_millis(100))
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, cli);
+        transport.connect().await.unwrap();
+        let pid = transport.child_pid().unwrap();
+
+        let ready = dir.path().join("ready");
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !ready.exists() && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // The signal escalation alone takes 700ms before SIGKILL
+        let start = Instant::now();
+        transport.disconnect().await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(!transport.is_connected());
+
+        // The abandoned process is still killed
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while unsafe { libc::kill(pid as i32, 0) } == 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_ne!(unsafe { libc::kill(pid as i32, 0) }, 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_disconnect_graceful_lets_cli_exit_on_eof() {
+        use std::time::{Duration, Instant};
+
+        // Fake CLI ignoring signals, logging its input and exiting on EOF
+        let dir = tempfile::tempdir().unwrap();
+        let cli = fake_cli(
+            dir.path(),
+            r#"trap '' INT TERM
+dir="$(dirname "$0")"
+touch "$dir/ready"
+while IFS= read -r line; do echo "$line" >> "$dir/input.log"; done
+touch "$dir/flushed"
+"#,
+        );
+
+        let options = ClaudeCodeOptions::builder()
+            .disconnect_timeout(Duration::from_millis(100))
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, cli);
+        transport.connect().await.unwrap();
+
+        let ready = dir.path().join("ready");
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !ready.exists() && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        transport
+            .disconnect_graceful(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(!transport.is_connected());
+
+        // The CLI saw the interrupt, then ran to completion instead of being killed
+        assert!(dir.path().join("flushed").exists());
+        let input = std::fs::read_to_string(dir.path().join("input.log")).unwrap();
+        let request: serde_json::Value = serde_json::from_str(input.trim()).unwrap();
+        assert_eq!(request["request"]["type"], "interrupt");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_log_sink_receives_diagnostic_events() {
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cli = fake_cli(
+            dir.path(),
+            r#"echo 'not json'
+echo '{"type":"result","subtype":"success","duration_ms":10,"duration_api_ms":8,"is_error":false,"num_turns":1,"session_id":"s1","total_cost_usd":0.25}'
+exec cat > /dev/null
+"#,
+        );
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let captured = events.clone();
+        let options = ClaudeCodeOptions::builder()
+            .log_sink(Arc::new(move |event: &DiagnosticEvent| {
+                captured.lock().unwrap().push(event.clone());
+            }))
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, &cli);
+        transport.connect().await.unwrap();
+
+        // The result is emitted after the parse error, so wait for it
+        let next =
+            tokio::time::timeout(Duration::from_secs(10), transport.receive_messages().next())
+                .await
+                .unwrap();
+        assert!(matches!(next, Some(Ok(Message::Result { .. }))));
+        transport
+            .send_control_request(ControlRequest::Interrupt {
+                request_id: "int-1".into(),
+            })
+            .await
+            .unwrap();
+        transport.disconnect().await.unwrap();
+
+        let events = events.lock().unwrap();
+        assert!(matches!(
+            &events[0],
+            DiagnosticEvent::Spawned { cli_path, pid: Some(_) } if *cli_path == cli
+        ));
+        // Output may be parsed before connect() returns, so only check presence
+        assert!(events.contains(&DiagnosticEvent::Connected));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            DiagnosticEvent::ParseError { line, .. } if line == "not json"
+        )));
+        assert!(events.contains(&DiagnosticEvent::Cost {
+            session_id: "s1".into(),
+            total_cost_usd: Some(0.25),
+            duration_ms: 10,
+        }));
+        assert_eq!(
+            events.last(),
+            Some(&DiagnosticEvent::InterruptSent {
+                request_id: "int-1".into()
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_log_sink_reports_failed_mcp_server() {
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cli = fake_cli(
+            dir.path(),
+            r#"echo '{"type":"system","subtype":"init","session_id":"s1","mcp_servers":[{"name":"db","status":"failed","error":"connection refused"}]}'
+exec cat > /dev/null
+"#,
+        );
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let captured = events.clone();
+        let options = ClaudeCodeOptions::builder()
+            .log_sink(Arc::new(move |event: &DiagnosticEvent| {
+                captured.lock().unwrap().push(event.clone());
+            }))
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, &cli);
+        transport.connect().await.unwrap();
+
+        // The failure is a warning: the init message is still delivered
+        let next =
+            tokio::time::timeout(Duration::from_secs(10), transport.receive_messages().next())
+                .await
+                .unwrap();
+        assert!(matches!(next, Some(Ok(Message::System { ref subtype, .. })) if subtype == "init"));
+        transport.disconnect().await.unwrap();
+
+        assert!(
+            events
+                .lock()
+                .unwrap()
+                .contains(&DiagnosticEvent::McpServerFailed {
+                    name: "db".into(),
+                    detail: "connection refused".into(),
+                })
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_suppress_stderr_errors_drops_synthetic_error_message() {
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cli = fake_cli(
+            dir.path(),
+            r#"echo "Error: something went sideways" >&2
+echo '{"type":"system","subtype":"init","session_id":"s1"}'
+"#,
+        );
+
+        for suppress in [false, true] {
+            let options = ClaudeCodeOptions::builder()
+                .suppress_stderr_errors(suppress)
+                .build();
+            let mut transport = SubprocessTransport::with_cli_path(options, &cli);
+            transport.connect().await.unwrap();
+            let subtypes: Vec<String> = tokio::time::timeout(
+                Duration::from_secs(10),
+                transport
+                    .receive_messages()
+                    .filter_map(|msg| async move {
+                        match msg {
+                            Ok(Message::System { subtype, .. }) => Some(subtype),
+                            _ => None,
+                        }
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+            transport.disconnect().await.unwrap();
+
+            assert!(subtypes.contains(&"init".to_string()));
+            assert_eq!(subtypes.contains(&"error".to_string()), !suppress);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_send_fails_after_cli_closes_stdin() {
+        use std::time::Duration;
+
+        // The CLI exits right away, closing its end of the stdin pipe
+        let dir = tempfile::tempdir().unwrap();
+        let mut transport = SubprocessTransport::with_cli_path(
+            ClaudeCodeOptions::default(),
+            chatty_cli(dir.path(), 0),
+        );
+        transport.connect().await.unwrap();
+        // The message stream ends once the process has closed its output
+        let end =
+            tokio::time::timeout(Duration::from_secs(10), transport.receive_messages().next())
+                .await
+                .unwrap();
+        assert!(end.is_none());
+
+        // The first write may still be queued; the failed write must then surface
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        let err = loop {
+            let message = InputMessage::user("hello".into(), "default".into());
+            match transport.send_message(message).await {
+                Ok(()) => {
+                    assert!(tokio::time::Instant::now() < deadline, "send never failed");
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                },
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(&err, SdkError::TransportError(msg) if msg.contains("stdin")));
+        assert_eq!(transport.state.get(), TransportState::Error);
+        assert!(!transport.is_connected());
+
+        // Every later write reports the same cause
+        let err = transport
+            .send_sdk_control_request(serde_json::json!({"type": "control_request"}))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SdkError::TransportError(_)));
+        transport.disconnect().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_rate_limited_result_surfaces_as_error() {
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = fake_cli(
+            dir.path(),
+            r#"echo '{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":true,"num_turns":1,"session_id":"s","result":"API Error: 429 {\"type\":\"error\",\"error\":{\"type\":\"rate_limit_error\"},\"retry_after\":30}"}'
+"#,
+        );
+
+        let mut transport = SubprocessTransport::with_cli_path(ClaudeCodeOptions::default(), path);
+        transport.connect().await.unwrap();
+        let next =
+            tokio::time::timeout(Duration::from_secs(10), transport.receive_messages().next())
+                .await
+                .unwrap();
+        match next {
+            Some(Err(SdkError::RateLimited { retry_after })) => {
+                assert_eq!(retry_after, Some(Duration::from_secs(30)));
+            },
+            other => panic!("expected a rate limit error, got {other:?}"),
+        }
+
+        // The Result itself still arrives, even on a fresh stream
+        let next =
+            tokio::time::timeout(Duration::from_secs(10), transport.receive_messages().next())
+                .await
+                .unwrap();
+        assert!(matches!(
+            next,
+            Some(Ok(Message::Result { is_error: true, .. }))
+        ));
+        transport.disconnect().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_on_tool_use_observes_each_tool_use() {
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = fake_cli(
+            dir.path(),
+            r#"echo '{"type":"assistant","message":{"content":[{"type":"text","text":"Looking"},{"type":"tool_use","id":"t1","name":"Read","input":{"file_path":"a.rs"}},{"type":"tool_use","id":"t2","name":"Grep","input":{"pattern":"fn"}}]}}'
+echo '{"type":"user","message":{"content":[{"type":"tool_result","tool_use_id":"t1","content":"ok"}]}}'
+echo '{"type":"assistant","message":{"content":[{"type":"tool_use","id":"t3","name":"Bash","input":{"command":"ls"}}]}}'
+echo '{"type":"assistant","message":{"content":[{"type":"text","text":"Done"}]}}'
+echo '{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":false,"num_turns":1,"session_id":"s"}'
+"#,
+        );
+
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let sink = observed.clone();
+        let options = ClaudeCodeOptions::builder()
+            .on_tool_use(Arc::new(move |tool_use: &crate::types::ToolUseContent| {
+                sink.lock().unwrap().push(tool_use.id.clone());
+            }))
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, path);
+        transport.connect().await.unwrap();
+        let messages = tokio::time::timeout(Duration::from_secs(10), async {
+            let mut stream = transport.receive_messages();
+            let mut count = 0;
+            while let Some(message) = stream.next().await {
+                count += 1;
+                if matches!(message.unwrap(), Message::Result { .. }) {
+                    break;
+                }
+            }
+            count
+        })
+        .await
+        .unwrap();
+        assert_eq!(messages, 5);
+        assert_eq!(*observed.lock().unwrap(), ["t1", "t2", "t3"]);
+        transport.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_messages_requires_connection() {
+        let mut transport =
+            SubprocessTransport::with_cli_path(ClaudeCodeOptions::default(), "/usr/bin/true");
+        let err = transport.send_messages(vec![]).await.unwrap_err();
+        assert!(matches!(err, SdkError::InvalidState { .. }));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sequence_numbers_are_monotonic_and_gap_free() {
+        use std::time::Duration;
+
+        const COUNT: usize = 50;
+        let dir = tempfile::tempdir().unwrap();
+        let options = ClaudeCodeOptions::builder()
+            .cli_channel_buffer_size(4)
+            .build();
+        let mut transport =
+            SubprocessTransport::with_cli_path(options, chatty_cli(dir.path(), COUNT));
+        transport.connect().await.unwrap();
+
+        // Through the trait, as transport-agnostic consumers see it
+        let dyn_transport: &mut dyn Transport = &mut transport;
+        let mut seqs = Vec::new();
+        while seqs.len() < COUNT {
+            let next = tokio::time::timeout(
+                Duration::from_secs(10),
+                dyn_transport.receive_sequenced_messages().unwrap().next(),
+            )
+            .await
+            .expect("timed out waiting for a message");
+            seqs.push(next.unwrap().unwrap().seq);
+        }
+
+        assert_eq!(seqs, (0..COUNT as u64).collect::<Vec<_>>());
+        transport.disconnect().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_allow_multiple_receivers_enables_subscriptions() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = ClaudeCodeOptions::builder()
+            .allow_multiple_receivers(true)
+            .build();
+        let mut transport = SubprocessTransport::with_cli_path(options, chatty_cli(dir.path(), 1));
+        transport.connect().await.unwrap();
+
+        assert!(transport.subscribe_messages().is_some());
+        assert!(transport.subscribe_messages().is_some());
+        transport.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_fires_on_interval_until_stdin_closes() {
+        use std::time::Duration;
+
+        let (tx, mut rx) = mpsc::channel::<String>(16);
+        let keepalive = tokio::spawn(send_keepalives(tx.downgrade(), Duration::from_millis(40)));
+
+        // Nothing is sent before the first interval has elapsed
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(rx.try_recv().is_err());
+
+        for _ in 0..3 {
+            let line = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+                .await
+                .expect("keepalive should fire every interval")
+                .unwrap();
+            assert_eq!(line, KEEPALIVE_LINE);
+        }
+
+        drop(tx);
+        tokio::time::timeout(Duration::from_secs(1), keepalive)
+            .await
+            .expect("keepalive should stop once stdin is closed")
+            .unwrap();
+    }
 }
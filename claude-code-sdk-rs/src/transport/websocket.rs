@@ -0,0 +1,854 @@
+//! WebSocket transport for a remote Claude CLI
+//!
+//! Some deployments can't spawn the CLI locally and expose it behind a
+//! gateway instead, speaking the same stream-json protocol over a WebSocket:
+//! every text frame carries one JSON line. [`WebSocketTransport`] sends each
+//! input message and control request as a text frame, and routes inbound
+//! lines to the message and control channels the way the subprocess
+//! transport routes the CLI's stdout.
+
+use super::{
+    HeldResult, InputMessage, RECONNECTED_CONTROL_SUBTYPE, SharedState, Transport,
+    TransportCapabilities, TransportState, control_request_json, surface_rate_limits,
+};
+use crate::{
+    errors::{Result, SdkError},
+    types::{ControlRequest, ControlResponse, Message, ReconnectBackoff},
+};
+use async_trait::async_trait;
+use futures::{
+    SinkExt,
+    stream::{Stream, StreamExt},
+};
+use std::{pin::Pin, sync::Arc, time::Duration};
+use tokio::{net::TcpStream, sync::mpsc, task::JoinHandle};
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, connect_async,
+    tungstenite::{
+        Message as Frame,
+        client::IntoClientRequest,
+        http::{HeaderName, HeaderValue, header::AUTHORIZATION},
+    },
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// Default buffer size for channels
+const CHANNEL_BUFFER_SIZE: usize = 100;
+
+/// How long `disconnect` waits for the close handshake
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// How a [`WebSocketTransport`] recovers from a dropped connection
+#[derive(Debug, Clone)]
+pub struct WebSocketReconnectPolicy {
+    /// Reconnect attempts before giving up
+    ///
+    /// The count resets once a turn completes on the new connection.
+    pub max_attempts: u32,
+    /// Delays between attempts
+    pub backoff: ReconnectBackoff,
+}
+
+impl Default for WebSocketReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: ReconnectBackoff::default(),
+        }
+    }
+}
+
+/// How often an idle WebSocket is pinged unless configured otherwise
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Connection settings shared with the connection task
+#[derive(Debug, Clone)]
+struct WebSocketConfig {
+    url: String,
+    bearer_token: Option<String>,
+    headers: Vec<(String, String)>,
+    reconnect: Option<WebSocketReconnectPolicy>,
+    ping_interval: Option<Duration>,
+}
+
+/// Builder for [`WebSocketTransport`]
+#[derive(Debug, Clone)]
+pub struct WebSocketTransportBuilder {
+    config: WebSocketConfig,
+}
+
+impl WebSocketTransportBuilder {
+    /// Send `Authorization: Bearer <token>` with the handshake
+    pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.config.bearer_token = Some(token.into());
+        self
+    }
+
+    /// Send an extra header with the handshake
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Reconnect with `policy` when the connection drops
+    ///
+    /// Without a policy a dropped connection ends the message stream and
+    /// leaves the transport in `TransportState::Error`.
+    pub fn reconnect(mut self, policy: WebSocketReconnectPolicy) -> Self {
+        self.config.reconnect = Some(policy);
+        self
+    }
+
+    /// Send a ping every `interval` to keep idle connections open
+    /// (default: [`DEFAULT_PING_INTERVAL`])
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.config.ping_interval = Some(interval);
+        self
+    }
+
+    /// Do not send pings
+    pub fn disable_ping(mut self) -> Self {
+        self.config.ping_interval = None;
+        self
+    }
+
+    /// Build the transport; nothing is connected until `connect`
+    pub fn build(self) -> WebSocketTransport {
+        WebSocketTransport {
+            config: Arc::new(self.config),
+            outbound_tx: None,
+            message_rx: None,
+            held_result: HeldResult::default(),
+            control_rx: None,
+            sdk_control_rx: None,
+            state: SharedState::new(TransportState::Disconnected),
+            shutdown: CancellationToken::new(),
+            task: None,
+        }
+    }
+}
+
+/// Transport talking stream-json to a remote CLI over a WebSocket
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use nexus_claude::{InteractiveClient, WebSocketReconnectPolicy, WebSocketTransport};
+/// use std::time::Duration;
+///
+/// # async fn example() -> nexus_claude::Result<()> {
+/// let transport = WebSocketTransport::builder("wss://gateway.example.com/claude")
+///     .bearer_token("secret")
+///     .reconnect(WebSocketReconnectPolicy::default())
+///     .ping_interval(Duration::from_secs(30))
+///     .build();
+/// let mut client = InteractiveClient::from_transport(Box::new(transport));
+/// client.connect().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct WebSocketTransport {
+    config: Arc<WebSocketConfig>,
+    /// Lines to send as text frames
+    outbound_tx: Option<mpsc::Sender<String>>,
+    /// Shared single-consumer message queue
+    message_rx: Option<Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<Message>>>>,
+    /// Rate-limited Result still to be delivered by `receive_messages`
+    held_result: HeldResult,
+    /// Receiver for interrupt acknowledgements
+    control_rx: Option<mpsc::Receiver<ControlResponse>>,
+    /// Receiver for SDK control messages
+    sdk_control_rx: Option<mpsc::Receiver<serde_json::Value>>,
+    state: SharedState,
+    /// Stops the connection task
+    shutdown: CancellationToken,
+    task: Option<JoinHandle<()>>,
+}
+
+impl WebSocketTransport {
+    /// Start building a transport for the gateway at `url` (`ws://` or `wss://`)
+    pub fn builder(url: impl Into<String>) -> WebSocketTransportBuilder {
+        WebSocketTransportBuilder {
+            config: WebSocketConfig {
+                url: url.into(),
+                bearer_token: None,
+                headers: Vec::new(),
+                reconnect: None,
+                ping_interval: Some(DEFAULT_PING_INTERVAL),
+            },
+        }
+    }
+
+    /// Create a transport for `url` with default settings
+    pub fn new(url: impl Into<String>) -> Self {
+        Self::builder(url).build()
+    }
+
+    fn accepts_input(&self) -> bool {
+        matches!(
+            self.state.get(),
+            TransportState::Connected | TransportState::Reconnecting
+        )
+    }
+
+    /// Queue a line to be sent as a text frame
+    async fn send_line(&self, line: String) -> Result<()> {
+        if !self.accepts_input() {
+            return Err(SdkError::InvalidState {
+                message: "Not connected".into(),
+            });
+        }
+        let Some(ref tx) = self.outbound_tx else {
+            return Err(SdkError::InvalidState {
+                message: "Input already ended".into(),
+            });
+        };
+        tx.send(line).await.map_err(|_| SdkError::ChannelSendError)
+    }
+}
+
+/// Open a WebSocket to the configured URL
+async fn open(config: &WebSocketConfig) -> Result<Socket> {
+    let mut request =
+        config.url.as_str().into_client_request().map_err(|e| {
+            SdkError::ConfigError(format!("Invalid WebSocket URL {}: {e}", config.url))
+        })?;
+    let headers = request.headers_mut();
+    for (name, value) in &config.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| SdkError::ConfigError(format!("Invalid header name {name:?}: {e}")))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|e| SdkError::ConfigError(format!("Invalid value for header {name}: {e}")))?;
+        headers.insert(name, value);
+    }
+    if let Some(ref token) = config.bearer_token {
+        let value = HeaderValue::from_str(&format!("Bearer {token}"))
+            .map_err(|_| SdkError::ConfigError("Invalid bearer token".into()))?;
+        headers.insert(AUTHORIZATION, value);
+    }
+
+    let (socket, _response) = connect_async(request).await.map_err(|e| {
+        SdkError::ConnectionError(format!(
+            "WebSocket connection to {} failed: {e}",
+            config.url
+        ))
+    })?;
+    Ok(socket)
+}
+
+/// Why the connection task stopped reading from a socket
+enum Stopped {
+    /// `disconnect` was called
+    Shutdown,
+    /// The gateway closed the connection with a close frame
+    Closed,
+    /// The connection failed
+    Lost,
+}
+
+/// Owns the socket: writes outbound lines, routes inbound ones, reconnects
+struct Connection {
+    config: Arc<WebSocketConfig>,
+    state: SharedState,
+    shutdown: CancellationToken,
+    outbound_rx: mpsc::Receiver<String>,
+    /// Set once every outbound sender is gone (`end_input`)
+    outbound_closed: bool,
+    /// A line whose send failed, retried on the next connection
+    unsent: Option<String>,
+    /// Routing never waits for a consumer, which would also stall the
+    /// outbound lines written by the same task: messages and SDK control
+    /// messages queue without bound, and interrupt acknowledgements nobody
+    /// reads are dropped.
+    message_tx: mpsc::UnboundedSender<Message>,
+    control_tx: mpsc::Sender<ControlResponse>,
+    /// Handed to the SDK control receiver by [`forward_control`]
+    sdk_control_tx: mpsc::UnboundedSender<serde_json::Value>,
+    /// Reconnect attempts since a turn last completed
+    attempts: u32,
+    /// Set when a turn finished; a connection that completes a turn is healthy
+    turn_completed: bool,
+}
+
+impl Connection {
+    async fn run(mut self, mut socket: Socket) {
+        loop {
+            match self.pump(&mut socket).await {
+                Stopped::Shutdown => {
+                    let _ = socket.close(None).await;
+                    break;
+                },
+                Stopped::Closed => {
+                    info!("WebSocket closed by the gateway");
+                    self.state.set(TransportState::Disconnected);
+                    break;
+                },
+                Stopped::Lost => match self.reconnect().await {
+                    Some(reopened) => socket = reopened,
+                    None => break,
+                },
+            }
+        }
+        debug!("WebSocket connection task finished");
+    }
+
+    /// Exchange frames until the socket stops or the transport shuts down
+    async fn pump(&mut self, socket: &mut Socket) -> Stopped {
+        if let Some(line) = self.unsent.take()
+            && let Err(e) = socket.send(Frame::text(line.clone())).await
+        {
+            warn!("Failed to send WebSocket frame: {}", e);
+            self.unsent = Some(line);
+            return Stopped::Lost;
+        }
+
+        let mut ping = self
+            .config
+            .ping_interval
+            .map(|period| tokio::time::interval_at(tokio::time::Instant::now() + period, period));
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return Stopped::Shutdown,
+                line = self.outbound_rx.recv(), if !self.outbound_closed => {
+                    let Some(line) = line else {
+                        self.outbound_closed = true;
+                        continue;
+                    };
+                    if let Err(e) = socket.send(Frame::text(line.clone())).await {
+                        warn!("Failed to send WebSocket frame: {}", e);
+                        self.unsent = Some(line);
+                        return Stopped::Lost;
+                    }
+                },
+                frame = socket.next() => match frame {
+                    Some(Ok(Frame::Text(text))) => {
+                        for line in text.lines() {
+                            self.route(line);
+                        }
+                    },
+                    Some(Ok(Frame::Binary(bytes))) => match std::str::from_utf8(&bytes) {
+                        Ok(text) => {
+                            for line in text.lines() {
+                                self.route(line);
+                            }
+                        },
+                        Err(e) => warn!("Ignoring non-UTF-8 binary frame: {}", e),
+                    },
+                    Some(Ok(Frame::Close(frame))) => {
+                        debug!("Received close frame: {:?}", frame);
+                        return Stopped::Closed;
+                    },
+                    // Pings are answered by tungstenite
+                    Some(Ok(_)) => {},
+                    Some(Err(e)) => {
+                        warn!("WebSocket error: {}", e);
+                        return Stopped::Lost;
+                    },
+                    None => return Stopped::Lost,
+                },
+                _ = async {
+                    match ping.as_mut() {
+                        Some(ping) => ping.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    if let Err(e) = socket.send(Frame::Ping(Default::default())).await {
+                        warn!("Failed to send WebSocket ping: {}", e);
+                        return Stopped::Lost;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Route one inbound line to the message or control channels
+    fn route(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+        debug!("Gateway output: {}", line);
+
+        let json: serde_json::Value = match serde_json::from_str(line) {
+            Ok(json) => json,
+            Err(e) => {
+                warn!("Failed to parse JSON: {} - Line: {}", e, line);
+                return;
+            },
+        };
+
+        match json.get("type").and_then(|v| v.as_str()) {
+            Some("control_response") => {
+                // Responses to our interrupts also go to the legacy control channel
+                if let Some(response) = json.get("response")
+                    && let Some(request_id) = response
+                        .get("request_id")
+                        .or_else(|| response.get("requestId"))
+                        .and_then(|v| v.as_str())
+                {
+                    let ack = ControlResponse::InterruptAck {
+                        request_id: request_id.to_string(),
+                        success: response.get("subtype").and_then(|v| v.as_str())
+                            == Some("success"),
+                    };
+                    if let Err(mpsc::error::TrySendError::Full(ack)) = self.control_tx.try_send(ack)
+                    {
+                        debug!("Control response channel full, dropping {:?}", ack);
+                    }
+                }
+                let _ = self.sdk_control_tx.send(json);
+                return;
+            },
+            Some("control_request") | Some("sdk_control_request") => {
+                let _ = self.sdk_control_tx.send(json);
+                return;
+            },
+            Some("control") => {
+                if let Some(control) = json.get("control") {
+                    let _ = self.sdk_control_tx.send(control.clone());
+                    return;
+                }
+            },
+            Some("system")
+                if json
+                    .get("subtype")
+                    .and_then(|v| v.as_str())
+                    .is_some_and(|subtype| subtype.starts_with("sdk_control:")) =>
+            {
+                let _ = self.sdk_control_tx.send(json.clone());
+            },
+            _ => {},
+        }
+
+        match crate::message_parser::parse_message(json) {
+            Ok(Some(message)) => {
+                if matches!(message, Message::Result { .. }) {
+                    self.turn_completed = true;
+                }
+                let _ = self.message_tx.send(message);
+            },
+            Ok(None) => {},
+            Err(e) => warn!("Failed to parse message: {}", e),
+        }
+    }
+
+    /// Re-open the connection per the reconnect policy
+    ///
+    /// Returns `None`, leaving the transport in `TransportState::Error`, when
+    /// there is no policy or every attempt failed, and also when the
+    /// transport was disconnected meanwhile.
+    async fn reconnect(&mut self) -> Option<Socket> {
+        if self.state.get() != TransportState::Connected {
+            return None;
+        }
+        let Some(policy) = self.config.reconnect.clone() else {
+            error!("WebSocket connection lost");
+            self.state.set(TransportState::Error);
+            return None;
+        };
+        self.state.set(TransportState::Reconnecting);
+
+        if std::mem::take(&mut self.turn_completed) {
+            self.attempts = 0;
+        }
+        while self.attempts < policy.max_attempts {
+            self.attempts += 1;
+            let delay = policy.backoff.delay(self.attempts);
+            info!(
+                "Reconnecting WebSocket in {:?} (attempt {}/{})",
+                delay, self.attempts, policy.max_attempts
+            );
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return None,
+                _ = tokio::time::sleep(delay) => {},
+            }
+
+            match open(&self.config).await {
+                Ok(socket) => {
+                    if self.shutdown.is_cancelled() {
+                        return None;
+                    }
+                    self.state.set(TransportState::Connected);
+                    // Control requests sent on the lost connection will never be answered
+                    let _ = self.sdk_control_tx.send(serde_json::json!({
+                        "type": "system",
+                        "subtype": RECONNECTED_CONTROL_SUBTYPE,
+                    }));
+                    let _ = self.message_tx.send(Message::System {
+                        subtype: "reconnected".to_string(),
+                        data: serde_json::json!({ "attempt": self.attempts }),
+                    });
+                    info!("Reconnected to the WebSocket gateway");
+                    return Some(socket);
+                },
+                Err(e) => warn!(
+                    "Failed to reconnect (attempt {}/{}): {}",
+                    self.attempts, policy.max_attempts, e
+                ),
+            }
+        }
+
+        error!(
+            "Giving up on the WebSocket gateway after {} attempts",
+            policy.max_attempts
+        );
+        self.state.set(TransportState::Error);
+        None
+    }
+}
+
+/// Deliver SDK control messages to the bounded receiver handed out by
+/// `take_sdk_control_receiver`, in order
+async fn forward_control(
+    mut inbound: mpsc::UnboundedReceiver<serde_json::Value>,
+    sdk_control_tx: mpsc::Sender<serde_json::Value>,
+) {
+    while let Some(message) = inbound.recv().await {
+        if sdk_control_tx.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for WebSocketTransport {
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+
+    async fn connect(&mut self) -> Result<()> {
+        if self.state.get() == TransportState::Connected {
+            return Ok(());
+        }
+
+        self.state.set(TransportState::Connecting);
+        let socket = match open(&self.config).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                self.state.set(TransportState::Disconnected);
+                return Err(e);
+            },
+        };
+
+        let (outbound_tx, outbound_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+        let (message_tx, message_rx) = mpsc::unbounded_channel();
+        let (control_tx, control_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+        let (inbound_control_tx, inbound_control_rx) = mpsc::unbounded_channel();
+        let (sdk_control_tx, sdk_control_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+        tokio::spawn(forward_control(inbound_control_rx, sdk_control_tx));
+        self.outbound_tx = Some(outbound_tx);
+        self.message_rx = Some(Arc::new(tokio::sync::Mutex::new(message_rx)));
+        self.control_rx = Some(control_rx);
+        self.sdk_control_rx = Some(sdk_control_rx);
+        self.shutdown = CancellationToken::new();
+
+        let connection = Connection {
+            config: self.config.clone(),
+            state: self.state.clone(),
+            shutdown: self.shutdown.clone(),
+            outbound_rx,
+            outbound_closed: false,
+            unsent: None,
+            message_tx,
+            control_tx,
+            sdk_control_tx: inbound_control_tx,
+            attempts: 0,
+            turn_completed: false,
+        };
+        self.state.set(TransportState::Connected);
+        self.task = Some(tokio::spawn(connection.run(socket)));
+        info!("Connected to WebSocket gateway {}", self.config.url);
+        Ok(())
+    }
+
+    async fn send_message(&mut self, message: InputMessage) -> Result<()> {
+        let json = serde_json::to_string(&message)?;
+        self.send_line(json).await
+    }
+
+    fn receive_messages(
+        &mut self,
+    ) -> Pin<Box<dyn Stream<Item = Result<Message>> + Send + 'static>> {
+        let Some(ref rx) = self.message_rx else {
+            return Box::pin(futures::stream::empty());
+        };
+        // Every stream reads from the same queue, so messages that arrive
+        // between two calls are delivered to the next one instead of lost
+        let messages = futures::stream::unfold(rx.clone(), |rx| async move {
+            let message = rx.lock().await.recv().await?;
+            Some((Ok(message), rx))
+        });
+        Box::pin(surface_rate_limits(
+            Box::pin(messages),
+            self.held_result.clone(),
+        ))
+    }
+
+    async fn send_control_request(&mut self, request: ControlRequest) -> Result<()> {
+        let control_msg = control_request_json(&request);
+        self.send_line(serde_json::to_string(&control_msg)?).await
+    }
+
+    async fn receive_control_response(&mut self) -> Result<Option<ControlResponse>> {
+        match self.control_rx {
+            Some(ref mut rx) => Ok(rx.recv().await),
+            None => Ok(None),
+        }
+    }
+
+    async fn send_sdk_control_request(&mut self, request: serde_json::Value) -> Result<()> {
+        self.send_line(serde_json::to_string(&request)?).await
+    }
+
+    async fn send_sdk_control_response(&mut self, response: serde_json::Value) -> Result<()> {
+        let control_response = serde_json::json!({
+            "type": "control_response",
+            "response": response
+        });
+        self.send_line(serde_json::to_string(&control_response)?)
+            .await
+    }
+
+    fn take_sdk_control_receiver(&mut self) -> Option<mpsc::Receiver<serde_json::Value>> {
+        self.sdk_control_rx.take()
+    }
+
+    /// Lines written to the returned sender are sent as text frames
+    fn clone_stdin_sender(&self) -> Option<mpsc::Sender<String>> {
+        self.outbound_tx.clone()
+    }
+
+    fn capabilities(&self) -> TransportCapabilities {
+        TransportCapabilities {
+            supports_control: true,
+            supports_interrupt: true,
+            supports_stdin_clone: true,
+            supports_inbound_control: true,
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.accepts_input()
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        if self.state.get() == TransportState::Disconnected && self.task.is_none() {
+            return Ok(());
+        }
+
+        self.state.set(TransportState::Disconnecting);
+        self.shutdown.cancel();
+        self.outbound_tx = None;
+        if let Some(task) = self.task.take()
+            && tokio::time::timeout(CLOSE_TIMEOUT, task).await.is_err()
+        {
+            warn!("WebSocket close handshake timed out");
+        }
+        self.state.set(TransportState::Disconnected);
+        info!("Disconnected from WebSocket gateway");
+        Ok(())
+    }
+
+    async fn end_input(&mut self) -> Result<()> {
+        self.outbound_tx = None;
+        Ok(())
+    }
+}
+
+impl Drop for WebSocketTransport {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+    const RESULT_LINE: &str = r#"{"type":"result","subtype":"success","duration_ms":1,"duration_api_ms":1,"is_error":false,"num_turns":1,"session_id":"s"}"#;
+
+    async fn listen() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    async fn next_text(socket: &mut WebSocketStream<TcpStream>) -> String {
+        loop {
+            match socket.next().await.unwrap().unwrap() {
+                Frame::Text(text) => return text.to_string(),
+                _ => continue,
+            }
+        }
+    }
+
+    // The handshake callback's error type is tungstenite's
+    #[allow(clippy::result_large_err)]
+    #[tokio::test]
+    async fn test_exchanges_lines_and_routes_control_messages() {
+        let (listener, url) = listen().await;
+        let gateway = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut authorization = None;
+            let mut socket =
+                tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response| {
+                    authorization = request
+                        .headers()
+                        .get(AUTHORIZATION)
+                        .map(|v| v.to_str().unwrap().to_string());
+                    Ok::<Response, _>(response)
+                })
+                .await
+                .unwrap();
+
+            let prompt = next_text(&mut socket).await;
+            for line in [
+                r#"{"type":"control_request","request_id":"req_1","request":{"subtype":"can_use_tool"}}"#,
+                r#"{"type":"assistant","message":{"content":[{"type":"text","text":"hi"}]}}"#,
+                RESULT_LINE,
+            ] {
+                socket.send(Frame::text(line)).await.unwrap();
+            }
+            let stdin_line = next_text(&mut socket).await;
+            (authorization, prompt, stdin_line)
+        });
+
+        let mut transport = WebSocketTransport::builder(url)
+            .bearer_token("secret")
+            .ping_interval(Duration::from_millis(10))
+            .build();
+        transport.connect().await.unwrap();
+        let mut control_rx = transport.take_sdk_control_receiver().unwrap();
+
+        transport
+            .send_message(InputMessage::user("hello".into(), "default".into()))
+            .await
+            .unwrap();
+        let messages: Vec<Message> = transport
+            .receive_messages()
+            .take(2)
+            .map(|m| m.unwrap())
+            .collect()
+            .await;
+        assert!(matches!(messages[0], Message::Assistant { .. }));
+        assert!(matches!(messages[1], Message::Result { .. }));
+
+        let control = control_rx.recv().await.unwrap();
+        assert_eq!(control["request_id"], "req_1");
+
+        let stdin = transport.clone_stdin_sender().unwrap();
+        stdin
+            .send(r#"{"type":"control_response"}"#.into())
+            .await
+            .unwrap();
+
+        let (authorization, prompt, stdin_line) = gateway.await.unwrap();
+        assert_eq!(authorization.as_deref(), Some("Bearer secret"));
+        let prompt: serde_json::Value = serde_json::from_str(&prompt).unwrap();
+        assert_eq!(prompt["message"]["content"], "hello");
+        assert_eq!(stdin_line, r#"{"type":"control_response"}"#);
+
+        transport.disconnect().await.unwrap();
+        assert!(!transport.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_unread_output_does_not_block_outbound_lines() {
+        let (listener, url) = listen().await;
+        let gateway = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            for i in 0..CHANNEL_BUFFER_SIZE * 3 {
+                for line in [
+                    r#"{"type":"assistant","message":{"content":[{"type":"text","text":"hi"}]}}"#
+                        .to_string(),
+                    format!(
+                        r#"{{"type":"control_response","response":{{"subtype":"success","request_id":"req_{i}"}}}}"#
+                    ),
+                    format!(
+                        r#"{{"type":"control_request","request_id":"cli_{i}","request":{{"subtype":"can_use_tool"}}}}"#
+                    ),
+                ] {
+                    socket.send(Frame::text(line)).await.unwrap();
+                }
+            }
+            socket.send(Frame::text(RESULT_LINE)).await.unwrap();
+            next_text(&mut socket).await
+        });
+
+        let mut transport = WebSocketTransport::builder(url).disable_ping().build();
+        transport.connect().await.unwrap();
+        let mut control_rx = transport.take_sdk_control_receiver().unwrap();
+
+        // Nothing has been read, yet the next line still reaches the gateway
+        let stdin = transport.clone_stdin_sender().unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stdin
+            .send(r#"{"type":"control_response"}"#.into())
+            .await
+            .unwrap();
+        let line = tokio::time::timeout(Duration::from_secs(5), gateway)
+            .await
+            .expect("outbound line was blocked")
+            .unwrap();
+        assert_eq!(line, r#"{"type":"control_response"}"#);
+
+        let messages = transport
+            .receive_messages()
+            .take(CHANNEL_BUFFER_SIZE * 3 + 1);
+        assert_eq!(messages.count().await, CHANNEL_BUFFER_SIZE * 3 + 1);
+        for _ in 0..CHANNEL_BUFFER_SIZE * 6 {
+            control_rx.recv().await.unwrap();
+        }
+        transport.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_after_connection_loss() {
+        let (listener, url) = listen().await;
+        tokio::spawn(async move {
+            // The first connection drops without a close handshake
+            let (stream, _) = listener.accept().await.unwrap();
+            let socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            drop(socket);
+
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            socket.send(Frame::text(RESULT_LINE)).await.unwrap();
+            // Keep the connection open until the client is done
+            let _ = socket.next().await;
+        });
+
+        let mut transport = WebSocketTransport::builder(url)
+            .reconnect(WebSocketReconnectPolicy {
+                max_attempts: 2,
+                backoff: ReconnectBackoff {
+                    base_delay: Duration::from_millis(10),
+                    ..Default::default()
+                },
+            })
+            .build();
+        transport.connect().await.unwrap();
+        let mut control_rx = transport.take_sdk_control_receiver().unwrap();
+
+        let messages: Vec<Message> = transport
+            .receive_messages()
+            .take(2)
+            .map(|m| m.unwrap())
+            .collect()
+            .await;
+        assert!(matches!(
+            &messages[0],
+            Message::System { subtype, data } if subtype == "reconnected" && data["attempt"] == 1
+        ));
+        assert!(matches!(messages[1], Message::Result { .. }));
+        assert!(super::super::is_reconnect_marker(
+            &control_rx.recv().await.unwrap()
+        ));
+        assert!(transport.is_connected());
+    }
+}
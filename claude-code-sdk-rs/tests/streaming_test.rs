@@ -76,6 +76,8 @@ async fn test_streaming_flow() {
                 usage: None,
                 result: Some("Success".to_string()),
                 structured_output: None,
+                parent_tool_use_id: None,
+                permission_denials: vec![],
             }))
             .await;
     });
@@ -131,6 +133,8 @@ async fn test_receive_response_stops_after_result() {
             usage: None,
             result: None,
             structured_output: None,
+            parent_tool_use_id: None,
+            permission_denials: vec![],
         });
 
         // This should NOT be received
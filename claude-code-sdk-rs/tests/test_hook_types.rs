@@ -222,6 +222,7 @@ fn test_pre_tool_use_hook_specific_output() {
         permission_decision_reason: Some("Tool not allowed".to_string()),
         updated_input: Some(json!({"modified": true})),
         additional_context: None,
+        modification_reason: None,
     });
 
     let json = serde_json::to_value(&specific_output)
@@ -267,6 +268,7 @@ fn test_hook_specific_output_discriminated_union() {
         permission_decision_reason: None,
         updated_input: None,
         additional_context: None,
+        modification_reason: None,
     });
 
     let json = serde_json::to_value(&pre_tool_use).expect("Failed to serialize HookSpecificOutput");
@@ -296,6 +298,7 @@ fn test_sync_hook_output_with_hook_specific() {
                 permission_decision_reason: Some("Requires confirmation".to_string()),
                 updated_input: None,
                 additional_context: None,
+                modification_reason: None,
             },
         )),
         ..Default::default()
@@ -316,6 +319,7 @@ fn test_pre_tool_use_additional_context_serialization() {
         permission_decision_reason: None,
         updated_input: None,
         additional_context: Some("Skill context: always use ULID for IDs".to_string()),
+        modification_reason: None,
     });
 
     let json = serde_json::to_value(&specific_output)
@@ -340,6 +344,7 @@ fn test_pre_tool_use_additional_context_none_not_serialized() {
         permission_decision_reason: None,
         updated_input: None,
         additional_context: None,
+        modification_reason: None,
     });
 
     let json = serde_json::to_value(&specific_output)
@@ -419,6 +424,7 @@ fn test_sync_hook_output_with_pre_tool_use_additional_context() {
                     "## Skill: Rust Error Handling\nAlways use anyhow::Result for public APIs."
                         .to_string(),
                 ),
+                modification_reason: None,
             },
         )),
         ..Default::default()